@@ -39,6 +39,49 @@ struct ContentView: View {
                     .background(Color.green.opacity(0.1))
                     .cornerRadius(8)
                 }
+                
+                if peerManager.dnsFallbackActive {
+                    VStack(alignment: .leading, spacing: 8) {
+                        Label("Fallback active", systemImage: "globe")
+                            .font(.headline)
+                        Text("No peers found on the local network, so known devices are being looked up over the internet.")
+                            .font(.caption)
+                    }
+                    .padding()
+                    .background(Color.blue.opacity(0.1))
+                    .cornerRadius(8)
+                }
+                
+                if let hotspot = peerManager.hotspot {
+                    VStack(alignment: .leading, spacing: 8) {
+                        Label(hotspot.role == "host" ? "Sharing Personal Hotspot" : "On a Personal Hotspot",
+                              systemImage: "personalhotspot")
+                            .font(.headline)
+                        Text("Discovery should work over \(hotspot.interface). Others can reach this device at \(hotspot.direct_addrs.first ?? hotspot.host_addr).")
+                            .font(.caption)
+                    }
+                    .padding()
+                    .background(Color.green.opacity(0.1))
+                    .cornerRadius(8)
+                }
+                
+                if peerManager.localNetworkLikelyDenied {
+                    VStack(alignment: .leading, spacing: 8) {
+                        Label("No local network traffic", systemImage: "exclamationmark.triangle")
+                            .font(.headline)
+                        Text("Local Network access appears to be blocked. Enable it in Settings > Privacy & Security > Local Network.")
+                            .font(.caption)
+                        Button("Open Settings") {
+                            if let url = URL(string: UIApplication.openSettingsURLString) {
+                                UIApplication.shared.open(url)
+                            }
+                        }
+                        .font(.caption.bold())
+                    }
+                    .padding()
+                    .background(Color.orange.opacity(0.15))
+                    .cornerRadius(8)
+                }
             }
             
             Spacer()
@@ -92,6 +135,22 @@ struct ContentView: View {
                     .cornerRadius(12)
             }
             .padding(.horizontal)
+            
+            // Diagnostics
+            VStack(spacing: 8) {
+                Button(peerManager.selfTestRunning ? "Running Diagnostics..." : "Run Diagnostics") {
+                    peerManager.runSelfTest()
+                }
+                .font(.subheadline.bold())
+                .disabled(peerManager.selfTestRunning)
+                
+                if let summary = peerManager.selfTestSummary {
+                    Text(summary)
+                        .font(.caption)
+                        .foregroundColor(.secondary)
+                        .multilineTextAlignment(.center)
+                }
+            }
         }
         .padding()
         .onAppear {
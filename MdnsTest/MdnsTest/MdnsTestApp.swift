@@ -9,9 +9,16 @@ import SwiftUI
 
 @main
 struct MdnsTestApp: App {
+    @Environment(\.scenePhase) private var scenePhase
+    
     var body: some Scene {
         WindowGroup {
             ContentView()
         }
+        .onChange(of: scenePhase) { phase in
+            if phase == .active {
+                PeerManager.shared.resume()
+            }
+        }
     }
 }
@@ -0,0 +1,245 @@
+// Generated by `cargo xtask swift-wrapper` from mdns-peer/src, do not edit.
+//
+// The completion-callback FFI as Swift `async` functions (`PeerAsync`), and
+// the event callback as an `AsyncStream` (`PeerEvents`).
+
+import Foundation
+
+/// How an operation ended, see `ResultCode` in mdns-peer/src/deadline.rs
+enum PeerResultCode: Int32 {
+    case ok = 0
+    case invalidArgument = 1
+    case notRunning = 2
+    case timedOut = 3
+    case failed = 4
+    case cancelled = 5
+}
+
+/// Event mask bits, see `event_mask` in mdns-peer/src/events.rs
+enum PeerEventMask {
+    static let discovered: UInt32 = 1 << 0
+    static let expired: UInt32 = 1 << 1
+    static let connectionUp: UInt32 = 1 << 2
+    static let connectionDown: UInt32 = 1 << 3
+    static let error: UInt32 = 1 << 4
+    static let stats: UInt32 = 1 << 5
+    static let status: UInt32 = 1 << 6
+    static let localAddrs: UInt32 = 1 << 7
+    static let inbound: UInt32 = 1 << 8
+    static let connectionPath: UInt32 = 1 << 9
+    static let reconnect: UInt32 = 1 << 10
+    static let messages: UInt32 = 1 << 11
+    static let transfers: UInt32 = 1 << 12
+    static let topics: UInt32 = 1 << 13
+    static let docs: UInt32 = 1 << 14
+    static let rateLimited: UInt32 = 1 << 15
+    static let versionMismatch: UInt32 = 1 << 16
+    static let overBudget: UInt32 = 1 << 17
+    static let relay: UInt32 = 1 << 18
+    static let flapped: UInt32 = 1 << 19
+    static let keyRotated: UInt32 = 1 << 20
+    static let operations: UInt32 = 1 << 21
+    static let discoveryMode: UInt32 = 1 << 22
+    static let hotspot: UInt32 = 1 << 23
+    static let connections: UInt32 = connectionUp | connectionDown | connectionPath | inbound | reconnect
+    static let all: UInt32 = UInt32.max
+}
+
+/// Called once when an `_async` call ends, see `CompletionCallback` in
+/// mdns-peer/src/ffi.rs
+typealias PeerCompletionCallback = @convention(c) (UInt64, Int32, UnsafePointer<CChar>?, UnsafeMutableRawPointer?) -> Void
+
+@_silgen_name("peer_cancel")
+private func raw_peer_cancel(_ op_id: UInt64) -> Bool
+
+@_silgen_name("peer_set_event_callback_filtered")
+private func raw_peer_set_event_callback_filtered(_ callback: (@convention(c) (UnsafePointer<CChar>?, UnsafeMutableRawPointer?) -> Void)?, _ context: UnsafeMutableRawPointer?, _ mask: UInt32)
+
+/// An operation that succeeded: its ID and the event that reported it, as
+/// JSON
+struct PeerOperationResult {
+    let opId: UInt64
+    let json: String
+}
+
+/// An operation that didn't succeed
+///
+/// `json` is the event that reported it. A call refused before it started,
+/// for an invalid argument or because the peer isn't running, throws
+/// `invalidArgument` with `opId` 0 and empty `json`.
+struct PeerOperationError: Error {
+    let code: PeerResultCode
+    let opId: UInt64
+    let json: String
+}
+
+/// One call in flight, shared by its continuation, the completion callback
+/// and the task's cancellation handler
+private final class PendingOperation: @unchecked Sendable {
+    private let lock = NSLock()
+    private var continuation: CheckedContinuation<PeerOperationResult, Error>?
+    private var opId: UInt64 = 0
+    private var cancelled = false
+
+    func wait(_ continuation: CheckedContinuation<PeerOperationResult, Error>) {
+        lock.lock()
+        self.continuation = continuation
+        lock.unlock()
+    }
+
+    /// The call returned `opId`, 0 if it was refused
+    func started(_ opId: UInt64) {
+        lock.lock()
+        self.opId = opId
+        let cancelled = self.cancelled
+        lock.unlock()
+        if opId == 0 {
+            finish(status: PeerResultCode.invalidArgument.rawValue, opId: 0, json: "")
+        } else if cancelled {
+            _ = raw_peer_cancel(opId)
+        }
+    }
+
+    func cancel() {
+        lock.lock()
+        cancelled = true
+        let opId = self.opId
+        lock.unlock()
+        if opId != 0 {
+            _ = raw_peer_cancel(opId)
+        }
+    }
+
+    func finish(status: Int32, opId: UInt64, json: String) {
+        lock.lock()
+        let continuation = self.continuation
+        self.continuation = nil
+        lock.unlock()
+        if status == PeerResultCode.ok.rawValue {
+            continuation?.resume(returning: PeerOperationResult(opId: opId, json: json))
+        } else {
+            let code = PeerResultCode(rawValue: status) ?? .failed
+            continuation?.resume(throwing: PeerOperationError(code: code, opId: opId, json: json))
+        }
+    }
+}
+
+private let completionTrampoline: PeerCompletionCallback = { opId, status, json, context in
+    guard let context else { return }
+    let pending = Unmanaged<PendingOperation>.fromOpaque(context).takeRetainedValue()
+    pending.finish(status: status, opId: opId, json: json.map { String(cString: $0) } ?? "")
+}
+
+/// Run one `_async` call, whose `start` passes the callback and context on
+/// and returns the operation ID
+private func awaitOperation(
+    _ start: (PeerCompletionCallback, UnsafeMutableRawPointer) -> UInt64
+) async throws -> PeerOperationResult {
+    let pending = PendingOperation()
+    return try await withTaskCancellationHandler {
+        try await withCheckedThrowingContinuation { continuation in
+            pending.wait(continuation)
+            // Released by the completion, which a refused call never calls
+            let context = Unmanaged.passRetained(pending).toOpaque()
+            let opId = start(completionTrampoline, context)
+            if opId == 0 {
+                Unmanaged<PendingOperation>.fromOpaque(context).release()
+            }
+            pending.started(opId)
+        }
+    } onCancel: {
+        pending.cancel()
+    }
+}
+
+/// The events the library emits, as an `AsyncStream`
+enum PeerEvents {
+    private static let lock = NSLock()
+    private static var continuation: AsyncStream<String>.Continuation?
+    private static var generation = 0
+
+    /// Events whose bit is set in `mask`, as the JSON the event callback
+    /// gets (an array per batch with `peer_set_event_batch_window`)
+    ///
+    /// Registers the event callback, replacing any other, so only the
+    /// latest stream gets events. Ending the stream unregisters it.
+    static func stream(mask: UInt32 = PeerEventMask.all) -> AsyncStream<String> {
+        AsyncStream { continuation in
+            PeerEvents.lock.lock()
+            PeerEvents.generation += 1
+            let current = PeerEvents.generation
+            let previous = PeerEvents.continuation
+            PeerEvents.continuation = continuation
+            PeerEvents.lock.unlock()
+            previous?.finish()
+
+            continuation.onTermination = { _ in
+                PeerEvents.lock.lock()
+                let latest = PeerEvents.generation == current
+                if latest {
+                    PeerEvents.continuation = nil
+                }
+                PeerEvents.lock.unlock()
+                if latest {
+                    raw_peer_set_event_callback_filtered(nil, nil, 0)
+                }
+            }
+            raw_peer_set_event_callback_filtered({ json, _ in
+                guard let json else { return }
+                PeerEvents.lock.lock()
+                let continuation = PeerEvents.continuation
+                PeerEvents.lock.unlock()
+                continuation?.yield(String(cString: json))
+            }, nil, mask)
+        }
+    }
+}
+
+@_silgen_name("peer_connect_async")
+private func raw_peer_connect_async(_ node_id: UnsafePointer<CChar>?, _ alpn: UnsafePointer<CChar>?, _ timeout_ms: UInt64, _ callback: PeerCompletionCallback?, _ context: UnsafeMutableRawPointer?) -> UInt64
+
+@_silgen_name("peer_ping_async")
+private func raw_peer_ping_async(_ node_id: UnsafePointer<CChar>?, _ timeout_ms: UInt64, _ callback: PeerCompletionCallback?, _ context: UnsafeMutableRawPointer?) -> UInt64
+
+@_silgen_name("peer_send_message_async")
+private func raw_peer_send_message_async(_ node_id: UnsafePointer<CChar>?, _ data: UnsafePointer<UInt8>?, _ len: Int, _ sealed: Bool, _ timeout_ms: UInt64, _ callback: PeerCompletionCallback?, _ context: UnsafeMutableRawPointer?) -> UInt64
+
+@_silgen_name("peer_send_file_async")
+private func raw_peer_send_file_async(_ node_id: UnsafePointer<CChar>?, _ path: UnsafePointer<CChar>?, _ timeout_ms: UInt64, _ callback: PeerCompletionCallback?, _ context: UnsafeMutableRawPointer?) -> UInt64
+
+/// The `peer_*_async` calls as Swift `async` functions
+///
+/// Each returns the operation ID and the JSON of the event it ended with,
+/// and throws a `PeerOperationError` unless that was a success.
+/// Cancelling the calling task cancels the operation.
+enum PeerAsync {
+    /// `peer_connect_async`, see mdns-peer/src/ffi.rs
+    static func connect(nodeId: String, alpn: String, timeoutMs: UInt64) async throws -> PeerOperationResult {
+        try await awaitOperation { callback, context in
+            raw_peer_connect_async(nodeId, alpn, timeoutMs, callback, context)
+        }
+    }
+
+    /// `peer_ping_async`, see mdns-peer/src/ffi.rs
+    static func ping(nodeId: String, timeoutMs: UInt64) async throws -> PeerOperationResult {
+        try await awaitOperation { callback, context in
+            raw_peer_ping_async(nodeId, timeoutMs, callback, context)
+        }
+    }
+
+    /// `peer_send_message_async`, see mdns-peer/src/ffi.rs
+    static func sendMessage(nodeId: String, data: Data, sealed: Bool, timeoutMs: UInt64) async throws -> PeerOperationResult {
+        try await awaitOperation { callback, context in
+            data.withUnsafeBytes { data in
+                raw_peer_send_message_async(nodeId, data.bindMemory(to: UInt8.self).baseAddress, data.count, sealed, timeoutMs, callback, context)
+            }
+        }
+    }
+
+    /// `peer_send_file_async`, see mdns-peer/src/ffi.rs
+    static func sendFile(nodeId: String, path: String, timeoutMs: UInt64) async throws -> PeerOperationResult {
+        try await awaitOperation { callback, context in
+            raw_peer_send_file_async(nodeId, path, timeoutMs, callback, context)
+        }
+    }
+}
@@ -8,13 +8,181 @@ func bob_start() -> Bool
 @_silgen_name("bob_stop")
 func bob_stop()
 
+@_silgen_name("peer_load_config")
+func peer_load_config(_ path: UnsafePointer<CChar>?) -> Bool
+
+@_silgen_name("peer_resume")
+func peer_resume()
+
+@_silgen_name("peer_status")
+func peer_status() -> Int32
+
+@_silgen_name("peer_event_schema_version")
+func peer_event_schema_version() -> UInt32
+
+@_silgen_name("peer_get_remote_info")
+func peer_get_remote_info(_ nodeId: UnsafePointer<CChar>) -> UnsafeMutablePointer<CChar>?
+
+@_silgen_name("peer_self_test")
+func peer_self_test() -> UnsafeMutablePointer<CChar>?
+
+@_silgen_name("peer_set_p2p_interface")
+func peer_set_p2p_interface(_ name: UnsafePointer<CChar>, _ available: Bool) -> Bool
+
+@_silgen_name("peer_free_string")
+func peer_free_string(_ s: UnsafeMutablePointer<CChar>?)
+
+typealias PeerEventCallback = @convention(c) (UnsafePointer<CChar>?, UnsafeMutableRawPointer?) -> Void
+
+@_silgen_name("peer_set_event_callback_filtered")
+func peer_set_event_callback_filtered(_ callback: PeerEventCallback?, _ context: UnsafeMutableRawPointer?, _ mask: UInt32)
+
+/// Version of the event schema `PeerEventPayload` was written against, see
+/// `cargo xtask event-schema`
+private let expectedEventSchemaVersion: UInt32 = 1
+
+/// Subset of the event JSON emitted by mdns-peer that the app reacts to
+private struct PeerEventPayload: Decodable {
+    let type: String
+    let status: String?
+    /// Why the peer stopped, only with status "stopped", or why discovery
+    /// switched modes
+    let reason: String?
+    /// "local" or "fallback", only with "discovery_mode_changed"
+    let mode: String?
+    /// Only with "hotspot_changed", absent once the device left it
+    let hotspot: HotspotPayload?
+}
+
+/// The Personal Hotspot this device is on, see `Hotspot` in
+/// mdns-peer/src/hotspot.rs
+struct HotspotPayload: Decodable {
+    /// "host" or "client"
+    let role: String
+    let interface: String
+    let host_addr: String
+    let direct_addrs: [String]
+    let hint: String
+}
+
+/// Report returned by `peer_self_test`
+private struct SelfTestPayload: Decodable {
+    struct Step: Decodable {
+        let name: String
+        let passed: Bool
+        let detail: String
+        let hint: String?
+    }
+
+    let passed: Bool
+    let steps: [Step]
+}
+
 /// Manager for mDNS discovery peer
 class PeerManager: ObservableObject {
     static let shared = PeerManager()
     
     @Published var isRunning = false
     
-    private init() {}
+    /// Set when no mDNS traffic at all has been received, which on iOS means
+    /// the user most likely denied the Local Network permission
+    @Published var localNetworkLikelyDenied = false
+    
+    /// Set while peers are looked up in DNS because local discovery found
+    /// nobody; needs `dns_fallback` in MdnsPeerConfig.plist
+    @Published var dnsFallbackActive = false
+    
+    /// Set while this device shares or joined a Personal Hotspot
+    @Published var hotspot: HotspotPayload?
+    
+    @Published var selfTestRunning = false
+    
+    /// Outcome of the last "Run Diagnostics", nil before the first run
+    @Published var selfTestSummary: String?
+    
+    private init() {
+        if peer_event_schema_version() != expectedEventSchemaVersion {
+            print("Warning: mdns-peer sends event schema v\(peer_event_schema_version()), this app decodes v\(expectedEventSchemaVersion)")
+        }
+        // Only status, discovery mode and hotspot changes are shown in the UI so far
+        peer_set_event_callback_filtered({ json, _ in
+            guard let json = json else { return }
+            PeerManager.shared.handleEvent(String(cString: json))
+        }, nil, PeerEventMask.status | PeerEventMask.discoveryMode | PeerEventMask.hotspot)
+    }
+    
+    private func handleEvent(_ json: String) {
+        guard let data = json.data(using: .utf8),
+              let event = try? JSONDecoder().decode(PeerEventPayload.self, from: data) else {
+            print("Warning: Could not decode peer event: \(json)")
+            return
+        }
+        
+        if event.type == "discovery_mode_changed", let mode = event.mode {
+            DispatchQueue.main.async {
+                self.dnsFallbackActive = mode == "fallback"
+            }
+            return
+        }
+        
+        if event.type == "hotspot_changed" {
+            DispatchQueue.main.async {
+                self.hotspot = event.hotspot
+            }
+            return
+        }
+        
+        guard event.type == "status_changed", let status = event.status else {
+            return
+        }
+        
+        DispatchQueue.main.async {
+            self.localNetworkLikelyDenied = status == "local_network_permission_likely_denied"
+            // Stops the host asked for are already handled by stop()
+            if status == "stopped", let reason = event.reason, reason != "host_requested" {
+                print("Warning: Peer stopped unexpectedly (\(reason))")
+                self.isRunning = false
+            }
+        }
+    }
+    
+    /// iroh's view of one peer (addresses, latency, relay) as JSON, or nil
+    /// if the endpoint doesn't know the node
+    func remoteInfo(for nodeId: String) -> String? {
+        guard let ptr = peer_get_remote_info(nodeId) else { return nil }
+        defer { peer_free_string(ptr) }
+        return String(cString: ptr)
+    }
+    
+    /// Run `peer_self_test` off the main thread and publish a one-line
+    /// summary, with the hint of the step that failed
+    func runSelfTest() {
+        guard !selfTestRunning else { return }
+        selfTestRunning = true
+        DispatchQueue.global(qos: .userInitiated).async {
+            var summary = "Diagnostics could not run"
+            if let ptr = peer_self_test() {
+                let json = String(cString: ptr)
+                peer_free_string(ptr)
+                print("Self-test: \(json)")
+                if let data = json.data(using: .utf8),
+                   let report = try? JSONDecoder().decode(SelfTestPayload.self, from: data) {
+                    if report.passed {
+                        summary = "All checks passed"
+                    } else if let failed = report.steps.first(where: { !$0.passed }) {
+                        summary = "\(failed.name) failed: \(failed.detail)"
+                        if let hint = failed.hint {
+                            summary += "\n\(hint)"
+                        }
+                    }
+                }
+            }
+            DispatchQueue.main.async {
+                self.selfTestSummary = summary
+                self.selfTestRunning = false
+            }
+        }
+    }
     
     func start() -> Bool {
         guard !isRunning else {
@@ -23,6 +191,11 @@ class PeerManager: ObservableObject {
         }
         
         print("Starting peer...")
+        // Optional settings bundled with the app, see mdns-peer/src/config.rs
+        let configPath = Bundle.main.path(forResource: "MdnsPeerConfig", ofType: "plist")
+        if !peer_load_config(configPath) {
+            print("Warning: Ignoring invalid MdnsPeerConfig.plist")
+        }
         let success = bob_start()
         
         if success {
@@ -36,6 +209,21 @@ class PeerManager: ObservableObject {
         return success
     }
     
+    /// Report whether a peer-to-peer interface such as "awdl0" is up, e.g.
+    /// from an `NWPathMonitor` on a connection with `includePeerToPeer`
+    func setPeerToPeerInterface(_ name: String, available: Bool) {
+        if !peer_set_p2p_interface(name, available) {
+            print("Warning: Could not report interface \(name)")
+        }
+    }
+    
+    /// Re-dial trusted peers whose connections dropped while the app was
+    /// suspended
+    func resume() {
+        guard isRunning else { return }
+        peer_resume()
+    }
+    
     func stop() {
         guard isRunning else {
             print("Warning: Peer is not running")
@@ -45,6 +233,8 @@ class PeerManager: ObservableObject {
         print("Stopping peer...")
         bob_stop()
         isRunning = false
+        localNetworkLikelyDenied = false
+        dnsFallbackActive = false
         print("Peer stopped")
     }
     
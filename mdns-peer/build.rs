@@ -5,4 +5,19 @@ fn main() {
         println!("cargo:rustc-link-lib=framework=Foundation");
         println!("cargo:rustc-link-lib=framework=Security");
     }
+
+    // Generate the `grpc` module's service and messages, with a protoc
+    // shipped as a crate so building doesn't need one installed
+    #[cfg(feature = "grpc")]
+    {
+        let protoc =
+            protoc_bin_vendored::protoc_bin_path().expect("No bundled protoc for this host");
+        std::env::set_var("PROTOC", protoc);
+        // Without transport helpers the client's `connect` constructor would
+        // clash with the `Connect` RPC; clients use `PeerControlClient::new`
+        tonic_prost_build::configure()
+            .build_transport(false)
+            .compile_protos(&["proto/mdns_peer.proto"], &["proto"])
+            .expect("Failed to compile proto/mdns_peer.proto");
+    }
 }
@@ -0,0 +1,10 @@
+//! Print the JSON schema of the events the FFI delivers, for
+//! `cargo xtask event-schema`
+
+fn main() {
+    let schema = mdns_peer::schema::event_schema();
+    println!(
+        "{}",
+        serde_json::to_string_pretty(schema).expect("schemas are always serializable")
+    );
+}
@@ -0,0 +1,17 @@
+//! Arbitrary bytes through the config parsing of `peer_load_config`
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mdns_peer::config::PeerConfig;
+use mdns_peer::options::PeerOptions;
+
+fuzz_target!(|data: &[u8]| {
+    for is_plist in [false, true] {
+        let Ok(config) = PeerConfig::parse(data, is_plist) else {
+            continue;
+        };
+        // Settings that parse may still be refused, but never with a panic
+        let _ = config.apply(&mut PeerOptions::default());
+    }
+});
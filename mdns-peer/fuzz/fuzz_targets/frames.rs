@@ -0,0 +1,30 @@
+//! Arbitrary bytes through the frame decoding of messages and transfers
+
+#![no_main]
+
+use std::fmt::Debug;
+
+use libfuzzer_sys::fuzz_target;
+use mdns_peer::protocol::{
+    self, FileAnswer, FileEnd, FileOffer, FileOutcome, MessageFrame, MessageReply,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Decoding never panics, and what decodes survives encoding again
+fn round_trip<T: Serialize + DeserializeOwned + PartialEq + Debug>(data: &[u8]) {
+    if let Ok(Some((frame, _))) = protocol::decode::<T>(data) {
+        let encoded = protocol::encode(&frame);
+        let decoded = protocol::decode::<T>(&encoded).unwrap();
+        assert_eq!(decoded, Some((frame, &[][..])));
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    round_trip::<MessageFrame>(data);
+    round_trip::<MessageReply>(data);
+    round_trip::<FileOffer>(data);
+    round_trip::<FileAnswer>(data);
+    round_trip::<FileEnd>(data);
+    round_trip::<FileOutcome>(data);
+});
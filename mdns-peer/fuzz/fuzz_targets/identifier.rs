@@ -0,0 +1,19 @@
+//! Arbitrary bytes through the identifier validation used by `peer_start`
+
+#![no_main]
+
+use std::ffi::CStr;
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // peer_start receives a NUL-terminated C string, so cut at the first NUL
+    let Ok(identifier) = CStr::from_bytes_until_nul(data) else {
+        return;
+    };
+
+    if let Ok(id) = mdns_peer::ffi::parse_identifier(identifier) {
+        // Accepted identifiers are passed through unchanged
+        assert_eq!(id.as_bytes(), identifier.to_bytes());
+    }
+});
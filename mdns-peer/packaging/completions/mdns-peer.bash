@@ -0,0 +1,47 @@
+# bash completion for mdns-peer
+
+_mdns_peer() {
+    local cur prev
+    cur="${COMP_WORDS[COMP_CWORD]}"
+    prev="${COMP_WORDS[COMP_CWORD-1]}"
+
+    case "$prev" in
+        --record|--replay|--config)
+            COMPREPLY=($(compgen -f -- "$cur"))
+            return
+            ;;
+        --duplicate)
+            COMPREPLY=($(compgen -W "refuse suffix allow" -- "$cur"))
+            return
+            ;;
+        --profile|--summary-interval|--relays|--speed|--interval|--hours|--count|--prefix|--rotate|--dashboard|--mdns-service|--mdns-cadence|--mdns-response-rate|--pair|--bind|--seed|--dns-fallback|--notes|--listen)
+            return
+            ;;
+    esac
+
+    local peer_flags="--profile --summary-interval --record --duplicate --relays --lan-only --mdns-service --mdns-cadence --mdns-response-rate --pair --bind --seed --log-names --dns-fallback --config --dry-run"
+    if [[ $COMP_CWORD -eq 1 ]]; then
+        COMPREPLY=($(compgen -W "daemon doctor sniff fake soak stats relays alias history snapshot diff find tasks --profile --replay" -- "$cur"))
+        return
+    fi
+
+    case "${COMP_WORDS[1]}" in
+        doctor) ;;
+        sniff) COMPREPLY=($(compgen -W "--all --mdns-service" -- "$cur")) ;;
+        fake) COMPREPLY=($(compgen -W "--count --prefix --rotate" -- "$cur")) ;;
+        soak) COMPREPLY=($(compgen -W "--hours" -- "$cur")) ;;
+        stats) COMPREPLY=($(compgen -W "--interval $peer_flags" -- "$cur")) ;;
+        daemon) COMPREPLY=($(compgen -W "--dashboard $peer_flags" -- "$cur")) ;;
+        relays) COMPREPLY=($(compgen -W "--relays" -- "$cur")) ;;
+        alias) COMPREPLY=($(compgen -W "--notes" -- "$cur")) ;;
+        snapshot) COMPREPLY=($(compgen -f -W "--listen $peer_flags" -- "$cur")) ;;
+        history) ;;
+        diff) COMPREPLY=($(compgen -f -- "$cur")) ;;
+        find) COMPREPLY=($(compgen -W "--listen --mdns-service" -- "$cur")) ;;
+        tasks) ;;
+        --replay) COMPREPLY=($(compgen -W "--speed" -- "$cur")) ;;
+        *) COMPREPLY=($(compgen -W "--events $peer_flags" -- "$cur")) ;;
+    esac
+}
+
+complete -F _mdns_peer mdns-peer
@@ -0,0 +1,49 @@
+# fish completion for mdns-peer
+
+set -l commands daemon doctor sniff fake soak stats relays alias history snapshot diff find tasks
+
+complete -c mdns-peer -f
+complete -c mdns-peer -n "not __fish_seen_subcommand_from $commands" -a daemon -d "Run a peer with the dashboard"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from $commands" -a doctor -d "Check the local network"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from $commands" -a sniff -d "Print mDNS traffic"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from $commands" -a fake -d "Advertise synthetic peers"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from $commands" -a soak -d "Long-running stability test"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from $commands" -a stats -d "Run a peer and log protocol traffic"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from $commands" -a relays -d "Probe relay latency"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from $commands" -a alias -d "Name peers locally"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from $commands" -a history -d "Print past sessions"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from $commands" -a snapshot -d "Save the discovered peers"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from $commands" -a diff -d "Compare two snapshots"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from $commands" -a find -d "List discovered peers matching a name"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from $commands" -a tasks -d "List the background tasks of a daemon"
+
+complete -c mdns-peer -n "not __fish_seen_subcommand_from doctor sniff fake soak relays alias history diff find tasks" -l profile -r -d "Use a stored identity"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from doctor sniff fake soak relays alias history diff find tasks" -l summary-interval -r -d "Seconds between summaries"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from doctor sniff fake soak relays alias history diff find tasks" -l record -r -F -d "Record discovery events"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from doctor sniff fake soak relays alias history diff find tasks" -l duplicate -r -a "refuse suffix allow" -d "When the identifier already runs here"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from doctor sniff fake soak relays alias history diff find tasks" -l lan-only -d "Never fall back to a relay"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from doctor sniff fake soak relays alias history diff find tasks" -l mdns-service -r -d "mDNS service name"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from doctor sniff fake soak relays alias history diff find tasks" -l mdns-cadence -r -d "Seconds between mDNS query cycles"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from doctor sniff fake soak relays alias history diff find tasks" -l mdns-response-rate -r -d "mDNS responses per second across the swarm"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from doctor sniff fake soak relays alias history diff find tasks" -l pair -r -d "Pairing payloads of peers to reach without discovery"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from doctor sniff fake soak relays alias history diff find tasks" -l bind -r -d "Local addresses or UDP port to bind"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from doctor sniff fake soak relays alias history diff find tasks" -l seed -r -d "Derive a fixed key for stable node IDs"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from doctor sniff fake soak relays alias history diff find tasks" -l log-names -d "Log device names unhashed"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from doctor sniff fake soak relays alias history diff find tasks" -l dns-fallback -r -d "Seconds without local peers before looking them up in DNS"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from doctor sniff fake soak relays alias history diff find tasks" -l config -r -F -d "Settings file, JSON or plist"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from doctor sniff fake soak relays alias history diff find tasks" -l dry-run -d "Check the settings without starting the peer"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from doctor sniff fake soak alias find tasks" -l relays -r -d "Relay URLs"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from $commands" -l events -d "Print events as JSON lines"
+complete -c mdns-peer -n "not __fish_seen_subcommand_from $commands" -l replay -r -F -d "Replay a recording"
+complete -c mdns-peer -l speed -r -d "Replay speed factor"
+complete -c mdns-peer -n "__fish_seen_subcommand_from daemon" -l dashboard -r -d "Serve the browser dashboard on addr:port"
+complete -c mdns-peer -n "__fish_seen_subcommand_from stats" -l interval -r -d "Seconds between reports"
+complete -c mdns-peer -n "__fish_seen_subcommand_from soak" -l hours -r -d "How long to run"
+complete -c mdns-peer -n "__fish_seen_subcommand_from fake" -l count -r -d "Number of fake peers"
+complete -c mdns-peer -n "__fish_seen_subcommand_from fake" -l prefix -r -d "User data prefix"
+complete -c mdns-peer -n "__fish_seen_subcommand_from fake" -l rotate -r -d "Seconds between rotations"
+complete -c mdns-peer -n "__fish_seen_subcommand_from alias" -l notes -r -d "Notes about the peer"
+complete -c mdns-peer -n "__fish_seen_subcommand_from sniff" -l all -d "Show every mDNS packet"
+complete -c mdns-peer -n "__fish_seen_subcommand_from sniff find" -l mdns-service -r -d "mDNS service name"
+complete -c mdns-peer -n "__fish_seen_subcommand_from snapshot find" -l listen -r -d "Seconds to listen to discovery"
+complete -c mdns-peer -n "__fish_seen_subcommand_from snapshot diff" -F
@@ -0,0 +1,144 @@
+//! Deciding which inbound connections to serve
+//!
+//! By the time a connection reaches the router, the QUIC handshake has
+//! proven the remote holds the secret key for its node ID, so the node ID is
+//! the authenticated identity to decide on. Every inbound connection on a
+//! registered protocol is reported as a [`PeerEvent::InboundConnection`]
+//! with whether the remote is trusted (one of the [`WarmUp::trusted`] peers)
+//! and whether it was let in.
+//!
+//! Without an [`AcceptPolicy`] every connection is accepted. With one, the
+//! connection waits for its decision, which may take as long as a user
+//! needs to tap a button, up to [`DECISION_TIMEOUT`]; no answer by then
+//! counts as a denial. Denied connections are closed with
+//! [`DENIED_ERROR_CODE`].
+//!
+//! [`AcceptLimits`] bound what remote peers can make this one hold in
+//! memory, however they behave: inbound connections beyond the limit are
+//! closed, and streams beyond a peer's quota are reset, both with
+//! [`LIMIT_ERROR_CODE`]. Refused connections are reported like denied ones.
+//!
+//! [`PeerEvent::InboundConnection`]: crate::PeerEvent::InboundConnection
+//! [`WarmUp::trusted`]: crate::options::WarmUp::trusted
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use iroh::NodeId;
+use n0_future::boxed::BoxFuture;
+
+/// How long an inbound connection waits for the policy to decide
+pub const DECISION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// QUIC application error code a denied connection is closed with
+pub const DENIED_ERROR_CODE: u32 = 403;
+
+/// QUIC application error code connections and streams over an
+/// [`AcceptLimits`] quota are refused with
+pub const LIMIT_ERROR_CODE: u32 = 429;
+
+/// Caps on what remote peers may open on this one, `None` meaning unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AcceptLimits {
+    /// Inbound connections served at the same time, across peers and
+    /// protocols, including those waiting for the accept policy
+    pub max_inbound_connections: Option<usize>,
+    /// Streams one peer may have open towards this one at the same time,
+    /// across its connections, including those waiting for a slot under
+    /// [`TransferLimits`](crate::limits::TransferLimits)
+    pub max_streams_per_peer: Option<usize>,
+}
+
+/// An inbound connection waiting to be accepted or denied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InboundRequest {
+    pub node_id: NodeId,
+    /// Protocol the remote connected with
+    pub alpn: Vec<u8>,
+    /// Whether the remote is in the trusted set
+    pub trusted: bool,
+}
+
+/// Decides whether to serve inbound connections
+pub trait AcceptPolicy: Send + Sync + 'static {
+    /// Resolve to true to accept `request`
+    fn decide(&self, request: InboundRequest) -> BoxFuture<bool>;
+}
+
+/// Ask `policy` about `request`, denying when it takes too long
+pub(crate) async fn decide(policy: &dyn AcceptPolicy, request: InboundRequest) -> bool {
+    tokio::time::timeout(DECISION_TIMEOUT, policy.decide(request))
+        .await
+        .unwrap_or(false)
+}
+
+/// Counts what remote peers hold open against [`AcceptLimits`]
+#[derive(Debug, Default)]
+pub(crate) struct Quotas {
+    state: Mutex<QuotaState>,
+}
+
+#[derive(Debug, Default)]
+struct QuotaState {
+    connections: usize,
+    streams: HashMap<NodeId, usize>,
+}
+
+impl Quotas {
+    /// Take one inbound connection under `limit`, or `None` if it is reached
+    pub(crate) fn try_connection(self: &Arc<Self>, limit: Option<usize>) -> Option<QuotaGuard> {
+        let mut state = self.state.lock().unwrap();
+        if limit.is_some_and(|max| state.connections >= max) {
+            return None;
+        }
+        state.connections += 1;
+        Some(QuotaGuard {
+            quotas: self.clone(),
+            stream_of: None,
+        })
+    }
+
+    /// Take one of `node_id`'s streams under `limit`, or `None` if it is
+    /// reached
+    pub(crate) fn try_stream(
+        self: &Arc<Self>,
+        node_id: NodeId,
+        limit: Option<usize>,
+    ) -> Option<QuotaGuard> {
+        let mut state = self.state.lock().unwrap();
+        let open = state.streams.entry(node_id).or_default();
+        if limit.is_some_and(|max| *open >= max) {
+            return None;
+        }
+        *open += 1;
+        Some(QuotaGuard {
+            quotas: self.clone(),
+            stream_of: Some(node_id),
+        })
+    }
+}
+
+/// Gives its connection or stream back to the quota when dropped
+pub(crate) struct QuotaGuard {
+    quotas: Arc<Quotas>,
+    /// The peer whose stream this is, or `None` for a connection
+    stream_of: Option<NodeId>,
+}
+
+impl Drop for QuotaGuard {
+    fn drop(&mut self) {
+        let mut state = self.quotas.state.lock().unwrap();
+        match self.stream_of {
+            Some(node_id) => {
+                if let Some(open) = state.streams.get_mut(&node_id) {
+                    *open -= 1;
+                    if *open == 0 {
+                        state.streams.remove(&node_id);
+                    }
+                }
+            }
+            None => state.connections -= 1,
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Local addresses and UDP ports the endpoint binds
+//!
+//! By default the endpoint binds every interface on an ephemeral port that
+//! changes on each run. Pinning the port lets firewall rules be written for
+//! the desktop peer, and binding one IP keeps it off the other interfaces.
+//! A pinned port that is already taken fails
+//! [`bind_endpoint_with`](crate::bind_endpoint_with) rather than falling back
+//! to another one, so a second instance on the same host can't quietly end up
+//! somewhere the firewall doesn't expect.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket};
+
+use iroh::endpoint::Builder;
+use iroh::Endpoint;
+
+/// Where to bind, per address family; `None` keeps iroh's default of every
+/// interface on an ephemeral port
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BindAddrs {
+    pub v4: Option<SocketAddrV4>,
+    pub v6: Option<SocketAddrV6>,
+}
+
+impl BindAddrs {
+    /// Every interface of both families, on `port`
+    pub fn port(port: u16) -> Self {
+        Self {
+            v4: Some(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port)),
+            v6: Some(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, port, 0, 0)),
+        }
+    }
+
+    /// Parse a comma-separated list of socket addresses (`192.168.1.5:7777`,
+    /// `[::]:7777`), bare IPs (ephemeral port) or a bare port (every
+    /// interface of both families)
+    ///
+    /// At most one address per family.
+    pub fn parse(list: &str) -> anyhow::Result<Self> {
+        let mut addrs = Self::default();
+        for item in list.split(',').map(str::trim) {
+            let parsed = if let Ok(port) = item.parse::<u16>() {
+                Self::port(port)
+            } else {
+                let addr = match item.parse::<IpAddr>() {
+                    Ok(ip) => SocketAddr::new(ip, 0),
+                    Err(_) => item
+                        .parse()
+                        .map_err(|_| anyhow::anyhow!("Invalid bind address {:?}", item))?,
+                };
+                match addr {
+                    SocketAddr::V4(v4) => Self {
+                        v4: Some(v4),
+                        v6: None,
+                    },
+                    SocketAddr::V6(v6) => Self {
+                        v4: None,
+                        v6: Some(v6),
+                    },
+                }
+            };
+            anyhow::ensure!(
+                !(addrs.v4.is_some() && parsed.v4.is_some()
+                    || addrs.v6.is_some() && parsed.v6.is_some()),
+                "More than one bind address per address family in {:?}",
+                list
+            );
+            addrs.v4 = addrs.v4.or(parsed.v4);
+            addrs.v6 = addrs.v6.or(parsed.v6);
+        }
+        Ok(addrs)
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Bind `builder` to these addresses
+    pub fn apply(&self, mut builder: Builder) -> Builder {
+        if let Some(v4) = self.v4 {
+            builder = builder.bind_addr_v4(v4);
+        }
+        if let Some(v6) = self.v6 {
+            builder = builder.bind_addr_v6(v6);
+        }
+        builder
+    }
+
+    /// Fail if a pinned port is taken right now, without binding the
+    /// endpoint
+    ///
+    /// Each address is bound briefly and released again, so a port that
+    /// passes may still be taken before the endpoint binds it.
+    pub fn check_free(&self) -> anyhow::Result<()> {
+        let pinned = [self.v4.map(SocketAddr::V4), self.v6.map(SocketAddr::V6)];
+        for addr in pinned.into_iter().flatten() {
+            if addr.port() == 0 {
+                continue;
+            }
+            if let Err(e) = UdpSocket::bind(addr) {
+                anyhow::bail!("UDP port {} is already in use: {}", addr, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Fail if `endpoint` didn't get a pinned port, because another process
+    /// holds it
+    pub fn check(&self, endpoint: &Endpoint) -> anyhow::Result<()> {
+        let bound = endpoint.bound_sockets();
+        let pinned = [self.v4.map(SocketAddr::V4), self.v6.map(SocketAddr::V6)];
+        for addr in pinned.into_iter().flatten() {
+            if addr.port() == 0 {
+                continue;
+            }
+            let got = bound
+                .iter()
+                .any(|b| b.port() == addr.port() && b.is_ipv4() == addr.is_ipv4());
+            anyhow::ensure!(got, "UDP port {} is already in use", addr);
+        }
+        Ok(())
+    }
+}
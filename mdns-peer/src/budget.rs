@@ -0,0 +1,99 @@
+//! Caps on what the library holds in memory on the host's behalf
+//!
+//! Inside an iOS app extension the whole process gets a few dozen
+//! megabytes, and some of the library's buffers grow with the network
+//! rather than with anything the host does: messages queued for peers that
+//! are away, peers announced on a busy LAN, events the host hasn't taken
+//! yet. A [`MemoryBudget`] caps each of these pools. When one is full, its
+//! [`DropPolicy`] decides whether the oldest entries make room or the new
+//! one is turned away, and a [`PeerEvent::OverBudget`] reports how many
+//! were dropped, at most once a second per pool.
+//!
+//! | Pool               | Default | Counts                                   |
+//! |--------------------|---------|------------------------------------------|
+//! | `queued_messages`  | 32 MiB  | Payload bytes queued for all peers       |
+//! | `discovered_peers` | 1024    | Peers in the discovery registry          |
+//! | `queued_events`    | 256     | Events waiting for the FFI callback      |
+//!
+//! Events waiting for the host always make room by dropping the oldest,
+//! since the newest ones describe the current state.
+//!
+//! [`PeerEvent::OverBudget`]: crate::PeerEvent::OverBudget
+
+use std::time::{Duration, Instant};
+
+/// Shortest time between two reports about one pool
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What a full pool does with one more entry
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Turn the new entry away: a message fails to send, a newly announced
+    /// peer isn't listed
+    #[default]
+    RefuseNew,
+    /// Drop the oldest entries until the new one fits: the longest-queued
+    /// messages fail, the peers announced longest ago expire early
+    DropOldest,
+}
+
+/// Limits on the library's memory pools, see the [module docs](self)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryBudget {
+    /// Message payload bytes queued for all peers together, see
+    /// [`crate::messages`]
+    pub queued_message_bytes: usize,
+    /// Peers listed in the discovery registry, see [`crate::registry`]
+    pub discovered_peers: usize,
+    /// Events waiting for the FFI callback, see [`crate::dispatch`]
+    pub queued_events: usize,
+    /// What full message queues and a full registry do; queued events
+    /// always drop the oldest
+    pub policy: DropPolicy,
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        Self {
+            queued_message_bytes: 32 * 1024 * 1024,
+            discovered_peers: 1024,
+            queued_events: 256,
+            policy: DropPolicy::default(),
+        }
+    }
+}
+
+/// Entries one pool dropped that weren't reported yet
+#[derive(Debug, Default)]
+pub(crate) struct Overflow {
+    count: u64,
+    reported: Option<Instant>,
+}
+
+impl Overflow {
+    pub(crate) const fn new() -> Self {
+        Self {
+            count: 0,
+            reported: None,
+        }
+    }
+
+    /// Count `dropped` more entries
+    pub(crate) fn add(&mut self, dropped: u64) {
+        self.count += dropped;
+    }
+
+    /// How many entries to report, unless none were dropped or the pool
+    /// was reported on within the last [`REPORT_INTERVAL`]
+    pub(crate) fn due(&mut self) -> Option<u64> {
+        if self.count == 0
+            || self
+                .reported
+                .is_some_and(|reported| reported.elapsed() < REPORT_INTERVAL)
+        {
+            return None;
+        }
+        self.reported = Some(Instant::now());
+        Some(std::mem::take(&mut self.count))
+    }
+}
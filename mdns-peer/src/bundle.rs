@@ -0,0 +1,275 @@
+//! Moving a peer's state to another device in one encrypted file
+//!
+//! A [`StateBundle`] holds what makes a device the same peer to the others:
+//! its profiles with their secret keys, the aliases and notes it keeps,
+//! its groups, the trusted peers it keeps warm connections to and the peers
+//! it paired with. Restored on a new phone, the other devices keep
+//! recognising it and it keeps recognising them, without pairing again.
+//!
+//! Since the bundle holds secret keys, it is only ever written encrypted
+//! with a passphrase:
+//!
+//! ```text
+//! [magic: "mdnspeer"] [format: 1 byte] [rounds: u32] [salt: 16 bytes] [sealed JSON]
+//! ```
+//!
+//! The key is the passphrase stretched with `rounds` of BLAKE3 over the
+//! salt, and the JSON is sealed like a [`PairKey`] payload, so a wrong
+//! passphrase or a modified file fails to open rather than restoring
+//! garbage.
+//!
+//! [`PairKey`]: crate::seal::PairKey
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Context;
+use iroh::{NodeId, SecretKey};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::groups::PeerGroups;
+use crate::notes::{PeerNote, PeerNotes};
+use crate::options::WarmUp;
+use crate::pairing;
+use crate::profile::{Profile, ProfileStore};
+use crate::seal::PairKey;
+
+/// Start of every bundle file
+const MAGIC: &[u8; 8] = b"mdnspeer";
+
+/// Format written after [`MAGIC`]
+const FORMAT: u8 = 1;
+
+/// BLAKE3 rounds stretching the passphrase, about a second on a phone
+pub const DEFAULT_ROUNDS: u32 = 1 << 20;
+
+/// Most rounds a bundle may ask for, so a crafted file can't keep the
+/// import busy for hours
+const MAX_ROUNDS: u32 = 1 << 26;
+
+const SALT_LEN: usize = 16;
+
+const HEADER_LEN: usize = MAGIC.len() + 1 + 4 + SALT_LEN;
+
+/// Everything that moves to the new device, see the [module docs](self)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateBundle {
+    pub profiles: Vec<BundledProfile>,
+    /// Aliases and notes by node ID
+    pub notes: BTreeMap<String, PeerNote>,
+    pub groups: BTreeMap<String, BTreeSet<NodeId>>,
+    pub warm_up: Option<BundledWarmUp>,
+    /// Pairing payloads of the paired peers, see [`crate::pairing`]
+    pub paired: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundledProfile {
+    pub name: String,
+    /// Hex encoded, as in the profile files
+    pub secret_key: String,
+    pub user_data: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundledWarmUp {
+    pub alpn: String,
+    pub trusted: BTreeSet<NodeId>,
+}
+
+/// What [`StateBundle::restore`] changed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RestoreReport {
+    /// Profiles created from the bundle
+    pub profiles: Vec<String>,
+    /// Profiles left alone because one with the same name exists
+    pub skipped_profiles: Vec<String>,
+    /// Peers whose alias or notes were restored
+    pub notes: usize,
+    pub groups: usize,
+    /// Trusted peers of the bundled warm-up settings, which
+    /// [`StateBundle::restore`] leaves to the caller
+    pub trusted: Vec<NodeId>,
+    /// Paired peers in the bundle, also left to the caller
+    pub paired: usize,
+}
+
+impl StateBundle {
+    /// Gather the state kept in `profiles`, `notes` and `groups`, and the
+    /// trusted and paired peers of the options
+    pub fn collect(
+        profiles: &ProfileStore,
+        notes: &PeerNotes,
+        groups: &PeerGroups,
+        warm_up: Option<&WarmUp>,
+        paired: &[iroh::NodeAddr],
+    ) -> anyhow::Result<Self> {
+        let profiles = profiles
+            .list()?
+            .into_iter()
+            .map(|name| {
+                let profile = profiles.load_or_create(&name, None)?;
+                anyhow::Ok(BundledProfile {
+                    name: profile.name,
+                    secret_key: data_encoding::HEXLOWER.encode(&profile.secret_key.to_bytes()),
+                    user_data: profile.user_data,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            profiles,
+            notes: notes
+                .all()
+                .into_iter()
+                .map(|(node_id, note)| (node_id.to_string(), note))
+                .collect(),
+            groups: groups.all(),
+            warm_up: warm_up.map(|warm_up| BundledWarmUp {
+                alpn: String::from_utf8_lossy(&warm_up.alpn).into_owned(),
+                trusted: warm_up.trusted.iter().copied().collect(),
+            }),
+            paired: paired.iter().map(pairing::encode_payload).collect(),
+        })
+    }
+
+    /// Write the profiles, notes and groups into the stores, merging with
+    /// what they hold
+    ///
+    /// Existing profiles win over bundled ones with the same name, so an
+    /// import never replaces an identity. The warm-up settings and paired
+    /// peers are for the caller to apply, see [`StateBundle::warm_up`] and
+    /// [`StateBundle::paired_addrs`].
+    pub fn restore(
+        &self,
+        profiles: &ProfileStore,
+        notes: &PeerNotes,
+        groups: &PeerGroups,
+    ) -> anyhow::Result<RestoreReport> {
+        let mut report = RestoreReport::default();
+        for bundled in &self.profiles {
+            let profile = Profile {
+                name: bundled.name.clone(),
+                secret_key: bundled
+                    .secret_key
+                    .parse::<SecretKey>()
+                    .with_context(|| format!("Invalid secret key of profile {}", bundled.name))?,
+                user_data: bundled.user_data.clone(),
+            };
+            if profiles.insert(&profile)? {
+                report.profiles.push(profile.name);
+            } else {
+                report.skipped_profiles.push(profile.name);
+            }
+        }
+        for (node_id, note) in &self.notes {
+            let node_id: NodeId = node_id
+                .parse()
+                .with_context(|| format!("Invalid node ID {:?} in notes", node_id))?;
+            if note.alias.is_some() {
+                notes.set_alias(node_id, note.alias.as_deref())?;
+            }
+            if note.notes.is_some() {
+                notes.set_notes(node_id, note.notes.as_deref())?;
+            }
+            report.notes += 1;
+        }
+        for (group, members) in &self.groups {
+            groups.create(group)?;
+            for &node_id in members {
+                groups.add(group, node_id)?;
+            }
+            report.groups += 1;
+        }
+        if let Some(warm_up) = &self.warm_up {
+            report.trusted = warm_up.trusted.iter().copied().collect();
+        }
+        report.paired = self.paired.len();
+        Ok(report)
+    }
+
+    /// The bundled warm-up settings, for [`PeerOptions::warm_up`]
+    ///
+    /// [`PeerOptions::warm_up`]: crate::PeerOptions::warm_up
+    pub fn warm_up(&self) -> Option<WarmUp> {
+        self.warm_up.as_ref().map(|warm_up| WarmUp {
+            alpn: warm_up.alpn.as_bytes().to_vec(),
+            trusted: warm_up.trusted.iter().copied().collect(),
+        })
+    }
+
+    /// The paired peers, for [`PeerOptions::paired`]
+    ///
+    /// [`PeerOptions::paired`]: crate::PeerOptions::paired
+    pub fn paired_addrs(&self) -> anyhow::Result<Vec<iroh::NodeAddr>> {
+        self.paired
+            .iter()
+            .map(|payload| pairing::decode_payload(payload))
+            .collect()
+    }
+
+    /// The bundle encrypted with `passphrase`
+    pub fn seal(&self, passphrase: &str) -> anyhow::Result<Vec<u8>> {
+        self.seal_with_rounds(passphrase, DEFAULT_ROUNDS)
+    }
+
+    /// [`StateBundle::seal`] with `rounds` of stretching instead of
+    /// [`DEFAULT_ROUNDS`]
+    pub fn seal_with_rounds(&self, passphrase: &str, rounds: u32) -> anyhow::Result<Vec<u8>> {
+        anyhow::ensure!(!passphrase.is_empty(), "The passphrase is empty");
+        anyhow::ensure!(
+            (1..=MAX_ROUNDS).contains(&rounds),
+            "Rounds must be 1 to {}",
+            MAX_ROUNDS
+        );
+        let mut salt = [0; SALT_LEN];
+        rand::rngs::OsRng.fill_bytes(&mut salt);
+
+        let json = serde_json::to_vec(self)?;
+        let mut bundle = Vec::with_capacity(HEADER_LEN + json.len() + 64);
+        bundle.extend(MAGIC);
+        bundle.push(FORMAT);
+        bundle.extend(rounds.to_be_bytes());
+        bundle.extend(salt);
+        bundle.extend(bundle_key(passphrase, &salt, rounds).seal(&json));
+        Ok(bundle)
+    }
+
+    /// Decrypt a bundle written by [`StateBundle::seal`]
+    pub fn open(bundle: &[u8], passphrase: &str) -> anyhow::Result<Self> {
+        let Some((header, sealed)) = bundle.split_first_chunk::<HEADER_LEN>() else {
+            anyhow::bail!("Not a state bundle: too short");
+        };
+        let (magic, rest) = header.split_at(MAGIC.len());
+        anyhow::ensure!(magic == MAGIC, "Not a state bundle");
+        anyhow::ensure!(
+            rest[0] == FORMAT,
+            "Unknown state bundle format {}; update the app to import it",
+            rest[0]
+        );
+        let rounds = u32::from_be_bytes(rest[1..5].try_into().expect("4 bytes"));
+        anyhow::ensure!(
+            (1..=MAX_ROUNDS).contains(&rounds),
+            "Corrupt state bundle: {} rounds",
+            rounds
+        );
+        let salt = &rest[5..];
+
+        let json = bundle_key(passphrase, salt, rounds)
+            .open(sealed)
+            .map_err(|_| anyhow::anyhow!("Wrong passphrase, or the bundle was modified"))?;
+        serde_json::from_slice(&json).context("Corrupt state bundle")
+    }
+}
+
+/// Key sealing a bundle: the passphrase stretched over `salt`
+fn bundle_key(passphrase: &str, salt: &[u8], rounds: u32) -> PairKey {
+    let mut hasher = blake3::Hasher::new_derive_key("mdns-peer state bundle");
+    hasher.update(salt);
+    hasher.update(passphrase.as_bytes());
+    let mut key = *hasher.finalize().as_bytes();
+    for _ in 1..rounds {
+        key = *blake3::keyed_hash(&key, salt).as_bytes();
+    }
+    let secret = SecretKey::from_bytes(&key);
+    PairKey::new(&secret, &secret.public())
+}
@@ -0,0 +1,97 @@
+//! Peer candidates the host learned over another channel
+//!
+//! When mDNS can't work, the app may still hear about nearby peers some other
+//! way, e.g. a CoreBluetooth advertisement carrying a node ticket. Injecting
+//! those into [`Candidates`] lets the endpoint dial them by node ID as if they
+//! had been discovered.
+//!
+//! Out-of-band channels tend to repeat themselves, so candidates are keyed by
+//! node ID: injecting one again replaces its addresses with the latest ones
+//! and extends its lifetime instead of adding a duplicate. A candidate that
+//! isn't refreshed within its TTL is forgotten, so a peer that walked away
+//! stops being dialed at stale addresses. Connections iroh already made stay
+//! up either way.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use iroh::discovery::static_provider::StaticProvider;
+use iroh::discovery::{Discovery, DiscoveryError, DiscoveryItem, NodeData};
+use iroh::{NodeAddr, NodeId};
+use n0_future::boxed::BoxStream;
+use tracing::debug;
+
+/// How long a candidate is kept without being injected again, unless the
+/// host picks a TTL
+pub const DEFAULT_CANDIDATE_TTL: Duration = Duration::from_secs(120);
+
+/// Injected candidates, shared between the host and the endpoint's discovery
+///
+/// Clones share the same candidates.
+#[derive(Debug, Clone, Default)]
+pub struct Candidates {
+    provider: StaticProvider,
+    expires: Arc<Mutex<HashMap<NodeId, Instant>>>,
+}
+
+impl Candidates {
+    /// Add or refresh a candidate for `ttl`; returns whether it is new
+    pub fn inject(&self, addr: NodeAddr, ttl: Duration) -> bool {
+        let node_id = addr.node_id;
+        self.remove_expired();
+        let mut expires = self.expires.lock().unwrap();
+        expires.insert(node_id, Instant::now() + ttl);
+        let new = self.provider.set_node_info(addr).is_none();
+        if new {
+            debug!("New peer candidate {}", node_id.fmt_short());
+        }
+        new
+    }
+
+    /// Forget a candidate; returns whether it was known
+    pub fn remove(&self, node_id: NodeId) -> bool {
+        self.expires.lock().unwrap().remove(&node_id);
+        self.provider.remove_node_info(node_id).is_some()
+    }
+
+    /// Forget every candidate
+    pub fn clear(&self) {
+        let mut expires = self.expires.lock().unwrap();
+        for node_id in expires.drain().map(|(node_id, _)| node_id) {
+            self.provider.remove_node_info(node_id);
+        }
+    }
+
+    /// The candidates that haven't expired, in no particular order
+    pub fn current(&self) -> Vec<NodeAddr> {
+        self.remove_expired();
+        let expires = self.expires.lock().unwrap();
+        expires
+            .keys()
+            .filter_map(|node_id| self.provider.get_node_info(*node_id))
+            .map(NodeAddr::from)
+            .collect()
+    }
+
+    fn remove_expired(&self) {
+        let now = Instant::now();
+        self.expires.lock().unwrap().retain(|node_id, expires| {
+            let alive = *expires > now;
+            if !alive {
+                debug!("Peer candidate {} expired", node_id.fmt_short());
+                self.provider.remove_node_info(*node_id);
+            }
+            alive
+        });
+    }
+}
+
+impl Discovery for Candidates {
+    fn publish(&self, _data: &NodeData) {}
+
+    fn resolve(&self, node_id: NodeId) -> Option<BoxStream<Result<DiscoveryItem, DiscoveryError>>> {
+        self.remove_expired();
+        self.provider.resolve(node_id)
+    }
+}
@@ -0,0 +1,231 @@
+//! Startup settings from the environment or a config file
+//!
+//! Lets a build's behaviour be tweaked without recompiling the library: on
+//! the desktop through `MDNS_PEER_*` environment variables, on iOS through a
+//! `MdnsPeerConfig.plist` (or `.json`) bundled with the app and handed to
+//! `peer_load_config`. Every setting is optional, and explicit settings win
+//! over both: CLI flags on the desktop, the `peer_set_*` setters on iOS.
+//!
+//! | Key                  | Variable                       | Meaning                              |
+//! |----------------------|--------------------------------|--------------------------------------|
+//! | `summary_interval`   | `MDNS_PEER_SUMMARY_INTERVAL`   | Seconds between summaries, 0 to stop |
+//! | `duplicate`          | `MDNS_PEER_DUPLICATE`          | `refuse`, `suffix` or `allow`        |
+//! | `relays`             | `MDNS_PEER_RELAYS`             | Relay URLs (comma-separated in env)  |
+//! | `lan_only`           | `MDNS_PEER_LAN_ONLY`           | `true` to never use a relay          |
+//! | `record`             | `MDNS_PEER_RECORD`             | Path to record discovery events to   |
+//! | `mdns_service`       | `MDNS_PEER_MDNS_SERVICE`       | mDNS service name                    |
+//! | `mdns_cadence`       | `MDNS_PEER_MDNS_CADENCE`       | Seconds between announcements        |
+//! | `mdns_response_rate` | `MDNS_PEER_MDNS_RESPONSE_RATE` | Swarm-wide responses per second      |
+//! | `bind`               | `MDNS_PEER_BIND`               | Addresses or port to bind            |
+//! | `seed`               | `MDNS_PEER_SEED`               | Fixed key, for stable node IDs       |
+//! | `log_names`          | `MDNS_PEER_LOG_NAMES`          | `true` to log names unhashed         |
+//! | `dns_fallback`       | `MDNS_PEER_DNS_FALLBACK`       | Seconds before DNS fallback, 0: off  |
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::bind::BindAddrs;
+use crate::fallback::DnsFallback;
+use crate::options::PeerOptions;
+use crate::profile::seeded_key;
+
+/// Prefix of the environment variables [`PeerConfig::from_env`] reads
+pub const ENV_PREFIX: &str = "MDNS_PEER_";
+
+/// Settings that override [`PeerOptions`] defaults, `None` where unset
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeerConfig {
+    /// Seconds between summary events, 0 to disable them
+    pub summary_interval: Option<f64>,
+    /// What to do about another local instance with the same identifier
+    pub duplicate: Option<String>,
+    /// Relays to choose the home relay from
+    pub relays: Option<Vec<String>>,
+    /// Never fall back to a relay
+    pub lan_only: Option<bool>,
+    /// Record raw discovery events to this file
+    pub record: Option<PathBuf>,
+    pub mdns_service: Option<String>,
+    /// Seconds between mDNS announcements
+    pub mdns_cadence: Option<f64>,
+    pub mdns_response_rate: Option<f32>,
+    /// Addresses or port to bind, in [`BindAddrs::parse`] syntax
+    pub bind: Option<String>,
+    /// Derive the secret key from this number instead of generating one,
+    /// for stable node IDs in demos and tests
+    pub seed: Option<u64>,
+    /// Log user data and aliases as they are instead of hashed
+    pub log_names: Option<bool>,
+    /// Seconds local discovery may find nobody before peers are looked up
+    /// in DNS, 0 to never do so
+    pub dns_fallback: Option<f64>,
+}
+
+impl PeerConfig {
+    /// Settings from the `MDNS_PEER_*` environment variables
+    pub fn from_env() -> anyhow::Result<Self> {
+        Self::from_vars(std::env::vars())
+    }
+
+    /// Settings from `MDNS_PEER_*` entries in `vars`; other entries are
+    /// ignored, and so are empty values
+    pub fn from_vars<K, V>(vars: impl IntoIterator<Item = (K, V)>) -> anyhow::Result<Self>
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let mut config = Self::default();
+        for (key, value) in vars {
+            let (key, value) = (key.as_ref(), value.as_ref().trim());
+            let Some(name) = key.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            if value.is_empty() {
+                continue;
+            }
+            let invalid = || format!("Invalid {}={:?}", key, value);
+            match name {
+                "SUMMARY_INTERVAL" => {
+                    config.summary_interval = Some(value.parse().with_context(invalid)?)
+                }
+                "DUPLICATE" => config.duplicate = Some(value.to_string()),
+                "RELAYS" => {
+                    config.relays = Some(value.split(',').map(|s| s.trim().to_string()).collect())
+                }
+                "LAN_ONLY" => config.lan_only = Some(value.parse().with_context(invalid)?),
+                "RECORD" => config.record = Some(value.into()),
+                "MDNS_SERVICE" => config.mdns_service = Some(value.to_string()),
+                "MDNS_CADENCE" => config.mdns_cadence = Some(value.parse().with_context(invalid)?),
+                "MDNS_RESPONSE_RATE" => {
+                    config.mdns_response_rate = Some(value.parse().with_context(invalid)?)
+                }
+                "BIND" => config.bind = Some(value.to_string()),
+                "SEED" => config.seed = Some(value.parse().with_context(invalid)?),
+                "LOG_NAMES" => config.log_names = Some(value.parse().with_context(invalid)?),
+                "DNS_FALLBACK" => config.dns_fallback = Some(value.parse().with_context(invalid)?),
+                // MDNS_PEER_HOME and friends belong to other modules
+                _ => {}
+            }
+        }
+        Ok(config)
+    }
+
+    /// Settings from a property list if `path` ends in `.plist`, otherwise
+    /// from JSON
+    ///
+    /// Keys are the field names; unknown keys are an error so typos don't go
+    /// unnoticed.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let is_plist = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("plist"));
+        let parsed = std::fs::read(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| Self::parse(&data, is_plist));
+        parsed.with_context(|| format!("Failed to load config {}", path.display()))
+    }
+
+    /// Settings from the contents of a property list if `is_plist`,
+    /// otherwise of a JSON file, as [`PeerConfig::load`] reads them
+    pub fn parse(data: &[u8], is_plist: bool) -> anyhow::Result<Self> {
+        if is_plist {
+            Ok(plist::from_bytes(data)?)
+        } else {
+            Ok(serde_json::from_slice(data)?)
+        }
+    }
+
+    /// These settings, with the ones set in `other` taking precedence
+    pub fn merge(self, other: PeerConfig) -> PeerConfig {
+        PeerConfig {
+            summary_interval: other.summary_interval.or(self.summary_interval),
+            duplicate: other.duplicate.or(self.duplicate),
+            relays: other.relays.or(self.relays),
+            lan_only: other.lan_only.or(self.lan_only),
+            record: other.record.or(self.record),
+            mdns_service: other.mdns_service.or(self.mdns_service),
+            mdns_cadence: other.mdns_cadence.or(self.mdns_cadence),
+            mdns_response_rate: other.mdns_response_rate.or(self.mdns_response_rate),
+            bind: other.bind.or(self.bind),
+            seed: other.seed.or(self.seed),
+            log_names: other.log_names.or(self.log_names),
+            dns_fallback: other.dns_fallback.or(self.dns_fallback),
+        }
+    }
+
+    /// Overwrite the settings in `options` that are set here
+    ///
+    /// Leaves `options` untouched if any setting is invalid.
+    pub fn apply(&self, options: &mut PeerOptions) -> anyhow::Result<()> {
+        let mut updated = options.clone();
+        if let Some(secs) = self.summary_interval {
+            updated.summary_interval = if secs == 0.0 {
+                None
+            } else {
+                Some(
+                    Duration::try_from_secs_f64(secs)
+                        .with_context(|| format!("Invalid summary interval {}", secs))?,
+                )
+            };
+        }
+        if let Some(policy) = &self.duplicate {
+            updated.on_duplicate = policy.parse()?;
+        }
+        if let Some(relays) = &self.relays {
+            let relays = relays
+                .iter()
+                .map(|url| {
+                    url.parse()
+                        .map_err(|e| anyhow::anyhow!("Invalid relay URL {:?}: {}", url, e))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            updated.relays = (!relays.is_empty()).then_some(relays);
+        }
+        if let Some(lan_only) = self.lan_only {
+            updated.lan_only = lan_only;
+        }
+        if let Some(path) = &self.record {
+            updated.record = Some(path.clone());
+        }
+        if let Some(name) = &self.mdns_service {
+            updated.mdns.service_name = name.clone();
+        }
+        if let Some(secs) = self.mdns_cadence {
+            updated.mdns.cadence = Duration::try_from_secs_f64(secs)
+                .with_context(|| format!("Invalid mDNS cadence {}", secs))?;
+        }
+        if let Some(rate) = self.mdns_response_rate {
+            updated.mdns.response_rate = rate;
+        }
+        updated.mdns.validate()?;
+        if let Some(bind) = &self.bind {
+            updated.bind = BindAddrs::parse(bind)?;
+        }
+        if let Some(seed) = self.seed {
+            warn!(
+                "Using the key derived from seed {}; never use it for real devices",
+                seed
+            );
+            updated.secret_key = Some(seeded_key(seed));
+        }
+        if let Some(log_names) = self.log_names {
+            updated.log_names = log_names;
+        }
+        if let Some(secs) = self.dns_fallback {
+            updated.dns_fallback = if secs == 0.0 {
+                None
+            } else {
+                let window = Duration::try_from_secs_f64(secs)
+                    .with_context(|| format!("Invalid DNS fallback window {}", secs))?;
+                Some(DnsFallback::new(window))
+            };
+        }
+        *options = updated;
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+//! Connection lifecycle events, separate from discovery
+//!
+//! Discovery says a peer is *around*; these events say we are actually
+//! *talking* to it. Every connection on a host protocol, in either direction,
+//! is reported when it opens ([`PeerEvent::Connected`]) and when it closes
+//! with the reason ([`PeerEvent::ConnectionClosed`]). While at least one
+//! connection to a peer is open, changes in how it is reached, e.g. from the
+//! relay to a direct LAN path once hole punching succeeds, are reported as
+//! [`PeerEvent::PathChanged`], with the local interface each path goes out
+//! on, so walking a phone out of Wi-Fi range shows up as a move from `en0`
+//! to the cellular interface. A path that starts out on, or moves to, a
+//! relay is also reported as [`PeerEvent::RelayFallback`], see
+//! [`crate::relay`].
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use iroh::{Endpoint, NodeId, Watcher};
+use serde::Serialize;
+use tokio::task::AbortHandle;
+
+use crate::events::{EventSink, PeerEvent};
+use crate::network;
+use crate::remote_info::ConnectionReport;
+use crate::supervise;
+
+/// Who opened a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// The remote connected to us
+    Inbound,
+    /// We connected to the remote
+    Outbound,
+}
+
+/// Open connections per peer, and the path watcher running for each peer
+#[derive(Debug, Default)]
+pub(crate) struct ConnectionTracker {
+    peers: Mutex<HashMap<NodeId, (usize, AbortHandle)>>,
+}
+
+impl ConnectionTracker {
+    /// Count a new connection to `node_id`, watching its path if it is the
+    /// first one
+    pub(crate) fn opened(&self, endpoint: &Endpoint, node_id: NodeId, events: EventSink) {
+        let mut peers = self.peers.lock().unwrap();
+        if let Some((count, _)) = peers.get_mut(&node_id) {
+            *count += 1;
+            return;
+        }
+        let watcher = supervise::spawn_supervised(
+            "path_watch",
+            events.clone(),
+            watch_path(endpoint.clone(), node_id, events),
+        );
+        peers.insert(node_id, (1, watcher.abort_handle()));
+    }
+
+    /// Count a connection to `node_id` as closed, stopping the path watcher
+    /// after the last one
+    pub(crate) fn closed(&self, node_id: NodeId) {
+        let mut peers = self.peers.lock().unwrap();
+        if let Some((count, watcher)) = peers.get_mut(&node_id) {
+            *count -= 1;
+            if *count == 0 {
+                watcher.abort();
+                peers.remove(&node_id);
+            }
+        }
+    }
+}
+
+/// How `endpoint` currently reaches `node_id`
+pub(crate) fn current_path(endpoint: &Endpoint, node_id: NodeId) -> ConnectionReport {
+    endpoint
+        .conn_type(node_id)
+        .map_or(ConnectionReport::None, |mut conn_type| {
+            conn_type.get().into()
+        })
+}
+
+/// Emit [`PeerEvent::PathChanged`] whenever the path to `node_id` changes,
+/// and [`PeerEvent::RelayFallback`] whenever it starts using a relay
+async fn watch_path(endpoint: Endpoint, node_id: NodeId, events: EventSink) {
+    let Some(mut conn_type) = endpoint.conn_type(node_id) else {
+        return;
+    };
+    let mut previous: ConnectionReport = conn_type.get().into();
+    if let Some(url) = relay_url(&previous) {
+        events(&PeerEvent::RelayFallback { node_id, url });
+    }
+    while let Ok(current) = conn_type.updated().await {
+        let current: ConnectionReport = current.into();
+        if current != previous {
+            if let (None, Some(url)) = (relay_url(&previous), relay_url(&current)) {
+                events(&PeerEvent::RelayFallback { node_id, url });
+            }
+            events(&PeerEvent::PathChanged {
+                node_id,
+                previous_interface: path_interface(&previous),
+                current_interface: path_interface(&current),
+                previous: std::mem::replace(&mut previous, current.clone()),
+                current,
+            });
+        }
+    }
+}
+
+/// The relay `path` sends through, alone or next to a direct address
+fn relay_url(path: &ConnectionReport) -> Option<String> {
+    match path {
+        ConnectionReport::Relay { url } | ConnectionReport::Mixed { url, .. } => Some(url.clone()),
+        ConnectionReport::Direct { .. } | ConnectionReport::None => None,
+    }
+}
+
+/// Name of the local interface `path` most likely goes out on: the one whose
+/// subnet holds the remote address, otherwise the one with the default route
+///
+/// `None` without a path, or if the interfaces can't be listed. Reported
+/// names are read when the path changes, so the previous path's interface
+/// may already be gone.
+fn path_interface(path: &ConnectionReport) -> Option<String> {
+    let remote = match path {
+        ConnectionReport::Direct { addr } | ConnectionReport::Mixed { addr, .. } => Some(addr.ip()),
+        ConnectionReport::Relay { .. } => None,
+        ConnectionReport::None => return None,
+    };
+    let interfaces = netdev::get_interfaces();
+    let on_link = remote.and_then(|ip| network::on_link_interface(&interfaces, ip));
+    on_link
+        .or_else(|| interfaces.iter().find(|iface| iface.default))
+        .map(|iface| iface.name.clone())
+}
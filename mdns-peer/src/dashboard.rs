@@ -0,0 +1,428 @@
+//! Browser dashboard for `mdns-peer daemon --dashboard <addr>`
+//!
+//! Serves a single embedded page with the live peer table, connect and ping
+//! buttons per peer, and the event log, so discovery can be watched from any
+//! machine on the LAN without the CLI. The HTTP side is deliberately tiny:
+//!
+//! | Route                               | Response                                    |
+//! | ----------------------------------- | ------------------------------------------- |
+//! | `GET /`                             | The dashboard page                          |
+//! | `GET /api/peers`                    | Current peer table as JSON                  |
+//! | `GET /api/events`                   | Server-sent events, one [`TimedEvent`] each |
+//! | `GET /api/tasks`                    | Running tasks, see [`crate::tasks`]         |
+//! | `POST /api/peers/<node_id>/connect` | Connect and report the connection type      |
+//! | `POST /api/peers/<node_id>/ping`    | Connect if needed and report the RTT        |
+//!
+//! Connecting uses [`DASHBOARD_ALPN`], which the daemon registers, so the
+//! buttons work against other daemons; peers without it refuse the
+//! handshake and the error is shown instead. Connect and ping give up after
+//! [`ACTION_TIMEOUT`], or `?timeout_ms=<n>`, and answer
+//! `504 Gateway Timeout`.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use iroh::NodeId;
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tracing::{debug, info};
+
+use crate::deadline;
+use crate::events::{EventSink, PeerEvent, TimedEvent};
+use crate::protocols::{Protocols, StreamHandler, StreamId};
+use crate::remote_info::ConnectionReport;
+use crate::tasks::{self, TaskReport};
+
+/// ALPN the dashboard connects on
+pub const DASHBOARD_ALPN: &[u8] = b"mdns-peer/dashboard/0";
+
+const PAGE: &str = include_str!("dashboard.html");
+
+/// Events kept for browsers that connect later
+const EVENT_LOG_LEN: usize = 200;
+
+/// Largest request head accepted
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+
+/// Comment sent on idle event streams so proxies don't close them
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How long connect and ping wait unless the request says otherwise
+pub const ACTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One row of the peer table
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PeerRow {
+    pub node_id: NodeId,
+    pub user_data: Option<String>,
+    /// As of the last summary, see [`crate::notes`]
+    pub alias: Option<String>,
+    pub notes: Option<String>,
+    pub provenance: Option<&'static str>,
+    /// As of the last summary
+    pub connection: ConnectionReport,
+    pub warm: bool,
+    /// As of the last summary
+    pub expires_in_ms: Option<u64>,
+}
+
+/// Result of a connect or ping from the dashboard
+#[derive(Debug, Clone, Serialize)]
+pub struct ActionReport {
+    pub node_id: NodeId,
+    pub connection: ConnectionReport,
+    /// QUIC's round-trip estimate for the connection
+    pub rtt_ms: f64,
+    /// Time taken to connect, `None` if a connection was already open
+    pub handshake_ms: Option<f64>,
+}
+
+/// Peer table and event log fed by the daemon's events
+#[derive(Clone)]
+pub struct Dashboard {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    protocols: Protocols,
+    peers: Mutex<BTreeMap<NodeId, PeerRow>>,
+    log: Mutex<VecDeque<TimedEvent>>,
+    live: broadcast::Sender<TimedEvent>,
+}
+
+impl Dashboard {
+    /// A dashboard acting through `protocols`, registering [`DASHBOARD_ALPN`]
+    /// on it
+    ///
+    /// Create it before the peer starts, so the ALPN is accepted.
+    pub fn new(protocols: Protocols) -> Self {
+        protocols.register(DASHBOARD_ALPN, Arc::new(IgnoreStreams));
+        let (live, _) = broadcast::channel(EVENT_LOG_LEN);
+        Self {
+            inner: Arc::new(Inner {
+                protocols,
+                peers: Mutex::new(BTreeMap::new()),
+                log: Mutex::new(VecDeque::with_capacity(EVENT_LOG_LEN)),
+                live,
+            }),
+        }
+    }
+
+    /// Sink to pass to [`run_peer`](crate::run_peer)
+    pub fn event_sink(&self) -> EventSink {
+        let dashboard = self.clone();
+        Arc::new(move |event| dashboard.record(event))
+    }
+
+    /// Update the table and log with `event`, and push it to open browsers
+    pub fn record(&self, event: &PeerEvent) {
+        self.apply(event);
+
+        let timed = TimedEvent::now(event.clone());
+        let mut log = self.inner.log.lock().unwrap();
+        if log.len() == EVENT_LOG_LEN {
+            log.pop_front();
+        }
+        log.push_back(timed.clone());
+        // No receivers just means no browser is watching
+        let _ = self.inner.live.send(timed);
+    }
+
+    fn apply(&self, event: &PeerEvent) {
+        let mut peers = self.inner.peers.lock().unwrap();
+        match event {
+            PeerEvent::Discovered {
+                node_id,
+                user_data,
+                provenance,
+                ..
+            } => {
+                let row = peers.entry(*node_id).or_insert_with(|| PeerRow {
+                    node_id: *node_id,
+                    user_data: None,
+                    alias: None,
+                    notes: None,
+                    provenance: None,
+                    connection: ConnectionReport::None,
+                    warm: false,
+                    expires_in_ms: None,
+                });
+                row.user_data = user_data.clone();
+                row.provenance = Some(provenance);
+            }
+            PeerEvent::Expired { node_id, .. } => {
+                peers.remove(node_id);
+            }
+            PeerEvent::Summary { peers: summary, .. } => {
+                for peer in summary {
+                    if let Some(row) = peers.get_mut(&peer.node_id) {
+                        row.alias = peer.alias.clone();
+                        row.notes = peer.notes.clone();
+                        row.connection = peer.connection.clone();
+                        row.warm = peer.warm;
+                        row.expires_in_ms = Some(peer.expires_in_ms);
+                    }
+                }
+            }
+            PeerEvent::Connected {
+                node_id,
+                connection,
+                ..
+            }
+            | PeerEvent::PathChanged {
+                node_id,
+                current: connection,
+                ..
+            } => {
+                if let Some(row) = peers.get_mut(node_id) {
+                    row.connection = connection.clone();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The peer table, ordered by node ID
+    pub fn peers(&self) -> Vec<PeerRow> {
+        self.inner.peers.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Connect to `node_id` on [`DASHBOARD_ALPN`], reusing an open
+    /// connection, failing with [`TimedOut`](deadline::TimedOut) after
+    /// `timeout`
+    pub async fn connect(
+        &self,
+        node_id: NodeId,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<ActionReport> {
+        let protocols = &self.inner.protocols;
+        let endpoint = protocols
+            .endpoint()
+            .ok_or_else(|| anyhow::anyhow!("The peer isn't running yet"))?;
+
+        let started = Instant::now();
+        let fresh = !protocols.is_connected(node_id, DASHBOARD_ALPN);
+        let conn = protocols
+            .connect_within(&endpoint, node_id, DASHBOARD_ALPN, timeout)
+            .await?;
+        let handshake = fresh.then(|| started.elapsed());
+
+        Ok(ActionReport {
+            node_id,
+            connection: endpoint
+                .remote_info(node_id)
+                .map_or(ConnectionReport::None, |info| info.conn_type.into()),
+            rtt_ms: millis(conn.rtt()),
+            handshake_ms: handshake.map(millis),
+        })
+    }
+
+    /// Serve the dashboard on `addr` until the process exits
+    ///
+    /// Returns the bound address (useful with port 0) once listening.
+    pub async fn spawn(&self, addr: SocketAddr) -> anyhow::Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to bind the dashboard to {}: {}", addr, e))?;
+        let addr = listener.local_addr()?;
+        info!("Dashboard on http://{}", addr);
+
+        let dashboard = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let Ok((stream, remote)) = listener.accept().await else {
+                    continue;
+                };
+                let dashboard = dashboard.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = dashboard.serve(stream).await {
+                        debug!("Dashboard request from {} failed: {:#}", remote, e);
+                    }
+                });
+            }
+        });
+        Ok(addr)
+    }
+
+    /// Answer one request on `stream`
+    async fn serve(&self, mut stream: TcpStream) -> anyhow::Result<()> {
+        let Some((method, path, query)) = read_request(&mut stream).await? else {
+            return respond(&mut stream, "400 Bad Request", "text/plain", "Bad request").await;
+        };
+
+        match (method.as_str(), path.as_str()) {
+            ("GET", "/") => respond(&mut stream, "200 OK", "text/html; charset=utf-8", PAGE).await,
+            ("GET", "/api/peers") => {
+                let json = serde_json::to_string(&self.peers())?;
+                respond(&mut stream, "200 OK", "application/json", &json).await
+            }
+            ("GET", "/api/events") => self.stream_events(stream).await,
+            ("GET", "/api/tasks") => {
+                let json = serde_json::to_string(&tasks::tasks())?;
+                respond(&mut stream, "200 OK", "application/json", &json).await
+            }
+            ("POST", path) => match parse_action(path, &query) {
+                Some((node_id, "connect" | "ping", timeout)) => {
+                    let (status, json) = match self.connect(node_id, timeout).await {
+                        Ok(report) => ("200 OK", serde_json::to_string(&report)?),
+                        Err(e) => (
+                            if deadline::is_timed_out(&e) {
+                                "504 Gateway Timeout"
+                            } else {
+                                "502 Bad Gateway"
+                            },
+                            serde_json::json!({ "error": format!("{:#}", e) }).to_string(),
+                        ),
+                    };
+                    respond(&mut stream, status, "application/json", &json).await
+                }
+                _ => respond(&mut stream, "404 Not Found", "text/plain", "Not found").await,
+            },
+            _ => respond(&mut stream, "404 Not Found", "text/plain", "Not found").await,
+        }
+    }
+
+    /// The event log, and a receiver for every event after it
+    pub fn subscribe(&self) -> (Vec<TimedEvent>, broadcast::Receiver<TimedEvent>) {
+        // Subscribe before copying the log so nothing falls in between
+        let live = self.inner.live.subscribe();
+        let backlog = self.inner.log.lock().unwrap().iter().cloned().collect();
+        (backlog, live)
+    }
+
+    /// Send the event log, then every new event, until the browser goes away
+    async fn stream_events(&self, mut stream: TcpStream) -> anyhow::Result<()> {
+        let (backlog, mut live) = self.subscribe();
+
+        stream
+            .write_all(
+                b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+                  Cache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n",
+            )
+            .await?;
+        for event in backlog {
+            stream.write_all(sse_frame(&event).as_bytes()).await?;
+        }
+
+        let mut keep_alive = crate::suspend::interval(KEEP_ALIVE_INTERVAL);
+        loop {
+            tokio::select! {
+                event = live.recv() => match event {
+                    Ok(event) => stream.write_all(sse_frame(&event).as_bytes()).await?,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        debug!("Dashboard event stream skipped {} events", missed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                },
+                _ = keep_alive.tick() => stream.write_all(b": keep-alive\n\n").await?,
+            }
+        }
+    }
+}
+
+/// Accepts streams on [`DASHBOARD_ALPN`] without doing anything; the
+/// dashboard only needs the connection
+struct IgnoreStreams;
+
+impl StreamHandler for IgnoreStreams {
+    fn on_open(&self, _stream: StreamId, _node_id: NodeId) {}
+    fn on_data(&self, _stream: StreamId, _data: &[u8]) {}
+    fn on_close(&self, _stream: StreamId, _error: Option<&str>) {}
+}
+
+/// Method, path and query of the request on `stream`, `None` if it isn't
+/// HTTP
+///
+/// Only the head is read; the dashboard's requests have no body.
+async fn read_request(stream: &mut TcpStream) -> anyhow::Result<Option<(String, String, String)>> {
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || head.len() + n > MAX_REQUEST_LEN {
+            return Ok(None);
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let mut parts = head.lines().next().unwrap_or_default().split(' ');
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Ok(None);
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    Ok(Some((
+        method.to_string(),
+        path.to_string(),
+        query.to_string(),
+    )))
+}
+
+/// The tasks running in the daemon whose dashboard listens on `addr`, see
+/// `GET /api/tasks`
+pub async fn fetch_tasks(addr: SocketAddr) -> anyhow::Result<Vec<TaskReport>> {
+    let mut stream = TcpStream::connect(addr)
+        .await
+        .map_err(|e| anyhow::anyhow!("No dashboard on {}: {}", addr, e))?;
+    let request = format!(
+        "GET /api/tasks HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        addr
+    );
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| anyhow::anyhow!("Malformed response from {}", addr))?;
+    let status = head.lines().next().unwrap_or_default();
+    anyhow::ensure!(
+        status.split(' ').nth(1) == Some("200"),
+        "{} answered {:?}; is it a daemon of this version?",
+        addr,
+        status
+    );
+    Ok(serde_json::from_str(body)?)
+}
+
+/// `/api/peers/<node_id>/<action>`, and the timeout from an optional
+/// `timeout_ms=<n>` query, where 0 waits as long as it takes
+fn parse_action<'a>(path: &'a str, query: &str) -> Option<(NodeId, &'a str, Option<Duration>)> {
+    let (node_id, action) = path.strip_prefix("/api/peers/")?.split_once('/')?;
+    let timeout = match query.strip_prefix("timeout_ms=") {
+        Some(ms) => deadline::from_millis(ms.parse().ok()?),
+        None => Some(ACTION_TIMEOUT),
+    };
+    Some((node_id.parse().ok()?, action, timeout))
+}
+
+async fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> anyhow::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+fn sse_frame(event: &TimedEvent) -> String {
+    format!("data: {}\n\n", event.to_json())
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,106 @@
+//! Timeouts the caller picks for operations that wait on the network
+//!
+//! Connecting, pinging, sending a message and starting a transfer can each
+//! wait on the remote for as long as iroh keeps trying, which the host can't
+//! see or change. The `*_within` variants of these operations take a
+//! timeout instead and fail with [`TimedOut`] once it passes, which callers
+//! tell apart from other failures with [`is_timed_out`].
+//!
+//! Over FFI, `timeout_ms` of 0 keeps the old behaviour. Blocking calls such
+//! as `peer_connect` return a [`ResultCode`]; background operations report
+//! the timeout where they report other failures: a stream's `on_close`
+//! error, a `message_failed` event with `timed_out` set, a
+//! `transfer_finished` event with the `timed_out` outcome. Error text from a
+//! missed timeout always starts with `Timed out`.
+
+use std::future::Future;
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::operations;
+
+/// An operation didn't finish within the timeout it was given
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedOut {
+    /// What was waited for, e.g. `connect`
+    pub operation: &'static str,
+    pub after: Duration,
+}
+
+impl std::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Timed out after {} ms waiting to {}",
+            self.after.as_millis(),
+            self.operation
+        )
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Run `operation`, failing with [`TimedOut`] if it takes longer than
+/// `timeout`; `None` waits as long as it takes
+pub async fn within<T>(
+    timeout: Option<Duration>,
+    operation: &'static str,
+    future: impl Future<Output = anyhow::Result<T>>,
+) -> anyhow::Result<T> {
+    let Some(after) = timeout else {
+        return future.await;
+    };
+    match tokio::time::timeout(after, future).await {
+        Ok(result) => result,
+        Err(_) => Err(TimedOut { operation, after }.into()),
+    }
+}
+
+/// Whether `err`, or anything it wraps, is a [`TimedOut`]
+pub fn is_timed_out(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| cause.is::<TimedOut>())
+}
+
+/// The timeout for an FFI `timeout_ms`, where 0 means none
+pub fn from_millis(timeout_ms: u64) -> Option<Duration> {
+    (timeout_ms > 0).then(|| Duration::from_millis(timeout_ms))
+}
+
+/// Result of an FFI operation such as `peer_connect`
+///
+/// The discriminants are part of the C ABI; events carry the snake_case
+/// name.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ResultCode {
+    Ok = 0,
+    /// A null or malformed argument
+    InvalidArgument = 1,
+    /// The peer isn't running
+    NotRunning = 2,
+    /// The timeout passed first
+    TimedOut = 3,
+    /// Any other failure, logged with its reason
+    Failed = 4,
+    /// Stopped with [`crate::operations::cancel`]
+    Cancelled = 5,
+}
+
+impl ResultCode {
+    /// [`ResultCode::TimedOut`] for a [`TimedOut`] error,
+    /// [`ResultCode::Cancelled`] for a
+    /// [`Cancelled`](operations::Cancelled) one, otherwise
+    /// [`ResultCode::Failed`]
+    pub fn from_error(err: &anyhow::Error) -> Self {
+        if is_timed_out(err) {
+            Self::TimedOut
+        } else if operations::is_cancelled(err) {
+            Self::Cancelled
+        } else {
+            Self::Failed
+        }
+    }
+}
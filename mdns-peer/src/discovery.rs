@@ -0,0 +1,175 @@
+//! Discovery event sources and the loop that consumes them
+//!
+//! The loop only sees a boxed stream of [`DiscoveryEvent`]s, so the same
+//! registry/dedup/event logic runs against a live endpoint or against a
+//! scripted sequence of synthetic events.
+//!
+//! Besides the mechanism that found a peer, every
+//! [`PeerEvent::Discovered`] carries the [`DiscoveryOrigin`] of the
+//! announcement, so a desktop on both Wi-Fi and Ethernet can tell which
+//! network a peer was seen on. Discovery doesn't pass on the address a
+//! packet came from, so the interface is the one whose subnet holds an
+//! announced address.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use iroh::{
+    discovery::{DiscoveryEvent, DiscoveryItem, Lagged},
+    Endpoint, NodeId,
+};
+use n0_future::{boxed::BoxStream, stream, StreamExt};
+
+use crate::events::{DiscoveryOrigin, PeerEvent};
+use crate::network;
+use crate::registry::PeerRegistry;
+use crate::warnings::Condition;
+
+/// Stream of raw discovery events consumed by [`run_discovery_loop`]
+pub type DiscoveryEventSource = BoxStream<Result<DiscoveryEvent, Lagged>>;
+
+/// Discovery events from a live endpoint
+pub fn endpoint_source(endpoint: &Endpoint) -> DiscoveryEventSource {
+    Box::pin(endpoint.discovery_stream())
+}
+
+/// A fixed sequence of events, delivered as fast as they are consumed
+pub fn scripted_source(events: impl IntoIterator<Item = DiscoveryEvent>) -> DiscoveryEventSource {
+    let events: Vec<_> = events.into_iter().map(Ok).collect();
+    Box::pin(stream::iter(events))
+}
+
+/// Wrap `source` so every raw event, including self-discovery and errors,
+/// increments `counter`
+///
+/// A counter that stays at zero means no mDNS traffic is reaching us at all.
+pub fn count_events(source: DiscoveryEventSource, counter: Arc<AtomicU64>) -> DiscoveryEventSource {
+    Box::pin(source.inspect(move |_| {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }))
+}
+
+/// `source` with the events of `extra` mixed in, ending when `source` does
+///
+/// Wrappers applied to `source` beforehand, such as [`count_events`], don't
+/// see the events of `extra`.
+pub fn merge_events(
+    source: DiscoveryEventSource,
+    extra: BoxStream<DiscoveryEvent>,
+) -> DiscoveryEventSource {
+    Box::pin(stream::unfold(
+        (source, Some(extra)),
+        |(mut source, mut extra)| async move {
+            loop {
+                tokio::select! {
+                    event = source.next() => return event.map(|event| (event, (source, extra))),
+                    event = async {
+                        match extra.as_mut() {
+                            Some(extra) => extra.next().await,
+                            None => std::future::pending().await,
+                        }
+                    } => match event {
+                        Some(event) => return Some((Ok(event), (source, extra))),
+                        None => extra = None,
+                    },
+                }
+            }
+        },
+    ))
+}
+
+/// Feed events from `source` through `registry`, passing each resulting
+/// [`PeerEvent`] to `on_event`
+///
+/// Events about `my_node_id` are skipped. Expiries the registry held back
+/// are reported when their grace period ends, or when the source does.
+/// Returns once the source ends.
+pub async fn run_discovery_loop(
+    mut source: DiscoveryEventSource,
+    my_node_id: NodeId,
+    registry: Arc<Mutex<PeerRegistry>>,
+    mut on_event: impl FnMut(PeerEvent),
+) {
+    let mut errors = Condition::new("discovery_errors");
+    loop {
+        let next_expiry = registry.lock().unwrap().next_expiry();
+        let event = tokio::select! {
+            event = source.next() => event,
+            _ = sleep_until(next_expiry) => {
+                let expired = registry.lock().unwrap().expire_due(Instant::now());
+                expired.into_iter().for_each(&mut on_event);
+                continue;
+            }
+        };
+        let Some(event) = event else {
+            break;
+        };
+        let event = match event {
+            Ok(event) => {
+                errors
+                    .clear(Instant::now())
+                    .into_iter()
+                    .for_each(&mut on_event);
+                event
+            }
+            Err(e) => {
+                let warning = errors.hold(Instant::now(), format!("Discovery error: {}", e));
+                warning.into_iter().for_each(&mut on_event);
+                continue;
+            }
+        };
+
+        // Skip self-discovery (not interesting)
+        let node_id = match &event {
+            DiscoveryEvent::Discovered(item) => item.node_id(),
+            DiscoveryEvent::Expired(node_id) => *node_id,
+        };
+        if node_id == my_node_id {
+            continue;
+        }
+
+        let (update, overflow) = {
+            let mut registry = registry.lock().unwrap();
+            (registry.apply(&event), registry.take_overflow())
+        };
+        // Peers pushed out to make room expire before the newcomer shows up
+        overflow.into_iter().for_each(&mut on_event);
+        if let Some(peer_event) = update {
+            on_event(peer_event);
+        }
+    }
+
+    let expired = registry.lock().unwrap().expire_pending();
+    expired.into_iter().for_each(on_event);
+}
+
+/// Wait until `deadline`, or forever without one
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Where the announcement in `item` came from, see the [module docs](self)
+///
+/// Interfaces are only listed if the announcement has direct addresses.
+pub fn origin(item: &DiscoveryItem) -> DiscoveryOrigin {
+    let data = &item.node_info().data;
+    let direct_addrs: Vec<_> = data.direct_addresses().iter().copied().collect();
+    let interfaces = if direct_addrs.is_empty() {
+        Vec::new()
+    } else {
+        netdev::get_interfaces()
+    };
+    let on_link = direct_addrs.iter().find_map(|addr| {
+        network::on_link_interface(&interfaces, addr.ip()).map(|iface| (iface, *addr))
+    });
+    DiscoveryOrigin {
+        interface: on_link.map(|(iface, _)| iface.name.clone()),
+        addr: on_link.map(|(_, addr)| addr),
+        direct_addrs,
+        relay_url: data.relay_url().map(ToString::to_string),
+    }
+}
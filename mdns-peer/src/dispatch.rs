@@ -0,0 +1,156 @@
+//! Event delivery on a dedicated thread
+//!
+//! Host callbacks can be slow (Swift hopping to the main queue, JSON
+//! decoding, UI work). Running them directly on tokio worker threads would
+//! stall discovery, and calling back into the library from inside a callback
+//! could deadlock. Instead, producers push into a bounded queue and a single
+//! thread delivers events in order. When the queue is full the oldest event
+//! is dropped and counted.
+//!
+//! Events can optionally be coalesced over a short window and delivered as a
+//! batch, which helps when a device wakes up to dozens of cached
+//! announcements at once.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Bounded, drop-oldest queue drained by one delivery thread
+pub struct EventDispatcher<T> {
+    shared: Arc<Shared<T>>,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    ready: Condvar,
+    capacity: AtomicUsize,
+    dropped: AtomicU64,
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    closed: bool,
+    /// How long to keep collecting after the first event of a batch
+    batch_window: Duration,
+}
+
+impl<T: Send + 'static> EventDispatcher<T> {
+    /// Start the delivery thread, which calls `deliver` for each event in order
+    pub fn spawn(capacity: usize, mut deliver: impl FnMut(T) + Send + 'static) -> Self {
+        Self::spawn_batched(capacity, move |batch| {
+            batch.into_iter().for_each(&mut deliver)
+        })
+    }
+
+    /// Start the delivery thread, which calls `deliver` with batches of events
+    ///
+    /// With a zero batch window (the default) a batch is whatever was queued
+    /// when the thread woke up. With a non-zero window (see
+    /// [`set_batch_window`](Self::set_batch_window)) the thread keeps
+    /// collecting for that long after the first event, coalescing bursts into
+    /// one delivery.
+    pub fn spawn_batched(
+        capacity: usize,
+        mut deliver: impl FnMut(Vec<T>) + Send + 'static,
+    ) -> Self {
+        assert!(capacity > 0, "dispatcher capacity must be non-zero");
+
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                queue: VecDeque::with_capacity(capacity),
+                closed: false,
+                batch_window: Duration::ZERO,
+            }),
+            ready: Condvar::new(),
+            capacity: AtomicUsize::new(capacity),
+            dropped: AtomicU64::new(0),
+        });
+
+        let worker = shared.clone();
+        thread::Builder::new()
+            .name("mdns-peer-events".to_string())
+            .spawn(move || {
+                while let Some(batch) = worker.next_batch() {
+                    // The lock is released here, so producers never wait on the host
+                    deliver(batch);
+                }
+            })
+            .expect("Failed to spawn event dispatch thread");
+
+        Self { shared }
+    }
+
+    /// Coalesce events for `window` after the first one of each batch
+    ///
+    /// `Duration::ZERO` disables the wait.
+    pub fn set_batch_window(&self, window: Duration) {
+        self.shared.state.lock().unwrap().batch_window = window;
+    }
+
+    /// Hold at most `capacity` events from the next push on
+    pub fn set_capacity(&self, capacity: usize) {
+        assert!(capacity > 0, "dispatcher capacity must be non-zero");
+        self.shared.capacity.store(capacity, Ordering::Relaxed);
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.capacity.load(Ordering::Relaxed)
+    }
+
+    /// Queue an event without blocking, dropping the oldest ones if full
+    pub fn push(&self, event: T) {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.closed {
+            return;
+        }
+        let capacity = self.shared.capacity.load(Ordering::Relaxed);
+        while state.queue.len() >= capacity {
+            state.queue.pop_front();
+            self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        state.queue.push_back(event);
+        drop(state);
+        self.shared.ready.notify_one();
+    }
+
+    /// Number of events dropped because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Shared<T> {
+    /// Block until at least one event is queued, then collect a batch
+    ///
+    /// Returns `None` once the dispatcher is closed and drained.
+    fn next_batch(&self) -> Option<Vec<T>> {
+        let mut state = self.state.lock().unwrap();
+        while state.queue.is_empty() {
+            if state.closed {
+                return None;
+            }
+            state = self.ready.wait(state).unwrap();
+        }
+
+        let deadline = Instant::now() + state.batch_window;
+        while !state.closed {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            state = self.ready.wait_timeout(state, deadline - now).unwrap().0;
+        }
+
+        Some(state.queue.drain(..).collect())
+    }
+}
+
+impl<T> Drop for EventDispatcher<T> {
+    /// Let the thread drain what is queued and exit, without waiting for it
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.ready.notify_one();
+    }
+}
@@ -0,0 +1,279 @@
+//! Key-value documents that sync live between peers, over iroh-docs
+//!
+//! A document maps keys to values, and every peer that joined it may write.
+//! [`SharedDocs::create`] returns a ticket; a peer that joins with it gets
+//! the document's current state and from then on every write made on either
+//! side while they are connected, so the iOS app and the desktop can edit
+//! the same data at once. When two peers write the same key, reads return
+//! the newest value.
+//!
+//! Each change is reported as [`PeerEvent::DocChanged`] once its value can
+//! be read: right away for local writes, and for remote ones once the value
+//! itself has arrived, which may be a moment after the key.
+//!
+//! Documents and values live in memory while the peer runs. They sync over
+//! the gossip instance [`crate::topics`] starts, so [`SharedDocs::attach`]
+//! takes that instead of spawning another one.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use iroh::protocol::RouterBuilder;
+use iroh::Endpoint;
+use iroh_blobs::store::mem::MemStore;
+use iroh_blobs::{BlobsProtocol, Hash};
+use iroh_docs::api::protocol::{AddrInfoOptions, ShareMode};
+use iroh_docs::api::Doc;
+use iroh_docs::engine::LiveEvent;
+use iroh_docs::protocol::Docs;
+use iroh_docs::store::Query;
+use iroh_docs::{AuthorId, ContentStatus, DocTicket};
+use iroh_gossip::net::Gossip;
+use n0_future::StreamExt;
+use serde::Serialize;
+use tokio::task::AbortHandle;
+use tracing::{debug, warn};
+
+use crate::events::{EventSink, PeerEvent};
+use crate::supervise;
+
+/// A document this peer created, and how others join it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DocShare {
+    /// Names the document in [`SharedDocs::set`] and [`SharedDocs::get`]
+    pub id: String,
+    /// Lets another peer join with write access, see [`SharedDocs::join`]
+    pub ticket: String,
+}
+
+/// Documents open on the running peer
+///
+/// Clones share the same documents. Everything but [`SharedDocs::attach`]
+/// fails while the peer isn't running.
+#[derive(Clone, Default)]
+pub struct SharedDocs {
+    running: Arc<Mutex<Option<Running>>>,
+}
+
+struct Running {
+    docs: Docs,
+    blobs: MemStore,
+    /// Signs this peer's writes
+    author: AuthorId,
+    events: EventSink,
+    /// Open documents by ID, with the task reporting their changes
+    open: BTreeMap<String, (Doc, AbortHandle)>,
+}
+
+/// What [`SharedDocs::attach`] needs the router to accept
+pub struct DocsProtocols {
+    docs: Docs,
+    blobs: BlobsProtocol,
+}
+
+impl DocsProtocols {
+    /// Accept the document and blob ALPNs on `builder`
+    pub fn accept(self, builder: RouterBuilder) -> RouterBuilder {
+        builder
+            .accept(iroh_blobs::ALPN, self.blobs)
+            .accept(iroh_docs::ALPN, self.docs)
+    }
+}
+
+impl std::fmt::Debug for SharedDocs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedDocs")
+            .field("open", &self.open())
+            .finish()
+    }
+}
+
+impl SharedDocs {
+    /// Start serving documents on `endpoint`, syncing over `gossip` and
+    /// reporting changes to `events`
+    ///
+    /// Documents from an earlier run are gone.
+    pub async fn attach(
+        &self,
+        endpoint: &Endpoint,
+        gossip: Gossip,
+        events: EventSink,
+    ) -> anyhow::Result<DocsProtocols> {
+        self.detach();
+        let blobs = MemStore::new();
+        let docs = Docs::memory()
+            .spawn(endpoint.clone(), (*blobs).clone(), gossip)
+            .await?;
+        let author = docs.author_default().await?;
+        *self.running.lock().unwrap() = Some(Running {
+            docs: docs.clone(),
+            blobs: blobs.clone(),
+            author,
+            events,
+            open: BTreeMap::new(),
+        });
+        Ok(DocsProtocols {
+            docs,
+            blobs: BlobsProtocol::new(&blobs, endpoint.clone(), None),
+        })
+    }
+
+    /// Close every document, once the peer stopped
+    pub fn detach(&self) {
+        if let Some(running) = self.running.lock().unwrap().take() {
+            for (_, watcher) in running.open.values() {
+                watcher.abort();
+            }
+        }
+    }
+
+    /// IDs of the open documents
+    pub fn open(&self) -> Vec<String> {
+        let running = self.running.lock().unwrap();
+        running
+            .as_ref()
+            .map(|running| running.open.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Create an empty document others can join with the returned ticket
+    pub async fn create(&self) -> anyhow::Result<DocShare> {
+        let docs = self.docs()?;
+        let doc = docs.create().await?;
+        let ticket = doc
+            .share(ShareMode::Write, AddrInfoOptions::RelayAndAddresses)
+            .await?;
+        let id = self.watch(doc).await?;
+        Ok(DocShare {
+            id,
+            ticket: ticket.to_string(),
+        })
+    }
+
+    /// Join the document `ticket` was created for, returning its ID
+    ///
+    /// Its contents sync in the background; joining a document that is
+    /// already open just returns its ID.
+    pub async fn join(&self, ticket: &str) -> anyhow::Result<String> {
+        let ticket: DocTicket = ticket.trim().parse()?;
+        let id = ticket.capability.id().to_string();
+        if self.open().contains(&id) {
+            return Ok(id);
+        }
+        let doc = self.docs()?.import(ticket).await?;
+        self.watch(doc).await
+    }
+
+    /// Set `key` in document `id` to `value`
+    pub async fn set(&self, id: &str, key: &[u8], value: Vec<u8>) -> anyhow::Result<()> {
+        let (doc, author) = {
+            let running = self.running.lock().unwrap();
+            let running = running
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("The peer isn't running"))?;
+            let (doc, _) = running
+                .open
+                .get(id)
+                .ok_or_else(|| anyhow::anyhow!("No open document {}", id))?;
+            (doc.clone(), running.author)
+        };
+        doc.set_bytes(author, key.to_vec(), value).await?;
+        Ok(())
+    }
+
+    /// The newest value of `key` in document `id`, by any writer
+    ///
+    /// `None` if the key was never set, or its value hasn't arrived yet.
+    pub async fn get(&self, id: &str, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        let (doc, blobs) = {
+            let running = self.running.lock().unwrap();
+            let running = running
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("The peer isn't running"))?;
+            let (doc, _) = running
+                .open
+                .get(id)
+                .ok_or_else(|| anyhow::anyhow!("No open document {}", id))?;
+            (doc.clone(), running.blobs.clone())
+        };
+        let query = Query::single_latest_per_key().key_exact(key);
+        let Some(entry) = doc.get_one(query).await? else {
+            return Ok(None);
+        };
+        match blobs.get_bytes(entry.content_hash()).await {
+            Ok(value) => Ok(Some(value.to_vec())),
+            Err(e) => {
+                debug!("Value of {:?} unavailable: {:#}", entry.key(), e);
+                Ok(None)
+            }
+        }
+    }
+
+    fn docs(&self) -> anyhow::Result<Docs> {
+        let running = self.running.lock().unwrap();
+        let running = running
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("The peer isn't running"))?;
+        Ok(running.docs.clone())
+    }
+
+    /// Keep `doc` open, reporting its changes, and return its ID
+    async fn watch(&self, doc: Doc) -> anyhow::Result<String> {
+        let id = doc.id().to_string();
+        let mut changes = doc.subscribe().await?;
+        let mut running = self.running.lock().unwrap();
+        let running = running
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("The peer isn't running"))?;
+        let events = running.events.clone();
+        let doc_id = id.clone();
+        let watcher = supervise::spawn_supervised("doc_changes", events.clone(), async move {
+            // Remote keys whose value is still on its way
+            let mut pending: HashMap<Hash, Vec<PeerEvent>> = HashMap::new();
+            while let Some(change) = changes.next().await {
+                let change = match change {
+                    Ok(change) => change,
+                    Err(e) => {
+                        warn!("Stopped watching document {}: {:#}", doc_id, e);
+                        return;
+                    }
+                };
+                match change {
+                    LiveEvent::InsertLocal { entry } => events(&PeerEvent::DocChanged {
+                        doc: doc_id.clone(),
+                        key: String::from_utf8_lossy(entry.key()).into_owned(),
+                        node_id: None,
+                    }),
+                    LiveEvent::InsertRemote {
+                        from,
+                        entry,
+                        content_status,
+                    } => {
+                        let event = PeerEvent::DocChanged {
+                            doc: doc_id.clone(),
+                            key: String::from_utf8_lossy(entry.key()).into_owned(),
+                            node_id: Some(from),
+                        };
+                        match content_status {
+                            ContentStatus::Complete => events(&event),
+                            _ => pending.entry(entry.content_hash()).or_default().push(event),
+                        }
+                    }
+                    LiveEvent::ContentReady { hash } => {
+                        for event in pending.remove(&hash).unwrap_or_default() {
+                            events(&event);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        });
+        if let Some((_, previous)) = running
+            .open
+            .insert(id.clone(), (doc, watcher.abort_handle()))
+        {
+            previous.abort();
+        }
+        Ok(id)
+    }
+}
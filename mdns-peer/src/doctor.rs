@@ -0,0 +1,424 @@
+//! Local environment checks (`mdns-peer doctor`)
+//!
+//! Most "discovery doesn't work" reports come down to the machine or the
+//! network rather than this crate: no multicast-capable interface, a VPN
+//! swallowing the multicast route, a firewall dropping inbound UDP 5353, or
+//! Wi-Fi that isolates clients. Each check here probes one of those and
+//! says what to do about a failure.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::hotspot;
+use crate::multicast::{self, bind_mdns, recv, MDNS_GROUP, MDNS_PORT};
+
+/// How long to collect our own query and answers from other responders
+const PROBE_WINDOW: Duration = Duration::from_millis(1500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+/// Outcome of one check
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+    /// What to try when the check didn't pass
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Ok,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Warn,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Fail,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tag = match self.status {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        };
+        write!(f, "[{:>4}] {}: {}", tag, self.name, self.detail)?;
+        if let Some(hint) = &self.hint {
+            write!(f, "\n       -> {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// Run every check, in order; blocks for a couple of seconds
+pub fn run_doctor() -> Vec<Check> {
+    let mut checks = vec![check_interfaces()];
+    checks.extend(check_hotspot());
+    checks.extend([check_multicast_route(), check_port()]);
+    checks.extend(check_delivery());
+    checks.extend(check_probe());
+    checks.extend(check_firewall());
+    checks
+}
+
+/// Print checks followed by a one-line verdict
+pub fn print_report(checks: &[Check]) {
+    for check in checks {
+        println!("{}", check);
+    }
+    let failed = checks.iter().filter(|c| c.status == Status::Fail).count();
+    let warned = checks.iter().filter(|c| c.status == Status::Warn).count();
+    println!();
+    match (failed, warned) {
+        (0, 0) => println!("No problems found"),
+        (0, w) => println!("{} warning(s); discovery may still work", w),
+        (f, _) => println!("{} check(s) failed; discovery is unlikely to work", f),
+    }
+}
+
+/// Interfaces that are up and have an IPv4 address should support multicast
+fn check_interfaces() -> Check {
+    const NAME: &str = "interfaces";
+
+    let usable: Vec<_> = netdev::get_interfaces()
+        .into_iter()
+        .filter(|iface| iface.is_up() && !iface.is_loopback() && !iface.ipv4.is_empty())
+        .collect();
+    if usable.is_empty() {
+        return Check::fail(
+            NAME,
+            "no interface is up with an IPv4 address",
+            "connect to Wi-Fi or Ethernet on the same network as the other peers",
+        );
+    }
+
+    let describe = |iface: &netdev::Interface| {
+        let default = if iface.default { ", default" } else { "" };
+        format!("{} ({}{})", iface.name, iface.ipv4[0].addr(), default)
+    };
+    let (multicast, unicast_only): (Vec<_>, Vec<_>) =
+        usable.iter().partition(|iface| iface.is_multicast());
+
+    let multicast = multicast.into_iter().map(describe).collect::<Vec<_>>();
+    let unicast_only = unicast_only.into_iter().map(describe).collect::<Vec<_>>();
+    if multicast.is_empty() {
+        Check::fail(
+            NAME,
+            format!("none of {} supports multicast", unicast_only.join(", ")),
+            "VPN and tunnel interfaces don't carry mDNS; use a Wi-Fi or Ethernet connection",
+        )
+    } else if !unicast_only.is_empty() {
+        Check::warn(
+            NAME,
+            format!(
+                "multicast on {}; not on {}",
+                multicast.join(", "),
+                unicast_only.join(", ")
+            ),
+            "peers reachable only through the interfaces without multicast won't be discovered",
+        )
+    } else {
+        Check::ok(NAME, format!("multicast on {}", multicast.join(", ")))
+    }
+}
+
+/// Sharing or joining an iPhone's Personal Hotspot, which isn't a problem
+/// but changes which interface and addresses discovery goes over
+fn check_hotspot() -> Option<Check> {
+    let hotspot = hotspot::current(None)?;
+    Some(Check::ok(
+        "hotspot",
+        format!("{} ({}), {}", hotspot.interface, hotspot.addr, hotspot.hint),
+    ))
+}
+
+/// Sending to the mDNS group fails outright without a multicast route
+///
+/// This is the errno 65 (`EHOSTUNREACH`) failure from the Known Issues
+/// section of the README.
+fn check_multicast_route() -> Check {
+    const NAME: &str = "multicast route";
+
+    let result = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|socket| socket.send_to(&services_query(), (MDNS_GROUP, MDNS_PORT)));
+    match result {
+        Ok(_) => Check::ok(NAME, format!("{} is routable", MDNS_GROUP)),
+        Err(e) => Check::fail(
+            NAME,
+            format!("sending to {} failed: {}", MDNS_GROUP, e),
+            if cfg!(target_os = "linux") {
+                "add a route for multicast, e.g. `sudo ip route add 224.0.0.0/4 dev <interface>`, \
+                 or disconnect a VPN that captures all traffic"
+            } else {
+                "disconnect a VPN that captures all traffic, or check that Wi-Fi is connected"
+            },
+        ),
+    }
+}
+
+/// Each interface should deliver a beacon sent out of it back to the group
+///
+/// Unlike the interface flags, this shows what the OS actually lets
+/// through; see [`crate::multicast`].
+fn check_delivery() -> Option<Check> {
+    const NAME: &str = "multicast delivery";
+
+    let report = match multicast::probe(multicast::DEFAULT_PROBE_WINDOW) {
+        Ok(report) => report,
+        Err(e) => {
+            return Some(Check::fail(
+                NAME,
+                format!("probe failed: {}", e),
+                "run the other checks' suggestions first",
+            ))
+        }
+    };
+    // No usable interface is already a failed check
+    if report.interfaces.is_empty() {
+        return None;
+    }
+
+    let describe = |iface: &multicast::InterfaceProbe| match &iface.error {
+        Some(error) => format!("{} ({})", iface.name, error),
+        None => iface.name.clone(),
+    };
+    let (delivering, silent): (Vec<_>, Vec<_>) =
+        report.interfaces.iter().partition(|iface| iface.delivers);
+    let delivering = delivering.into_iter().map(describe).collect::<Vec<_>>();
+    let silent = silent.into_iter().map(describe).collect::<Vec<_>>();
+    Some(if delivering.is_empty() {
+        Check::fail(
+            NAME,
+            format!("no beacon came back on {}", silent.join(", ")),
+            firewall_hint(),
+        )
+    } else if !silent.is_empty() {
+        Check::warn(
+            NAME,
+            format!(
+                "beacons came back on {}; not on {}",
+                delivering.join(", "),
+                silent.join(", ")
+            ),
+            "peers reachable only through the interfaces without multicast won't be discovered",
+        )
+    } else {
+        Check::ok(
+            NAME,
+            format!("beacons came back on {}", delivering.join(", ")),
+        )
+    })
+}
+
+/// The mDNS port must be shareable with any system responder
+fn check_port() -> Check {
+    const NAME: &str = "port 5353";
+
+    if let Err(e) = bind_mdns(true) {
+        return Check::fail(
+            NAME,
+            format!(
+                "can't bind UDP {} even with address reuse: {}",
+                MDNS_PORT, e
+            ),
+            "another program holds the mDNS port exclusively; stop it or find which one with \
+             `sudo lsof -i UDP:5353`",
+        );
+    }
+
+    match bind_mdns(false) {
+        Ok(_) => Check::ok(NAME, "free; no other mDNS responder is running"),
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => Check::ok(
+            NAME,
+            "shared with another mDNS responder (e.g. mDNSResponder or avahi-daemon)",
+        ),
+        Err(e) => Check::warn(
+            NAME,
+            format!("exclusive bind failed: {}", e),
+            "shared binding works, so discovery should still run",
+        ),
+    }
+}
+
+/// Send a query to the group and see who hears it
+///
+/// Our own query arriving on a socket joined to the group shows inbound
+/// multicast isn't being dropped. Because the query comes from an ephemeral
+/// port, other responders answer it by unicast (RFC 6762 section 6.7),
+/// which shows whether anything else on the LAN is reachable.
+fn check_probe() -> Vec<Check> {
+    const LOOPBACK: &str = "multicast loopback";
+    const RESPONDERS: &str = "other responders";
+
+    let probe = match Probe::run() {
+        Ok(probe) => probe,
+        Err(e) => {
+            return vec![Check::fail(
+                LOOPBACK,
+                format!("probe failed: {}", e),
+                "run the other checks' suggestions first",
+            )]
+        }
+    };
+
+    let loopback = if probe.heard_self {
+        Check::ok(LOOPBACK, "our own query came back through the group")
+    } else {
+        Check::fail(
+            LOOPBACK,
+            "our own query never arrived on the group socket",
+            firewall_hint(),
+        )
+    };
+
+    let responders = if probe.responders.is_empty() {
+        Check::warn(
+            RESPONDERS,
+            "no other device answered a service query",
+            "if other peers are on this network, it may isolate clients (guest or hotel Wi-Fi, \
+             AP isolation); try a network you control or a personal hotspot",
+        )
+    } else {
+        let addrs: Vec<_> = probe.responders.iter().map(ToString::to_string).collect();
+        Check::ok(
+            RESPONDERS,
+            format!("{} answered: {}", addrs.len(), addrs.join(", ")),
+        )
+    };
+
+    vec![loopback, responders]
+}
+
+/// What one query to the group turned up
+struct Probe {
+    heard_self: bool,
+    responders: BTreeSet<Ipv4Addr>,
+}
+
+impl Probe {
+    fn run() -> io::Result<Self> {
+        let group = bind_mdns(true)?;
+        group.join_multicast_v4(&MDNS_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+        group.set_multicast_loop_v4(true)?;
+        group.set_read_timeout(Some(Duration::from_millis(50)))?;
+
+        let sender = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        sender.set_multicast_loop_v4(true)?;
+        sender.set_read_timeout(Some(Duration::from_millis(50)))?;
+        let sender_port = sender.local_addr()?.port();
+
+        let query = services_query();
+        sender.send_to(&query, (MDNS_GROUP, MDNS_PORT))?;
+
+        let mut probe = Probe {
+            heard_self: false,
+            responders: BTreeSet::new(),
+        };
+        let mut buf = [0u8; 9000];
+        let deadline = Instant::now() + PROBE_WINDOW;
+        while Instant::now() < deadline {
+            if let Some((len, from)) = recv(&group, &mut buf)? {
+                if from.port() == sender_port && buf[..len] == query[..] {
+                    probe.heard_self = true;
+                }
+            }
+            if let Some((len, SocketAddr::V4(from))) = recv(&sender, &mut buf)? {
+                if is_response(&buf[..len]) {
+                    probe.responders.insert(*from.ip());
+                }
+            }
+        }
+        Ok(probe)
+    }
+}
+
+/// Firewall status where it can be read without privileges
+fn check_firewall() -> Option<Check> {
+    const NAME: &str = "firewall";
+
+    if cfg!(target_os = "macos") {
+        let output = std::process::Command::new("/usr/libexec/ApplicationFirewall/socketfilterfw")
+            .arg("--getblockall")
+            .output()
+            .ok()?;
+        let text = String::from_utf8_lossy(&output.stdout).to_lowercase();
+        if text.contains("enabled") || text.contains("block all incoming") {
+            return Some(Check::fail(
+                NAME,
+                "\"Block all incoming connections\" is on",
+                firewall_hint(),
+            ));
+        }
+        return Some(Check::ok(NAME, "not blocking all incoming connections"));
+    }
+
+    if cfg!(target_os = "linux") {
+        let ufw = std::fs::read_to_string("/etc/ufw/ufw.conf").unwrap_or_default();
+        if ufw.lines().any(|line| line.trim() == "ENABLED=yes") {
+            return Some(Check::warn(NAME, "ufw is enabled", firewall_hint()));
+        }
+        if std::path::Path::new("/run/firewalld").exists() {
+            return Some(Check::warn(NAME, "firewalld is running", firewall_hint()));
+        }
+    }
+    None
+}
+
+fn firewall_hint() -> &'static str {
+    if cfg!(target_os = "macos") {
+        "in System Settings > Network > Firewall, turn off \"Block all incoming connections\" \
+         and allow incoming connections for mdns-peer"
+    } else if cfg!(target_os = "linux") {
+        "allow inbound UDP 5353, e.g. `sudo ufw allow 5353/udp` or \
+         `sudo firewall-cmd --add-service=mdns`"
+    } else {
+        "allow inbound UDP 5353 in the firewall"
+    }
+}
+
+/// A DNS-SD service enumeration query (`_services._dns-sd._udp.local PTR`)
+fn services_query() -> Vec<u8> {
+    // ID 0, standard query, one question
+    let mut packet = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in ["_services", "_dns-sd", "_udp", "local"] {
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    // Root label, type PTR, class IN
+    packet.extend_from_slice(&[0, 0, 12, 0, 1]);
+    packet
+}
+
+/// Whether `packet` looks like a DNS response (QR bit set)
+fn is_response(packet: &[u8]) -> bool {
+    packet.len() >= 12 && packet[2] & 0x80 != 0
+}
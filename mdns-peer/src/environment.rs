@@ -0,0 +1,217 @@
+//! Everything an issue triager asks about first, in one report
+//!
+//! "Which version, which OS, which network?" is the first round trip on
+//! most bug reports. An [`EnvironmentReport`] answers it up front: the
+//! library and iroh versions, the platform and enabled features, the
+//! network interfaces, how discovery and relays are set up, and which key
+//! the peer runs with. It's logged as a banner when a peer starts, and the
+//! FFI returns it as JSON from `peer_get_environment`, for the app to
+//! attach to feedback.
+//!
+//! Nothing secret is included: the key shows up as its node ID, which every
+//! peer on the network sees anyway.
+
+use std::net::SocketAddr;
+
+use iroh::{Endpoint, NodeId};
+use serde::Serialize;
+use tracing::info;
+
+use crate::options::PeerOptions;
+use crate::relay;
+use crate::schema::EVENT_SCHEMA_VERSION;
+
+/// iroh release the library is built against, kept in step with
+/// `Cargo.toml`
+pub const IROH_VERSION: &str = "0.92";
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EnvironmentReport {
+    /// Version of this crate
+    pub version: &'static str,
+    pub iroh_version: &'static str,
+    /// See [`crate::schema`]
+    pub event_schema_version: u32,
+    /// `std::env::consts::OS`, e.g. `ios` or `macos`
+    pub os: &'static str,
+    pub arch: &'static str,
+    /// Cargo features the library was built with
+    pub features: Vec<&'static str>,
+    pub interfaces: Vec<InterfaceReport>,
+    /// Discovery mechanisms in use, e.g. `mdns` or
+    /// `mdns (service my-app, cadence 2s)` when tuned
+    pub discovery: Vec<String>,
+    /// `default`, `custom` or `disabled`
+    pub relay_mode: &'static str,
+    pub relays: Vec<String>,
+    /// Only discovery and outbound transfers run, see
+    /// [`PeerOptions::lite`]
+    pub lite: bool,
+    /// The key comes from the options (e.g. a stored profile) rather than
+    /// being generated for this run
+    pub persistent_key: bool,
+    /// `None` while no endpoint is bound
+    pub node_id: Option<NodeId>,
+    /// Short form of the node ID, the one the logs show
+    pub key_fingerprint: Option<String>,
+    pub bound_sockets: Vec<SocketAddr>,
+}
+
+/// One network interface as the OS lists it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InterfaceReport {
+    pub name: String,
+    pub up: bool,
+    pub multicast: bool,
+    pub loopback: bool,
+    /// Carries the default route
+    pub default: bool,
+    /// IPv4 and IPv6 addresses with their prefix lengths
+    pub addrs: Vec<String>,
+}
+
+impl EnvironmentReport {
+    /// Report on the build, this machine and `options`, with the identity
+    /// and sockets of `endpoint` if one is bound
+    pub fn collect(options: &PeerOptions, endpoint: Option<&Endpoint>) -> Self {
+        let relay_mode = if options.lan_only {
+            "disabled"
+        } else if options.relays.is_some() {
+            "custom"
+        } else {
+            "default"
+        };
+        let node_id = endpoint.map(Endpoint::node_id);
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            iroh_version: IROH_VERSION,
+            event_schema_version: EVENT_SCHEMA_VERSION,
+            os: std::env::consts::OS,
+            arch: std::env::consts::ARCH,
+            features: features(),
+            interfaces: interfaces(),
+            discovery: discovery(options),
+            relay_mode,
+            relays: relay::configured_relays(options)
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            lite: options.lite,
+            persistent_key: options.secret_key.is_some(),
+            node_id,
+            key_fingerprint: node_id.map(|id| id.fmt_short().to_string()),
+            bound_sockets: endpoint.map(Endpoint::bound_sockets).unwrap_or_default(),
+        }
+    }
+}
+
+fn features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    if cfg!(feature = "ffi") {
+        features.push("ffi");
+    }
+    if cfg!(feature = "cli") {
+        features.push("cli");
+    }
+    if cfg!(feature = "metrics") {
+        features.push("metrics");
+    }
+    if cfg!(feature = "gossip") {
+        features.push("gossip");
+    }
+    if cfg!(feature = "blobs") {
+        features.push("blobs");
+    }
+    if cfg!(feature = "docs") {
+        features.push("docs");
+    }
+    features
+}
+
+fn interfaces() -> Vec<InterfaceReport> {
+    netdev::get_interfaces()
+        .into_iter()
+        .map(|iface| InterfaceReport {
+            up: iface.is_up(),
+            multicast: iface.is_multicast(),
+            loopback: iface.is_loopback(),
+            default: iface.default,
+            addrs: iface
+                .ipv4
+                .iter()
+                .map(ToString::to_string)
+                .chain(iface.ipv6.iter().map(ToString::to_string))
+                .collect(),
+            name: iface.name,
+        })
+        .collect()
+}
+
+fn discovery(options: &PeerOptions) -> Vec<String> {
+    let mdns = &options.mdns;
+    let mut discovery = vec![if mdns.is_default() {
+        "mdns".to_string()
+    } else {
+        format!(
+            "mdns (service {}, cadence {:?}, {} Hz)",
+            mdns.service_name, mdns.cadence, mdns.response_rate
+        )
+    }];
+    discovery.push("candidates".to_string());
+    if options.p2p.is_managed() {
+        let available = options.p2p.available();
+        discovery.push(if available.is_empty() {
+            "p2p (none available)".to_string()
+        } else {
+            format!("p2p ({})", available.join(", "))
+        });
+    }
+    if !options.paired.is_empty() {
+        discovery.push(format!("paired ({})", options.paired.len()));
+    }
+    discovery
+}
+
+/// Log `report` as the startup banner
+pub fn log_environment(report: &EnvironmentReport) {
+    info!(
+        "mdns-peer {} (iroh {}, event schema {}) on {}/{}, features: {}",
+        report.version,
+        report.iroh_version,
+        report.event_schema_version,
+        report.os,
+        report.arch,
+        report.features.join(", ")
+    );
+    info!(
+        "Discovery: {}; relays: {} {:?}{}",
+        report.discovery.join(", "),
+        report.relay_mode,
+        report.relays,
+        if report.lite { "; lite" } else { "" }
+    );
+    if let Some(fingerprint) = &report.key_fingerprint {
+        info!(
+            "Key {} ({})",
+            fingerprint,
+            if report.persistent_key {
+                "persistent"
+            } else {
+                "generated for this run"
+            }
+        );
+    }
+    for iface in report.interfaces.iter().filter(|iface| iface.up) {
+        info!(
+            "Interface {}{}{}: {}",
+            iface.name,
+            if iface.default { ", default" } else { "" },
+            if iface.multicast {
+                ""
+            } else {
+                ", no multicast"
+            },
+            iface.addrs.join(" ")
+        );
+    }
+}
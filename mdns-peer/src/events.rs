@@ -0,0 +1,611 @@
+//! Events reported to the host
+//!
+//! Events serialize to JSON with a `type` tag, which is what the FFI
+//! callback hands to Swift:
+//!
+//! ```json
+//! {"type":"discovered","node_id":"a8a2...","user_data":"alice","provenance":"mdns","origin":{"interface":"en0","addr":"192.168.1.20:51234","direct_addrs":["192.168.1.20:51234"],"relay_url":null},"monotonic_ms":5120,"wall_ms":1760601600000}
+//! ```
+//!
+//! Every delivered event is a [`TimedEvent`], stamped when it was emitted
+//! with both a monotonic and a wall-clock time. Order events by
+//! `monotonic_ms`: wall time can jump when a phone resumes from suspend or
+//! syncs its clock, the monotonic clock never goes backwards.
+
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use iroh::NodeId;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::connections::Direction;
+use crate::deadline::ResultCode;
+use crate::fallback::DiscoveryMode;
+use crate::hotspot::Hotspot;
+use crate::messages::MessageId;
+use crate::operations::{OperationId, OperationKind};
+use crate::remote_info::ConnectionReport;
+use crate::transfer::{TransferId, TransferOutcome};
+
+/// Something the host should know about the peer or its neighbours
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PeerEvent {
+    /// A new peer was discovered, or a known peer changed its user data
+    Discovered {
+        #[schemars(with = "String")]
+        node_id: NodeId,
+        user_data: Option<String>,
+        provenance: &'static str,
+        /// Where the announcement came from
+        origin: DiscoveryOrigin,
+    },
+    /// A previously discovered peer stopped announcing itself
+    Expired {
+        #[schemars(with = "String")]
+        node_id: NodeId,
+        user_data: Option<String>,
+    },
+    /// A peer expired and was announced again within the grace period, so
+    /// neither was reported, see [`crate::registry`]
+    Flapped {
+        #[schemars(with = "String")]
+        node_id: NodeId,
+        user_data: Option<String>,
+        /// How long the peer was gone
+        offline_ms: u64,
+        /// Times the peer flapped since it was discovered
+        flaps: u64,
+    },
+    /// The local peer moved to a new [`PeerStatus`]
+    StatusChanged {
+        status: PeerStatus,
+        /// Why the peer stopped, set only with [`PeerStatus::Stopped`]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<ShutdownReason>,
+    },
+    /// A problem started, is still there, or went away, see
+    /// [`crate::warnings`]
+    ///
+    /// Sent when it starts, every few minutes while it lasts and once when
+    /// it clears, rather than on every check.
+    Warning {
+        /// What it is about, e.g. `no_peers` or `discovery_errors`
+        condition: String,
+        /// Whether it still holds; false once it cleared
+        active: bool,
+        /// The latest occurrence, absent once it cleared
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+        /// How long it has held, or held in total once cleared
+        duration_ms: u64,
+        /// Times it was seen since it started
+        occurrences: u64,
+    },
+    /// The local endpoint's direct addresses or home relay changed, e.g.
+    /// after a Wi-Fi roam or a VPN coming up; the new addresses are being
+    /// re-announced
+    LocalAddrsChanged {
+        previous: LocalAddrs,
+        current: LocalAddrs,
+    },
+    /// Periodic overview of the routing table, see
+    /// [`PeerOptions::summary_interval`](crate::PeerOptions::summary_interval)
+    Summary {
+        /// Nodes the endpoint knows about, discovered or not
+        routing_table_size: usize,
+        /// Peers found through discovery
+        peers: Vec<PeerSummary>,
+    },
+    /// A peer connected to one of the host's protocols, see [`crate::accept`]
+    InboundConnection {
+        #[schemars(with = "String")]
+        node_id: NodeId,
+        alpn: String,
+        /// The peer is in the trusted set
+        trusted: bool,
+        /// The connection is being served; false if the accept policy
+        /// denied it or too many inbound connections were open
+        accepted: bool,
+    },
+    /// A connection on a host protocol opened, see [`crate::connections`]
+    Connected {
+        #[schemars(with = "String")]
+        node_id: NodeId,
+        alpn: String,
+        direction: Direction,
+        /// How the peer is reached right now
+        connection: ConnectionReport,
+    },
+    /// How a connected peer is reached changed, e.g. from the relay to a
+    /// direct path
+    PathChanged {
+        #[schemars(with = "String")]
+        node_id: NodeId,
+        previous: ConnectionReport,
+        current: ConnectionReport,
+        /// Local interface each path goes out on, e.g. `en0` for Wi-Fi and
+        /// `pdp_ip0` for cellular on iOS
+        previous_interface: Option<String>,
+        current_interface: Option<String>,
+    },
+    /// Discovery switched to or from looking peers up in DNS, or started
+    /// out on local discovery, see [`crate::fallback`]
+    DiscoveryModeChanged {
+        mode: DiscoveryMode,
+        /// Why, e.g. `local discovery found no peers for 30 s`
+        reason: String,
+    },
+    /// The device joined or left an iPhone's Personal Hotspot, or started
+    /// out on one, see [`crate::hotspot`]
+    HotspotChanged {
+        /// Absent once it left
+        hotspot: Option<Hotspot>,
+    },
+    /// Traffic to a connected peer started going through a relay, so it
+    /// leaves the local network, see [`crate::relay`]
+    ///
+    /// Sent when a connection opens on a relayed path and whenever the path
+    /// moves to one, alongside [`PeerEvent::PathChanged`].
+    RelayFallback {
+        #[schemars(with = "String")]
+        node_id: NodeId,
+        url: String,
+    },
+    /// A connection failed that a relay might have carried, but
+    /// [`PeerOptions::lan_only`](crate::PeerOptions::lan_only) is set
+    RelayRefused {
+        #[schemars(with = "String")]
+        node_id: NodeId,
+        alpn: String,
+        /// Why the direct dial failed
+        reason: String,
+    },
+    /// A trusted or paired peer moved to a new key and told this one from
+    /// its old node ID, see [`crate::rotation`]
+    ///
+    /// Its alias and notes already moved to the new node ID.
+    KeyRotated {
+        #[schemars(with = "String")]
+        old_node_id: NodeId,
+        #[schemars(with = "String")]
+        new_node_id: NodeId,
+        /// The old node ID was trusted, and the new one now is instead
+        trusted: bool,
+    },
+    /// A connection on a host protocol closed
+    ConnectionClosed {
+        #[schemars(with = "String")]
+        node_id: NodeId,
+        alpn: String,
+        direction: Direction,
+        /// Why, e.g. `closed by peer: 0` or `timed out`
+        reason: String,
+    },
+    /// A connection was closed because the two peers speak incompatible
+    /// versions of a protocol, see [`crate::version`]
+    ///
+    /// Reported by both sides: the one that closed it knows the version
+    /// the remote sent, the other one only gets the reason.
+    VersionMismatch {
+        #[schemars(with = "String")]
+        node_id: NodeId,
+        alpn: String,
+        /// Version this peer speaks
+        local_version: u8,
+        /// Version the remote sent, if this peer closed the connection
+        remote_version: Option<u8>,
+        /// The close reason, naming both versions and which side needs an
+        /// update
+        reason: String,
+    },
+    /// A trusted peer was re-dialed after the app resumed, see
+    /// [`crate::reconnect`]
+    Reconnect {
+        #[schemars(with = "String")]
+        node_id: NodeId,
+        connected: bool,
+        /// Why it failed, if it did
+        error: Option<String>,
+    },
+    /// A message's stream opened, see [`crate::messages`]
+    MessageSent {
+        id: MessageId,
+        #[schemars(with = "String")]
+        node_id: NodeId,
+    },
+    /// The receiver acknowledged a message
+    MessageDelivered {
+        id: MessageId,
+        #[schemars(with = "String")]
+        node_id: NodeId,
+    },
+    /// A message didn't get through
+    MessageFailed {
+        id: MessageId,
+        #[schemars(with = "String")]
+        node_id: NodeId,
+        reason: String,
+        /// Whether it stays queued for the next attempt, or was dropped
+        retrying: bool,
+        /// Whether it was dropped for missing the timeout it was sent with,
+        /// see [`crate::deadline`]
+        timed_out: bool,
+    },
+    /// Messages from a peer were refused for going over a receive limit,
+    /// see [`crate::messages::MessageLimits`]
+    ///
+    /// Sent at most once a second per peer and limit, counting what was
+    /// refused meanwhile.
+    RateLimited {
+        #[schemars(with = "String")]
+        node_id: NodeId,
+        limit: MessageLimit,
+        /// Messages refused since the last such event about this peer
+        refused: u64,
+    },
+    /// A memory pool was full, so entries were dropped or turned away, see
+    /// [`crate::budget`]
+    ///
+    /// Sent at most once a second per pool, counting what was dropped
+    /// meanwhile.
+    OverBudget {
+        pool: MemoryPool,
+        /// The pool's limit, in bytes for queued messages and entries
+        /// otherwise
+        limit: u64,
+        /// Entries dropped or turned away since the last such event
+        dropped: u64,
+    },
+    /// A file transfer got a slot and agreed on where to start, see
+    /// [`crate::transfer`]
+    TransferStarted {
+        id: TransferId,
+        #[schemars(with = "String")]
+        node_id: NodeId,
+        name: String,
+        direction: Direction,
+        /// Bytes the receiver already had from earlier attempts
+        offset: u64,
+        size: u64,
+    },
+    /// A file transfer completed, failed or was cancelled
+    TransferFinished {
+        id: TransferId,
+        #[schemars(with = "String")]
+        node_id: NodeId,
+        name: String,
+        direction: Direction,
+        outcome: TransferOutcome,
+        /// Bytes the receiver has, including ones from earlier attempts
+        bytes: u64,
+        /// BLAKE3 hash of the whole file in hex, once both sides verified it
+        hash: Option<String>,
+        /// Why it failed, if it did
+        error: Option<String>,
+    },
+    /// An operation started with an ID, such as `peer_connect_async`,
+    /// ended, see [`crate::operations`]
+    ///
+    /// Transfers report [`PeerEvent::TransferFinished`] instead.
+    OperationFinished {
+        id: OperationId,
+        kind: OperationKind,
+        status: ResultCode,
+        /// Why it failed, if it did
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        /// QUIC's round-trip estimate in microseconds, for a ping that
+        /// succeeded
+        #[serde(skip_serializing_if = "Option::is_none")]
+        rtt_us: Option<u64>,
+    },
+    /// A peer became a direct gossip neighbour on a subscribed topic, see
+    /// `crate::topics`
+    NeighborUp {
+        topic: String,
+        #[schemars(with = "String")]
+        node_id: NodeId,
+    },
+    /// A gossip neighbour on a subscribed topic went away
+    NeighborDown {
+        topic: String,
+        #[schemars(with = "String")]
+        node_id: NodeId,
+    },
+    /// A key in an open document changed and its new value can be read,
+    /// see `crate::docs`
+    DocChanged {
+        doc: String,
+        /// The key, with invalid UTF-8 replaced
+        key: String,
+        /// The peer the change synced from, `None` for local writes
+        #[schemars(with = "Option<String>")]
+        node_id: Option<NodeId>,
+    },
+    /// A background task panicked or failed, see [`crate::supervise`]
+    Error {
+        /// Which task, e.g. `discovery` or `accept`
+        task: String,
+        message: String,
+        /// Backtrace text, if one was captured
+        backtrace: Option<String>,
+    },
+}
+
+/// When an event was emitted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, JsonSchema)]
+pub struct Timestamp {
+    /// Milliseconds since the library first stamped an event. On Apple
+    /// platforms this clock pauses while the device sleeps.
+    pub monotonic_ms: u64,
+    /// Milliseconds since the Unix epoch, as the wall clock reads it
+    pub wall_ms: u64,
+}
+
+impl Timestamp {
+    pub fn now() -> Self {
+        static START: OnceLock<Instant> = OnceLock::new();
+        let start = *START.get_or_init(Instant::now);
+        Self {
+            monotonic_ms: start.elapsed().as_millis() as u64,
+            wall_ms: wall_clock_ms(),
+        }
+    }
+}
+
+/// Milliseconds since the Unix epoch, 0 if the clock is set before it
+pub fn wall_clock_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+/// A [`PeerEvent`] with the time it was emitted, serialized as the event's
+/// own fields plus `monotonic_ms` and `wall_ms`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct TimedEvent {
+    #[serde(flatten)]
+    pub event: PeerEvent,
+    #[serde(flatten)]
+    pub at: Timestamp,
+}
+
+impl TimedEvent {
+    /// Stamp `event` with the current time
+    pub fn now(event: PeerEvent) -> Self {
+        Self {
+            event,
+            at: Timestamp::now(),
+        }
+    }
+
+    /// JSON representation handed to FFI consumers
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("TimedEvent is always serializable")
+    }
+}
+
+/// Addresses the local endpoint can be reached on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct LocalAddrs {
+    pub direct_addrs: Vec<SocketAddr>,
+    pub home_relay: Vec<String>,
+}
+
+/// Where the announcement behind a [`PeerEvent::Discovered`] came from, see
+/// [`crate::discovery::origin`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct DiscoveryOrigin {
+    /// Local interface the announcement most likely arrived on, e.g. `en0`
+    /// for Wi-Fi and `en7` for a USB Ethernet adapter on a Mac
+    pub interface: Option<String>,
+    /// The announced address on that interface's subnet
+    pub addr: Option<SocketAddr>,
+    /// Every direct address the announcement listed
+    pub direct_addrs: Vec<SocketAddr>,
+    pub relay_url: Option<String>,
+}
+
+/// One discovered peer in a [`PeerEvent::Summary`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct PeerSummary {
+    #[schemars(with = "String")]
+    pub node_id: NodeId,
+    pub user_data: Option<String>,
+    /// Local name for the peer, see [`crate::notes`]
+    pub alias: Option<String>,
+    pub notes: Option<String>,
+    pub connection: ConnectionReport,
+    /// A pre-established connection is ready
+    pub warm: bool,
+    /// Time left before the peer expires unless it announces itself again,
+    /// see [`PeerRegistry::expires_at`](crate::registry::PeerRegistry::expires_at)
+    pub expires_in_ms: u64,
+}
+
+impl PeerEvent {
+    /// A status change other than stopping
+    pub fn status(status: PeerStatus) -> Self {
+        Self::StatusChanged {
+            status,
+            reason: None,
+        }
+    }
+
+    /// The final event of a peer that stopped for `reason`
+    pub fn stopped(reason: ShutdownReason) -> Self {
+        Self::StatusChanged {
+            status: PeerStatus::Stopped,
+            reason: Some(reason),
+        }
+    }
+
+    /// JSON representation without a timestamp, see [`TimedEvent::to_json`]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("PeerEvent is always serializable")
+    }
+
+    /// The [`event_mask`] bit this event is filtered by
+    pub fn mask_bit(&self) -> u32 {
+        match self {
+            PeerEvent::Discovered { .. } => event_mask::DISCOVERED,
+            PeerEvent::Expired { .. } => event_mask::EXPIRED,
+            PeerEvent::Flapped { .. } => event_mask::FLAPPED,
+            PeerEvent::KeyRotated { .. } => event_mask::KEY_ROTATED,
+            PeerEvent::StatusChanged { .. } | PeerEvent::Warning { .. } => event_mask::STATUS,
+            PeerEvent::Summary { .. } => event_mask::STATS,
+            PeerEvent::LocalAddrsChanged { .. } => event_mask::LOCAL_ADDRS,
+            PeerEvent::InboundConnection { .. } => event_mask::INBOUND,
+            PeerEvent::Connected { .. } => event_mask::CONNECTION_UP,
+            PeerEvent::PathChanged { .. } => event_mask::CONNECTION_PATH,
+            PeerEvent::ConnectionClosed { .. } => event_mask::CONNECTION_DOWN,
+            PeerEvent::VersionMismatch { .. } => event_mask::VERSION_MISMATCH,
+            PeerEvent::Reconnect { .. } => event_mask::RECONNECT,
+            PeerEvent::MessageSent { .. }
+            | PeerEvent::MessageDelivered { .. }
+            | PeerEvent::MessageFailed { .. } => event_mask::MESSAGES,
+            PeerEvent::RateLimited { .. } => event_mask::RATE_LIMITED,
+            PeerEvent::OverBudget { .. } => event_mask::OVER_BUDGET,
+            PeerEvent::RelayFallback { .. } | PeerEvent::RelayRefused { .. } => event_mask::RELAY,
+            PeerEvent::TransferStarted { .. } | PeerEvent::TransferFinished { .. } => {
+                event_mask::TRANSFERS
+            }
+            PeerEvent::OperationFinished { .. } => event_mask::OPERATIONS,
+            PeerEvent::DiscoveryModeChanged { .. } => event_mask::DISCOVERY_MODE,
+            PeerEvent::HotspotChanged { .. } => event_mask::HOTSPOT,
+            PeerEvent::NeighborUp { .. } | PeerEvent::NeighborDown { .. } => event_mask::TOPICS,
+            PeerEvent::DocChanged { .. } => event_mask::DOCS,
+            PeerEvent::Error { .. } => event_mask::ERROR,
+        }
+    }
+}
+
+/// Bits for filtering which events reach an FFI subscriber
+///
+/// Combine with `|` and pass to `peer_set_event_callback_filtered`. Bits
+/// without a matching event yet are reserved so the values stay stable as
+/// event types are added.
+pub mod event_mask {
+    pub const DISCOVERED: u32 = 1 << 0;
+    pub const EXPIRED: u32 = 1 << 1;
+    /// Connections opening
+    pub const CONNECTION_UP: u32 = 1 << 2;
+    /// Connections closing
+    pub const CONNECTION_DOWN: u32 = 1 << 3;
+    /// Panics and failures in background tasks
+    pub const ERROR: u32 = 1 << 4;
+    /// Periodic summary events
+    pub const STATS: u32 = 1 << 5;
+    /// Status changes and lasting problems
+    pub const STATUS: u32 = 1 << 6;
+    /// Local address changes
+    pub const LOCAL_ADDRS: u32 = 1 << 7;
+    /// Inbound connections, accepted or denied
+    pub const INBOUND: u32 = 1 << 8;
+    /// Connected peers changing path
+    pub const CONNECTION_PATH: u32 = 1 << 9;
+    /// Re-dials of trusted peers after resuming
+    pub const RECONNECT: u32 = 1 << 10;
+    /// Progress of outbound messages
+    pub const MESSAGES: u32 = 1 << 11;
+    /// File transfers starting and finishing
+    pub const TRANSFERS: u32 = 1 << 12;
+    /// Gossip neighbours joining and leaving subscribed topics
+    pub const TOPICS: u32 = 1 << 13;
+    /// Changes to open documents
+    pub const DOCS: u32 = 1 << 14;
+    /// Messages refused for going over a receive limit
+    pub const RATE_LIMITED: u32 = 1 << 15;
+    /// Connections closed over incompatible protocol versions
+    pub const VERSION_MISMATCH: u32 = 1 << 16;
+    /// Memory pools dropping entries to stay within their budget
+    pub const OVER_BUDGET: u32 = 1 << 17;
+    /// Traffic falling back to a relay, or refused it with `lan_only`
+    pub const RELAY: u32 = 1 << 18;
+    /// Peers expiring and coming straight back
+    pub const FLAPPED: u32 = 1 << 19;
+    /// Peers moving to a new key
+    pub const KEY_ROTATED: u32 = 1 << 20;
+    /// Operations started with an ID ending
+    pub const OPERATIONS: u32 = 1 << 21;
+    /// Switches between local discovery and the DNS fallback
+    pub const DISCOVERY_MODE: u32 = 1 << 22;
+    /// Joining and leaving a Personal Hotspot
+    pub const HOTSPOT: u32 = 1 << 23;
+    /// Everything about connections, as opposed to discovery
+    pub const CONNECTIONS: u32 =
+        CONNECTION_UP | CONNECTION_DOWN | CONNECTION_PATH | INBOUND | RECONNECT;
+    pub const ALL: u32 = u32::MAX;
+}
+
+/// Which receive limit a peer's messages went over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageLimit {
+    /// Too many messages in too little time
+    Rate,
+    /// A message larger than the largest accepted
+    Size,
+}
+
+/// A pool of memory limited by [`crate::budget::MemoryBudget`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MemoryPool {
+    /// Messages waiting to be sent
+    QueuedMessages,
+    /// Peers listed by discovery
+    DiscoveredPeers,
+    /// Events waiting for the host's callback
+    QueuedEvents,
+}
+
+/// Lifecycle state of the local peer
+///
+/// The discriminants are part of the C ABI (see `peer_status`).
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerStatus {
+    NotStarted = 0,
+    Starting = 1,
+    /// Endpoint bound and discovery traffic is flowing
+    Running = 2,
+    /// Endpoint bound, but no discovery traffic at all has been received, not
+    /// even our own announcements. On iOS this is what a denied "Local
+    /// Network" permission looks like.
+    LocalNetworkPermissionLikelyDenied = 3,
+    Stopped = 4,
+}
+
+/// Why the local peer stopped
+///
+/// The discriminants are part of the C ABI (see `peer_shutdown_reason`),
+/// where 0 means the peer has not stopped.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ShutdownReason {
+    /// The host asked the peer to stop
+    HostRequested = 1,
+    /// The peer task panicked
+    RuntimePanic = 2,
+    /// The endpoint could not bind its sockets, or lost them
+    BindLost = 3,
+    /// The endpoint closed without the host asking, e.g. the OS tore down
+    /// its sockets while the app was suspended
+    EndpointClosedRemotely = 4,
+    /// The peer stopped on any other error, reported in an `error` event
+    Failed = 5,
+}
+
+/// Receives every event the peer emits
+pub type EventSink = Arc<dyn Fn(&PeerEvent) + Send + Sync>;
+
+/// An [`EventSink`] that drops everything, for callers that only want logs
+pub fn discard_events() -> EventSink {
+    Arc::new(|_| {})
+}
@@ -0,0 +1,150 @@
+//! Synthetic peers for UI development (`mdns-peer fake`)
+//!
+//! Advertises a fleet of endpoints from one process, each with its own user
+//! data, and periodically takes some of them offline and brings them back, so
+//! a peer-list screen sees discoveries and expiries without a desk full of
+//! devices. A peer keeps its node ID when it comes back, like a real device
+//! that left and returned.
+
+use std::time::Duration;
+
+use iroh::{Endpoint, NodeId, SecretKey};
+use rand::seq::SliceRandom;
+use tracing::info;
+
+use crate::{bind_endpoint_with, initialize_logging, PeerOptions};
+
+/// Settings for a fake fleet
+#[derive(Debug, Clone)]
+pub struct FakeOptions {
+    /// Number of synthetic peers
+    pub count: usize,
+    /// User data is `<prefix>-01`, `<prefix>-02`, ...
+    pub prefix: String,
+    /// How often to rotate availability, `None` to keep every peer online
+    pub rotate_interval: Option<Duration>,
+}
+
+impl Default for FakeOptions {
+    fn default() -> Self {
+        Self {
+            count: 10,
+            prefix: "fake".to_string(),
+            rotate_interval: Some(Duration::from_secs(15)),
+        }
+    }
+}
+
+struct FakePeer {
+    user_data: String,
+    secret_key: SecretKey,
+    endpoint: Option<Endpoint>,
+}
+
+impl FakePeer {
+    async fn bring_online(&mut self) -> anyhow::Result<()> {
+        let options = PeerOptions {
+            secret_key: Some(self.secret_key.clone()),
+            ..Default::default()
+        };
+        self.endpoint = Some(bind_endpoint_with(&self.user_data, &options).await?);
+        Ok(())
+    }
+
+    async fn take_offline(&mut self) {
+        if let Some(endpoint) = self.endpoint.take() {
+            endpoint.close().await;
+        }
+    }
+}
+
+/// Synthetic peers advertised from this process
+pub struct FakeFleet {
+    peers: Vec<FakePeer>,
+}
+
+impl FakeFleet {
+    /// Bind every peer, all of them online
+    pub async fn start(options: &FakeOptions) -> anyhow::Result<Self> {
+        let mut peers = Vec::with_capacity(options.count);
+        for i in 1..=options.count {
+            let mut peer = FakePeer {
+                user_data: format!("{}-{:02}", options.prefix, i),
+                secret_key: SecretKey::generate(rand::rngs::OsRng),
+                endpoint: None,
+            };
+            peer.bring_online().await?;
+            peers.push(peer);
+        }
+        Ok(Self { peers })
+    }
+
+    /// User data and node ID of the peers currently online
+    pub fn online(&self) -> Vec<(String, NodeId)> {
+        self.peers
+            .iter()
+            .filter(|peer| peer.endpoint.is_some())
+            .map(|peer| (peer.user_data.clone(), peer.secret_key.public()))
+            .collect()
+    }
+
+    /// Bring back every offline peer and take about a fifth of the others
+    /// (at least one) offline
+    pub async fn rotate(&mut self) -> anyhow::Result<()> {
+        let (offline, online): (Vec<usize>, Vec<usize>) =
+            (0..self.peers.len()).partition(|&i| self.peers[i].endpoint.is_none());
+
+        let leaving = online.len().div_ceil(5);
+        let leaving: Vec<usize> = online
+            .choose_multiple(&mut rand::thread_rng(), leaving)
+            .copied()
+            .collect();
+        for i in leaving {
+            let peer = &mut self.peers[i];
+            peer.take_offline().await;
+            info!("{} went offline", peer.user_data);
+        }
+
+        for i in offline {
+            let peer = &mut self.peers[i];
+            peer.bring_online().await?;
+            info!("{} is back online", peer.user_data);
+        }
+        Ok(())
+    }
+
+    /// Close every endpoint
+    pub async fn close(mut self) {
+        for peer in &mut self.peers {
+            peer.take_offline().await;
+        }
+    }
+}
+
+/// Advertise a fake fleet until Ctrl+C
+pub async fn run_fake(options: FakeOptions) -> anyhow::Result<()> {
+    initialize_logging();
+
+    let mut fleet = FakeFleet::start(&options).await?;
+    for (user_data, node_id) in fleet.online() {
+        info!("Advertising {} as {}", user_data, node_id.fmt_short());
+    }
+
+    let mut rotation = options
+        .rotate_interval
+        .map(|every| tokio::time::interval_at(tokio::time::Instant::now() + every, every));
+    loop {
+        tokio::select! {
+            _ = async { rotation.as_mut().unwrap().tick().await }, if rotation.is_some() => {
+                fleet.rotate().await?;
+            }
+            _ = tokio::signal::ctrl_c() => {
+                info!("Taking the fleet offline...");
+                break;
+            }
+        }
+    }
+
+    fleet.close().await;
+    Ok(())
+}
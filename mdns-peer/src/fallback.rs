@@ -0,0 +1,331 @@
+//! Falling back to DNS discovery when local discovery finds nobody
+//!
+//! Some networks never carry multicast between devices: guest Wi-Fi with
+//! client isolation, access points that filter mDNS, a phone whose Local
+//! Network permission was denied. With a [`DnsFallback`] in
+//! [`PeerOptions::dns_fallback`](crate::PeerOptions::dns_fallback), a peer
+//! starts out on local discovery alone. Once its window passes without
+//! local discovery listing a single peer, it switches to fallback mode: it
+//! publishes its addresses and user data through n0's pkarr relay and looks
+//! up the peers it knows about in n0's DNS every 30 seconds. Those are
+//! trusted and paired peers, peers with an alias, and peers seen earlier in
+//! the session; the ones found are reported like any other discovery, with
+//! `dns` as their provenance. Dialing by node ID resolves through DNS too
+//! while the fallback is active. As soon as local discovery lists a peer
+//! again, the peer switches back, and peers only DNS found expire.
+//!
+//! Every switch is reported as a [`PeerEvent::DiscoveryModeChanged`], and so
+//! is the mode a peer starts in, which is what an app's "fallback active"
+//! indicator follows.
+//!
+//! DNS only finds peers that publish there, i.e. ones in fallback mode
+//! themselves, and it sends the node ID, addresses and user data off the
+//! local network, which is why the fallback is off unless configured. It
+//! doesn't need a relay: peers found on a network that filters multicast
+//! but not unicast are dialed on their direct addresses.
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use iroh::discovery::dns::DnsDiscovery;
+use iroh::discovery::pkarr::PkarrPublisher;
+use iroh::discovery::{
+    mdns::NAME, Discovery, DiscoveryContext, DiscoveryError, DiscoveryEvent, DiscoveryItem,
+    IntoDiscovery, IntoDiscoveryError, NodeData,
+};
+use iroh::NodeId;
+use n0_future::{boxed::BoxStream, stream, StreamExt};
+use schemars::JsonSchema;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tracing::{debug, warn};
+
+use crate::events::{EventSink, PeerEvent};
+use crate::registry::PeerRegistry;
+use crate::suspend;
+
+/// How long local discovery may find nobody before falling back, unless
+/// the host picks a window
+pub const DEFAULT_FALLBACK_WINDOW: Duration = Duration::from_secs(30);
+
+/// How often to check whether local discovery lists a peer
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often known peers are looked up while the fallback is active
+const LOOKUP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long one DNS lookup may take
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Discovery events buffered per subscriber
+const EVENT_CAPACITY: usize = 64;
+
+/// Which discovery a peer is using, see the [module docs](self)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscoveryMode {
+    /// Local discovery only
+    Local,
+    /// DNS as well, local discovery having found nobody
+    Fallback,
+}
+
+/// Switches a peer between local discovery and DNS, shared between the
+/// endpoint's discovery and the running peer
+///
+/// Clones share the same state.
+#[derive(Debug, Clone)]
+pub struct DnsFallback {
+    window: Duration,
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    active: AtomicBool,
+    /// Publisher and resolver of the endpoint bound last
+    services: Mutex<Option<Arc<Services>>>,
+    /// What the endpoint last asked to publish, published on falling back
+    published: Mutex<Option<NodeData>>,
+    events: broadcast::Sender<DiscoveryEvent>,
+}
+
+impl std::fmt::Debug for Inner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Inner")
+            .field("active", &self.active.load(Ordering::Relaxed))
+            .field("attached", &self.services.lock().unwrap().is_some())
+            .finish()
+    }
+}
+
+struct Services {
+    publisher: Box<dyn Discovery>,
+    resolver: Box<dyn Discovery>,
+}
+
+impl DnsFallback {
+    /// Fall back once local discovery has found nobody for `window`
+    pub fn new(window: Duration) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        Self {
+            window,
+            inner: Arc::new(Inner {
+                active: AtomicBool::new(false),
+                services: Mutex::new(None),
+                published: Mutex::new(None),
+                events,
+            }),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    pub fn mode(&self) -> DiscoveryMode {
+        if self.inner.active.load(Ordering::Relaxed) {
+            DiscoveryMode::Fallback
+        } else {
+            DiscoveryMode::Local
+        }
+    }
+
+    /// The discovery service to add to an endpoint builder
+    pub fn service(&self) -> impl IntoDiscovery {
+        Attach(self.clone())
+    }
+
+    /// Peers DNS lookups found or lost, to be merged into the endpoint's
+    /// discovery events
+    pub fn events(&self) -> BoxStream<DiscoveryEvent> {
+        let events = self.inner.events.subscribe();
+        Box::pin(stream::unfold(events, |mut events| async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => return Some((event, events)),
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!(
+                            "DNS fallback subscriber fell behind, skipped {} events",
+                            missed
+                        );
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        }))
+    }
+
+    /// Switch modes given whether local discovery lists a peer and how long
+    /// it has listed none, returning the event reporting a switch
+    pub fn check(&self, local_peers: bool, silent_for: Duration) -> Option<PeerEvent> {
+        match self.mode() {
+            DiscoveryMode::Local if !local_peers && silent_for >= self.window => {
+                let reason = format!(
+                    "local discovery found no peers for {} s",
+                    silent_for.as_secs()
+                );
+                Some(self.switch(DiscoveryMode::Fallback, reason))
+            }
+            DiscoveryMode::Fallback if local_peers => Some(self.switch(
+                DiscoveryMode::Local,
+                "local discovery found a peer".to_string(),
+            )),
+            _ => None,
+        }
+    }
+
+    fn switch(&self, mode: DiscoveryMode, reason: String) -> PeerEvent {
+        let active = mode == DiscoveryMode::Fallback;
+        self.inner.active.store(active, Ordering::Relaxed);
+        if active {
+            let services = self.inner.services.lock().unwrap().clone();
+            let published = self.inner.published.lock().unwrap().clone();
+            if let (Some(services), Some(data)) = (services, published) {
+                services.publisher.publish(&data);
+            }
+        }
+        PeerEvent::DiscoveryModeChanged { mode, reason }
+    }
+
+    /// Look up `node_ids` in DNS, reporting each one found as discovered;
+    /// returns the ones found
+    pub async fn look_up(&self, node_ids: impl IntoIterator<Item = NodeId>) -> HashSet<NodeId> {
+        let Some(services) = self.inner.services.lock().unwrap().clone() else {
+            return HashSet::new();
+        };
+        let lookups = node_ids.into_iter().filter_map(|node_id| {
+            let mut items = services.resolver.resolve(node_id)?;
+            Some(async move {
+                let first = async {
+                    while let Some(item) = items.next().await {
+                        match item {
+                            Ok(item) => return Some(item),
+                            Err(e) => debug!("DNS lookup of {} failed: {}", node_id.fmt_short(), e),
+                        }
+                    }
+                    None
+                };
+                tokio::time::timeout(LOOKUP_TIMEOUT, first)
+                    .await
+                    .ok()
+                    .flatten()
+            })
+        });
+        let mut found = HashSet::new();
+        for item in n0_future::join_all(lookups).await.into_iter().flatten() {
+            found.insert(item.node_id());
+            // No receivers just means the peer isn't running
+            let _ = self.inner.events.send(DiscoveryEvent::Discovered(item));
+        }
+        found
+    }
+
+    /// Report peers DNS found as expired, unless local discovery took them
+    /// over
+    fn expire(&self, node_ids: impl IntoIterator<Item = NodeId>, registry: &PeerRegistry) {
+        for node_id in node_ids {
+            if registry
+                .get(&node_id)
+                .is_some_and(|entry| entry.provenance == NAME)
+            {
+                continue;
+            }
+            let _ = self.inner.events.send(DiscoveryEvent::Expired(node_id));
+        }
+    }
+
+    /// Watch `registry` and switch modes, looking up `known()` peers and the
+    /// ones seen so far while falling back; runs until dropped
+    ///
+    /// Starts in local mode and reports it.
+    pub async fn run(
+        &self,
+        registry: Arc<Mutex<PeerRegistry>>,
+        known: impl Fn() -> HashSet<NodeId>,
+        emit: EventSink,
+    ) {
+        self.inner.active.store(false, Ordering::Relaxed);
+        emit(&PeerEvent::DiscoveryModeChanged {
+            mode: DiscoveryMode::Local,
+            reason: "starting with local discovery".to_string(),
+        });
+
+        let mut last_local = Instant::now();
+        let mut seen = HashSet::new();
+        let mut found = HashSet::new();
+        let mut next_lookup = Instant::now();
+        let mut check = suspend::interval(CHECK_INTERVAL);
+        loop {
+            check.tick().await;
+            let local_peers = {
+                let registry = registry.lock().unwrap();
+                seen.extend(registry.peers().map(|entry| entry.node_id));
+                let local_peers = registry.peers().any(|entry| entry.provenance == NAME);
+                local_peers
+            };
+            let now = Instant::now();
+            if local_peers {
+                last_local = now;
+            }
+            if let Some(event) = self.check(local_peers, now - last_local) {
+                if self.mode() == DiscoveryMode::Local {
+                    // Nothing refreshes them anymore
+                    self.expire(found.drain(), &registry.lock().unwrap());
+                }
+                next_lookup = now;
+                emit(&event);
+            }
+
+            if self.mode() == DiscoveryMode::Fallback && now >= next_lookup {
+                next_lookup = now + LOOKUP_INTERVAL;
+                let wanted: HashSet<_> = known().into_iter().chain(seen.iter().copied()).collect();
+                let now_found = self.look_up(wanted).await;
+                let lost: Vec<_> = found.difference(&now_found).copied().collect();
+                self.expire(lost, &registry.lock().unwrap());
+                found = now_found;
+            }
+        }
+    }
+}
+
+impl Discovery for DnsFallback {
+    fn publish(&self, data: &NodeData) {
+        *self.inner.published.lock().unwrap() = Some(data.clone());
+        if self.mode() == DiscoveryMode::Fallback {
+            if let Some(services) = self.inner.services.lock().unwrap().as_ref() {
+                services.publisher.publish(data);
+            }
+        }
+    }
+
+    fn resolve(&self, node_id: NodeId) -> Option<BoxStream<Result<DiscoveryItem, DiscoveryError>>> {
+        if self.mode() == DiscoveryMode::Local {
+            return None;
+        }
+        let services = self.inner.services.lock().unwrap().clone()?;
+        services.resolver.resolve(node_id)
+    }
+}
+
+/// Builds the publisher and resolver for the endpoint being bound
+///
+/// Types implementing [`Discovery`] get iroh's blanket [`IntoDiscovery`],
+/// which has no access to the endpoint's key, hence the wrapper.
+#[derive(Debug)]
+struct Attach(DnsFallback);
+
+impl IntoDiscovery for Attach {
+    fn into_discovery(
+        self,
+        context: &DiscoveryContext,
+    ) -> Result<impl Discovery, IntoDiscoveryError> {
+        let services = Services {
+            publisher: Box::new(PkarrPublisher::n0_dns().into_discovery(context)?),
+            resolver: Box::new(DnsDiscovery::n0_dns().into_discovery(context)?),
+        };
+        *self.0.inner.services.lock().unwrap() = Some(Arc::new(services));
+        Ok(self.0)
+    }
+}
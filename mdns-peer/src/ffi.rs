@@ -0,0 +1,3889 @@
+//! C ABI used by the iOS app
+//!
+//! The host app calls these functions through `@_silgen_name` declarations in
+//! Swift. Everything runs on a process-wide tokio runtime that is created on
+//! the first call that needs it, usually `peer_start`.
+//!
+//! Events are delivered as JSON strings to the callback registered with
+//! `peer_set_event_callback` (see [`crate::events`] for the format).
+
+use bytes::Bytes;
+use iroh::{discovery::UserData, Endpoint, NodeId, RelayUrl, SecretKey};
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::{c_void, CStr, CString};
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::{broadcast, oneshot};
+use tracing::{info, warn};
+
+use crate::accept::{AcceptLimits, AcceptPolicy, InboundRequest};
+use crate::bind::BindAddrs;
+use crate::budget::{DropPolicy, MemoryBudget, Overflow};
+use crate::bundle::StateBundle;
+use crate::candidates::{Candidates, DEFAULT_CANDIDATE_TTL};
+use crate::config::PeerConfig;
+use crate::deadline::{self, ResultCode};
+use crate::dispatch::EventDispatcher;
+#[cfg(feature = "docs")]
+use crate::docs::SharedDocs;
+use crate::environment::EnvironmentReport;
+use crate::events::{event_mask, MemoryPool, TimedEvent};
+use crate::fallback::DnsFallback;
+use crate::find;
+use crate::groups::PeerGroups;
+use crate::history::SessionHistory;
+use crate::hotspot;
+use crate::instance::{self, DuplicatePolicy};
+use crate::keystore::SecretStore;
+use crate::limits::TransferLimits;
+use crate::mdns::MdnsOptions;
+use crate::messages::{MessageHandler, MessageId, MessageLimits, Messages};
+use crate::multicast;
+use crate::naming;
+use crate::notes::PeerNotes;
+use crate::operations::{self, OperationId, OperationKind};
+use crate::options::WarmUp;
+use crate::p2p::PeerToPeer;
+use crate::pairing;
+use crate::privacy;
+use crate::profile::ProfileStore;
+use crate::protocols::{Protocols, StreamHandler, StreamId};
+use crate::reconnect::Resume;
+use crate::registry::PeerRegistry;
+use crate::relay;
+use crate::remote_info::{self, NodeAddrReport, RemoteInfoReport};
+use crate::rotation;
+use crate::self_test;
+use crate::session::Session;
+use crate::state;
+use crate::supervise;
+use crate::tasks;
+#[cfg(feature = "gossip")]
+use crate::topics::{TopicHandler, Topics};
+use crate::transfer::{FileTransfers, IncomingFile, ReceivePolicy, TransferId, TransferOutcome};
+use n0_future::boxed::BoxFuture;
+
+use crate::{
+    bind_endpoint_with, bind_peer_endpoint, initialize_logging, run_endpoint, EventSink, PeerEvent,
+    PeerOptions, PeerStatus, ShutdownReason,
+};
+
+static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+static SHUTDOWN_SENDER: OnceLock<Arc<Mutex<broadcast::Sender<()>>>> = OnceLock::new();
+static STATUS: AtomicI32 = AtomicI32::new(PeerStatus::NotStarted as i32);
+/// [`ShutdownReason`] of the last stop, 0 while the peer has not stopped
+static SHUTDOWN_REASON: AtomicI32 = AtomicI32::new(0);
+static EVENT_CALLBACK: Mutex<Option<RegisteredCallback>> = Mutex::new(None);
+/// Endpoint of the running peer, for query functions
+static ENDPOINT: Mutex<Option<Endpoint>> = Mutex::new(None);
+/// The peer started last, for `peer_rotate_key` to restart it
+static RUNNING: Mutex<Option<RunningPeer>> = Mutex::new(None);
+/// Options for the next `peer_start`, see the `peer_set_*` setters
+static OPTIONS: Mutex<Option<PeerOptions>> = Mutex::new(None);
+/// Directory for persistent state such as profiles, see `peer_set_state_dir`
+static STATE_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+/// Where profile keys are kept instead of the state directory, see
+/// `peer_set_secret_store`
+static SECRET_STORE: Mutex<Option<Arc<dyn SecretStore>>> = Mutex::new(None);
+/// Aliases and notes in the state directory, opened on first use
+static NOTES: Mutex<Option<PeerNotes>> = Mutex::new(None);
+/// Peer groups in the state directory, opened on first use
+static GROUPS: Mutex<Option<PeerGroups>> = Mutex::new(None);
+/// Session history in the state directory, opened on first use
+static HISTORY: Mutex<Option<SessionHistory>> = Mutex::new(None);
+/// Batch window set with `peer_set_event_batch_window`, 0 when disabled
+static BATCH_WINDOW_MS: AtomicU32 = AtomicU32::new(0);
+/// Inbound connections waiting for `peer_respond_accept`, by request ID
+static ACCEPT_REQUESTS: Mutex<BTreeMap<u64, oneshot::Sender<bool>>> = Mutex::new(BTreeMap::new());
+static NEXT_ACCEPT_REQUEST: AtomicU64 = AtomicU64::new(1);
+/// Incoming files waiting for `peer_respond_receive`, by request ID
+static RECEIVE_REQUESTS: Mutex<BTreeMap<u64, oneshot::Sender<Option<PathBuf>>>> =
+    Mutex::new(BTreeMap::new());
+static NEXT_RECEIVE_REQUEST: AtomicU64 = AtomicU64::new(1);
+
+/// Callback receiving each event as a NUL-terminated JSON string
+///
+/// The string is only valid for the duration of the call. `context` is the
+/// pointer passed to `peer_set_event_callback`, handed back unchanged.
+pub type EventCallback = extern "C" fn(event_json: *const c_char, context: *mut c_void);
+
+#[derive(Clone, Copy)]
+struct RegisteredCallback {
+    callback: EventCallback,
+    /// Opaque host pointer, stored as an address so the registration is `Send`
+    context: usize,
+    /// [`event_mask`] bits the host subscribed to
+    mask: u32,
+}
+
+impl RegisteredCallback {
+    fn wants(&self, event: &PeerEvent) -> bool {
+        self.mask & event.mask_bit() != 0
+    }
+
+    fn invoke(&self, json: String) {
+        let json = CString::new(json).expect("JSON never contains NUL bytes");
+        (self.callback)(json.as_ptr(), self.context as *mut c_void);
+    }
+}
+
+/// How the peer started last was started
+struct RunningPeer {
+    identifier: &'static str,
+    /// Profile it was started as, see `peer_start_with_profile`
+    profile: Option<String>,
+    lite: bool,
+    task: tokio::task::JoinHandle<()>,
+}
+
+/// How long `peer_get_addr_qr_payload` waits for the first address
+const QR_PAYLOAD_WAIT: Duration = Duration::from_secs(5);
+
+fn set_status(status: PeerStatus, reason: Option<ShutdownReason>) {
+    SHUTDOWN_REASON.store(reason.map_or(0, |reason| reason as i32), Ordering::SeqCst);
+    STATUS.store(status as i32, Ordering::SeqCst);
+}
+
+fn registered_callback() -> Option<RegisteredCallback> {
+    *EVENT_CALLBACK.lock().unwrap()
+}
+
+/// The process-wide runtime, created on first use
+fn runtime() -> &'static tokio::runtime::Runtime {
+    RUNTIME.get_or_init(|| tokio::runtime::Runtime::new().expect("Failed to create tokio runtime"))
+}
+
+/// Tasks alive on the runtime, 0 before it exists, for `mdns-peer soak`
+#[cfg(feature = "cli")]
+pub(crate) fn alive_tasks() -> usize {
+    RUNTIME.get().map_or(0, |rt| rt.metrics().num_alive_tasks())
+}
+
+/// Create the runtime with as few threads as a lite peer needs, unless it
+/// already exists
+fn init_lite_runtime() {
+    if RUNTIME.get().is_some() {
+        info!("Runtime already created, keeping it for the lite peer");
+        return;
+    }
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            // File reads and host readers run on blocking threads
+            .max_blocking_threads(2)
+            .enable_all()
+            .build()
+            .expect("Failed to create tokio runtime")
+    });
+}
+
+fn dispatcher() -> &'static EventDispatcher<TimedEvent> {
+    static DISPATCHER: OnceLock<EventDispatcher<TimedEvent>> = OnceLock::new();
+    DISPATCHER.get_or_init(|| {
+        let capacity = MemoryBudget::default().queued_events;
+        EventDispatcher::spawn_batched(capacity, |batch: Vec<TimedEvent>| {
+            // Re-check: the host may have changed its registration meanwhile
+            let Some(registered) = registered_callback() else {
+                return;
+            };
+            let wanted: Vec<_> = batch
+                .iter()
+                .filter(|timed| registered.wants(&timed.event))
+                .collect();
+            if wanted.is_empty() {
+                return;
+            }
+
+            if BATCH_WINDOW_MS.load(Ordering::Relaxed) > 0 {
+                let json = serde_json::to_string(&wanted).expect("events are always serializable");
+                registered.invoke(json);
+            } else {
+                for event in wanted {
+                    registered.invoke(event.to_json());
+                }
+            }
+        })
+    })
+}
+
+/// Queue an event for the registered callback, if any
+///
+/// Never blocks on the host: delivery happens on the dispatch thread. The
+/// event is stamped now, not when the host gets to it.
+fn deliver_event(event: &PeerEvent) {
+    if let PeerEvent::StatusChanged { status, reason } = event {
+        set_status(*status, *reason);
+    }
+
+    // Filter before queueing so unwanted events cost nothing
+    if registered_callback().is_some_and(|r| r.wants(event)) {
+        let timed = TimedEvent::now(event.clone());
+        #[cfg(debug_assertions)]
+        {
+            let json = serde_json::to_value(&timed).expect("events are always serializable");
+            if let Err(e) = crate::schema::check_event(&json) {
+                tracing::error!("Event doesn't match the event schema: {}", e);
+            }
+        }
+        dispatcher().push(timed);
+        report_dropped_events();
+    }
+}
+
+/// Report events the dispatcher dropped since the last call, at most once
+/// a second
+fn report_dropped_events() {
+    static SEEN: AtomicU64 = AtomicU64::new(0);
+    static OVERFLOW: Mutex<Overflow> = Mutex::new(Overflow::new());
+
+    let dropped = dispatcher().dropped();
+    let new = dropped - SEEN.swap(dropped, Ordering::Relaxed).min(dropped);
+    let due = {
+        let mut overflow = OVERFLOW.lock().unwrap();
+        overflow.add(new);
+        overflow.due()
+    };
+    if let Some(dropped) = due {
+        deliver_event(&PeerEvent::OverBudget {
+            pool: MemoryPool::QueuedEvents,
+            limit: dispatcher().capacity() as u64,
+            dropped,
+        });
+    }
+}
+
+/// Options for the next start, as configured by the `peer_set_*` setters
+fn current_options() -> PeerOptions {
+    let mut options = OPTIONS.lock().unwrap().clone().unwrap_or_default();
+    options.protocols = protocols().clone();
+    options.candidates = candidates().clone();
+    options.p2p = p2p_interfaces().clone();
+    options.messages = messages().clone();
+    options.resume = resume().clone();
+    options.registry = registry().clone();
+    options.transfers = transfers().clone();
+    #[cfg(feature = "gossip")]
+    {
+        options.topics = topics().clone();
+    }
+    #[cfg(feature = "docs")]
+    {
+        options.docs = docs().clone();
+    }
+    match peer_notes() {
+        Ok(notes) => options.notes = notes,
+        Err(e) => warn!("Peer notes unavailable: {:#}", e),
+    }
+    match session_history() {
+        Ok(history) => options.history = history,
+        Err(e) => warn!("Session history unavailable: {:#}", e),
+    }
+    options
+}
+
+/// Protocols registered with `peer_register_protocol`
+fn protocols() -> &'static Protocols {
+    static PROTOCOLS: OnceLock<Protocols> = OnceLock::new();
+    PROTOCOLS.get_or_init(Protocols::default)
+}
+
+/// Candidates injected with `peer_inject_candidate`
+fn candidates() -> &'static Candidates {
+    static CANDIDATES: OnceLock<Candidates> = OnceLock::new();
+    CANDIDATES.get_or_init(Candidates::default)
+}
+
+/// Interfaces reported with `peer_set_p2p_interface`
+fn p2p_interfaces() -> &'static PeerToPeer {
+    static P2P: OnceLock<PeerToPeer> = OnceLock::new();
+    P2P.get_or_init(PeerToPeer::default)
+}
+
+/// Message queues behind `peer_send_message`
+fn messages() -> &'static Messages {
+    static MESSAGES: OnceLock<Messages> = OnceLock::new();
+    MESSAGES.get_or_init(Messages::default)
+}
+
+/// File transfers behind `peer_send_file`
+fn transfers() -> &'static FileTransfers {
+    static TRANSFERS: OnceLock<FileTransfers> = OnceLock::new();
+    TRANSFERS.get_or_init(FileTransfers::default)
+}
+
+/// Gossip topics behind `peer_subscribe`
+#[cfg(feature = "gossip")]
+fn topics() -> &'static Topics {
+    static TOPICS: OnceLock<Topics> = OnceLock::new();
+    TOPICS.get_or_init(Topics::default)
+}
+
+/// Documents behind `peer_doc_create`
+#[cfg(feature = "docs")]
+fn docs() -> &'static SharedDocs {
+    static DOCS: OnceLock<SharedDocs> = OnceLock::new();
+    DOCS.get_or_init(SharedDocs::default)
+}
+
+/// Discovered peers behind `peer_count` and `peer_has_peer`
+fn registry() -> &'static Arc<Mutex<PeerRegistry>> {
+    static REGISTRY: OnceLock<Arc<Mutex<PeerRegistry>>> = OnceLock::new();
+    REGISTRY.get_or_init(Arc::default)
+}
+
+/// Signalled by `peer_resume`
+fn resume() -> &'static Resume {
+    static RESUME: OnceLock<Resume> = OnceLock::new();
+    RESUME.get_or_init(Resume::default)
+}
+
+/// Initialize with a given peer identifier
+fn start_peer(identifier: &'static str, options: PeerOptions, profile: Option<String>) -> bool {
+    initialize_logging();
+
+    // Refuse synchronously, so peer_start can report it
+    let instance = match instance::claim(
+        identifier,
+        options.on_duplicate,
+        options.secret_key.is_some(),
+    ) {
+        Ok(instance) => instance,
+        Err(e) => {
+            warn!("Not starting: {:#}", e);
+            return false;
+        }
+    };
+    let identifier: &'static str = if instance.identifier() == identifier {
+        identifier
+    } else {
+        warn!(
+            "{} is already running on this machine, advertising {} instead",
+            privacy::redact(identifier),
+            privacy::redact(instance.identifier())
+        );
+        Box::leak(instance.identifier().to_string().into_boxed_str())
+    };
+
+    info!("{} starting...", privacy::redact(identifier));
+
+    let rt = runtime();
+
+    // Create shutdown channel if needed
+    let shutdown_sender = SHUTDOWN_SENDER.get_or_init(|| {
+        let (tx, _) = broadcast::channel(1);
+        Arc::new(Mutex::new(tx))
+    });
+
+    let shutdown_rx = shutdown_sender.lock().unwrap().subscribe();
+
+    deliver_event(&PeerEvent::status(PeerStatus::Starting));
+    let events: EventSink = Arc::new(deliver_event);
+
+    supervise::install_panic_hook();
+    let lite = options.lite;
+    let task = rt.spawn(async move {
+        let peer_events = events.clone();
+        let result = supervise::catch_panic(tasks::track("peer", async {
+            let endpoint = bind_peer_endpoint(identifier, &options).await?;
+            *ENDPOINT.lock().unwrap() = Some(endpoint.clone());
+            run_endpoint(identifier, endpoint, options, shutdown_rx, peer_events)
+                .await
+                .map_err(|e| (ShutdownReason::Failed, e))
+        }))
+        .await;
+        ENDPOINT.lock().unwrap().take();
+        drop(instance);
+
+        match result {
+            Ok(Ok(())) => info!("{} completed successfully", privacy::redact(identifier)),
+            Ok(Err((reason, e))) => {
+                warn!("{} error: {}", privacy::redact(identifier), e);
+                supervise::report_error("peer", &e, &events);
+                deliver_event(&PeerEvent::stopped(reason));
+            }
+            Err(panic) => {
+                warn!(
+                    "{} panicked: {}",
+                    privacy::redact(identifier),
+                    panic.message
+                );
+                supervise::report_panic("peer", panic, &events);
+                deliver_event(&PeerEvent::stopped(ShutdownReason::RuntimePanic));
+            }
+        }
+    });
+    *RUNNING.lock().unwrap() = Some(RunningPeer {
+        identifier,
+        profile,
+        lite,
+        task,
+    });
+
+    true
+}
+
+/// Validate an identifier passed in from the host
+///
+/// The identifier must be UTF-8 and fit in discovery user data. Checking it
+/// here lets `peer_start` fail synchronously instead of inside the spawned
+/// peer task.
+pub fn parse_identifier(identifier: &CStr) -> anyhow::Result<&str> {
+    let id = identifier.to_str()?;
+    id.parse::<UserData>()?;
+    Ok(id)
+}
+
+/// Start peer with given identifier (for iOS)
+///
+/// Returns false if the identifier is invalid (pass device names through
+/// `peer_sanitize_identifier` first), or if another instance on
+/// this machine already advertises it (see `peer_set_duplicate_policy`).
+///
+/// # Safety
+///
+/// `identifier` must be null or point to a valid NUL-terminated C string that
+/// stays alive for the duration of the call.
+#[no_mangle]
+pub unsafe extern "C" fn peer_start(identifier: *const std::os::raw::c_char) -> bool {
+    let Some(identifier) = (unsafe { start_identifier(identifier, "peer_start") }) else {
+        return false;
+    };
+    start_peer(identifier, current_options(), None)
+}
+
+/// Start a lightweight peer that only discovers peers and sends files, for
+/// an iOS Share Extension
+///
+/// Runs on a runtime with a single worker thread, unless the runtime
+/// already exists, and accepts nothing: other peers can't connect to it,
+/// and messages, topics and documents aren't available. Files sent with
+/// `peer_send_file` or `peer_send_from_reader` go out one at a time. Call
+/// `peer_stop` once the `transfer_finished` event arrives. Returns false
+/// like `peer_start`.
+///
+/// # Safety
+///
+/// `identifier` must be null or point to a valid NUL-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn peer_start_lite(identifier: *const c_char) -> bool {
+    let Some(identifier) = (unsafe { start_identifier(identifier, "peer_start_lite") }) else {
+        return false;
+    };
+    init_lite_runtime();
+    let mut options = current_options();
+    options.lite = true;
+    start_peer(identifier, options, None)
+}
+
+/// Validate the identifier passed to `caller`, leaking it for the peer's
+/// lifetime
+///
+/// # Safety
+///
+/// `identifier` must be null or point to a valid NUL-terminated C string.
+unsafe fn start_identifier(identifier: *const c_char, caller: &str) -> Option<&'static str> {
+    if identifier.is_null() {
+        warn!("{} called with null identifier", caller);
+        return None;
+    }
+
+    let c_str = unsafe { CStr::from_ptr(identifier) };
+    let id = match parse_identifier(c_str) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("Invalid identifier: {}", e);
+            return None;
+        }
+    };
+
+    // Convert to static string (leaks but OK for app lifecycle)
+    Some(Box::leak(id.to_string().into_boxed_str()))
+}
+
+/// Start the peer as stored profile `name`, creating the profile on first use
+///
+/// The profile's secret key is used for the endpoint and its user data is
+/// advertised; new profiles advertise their name. To switch profiles at
+/// runtime, call `peer_stop` and then start with the other profile.
+/// Profiles live under the directory set with `peer_set_state_dir`.
+///
+/// # Safety
+///
+/// `name` must be null or point to a valid NUL-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn peer_start_with_profile(name: *const c_char) -> bool {
+    if name.is_null() {
+        warn!("peer_start_with_profile called with null name");
+        return false;
+    }
+
+    let name = unsafe { CStr::from_ptr(name) };
+    let profile = name
+        .to_str()
+        .map_err(anyhow::Error::from)
+        .and_then(|name| profile_store()?.load_or_create(name, None));
+    let profile = match profile {
+        Ok(profile) => profile,
+        Err(e) => {
+            warn!("Failed to load profile {:?}: {:#}", name, e);
+            return false;
+        }
+    };
+    info!("Using profile {}", profile.name);
+
+    let mut options = current_options();
+    options.secret_key = Some(profile.secret_key);
+    let static_id: &'static str = Box::leak(profile.user_data.into_boxed_str());
+    start_peer(static_id, options, Some(profile.name))
+}
+
+/// Set the directory for persistent state such as profiles
+///
+/// On iOS pass a path inside the app container (e.g. Application Support).
+/// Without it, `$MDNS_PEER_HOME` or `~/.mdns-peer` is used.
+///
+/// # Safety
+///
+/// `path` must be null or point to a valid NUL-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn peer_set_state_dir(path: *const c_char) -> bool {
+    // Reopen notes and groups from the new directory on next use
+    NOTES.lock().unwrap().take();
+    GROUPS.lock().unwrap().take();
+    HISTORY.lock().unwrap().take();
+    if path.is_null() {
+        *STATE_DIR.lock().unwrap() = None;
+        return true;
+    }
+    match unsafe { CStr::from_ptr(path) }.to_str() {
+        Ok(path) => {
+            *STATE_DIR.lock().unwrap() = Some(PathBuf::from(path));
+            true
+        }
+        Err(e) => {
+            warn!("Invalid state directory: {}", e);
+            false
+        }
+    }
+}
+
+fn profile_store() -> anyhow::Result<ProfileStore> {
+    let store = match STATE_DIR.lock().unwrap().as_ref() {
+        Some(dir) => ProfileStore::new(dir),
+        None => ProfileStore::open_default()?,
+    };
+    Ok(match SECRET_STORE.lock().unwrap().clone() {
+        Some(secrets) => store.with_secret_store(secrets),
+        None => store,
+    })
+}
+
+/// Longest secret read back from the host's secure store
+const MAX_SECRET_LEN: usize = 1024;
+
+/// Host secure store registered with `peer_set_secret_store`, such as the
+/// Keychain
+///
+/// Both callbacks may run on any thread. Pointers passed to them are only
+/// valid for the duration of the call.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PeerSecretStoreCallbacks {
+    /// Handed back unchanged to both callbacks
+    pub context: *mut c_void,
+    /// Copy the secret stored for `account` into `buf`, which holds `len`
+    /// bytes; return the secret's length, 0 if there is none, or -1 on
+    /// failure
+    pub get: extern "C" fn(
+        account: *const c_char,
+        buf: *mut u8,
+        len: usize,
+        context: *mut c_void,
+    ) -> isize,
+    /// Store the `len` bytes at `data` for `account`, replacing what was
+    /// there; return false on failure
+    pub set: extern "C" fn(
+        account: *const c_char,
+        data: *const u8,
+        len: usize,
+        context: *mut c_void,
+    ) -> bool,
+}
+
+/// [`SecretStore`] forwarding to host callbacks
+struct FfiSecretStore {
+    callbacks: PeerSecretStoreCallbacks,
+}
+
+// The context pointer is owned by the host, which promises it can be used
+// from any thread
+unsafe impl Send for FfiSecretStore {}
+unsafe impl Sync for FfiSecretStore {}
+
+impl SecretStore for FfiSecretStore {
+    fn get(&self, account: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let account = CString::new(account)?;
+        let mut buf = vec![0; MAX_SECRET_LEN];
+        let len = (self.callbacks.get)(
+            account.as_ptr(),
+            buf.as_mut_ptr(),
+            buf.len(),
+            self.callbacks.context,
+        );
+        let len = usize::try_from(len)
+            .map_err(|_| anyhow::anyhow!("The host failed to read {:?}", account))?;
+        anyhow::ensure!(
+            len <= buf.len(),
+            "The secret for {:?} is longer than {} bytes",
+            account,
+            MAX_SECRET_LEN
+        );
+        buf.truncate(len);
+        Ok((len > 0).then_some(buf))
+    }
+
+    fn set(&self, account: &str, secret: &[u8]) -> anyhow::Result<()> {
+        let account = CString::new(account)?;
+        let stored = (self.callbacks.set)(
+            account.as_ptr(),
+            secret.as_ptr(),
+            secret.len(),
+            self.callbacks.context,
+        );
+        anyhow::ensure!(stored, "The host failed to store {:?}", account);
+        Ok(())
+    }
+}
+
+/// Keep profile keys in the host's secure store, e.g. the Keychain, instead
+/// of the state directory
+///
+/// Call before `peer_start_with_profile`. Keys are stored under accounts
+/// named `mdns-peer/profile/<name>`; profiles saved before a store was set
+/// move their key into it when they next load. Setting a store again
+/// replaces the previous one.
+///
+/// # Safety
+///
+/// `callbacks.context` must stay valid until `peer_clear_secret_store` is
+/// called or another store is set, and be usable from any thread.
+#[no_mangle]
+pub unsafe extern "C" fn peer_set_secret_store(callbacks: PeerSecretStoreCallbacks) {
+    *SECRET_STORE.lock().unwrap() = Some(Arc::new(FfiSecretStore { callbacks }));
+}
+
+/// Go back to keeping profile keys in the state directory
+///
+/// Profiles whose key moved to the secure store no longer load until a
+/// store is set again.
+#[no_mangle]
+pub extern "C" fn peer_clear_secret_store() {
+    SECRET_STORE.lock().unwrap().take();
+}
+
+fn peer_notes() -> anyhow::Result<PeerNotes> {
+    let mut notes = NOTES.lock().unwrap();
+    if let Some(notes) = notes.as_ref() {
+        return Ok(notes.clone());
+    }
+    let opened = match STATE_DIR.lock().unwrap().as_ref() {
+        Some(dir) => PeerNotes::open(dir)?,
+        None => PeerNotes::open_default()?,
+    };
+    Ok(notes.insert(opened).clone())
+}
+
+/// Give a peer a local name, or remove it with a null or blank `alias`
+///
+/// Stored in the state directory and shown in `summary` events as `alias`,
+/// so the UI can show "Dad's MacBook" instead of a node ID. Applies to the
+/// running peer immediately. Returns false if the node ID is invalid or the
+/// alias is longer than 64 characters.
+///
+/// # Safety
+///
+/// `node_id` and `alias` must be null or point to valid NUL-terminated C
+/// strings.
+#[no_mangle]
+pub unsafe extern "C" fn peer_set_peer_alias(node_id: *const c_char, alias: *const c_char) -> bool {
+    let Some(node_id) = (unsafe { parse_node_id(node_id) }) else {
+        return false;
+    };
+    update_notes(|notes| notes.set_alias(node_id, unsafe { optional_str(alias) }?))
+}
+
+/// Replace the notes on a peer, or remove them with a null or blank `notes`
+///
+/// Stored and reported like `peer_set_peer_alias`, as `notes`; up to 4096
+/// bytes.
+///
+/// # Safety
+///
+/// `node_id` and `notes` must be null or point to valid NUL-terminated C
+/// strings.
+#[no_mangle]
+pub unsafe extern "C" fn peer_set_peer_notes(node_id: *const c_char, notes: *const c_char) -> bool {
+    let Some(node_id) = (unsafe { parse_node_id(node_id) }) else {
+        return false;
+    };
+    update_notes(|peer_notes| peer_notes.set_notes(node_id, unsafe { optional_str(notes) }?))
+}
+
+/// Every peer with an alias or notes, as a JSON object keyed by node ID:
+/// `{"<node_id>":{"alias":"Dad's MacBook","notes":null}}`
+///
+/// Returns null if the notes can't be read. Free the result with
+/// `peer_free_string`.
+#[no_mangle]
+pub extern "C" fn peer_get_peer_notes() -> *mut c_char {
+    match peer_notes() {
+        Ok(notes) => into_c_json(&notes.all()),
+        Err(e) => {
+            warn!("Peer notes unavailable: {:#}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+fn update_notes(change: impl FnOnce(&PeerNotes) -> anyhow::Result<()>) -> bool {
+    match peer_notes().and_then(|notes| change(&notes)) {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("Failed to update peer notes: {:#}", e);
+            false
+        }
+    }
+}
+
+fn session_history() -> anyhow::Result<SessionHistory> {
+    let mut history = HISTORY.lock().unwrap();
+    if let Some(history) = history.as_ref() {
+        return Ok(history.clone());
+    }
+    let opened = match STATE_DIR.lock().unwrap().as_ref() {
+        Some(dir) => SessionHistory::open(dir)?,
+        None => SessionHistory::open_default()?,
+    };
+    Ok(history.insert(opened).clone())
+}
+
+/// Summaries of past sessions and the running one, oldest first, as a JSON
+/// array (see [`SessionSummary`](crate::history::SessionSummary)), or null
+/// if the history can't be read
+///
+/// Each has the `identifier`, `node_id`, `started_wall_ms`, `uptime_ms`,
+/// `peers_discovered`, `connections`, `reconnects`, `reconnects_failed`,
+/// `errors` with the `last_error`, and whether it `ended` cleanly. The last
+/// 200 sessions are kept in the state directory. Free the result with
+/// `peer_free_string`.
+#[no_mangle]
+pub extern "C" fn peer_get_session_history() -> *mut c_char {
+    match session_history() {
+        Ok(history) => into_c_json(&history.sessions()),
+        Err(e) => {
+            warn!("Session history unavailable: {:#}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+fn peer_groups() -> anyhow::Result<PeerGroups> {
+    let mut groups = GROUPS.lock().unwrap();
+    if let Some(groups) = groups.as_ref() {
+        return Ok(groups.clone());
+    }
+    let opened = match STATE_DIR.lock().unwrap().as_ref() {
+        Some(dir) => PeerGroups::open(dir)?,
+        None => PeerGroups::open_default()?,
+    };
+    Ok(groups.insert(opened).clone())
+}
+
+/// Write the profiles with their keys, aliases and notes, groups, trusted
+/// peers and paired peers to `path`, encrypted with `passphrase`
+///
+/// For moving to a new device with `peer_import_state`; see
+/// [`crate::bundle`]. Stretching the passphrase takes about a second, so
+/// call it off the main thread. Returns false if either argument is null
+/// or empty, or the file can't be written.
+///
+/// # Safety
+///
+/// `path` and `passphrase` must be null or point to valid NUL-terminated C
+/// strings.
+#[no_mangle]
+pub unsafe extern "C" fn peer_export_state(path: *const c_char, passphrase: *const c_char) -> bool {
+    match unsafe { bundle_args(path, passphrase) }.and_then(|(path, passphrase)| {
+        let options = current_options();
+        let bundle = StateBundle::collect(
+            &profile_store()?,
+            &peer_notes()?,
+            &peer_groups()?,
+            options.warm_up.as_ref(),
+            &options.paired,
+        )?;
+        state::write_atomic_private(Path::new(path), &bundle.seal(passphrase)?)
+    }) {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("Failed to export state: {:#}", e);
+            false
+        }
+    }
+}
+
+/// Restore a bundle written by `peer_export_state`, returning what changed
+/// as JSON (see [`RestoreReport`](crate::bundle::RestoreReport)) or null
+/// on failure
+///
+/// Profiles, aliases and notes, and groups go into the state directory;
+/// existing profiles are kept over bundled ones with the same name. The
+/// bundled warm-up settings apply unless some are set already, and the
+/// paired peers are added; both take effect on the next `peer_start`. The
+/// report lists the bundled `trusted` peers for the app to store. Fails
+/// with a wrong passphrase or a modified file. Free the result with
+/// `peer_free_string`.
+///
+/// # Safety
+///
+/// `path` and `passphrase` must be null or point to valid NUL-terminated C
+/// strings.
+#[no_mangle]
+pub unsafe extern "C" fn peer_import_state(
+    path: *const c_char,
+    passphrase: *const c_char,
+) -> *mut c_char {
+    match unsafe { bundle_args(path, passphrase) }.and_then(|(path, passphrase)| {
+        let bytes =
+            std::fs::read(path).map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path, e))?;
+        let bundle = StateBundle::open(&bytes, passphrase)?;
+        let paired = bundle.paired_addrs()?;
+        let report = bundle.restore(&profile_store()?, &peer_notes()?, &peer_groups()?)?;
+
+        let mut options = OPTIONS.lock().unwrap();
+        let options = options.get_or_insert_with(PeerOptions::default);
+        if options.warm_up.is_none() {
+            options.warm_up = bundle.warm_up();
+        }
+        for addr in paired {
+            if !options
+                .paired
+                .iter()
+                .any(|known| known.node_id == addr.node_id)
+            {
+                options.paired.push(addr);
+            }
+        }
+        Ok(report)
+    }) {
+        Ok(report) => into_c_json(&report),
+        Err(e) => {
+            warn!("Failed to import state: {:#}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Path and passphrase of `peer_export_state` and `peer_import_state`
+///
+/// # Safety
+///
+/// Both must be null or point to valid NUL-terminated C strings.
+unsafe fn bundle_args<'a>(
+    path: *const c_char,
+    passphrase: *const c_char,
+) -> anyhow::Result<(&'a str, &'a str)> {
+    match unsafe { (optional_str(path)?, optional_str(passphrase)?) } {
+        (Some(path), Some(passphrase)) if !path.is_empty() => Ok((path, passphrase)),
+        _ => anyhow::bail!("A path and a passphrase are required"),
+    }
+}
+
+/// Create an empty peer group called `group`, e.g. "My devices"
+///
+/// Groups are stored in the state directory. Creating a group that exists
+/// keeps its members. Returns false if the name is blank or longer than 64
+/// characters.
+///
+/// # Safety
+///
+/// `group` must be null or point to a valid NUL-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn peer_create_group(group: *const c_char) -> bool {
+    let Ok(Some(group)) = (unsafe { optional_str(group) }) else {
+        warn!("peer_create_group called without a valid name");
+        return false;
+    };
+    update_groups(|groups| groups.create(group).map(|()| true))
+}
+
+/// Delete `group` with all its members
+///
+/// Returns false if there is no such group.
+///
+/// # Safety
+///
+/// `group` must be null or point to a valid NUL-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn peer_delete_group(group: *const c_char) -> bool {
+    let Ok(Some(group)) = (unsafe { optional_str(group) }) else {
+        warn!("peer_delete_group called without a valid name");
+        return false;
+    };
+    update_groups(|groups| groups.delete(group))
+}
+
+/// Put `node_id` in `group`, which must have been created
+///
+/// # Safety
+///
+/// `group` and `node_id` must be null or point to valid NUL-terminated C
+/// strings.
+#[no_mangle]
+pub unsafe extern "C" fn peer_add_to_group(group: *const c_char, node_id: *const c_char) -> bool {
+    let Some(node_id) = (unsafe { parse_node_id(node_id) }) else {
+        return false;
+    };
+    let Ok(Some(group)) = (unsafe { optional_str(group) }) else {
+        warn!("peer_add_to_group called without a valid name");
+        return false;
+    };
+    update_groups(|groups| groups.add(group, node_id).map(|()| true))
+}
+
+/// Take `node_id` out of `group`
+///
+/// Returns false if it wasn't in the group.
+///
+/// # Safety
+///
+/// `group` and `node_id` must be null or point to valid NUL-terminated C
+/// strings.
+#[no_mangle]
+pub unsafe extern "C" fn peer_remove_from_group(
+    group: *const c_char,
+    node_id: *const c_char,
+) -> bool {
+    let Some(node_id) = (unsafe { parse_node_id(node_id) }) else {
+        return false;
+    };
+    let Ok(Some(group)) = (unsafe { optional_str(group) }) else {
+        warn!("peer_remove_from_group called without a valid name");
+        return false;
+    };
+    update_groups(|groups| groups.remove(group, node_id))
+}
+
+/// Every group with its members, as a JSON object keyed by name:
+/// `{"My devices":["<node_id>","<node_id>"]}`
+///
+/// Returns null if the groups can't be read. Free the result with
+/// `peer_free_string`.
+#[no_mangle]
+pub extern "C" fn peer_get_groups() -> *mut c_char {
+    match peer_groups() {
+        Ok(groups) => into_c_json(&groups.all()),
+        Err(e) => {
+            warn!("Peer groups unavailable: {:#}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Send `len` bytes from `data` to every member of `group`, each as a
+/// message like `peer_send_message`
+///
+/// Members with an open connection get it right away, the others once they
+/// are reachable. Returns the message ID per member as a JSON object keyed
+/// by node ID, leaving out members whose queue is full, or null if there is
+/// no such group or the message is over 1 MiB. Free the result with
+/// `peer_free_string`.
+///
+/// # Safety
+///
+/// `group` must be null or point to a valid NUL-terminated C string, and
+/// `data` must point to at least `len` readable bytes (or be null with `len`
+/// 0).
+#[no_mangle]
+pub unsafe extern "C" fn peer_send_to_group(
+    group: *const c_char,
+    data: *const u8,
+    len: usize,
+) -> *mut c_char {
+    let Ok(Some(group)) = (unsafe { optional_str(group) }) else {
+        warn!("peer_send_to_group called without a valid name");
+        return std::ptr::null_mut();
+    };
+    let data = if len == 0 {
+        &[][..]
+    } else if data.is_null() {
+        return std::ptr::null_mut();
+    } else {
+        unsafe { std::slice::from_raw_parts(data, len) }
+    };
+
+    // Sending right away spawns on the runtime
+    let _guard = RUNTIME.get().map(|rt| rt.enter());
+    match peer_groups().and_then(|groups| groups.send(messages(), group, data)) {
+        Ok(sent) => into_c_json(&sent),
+        Err(e) => {
+            warn!("Not sending to group {:?}: {:#}", group, e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Which members of `group` are connected, as a JSON array of
+/// `{"node_id":"...","online":true,"connection":{"kind":"direct",...}}`
+///
+/// Returns null if the peer isn't running or there is no such group. Free
+/// the result with `peer_free_string`.
+///
+/// # Safety
+///
+/// `group` must be null or point to a valid NUL-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn peer_get_group_presence(group: *const c_char) -> *mut c_char {
+    let Ok(Some(group)) = (unsafe { optional_str(group) }) else {
+        warn!("peer_get_group_presence called without a valid name");
+        return std::ptr::null_mut();
+    };
+    let Some(endpoint) = ENDPOINT.lock().unwrap().clone() else {
+        return std::ptr::null_mut();
+    };
+    match peer_groups().map(|groups| groups.presence(&endpoint, group)) {
+        Ok(Some(presence)) => into_c_json(&presence),
+        Ok(None) => std::ptr::null_mut(),
+        Err(e) => {
+            warn!("Peer groups unavailable: {:#}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Apply `change` to the groups, returning whether it changed anything
+fn update_groups(change: impl FnOnce(&PeerGroups) -> anyhow::Result<bool>) -> bool {
+    match peer_groups().and_then(|groups| change(&groups)) {
+        Ok(changed) => changed,
+        Err(e) => {
+            warn!("Failed to update peer groups: {:#}", e);
+            false
+        }
+    }
+}
+
+/// A C string that may be null, as UTF-8
+///
+/// # Safety
+///
+/// `s` must be null or point to a valid NUL-terminated C string.
+unsafe fn optional_str<'a>(s: *const c_char) -> anyhow::Result<Option<&'a str>> {
+    if s.is_null() {
+        return Ok(None);
+    }
+    Ok(Some(unsafe { CStr::from_ptr(s) }.to_str()?))
+}
+
+/// Legacy name for backwards compatibility (defaults to "bob")
+#[no_mangle]
+pub extern "C" fn bob_start() -> bool {
+    start_peer("bob", current_options(), None)
+}
+
+/// Stop the peer
+#[no_mangle]
+pub extern "C" fn peer_stop() {
+    info!("Stopping peer...");
+
+    if let Some(sender) = SHUTDOWN_SENDER.get() {
+        let _ = sender.lock().unwrap().send(());
+        info!("Shutdown signal sent");
+    } else {
+        warn!("Peer was never started");
+    }
+}
+
+/// Move the running peer to a new secret key, returning its new node ID
+///
+/// The peer restarts on the new key. For `overlap_secs` (a day with 0) the
+/// old node ID stays announced, and the trusted peers from
+/// `peer_set_warm_up` and the paired ones are told the new one; see
+/// [`crate::rotation`]. A profile the peer started as keeps the new key,
+/// otherwise it replaces a key set through the options. Returns null if the
+/// peer isn't running or the new key can't be stored. Free the result with
+/// `peer_free_string`.
+#[no_mangle]
+pub extern "C" fn peer_rotate_key(overlap_secs: u64) -> *mut c_char {
+    let mut running = RUNNING.lock().unwrap();
+    let endpoint = ENDPOINT.lock().unwrap().clone();
+    let (Some(endpoint), Some(peer)) = (endpoint, running.as_ref()) else {
+        warn!("peer_rotate_key called while the peer isn't running");
+        return std::ptr::null_mut();
+    };
+
+    let new_key = SecretKey::generate(rand::rngs::OsRng);
+    if let Some(name) = &peer.profile {
+        let stored = profile_store().and_then(|store| store.replace_key(name, new_key.clone()));
+        if let Err(e) = stored {
+            warn!("Failed to store the new key of profile {}: {:#}", name, e);
+            return std::ptr::null_mut();
+        }
+    } else if let Some(options) = OPTIONS.lock().unwrap().as_mut() {
+        if options.secret_key.is_some() {
+            options.secret_key = Some(new_key.clone());
+        }
+    }
+    let peer = running.take().expect("checked above");
+    drop(running);
+
+    let mut options = current_options();
+    options.lite = peer.lite;
+    options.secret_key = Some(new_key.clone());
+    let trusted = rotation::trusted_peers(&options);
+    let notice = rotation::sign(endpoint.node_id(), &new_key);
+    let old_options = PeerOptions {
+        secret_key: Some(endpoint.secret_key().clone()),
+        mdns: options.mdns.clone(),
+        relays: options.relays.clone(),
+        lan_only: options.lan_only,
+        ..Default::default()
+    };
+    let overlap = match overlap_secs {
+        0 => rotation::DEFAULT_OVERLAP,
+        secs => Duration::from_secs(secs),
+    };
+    info!(
+        "Rotating key from {} to {}",
+        endpoint.node_id(),
+        new_key.public()
+    );
+
+    peer_stop();
+    runtime().spawn(async move {
+        let _ = peer.task.await;
+        if !start_peer(peer.identifier, options, peer.profile) {
+            return;
+        }
+        let shutdown = SHUTDOWN_SENDER
+            .get()
+            .expect("created by start_peer")
+            .lock()
+            .unwrap()
+            .subscribe();
+        match bind_endpoint_with(peer.identifier, &old_options).await {
+            Ok(old) => rotation::run_overlap(old, notice, trusted, overlap, shutdown).await,
+            Err(e) => warn!("Failed to keep announcing the old node ID: {:#}", e),
+        }
+    });
+
+    CString::new(new_key.public().to_string())
+        .expect("node IDs never contain NUL bytes")
+        .into_raw()
+}
+
+/// Load startup settings from the config file at `path` (a `.plist` or
+/// JSON file, e.g. `MdnsPeerConfig.plist` from the app bundle) and the
+/// `MDNS_PEER_*` environment variables, which take precedence
+///
+/// Pass null to read only the environment. Call it before the other
+/// `peer_set_*` setters, which overwrite what it loaded. Returns false, and
+/// changes nothing, if the file can't be read or a setting is invalid. Takes
+/// effect on the next `peer_start`; see [`crate::config`] for the keys.
+///
+/// # Safety
+///
+/// `path` must be null or point to a valid NUL-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn peer_load_config(path: *const c_char) -> bool {
+    initialize_logging();
+
+    let config = unsafe { optional_str(path) }.and_then(|path| {
+        let file = match path {
+            Some(path) => PeerConfig::load(Path::new(path))?,
+            None => PeerConfig::default(),
+        };
+        Ok(file.merge(PeerConfig::from_env()?))
+    });
+    let mut options = OPTIONS.lock().unwrap();
+    let options = options.get_or_insert_with(PeerOptions::default);
+    match config.and_then(|config| {
+        config.apply(options)?;
+        Ok(config)
+    }) {
+        Ok(config) => {
+            info!("Loaded config {:?}", config);
+            true
+        }
+        Err(e) => {
+            warn!("Failed to load config: {:#}", e);
+            false
+        }
+    }
+}
+
+/// Set how often a `summary` event is emitted, 0 to disable
+///
+/// Takes effect on the next `peer_start`. The default is 5000 ms.
+#[no_mangle]
+pub extern "C" fn peer_set_summary_interval_ms(interval_ms: u32) {
+    let mut options = OPTIONS.lock().unwrap();
+    options
+        .get_or_insert_with(PeerOptions::default)
+        .summary_interval = (interval_ms > 0).then(|| Duration::from_millis(interval_ms.into()));
+}
+
+/// Pre-establish connections on `alpn` to the given trusted peers as soon as
+/// they are discovered, so the first `peer_open_stream` skips the handshake
+///
+/// `node_ids` points to `count` node ID strings. Pass a null `alpn` to turn
+/// warm-up off. Takes effect on the next `peer_start`; the warm state shows
+/// up in `summary` events.
+///
+/// # Safety
+///
+/// `alpn` must be null or a valid NUL-terminated C string, and `node_ids`
+/// must point to `count` valid NUL-terminated C strings (or be null with
+/// `count` 0).
+#[no_mangle]
+pub unsafe extern "C" fn peer_set_warm_up(
+    alpn: *const c_char,
+    node_ids: *const *const c_char,
+    count: usize,
+) -> bool {
+    let warm_up = if alpn.is_null() {
+        None
+    } else {
+        if node_ids.is_null() && count > 0 {
+            return false;
+        }
+        let mut trusted = HashSet::new();
+        for i in 0..count {
+            let Some(node_id) = (unsafe { parse_node_id(*node_ids.add(i)) }) else {
+                return false;
+            };
+            trusted.insert(node_id);
+        }
+        Some(WarmUp {
+            alpn: unsafe { CStr::from_ptr(alpn) }.to_bytes().to_vec(),
+            trusted,
+        })
+    };
+
+    let mut options = OPTIONS.lock().unwrap();
+    options.get_or_insert_with(PeerOptions::default).warm_up = warm_up;
+    true
+}
+
+/// `name`, e.g. the device name, cut to fit discovery user data without
+/// splitting a character or emoji, and without control characters (see
+/// [`crate::naming::sanitize_identifier`])
+///
+/// Pass the result to `peer_start` and show it in the UI, since it is the
+/// name other peers see. Returns null if `name` is null or not UTF-8. Free
+/// the result with `peer_free_string`.
+///
+/// # Safety
+///
+/// `name` must be null or point to a valid NUL-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn peer_sanitize_identifier(name: *const c_char) -> *mut c_char {
+    if name.is_null() {
+        warn!("Null identifier");
+        return std::ptr::null_mut();
+    }
+    match unsafe { CStr::from_ptr(name) }.to_str() {
+        Ok(name) => CString::new(naming::sanitize_identifier(name))
+            .map_or(std::ptr::null_mut(), CString::into_raw),
+        Err(e) => {
+            warn!("Invalid identifier: {}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// An identifier based on `base` that no peer on the local network
+/// presents yet, such as `Jamie's iPhone-2` (see [`crate::naming`])
+///
+/// Listens to discovery for `listen_ms` (0 for 3 seconds) with the settings
+/// from `peer_set_mdns_params`, so call it off the main thread, before
+/// `peer_start`. Returns null if `base` isn't a valid identifier or
+/// discovery can't start. Free the result with `peer_free_string`.
+///
+/// # Safety
+///
+/// `base` must be null or point to a valid NUL-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn peer_pick_identifier(base: *const c_char, listen_ms: u32) -> *mut c_char {
+    if base.is_null() {
+        warn!("Null identifier");
+        return std::ptr::null_mut();
+    }
+    let base = match unsafe { CStr::from_ptr(base) }.to_str() {
+        Ok(base) => base,
+        Err(e) => {
+            warn!("Invalid identifier: {}", e);
+            return std::ptr::null_mut();
+        }
+    };
+    let listen = match listen_ms {
+        0 => naming::DEFAULT_LISTEN,
+        ms => Duration::from_millis(ms.into()),
+    };
+    let mdns = current_options().mdns;
+    match runtime().block_on(naming::pick_identifier(base, &mdns, listen)) {
+        Ok(identifier) => CString::new(identifier).map_or(std::ptr::null_mut(), CString::into_raw),
+        Err(e) => {
+            warn!("{:#}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// What `peer_start` does when another instance on this machine already
+/// advertises the identifier: 0 refuses (the default), 1 advertises a
+/// suffixed identifier such as `alice-2`, 2 doesn't check
+///
+/// Takes effect on the next `peer_start`. Profiles always refuse, since a
+/// suffix wouldn't change their node ID.
+#[no_mangle]
+pub extern "C" fn peer_set_duplicate_policy(policy: i32) -> bool {
+    let policy = match policy {
+        0 => DuplicatePolicy::Refuse,
+        1 => DuplicatePolicy::Suffix,
+        2 => DuplicatePolicy::Allow,
+        _ => return false,
+    };
+    let mut options = OPTIONS.lock().unwrap();
+    options
+        .get_or_insert_with(PeerOptions::default)
+        .on_duplicate = policy;
+    true
+}
+
+/// Record raw discovery events to the NDJSON file at `path`, null to stop
+/// recording
+///
+/// Takes effect on the next `peer_start`. The file is replaced each time.
+/// Pull it off a device that misbehaves in the field and feed it to
+/// `peer_replay_session` or `mdns-peer --replay` to reproduce the problem.
+///
+/// # Safety
+///
+/// `path` must be null or point to a valid NUL-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn peer_set_record_path(path: *const c_char) -> bool {
+    let record = if path.is_null() {
+        None
+    } else {
+        match unsafe { CStr::from_ptr(path) }.to_str() {
+            Ok(path) => Some(PathBuf::from(path)),
+            Err(e) => {
+                warn!("Invalid recording path: {}", e);
+                return false;
+            }
+        }
+    };
+
+    let mut options = OPTIONS.lock().unwrap();
+    options.get_or_insert_with(PeerOptions::default).record = record;
+    true
+}
+
+/// Replay a recording through the event callback, `speed` times faster than
+/// it was recorded (1.0 for the original pace)
+///
+/// Events are produced by a fresh registry, so they match what the recording
+/// device reported. Returns false if the file can't be read; the replay
+/// itself runs in the background.
+///
+/// # Safety
+///
+/// `path` must be null or point to a valid NUL-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn peer_replay_session(path: *const c_char, speed: f64) -> bool {
+    initialize_logging();
+
+    if path.is_null() {
+        warn!("peer_replay_session called with null path");
+        return false;
+    }
+    let session = unsafe { CStr::from_ptr(path) }
+        .to_str()
+        .map_err(anyhow::Error::from)
+        .and_then(Session::read);
+    let session = match session {
+        Ok(session) => session,
+        Err(e) => {
+            warn!("Failed to read recording: {:#}", e);
+            return false;
+        }
+    };
+
+    info!("Replaying {} recorded events", session.events.len());
+    let _runtime = runtime().enter();
+    supervise::spawn_supervised(
+        "replay",
+        Arc::new(deliver_event),
+        session.replay(speed, |event| deliver_event(&event)),
+    );
+    true
+}
+
+/// Version of the JSON schema the event callback's events follow, see
+/// [`crate::schema`]
+#[no_mangle]
+pub extern "C" fn peer_event_schema_version() -> u32 {
+    crate::schema::EVENT_SCHEMA_VERSION
+}
+
+/// Current [`PeerStatus`] as its C discriminant
+#[no_mangle]
+pub extern "C" fn peer_status() -> i32 {
+    STATUS.load(Ordering::SeqCst)
+}
+
+/// Why the peer last stopped, as the C discriminant of its
+/// [`ShutdownReason`], or 0 if it has not stopped since it was started
+#[no_mangle]
+pub extern "C" fn peer_shutdown_reason() -> i32 {
+    SHUTDOWN_REASON.load(Ordering::SeqCst)
+}
+
+/// Number of peers currently discovered, 0 while the peer isn't running
+///
+/// Read straight from the registry without building JSON, so views can
+/// poll it every frame.
+#[no_mangle]
+pub extern "C" fn peer_count() -> u32 {
+    registry().lock().unwrap().len() as u32
+}
+
+/// Whether a currently discovered peer announces exactly `user_data`
+///
+/// As cheap as `peer_count`. Returns false for a null or invalid string.
+///
+/// # Safety
+///
+/// `user_data` must be null or point to a valid NUL-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn peer_has_peer(user_data: *const c_char) -> bool {
+    match unsafe { optional_str(user_data) } {
+        Ok(Some(user_data)) => registry().lock().unwrap().has_user_data(user_data),
+        _ => false,
+    }
+}
+
+/// Currently discovered peers whose user data or alias matches `pattern`,
+/// as a JSON array (see [`FoundPeer`](crate::find::FoundPeer))
+///
+/// A pattern with `*` or `?` is a glob matching the whole name, any other
+/// pattern matches names starting with it; case is ignored (see
+/// [`crate::find`]). Returns null for a null or invalid string. Free the
+/// result with `peer_free_string`.
+///
+/// # Safety
+///
+/// `pattern` must be null or point to a valid NUL-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn peer_find(pattern: *const c_char) -> *mut c_char {
+    let Ok(Some(pattern)) = (unsafe { optional_str(pattern) }) else {
+        return std::ptr::null_mut();
+    };
+    let notes = peer_notes().unwrap_or_default();
+    let found = find::find_peers(
+        &registry().lock().unwrap(),
+        &notes,
+        &find::PeerPattern::new(pattern),
+    );
+    into_c_json(&found)
+}
+
+/// Register the callback that receives all events as JSON, replacing any
+/// previous one. Pass a null callback to unregister.
+///
+/// The callback is invoked from a dedicated Rust thread, one event at a
+/// time (or one batch, see `peer_set_event_batch_window`). If the host
+/// falls behind by more events than the queue holds, the oldest queued
+/// events are dropped (see `peer_dropped_event_count`). The queue holds
+/// `queued_events` from `peer_set_memory_budget`, 256 by default.
+#[no_mangle]
+pub extern "C" fn peer_set_event_callback(callback: Option<EventCallback>, context: *mut c_void) {
+    peer_set_event_callback_filtered(callback, context, event_mask::ALL)
+}
+
+/// Like `peer_set_event_callback`, but only events whose [`event_mask`] bit
+/// is set in `mask` are serialized and delivered
+#[no_mangle]
+pub extern "C" fn peer_set_event_callback_filtered(
+    callback: Option<EventCallback>,
+    context: *mut c_void,
+    mask: u32,
+) {
+    let mut guard = EVENT_CALLBACK.lock().unwrap();
+    *guard = callback.map(|callback| RegisteredCallback {
+        callback,
+        context: context as usize,
+        mask,
+    });
+}
+
+/// Coalesce events for `window_ms` milliseconds and deliver them together
+///
+/// While enabled, each callback receives a JSON array of events instead of a
+/// single event object; the window starts at the first event of a batch. A
+/// burst of cached announcements after the app wakes up then costs one
+/// callback instead of dozens. Pass 0 to go back to one event per callback.
+#[no_mangle]
+pub extern "C" fn peer_set_event_batch_window(window_ms: u32) {
+    BATCH_WINDOW_MS.store(window_ms, Ordering::Relaxed);
+    dispatcher().set_batch_window(Duration::from_millis(window_ms.into()));
+}
+
+/// Number of events dropped because the host callback fell behind
+#[no_mangle]
+pub extern "C" fn peer_dropped_event_count() -> u64 {
+    dispatcher().dropped()
+}
+
+/// Full iroh remote info for one peer as JSON (addresses, latency, last
+/// use, relay), see [`RemoteInfoReport`]
+///
+/// `quic` lists the transport state of each open connection on a host
+/// protocol, messages or files: RTT and its variance, congestion window,
+/// losses and UDP datagram counts.
+///
+/// Returns null if the peer isn't running, `node_id` doesn't parse, or the
+/// endpoint knows nothing about that node. Free the result with
+/// `peer_free_string`.
+///
+/// # Safety
+///
+/// `node_id` must be null or point to a valid NUL-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn peer_get_remote_info(node_id: *const c_char) -> *mut c_char {
+    let Some(node_id) = (unsafe { parse_node_id(node_id) }) else {
+        return std::ptr::null_mut();
+    };
+    let Some(endpoint) = ENDPOINT.lock().unwrap().clone() else {
+        warn!("peer_get_remote_info called while the peer is not running");
+        return std::ptr::null_mut();
+    };
+    let Some(info) = endpoint.remote_info(node_id) else {
+        return std::ptr::null_mut();
+    };
+    let mut report = RemoteInfoReport::from(info);
+    report.quic = protocols().quic_stats(node_id);
+    into_c_json(&report)
+}
+
+/// Where to dial a peer from another endpoint, as JSON:
+/// `{"node_id":"...","relay_url":"https://...","direct_addrs":["192.168.1.20:4433"],"ticket":"node..."}`
+///
+/// Covers whatever the endpoint learned about the node, through discovery,
+/// pairing or injected candidates, so apps with their own iroh endpoint (or
+/// another QUIC stack) can reuse it; `ticket` is an iroh node ticket, which
+/// also works as a pairing payload. Returns null if the peer isn't running,
+/// `node_id` doesn't parse, or the endpoint knows nothing about that node.
+/// Free the result with `peer_free_string`.
+///
+/// # Safety
+///
+/// `node_id` must be null or point to a valid NUL-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn peer_get_node_addr(node_id: *const c_char) -> *mut c_char {
+    let Some(node_id) = (unsafe { parse_node_id(node_id) }) else {
+        return std::ptr::null_mut();
+    };
+    let Some(endpoint) = ENDPOINT.lock().unwrap().clone() else {
+        warn!("peer_get_node_addr called while the peer is not running");
+        return std::ptr::null_mut();
+    };
+    match remote_info::node_addr(&endpoint, node_id) {
+        Some(addr) => into_c_json(&NodeAddrReport::from(addr)),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Callback asked whether to accept an inbound connection
+///
+/// Answer with `peer_respond_accept(request_id, ...)`, right away or after
+/// asking the user. The strings are only valid for the duration of the call.
+pub type AcceptCallback = extern "C" fn(
+    request_id: u64,
+    node_id: *const c_char,
+    alpn: *const c_char,
+    trusted: bool,
+    context: *mut c_void,
+);
+
+/// [`AcceptPolicy`] that asks the host through an [`AcceptCallback`]
+struct HostAcceptPolicy {
+    callback: AcceptCallback,
+    /// Opaque host pointer, stored as an address so the policy is `Send`
+    context: usize,
+}
+
+impl AcceptPolicy for HostAcceptPolicy {
+    fn decide(&self, request: InboundRequest) -> BoxFuture<bool> {
+        let id = NEXT_ACCEPT_REQUEST.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        ACCEPT_REQUESTS.lock().unwrap().insert(id, tx);
+
+        let node_id = CString::new(request.node_id.to_string()).expect("node IDs are hex");
+        let alpn = CString::new(String::from_utf8_lossy(&request.alpn).replace('\0', ""))
+            .expect("NUL bytes were removed");
+        (self.callback)(
+            id,
+            node_id.as_ptr(),
+            alpn.as_ptr(),
+            request.trusted,
+            self.context as *mut c_void,
+        );
+
+        Box::pin(async move {
+            let _pending = PendingAccept(id);
+            rx.await.unwrap_or(false)
+        })
+    }
+}
+
+/// Forgets an accept request once it is answered or given up on
+struct PendingAccept(u64);
+
+impl Drop for PendingAccept {
+    fn drop(&mut self) {
+        ACCEPT_REQUESTS.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// Ask the host before serving each inbound connection, or accept all
+/// again with a null callback
+///
+/// The callback gets the remote node ID (authenticated by the handshake),
+/// the protocol's ALPN and whether the peer is one of the trusted peers
+/// from `peer_set_warm_up`. It runs on a runtime thread and must return
+/// quickly; answer with `peer_respond_accept`. A connection not answered
+/// within 30 seconds is denied. Every decision is reported as an
+/// `inbound_connection` event. Applies immediately.
+#[no_mangle]
+pub extern "C" fn peer_set_accept_policy(callback: Option<AcceptCallback>, context: *mut c_void) {
+    let policy = callback.map(|callback| {
+        Arc::new(HostAcceptPolicy {
+            callback,
+            context: context as usize,
+        }) as Arc<dyn AcceptPolicy>
+    });
+    protocols().set_accept_policy(policy);
+}
+
+/// Accept or deny the inbound connection the accept callback asked about
+///
+/// Returns false if `request_id` is unknown, e.g. because it timed out.
+#[no_mangle]
+pub extern "C" fn peer_respond_accept(request_id: u64, accept: bool) -> bool {
+    let pending = ACCEPT_REQUESTS.lock().unwrap().remove(&request_id);
+    pending.is_some_and(|tx| tx.send(accept).is_ok())
+}
+
+/// Limit host protocol traffic per connection; 0 means unlimited
+///
+/// `send_bytes_per_sec` caps the payload sent on one connection across all
+/// its streams; `max_concurrent_streams` caps how many streams run at once,
+/// with further streams waiting for a free slot. Applies immediately,
+/// including to open streams, so the app can throttle a background sync
+/// while the user is on a call.
+#[no_mangle]
+pub extern "C" fn peer_set_transfer_limits(send_bytes_per_sec: u64, max_concurrent_streams: u32) {
+    protocols().set_limits(TransferLimits {
+        send_bytes_per_sec: (send_bytes_per_sec > 0).then_some(send_bytes_per_sec),
+        max_concurrent_streams: (max_concurrent_streams > 0)
+            .then_some(max_concurrent_streams as usize),
+    });
+}
+
+/// Cap what other peers may open on this one; 0 means unlimited
+///
+/// `max_inbound_connections` caps the connections peers open to the host's
+/// protocols at the same time; further ones are closed with error code 429
+/// and reported as `inbound_connection` events with `accepted` false.
+/// `max_streams_per_peer` caps the streams one peer has open towards this
+/// one, across its connections; further streams are reset with code 429.
+/// Applies to connections and streams arriving from now on.
+#[no_mangle]
+pub extern "C" fn peer_set_accept_limits(max_inbound_connections: u32, max_streams_per_peer: u32) {
+    protocols().set_accept_limits(AcceptLimits {
+        max_inbound_connections: (max_inbound_connections > 0)
+            .then_some(max_inbound_connections as usize),
+        max_streams_per_peer: (max_streams_per_peer > 0).then_some(max_streams_per_peer as usize),
+    });
+}
+
+/// Choose the home relay from these relays instead of iroh's defaults
+///
+/// `urls` points to `count` relay URL strings; pass null with `count` 0 to
+/// go back to the defaults. Takes effect on the next `peer_start`. Use
+/// `peer_probe_relays` to find the nearest ones.
+///
+/// # Safety
+///
+/// `urls` must point to `count` valid NUL-terminated C strings (or be null
+/// with `count` 0).
+#[no_mangle]
+pub unsafe extern "C" fn peer_set_relays(urls: *const *const c_char, count: usize) -> bool {
+    let relays = if count == 0 {
+        None
+    } else {
+        if urls.is_null() {
+            return false;
+        }
+        let mut relays = Vec::with_capacity(count);
+        for i in 0..count {
+            let url = unsafe { CStr::from_ptr(*urls.add(i)) };
+            match url.to_str().map(str::parse::<RelayUrl>) {
+                Ok(Ok(url)) => relays.push(url),
+                _ => {
+                    warn!("Invalid relay URL: {:?}", url);
+                    return false;
+                }
+            }
+        }
+        Some(relays)
+    };
+
+    let mut options = OPTIONS.lock().unwrap();
+    options.get_or_insert_with(PeerOptions::default).relays = relays;
+    true
+}
+
+/// Keep all traffic on the local network: with `lan_only`, no relay is
+/// used, and connections that would have needed one fail with a
+/// `relay_refused` event instead
+///
+/// Without it, peers that can't be reached directly fall back to a relay,
+/// reported as `relay_fallback` events. Takes effect on the next
+/// `peer_start`.
+#[no_mangle]
+pub extern "C" fn peer_set_lan_only(lan_only: bool) {
+    let mut options = OPTIONS.lock().unwrap();
+    options.get_or_insert_with(PeerOptions::default).lan_only = lan_only;
+}
+
+/// Look peers up in DNS once local discovery has found nobody for
+/// `window_ms`, or never with 0 (the default)
+///
+/// 30000 is a good default. Switches are reported as
+/// `discovery_mode_changed` events, the first one when the peer starts.
+/// Publishes this device's node ID, addresses and user data to n0's DNS
+/// servers while falling back. Takes effect on the next `peer_start`.
+#[no_mangle]
+pub extern "C" fn peer_set_dns_fallback(window_ms: u64) {
+    let fallback = (window_ms > 0).then(|| DnsFallback::new(Duration::from_millis(window_ms)));
+    let mut options = OPTIONS.lock().unwrap();
+    options
+        .get_or_insert_with(PeerOptions::default)
+        .dns_fallback = fallback;
+}
+
+/// Log user data, identifiers and aliases as they are (`true`) instead of
+/// as hashes (`false`, the default)
+///
+/// Applies to log lines right away and to recordings from the next
+/// `peer_start`. Events always carry names as they are.
+#[no_mangle]
+pub extern "C" fn peer_set_log_names(log_names: bool) {
+    privacy::set_log_names(log_names);
+    let mut options = OPTIONS.lock().unwrap();
+    options.get_or_insert_with(PeerOptions::default).log_names = log_names;
+}
+
+/// Tune local network discovery: the mDNS service name (null for the
+/// default `iroh.local.swarm`), the announcement cadence in milliseconds and
+/// the swarm-wide response rate in Hz (0 for the defaults, 700 ms and 2.5 Hz)
+///
+/// A longer cadence or lower rate means less multicast traffic on a crowded
+/// network but slower discovery; a different service name only sees peers
+/// using the same one. Returns false if the combination is invalid. Takes
+/// effect on the next `peer_start`.
+///
+/// # Safety
+///
+/// `service_name` must be null or point to a valid NUL-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn peer_set_mdns_params(
+    service_name: *const c_char,
+    cadence_ms: u32,
+    response_rate: f32,
+) -> bool {
+    let mut mdns = MdnsOptions::default();
+    if !service_name.is_null() {
+        match unsafe { CStr::from_ptr(service_name) }.to_str() {
+            Ok(name) => mdns.service_name = name.to_string(),
+            Err(e) => {
+                warn!("Invalid mDNS service name: {}", e);
+                return false;
+            }
+        }
+    }
+    if cadence_ms > 0 {
+        mdns.cadence = Duration::from_millis(cadence_ms.into());
+    }
+    if response_rate != 0.0 {
+        mdns.response_rate = response_rate;
+    }
+    if let Err(e) = mdns.validate() {
+        warn!("{:#}", e);
+        return false;
+    }
+
+    let mut options = OPTIONS.lock().unwrap();
+    options.get_or_insert_with(PeerOptions::default).mdns = mdns;
+    true
+}
+
+/// Bind the endpoint to these local addresses or this UDP port instead of
+/// every interface on an ephemeral port, null for the default
+///
+/// `addrs` is a comma-separated list of at most one address per family
+/// (`192.168.1.5:7777`, `[::]:7777`), or a bare port for every interface.
+/// Takes effect on the next `peer_start`, which fails if a pinned port is
+/// taken.
+///
+/// # Safety
+///
+/// `addrs` must be null or point to a valid NUL-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn peer_set_bind_addrs(addrs: *const c_char) -> bool {
+    let bind = match unsafe { optional_str(addrs) } {
+        Ok(None) => Ok(BindAddrs::default()),
+        Ok(Some(addrs)) => BindAddrs::parse(addrs),
+        Err(e) => Err(e),
+    };
+    let bind = match bind {
+        Ok(bind) => bind,
+        Err(e) => {
+            warn!("Invalid bind addresses: {:#}", e);
+            return false;
+        }
+    };
+
+    let mut options = OPTIONS.lock().unwrap();
+    options.get_or_insert_with(PeerOptions::default).bind = bind;
+    true
+}
+
+/// URL of the relay the running peer is reachable through, or null if it
+/// isn't running or hasn't selected one
+///
+/// Free the result with `peer_free_string`.
+#[no_mangle]
+pub extern "C" fn peer_get_home_relay() -> *mut c_char {
+    let home = ENDPOINT
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(relay::home_relay);
+    match home {
+        Some(url) => CString::new(url.to_string()).map_or(std::ptr::null_mut(), CString::into_raw),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// The iPhone Personal Hotspot this device shares or joined, as JSON (see
+/// [`Hotspot`](crate::hotspot::Hotspot)), or null if it's on none
+///
+/// While a peer is running, `direct_addrs` lists its addresses on the
+/// hotspot, the ones to pair or dial with. Free the result with
+/// `peer_free_string`.
+#[no_mangle]
+pub extern "C" fn peer_get_hotspot() -> *mut c_char {
+    let endpoint = ENDPOINT.lock().unwrap().clone();
+    match hotspot::current(endpoint.as_ref()) {
+        Some(hotspot) => into_c_json(&hotspot),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Pairing payload of the running peer, for the host to show as a QR code,
+/// or null if it isn't running or has no address yet
+///
+/// The payload is upper-case alphanumeric, so QR encoders can use their
+/// compact alphanumeric mode (see [`crate::pairing`]). Blocks for up to 5
+/// seconds right after `peer_start` while the first addresses are found.
+/// Free the result with `peer_free_string`.
+#[no_mangle]
+pub extern "C" fn peer_get_addr_qr_payload() -> *mut c_char {
+    let Some(endpoint) = ENDPOINT.lock().unwrap().clone() else {
+        warn!("peer_get_addr_qr_payload called while the peer is not running");
+        return std::ptr::null_mut();
+    };
+    match runtime().block_on(pairing::local_payload(&endpoint, QR_PAYLOAD_WAIT)) {
+        Some(payload) => CString::new(payload).map_or(std::ptr::null_mut(), CString::into_raw),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Add a peer from a scanned pairing payload, so it can be connected to
+/// without being discovered
+///
+/// Applies to the running peer immediately, and to every later
+/// `peer_start`. Returns false if the payload is invalid.
+///
+/// # Safety
+///
+/// `payload` must be null or point to a valid NUL-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn peer_add_peer_qr_payload(payload: *const c_char) -> bool {
+    if payload.is_null() {
+        warn!("Null pairing payload");
+        return false;
+    }
+    let payload = unsafe { CStr::from_ptr(payload) };
+    let addr = match payload.to_str().map(pairing::decode_payload) {
+        Ok(Ok(addr)) => addr,
+        Ok(Err(e)) => {
+            warn!("{:#}", e);
+            return false;
+        }
+        Err(e) => {
+            warn!("Invalid pairing payload: {}", e);
+            return false;
+        }
+    };
+
+    if let Some(endpoint) = ENDPOINT.lock().unwrap().as_ref() {
+        if let Err(e) = pairing::add_paired_peer(endpoint, addr.clone()) {
+            warn!("{:#}", e);
+            return false;
+        }
+    }
+    let mut options = OPTIONS.lock().unwrap();
+    let paired = &mut options.get_or_insert_with(PeerOptions::default).paired;
+    paired.retain(|known| known.node_id != addr.node_id);
+    paired.push(addr);
+    true
+}
+
+/// Inject a peer candidate learned over another channel, such as a
+/// Bluetooth advertisement carrying a node ticket
+///
+/// `ticket` is a node ticket in either case, e.g. a pairing payload. The
+/// peer can then be connected to by node ID. Injecting the same node again
+/// replaces its addresses and restarts its lifetime; after `ttl_secs`
+/// without that (0 for 2 minutes) it is forgotten. Works before and while
+/// the peer runs. Returns false if the ticket is invalid.
+///
+/// # Safety
+///
+/// `ticket` must be null or point to a valid NUL-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn peer_inject_candidate(ticket: *const c_char, ttl_secs: u32) -> bool {
+    if ticket.is_null() {
+        warn!("Null candidate ticket");
+        return false;
+    }
+    let ticket = unsafe { CStr::from_ptr(ticket) };
+    let addr = match ticket.to_str().map(pairing::decode_payload) {
+        Ok(Ok(addr)) => addr,
+        _ => {
+            warn!("Invalid candidate ticket: {:?}", ticket);
+            return false;
+        }
+    };
+    let ttl = match ttl_secs {
+        0 => DEFAULT_CANDIDATE_TTL,
+        secs => Duration::from_secs(secs.into()),
+    };
+    candidates().inject(addr, ttl);
+    true
+}
+
+/// Tell the peer whether a peer-to-peer interface, such as `awdl0` once
+/// Network.framework brought AWDL up for the app, is usable right now
+///
+/// Once any interface was reported, addresses on `awdl*`, `llw*` and every
+/// reported interface are left out of announcements and dialing unless
+/// reported available, see [`crate::p2p`]; report them before
+/// `peer_start`, as unavailable if they aren't up yet. Works before and
+/// while the peer runs; a change re-announces it. Returns false if `name`
+/// is null or not UTF-8.
+///
+/// # Safety
+///
+/// `name` must be null or point to a valid NUL-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn peer_set_p2p_interface(name: *const c_char, available: bool) -> bool {
+    if name.is_null() {
+        warn!("Null interface name");
+        return false;
+    }
+    match unsafe { CStr::from_ptr(name) }.to_str() {
+        Ok(name) => {
+            p2p_interfaces().set_available(name, available);
+            true
+        }
+        Err(_) => {
+            warn!("Interface name is not UTF-8");
+            false
+        }
+    }
+}
+
+/// Forget an injected candidate before it expires; returns whether it was
+/// known
+///
+/// # Safety
+///
+/// `node_id` must be null or point to a valid NUL-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn peer_remove_candidate(node_id: *const c_char) -> bool {
+    match unsafe { parse_node_id(node_id) } {
+        Some(node_id) => candidates().remove(node_id),
+        None => false,
+    }
+}
+
+/// Forget every injected candidate
+#[no_mangle]
+pub extern "C" fn peer_clear_candidates() {
+    candidates().clear();
+}
+
+/// Tell the peer the app is back in the foreground
+///
+/// Trusted peers from `peer_set_warm_up` whose connection dropped while the
+/// app was suspended are dialed again; each attempt is reported as a
+/// `reconnect` event. Does nothing if the peer isn't running.
+#[no_mangle]
+pub extern "C" fn peer_resume() {
+    resume().resume();
+}
+
+/// Measure the latency to every configured relay, as JSON (see
+/// [`RelayReport`](crate::relay::RelayReport)), nearest first
+///
+/// Blocks for up to about 10 seconds when relays are unreachable, so call it
+/// off the main thread. Free the result with `peer_free_string`.
+#[no_mangle]
+pub extern "C" fn peer_probe_relays() -> *mut c_char {
+    let relays = relay::configured_relays(&current_options());
+    let home = ENDPOINT
+        .lock()
+        .unwrap()
+        .as_ref()
+        .and_then(relay::home_relay);
+    let report = runtime().block_on(relay::probe_relays(relays, home));
+    into_c_json(&report)
+}
+
+/// Which interfaces deliver multicast, as JSON (see
+/// [`MulticastReport`](crate::multicast::MulticastReport))
+///
+/// Sends a beacon to the mDNS group out of each interface and listens for
+/// it for a second, without iroh, so a silent discovery can be pinned on
+/// the OS or network (no interface `delivers`, or sending fails with errno
+/// 65 when the multicast entitlement is missing) rather than on the peer.
+/// Blocks for that second, so call it off the main thread. Returns null if
+/// the mDNS port can't be bound. Free the result with `peer_free_string`.
+#[no_mangle]
+pub extern "C" fn peer_probe_multicast() -> *mut c_char {
+    match multicast::probe(multicast::DEFAULT_PROBE_WINDOW) {
+        Ok(report) => into_c_json(&report),
+        Err(e) => {
+            warn!("Multicast probe failed: {}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Run the loopback self-test and return its report as JSON (see
+/// [`SelfTestReport`](crate::self_test::SelfTestReport))
+///
+/// Starts two temporary peers in this process and checks that they
+/// discover each other over mDNS and that a message gets through, which
+/// shows whether the multicast entitlement, Local Network permission and
+/// the network work without a second device. They use the cadence from
+/// `peer_set_mdns_params` on a service name of their own, so other peers
+/// never see them, and a running peer is left alone. Blocks for up to
+/// about 20 seconds, so call it off the main thread. Free the result with
+/// `peer_free_string`.
+#[no_mangle]
+pub extern "C" fn peer_self_test() -> *mut c_char {
+    let mdns = current_options().mdns;
+    let report = runtime().block_on(self_test::run_self_test(
+        &mdns,
+        self_test::DEFAULT_STEP_TIMEOUT,
+    ));
+    if !report.passed {
+        warn!("Self-test failed: {:?}", report.steps.last());
+    }
+    into_c_json(&report)
+}
+
+/// Bytes sent and received on host protocols, per peer and ALPN, as JSON
+/// (see [`StatsReport`](crate::stats::StatsReport))
+///
+/// Includes rates over the last 10 seconds, each peer's connection type,
+/// the file transfers in progress and the addresses the network sees this
+/// peer as. Free the result with `peer_free_string`.
+#[no_mangle]
+pub extern "C" fn peer_get_stats() -> *mut c_char {
+    let mut report = protocols().stats();
+    report.transfers = transfers().transfers();
+    into_c_json(&report)
+}
+
+/// Versions, platform, interfaces and how discovery and relays are set
+/// up, as JSON (see [`EnvironmentReport`])
+///
+/// Meant to be attached to bug reports. Reflects the options set so far,
+/// plus the node ID and sockets while a peer is running. Free the result
+/// with `peer_free_string`.
+#[no_mangle]
+pub extern "C" fn peer_get_environment() -> *mut c_char {
+    let endpoint = ENDPOINT.lock().unwrap().clone();
+    into_c_json(&EnvironmentReport::collect(
+        &current_options(),
+        endpoint.as_ref(),
+    ))
+}
+
+/// The background tasks currently running, as a JSON array (see
+/// [`TaskReport`](crate::tasks::TaskReport))
+///
+/// When the peer seems stuck, a task `running` for long is blocked in its
+/// own code and one `waiting` far longer than usual is blocked on what it
+/// awaits. Free the result with `peer_free_string`.
+#[no_mangle]
+pub extern "C" fn peer_get_tasks() -> *mut c_char {
+    into_c_json(&tasks::tasks())
+}
+
+/// Free a string returned by one of the `peer_get_*` functions
+///
+/// # Safety
+///
+/// `s` must be null or a pointer returned by this library that has not been
+/// freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn peer_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(unsafe { CString::from_raw(s) });
+    }
+}
+
+/// Parse a node ID passed in as a C string, logging why it was rejected
+///
+/// # Safety
+///
+/// `node_id` must be null or point to a valid NUL-terminated C string.
+unsafe fn parse_node_id(node_id: *const c_char) -> Option<NodeId> {
+    if node_id.is_null() {
+        warn!("Null node ID");
+        return None;
+    }
+    let c_str = unsafe { CStr::from_ptr(node_id) };
+    match c_str.to_str().map(str::parse::<NodeId>) {
+        Ok(Ok(node_id)) => Some(node_id),
+        _ => {
+            warn!("Invalid node ID: {:?}", c_str);
+            None
+        }
+    }
+}
+
+/// Serialize `value` into a heap C string owned by the caller
+fn into_c_json(value: &impl serde::Serialize) -> *mut c_char {
+    let json = serde_json::to_string(value).expect("reports are always serializable");
+    CString::new(json)
+        .expect("JSON never contains NUL bytes")
+        .into_raw()
+}
+
+/// Host callbacks for one protocol registered with `peer_register_protocol`
+///
+/// All callbacks run on runtime threads and should return quickly. Pointers
+/// passed to them are only valid for the duration of the call.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PeerProtocolCallbacks {
+    /// Handed back unchanged to every callback
+    pub context: *mut c_void,
+    /// A stream is ready; `node_id` is the remote node as a C string
+    pub on_open: extern "C" fn(stream_id: u64, node_id: *const c_char, context: *mut c_void),
+    /// `len` bytes arrived on the stream
+    pub on_data: extern "C" fn(stream_id: u64, data: *const u8, len: usize, context: *mut c_void),
+    /// The remote finished sending (`error` is null), or the stream failed
+    pub on_close: extern "C" fn(stream_id: u64, error: *const c_char, context: *mut c_void),
+}
+
+/// [`StreamHandler`] forwarding to host callbacks
+struct FfiStreamHandler {
+    callbacks: PeerProtocolCallbacks,
+}
+
+// The context pointer is owned by the host, which promises it can be used
+// from any thread
+unsafe impl Send for FfiStreamHandler {}
+unsafe impl Sync for FfiStreamHandler {}
+
+impl StreamHandler for FfiStreamHandler {
+    fn on_open(&self, stream: StreamId, node_id: NodeId) {
+        let node_id = CString::new(node_id.to_string()).expect("node IDs never contain NUL bytes");
+        (self.callbacks.on_open)(stream, node_id.as_ptr(), self.callbacks.context);
+    }
+
+    fn on_data(&self, stream: StreamId, data: &[u8]) {
+        (self.callbacks.on_data)(stream, data.as_ptr(), data.len(), self.callbacks.context);
+    }
+
+    fn on_close(&self, stream: StreamId, error: Option<&str>) {
+        let error = error.map(|e| CString::new(e.replace('\0', "")).unwrap_or_default());
+        let error_ptr = error.as_ref().map_or(std::ptr::null(), |e| e.as_ptr());
+        (self.callbacks.on_close)(stream, error_ptr, self.callbacks.context);
+    }
+}
+
+/// Register a host protocol: streams on `alpn` are surfaced through
+/// `callbacks`
+///
+/// Register before `peer_start`; inbound connections are only accepted for
+/// ALPNs known when the peer starts. Registering an ALPN again replaces its
+/// callbacks.
+///
+/// # Safety
+///
+/// `alpn` must be null or point to a valid NUL-terminated C string, and
+/// `callbacks.context` must stay valid for as long as the library may call
+/// back, from any thread.
+#[no_mangle]
+pub unsafe extern "C" fn peer_register_protocol(
+    alpn: *const c_char,
+    callbacks: PeerProtocolCallbacks,
+) -> bool {
+    if alpn.is_null() {
+        warn!("peer_register_protocol called with null ALPN");
+        return false;
+    }
+    let alpn = unsafe { CStr::from_ptr(alpn) }.to_bytes();
+    if alpn.is_empty() {
+        warn!("peer_register_protocol called with empty ALPN");
+        return false;
+    }
+    protocols().register(alpn, Arc::new(FfiStreamHandler { callbacks }));
+    true
+}
+
+/// Register a host protocol whose incoming data is read with
+/// `peer_stream_read` instead of `on_data`
+///
+/// Otherwise like `peer_register_protocol`: `on_open` and `on_close` are
+/// called as usual, `on_data` never. Data the host hasn't read yet stays
+/// with the sender, so the host reads at its own pace.
+///
+/// # Safety
+///
+/// Same as `peer_register_protocol`.
+#[no_mangle]
+pub unsafe extern "C" fn peer_register_pull_protocol(
+    alpn: *const c_char,
+    callbacks: PeerProtocolCallbacks,
+) -> bool {
+    if alpn.is_null() {
+        warn!("peer_register_pull_protocol called with null ALPN");
+        return false;
+    }
+    let alpn = unsafe { CStr::from_ptr(alpn) }.to_bytes();
+    if alpn.is_empty() {
+        warn!("peer_register_pull_protocol called with empty ALPN");
+        return false;
+    }
+    protocols().register_pull(alpn, Arc::new(FfiStreamHandler { callbacks }));
+    true
+}
+
+/// Read up to `len` bytes of incoming data on `stream_id` into `buf`,
+/// blocking until some arrive
+///
+/// For streams on protocols registered with `peer_register_pull_protocol`.
+/// Returns the number of bytes read, 0 once the remote finished sending and
+/// everything was read, or -1 if the stream failed or isn't read this way.
+/// Call it from a background thread, never from a protocol callback.
+///
+/// # Safety
+///
+/// `buf` must point to at least `len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn peer_stream_read(stream_id: u64, buf: *mut u8, len: usize) -> i64 {
+    if buf.is_null() || len == 0 {
+        warn!("peer_stream_read called without a buffer");
+        return -1;
+    }
+    // Blocking a runtime thread would stall the reading it waits for
+    if tokio::runtime::Handle::try_current().is_ok() {
+        warn!("peer_stream_read called from a runtime thread");
+        return -1;
+    }
+    match runtime().block_on(protocols().read(stream_id, len)) {
+        Ok(Some(data)) => {
+            unsafe { std::ptr::copy_nonoverlapping(data.as_ptr(), buf, data.len()) };
+            data.len() as i64
+        }
+        Ok(None) => 0,
+        Err(e) => {
+            warn!("peer_stream_read on stream {}: {:#}", stream_id, e);
+            -1
+        }
+    }
+}
+
+/// Connect to `node_id` on `alpn`, waiting at most `timeout_ms`
+///
+/// Blocks until the connection is open, so call it off the main thread.
+/// Returns a [`ResultCode`]: 0 once connected (or if a connection was open
+/// already), 3 if `timeout_ms` passed first, 1 for an invalid argument, 2
+/// if the peer isn't running, and 4 for any other failure, which is
+/// logged. A `timeout_ms` of 0 waits as long as iroh keeps trying.
+/// Connecting ahead of time saves the handshake on the first stream.
+///
+/// # Safety
+///
+/// `node_id` and `alpn` must be null or point to valid NUL-terminated C
+/// strings.
+#[no_mangle]
+pub unsafe extern "C" fn peer_connect(
+    node_id: *const c_char,
+    alpn: *const c_char,
+    timeout_ms: u64,
+) -> i32 {
+    let Some(node_id) = (unsafe { parse_node_id(node_id) }) else {
+        return ResultCode::InvalidArgument as i32;
+    };
+    if alpn.is_null() {
+        warn!("peer_connect called with null ALPN");
+        return ResultCode::InvalidArgument as i32;
+    }
+    let alpn = unsafe { CStr::from_ptr(alpn) }.to_bytes();
+    match connect(node_id, alpn, timeout_ms) {
+        Ok(_) => ResultCode::Ok as i32,
+        Err(code) => code as i32,
+    }
+}
+
+/// Measure the round-trip time to `node_id`, connecting first if needed,
+/// waiting at most `timeout_ms`
+///
+/// Writes QUIC's current round-trip estimate in milliseconds to `rtt_ms`
+/// and returns a [`ResultCode`] like `peer_connect`. Connects on the
+/// message protocol, which every peer but a lite one serves.
+///
+/// # Safety
+///
+/// `node_id` must be null or point to a valid NUL-terminated C string, and
+/// `rtt_ms` must be null or point to a writable `double`.
+#[no_mangle]
+pub unsafe extern "C" fn peer_ping(
+    node_id: *const c_char,
+    timeout_ms: u64,
+    rtt_ms: *mut f64,
+) -> i32 {
+    let Some(node_id) = (unsafe { parse_node_id(node_id) }) else {
+        return ResultCode::InvalidArgument as i32;
+    };
+    match connect(node_id, crate::messages::MESSAGE_ALPN, timeout_ms) {
+        Ok(conn) => {
+            if !rtt_ms.is_null() {
+                unsafe { *rtt_ms = conn.rtt().as_secs_f64() * 1000.0 };
+            }
+            ResultCode::Ok as i32
+        }
+        Err(code) => code as i32,
+    }
+}
+
+/// Connect for `peer_connect` and `peer_ping`, logging why it failed
+fn connect(
+    node_id: NodeId,
+    alpn: &[u8],
+    timeout_ms: u64,
+) -> Result<iroh::endpoint::Connection, ResultCode> {
+    let (Some(rt), Some(endpoint)) = (RUNTIME.get(), ENDPOINT.lock().unwrap().clone()) else {
+        warn!("Connecting while the peer is not running");
+        return Err(ResultCode::NotRunning);
+    };
+    let timeout = deadline::from_millis(timeout_ms);
+    rt.block_on(protocols().connect_within(&endpoint, node_id, alpn, timeout))
+        .map_err(|e| {
+            warn!("Connecting to {} failed: {:#}", node_id.fmt_short(), e);
+            ResultCode::from_error(&e)
+        })
+}
+
+/// Called once when an operation started by an `_async` call ends
+///
+/// `status` is a `ResultCode`: 0 on success, 3 if it timed out, 4 if it
+/// failed and 5 if it was cancelled. `result_json` is the event reporting
+/// how it ended, the same one the event callback gets: `operation_finished`,
+/// `transfer_finished`, `message_delivered` or `message_failed`. Runs on a
+/// runtime thread and should return quickly; `result_json` is only valid
+/// for the duration of the call.
+pub type CompletionCallback =
+    extern "C" fn(op_id: u64, status: i32, result_json: *const c_char, context: *mut c_void);
+
+/// A host's [`CompletionCallback`], called at most once
+struct Completion {
+    callback: CompletionCallback,
+    /// Opaque host pointer, stored as an address so the completion is `Send`
+    context: usize,
+}
+
+impl Completion {
+    fn new(callback: Option<CompletionCallback>, context: *mut c_void) -> Option<Self> {
+        callback.map(|callback| Self {
+            callback,
+            context: context as usize,
+        })
+    }
+
+    /// Hand the host `event`, which ended operation `op_id`
+    fn complete(self, op_id: OperationId, event: &PeerEvent) {
+        let json = CString::new(event.to_json()).expect("JSON never contains NUL bytes");
+        let status = completion_status(event);
+        (self.callback)(
+            op_id,
+            status as i32,
+            json.as_ptr(),
+            self.context as *mut c_void,
+        );
+    }
+
+    /// As the callback of a transfer, whose ID is its operation ID
+    fn on_transfer_finished(self) -> impl FnOnce(&PeerEvent) + Send + 'static {
+        move |event| {
+            if let PeerEvent::TransferFinished { id, .. } = event {
+                self.complete(*id, event);
+            }
+        }
+    }
+}
+
+/// The status a [`CompletionCallback`] gets for the event ending an
+/// operation
+fn completion_status(event: &PeerEvent) -> ResultCode {
+    match event {
+        PeerEvent::OperationFinished { status, .. } => *status,
+        PeerEvent::TransferFinished { outcome, .. } => match outcome {
+            TransferOutcome::Completed => ResultCode::Ok,
+            TransferOutcome::Failed => ResultCode::Failed,
+            TransferOutcome::Cancelled => ResultCode::Cancelled,
+            TransferOutcome::TimedOut => ResultCode::TimedOut,
+        },
+        PeerEvent::MessageFailed {
+            timed_out: true, ..
+        } => ResultCode::TimedOut,
+        PeerEvent::MessageFailed { .. } => ResultCode::Failed,
+        _ => ResultCode::Ok,
+    }
+}
+
+/// Like `peer_connect`, but in the background, returning an operation ID
+/// right away so the connect can be stopped with `peer_cancel`
+///
+/// How it ended is reported as an `operation_finished` event with this ID
+/// and the `status` (`ok`, `timed_out`, `cancelled` or `failed`, with the
+/// `error`), then passed to `callback` unless it is null. Returns 0 if an
+/// argument is invalid or the peer isn't running, and never calls
+/// `callback` then.
+///
+/// # Safety
+///
+/// Same as `peer_connect`, and `context` must stay valid until `callback`
+/// is called, from any thread.
+#[no_mangle]
+pub unsafe extern "C" fn peer_connect_async(
+    node_id: *const c_char,
+    alpn: *const c_char,
+    timeout_ms: u64,
+    callback: Option<CompletionCallback>,
+    context: *mut c_void,
+) -> u64 {
+    let Some(node_id) = (unsafe { parse_node_id(node_id) }) else {
+        return 0;
+    };
+    if alpn.is_null() {
+        warn!("peer_connect_async called with null ALPN");
+        return 0;
+    }
+    let alpn = unsafe { CStr::from_ptr(alpn) }.to_bytes().to_vec();
+    let completion = Completion::new(callback, context);
+    start_connect(
+        OperationKind::Connect,
+        node_id,
+        alpn,
+        timeout_ms,
+        completion,
+    )
+}
+
+/// Like `peer_ping`, but in the background, returning an operation ID and
+/// calling `callback` as `peer_connect_async` does
+///
+/// The `operation_finished` event of a ping that succeeded carries the
+/// round-trip estimate as `rtt_us`, in microseconds.
+///
+/// # Safety
+///
+/// `node_id` must be null or point to a valid NUL-terminated C string, and
+/// `context` must stay valid until `callback` is called, from any thread.
+#[no_mangle]
+pub unsafe extern "C" fn peer_ping_async(
+    node_id: *const c_char,
+    timeout_ms: u64,
+    callback: Option<CompletionCallback>,
+    context: *mut c_void,
+) -> u64 {
+    let Some(node_id) = (unsafe { parse_node_id(node_id) }) else {
+        return 0;
+    };
+    let alpn = crate::messages::MESSAGE_ALPN.to_vec();
+    let completion = Completion::new(callback, context);
+    start_connect(OperationKind::Ping, node_id, alpn, timeout_ms, completion)
+}
+
+/// Run a connect as a cancellable operation, reporting how it ended
+fn start_connect(
+    kind: OperationKind,
+    node_id: NodeId,
+    alpn: Vec<u8>,
+    timeout_ms: u64,
+    completion: Option<Completion>,
+) -> OperationId {
+    let (Some(rt), Some(endpoint)) = (RUNTIME.get(), ENDPOINT.lock().unwrap().clone()) else {
+        warn!("Connecting while the peer is not running");
+        return 0;
+    };
+    let timeout = deadline::from_millis(timeout_ms);
+    let (id, connecting) = operations::cancellable(kind, async move {
+        protocols()
+            .connect_within(&endpoint, node_id, &alpn, timeout)
+            .await
+    });
+
+    let events = protocols().events();
+    let _guard = rt.enter();
+    supervise::spawn_supervised("operation", events.clone(), async move {
+        let (status, error, rtt_us) = match connecting.await {
+            Ok(conn) => {
+                let rtt = (kind == OperationKind::Ping).then(|| conn.rtt().as_micros() as u64);
+                (ResultCode::Ok, None, rtt)
+            }
+            Err(e) => (ResultCode::from_error(&e), Some(format!("{:#}", e)), None),
+        };
+        let finished = PeerEvent::OperationFinished {
+            id,
+            kind,
+            status,
+            error,
+            rtt_us,
+        };
+        events(&finished);
+        if let Some(completion) = completion {
+            completion.complete(id, &finished);
+        }
+    });
+    id
+}
+
+/// Stop operation `op_id`: a connect or ping started with
+/// `peer_connect_async` or `peer_ping_async`, or a file transfer, whose
+/// transfer ID is its operation ID
+///
+/// Returns false if it already ended or never existed. A stopped connect
+/// reports `operation_finished` with the `cancelled` status, a transfer
+/// `transfer_finished` with the `cancelled` outcome.
+#[no_mangle]
+pub extern "C" fn peer_cancel(op_id: u64) -> bool {
+    operations::cancel(op_id)
+}
+
+/// Open a stream to `node_id` on a registered `alpn`
+///
+/// Returns the stream ID, or 0 if the peer isn't running or an argument is
+/// invalid. The protocol's `on_open` fires once connected, or `on_close`
+/// with an error if the connection fails.
+///
+/// # Safety
+///
+/// `node_id` and `alpn` must be null or point to valid NUL-terminated C
+/// strings.
+#[no_mangle]
+pub unsafe extern "C" fn peer_open_stream(node_id: *const c_char, alpn: *const c_char) -> u64 {
+    unsafe { peer_open_stream_timeout(node_id, alpn, 0) }
+}
+
+/// Like `peer_open_stream`, but `on_close` fires with an error starting
+/// with "Timed out" if the stream isn't open within `timeout_ms`
+///
+/// 0 waits as long as connecting takes, like `peer_open_stream`.
+///
+/// # Safety
+///
+/// Same as `peer_open_stream`.
+#[no_mangle]
+pub unsafe extern "C" fn peer_open_stream_timeout(
+    node_id: *const c_char,
+    alpn: *const c_char,
+    timeout_ms: u64,
+) -> u64 {
+    let Some(node_id) = (unsafe { parse_node_id(node_id) }) else {
+        return 0;
+    };
+    if alpn.is_null() {
+        warn!("peer_open_stream called with null ALPN");
+        return 0;
+    }
+    let alpn = unsafe { CStr::from_ptr(alpn) }.to_bytes();
+    let (Some(rt), Some(endpoint)) = (RUNTIME.get(), ENDPOINT.lock().unwrap().clone()) else {
+        warn!("peer_open_stream called while the peer is not running");
+        return 0;
+    };
+
+    let _guard = rt.enter();
+    let timeout = deadline::from_millis(timeout_ms);
+    match protocols().open_stream_within(&endpoint, node_id, alpn, timeout) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Failed to open stream: {}", e);
+            0
+        }
+    }
+}
+
+/// Queue `len` bytes from `data` for sending on `stream_id`
+///
+/// Returns false if the stream is unknown or already finished.
+///
+/// # Safety
+///
+/// `data` must point to at least `len` readable bytes (or be null with `len`
+/// 0).
+#[no_mangle]
+pub unsafe extern "C" fn peer_stream_write(stream_id: u64, data: *const u8, len: usize) -> bool {
+    let data = if len == 0 {
+        Vec::new()
+    } else if data.is_null() {
+        return false;
+    } else {
+        unsafe { std::slice::from_raw_parts(data, len) }.to_vec()
+    };
+    protocols().write(stream_id, data)
+}
+
+/// Finish sending on `stream_id` once queued writes are sent
+#[no_mangle]
+pub extern "C" fn peer_stream_finish(stream_id: u64) -> bool {
+    protocols().finish(stream_id)
+}
+
+/// Open a send-only stream to `node_id` on a registered `alpn`, for
+/// payloads too large to send as one buffer
+///
+/// Returns the stream ID, or 0 if the peer isn't running or an argument is
+/// invalid. Write with `peer_send_stream_write` and end it with
+/// `peer_stream_finish`; the protocol's `on_close` fires with a null error
+/// once the remote received everything, or with an error if the stream
+/// failed. The remote gets it through its own protocol callbacks.
+///
+/// # Safety
+///
+/// `node_id` and `alpn` must be null or point to valid NUL-terminated C
+/// strings.
+#[no_mangle]
+pub unsafe extern "C" fn peer_open_send_stream(node_id: *const c_char, alpn: *const c_char) -> u64 {
+    unsafe { peer_open_send_stream_timeout(node_id, alpn, 0) }
+}
+
+/// Like `peer_open_send_stream`, with a timeout for opening the stream as
+/// in `peer_open_stream_timeout`
+///
+/// # Safety
+///
+/// Same as `peer_open_send_stream`.
+#[no_mangle]
+pub unsafe extern "C" fn peer_open_send_stream_timeout(
+    node_id: *const c_char,
+    alpn: *const c_char,
+    timeout_ms: u64,
+) -> u64 {
+    let Some(node_id) = (unsafe { parse_node_id(node_id) }) else {
+        return 0;
+    };
+    if alpn.is_null() {
+        warn!("peer_open_send_stream called with null ALPN");
+        return 0;
+    }
+    let alpn = unsafe { CStr::from_ptr(alpn) }.to_bytes();
+    let (Some(rt), Some(endpoint)) = (RUNTIME.get(), ENDPOINT.lock().unwrap().clone()) else {
+        warn!("peer_open_send_stream called while the peer is not running");
+        return 0;
+    };
+
+    let _guard = rt.enter();
+    let timeout = deadline::from_millis(timeout_ms);
+    match protocols().open_send_stream_within(&endpoint, node_id, alpn, timeout) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Failed to open send stream: {}", e);
+            0
+        }
+    }
+}
+
+/// Queue `len` bytes from `data` for sending on `stream_id`, blocking while
+/// more than 1 MiB written earlier is still waiting to go out
+///
+/// Writing a large payload in pieces this way keeps at most about 1 MiB of
+/// it in memory. Call it from a background thread; from a protocol callback
+/// it queues without waiting, like `peer_stream_write`. Works on any stream.
+/// Returns false if the stream is unknown, finished or failed.
+///
+/// # Safety
+///
+/// `data` must point to at least `len` readable bytes (or be null with `len`
+/// 0).
+#[no_mangle]
+pub unsafe extern "C" fn peer_send_stream_write(
+    stream_id: u64,
+    data: *const u8,
+    len: usize,
+) -> bool {
+    let data = if len == 0 {
+        Vec::new()
+    } else if data.is_null() {
+        return false;
+    } else {
+        unsafe { std::slice::from_raw_parts(data, len) }.to_vec()
+    };
+    // Blocking a runtime thread would stall the sending it waits for
+    if tokio::runtime::Handle::try_current().is_ok() {
+        return protocols().write(stream_id, data);
+    }
+    runtime().block_on(protocols().write_buffered(stream_id, data))
+}
+
+/// A host buffer lent to a stream, released through `done` once dropped
+///
+/// QUIC keeps written bytes until the remote acknowledges them, so the
+/// buffer is dropped only then, or when the stream goes away first.
+struct HostBuffer {
+    /// Stored as addresses so the buffer is `Send`
+    data: usize,
+    len: usize,
+    done: ReleaseCallback,
+    context: usize,
+}
+
+impl AsRef<[u8]> for HostBuffer {
+    fn as_ref(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        // The host keeps the bytes alive and unchanged until `done`
+        unsafe { std::slice::from_raw_parts(self.data as *const u8, self.len) }
+    }
+}
+
+impl Drop for HostBuffer {
+    fn drop(&mut self) {
+        (self.done)(self.context as *mut c_void);
+    }
+}
+
+/// Lend `len` bytes at `data` to a write, or `None` (after calling `done`)
+/// if `data` is null
+fn lend(data: *const u8, len: usize, done: ReleaseCallback, context: *mut c_void) -> Option<Bytes> {
+    let buffer = HostBuffer {
+        data: data as usize,
+        len,
+        done,
+        context: context as usize,
+    };
+    if data.is_null() && len > 0 {
+        return None;
+    }
+    Some(Bytes::from_owner(buffer))
+}
+
+/// Like `peer_stream_write`, but lends `data` to the stream instead of
+/// copying it
+///
+/// Worth it for multi-megabyte payloads such as photos, which would
+/// otherwise be copied at the boundary. `done(context)` is called exactly
+/// once, on any thread, when the library no longer needs the buffer: once
+/// the remote acknowledged every byte, or the stream failed or closed
+/// first. That includes when this returns false, in which case it's called
+/// before returning.
+///
+/// # Safety
+///
+/// `data` must point to at least `len` readable bytes (or be null with `len`
+/// 0), which must stay valid and unchanged until `done` is called.
+#[no_mangle]
+pub unsafe extern "C" fn peer_stream_write_borrowed(
+    stream_id: u64,
+    data: *const u8,
+    len: usize,
+    done: ReleaseCallback,
+    context: *mut c_void,
+) -> bool {
+    let Some(data) = lend(data, len, done, context) else {
+        return false;
+    };
+    protocols().write(stream_id, data)
+}
+
+/// Like `peer_send_stream_write`, but lends `data` to the stream instead of
+/// copying it, with `done` called as for `peer_stream_write_borrowed`
+///
+/// Blocks the same way, so a host lending one piece of a large payload at a
+/// time still keeps at most about 1 MiB of it queued.
+///
+/// # Safety
+///
+/// `data` must point to at least `len` readable bytes (or be null with `len`
+/// 0), which must stay valid and unchanged until `done` is called.
+#[no_mangle]
+pub unsafe extern "C" fn peer_send_stream_write_borrowed(
+    stream_id: u64,
+    data: *const u8,
+    len: usize,
+    done: ReleaseCallback,
+    context: *mut c_void,
+) -> bool {
+    let Some(data) = lend(data, len, done, context) else {
+        return false;
+    };
+    if tokio::runtime::Handle::try_current().is_ok() {
+        return protocols().write(stream_id, data);
+    }
+    runtime().block_on(protocols().write_buffered(stream_id, data))
+}
+
+/// Send `len` bytes from `data` to `node_id` as one message, returning its
+/// message ID
+///
+/// If the peer isn't reachable yet, or the peer itself isn't running, the
+/// message is queued and sent in order once it is (see
+/// `peer_get_message_queue`). Progress is reported as `message_sent`,
+/// `message_delivered` and `message_failed` events with this ID. Returns
+/// null if an argument is invalid, the message is over 1 MiB, or 256
+/// messages are already queued for that peer. Release the ID with
+/// `peer_free_string`.
+///
+/// # Safety
+///
+/// `node_id` must be null or point to a valid NUL-terminated C string, and
+/// `data` must point to at least `len` readable bytes (or be null with `len`
+/// 0).
+#[no_mangle]
+pub unsafe extern "C" fn peer_send_message(
+    node_id: *const c_char,
+    data: *const u8,
+    len: usize,
+) -> *mut c_char {
+    unsafe { send_message(node_id, data, len, false, 0) }
+}
+
+/// Like `peer_send_message`, but gives up on the message if it isn't
+/// delivered within `timeout_ms`
+///
+/// A message that misses it is dropped from the queue and reported as
+/// `message_failed` with `timed_out` true. 0 keeps it queued until it is
+/// delivered, like `peer_send_message`.
+///
+/// # Safety
+///
+/// Same as `peer_send_message`.
+#[no_mangle]
+pub unsafe extern "C" fn peer_send_message_timeout(
+    node_id: *const c_char,
+    data: *const u8,
+    len: usize,
+    timeout_ms: u64,
+) -> *mut c_char {
+    unsafe { send_message(node_id, data, len, false, timeout_ms) }
+}
+
+/// Like `peer_send_message`, but seals the payload so only `node_id` and
+/// this peer can open it, even after it leaves the connection
+///
+/// The key is derived from both node identities, so nothing needs to be
+/// exchanged beyond pairing. The receiver gets the opened payload through
+/// `peer_set_sealed_message_callback`. At most 1 MiB minus 41 bytes.
+///
+/// # Safety
+///
+/// Same as `peer_send_message`.
+#[no_mangle]
+pub unsafe extern "C" fn peer_send_sealed(
+    node_id: *const c_char,
+    data: *const u8,
+    len: usize,
+) -> *mut c_char {
+    unsafe { send_message(node_id, data, len, true, 0) }
+}
+
+/// Like `peer_send_sealed`, with a timeout as in
+/// `peer_send_message_timeout`
+///
+/// # Safety
+///
+/// Same as `peer_send_message`.
+#[no_mangle]
+pub unsafe extern "C" fn peer_send_sealed_timeout(
+    node_id: *const c_char,
+    data: *const u8,
+    len: usize,
+    timeout_ms: u64,
+) -> *mut c_char {
+    unsafe { send_message(node_id, data, len, true, timeout_ms) }
+}
+
+/// Like `peer_send_message_timeout`, or `peer_send_sealed_timeout` if
+/// `sealed`, but returns an operation ID and calls `callback` once the
+/// message is delivered or given up on
+///
+/// `callback` gets the `message_delivered` or final `message_failed` event,
+/// whose `id` is the message ID. Without a timeout, that can take until the
+/// peer shows up. Returns 0 if the message wasn't queued, and never calls
+/// `callback` then. Messages can't be stopped with `peer_cancel`.
+///
+/// # Safety
+///
+/// Same as `peer_send_message`, and `context` must stay valid until
+/// `callback` is called, from any thread.
+#[no_mangle]
+pub unsafe extern "C" fn peer_send_message_async(
+    node_id: *const c_char,
+    data: *const u8,
+    len: usize,
+    sealed: bool,
+    timeout_ms: u64,
+    callback: Option<CompletionCallback>,
+    context: *mut c_void,
+) -> u64 {
+    let op_id = operations::next_id();
+    let completion = Completion::new(callback, context).map(|completion| (op_id, completion));
+    match unsafe { queue_message(node_id, data, len, sealed, timeout_ms, completion) } {
+        Some(_) => op_id,
+        None => 0,
+    }
+}
+
+/// Queue a message from the host, returning its ID as a C string or null
+///
+/// # Safety
+///
+/// Same as `peer_send_message`.
+unsafe fn send_message(
+    node_id: *const c_char,
+    data: *const u8,
+    len: usize,
+    sealed: bool,
+    timeout_ms: u64,
+) -> *mut c_char {
+    match unsafe { queue_message(node_id, data, len, sealed, timeout_ms, None) } {
+        Some(id) => CString::new(id.to_string())
+            .expect("UUIDs never contain NUL bytes")
+            .into_raw(),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Queue a message from the host, handing how it ended to `completion`
+/// under its operation ID
+///
+/// # Safety
+///
+/// Same as `peer_send_message`.
+unsafe fn queue_message(
+    node_id: *const c_char,
+    data: *const u8,
+    len: usize,
+    sealed: bool,
+    timeout_ms: u64,
+    completion: Option<(OperationId, Completion)>,
+) -> Option<MessageId> {
+    let node_id = unsafe { parse_node_id(node_id) }?;
+    let data = if len == 0 {
+        Vec::new()
+    } else if data.is_null() {
+        return None;
+    } else {
+        unsafe { std::slice::from_raw_parts(data, len) }.to_vec()
+    };
+
+    // Sending right away spawns on the runtime
+    let _guard = RUNTIME.get().map(|rt| rt.enter());
+    let timeout = deadline::from_millis(timeout_ms);
+    let sent = match completion {
+        Some((op_id, completion)) => {
+            messages().send_then(node_id, data, sealed, timeout, move |event| {
+                completion.complete(op_id, event)
+            })
+        }
+        None if sealed => messages().send_sealed_within(node_id, data, timeout),
+        None => messages().send_within(node_id, data, timeout),
+    };
+    match sent {
+        Ok(id) => Some(id),
+        Err(e) => {
+            warn!("Not sending message: {:#}", e);
+            None
+        }
+    }
+}
+
+/// Called with each message another peer sent; `node_id` is the sender and
+/// `message_id` the sender's ID for it, both as C strings
+///
+/// Runs on a runtime thread and should return quickly. The pointers are
+/// only valid for the duration of the call.
+pub type MessageCallback = extern "C" fn(
+    node_id: *const c_char,
+    message_id: *const c_char,
+    data: *const u8,
+    len: usize,
+    context: *mut c_void,
+);
+
+/// [`MessageHandler`] forwarding to a [`MessageCallback`]
+struct FfiMessageHandler {
+    callback: MessageCallback,
+    /// Opaque host pointer, stored as an address so the handler is `Send`
+    context: usize,
+}
+
+impl MessageHandler for FfiMessageHandler {
+    fn on_message(&self, node_id: NodeId, id: MessageId, data: &[u8]) {
+        let node_id = CString::new(node_id.to_string()).expect("node IDs never contain NUL bytes");
+        let id = CString::new(id.to_string()).expect("UUIDs never contain NUL bytes");
+        (self.callback)(
+            node_id.as_ptr(),
+            id.as_ptr(),
+            data.as_ptr(),
+            data.len(),
+            self.context as *mut c_void,
+        );
+    }
+}
+
+/// Receive messages from other peers through `callback`, or drop them with
+/// a null callback. Applies immediately.
+#[no_mangle]
+pub extern "C" fn peer_set_message_callback(
+    callback: Option<MessageCallback>,
+    context: *mut c_void,
+) {
+    let handler = callback.map(|callback| {
+        Arc::new(FfiMessageHandler {
+            callback,
+            context: context as usize,
+        }) as Arc<dyn MessageHandler>
+    });
+    messages().set_handler(handler);
+}
+
+/// Receive sealed messages through `callback` once opened, or drop them
+/// with a null callback. Applies immediately.
+///
+/// Sealed messages never reach the `peer_set_message_callback` callback,
+/// so the app knows which payloads were sealed.
+#[no_mangle]
+pub extern "C" fn peer_set_sealed_message_callback(
+    callback: Option<MessageCallback>,
+    context: *mut c_void,
+) {
+    let handler = callback.map(|callback| {
+        Arc::new(FfiMessageHandler {
+            callback,
+            context: context as usize,
+        }) as Arc<dyn MessageHandler>
+    });
+    messages().set_sealed_handler(handler);
+}
+
+/// Limit what each peer may send this one in messages
+///
+/// `max_size` is the largest payload accepted in bytes (0 for the 1 MiB
+/// maximum); `per_second` is how many messages one peer may send per second
+/// on average (0 for no limit), after a burst of up to `burst` (0 for the
+/// default of 50). Refused messages are reported as `rate_limited` events.
+/// The default is 20 per second. Applies to messages arriving from now on.
+#[no_mangle]
+pub extern "C" fn peer_set_message_limits(max_size: u32, per_second: u32, burst: u32) {
+    let defaults = MessageLimits::default();
+    messages().set_limits(MessageLimits {
+        max_size: match max_size {
+            0 => defaults.max_size,
+            max_size => max_size as usize,
+        },
+        per_second: (per_second > 0).then_some(per_second),
+        burst: match burst {
+            0 => defaults.burst,
+            burst => burst,
+        },
+    });
+}
+
+/// Messages waiting to be sent, as a JSON object from node ID to count
+///
+/// Peers with nothing queued are left out.
+#[no_mangle]
+pub extern "C" fn peer_get_message_queue() -> *mut c_char {
+    into_c_json(&messages().queue_depths())
+}
+
+/// Cap what the library holds in memory; 0 keeps a limit's default
+///
+/// `queued_message_bytes` caps the payloads of messages waiting to be sent,
+/// to all peers together (32 MiB by default), `discovered_peers` the peers
+/// discovery lists (1024), and `queued_events` the events waiting for the
+/// event callback (256). When a pool is full and `drop_oldest` is false,
+/// new entries are turned away: `peer_send_message` returns null and newly
+/// announced peers aren't listed. With `drop_oldest` true, the
+/// longest-queued messages fail with a `message_failed` event and the
+/// peers announced longest ago are reported as expired instead. Events
+/// waiting for the callback always drop the oldest. Each pool reports what
+/// it dropped in an `over_budget` event, at most once a second.
+///
+/// Applies right away to messages and events, and on the next `peer_start`
+/// to discovered peers.
+#[no_mangle]
+pub extern "C" fn peer_set_memory_budget(
+    queued_message_bytes: u64,
+    discovered_peers: u32,
+    queued_events: u32,
+    drop_oldest: bool,
+) {
+    let defaults = MemoryBudget::default();
+    let budget = MemoryBudget {
+        queued_message_bytes: match queued_message_bytes {
+            0 => defaults.queued_message_bytes,
+            bytes => usize::try_from(bytes).unwrap_or(usize::MAX),
+        },
+        discovered_peers: match discovered_peers {
+            0 => defaults.discovered_peers,
+            peers => peers as usize,
+        },
+        queued_events: match queued_events {
+            0 => defaults.queued_events,
+            events => events as usize,
+        },
+        policy: if drop_oldest {
+            DropPolicy::DropOldest
+        } else {
+            DropPolicy::RefuseNew
+        },
+    };
+    messages().set_budget(&budget);
+    dispatcher().set_capacity(budget.queued_events);
+    OPTIONS
+        .lock()
+        .unwrap()
+        .get_or_insert_with(PeerOptions::default)
+        .budget = budget;
+}
+
+/// Send the file at `path` to `node_id` in the background
+///
+/// Returns the transfer's ID, or 0 if the peer isn't running or an argument
+/// is invalid. Progress shows up in `peer_get_transfers`, and the outcome
+/// as a `transfer_finished` event. Sending a file that was interrupted
+/// before continues where the receiver stopped.
+///
+/// # Safety
+///
+/// `node_id` and `path` must be null or point to valid NUL-terminated C
+/// strings.
+#[no_mangle]
+pub unsafe extern "C" fn peer_send_file(node_id: *const c_char, path: *const c_char) -> u64 {
+    unsafe { peer_send_file_timeout(node_id, path, 0) }
+}
+
+/// Like `peer_send_file`, but the transfer ends with the `timed_out`
+/// outcome if the receiver hasn't accepted it within `timeout_ms`
+///
+/// The timeout covers connecting and the receiver deciding, not sending
+/// the file once it's accepted. 0 waits as long as that takes, like
+/// `peer_send_file`.
+///
+/// # Safety
+///
+/// Same as `peer_send_file`.
+#[no_mangle]
+pub unsafe extern "C" fn peer_send_file_timeout(
+    node_id: *const c_char,
+    path: *const c_char,
+    timeout_ms: u64,
+) -> u64 {
+    unsafe { send_file(node_id, path, timeout_ms, None) }
+}
+
+/// Like `peer_send_file_timeout`, also calling `callback` with the
+/// `transfer_finished` event once the transfer ends
+///
+/// The transfer ID is the operation ID `callback` gets. Returns 0 like
+/// `peer_send_file`, and never calls `callback` then.
+///
+/// # Safety
+///
+/// Same as `peer_send_file`, and `context` must stay valid until `callback`
+/// is called, from any thread.
+#[no_mangle]
+pub unsafe extern "C" fn peer_send_file_async(
+    node_id: *const c_char,
+    path: *const c_char,
+    timeout_ms: u64,
+    callback: Option<CompletionCallback>,
+    context: *mut c_void,
+) -> u64 {
+    let completion = Completion::new(callback, context);
+    unsafe { send_file(node_id, path, timeout_ms, completion) }
+}
+
+/// Start sending a file from the host, returning the transfer's ID or 0
+///
+/// # Safety
+///
+/// Same as `peer_send_file`.
+unsafe fn send_file(
+    node_id: *const c_char,
+    path: *const c_char,
+    timeout_ms: u64,
+    completion: Option<Completion>,
+) -> u64 {
+    let Some(node_id) = (unsafe { parse_node_id(node_id) }) else {
+        return 0;
+    };
+    let Ok(Some(path)) = (unsafe { optional_str(path) }) else {
+        warn!("peer_send_file called without a valid path");
+        return 0;
+    };
+    let Some(rt) = RUNTIME.get() else {
+        warn!("peer_send_file called while the peer is not running");
+        return 0;
+    };
+
+    let _guard = rt.enter();
+    let timeout = deadline::from_millis(timeout_ms);
+    let path = PathBuf::from(path);
+    let sent = match completion {
+        Some(completion) => {
+            transfers().spawn_send_then(node_id, path, timeout, completion.on_transfer_finished())
+        }
+        None => transfers().spawn_send_within(node_id, path, timeout),
+    };
+    match sent {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Not sending file: {:#}", e);
+            0
+        }
+    }
+}
+
+/// Callback filling `buf` with up to `len` bytes of a file being sent
+///
+/// Returns how many bytes it wrote, 0 at the end of the content, or -1 on
+/// an error, which fails the transfer. It runs on a blocking thread and
+/// may wait for the data, e.g. while a photo streams out of the library.
+pub type ReadCallback = extern "C" fn(buf: *mut u8, len: usize, context: *mut c_void) -> isize;
+
+/// Callback letting the host free `context` once the library is done with
+/// it, e.g. a reader or a lent buffer
+pub type ReleaseCallback = extern "C" fn(context: *mut c_void);
+
+/// [`std::io::Read`] that pulls bytes from the host through a
+/// [`ReadCallback`]
+struct HostReader {
+    read: ReadCallback,
+    release: Option<ReleaseCallback>,
+    /// Opaque host pointer, stored as an address so the reader is `Send`
+    context: usize,
+}
+
+impl std::io::Read for HostReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = (self.read)(buf.as_mut_ptr(), buf.len(), self.context as *mut c_void);
+        match usize::try_from(read) {
+            Ok(read) if read <= buf.len() => Ok(read),
+            Ok(_) => Err(std::io::Error::other(
+                "The host read more than it was asked to",
+            )),
+            Err(_) => Err(std::io::Error::other("The host failed to read")),
+        }
+    }
+}
+
+impl Drop for HostReader {
+    fn drop(&mut self) {
+        if let Some(release) = self.release {
+            release(self.context as *mut c_void);
+        }
+    }
+}
+
+/// Send `size` bytes pulled through `read` to `node_id` in the background,
+/// as a file called `name`
+///
+/// Like `peer_send_file`, for content that isn't a file on disk. `size`
+/// must be known upfront and `read` must yield exactly that many bytes.
+/// Resuming an interrupted transfer reads the content from the start again,
+/// but only sends what the receiver is missing. `release`, if not null, is
+/// called once when the transfer no longer needs `context`, including when
+/// this returns 0.
+///
+/// # Safety
+///
+/// `node_id` and `name` must be null or point to valid NUL-terminated C
+/// strings.
+#[no_mangle]
+pub unsafe extern "C" fn peer_send_from_reader(
+    node_id: *const c_char,
+    name: *const c_char,
+    size: u64,
+    read: ReadCallback,
+    release: Option<ReleaseCallback>,
+    context: *mut c_void,
+) -> u64 {
+    unsafe { peer_send_from_reader_timeout(node_id, name, size, read, release, context, 0) }
+}
+
+/// Like `peer_send_from_reader`, with a timeout for the transfer to start
+/// as in `peer_send_file_timeout`
+///
+/// # Safety
+///
+/// Same as `peer_send_from_reader`.
+#[no_mangle]
+pub unsafe extern "C" fn peer_send_from_reader_timeout(
+    node_id: *const c_char,
+    name: *const c_char,
+    size: u64,
+    read: ReadCallback,
+    release: Option<ReleaseCallback>,
+    context: *mut c_void,
+    timeout_ms: u64,
+) -> u64 {
+    unsafe {
+        send_from_reader(
+            node_id, name, size, read, release, context, timeout_ms, None,
+        )
+    }
+}
+
+/// Like `peer_send_from_reader_timeout`, also calling `callback` with the
+/// `transfer_finished` event as `peer_send_file_async` does
+///
+/// `callback_context` is handed to `callback`, `context` to `read` and
+/// `release` as before.
+///
+/// # Safety
+///
+/// Same as `peer_send_from_reader`, and `callback_context` must stay valid
+/// until `callback` is called, from any thread.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn peer_send_from_reader_async(
+    node_id: *const c_char,
+    name: *const c_char,
+    size: u64,
+    read: ReadCallback,
+    release: Option<ReleaseCallback>,
+    context: *mut c_void,
+    timeout_ms: u64,
+    callback: Option<CompletionCallback>,
+    callback_context: *mut c_void,
+) -> u64 {
+    let completion = Completion::new(callback, callback_context);
+    unsafe {
+        send_from_reader(
+            node_id, name, size, read, release, context, timeout_ms, completion,
+        )
+    }
+}
+
+/// Start sending a file from a host reader, returning the transfer's ID or
+/// 0
+///
+/// # Safety
+///
+/// Same as `peer_send_from_reader`.
+#[allow(clippy::too_many_arguments)]
+unsafe fn send_from_reader(
+    node_id: *const c_char,
+    name: *const c_char,
+    size: u64,
+    read: ReadCallback,
+    release: Option<ReleaseCallback>,
+    context: *mut c_void,
+    timeout_ms: u64,
+    completion: Option<Completion>,
+) -> u64 {
+    let reader = Box::new(HostReader {
+        read,
+        release,
+        context: context as usize,
+    });
+    let Some(node_id) = (unsafe { parse_node_id(node_id) }) else {
+        return 0;
+    };
+    let Ok(Some(name)) = (unsafe { optional_str(name) }) else {
+        warn!("peer_send_from_reader called without a valid name");
+        return 0;
+    };
+    let Some(rt) = RUNTIME.get() else {
+        warn!("peer_send_from_reader called while the peer is not running");
+        return 0;
+    };
+
+    let _guard = rt.enter();
+    let timeout = deadline::from_millis(timeout_ms);
+    let name = name.to_string();
+    let sent = match completion {
+        Some(completion) => transfers().spawn_send_reader_then(
+            node_id,
+            name,
+            size,
+            reader,
+            timeout,
+            completion.on_transfer_finished(),
+        ),
+        None => transfers().spawn_send_reader_within(node_id, name, size, reader, timeout),
+    };
+    match sent {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Not sending file: {:#}", e);
+            0
+        }
+    }
+}
+
+/// Stop transfer `id` in either direction
+///
+/// The receiver keeps what arrived, so sending the file again resumes it.
+/// Returns false if the transfer already ended or never existed.
+#[no_mangle]
+pub extern "C" fn peer_cancel_transfer(id: TransferId) -> bool {
+    transfers().cancel(id)
+}
+
+/// Let at most `max` file transfers run at once, 0 for no limit
+///
+/// Counts both directions. Further transfers wait in `peer_get_transfers`
+/// as `queued` until one ends. Applies immediately.
+#[no_mangle]
+pub extern "C" fn peer_set_max_transfers(max: u32) {
+    transfers().set_max_active((max > 0).then_some(max as usize));
+}
+
+/// File transfers in progress, as a JSON array (see
+/// [`TransferInfo`](crate::transfer::TransferInfo))
+///
+/// Each has its `id`, `node_id`, `name`, `direction`, `bytes` and `size`,
+/// with `bytes_per_sec` and `eta_secs`. Free the result with
+/// `peer_free_string`.
+#[no_mangle]
+pub extern "C" fn peer_get_transfers() -> *mut c_char {
+    into_c_json(&transfers().transfers())
+}
+
+/// Callback asked where an incoming file should go
+///
+/// Answer with `peer_respond_receive(request_id, dir)`, right away or after
+/// checking free space or asking the user. `size` is in bytes, as the sender
+/// states it. The strings are only valid for the duration of the call.
+pub type ReceiveCallback = extern "C" fn(
+    request_id: u64,
+    node_id: *const c_char,
+    name: *const c_char,
+    size: u64,
+    context: *mut c_void,
+);
+
+/// [`ReceivePolicy`] that asks the host through a [`ReceiveCallback`]
+struct HostReceivePolicy {
+    callback: ReceiveCallback,
+    /// Opaque host pointer, stored as an address so the policy is `Send`
+    context: usize,
+}
+
+impl ReceivePolicy for HostReceivePolicy {
+    fn decide(&self, file: IncomingFile) -> BoxFuture<Option<PathBuf>> {
+        let id = NEXT_RECEIVE_REQUEST.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        RECEIVE_REQUESTS.lock().unwrap().insert(id, tx);
+
+        let node_id = CString::new(file.node_id.to_string()).expect("node IDs are hex");
+        let name = CString::new(file.name.replace('\0', "")).expect("NUL bytes were removed");
+        (self.callback)(
+            id,
+            node_id.as_ptr(),
+            name.as_ptr(),
+            file.size,
+            self.context as *mut c_void,
+        );
+
+        Box::pin(async move {
+            let _pending = PendingReceive(id);
+            rx.await.unwrap_or(None)
+        })
+    }
+}
+
+/// Forgets a receive request once it is answered or given up on
+struct PendingReceive(u64);
+
+impl Drop for PendingReceive {
+    fn drop(&mut self) {
+        RECEIVE_REQUESTS.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// Ask the host where each incoming file goes, or refuse all files again
+/// with a null callback (the default)
+///
+/// The callback gets the sender's node ID, the file name (without
+/// directories) and its size. It runs on a runtime thread and must return
+/// quickly; answer with `peer_respond_receive`. A file not answered within
+/// 30 seconds is refused. Applies immediately.
+#[no_mangle]
+pub extern "C" fn peer_set_receive_policy(callback: Option<ReceiveCallback>, context: *mut c_void) {
+    let policy = callback.map(|callback| {
+        Arc::new(HostReceivePolicy {
+            callback,
+            context: context as usize,
+        }) as Arc<dyn ReceivePolicy>
+    });
+    transfers().set_receive_policy(policy);
+}
+
+/// Take the file the receive callback asked about into directory `dir`, or
+/// refuse it with a null `dir`
+///
+/// The directory is created if needed; pass one inside the app container.
+/// An interrupted transfer only resumes if its file goes to the same
+/// directory again. Returns false if `request_id` is unknown, e.g. because
+/// it timed out, or `dir` isn't valid UTF-8.
+///
+/// # Safety
+///
+/// `dir` must be null or point to a valid NUL-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn peer_respond_receive(request_id: u64, dir: *const c_char) -> bool {
+    let Ok(dir) = (unsafe { optional_str(dir) }) else {
+        warn!("peer_respond_receive called with an invalid directory");
+        return false;
+    };
+    let pending = RECEIVE_REQUESTS.lock().unwrap().remove(&request_id);
+    pending.is_some_and(|tx| tx.send(dir.map(PathBuf::from)).is_ok())
+}
+
+/// Called with each payload published to a subscribed topic; `node_id` is
+/// the neighbour that delivered it, as a C string
+///
+/// Runs on a runtime thread and should return quickly. The pointers are
+/// only valid for the duration of the call.
+#[cfg(feature = "gossip")]
+pub type TopicCallback = extern "C" fn(
+    topic: *const c_char,
+    node_id: *const c_char,
+    data: *const u8,
+    len: usize,
+    context: *mut c_void,
+);
+
+/// [`TopicHandler`] forwarding to a [`TopicCallback`]
+#[cfg(feature = "gossip")]
+struct FfiTopicHandler {
+    callback: TopicCallback,
+    /// Opaque host pointer, stored as an address so the handler is `Send`
+    context: usize,
+}
+
+#[cfg(feature = "gossip")]
+impl TopicHandler for FfiTopicHandler {
+    fn on_message(&self, topic: &str, node_id: NodeId, data: &[u8]) {
+        let topic = CString::new(topic).expect("subscribed from a C string");
+        let node_id = CString::new(node_id.to_string()).expect("node IDs never contain NUL bytes");
+        (self.callback)(
+            topic.as_ptr(),
+            node_id.as_ptr(),
+            data.as_ptr(),
+            data.len(),
+            self.context as *mut c_void,
+        );
+    }
+}
+
+/// Receive what peers publish to `topic` through `callback`, replacing any
+/// earlier callback for it
+///
+/// Works before `peer_start`; the topic's gossip swarm is joined while the
+/// peer runs, through every peer that is discovered or connects. Neighbours
+/// joining and leaving are reported as `neighbor_up` and `neighbor_down`
+/// events. Returns false if `topic` is empty or longer than 256 bytes.
+///
+/// # Safety
+///
+/// `topic` must be null or point to a valid NUL-terminated C string, and
+/// `context` must stay valid until the topic is unsubscribed.
+#[cfg(feature = "gossip")]
+#[no_mangle]
+pub unsafe extern "C" fn peer_subscribe(
+    topic: *const c_char,
+    callback: TopicCallback,
+    context: *mut c_void,
+) -> bool {
+    let Ok(Some(topic)) = (unsafe { optional_str(topic) }) else {
+        warn!("peer_subscribe called without a valid topic");
+        return false;
+    };
+    let handler = Arc::new(FfiTopicHandler {
+        callback,
+        context: context as usize,
+    });
+
+    // Joining right away spawns on the runtime
+    let _guard = RUNTIME.get().map(|rt| rt.enter());
+    match topics().subscribe(topic, handler) {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("Not subscribing to {:?}: {:#}", topic, e);
+            false
+        }
+    }
+}
+
+/// Leave `topic`, which stops its callback
+///
+/// Returns false if it wasn't subscribed.
+///
+/// # Safety
+///
+/// `topic` must be null or point to a valid NUL-terminated C string.
+#[cfg(feature = "gossip")]
+#[no_mangle]
+pub unsafe extern "C" fn peer_unsubscribe(topic: *const c_char) -> bool {
+    let Ok(Some(topic)) = (unsafe { optional_str(topic) }) else {
+        warn!("peer_unsubscribe called without a valid topic");
+        return false;
+    };
+    topics().unsubscribe(topic)
+}
+
+/// Publish `len` bytes from `data` to everyone subscribed to `topic`
+///
+/// The topic must be subscribed and the peer running. Delivery is best
+/// effort: peers that aren't in the topic's swarm right now never get it.
+/// Returns false if the payload is over 16 KiB or it couldn't be handed to
+/// gossip.
+///
+/// # Safety
+///
+/// `topic` must be null or point to a valid NUL-terminated C string, and
+/// `data` must point to at least `len` readable bytes (or be null with `len`
+/// 0).
+#[cfg(feature = "gossip")]
+#[no_mangle]
+pub unsafe extern "C" fn peer_publish(topic: *const c_char, data: *const u8, len: usize) -> bool {
+    let Ok(Some(topic)) = (unsafe { optional_str(topic) }) else {
+        warn!("peer_publish called without a valid topic");
+        return false;
+    };
+    let data = if len == 0 {
+        Vec::new()
+    } else if data.is_null() {
+        return false;
+    } else {
+        unsafe { std::slice::from_raw_parts(data, len) }.to_vec()
+    };
+    let Some(rt) = RUNTIME.get() else {
+        warn!("peer_publish called while the peer is not running");
+        return false;
+    };
+    match rt.block_on(topics().publish(topic, data)) {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("Not publishing to {:?}: {:#}", topic, e);
+            false
+        }
+    }
+}
+
+/// Create a shared key-value document, returning
+/// `{"id":"...","ticket":"..."}` as JSON
+///
+/// Pass the ticket to another peer's `peer_doc_join` to edit the document
+/// together; use the ID with `peer_doc_set` and `peer_doc_get`. Returns null
+/// if the peer isn't running. Free the result with `peer_free_string`.
+#[cfg(feature = "docs")]
+#[no_mangle]
+pub extern "C" fn peer_doc_create() -> *mut c_char {
+    let Some(rt) = RUNTIME.get() else {
+        warn!("peer_doc_create called while the peer is not running");
+        return std::ptr::null_mut();
+    };
+    match rt.block_on(docs().create()) {
+        Ok(share) => into_c_json(&share),
+        Err(e) => {
+            warn!("Failed to create document: {:#}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Join the document a ticket from `peer_doc_create` was made for,
+/// returning its ID
+///
+/// The contents sync in the background, and every change on either side is
+/// reported as a `doc_changed` event with the `doc` ID, the `key` and the
+/// `node_id` it came from (null for local writes). Returns null if the
+/// ticket is invalid or the peer isn't running. Free the result with
+/// `peer_free_string`.
+///
+/// # Safety
+///
+/// `ticket` must be null or point to a valid NUL-terminated C string.
+#[cfg(feature = "docs")]
+#[no_mangle]
+pub unsafe extern "C" fn peer_doc_join(ticket: *const c_char) -> *mut c_char {
+    let Ok(Some(ticket)) = (unsafe { optional_str(ticket) }) else {
+        warn!("peer_doc_join called without a valid ticket");
+        return std::ptr::null_mut();
+    };
+    let Some(rt) = RUNTIME.get() else {
+        warn!("peer_doc_join called while the peer is not running");
+        return std::ptr::null_mut();
+    };
+    match rt.block_on(docs().join(ticket)) {
+        Ok(id) => CString::new(id)
+            .expect("document IDs never contain NUL bytes")
+            .into_raw(),
+        Err(e) => {
+            warn!("Failed to join document: {:#}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Set `key` in document `doc` to `len` bytes from `data`
+///
+/// Returns false if the document isn't open or the peer isn't running.
+///
+/// # Safety
+///
+/// `doc` and `key` must be null or point to valid NUL-terminated C strings,
+/// and `data` must point to at least `len` readable bytes (or be null with
+/// `len` 0).
+#[cfg(feature = "docs")]
+#[no_mangle]
+pub unsafe extern "C" fn peer_doc_set(
+    doc: *const c_char,
+    key: *const c_char,
+    data: *const u8,
+    len: usize,
+) -> bool {
+    let (Ok(Some(doc)), Ok(Some(key))) =
+        (unsafe { optional_str(doc) }, unsafe { optional_str(key) })
+    else {
+        warn!("peer_doc_set called without a valid document or key");
+        return false;
+    };
+    let value = if len == 0 {
+        Vec::new()
+    } else if data.is_null() {
+        return false;
+    } else {
+        unsafe { std::slice::from_raw_parts(data, len) }.to_vec()
+    };
+    let Some(rt) = RUNTIME.get() else {
+        warn!("peer_doc_set called while the peer is not running");
+        return false;
+    };
+    match rt.block_on(docs().set(doc, key.as_bytes(), value)) {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("Failed to set {:?} in {}: {:#}", key, doc, e);
+            false
+        }
+    }
+}
+
+/// The newest value of `key` in document `doc`, storing its length in
+/// `len`
+///
+/// Returns null if the key isn't set, its value hasn't synced yet, or the
+/// document isn't open. Free the result with `peer_free_bytes`.
+///
+/// # Safety
+///
+/// `doc` and `key` must be null or point to valid NUL-terminated C strings,
+/// and `len` must point to a writable `size_t`.
+#[cfg(feature = "docs")]
+#[no_mangle]
+pub unsafe extern "C" fn peer_doc_get(
+    doc: *const c_char,
+    key: *const c_char,
+    len: *mut usize,
+) -> *mut u8 {
+    let (Ok(Some(doc)), Ok(Some(key))) =
+        (unsafe { optional_str(doc) }, unsafe { optional_str(key) })
+    else {
+        warn!("peer_doc_get called without a valid document or key");
+        return std::ptr::null_mut();
+    };
+    if len.is_null() {
+        warn!("peer_doc_get called without a length pointer");
+        return std::ptr::null_mut();
+    }
+    let Some(rt) = RUNTIME.get() else {
+        warn!("peer_doc_get called while the peer is not running");
+        return std::ptr::null_mut();
+    };
+    match rt.block_on(docs().get(doc, key.as_bytes())) {
+        Ok(Some(value)) => {
+            unsafe { *len = value.len() };
+            Box::into_raw(value.into_boxed_slice()).cast()
+        }
+        Ok(None) => std::ptr::null_mut(),
+        Err(e) => {
+            warn!("Failed to get {:?} from {}: {:#}", key, doc, e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Free a value returned by `peer_doc_get`
+///
+/// # Safety
+///
+/// `data` must be null or a pointer returned by `peer_doc_get` together with
+/// the length it stored, not yet freed.
+#[cfg(feature = "docs")]
+#[no_mangle]
+pub unsafe extern "C" fn peer_free_bytes(data: *mut u8, len: usize) {
+    if !data.is_null() {
+        drop(unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(data, len)) });
+    }
+}
+
+/// Legacy name for backwards compatibility
+#[no_mangle]
+pub extern "C" fn bob_stop() {
+    peer_stop()
+}
@@ -0,0 +1,110 @@
+//! Finding discovered peers by name
+//!
+//! Apps that only talk to their own devices usually give them names that
+//! follow a scheme, such as `kitchen-ipad` and `kitchen-mac`. A
+//! [`PeerPattern`] picks those out of the registry by the user data they
+//! announce or the alias the user gave them (see [`crate::notes`]).
+//!
+//! A pattern with `*` (any run of characters) or `?` (one character) must
+//! match the whole name; a pattern without them matches names starting with
+//! it, so `kitchen` and `kitchen*` find the same peers. Like
+//! [`crate::naming`], matching ignores case.
+
+use iroh::NodeId;
+use serde::Serialize;
+
+use crate::notes::PeerNotes;
+use crate::registry::PeerRegistry;
+
+/// A glob or prefix to match peer names against, see the
+/// [module docs](self)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerPattern {
+    chars: Vec<char>,
+    glob: bool,
+}
+
+impl PeerPattern {
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            chars: pattern.chars().flat_map(char::to_lowercase).collect(),
+            glob: pattern.contains(['*', '?']),
+        }
+    }
+
+    /// Whether `name` matches
+    pub fn matches(&self, name: &str) -> bool {
+        let name: Vec<char> = name.chars().flat_map(char::to_lowercase).collect();
+        if !self.glob {
+            return name.starts_with(&self.chars);
+        }
+
+        // Backtrack to the last `*` on a mismatch, letting it take one more
+        // character
+        let (mut p, mut n) = (0, 0);
+        let mut star: Option<(usize, usize)> = None;
+        while n < name.len() {
+            match self.chars.get(p) {
+                Some('*') => {
+                    star = Some((p, n));
+                    p += 1;
+                }
+                Some(&c) if c == '?' || c == name[n] => {
+                    p += 1;
+                    n += 1;
+                }
+                _ => match star {
+                    Some((star_p, star_n)) => {
+                        star = Some((star_p, star_n + 1));
+                        p = star_p + 1;
+                        n = star_n + 1;
+                    }
+                    None => return false,
+                },
+            }
+        }
+        self.chars[p..].iter().all(|&c| c == '*')
+    }
+}
+
+/// A discovered peer matching a [`PeerPattern`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FoundPeer {
+    pub node_id: NodeId,
+    pub user_data: Option<String>,
+    /// Local name for the peer, see [`crate::notes`]
+    pub alias: Option<String>,
+    /// Discovery mechanism that last reported the peer
+    pub provenance: &'static str,
+}
+
+/// Peers in `registry` whose user data or alias in `notes` matches
+/// `pattern`, ordered by user data
+pub fn find_peers(
+    registry: &PeerRegistry,
+    notes: &PeerNotes,
+    pattern: &PeerPattern,
+) -> Vec<FoundPeer> {
+    let mut found: Vec<_> = registry
+        .peers()
+        .filter_map(|entry| {
+            let alias = notes.get(entry.node_id).alias;
+            let matches = [entry.user_data.as_deref(), alias.as_deref()]
+                .into_iter()
+                .flatten()
+                .any(|name| pattern.matches(name));
+            matches.then(|| FoundPeer {
+                node_id: entry.node_id,
+                user_data: entry.user_data.clone(),
+                alias,
+                provenance: entry.provenance,
+            })
+        })
+        .collect();
+    found.sort_by(|a, b| {
+        a.user_data
+            .cmp(&b.user_data)
+            .then(a.node_id.cmp(&b.node_id))
+    });
+    found
+}
@@ -0,0 +1,207 @@
+//! Named groups of peers, such as "all my devices"
+//!
+//! Groups are local like [`crate::notes`]: the user puts node IDs in them
+//! and they are kept in `<state dir>/groups.json`:
+//!
+//! ```json
+//! {"My devices":["a8a2...","0f31..."]}
+//! ```
+//!
+//! [`PeerGroups::send`] fans a message out to every member through
+//! [`Messages`], so members with an open connection get it right away and
+//! the others once they are reachable again. [`PeerGroups::presence`] tells
+//! which members are connected right now.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use iroh::{Endpoint, NodeId};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::connections::current_path;
+use crate::messages::{MessageId, Messages, MAX_MESSAGE_SIZE};
+use crate::profile;
+use crate::remote_info::ConnectionReport;
+use crate::state;
+
+/// Longest group name accepted, in characters
+pub const MAX_GROUP_NAME_LEN: usize = 64;
+
+/// How one member of a group is reached right now
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MemberPresence {
+    pub node_id: NodeId,
+    /// A path to the member is known
+    pub online: bool,
+    pub connection: ConnectionReport,
+}
+
+/// Members by group name, saved on every change
+///
+/// Clones share the same groups. The default keeps them in memory only.
+#[derive(Debug, Clone, Default)]
+pub struct PeerGroups {
+    path: Option<PathBuf>,
+    groups: Arc<Mutex<BTreeMap<String, BTreeSet<NodeId>>>>,
+}
+
+impl PeerGroups {
+    /// Groups stored under `state_dir`
+    pub fn open(state_dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        state::migrate(state_dir.as_ref())?;
+        let path = state_dir.as_ref().join("groups.json");
+        let groups = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("Corrupt peer groups {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+        Ok(Self {
+            path: Some(path),
+            groups: Arc::new(Mutex::new(groups)),
+        })
+    }
+
+    /// Groups under `$MDNS_PEER_HOME`, or `~/.mdns-peer` if that is unset
+    pub fn open_default() -> anyhow::Result<Self> {
+        Self::open(profile::default_state_dir()?)
+    }
+
+    /// Every group with its members, ordered by name
+    pub fn all(&self) -> BTreeMap<String, BTreeSet<NodeId>> {
+        self.groups.lock().unwrap().clone()
+    }
+
+    /// Members of `group`, or `None` if there is no such group
+    pub fn members(&self, group: &str) -> Option<BTreeSet<NodeId>> {
+        self.groups.lock().unwrap().get(group.trim()).cloned()
+    }
+
+    /// Names of the groups `node_id` is in
+    pub fn groups_of(&self, node_id: NodeId) -> Vec<String> {
+        let groups = self.groups.lock().unwrap();
+        groups
+            .iter()
+            .filter(|(_, members)| members.contains(&node_id))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Create an empty group called `group`, keeping it as is if it exists
+    pub fn create(&self, group: &str) -> anyhow::Result<()> {
+        let group = group_name(group)?;
+        self.update(|groups| {
+            groups.entry(group).or_default();
+            Ok(())
+        })
+    }
+
+    /// Delete `group`, returning whether it existed
+    pub fn delete(&self, group: &str) -> anyhow::Result<bool> {
+        self.update(|groups| Ok(groups.remove(group.trim()).is_some()))
+    }
+
+    /// Put `node_id` in `group`, which must exist
+    pub fn add(&self, group: &str, node_id: NodeId) -> anyhow::Result<()> {
+        self.update(|groups| {
+            let members = groups
+                .get_mut(group.trim())
+                .ok_or_else(|| anyhow::anyhow!("No group called {:?}", group))?;
+            members.insert(node_id);
+            Ok(())
+        })
+    }
+
+    /// Take `node_id` out of `group`, returning whether it was in it
+    pub fn remove(&self, group: &str, node_id: NodeId) -> anyhow::Result<bool> {
+        self.update(|groups| {
+            Ok(groups
+                .get_mut(group.trim())
+                .is_some_and(|members| members.remove(&node_id)))
+        })
+    }
+
+    /// Send `data` to every member of `group` as a message of its own,
+    /// returning the message ID per member
+    ///
+    /// Fails if there is no such group or `data` is too large for a message.
+    /// A member whose queue is full is skipped with a warning.
+    pub fn send(
+        &self,
+        messages: &Messages,
+        group: &str,
+        data: &[u8],
+    ) -> anyhow::Result<BTreeMap<NodeId, MessageId>> {
+        let members = self
+            .members(group)
+            .ok_or_else(|| anyhow::anyhow!("No group called {:?}", group))?;
+        anyhow::ensure!(
+            data.len() <= MAX_MESSAGE_SIZE,
+            "Message is larger than {} bytes",
+            MAX_MESSAGE_SIZE
+        );
+        let mut sent = BTreeMap::new();
+        for node_id in members {
+            match messages.send(node_id, data.to_vec()) {
+                Ok(id) => {
+                    sent.insert(node_id, id);
+                }
+                Err(e) => warn!("Not sending to {}: {:#}", node_id.fmt_short(), e),
+            }
+        }
+        Ok(sent)
+    }
+
+    /// How `endpoint` reaches each member of `group`, or `None` if there is
+    /// no such group
+    pub fn presence(&self, endpoint: &Endpoint, group: &str) -> Option<Vec<MemberPresence>> {
+        let members = self.members(group)?;
+        let presence = members
+            .into_iter()
+            .map(|node_id| {
+                let connection = current_path(endpoint, node_id);
+                MemberPresence {
+                    node_id,
+                    online: connection != ConnectionReport::None,
+                    connection,
+                }
+            })
+            .collect();
+        Some(presence)
+    }
+
+    fn update<T>(
+        &self,
+        change: impl FnOnce(&mut BTreeMap<String, BTreeSet<NodeId>>) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let mut groups = self.groups.lock().unwrap();
+        let changed = change(&mut groups)?;
+        self.save(&groups)?;
+        Ok(changed)
+    }
+
+    fn save(&self, groups: &BTreeMap<String, BTreeSet<NodeId>>) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        state::write_atomic(path, &serde_json::to_vec(groups)?)
+    }
+}
+
+/// `group` trimmed, if it is a usable name
+fn group_name(group: &str) -> anyhow::Result<String> {
+    let group = group.trim();
+    anyhow::ensure!(!group.is_empty(), "Group name is empty");
+    anyhow::ensure!(
+        group.chars().count() <= MAX_GROUP_NAME_LEN,
+        "Group name is longer than {} characters",
+        MAX_GROUP_NAME_LEN
+    );
+    Ok(group.to_string())
+}
@@ -0,0 +1,306 @@
+//! gRPC control plane for `mdns-peer daemon --grpc <addr>`
+//!
+//! For backend test rigs that drive a peer from another process or
+//! language. The service is defined in `proto/mdns_peer.proto`:
+//!
+//! | RPC            | Does                                                        |
+//! | -------------- | ----------------------------------------------------------- |
+//! | `ListPeers`    | The dashboard's peer table                                  |
+//! | `StreamEvents` | The recent event log, then every new event, as JSON         |
+//! | `Connect`      | Connect like the dashboard's button and report the path     |
+//! | `SendMessage`  | Queue a message, see [`Messages::send`]                     |
+//! | `GetStats`     | Traffic, transfers and addresses, see [`StatsReport`]       |
+//!
+//! The service shares its peer table, event log and [`DASHBOARD_ALPN`]
+//! connections with a [`Dashboard`], whether or not that one is served
+//! over HTTP too. `GetStats` leaves out the QUIC stats of each connection,
+//! which `peer_get_stats` and the `stats` command include.
+//!
+//! [`DASHBOARD_ALPN`]: crate::dashboard::DASHBOARD_ALPN
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use iroh::NodeId;
+use n0_future::boxed::BoxStream;
+use n0_future::{stream, StreamExt};
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Response, Status};
+use tracing::{debug, info, warn};
+
+use crate::connections::Direction;
+use crate::dashboard::{Dashboard, PeerRow, ACTION_TIMEOUT};
+use crate::deadline;
+use crate::events::TimedEvent;
+use crate::messages::Messages;
+use crate::network::ObservedAddrs;
+use crate::protocols::Protocols;
+use crate::remote_info::ConnectionReport;
+use crate::stats::{PeerStats, ProtocolStats, StatsReport};
+use crate::transfer::{FileTransfers, TransferInfo};
+use crate::PeerOptions;
+
+/// Messages and service generated from `proto/mdns_peer.proto`, including
+/// a client for it
+///
+/// Create the client with `PeerControlClient::new` on a connected
+/// [`Channel`](tonic::transport::Channel), as its `connect` would clash
+/// with the `Connect` RPC.
+pub mod proto {
+    tonic::include_proto!("mdns_peer.v1");
+}
+
+use proto::peer_control_server::{PeerControl, PeerControlServer};
+
+/// Answers the RPCs for one daemon's peer
+#[derive(Clone)]
+pub struct ControlService {
+    dashboard: Dashboard,
+    messages: Messages,
+    protocols: Protocols,
+    transfers: FileTransfers,
+}
+
+impl ControlService {
+    /// A service for the peer `options` will run, sharing `dashboard`'s
+    /// peer table and event log
+    ///
+    /// Pass the dashboard's [`event_sink`](Dashboard::event_sink) to the
+    /// peer so both see its events.
+    pub fn new(dashboard: Dashboard, options: &PeerOptions) -> Self {
+        Self {
+            dashboard,
+            messages: options.messages.clone(),
+            protocols: options.protocols.clone(),
+            transfers: options.transfers.clone(),
+        }
+    }
+
+    /// Serve on `addr` until the process exits
+    ///
+    /// Returns the bound address (useful with port 0) once listening.
+    pub async fn spawn(self, addr: SocketAddr) -> anyhow::Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to bind gRPC to {}: {}", addr, e))?;
+        let addr = listener.local_addr()?;
+        info!("gRPC control plane on {}", addr);
+
+        tokio::spawn(async move {
+            let served = tonic::transport::Server::builder()
+                .add_service(PeerControlServer::new(self))
+                .serve_with_incoming(TcpIncoming::from(listener))
+                .await;
+            if let Err(e) = served {
+                warn!("gRPC control plane stopped: {}", e);
+            }
+        });
+        Ok(addr)
+    }
+}
+
+type EventStream = BoxStream<Result<proto::Event, Status>>;
+
+#[tonic::async_trait]
+impl PeerControl for ControlService {
+    async fn list_peers(
+        &self,
+        _request: Request<proto::ListPeersRequest>,
+    ) -> Result<Response<proto::ListPeersResponse>, Status> {
+        let peers = self.dashboard.peers().into_iter().map(peer).collect();
+        Ok(Response::new(proto::ListPeersResponse { peers }))
+    }
+
+    type StreamEventsStream = EventStream;
+
+    async fn stream_events(
+        &self,
+        request: Request<proto::StreamEventsRequest>,
+    ) -> Result<Response<EventStream>, Status> {
+        let (backlog, live) = self.dashboard.subscribe();
+        let backlog = if request.into_inner().skip_backlog {
+            Vec::new()
+        } else {
+            backlog
+        };
+        let backlog = stream::iter(backlog.into_iter().map(|timed| Ok(event(&timed))));
+        let live = stream::unfold(live, |mut live| async move {
+            loop {
+                match live.recv().await {
+                    Ok(timed) => return Some((Ok(event(&timed)), live)),
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        debug!("gRPC event stream skipped {} events", missed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(backlog.chain(live))))
+    }
+
+    async fn connect(
+        &self,
+        request: Request<proto::ConnectRequest>,
+    ) -> Result<Response<proto::ConnectResponse>, Status> {
+        let request = request.into_inner();
+        let node_id = node_id(&request.node_id)?;
+        let timeout = request
+            .timeout_ms
+            .map_or(Some(ACTION_TIMEOUT), deadline::from_millis);
+        let report = self
+            .dashboard
+            .connect(node_id, timeout)
+            .await
+            .map_err(|e| {
+                if deadline::is_timed_out(&e) {
+                    Status::deadline_exceeded(format!("{:#}", e))
+                } else {
+                    Status::unavailable(format!("{:#}", e))
+                }
+            })?;
+        Ok(Response::new(proto::ConnectResponse {
+            node_id: report.node_id.to_string(),
+            connection: Some(connection(&report.connection)),
+            rtt_ms: report.rtt_ms,
+            handshake_ms: report.handshake_ms,
+        }))
+    }
+
+    async fn send_message(
+        &self,
+        request: Request<proto::SendMessageRequest>,
+    ) -> Result<Response<proto::SendMessageResponse>, Status> {
+        let request = request.into_inner();
+        let node_id = node_id(&request.node_id)?;
+        let timeout = request.timeout_ms.map(Duration::from_millis);
+        let sent = if request.sealed {
+            self.messages
+                .send_sealed_within(node_id, request.data, timeout)
+        } else {
+            self.messages.send_within(node_id, request.data, timeout)
+        };
+        // Too large, a full queue or no room in the memory budget
+        let message_id = sent.map_err(|e| Status::resource_exhausted(format!("{:#}", e)))?;
+        Ok(Response::new(proto::SendMessageResponse {
+            message_id: message_id.to_string(),
+        }))
+    }
+
+    async fn get_stats(
+        &self,
+        _request: Request<proto::GetStatsRequest>,
+    ) -> Result<Response<proto::GetStatsResponse>, Status> {
+        let mut report = self.protocols.stats();
+        report.transfers = self.transfers.transfers();
+        Ok(Response::new(stats(report)))
+    }
+}
+
+fn node_id(node_id: &str) -> Result<NodeId, Status> {
+    node_id
+        .parse()
+        .map_err(|e| Status::invalid_argument(format!("Invalid node ID {:?}: {}", node_id, e)))
+}
+
+fn event(timed: &TimedEvent) -> proto::Event {
+    let json = timed.to_json();
+    let kind = serde_json::from_str::<serde_json::Value>(&json)
+        .ok()
+        .and_then(|value| value["type"].as_str().map(str::to_string))
+        .unwrap_or_default();
+    proto::Event {
+        r#type: kind,
+        json,
+        monotonic_ms: timed.at.monotonic_ms,
+        wall_ms: timed.at.wall_ms,
+    }
+}
+
+fn peer(row: PeerRow) -> proto::Peer {
+    proto::Peer {
+        node_id: row.node_id.to_string(),
+        user_data: row.user_data,
+        alias: row.alias,
+        notes: row.notes,
+        provenance: row.provenance.map(str::to_string),
+        connection: Some(connection(&row.connection)),
+        warm: row.warm,
+        expires_in_ms: row.expires_in_ms,
+    }
+}
+
+fn connection(report: &ConnectionReport) -> proto::Connection {
+    let (kind, addr, url) = match report {
+        ConnectionReport::Direct { addr } => ("direct", Some(addr), None),
+        ConnectionReport::Relay { url } => ("relay", None, Some(url)),
+        ConnectionReport::Mixed { addr, url } => ("mixed", Some(addr), Some(url)),
+        ConnectionReport::None => ("none", None, None),
+    };
+    proto::Connection {
+        kind: kind.to_string(),
+        addr: addr.map(ToString::to_string),
+        url: url.cloned(),
+    }
+}
+
+fn stats(report: StatsReport) -> proto::GetStatsResponse {
+    proto::GetStatsResponse {
+        window_secs: report.window_secs,
+        peers: report.peers.into_iter().map(peer_stats).collect(),
+        transfers: report.transfers.into_iter().map(transfer).collect(),
+        addrs: report.addrs.map(observed_addrs),
+    }
+}
+
+fn peer_stats(peer: PeerStats) -> proto::PeerStats {
+    proto::PeerStats {
+        node_id: peer.node_id.to_string(),
+        connection: Some(connection(&peer.connection)),
+        bytes_sent: peer.bytes_sent,
+        bytes_received: peer.bytes_received,
+        send_bytes_per_sec: peer.send_bytes_per_sec,
+        receive_bytes_per_sec: peer.receive_bytes_per_sec,
+        protocols: peer.protocols.into_iter().map(protocol_stats).collect(),
+    }
+}
+
+fn protocol_stats(protocol: ProtocolStats) -> proto::ProtocolStats {
+    proto::ProtocolStats {
+        alpn: protocol.alpn,
+        bytes_sent: protocol.bytes_sent,
+        bytes_received: protocol.bytes_received,
+        send_bytes_per_sec: protocol.send_bytes_per_sec,
+        receive_bytes_per_sec: protocol.receive_bytes_per_sec,
+    }
+}
+
+fn transfer(transfer: TransferInfo) -> proto::Transfer {
+    proto::Transfer {
+        id: transfer.id,
+        node_id: transfer.node_id.to_string(),
+        name: transfer.name,
+        direction: match transfer.direction {
+            Direction::Inbound => "inbound",
+            Direction::Outbound => "outbound",
+        }
+        .to_string(),
+        queued: transfer.queued,
+        bytes: transfer.bytes,
+        size: transfer.size,
+        bytes_per_sec: transfer.bytes_per_sec,
+        eta_secs: transfer.eta_secs,
+    }
+}
+
+fn observed_addrs(addrs: ObservedAddrs) -> proto::ObservedAddrs {
+    let strings = |addrs: Vec<SocketAddr>| addrs.iter().map(ToString::to_string).collect();
+    proto::ObservedAddrs {
+        local: strings(addrs.local),
+        observed: strings(addrs.observed),
+        portmapped: strings(addrs.portmapped),
+        translated: addrs.translated,
+        symmetric_nat: addrs.symmetric_nat,
+    }
+}
@@ -0,0 +1,227 @@
+//! Summaries of past sessions, kept across runs
+//!
+//! Every run of the peer adds one [`SessionSummary`] to
+//! `<state dir>/history.json`: how long it ran, how many peers it found,
+//! how reconnects went and which errors it hit. A user filing an issue can
+//! attach the file (or `peer_get_session_history` on iOS) to show how the
+//! peer behaved over days rather than in one screenshot of logs. Only the
+//! newest [`MAX_SESSIONS`] are kept.
+//!
+//! The running session is saved at most every [`SAVE_INTERVAL`] and when it
+//! stops, so a session killed by the OS is still recorded, with `ended`
+//! false and its uptime as of the last save.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use iroh::NodeId;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::events::{wall_clock_ms, PeerEvent};
+use crate::profile;
+use crate::state;
+
+/// Sessions kept in the history, oldest dropped first
+pub const MAX_SESSIONS: usize = 200;
+
+/// Longest the running session goes unsaved
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Longest error message kept per session
+const MAX_ERROR_LEN: usize = 512;
+
+/// What happened during one run of the peer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionSummary {
+    pub identifier: String,
+    pub node_id: NodeId,
+    /// When the session started, in milliseconds since the Unix epoch
+    pub started_wall_ms: u64,
+    pub uptime_ms: u64,
+    /// Distinct peers found through discovery
+    pub peers_discovered: u64,
+    /// Connections opened on host protocols, in either direction
+    pub connections: u64,
+    /// Trusted peers re-dialed after the app resumed, and how many of those
+    /// failed
+    pub reconnects: u64,
+    pub reconnects_failed: u64,
+    /// Background tasks that failed or panicked
+    pub errors: u64,
+    pub last_error: Option<String>,
+    /// The session stopped cleanly; false while it runs, or if the process
+    /// died first
+    pub ended: bool,
+}
+
+/// Past sessions and the running one, saved to the state directory
+///
+/// Clones share the same history. The default keeps it in memory only.
+#[derive(Debug, Clone, Default)]
+pub struct SessionHistory {
+    path: Option<PathBuf>,
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Oldest first; the running session, if any, is the last one
+    sessions: Vec<SessionSummary>,
+    running: Option<Running>,
+}
+
+#[derive(Debug)]
+struct Running {
+    started: Instant,
+    last_saved: Instant,
+    peers: HashSet<NodeId>,
+}
+
+impl SessionHistory {
+    /// History stored under `state_dir`
+    pub fn open(state_dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        state::migrate(state_dir.as_ref())?;
+        let path = state_dir.as_ref().join("history.json");
+        let sessions = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("Corrupt session history {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+        Ok(Self {
+            path: Some(path),
+            inner: Arc::new(Mutex::new(Inner {
+                sessions,
+                running: None,
+            })),
+        })
+    }
+
+    /// History under `$MDNS_PEER_HOME`, or `~/.mdns-peer` if that is unset
+    pub fn open_default() -> anyhow::Result<Self> {
+        Self::open(profile::default_state_dir()?)
+    }
+
+    /// Every recorded session, oldest first, the running one with its
+    /// current uptime
+    pub fn sessions(&self) -> Vec<SessionSummary> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.refresh_uptime();
+        inner.sessions.clone()
+    }
+
+    /// Start recording a session of `identifier` as `node_id`, ending any
+    /// session that is still running
+    pub fn begin(&self, identifier: &str, node_id: NodeId) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.running.is_some() {
+            inner.finish();
+        }
+        inner.sessions.push(SessionSummary {
+            identifier: identifier.to_string(),
+            node_id,
+            started_wall_ms: wall_clock_ms(),
+            uptime_ms: 0,
+            peers_discovered: 0,
+            connections: 0,
+            reconnects: 0,
+            reconnects_failed: 0,
+            errors: 0,
+            last_error: None,
+            ended: false,
+        });
+        let excess = inner.sessions.len().saturating_sub(MAX_SESSIONS);
+        inner.sessions.drain(..excess);
+        let now = Instant::now();
+        inner.running = Some(Running {
+            started: now,
+            last_saved: now,
+            peers: HashSet::new(),
+        });
+        self.save(&inner);
+    }
+
+    /// Count `event` towards the running session, saving it if it went
+    /// unsaved for [`SAVE_INTERVAL`]
+    pub fn peer_event(&self, event: &PeerEvent) {
+        let mut inner = self.inner.lock().unwrap();
+        let Inner { sessions, running } = &mut *inner;
+        let (Some(running), Some(session)) = (running.as_mut(), sessions.last_mut()) else {
+            return;
+        };
+        match event {
+            PeerEvent::Discovered { node_id, .. } if running.peers.insert(*node_id) => {
+                session.peers_discovered += 1;
+            }
+            PeerEvent::Connected { .. } => session.connections += 1,
+            PeerEvent::Reconnect { connected, .. } => {
+                session.reconnects += 1;
+                if !connected {
+                    session.reconnects_failed += 1;
+                }
+            }
+            PeerEvent::Error { task, message, .. } => {
+                session.errors += 1;
+                let mut error = format!("{}: {}", task, message);
+                if error.len() > MAX_ERROR_LEN {
+                    let mut end = MAX_ERROR_LEN;
+                    while !error.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    error.truncate(end);
+                }
+                session.last_error = Some(error);
+            }
+            _ => {}
+        }
+        if running.last_saved.elapsed() >= SAVE_INTERVAL {
+            running.last_saved = Instant::now();
+            inner.refresh_uptime();
+            self.save(&inner);
+        }
+    }
+
+    /// Mark the running session as stopped cleanly and save it
+    pub fn end(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.running.is_some() {
+            inner.finish();
+            self.save(&inner);
+        }
+    }
+
+    fn save(&self, inner: &Inner) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let saved = serde_json::to_string_pretty(&inner.sessions)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| state::write_atomic(path, json.as_bytes()));
+        if let Err(e) = saved {
+            warn!("Failed to save session history {}: {:#}", path.display(), e);
+        }
+    }
+}
+
+impl Inner {
+    fn refresh_uptime(&mut self) {
+        if let (Some(running), Some(session)) = (&self.running, self.sessions.last_mut()) {
+            session.uptime_ms = running.started.elapsed().as_millis() as u64;
+        }
+    }
+
+    fn finish(&mut self) {
+        self.refresh_uptime();
+        if let Some(session) = self.sessions.last_mut() {
+            session.ended = true;
+        }
+        self.running = None;
+    }
+}
@@ -0,0 +1,135 @@
+//! Peers on an iPhone's Personal Hotspot
+//!
+//! A desktop that joined an iPhone's Personal Hotspot sits on a network
+//! unlike any Wi-Fi: the phone is the access point, at 172.20.10.1 on a
+//! `bridge` interface (`bridge100`), and hands its clients addresses from
+//! 172.20.10.0/28. The phone's default route goes over cellular and its
+//! `en0` has no address, so anything that only looks at the default
+//! interface, or sends multicast without picking one, misses the hotspot
+//! altogether. Local discovery announces on every interface and does reach
+//! the hotspot's clients, but the phone is the only way between them: when
+//! a client isn't listed, dialing it, or the phone, on its hotspot address
+//! is what works.
+//!
+//! [`detect`] recognises both ends of that topology from the interface
+//! list. A running peer reports it as a
+//! [`PeerEvent::HotspotChanged`](crate::PeerEvent::HotspotChanged) when
+//! it starts on a hotspot and whenever that changes, with the direct
+//! addresses other peers reach it at there, and the FFI returns it from
+//! `peer_get_hotspot`. `mdns-peer doctor` lists it as well.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use iroh::{Endpoint, Watcher};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+/// Network an iPhone hands its hotspot clients addresses from
+pub const HOTSPOT_NETWORK: Ipv4Addr = Ipv4Addr::new(172, 20, 10, 0);
+/// Prefix length of [`HOTSPOT_NETWORK`]
+pub const HOTSPOT_PREFIX_LEN: u8 = 28;
+
+/// The sharing iPhone's own address on its hotspot
+pub const HOTSPOT_HOST: Ipv4Addr = Ipv4Addr::new(172, 20, 10, 1);
+
+/// Which end of the hotspot this device is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HotspotRole {
+    /// The iPhone sharing its connection
+    Host,
+    /// A device that joined it
+    Client,
+}
+
+/// This device's place on a Personal Hotspot, see the [module docs](self)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct Hotspot {
+    pub role: HotspotRole,
+    /// Interface on the hotspot, `bridge100` on the phone and usually `en0`
+    /// on a Mac that joined
+    pub interface: String,
+    /// This device's address on the hotspot
+    pub addr: Ipv4Addr,
+    /// Where the phone is reached, the same as `addr` on the phone itself
+    pub host_addr: Ipv4Addr,
+    /// The endpoint's direct addresses on the hotspot, the ones to pair or
+    /// dial with when discovery doesn't list a peer
+    pub direct_addrs: Vec<SocketAddr>,
+    /// What to expect from discovery in this role
+    pub hint: String,
+}
+
+impl Hotspot {
+    /// Keep those of the endpoint's `addrs` that are on the hotspot
+    pub fn with_direct_addrs(mut self, addrs: impl IntoIterator<Item = SocketAddr>) -> Self {
+        let mut direct_addrs: Vec<_> = addrs
+            .into_iter()
+            .filter(|addr| addr.ip().to_canonical() == IpAddr::V4(self.addr))
+            .collect();
+        direct_addrs.sort();
+        self.direct_addrs = direct_addrs;
+        self
+    }
+}
+
+/// Whether `addr` is one an iPhone hands out on its hotspot
+pub fn on_hotspot(addr: Ipv4Addr) -> bool {
+    u32::from(addr) >> (32 - HOTSPOT_PREFIX_LEN)
+        == u32::from(HOTSPOT_NETWORK) >> (32 - HOTSPOT_PREFIX_LEN)
+}
+
+/// The hotspot, if any, given the IPv4 addresses of the interfaces that are
+/// up, by interface name
+///
+/// The phone is recognised by holding the host address on a `bridge`
+/// interface, a client by any other address on the hotspot network.
+pub fn detect<'a>(interfaces: impl IntoIterator<Item = (&'a str, Ipv4Addr)>) -> Option<Hotspot> {
+    let (interface, addr) = interfaces.into_iter().find(|(_, addr)| on_hotspot(*addr))?;
+    let role = if addr == HOTSPOT_HOST && interface.starts_with("bridge") {
+        HotspotRole::Host
+    } else {
+        HotspotRole::Client
+    };
+    let hint = match role {
+        HotspotRole::Host => format!(
+            "sharing this phone's connection: devices that joined are discovered over {}; \
+             if one isn't listed, pair with it or have it dial {}",
+            interface, HOTSPOT_HOST
+        ),
+        HotspotRole::Client => format!(
+            "joined an iPhone's Personal Hotspot: the phone is at {} and discovered over {}; \
+             other devices that joined are reached through it",
+            HOTSPOT_HOST, interface
+        ),
+    };
+    Some(Hotspot {
+        role,
+        interface: interface.to_string(),
+        addr,
+        host_addr: HOTSPOT_HOST,
+        direct_addrs: Vec::new(),
+        hint,
+    })
+}
+
+/// The hotspot this machine is on right now, with `endpoint`'s addresses
+/// there if one is given
+pub fn current(endpoint: Option<&Endpoint>) -> Option<Hotspot> {
+    let interfaces = netdev::get_interfaces();
+    let hotspot = detect(
+        interfaces
+            .iter()
+            .filter(|iface| iface.is_up() && !iface.is_loopback())
+            .flat_map(|iface| {
+                iface
+                    .ipv4
+                    .iter()
+                    .map(move |net| (iface.name.as_str(), net.addr()))
+            }),
+    )?;
+    let addrs = endpoint
+        .and_then(|endpoint| endpoint.direct_addresses().get())
+        .unwrap_or_default();
+    Some(hotspot.with_direct_addrs(addrs.into_iter().map(|addr| addr.addr)))
+}
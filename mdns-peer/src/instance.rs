@@ -0,0 +1,163 @@
+//! One advertised identifier per machine
+//!
+//! Two peers on the same host advertising the same user data look like one
+//! flapping device to everyone else. Before binding, a peer takes an
+//! exclusive lock on a file named after its identifier in the temp
+//! directory; a second instance then either refuses to start or picks a
+//! suffixed identifier, per [`DuplicatePolicy`]. The OS drops the lock when
+//! the process exits, so a crash never leaves a stale claim.
+
+use std::fmt;
+use std::fs::{self, File, TryLockError};
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::Context;
+
+/// Highest suffix tried by [`DuplicatePolicy::Suffix`]
+const MAX_SUFFIX: u32 = 32;
+
+/// What to do when another instance on this machine already advertises the
+/// same identifier
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Fail with [`AlreadyRunning`]
+    #[default]
+    Refuse,
+    /// Advertise `<identifier>-2`, `<identifier>-3`, ... instead
+    Suffix,
+    /// Don't check
+    Allow,
+}
+
+impl std::str::FromStr for DuplicatePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "refuse" => Ok(Self::Refuse),
+            "suffix" => Ok(Self::Suffix),
+            "allow" => Ok(Self::Allow),
+            _ => anyhow::bail!(
+                "Unknown duplicate policy {:?}: use refuse, suffix or allow",
+                s
+            ),
+        }
+    }
+}
+
+/// Another instance on this machine holds the identifier
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlreadyRunning {
+    pub identifier: String,
+}
+
+impl fmt::Display for AlreadyRunning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "another instance on this machine is already advertising {:?}",
+            self.identifier
+        )
+    }
+}
+
+impl std::error::Error for AlreadyRunning {}
+
+/// Exclusive claim on an identifier, released when dropped
+#[derive(Debug)]
+pub struct InstanceLock {
+    identifier: String,
+    _file: Option<File>,
+}
+
+impl InstanceLock {
+    /// The identifier to advertise, suffixed if the requested one was taken
+    pub fn identifier(&self) -> &str {
+        &self.identifier
+    }
+}
+
+/// Claim `identifier` for this process according to `policy`
+///
+/// With a fixed secret key, suffixing would still leave two endpoints with
+/// the same node ID, so `keyed` turns [`DuplicatePolicy::Suffix`] into
+/// [`DuplicatePolicy::Refuse`].
+pub fn claim(
+    identifier: &str,
+    policy: DuplicatePolicy,
+    keyed: bool,
+) -> anyhow::Result<InstanceLock> {
+    if policy == DuplicatePolicy::Allow {
+        return Ok(InstanceLock {
+            identifier: identifier.to_string(),
+            _file: None,
+        });
+    }
+
+    let dir = std::env::temp_dir().join("mdns-peer-instances");
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let suffixes = if policy == DuplicatePolicy::Suffix && !keyed {
+        MAX_SUFFIX
+    } else {
+        1
+    };
+    for n in 1..=suffixes {
+        let candidate = match n {
+            1 => identifier.to_string(),
+            n => format!("{}-{}", identifier, n),
+        };
+        if let Some(file) = try_lock(dir.join(lock_file_name(&candidate)), &candidate)? {
+            return Ok(InstanceLock {
+                identifier: candidate,
+                _file: Some(file),
+            });
+        }
+    }
+
+    Err(AlreadyRunning {
+        identifier: identifier.to_string(),
+    }
+    .into())
+}
+
+/// The locked file, or `None` if another process holds it
+fn try_lock(path: PathBuf, identifier: &str) -> anyhow::Result<Option<File>> {
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => return Ok(None),
+        Err(TryLockError::Error(e)) => {
+            return Err(e).with_context(|| format!("Failed to lock {}", path.display()))
+        }
+    }
+
+    // For whoever goes looking at the lock directory
+    file.set_len(0)?;
+    writeln!(file, "{} {}", std::process::id(), identifier)?;
+    Ok(Some(file))
+}
+
+/// Identifiers are arbitrary UTF-8, so the file name is a readable prefix
+/// plus a hash of the full identifier
+fn lock_file_name(identifier: &str) -> String {
+    let readable: String = identifier
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+        .take(32)
+        .collect();
+
+    // FNV-1a, stable across builds unlike the std hasher
+    let hash = identifier
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+    format!("{}-{:016x}.lock", readable, hash)
+}
@@ -0,0 +1,56 @@
+//! Keeping secret keys in the platform's secure store
+//!
+//! By default a profile's secret key sits in its JSON file in the state
+//! directory, readable by anything that can read the app's sandbox or a
+//! backup of it. A host with a proper secure store (the Keychain on iOS, the
+//! system keyring on desktops) can implement [`SecretStore`] and hand it to
+//! [`ProfileStore::with_secret_store`], or register callbacks with
+//! `peer_set_secret_store` over FFI. Keys are then only ever written to the
+//! store, under [`profile_account`], and profile files keep just the user
+//! data.
+//!
+//! Profiles created before a store was registered move their key into it
+//! the next time they load, and the copy in the file is dropped.
+//!
+//! [`ProfileStore::with_secret_store`]: crate::profile::ProfileStore::with_secret_store
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Host storage for secrets, keyed by account name
+///
+/// Calls happen when profiles load or are created, on whatever thread does
+/// that.
+pub trait SecretStore: Send + Sync + 'static {
+    /// The secret stored for `account`, `None` if there is none
+    fn get(&self, account: &str) -> anyhow::Result<Option<Vec<u8>>>;
+    /// Store `secret` for `account`, replacing what was there
+    fn set(&self, account: &str, secret: &[u8]) -> anyhow::Result<()>;
+}
+
+/// Account the key of profile `name` is stored under
+pub fn profile_account(name: &str) -> String {
+    format!("mdns-peer/profile/{}", name)
+}
+
+/// [`SecretStore`] in memory, for tests and hosts that derive keys each run
+///
+/// Clones share the same secrets.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySecretStore {
+    secrets: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+}
+
+impl SecretStore for MemorySecretStore {
+    fn get(&self, account: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.secrets.lock().unwrap().get(account).cloned())
+    }
+
+    fn set(&self, account: &str, secret: &[u8]) -> anyhow::Result<()> {
+        self.secrets
+            .lock()
+            .unwrap()
+            .insert(account.to_string(), secret.to_vec());
+        Ok(())
+    }
+}
@@ -1,14 +1,111 @@
-use iroh::{discovery::DiscoveryEvent, Endpoint};
-use n0_future::StreamExt;
-use std::sync::{Arc, Mutex, OnceLock};
-use std::time::Duration;
+//! Shared mDNS peer implementation
+//!
+//! Cargo features select which front-ends are compiled in:
+//!
+//! - `ffi`: C ABI for the iOS app (see [`ffi`])
+//! - `cli`: desktop entry point used by the `mdns-peer` binary
+//! - `metrics`: iroh's internal metrics collection
+//! - `gossip`: topic pub/sub over iroh-gossip (see `topics`)
+//! - `docs`: key-value documents synced over iroh-docs (see `docs`)
+//! - `grpc`: gRPC control plane for `mdns-peer daemon` (see `grpc`)
+//!
+//! Rust apps can embed the peer directly through [`MdnsPeer`], which needs
+//! none of them.
+//!
+//! The iOS static library only needs `ffi`, so `cargo xtask build-ios` builds
+//! with `--no-default-features --features ffi`.
+
+use iroh::endpoint::Connection;
+use iroh::{Endpoint, NodeId};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::sync::broadcast;
-use tracing::{info, warn};
+use tracing::{debug, error, info, warn};
+
+pub mod accept;
+pub mod bind;
+pub mod budget;
+pub mod bundle;
+pub mod candidates;
+pub mod config;
+pub mod connections;
+#[cfg(feature = "cli")]
+pub mod dashboard;
+pub mod deadline;
+pub mod discovery;
+pub mod dispatch;
+#[cfg(feature = "docs")]
+pub mod docs;
+#[cfg(feature = "cli")]
+pub mod doctor;
+pub mod environment;
+pub mod events;
+#[cfg(feature = "cli")]
+pub mod fake;
+pub mod fallback;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod find;
+pub mod groups;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod history;
+pub mod hotspot;
+pub mod instance;
+pub mod keystore;
+pub mod limits;
+pub mod mdns;
+pub mod messages;
+pub mod multicast;
+pub mod naming;
+pub mod network;
+pub mod notes;
+pub mod operations;
+pub mod options;
+pub mod p2p;
+pub mod pairing;
+pub mod peer;
+pub mod privacy;
+pub mod profile;
+pub mod protocol;
+pub mod protocols;
+pub mod quic;
+pub mod reconnect;
+pub mod registry;
+pub mod relay;
+pub mod remote_info;
+pub mod rotation;
+pub mod schema;
+pub mod seal;
+pub mod self_test;
+pub mod session;
+pub mod snapshot;
+#[cfg(feature = "cli")]
+pub mod sniff;
+#[cfg(feature = "cli")]
+pub mod soak;
+pub mod state;
+pub mod stats;
+pub mod supervise;
+pub mod suspend;
+pub mod tasks;
+#[cfg(feature = "gossip")]
+pub mod topics;
+pub mod transfer;
+pub mod version;
+pub mod warnings;
 
-static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
-static SHUTDOWN_SENDER: OnceLock<Arc<Mutex<broadcast::Sender<()>>>> = OnceLock::new();
+use environment::EnvironmentReport;
+use events::PeerSummary;
+pub use events::{EventSink, PeerEvent, PeerStatus, ShutdownReason};
+pub use options::PeerOptions;
+pub use peer::{MdnsPeer, MdnsPeerBuilder};
+use registry::PeerRegistry;
+use remote_info::ConnectionReport;
 
-fn initialize_logging() {
+#[cfg(any(feature = "ffi", feature = "cli"))]
+pub(crate) fn initialize_logging() {
     use std::sync::Once;
     static INIT: Once = Once::new();
 
@@ -23,181 +120,962 @@ fn initialize_logging() {
     });
 }
 
-/// Initialize with a given peer identifier
-fn start_peer(identifier: &'static str) -> bool {
-    initialize_logging();
+/// Bind an endpoint with mDNS discovery that advertises `identifier` as its
+/// user data
+pub async fn bind_endpoint(identifier: &str) -> anyhow::Result<Endpoint> {
+    bind_endpoint_with(identifier, &PeerOptions::default()).await
+}
 
-    info!("{} starting...", identifier);
+/// Like [`bind_endpoint`], honoring the endpoint settings in `options`
+pub async fn bind_endpoint_with(
+    identifier: &str,
+    options: &PeerOptions,
+) -> anyhow::Result<Endpoint> {
+    bind_peer_endpoint(identifier, options)
+        .await
+        .map_err(|(_, e)| e)
+}
 
-    // Create tokio runtime if needed
-    let rt = RUNTIME
-        .get_or_init(|| tokio::runtime::Runtime::new().expect("Failed to create tokio runtime"));
+/// Like [`bind_endpoint_with`], failing with the [`ShutdownReason`] a peer
+/// that can't start reports: [`ShutdownReason::Failed`] for invalid user
+/// data or mDNS options, [`ShutdownReason::BindLost`] if the sockets can't
+/// be bound
+pub(crate) async fn bind_peer_endpoint(
+    identifier: &str,
+    options: &PeerOptions,
+) -> Result<Endpoint, (ShutdownReason, anyhow::Error)> {
+    let failed = |e: anyhow::Error| (ShutdownReason::Failed, e);
+    let bind_lost = |e: anyhow::Error| (ShutdownReason::BindLost, e);
 
-    // Create shutdown channel if needed
-    let shutdown_sender = SHUTDOWN_SENDER.get_or_init(|| {
-        let (tx, _) = broadcast::channel(1);
-        Arc::new(Mutex::new(tx))
-    });
+    let user_data = identifier
+        .parse()
+        .map_err(|e| failed(anyhow::Error::from(e)))?;
+    let mut builder = Endpoint::builder();
+    builder = if options.mdns.is_default() && !options.p2p.is_managed() {
+        builder.discovery_local_network()
+    } else {
+        options.mdns.validate().map_err(failed)?;
+        builder.add_discovery(mdns::WithPeerToPeer(
+            options.mdns.clone(),
+            options.p2p.clone(),
+        ))
+    };
+    if let Some(fallback) = &options.dns_fallback {
+        builder = builder.add_discovery(fallback.service());
+    }
+    builder = builder
+        .add_discovery(options.candidates.clone())
+        .user_data_for_discovery(user_data)
+        .relay_mode(relay::relay_mode(options));
+    if let Some(secret_key) = &options.secret_key {
+        builder = builder.secret_key(secret_key.clone());
+    }
+    let endpoint = options
+        .bind
+        .apply(builder)
+        .bind()
+        .await
+        .map_err(|e| bind_lost(e.into()))?;
+    if let Err(e) = options.bind.check(&endpoint) {
+        endpoint.close().await;
+        return Err(bind_lost(e));
+    }
+    Ok(endpoint)
+}
 
-    let shutdown_rx = shutdown_sender.lock().unwrap().subscribe();
+/// How long to wait for any discovery traffic before reporting
+/// [`PeerStatus::LocalNetworkPermissionLikelyDenied`]
+const LOCAL_NETWORK_SILENCE_WINDOW: Duration = Duration::from_secs(20);
 
-    rt.spawn(async move {
-        match run_peer(identifier, shutdown_rx).await {
-            Ok(_) => info!("{} completed successfully", identifier),
-            Err(e) => warn!("{} error: {}", identifier, e),
-        }
-    });
+/// How long to wait for a direct address or home relay before giving up on
+/// logging the pairing payload
+const PAIRING_WAIT: Duration = Duration::from_secs(10);
 
-    true
-}
+/// How often to check whether any discovery traffic has arrived
+const SILENCE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
 
-/// Start peer with given identifier (for iOS)
-#[no_mangle]
-pub extern "C" fn peer_start(identifier: *const std::os::raw::c_char) -> bool {
-    if identifier.is_null() {
-        warn!("peer_start called with null identifier");
-        return false;
-    }
+/// How long a peer may find nobody before the `no_peers` warning
+const NO_PEERS_GRACE: Duration = Duration::from_secs(10);
 
-    let c_str = unsafe { std::ffi::CStr::from_ptr(identifier) };
-    let id = match c_str.to_str() {
-        Ok(s) => s,
-        Err(e) => {
-            warn!("Invalid UTF-8 in identifier: {}", e);
-            return false;
-        }
-    };
-
-    // Convert to static string (leaks but OK for app lifecycle)
-    let static_id: &'static str = Box::leak(id.to_string().into_boxed_str());
-    start_peer(static_id)
-}
+/// Run a peer until a shutdown signal is received
+///
+/// Logs discovered peers and a periodic routing table summary, passing every
+/// [`PeerEvent`] to `events` as well, then closes the endpoint gracefully once
+/// `shutdown_rx` fires. Fails with [`instance::AlreadyRunning`] if another
+/// instance on this machine advertises `identifier`, unless
+/// [`PeerOptions::on_duplicate`] says otherwise.
+pub async fn run_peer(
+    identifier: &str,
+    options: PeerOptions,
+    shutdown_rx: broadcast::Receiver<()>,
+    events: EventSink,
+) -> anyhow::Result<()> {
+    let instance = instance::claim(
+        identifier,
+        options.on_duplicate,
+        options.secret_key.is_some(),
+    )?;
+    if instance.identifier() != identifier {
+        warn!(
+            "{} is already running on this machine, advertising {} instead",
+            privacy::redact(identifier),
+            privacy::redact(instance.identifier())
+        );
+    }
+    let identifier = instance.identifier();
 
-/// Legacy name for backwards compatibility (defaults to "bob")
-#[no_mangle]
-pub extern "C" fn bob_start() -> bool {
-    start_peer("bob")
-}
+    info!("Creating endpoint with mDNS discovery...");
 
-/// Stop the peer
-#[no_mangle]
-pub extern "C" fn peer_stop() {
-    info!("Stopping peer...");
+    // Create endpoint with mDNS discovery and user data
+    let endpoint = bind_endpoint_with(identifier, &options).await?;
 
-    if let Some(sender) = SHUTDOWN_SENDER.get() {
-        let _ = sender.lock().unwrap().send(());
-        info!("Shutdown signal sent");
-    } else {
-        warn!("Peer was never started");
-    }
+    run_endpoint(identifier, endpoint, options, shutdown_rx, events).await
 }
 
-async fn run_peer(
+/// Like [`run_peer`], but on an endpoint the caller already bound
+///
+/// Lets callers keep a clone of the endpoint for queries while it runs.
+pub async fn run_endpoint(
     identifier: &str,
+    endpoint: Endpoint,
+    mut options: PeerOptions,
     mut shutdown_rx: broadcast::Receiver<()>,
+    events: EventSink,
 ) -> anyhow::Result<()> {
-    info!("Creating endpoint with mDNS discovery...");
-
-    // Create endpoint with mDNS discovery and user data
-    let user_data = identifier.parse()?;
-    let endpoint = Endpoint::builder()
-        .discovery_local_network()
-        .user_data_for_discovery(user_data)
-        .bind()
-        .await?;
+    if options.lite {
+        // Warm connections cost memory a lite peer doesn't have
+        options.warm_up = None;
+    }
+    privacy::set_log_names(options.log_names);
+    let messages = options.messages.clone();
+    #[cfg(feature = "gossip")]
+    let topics = options.topics.clone();
+    let history = options.history.clone();
+    let emit: EventSink = Arc::new(move |event| {
+        log_peer_event(event);
+        messages.peer_event(event);
+        #[cfg(feature = "gossip")]
+        topics.peer_event(event);
+        history.peer_event(event);
+        events(event);
+    });
+    supervise::install_panic_hook();
+    options.protocols.set_event_sink(emit.clone());
+    let trusted = options.warm_up.as_ref().map(|w| w.trusted.clone());
+    options.protocols.set_trusted(trusted.unwrap_or_default());
+    options.protocols.set_lan_only(options.lan_only);
 
     let node_id = endpoint.node_id();
-    info!("{} node ID: {}", identifier, node_id);
+    info!("{} node ID: {}", privacy::redact(identifier), node_id);
+    info!("Bound to {:?}", endpoint.bound_sockets());
+    environment::log_environment(&EnvironmentReport::collect(&options, Some(&endpoint)));
+    let recorder = match prepare(identifier, &endpoint, &options) {
+        Ok(recorder) => recorder,
+        Err(e) => {
+            endpoint.close().await;
+            return Err(e);
+        }
+    };
+    // Accept connections for host-defined protocols, messages, files and
+    // gossip, unless a lite peer only sends files
+    options.messages.set_budget(&options.budget);
+    options.transfers.attach(&options.protocols);
+    let router = if options.lite {
+        info!("Running lite: sending files only, one at a time");
+        options.transfers.set_max_active(Some(1));
+        options.protocols.attach_endpoint(endpoint.clone());
+        // Without a router nobody takes incoming connections, which would
+        // leave dialers waiting for the handshake until they time out
+        let refusing = endpoint.clone();
+        supervise::spawn_supervised("refuse", emit.clone(), async move {
+            while let Some(incoming) = refusing.accept().await {
+                incoming.refuse();
+            }
+        });
+        None
+    } else {
+        options.messages.attach(&options.protocols);
+        #[cfg(feature = "gossip")]
+        let gossip = options.topics.attach(&endpoint, emit.clone());
+        #[cfg(feature = "docs")]
+        let docs = match options
+            .docs
+            .attach(&endpoint, gossip.clone(), emit.clone())
+            .await
+        {
+            Ok(docs) => docs,
+            Err(e) => {
+                endpoint.close().await;
+                return Err(e);
+            }
+        };
+        let router = options
+            .protocols
+            .spawn_router_with(endpoint.clone(), |builder| {
+                #[cfg(feature = "docs")]
+                let builder = docs.accept(builder);
+                #[cfg(feature = "gossip")]
+                let builder = builder.accept(iroh_gossip::ALPN, gossip);
+                builder.accept(
+                    rotation::ROTATION_ALPN,
+                    rotation::RotationHandler::new(
+                        options.protocols.clone(),
+                        options.notes.clone(),
+                    ),
+                )
+            });
+        Some(router)
+    };
+
+    options.history.begin(identifier, node_id);
+    let pairing_endpoint = endpoint.clone();
+    supervise::spawn_supervised("pairing", emit.clone(), async move {
+        if let Some(payload) = pairing::local_payload(&pairing_endpoint, PAIRING_WAIT).await {
+            info!("Pairing payload: {}", payload);
+        }
+    });
+    // Messages queued before the start, a no-op for a lite peer
+    options.messages.flush_all();
 
     info!("Listening for peers via mDNS discovery...");
+    emit(&PeerEvent::status(PeerStatus::Running));
 
     // Subscribe to discovery events to see user_data
     let my_node_id = node_id;
-    let mut discovery_stream = endpoint.discovery_stream();
+    let raw_events = Arc::new(AtomicU64::new(0));
+    let mut source = discovery::endpoint_source(&endpoint);
+    if let Some(recorder) = recorder {
+        source = recorder.record_source(source);
+    }
+    let mut source = discovery::count_events(source, raw_events.clone());
+    if let Some(fallback) = &options.dns_fallback {
+        // Kept out of the count, which is about local network traffic
+        source = discovery::merge_events(source, fallback.events());
+    }
+    let mut fresh = PeerRegistry::for_mdns(&options.mdns);
+    fresh.set_budget(&options.budget);
+    fresh.set_expiry_grace(options.expiry_grace);
+    let registry = options.registry.clone();
+    *registry.lock().unwrap() = fresh;
     let mut discovery_shutdown = shutdown_rx.resubscribe();
 
-    tokio::spawn(async move {
-        loop {
+    let discovery_emit = emit.clone();
+    let discovery_registry = registry.clone();
+    let warm_up = options.warm_up.clone();
+    let warm_up_endpoint = endpoint.clone();
+    let warm_up_protocols = options.protocols.clone();
+    let warm_up_registry = registry.clone();
+    let warm_up_emit = emit.clone();
+    let on_discovery_event = move |event: PeerEvent| {
+        if let (Some(warm_up), PeerEvent::Discovered { node_id, .. }) = (&warm_up, &event) {
+            if warm_up.trusted.contains(node_id) {
+                supervise::spawn_supervised(
+                    "warm_up",
+                    warm_up_emit.clone(),
+                    keep_warm(
+                        warm_up_endpoint.clone(),
+                        warm_up_protocols.clone(),
+                        warm_up_registry.clone(),
+                        *node_id,
+                        warm_up.alpn.clone(),
+                    ),
+                );
+            }
+        }
+        discovery_emit(&event);
+    };
+    supervise::spawn_supervised("discovery", emit.clone(), async move {
+        tokio::select! {
+            _ = discovery::run_discovery_loop(source, my_node_id, discovery_registry, on_discovery_event) => {}
+            _ = discovery_shutdown.recv() => {
+                info!("Discovery task shutting down...");
+            }
+        }
+    });
+
+    // Look peers up in DNS while local discovery finds nobody
+    if let Some(fallback) = options.dns_fallback.clone() {
+        let mut fallback_shutdown = shutdown_rx.resubscribe();
+        let fallback_registry = registry.clone();
+        let fallback_emit = emit.clone();
+        let trusted = rotation::trusted_peers(&options);
+        let notes = options.notes.clone();
+        let known = move || {
+            let mut known = trusted.clone();
+            known.extend(notes.all().into_keys());
+            known
+        };
+        supervise::spawn_supervised("dns_fallback", emit.clone(), async move {
             tokio::select! {
-                event = discovery_stream.next() => {
-                    match event {
-                        Some(Ok(DiscoveryEvent::Discovered(item))) => {
-                            let discovered_node_id = item.node_id();
-
-                            // Skip self-discovery (not interesting)
-                            if discovered_node_id == my_node_id {
-                                continue;
-                            }
-
-                            // Check user_data to definitively identify the peer
-                            let user_data = item.node_info().data.user_data();
-
-                            info!("Peer discovered:");
-                            info!("  Node ID: {}", discovered_node_id);
-                            info!("  User data: {:?}", user_data);
-                            info!("  Source: {}", item.provenance());
-
-                            if let Some(ref data) = user_data {
-                                info!("[[[ SUCCESS ]]]: Discovered peer '{}'!", data);
-                            } else {
-                                info!("  Note: No user_data (legacy iroh peer or different app)");
-                            }
-                        }
-                        Some(Ok(DiscoveryEvent::Expired(node_id))) => {
-                            info!("Peer expired: {}", node_id);
-                        }
-                        Some(Err(e)) => {
-                            warn!("Discovery error: {}", e);
-                        }
-                        None => break,
-                    }
-                }
-                _ = discovery_shutdown.recv() => {
-                    info!("Discovery task shutting down...");
-                    break;
-                }
+                _ = fallback.run(fallback_registry, known, fallback_emit) => {}
+                _ = fallback_shutdown.recv() => {}
             }
+        });
+    }
+
+    // Report local address changes (Wi-Fi roam, VPN up/down)
+    let mut network_shutdown = shutdown_rx.resubscribe();
+    let network_emit = emit.clone();
+    let network_endpoint = endpoint.clone();
+    supervise::spawn_supervised("network", emit.clone(), async move {
+        tokio::select! {
+            _ = network::watch_local_addrs(network_endpoint, |event| network_emit(&event)) => {}
+            _ = network_shutdown.recv() => {}
         }
     });
 
-    // Show periodic summary
-    let mut interval = tokio::time::interval(Duration::from_secs(5));
-    loop {
+    // Watch for a blocked local network and hotspots, and show periodic summary
+    let started = Instant::now();
+    let mut likely_denied = false;
+    let mut no_peers = warnings::Condition::new("no_peers");
+    let mut on_hotspot = None;
+    let mut silence_check = suspend::interval(SILENCE_CHECK_INTERVAL);
+    let mut suspended = suspend::SuspendDetector::new(SILENCE_CHECK_INTERVAL);
+    let mut summary = options.summary_interval.map(suspend::interval);
+    let reason = loop {
         tokio::select! {
-            _ = interval.tick() => {
-                let remotes: Vec<_> = endpoint.remote_info_iter().collect();
-                if remotes.is_empty() {
-                    warn!("No peers discovered yet");
+            _ = silence_check.tick() => {
+                if endpoint.is_closed() {
+                    warn!("Endpoint closed without a shutdown request");
+                    break ShutdownReason::EndpointClosedRemotely;
+                }
+                if let Some(slept) = suspended.check() {
+                    // Peers that left meanwhile would otherwise stay listed
+                    // for the rest of their TTL
+                    info!("Suspended for about {:?}, refreshing discovery", slept);
+                    let expired = registry.lock().unwrap().age(slept);
+                    for event in &expired {
+                        emit(event);
+                    }
+                    endpoint.network_change().await;
+                    if summary.is_some() {
+                        emit(&summarize(&endpoint, &registry.lock().unwrap(), &options.notes));
+                    }
+                }
+                // With working multicast we always hear our own announcements,
+                // so total silence points at a blocked local network
+                let silent = raw_events.load(Ordering::Relaxed) == 0;
+                if silent && !likely_denied && started.elapsed() >= LOCAL_NETWORK_SILENCE_WINDOW {
+                    likely_denied = true;
+                    emit(&PeerEvent::status(PeerStatus::LocalNetworkPermissionLikelyDenied));
+                } else if !silent && likely_denied {
+                    likely_denied = false;
+                    emit(&PeerEvent::status(PeerStatus::Running));
+                }
+
+                let now = Instant::now();
+                let warning = if registry.lock().unwrap().is_empty() {
+                    (started.elapsed() >= NO_PEERS_GRACE)
+                        .then(|| no_peers.hold(now, "No peers discovered yet"))
+                        .flatten()
                 } else {
-                    info!("Total peers in routing table: {}", remotes.len());
+                    no_peers.clear(now)
+                };
+                if let Some(warning) = warning {
+                    emit(&warning);
+                }
+
+                let hotspot = hotspot::current(Some(&endpoint));
+                if hotspot != on_hotspot {
+                    on_hotspot = hotspot.clone();
+                    emit(&PeerEvent::HotspotChanged { hotspot });
+                }
+            }
+            _ = options.p2p.changed() => {
+                // Let iroh pick up the interface, and re-announce with its
+                // addresses put in or left out
+                endpoint.network_change().await;
+                endpoint.set_user_data_for_discovery(identifier.parse().ok());
+            }
+            _ = options.resume.resumed() => {
+                if let Some(warm_up) = &options.warm_up {
+                    reconnect_trusted(&endpoint, &options.protocols, &registry, warm_up, &emit);
                 }
             }
+            _ = async { summary.as_mut().unwrap().tick().await }, if summary.is_some() => {
+                emit(&summarize(&endpoint, &registry.lock().unwrap(), &options.notes));
+            }
             _ = shutdown_rx.recv() => {
                 info!("Peer shutting down...");
-                // Close endpoint gracefully
-                endpoint.close().await;
-                info!("Peer shutdown complete");
-                break;
+                break ShutdownReason::HostRequested;
             }
         }
+    };
+
+    // Stop accepting and close the endpoint gracefully
+    match router {
+        Some(router) => {
+            if let Err(e) = router.shutdown().await {
+                warn!("Router shutdown failed: {}", e);
+            }
+        }
+        None => endpoint.close().await,
     }
+    #[cfg(feature = "docs")]
+    options.docs.detach();
+    #[cfg(feature = "gossip")]
+    options.topics.detach();
+    options.protocols.detach();
+    options.history.end();
+    registry.lock().unwrap().clear();
+    info!("Peer shutdown complete");
+    emit(&PeerEvent::stopped(reason));
 
     Ok(())
 }
 
-/// Legacy name for backwards compatibility
-#[no_mangle]
-pub extern "C" fn bob_stop() {
-    peer_stop()
+/// The steps of [`run_endpoint`] that can fail, run before anything is
+/// spawned so that closing the endpoint is all a failure has to undo
+///
+/// Returns the recorder for [`PeerOptions::record`].
+fn prepare(
+    identifier: &str,
+    endpoint: &Endpoint,
+    options: &PeerOptions,
+) -> anyhow::Result<Option<session::SessionRecorder>> {
+    for addr in &options.paired {
+        pairing::add_paired_peer(endpoint, addr.clone())?;
+    }
+    let Some(path) = &options.record else {
+        return Ok(None);
+    };
+    let recorder = session::SessionRecorder::create_with(
+        path,
+        endpoint.node_id(),
+        Some(identifier),
+        !options.log_names,
+    )?;
+    info!("Recording discovery events to {}", path.display());
+    Ok(Some(recorder))
+}
+
+/// Connect to a trusted peer ahead of time and track the connection in the
+/// registry until it closes
+async fn keep_warm(
+    endpoint: Endpoint,
+    protocols: protocols::Protocols,
+    registry: Arc<Mutex<PeerRegistry>>,
+    node_id: NodeId,
+    alpn: Vec<u8>,
+) {
+    if protocols.is_connected(node_id, &alpn) {
+        return;
+    }
+    let conn = match protocols.connect(&endpoint, node_id, &alpn).await {
+        Ok(conn) => conn,
+        Err(e) => {
+            warn!(
+                "Warm-up connection to {} failed: {:#}",
+                node_id.fmt_short(),
+                e
+            );
+            return;
+        }
+    };
+
+    info!("Warm connection to {} ready", node_id.fmt_short());
+    stay_warm(registry, node_id, conn).await;
+}
+
+/// Mark `node_id` warm in the registry until `conn` closes
+async fn stay_warm(registry: Arc<Mutex<PeerRegistry>>, node_id: NodeId, conn: Connection) {
+    registry.lock().unwrap().set_warm(&node_id, true);
+    conn.closed().await;
+    registry.lock().unwrap().set_warm(&node_id, false);
+}
+
+/// Re-dial every trusted peer without an open connection, reporting each
+/// attempt, see [`reconnect`]
+fn reconnect_trusted(
+    endpoint: &Endpoint,
+    protocols: &protocols::Protocols,
+    registry: &Arc<Mutex<PeerRegistry>>,
+    warm_up: &options::WarmUp,
+    emit: &EventSink,
+) {
+    let dropped: Vec<_> = warm_up
+        .trusted
+        .iter()
+        .filter(|node_id| !protocols.is_connected(**node_id, &warm_up.alpn))
+        .copied()
+        .collect();
+    info!("Resumed, reconnecting {} trusted peers", dropped.len());
+    for node_id in dropped {
+        let endpoint = endpoint.clone();
+        let protocols = protocols.clone();
+        let registry = registry.clone();
+        let alpn = warm_up.alpn.clone();
+        let emit_result = emit.clone();
+        supervise::spawn_supervised("reconnect", emit.clone(), async move {
+            let result = tokio::time::timeout(
+                reconnect::RECONNECT_TIMEOUT,
+                protocols.connect(&endpoint, node_id, &alpn),
+            )
+            .await
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("Timed out")));
+            match result {
+                Ok(conn) => {
+                    emit_result(&PeerEvent::Reconnect {
+                        node_id,
+                        connected: true,
+                        error: None,
+                    });
+                    stay_warm(registry, node_id, conn).await;
+                }
+                Err(e) => emit_result(&PeerEvent::Reconnect {
+                    node_id,
+                    connected: false,
+                    error: Some(format!("{:#}", e)),
+                }),
+            }
+        });
+    }
+}
+
+/// Build a [`PeerEvent::Summary`] from the registry, the endpoint's
+/// connection state and the user's notes
+fn summarize(endpoint: &Endpoint, registry: &PeerRegistry, notes: &notes::PeerNotes) -> PeerEvent {
+    let now = Instant::now();
+    let mut peers: Vec<_> = registry
+        .peers()
+        .map(|entry| {
+            let note = notes.get(entry.node_id);
+            PeerSummary {
+                node_id: entry.node_id,
+                user_data: entry.user_data.clone(),
+                alias: note.alias,
+                notes: note.notes,
+                connection: endpoint
+                    .remote_info(entry.node_id)
+                    .map_or(ConnectionReport::None, |info| info.conn_type.into()),
+                warm: entry.warm,
+                expires_in_ms: registry
+                    .expires_at(entry)
+                    .saturating_duration_since(now)
+                    .as_millis() as u64,
+            }
+        })
+        .collect();
+    peers.sort_by(|a, b| a.user_data.cmp(&b.user_data));
+
+    PeerEvent::Summary {
+        routing_table_size: endpoint.remote_info_iter().count(),
+        peers,
+    }
+}
+
+/// Log a peer event in the format the README documents
+fn log_peer_event(event: &PeerEvent) {
+    match event {
+        PeerEvent::Discovered {
+            node_id,
+            user_data,
+            provenance,
+            origin,
+        } => {
+            info!("Peer discovered:");
+            info!("  Node ID: {}", node_id);
+            info!(
+                "  User data: {:?}",
+                user_data
+                    .as_deref()
+                    .map(|data| privacy::redact(data).to_string())
+            );
+            info!("  Source: {}", provenance);
+            if let (Some(interface), Some(addr)) = (&origin.interface, origin.addr) {
+                info!("  Seen on: {} at {}", interface, addr);
+            }
+
+            // user_data definitively identifies the peer
+            if let Some(data) = user_data {
+                info!(
+                    "[[[ SUCCESS ]]]: Discovered peer '{}'!",
+                    privacy::redact(data)
+                );
+            } else {
+                info!("  Note: No user_data (legacy iroh peer or different app)");
+            }
+        }
+        PeerEvent::Expired { node_id, .. } => {
+            info!("Peer expired: {}", node_id);
+        }
+        PeerEvent::Flapped {
+            node_id,
+            offline_ms,
+            flaps,
+            ..
+        } => {
+            debug!(
+                "Peer {} came back after {} ms ({} flaps)",
+                node_id.fmt_short(),
+                offline_ms,
+                flaps
+            );
+        }
+        PeerEvent::StatusChanged {
+            status: PeerStatus::LocalNetworkPermissionLikelyDenied,
+            ..
+        } => {
+            warn!(
+                "No mDNS traffic received after {:?}, not even our own announcements",
+                LOCAL_NETWORK_SILENCE_WINDOW
+            );
+            warn!("Local network access is likely blocked (iOS: Settings > Privacy & Security > Local Network)");
+        }
+        PeerEvent::StatusChanged {
+            status,
+            reason: Some(reason),
+        } => {
+            info!("Status: {:?} ({:?})", status, reason);
+        }
+        PeerEvent::StatusChanged { status, .. } => {
+            info!("Status: {:?}", status);
+        }
+        PeerEvent::Warning { .. } => warnings::log(event),
+        PeerEvent::LocalAddrsChanged { previous, current } => {
+            info!("Network changed, re-announcing:");
+            info!(
+                "  Direct addresses: {:?} -> {:?}",
+                previous.direct_addrs, current.direct_addrs
+            );
+            if previous.home_relay != current.home_relay {
+                info!(
+                    "  Home relay: {:?} -> {:?}",
+                    previous.home_relay, current.home_relay
+                );
+            }
+        }
+        PeerEvent::InboundConnection {
+            node_id,
+            alpn,
+            trusted,
+            accepted,
+        } => {
+            info!(
+                "Inbound {} connection from {}{}: {}",
+                alpn,
+                node_id.fmt_short(),
+                if *trusted { " (trusted)" } else { "" },
+                if *accepted { "accepted" } else { "denied" }
+            );
+        }
+        PeerEvent::Connected {
+            node_id,
+            alpn,
+            direction,
+            connection,
+        } => {
+            info!(
+                "Connected to {} ({:?}, {}): {:?}",
+                node_id.fmt_short(),
+                direction,
+                alpn,
+                connection
+            );
+        }
+        PeerEvent::PathChanged {
+            node_id,
+            previous,
+            current,
+            previous_interface,
+            current_interface,
+        } => {
+            info!(
+                "Path to {} changed: {:?} on {} -> {:?} on {}",
+                node_id.fmt_short(),
+                previous,
+                previous_interface.as_deref().unwrap_or("?"),
+                current,
+                current_interface.as_deref().unwrap_or("?")
+            );
+        }
+        PeerEvent::DiscoveryModeChanged { mode, reason } => {
+            info!("Discovery mode {:?}: {}", mode, reason);
+        }
+        PeerEvent::HotspotChanged {
+            hotspot: Some(hotspot),
+        } => {
+            info!(
+                "On a Personal Hotspot as {:?} via {} ({}): {}",
+                hotspot.role, hotspot.interface, hotspot.addr, hotspot.hint
+            );
+        }
+        PeerEvent::HotspotChanged { hotspot: None } => {
+            info!("Left the Personal Hotspot");
+        }
+        PeerEvent::RelayFallback { node_id, url } => {
+            info!(
+                "Traffic with {} now goes through relay {}",
+                node_id.fmt_short(),
+                url
+            );
+        }
+        PeerEvent::RelayRefused {
+            node_id,
+            alpn,
+            reason,
+        } => {
+            warn!(
+                "No direct path to {} on {} and relays are off (lan_only): {}",
+                node_id.fmt_short(),
+                alpn,
+                reason
+            );
+        }
+        PeerEvent::KeyRotated {
+            old_node_id,
+            new_node_id,
+            trusted,
+        } => {
+            info!(
+                "{} rotated its key and is now {}{}",
+                old_node_id.fmt_short(),
+                new_node_id.fmt_short(),
+                if *trusted { ", still trusted" } else { "" }
+            );
+        }
+        PeerEvent::ConnectionClosed {
+            node_id,
+            alpn,
+            reason,
+            ..
+        } => {
+            info!(
+                "Connection to {} ({}) closed: {}",
+                node_id.fmt_short(),
+                alpn,
+                reason
+            );
+        }
+        PeerEvent::VersionMismatch {
+            node_id,
+            alpn,
+            reason,
+            ..
+        } => {
+            warn!(
+                "Incompatible {} with {}: {}",
+                alpn,
+                node_id.fmt_short(),
+                reason
+            );
+        }
+        PeerEvent::Reconnect {
+            node_id,
+            error: None,
+            ..
+        } => {
+            info!("Reconnected to {}", node_id.fmt_short());
+        }
+        PeerEvent::Reconnect {
+            node_id,
+            error: Some(error),
+            ..
+        } => {
+            warn!("Reconnecting to {} failed: {}", node_id.fmt_short(), error);
+        }
+        PeerEvent::MessageSent { id, node_id } => {
+            debug!("Message {} to {} sent", id, node_id.fmt_short());
+        }
+        PeerEvent::MessageDelivered { id, node_id } => {
+            info!("Message {} to {} delivered", id, node_id.fmt_short());
+        }
+        PeerEvent::MessageFailed {
+            id,
+            node_id,
+            reason,
+            retrying,
+            ..
+        } => {
+            warn!(
+                "Message {} to {} failed{}: {}",
+                id,
+                node_id.fmt_short(),
+                if *retrying { ", will retry" } else { "" },
+                reason
+            );
+        }
+        PeerEvent::RateLimited {
+            node_id,
+            limit,
+            refused,
+        } => {
+            warn!(
+                "Refused {} message(s) from {} over the {:?} limit",
+                refused,
+                node_id.fmt_short(),
+                limit
+            );
+        }
+        PeerEvent::OverBudget {
+            pool,
+            limit,
+            dropped,
+        } => {
+            warn!(
+                "Dropped {} from {:?}, which is limited to {}",
+                dropped, pool, limit
+            );
+        }
+        PeerEvent::TransferStarted {
+            id,
+            node_id,
+            name,
+            direction,
+            offset,
+            size,
+        } => {
+            info!(
+                "Transfer {} ({:?}) of {} with {} started at {} of {} bytes",
+                id,
+                direction,
+                name,
+                node_id.fmt_short(),
+                offset,
+                size
+            );
+        }
+        PeerEvent::TransferFinished {
+            id,
+            name,
+            outcome,
+            hash,
+            error,
+            ..
+        } => match (error, hash) {
+            (Some(error), _) => warn!("Transfer {} of {} {:?}: {}", id, name, outcome, error),
+            (None, Some(hash)) => {
+                info!("Transfer {} of {} {:?}, BLAKE3 {}", id, name, outcome, hash)
+            }
+            (None, None) => info!("Transfer {} of {} {:?}", id, name, outcome),
+        },
+        PeerEvent::OperationFinished {
+            id,
+            kind,
+            status,
+            error,
+            ..
+        } => match error {
+            Some(error) => warn!("Operation {} ({:?}) {:?}: {}", id, kind, status, error),
+            None => debug!("Operation {} ({:?}) {:?}", id, kind, status),
+        },
+        PeerEvent::NeighborUp { topic, node_id } => {
+            info!(
+                "Gossip neighbour up on {:?}: {}",
+                topic,
+                node_id.fmt_short()
+            );
+        }
+        PeerEvent::NeighborDown { topic, node_id } => {
+            info!(
+                "Gossip neighbour down on {:?}: {}",
+                topic,
+                node_id.fmt_short()
+            );
+        }
+        PeerEvent::DocChanged { doc, key, node_id } => match node_id {
+            Some(node_id) => info!(
+                "Document {} changed {:?} from {}",
+                doc,
+                key,
+                node_id.fmt_short()
+            ),
+            None => info!("Document {} changed {:?}", doc, key),
+        },
+        PeerEvent::Error {
+            task,
+            message,
+            backtrace,
+        } => {
+            error!("Error in {}: {}", task, message);
+            if let Some(backtrace) = backtrace {
+                error!("{}", backtrace);
+            }
+        }
+        PeerEvent::Summary {
+            routing_table_size,
+            peers,
+        } => {
+            if *routing_table_size == 0 {
+                // Reported as the `no_peers` warning, not on every summary
+                debug!("No peers discovered yet");
+                return;
+            }
+            info!("Total peers in routing table: {}", routing_table_size);
+            for peer in peers {
+                info!(
+                    "  {} ({}): {:?}{}",
+                    peer.alias
+                        .as_deref()
+                        .or(peer.user_data.as_deref())
+                        .map_or_else(
+                            || "<no user data>".to_string(),
+                            |name| privacy::redact(name).to_string()
+                        ),
+                    peer.node_id.fmt_short(),
+                    peer.connection,
+                    if peer.warm { " [warm]" } else { "" }
+                );
+            }
+        }
+    }
+}
+
+/// Bind a throwaway endpoint, wait briefly for it to pick a home relay, and
+/// log the latency to every relay in `options`
+#[cfg(feature = "cli")]
+pub async fn run_relay_probe(options: PeerOptions) -> anyhow::Result<()> {
+    initialize_logging();
+
+    let endpoint = bind_endpoint_with("relay-probe", &options).await?;
+    let home = relay::wait_for_home_relay(&endpoint, Duration::from_secs(5)).await;
+
+    info!("Probing relays...");
+    let report = relay::probe_relays(relay::configured_relays(&options), home).await;
+    relay::log_relay_report(&report);
+
+    endpoint.close().await;
+    Ok(())
+}
+
+/// Replay a recorded session, logging the events it produces
+#[cfg(feature = "cli")]
+pub async fn run_replay(path: &std::path::Path, speed: f64) -> anyhow::Result<()> {
+    initialize_logging();
+
+    let session = session::Session::read(path)?;
+    info!(
+        "Replaying {} events recorded by {} at {}x",
+        session.events.len(),
+        session.user_data.as_deref().map_or_else(
+            || "<no user data>".to_string(),
+            |name| privacy::redact(name).to_string()
+        ),
+        speed
+    );
+    session.replay(speed, |event| log_peer_event(&event)).await;
+    info!("Replay finished");
+    Ok(())
 }
 
 /// Run as desktop binary (used by alice/bob CLI wrappers)
-pub async fn run_desktop() -> anyhow::Result<()> {
+#[cfg(feature = "cli")]
+pub async fn run_desktop(options: PeerOptions) -> anyhow::Result<()> {
+    run_desktop_with_events(options, events::discard_events()).await
+}
+
+/// Like [`run_desktop`], passing every event to `events` as well
+#[cfg(feature = "cli")]
+pub async fn run_desktop_with_events(
+    options: PeerOptions,
+    events: EventSink,
+) -> anyhow::Result<()> {
     initialize_logging();
 
     // Get identifier from env var or default to "bob"
     let identifier = std::env::var("PEER_ID").unwrap_or_else(|_| "bob".to_string());
-    info!("Running as: {}", identifier);
+    privacy::set_log_names(options.log_names);
+    info!("Running as: {}", privacy::redact(&identifier));
 
     // For desktop, create a shutdown channel that listens for Ctrl+C
     let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
@@ -215,7 +1093,7 @@ pub async fn run_desktop() -> anyhow::Result<()> {
         }
     });
 
-    run_peer(&identifier, shutdown_rx).await
+    run_peer(&identifier, options, shutdown_rx, events).await
 }
 
 // Note: The binary entry point is in src/main.rs
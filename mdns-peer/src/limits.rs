@@ -0,0 +1,113 @@
+//! Flow control knobs for host protocol streams
+//!
+//! A background sync shouldn't saturate a phone's Wi-Fi while the user is on
+//! a call, so the host can cap the send rate and the number of concurrent
+//! streams per connection. Limits can be changed at any time and apply to
+//! streams that are already open.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::Notify;
+
+/// Limits applied to every connection, `None` meaning unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransferLimits {
+    /// Payload bytes per second sent on one connection, across its streams
+    pub send_bytes_per_sec: Option<u64>,
+    /// Streams open at the same time on one connection; further streams
+    /// wait for a free slot
+    pub max_concurrent_streams: Option<usize>,
+}
+
+/// Token bucket pacing writes on one connection
+///
+/// Writers take what they need and sleep off any debt, so concurrent streams
+/// share the rate. Up to one second's worth of sending can burst.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    state: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(Bucket {
+                tokens: 0.0,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+}
+
+impl RateLimiter {
+    /// Wait until `bytes` may be sent at `rate` bytes per second
+    pub(crate) async fn acquire(&self, bytes: usize, rate: u64) {
+        let rate = rate.max(1) as f64;
+        let wait = {
+            let mut bucket = self.state.lock().unwrap();
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * rate).min(rate);
+            bucket.refilled = now;
+            bucket.tokens -= bytes as f64;
+            (bucket.tokens < 0.0).then(|| Duration::from_secs_f64(-bucket.tokens / rate))
+        };
+        if let Some(wait) = wait {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Counts open streams on one connection against a changeable limit
+#[derive(Debug, Default)]
+pub(crate) struct StreamSlots {
+    open: Mutex<usize>,
+    freed: Notify,
+}
+
+impl StreamSlots {
+    /// Wait for a free slot under `limit()`, which is re-read after every
+    /// change so raising the limit wakes waiters
+    pub(crate) async fn acquire(self: &Arc<Self>, limit: impl Fn() -> Option<usize>) -> SlotGuard {
+        loop {
+            let freed = self.freed.notified();
+            tokio::pin!(freed);
+            freed.as_mut().enable();
+
+            {
+                let mut open = self.open.lock().unwrap();
+                if limit().is_none_or(|max| *open < max) {
+                    *open += 1;
+                    return SlotGuard {
+                        slots: self.clone(),
+                    };
+                }
+            }
+            freed.await;
+        }
+    }
+
+    /// Wake waiters so they re-check the limit
+    pub(crate) fn limit_changed(&self) {
+        self.freed.notify_waiters();
+    }
+}
+
+/// Releases its stream slot when dropped
+pub(crate) struct SlotGuard {
+    slots: Arc<StreamSlots>,
+}
+
+impl Drop for SlotGuard {
+    fn drop(&mut self) {
+        *self.slots.open.lock().unwrap() -= 1;
+        self.slots.freed.notify_waiters();
+    }
+}
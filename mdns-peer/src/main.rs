@@ -1,20 +1,939 @@
 use anyhow::Result;
+use iroh::NodeId;
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Get peer identifier from command line argument
     let args: Vec<String> = env::args().collect();
-    let identifier = if args.len() > 1 {
-        args[1].clone()
-    } else {
-        eprintln!("Usage: mdns-peer <identifier>");
-        eprintln!("Example: mdns-peer alice");
+
+    match args.get(1).map(String::as_str) {
+        Some("doctor") => run_doctor().await,
+        Some("sniff") => run_sniff(&args[2..]).await,
+        Some("fake") => run_fake(&args[2..]).await,
+        Some("soak") => run_soak(&args[2..]).await,
+        Some("stats") => run_stats(&args[2..]).await,
+        Some("relays") => run_relays(&args[2..]).await,
+        Some("daemon") => run_daemon(&args[2..]).await,
+        Some("alias") => run_alias(&args[2..]),
+        Some("history") => run_history(),
+        Some("send") => exit_on_timeout(run_send(&args[2..]).await),
+        Some("ping") => exit_on_timeout(run_ping(&args[2..]).await),
+        Some("recv") => run_recv(&args[2..]).await,
+        Some("snapshot") => run_snapshot(&args[2..]).await,
+        Some("find") => run_find(&args[2..]).await,
+        Some("tasks") => run_tasks(&args[2..]).await,
+        Some("diff") => run_diff(&args[2..]),
+        #[cfg(feature = "docs")]
+        Some("doc") => run_doc(&args[2..]).await,
+        Some(_) if args.iter().any(|arg| arg == "--replay") => run_replay(&args[1..]).await,
+        Some(_) if args.iter().any(|arg| arg == "--dry-run") => run_dry_run(&args[1..]),
+        Some(_) => {
+            let (identifier, options) = peer_options(&args[1..])?;
+
+            // Set as env var for the shared implementation
+            env::set_var("PEER_ID", identifier);
+
+            if args.iter().any(|arg| arg == "--events") {
+                mdns_peer::run_desktop_with_events(options, print_events()).await
+            } else {
+                mdns_peer::run_desktop(options).await
+            }
+        }
+        None => {
+            print_usage();
+            std::process::exit(1);
+        }
+    }
+}
+
+fn print_usage() {
+    eprintln!("Usage: mdns-peer <identifier> [--profile <name>] [--summary-interval <secs>]");
+    eprintln!("                 [--record <file.ndjson>] [--duplicate refuse|suffix|allow]");
+    eprintln!("                 [--relays <url,...>] [--lan-only] [--mdns-service <name>]");
+    eprintln!("                 [--mdns-cadence <secs>] [--mdns-response-rate <hz>]");
+    eprintln!("                 [--pair <payload,...>] [--bind <addr,...|port>]");
+    eprintln!("                 [--seed <n>] [--log-names] [--dns-fallback <secs>]");
+    eprintln!("                 [--config <file>] [--dry-run]");
+    eprintln!("                 [--events]");
+    eprintln!("       mdns-peer --profile <name> [--summary-interval <secs>]");
+    eprintln!("       mdns-peer --replay <file.ndjson> [--speed <factor>]");
+    eprintln!("       mdns-peer daemon <identifier> [--dashboard <addr:port>] [peer options]");
+    eprintln!("                 [--grpc <addr:port>] (with the grpc feature)");
+    eprintln!("       mdns-peer relays [--relays <url,...>]");
+    eprintln!("       mdns-peer alias [<node_id> [<alias>] [--notes <text>]]");
+    eprintln!("       mdns-peer history");
+    eprintln!("       mdns-peer send <identifier-or-node-id> <path> --as <identifier>");
+    eprintln!("                 [--timeout <secs>] [peer options]");
+    eprintln!("       mdns-peer ping <identifier-or-node-id> --as <identifier>");
+    eprintln!("                 [--timeout <secs>] [peer options]");
+    eprintln!("       mdns-peer recv <identifier> --accept-from <identifier-or-node-id,...>");
+    eprintln!("                 --out <dir> [--max-transfers <n>] [peer options]");
+    eprintln!("       mdns-peer doc <identifier> [--join <ticket>] [peer options]");
+    eprintln!("                 (with the docs feature)");
+    eprintln!("       mdns-peer stats <identifier> [--interval <secs>] [peer options]");
+    eprintln!("       mdns-peer snapshot <identifier> <file.json> [--listen <secs>]");
+    eprintln!("                 [peer options]");
+    eprintln!("       mdns-peer diff <old.json> <new.json>");
+    eprintln!("       mdns-peer find <pattern> [--listen <secs>] [--mdns-service <name>]");
+    eprintln!("       mdns-peer tasks [<dashboard addr:port>]");
+    eprintln!("       mdns-peer soak [--hours <n>]");
+    eprintln!("       mdns-peer fake [--count <n>] [--prefix <name>] [--rotate <secs>]");
+    eprintln!("       mdns-peer doctor");
+    eprintln!("       mdns-peer sniff [--all] [--mdns-service <name>]");
+    eprintln!("Example: mdns-peer alice");
+}
+
+/// Identifier and options for `mdns-peer [<identifier>] [options]`
+///
+/// With `--profile`, the profile's stored key and user data are used; an
+/// identifier given alongside replaces the profile's user data.
+/// `--summary-interval 0` disables the periodic summary, and `--record`
+/// writes raw discovery events to a file for `--replay`. `--duplicate` picks
+/// what happens when the identifier is already running on this machine.
+/// The `--mdns-*` flags tune local discovery, see [`mdns_peer::mdns`], and
+/// `--pair` takes comma-separated pairing payloads of peers to reach without
+/// discovery, see [`mdns_peer::pairing`]. `--bind` pins the local addresses
+/// or UDP port, see [`mdns_peer::bind`], and `--lan-only` turns relays off,
+/// see [`mdns_peer::relay`]. `--seed` derives a fixed key for demos, see
+/// [`mdns_peer::profile::seeded_key`], and `--log-names` logs names
+/// unhashed, see [`mdns_peer::privacy`]. `--dns-fallback` looks peers up in
+/// DNS after that many seconds without local ones, see
+/// [`mdns_peer::fallback`]. Settings from `--config` and the
+/// `MDNS_PEER_*` environment variables apply first, so flags override them,
+/// see [`mdns_peer::config`].
+fn peer_options(args: &[String]) -> Result<(String, mdns_peer::PeerOptions)> {
+    let mut options = mdns_peer::PeerOptions::default();
+    let mut identifier = args.first().filter(|arg| !arg.starts_with("--")).cloned();
+
+    let file = match flag_value(args, "--config") {
+        Some(path) => mdns_peer::config::PeerConfig::load(std::path::Path::new(path))?,
+        None => mdns_peer::config::PeerConfig::default(),
+    };
+    file.merge(mdns_peer::config::PeerConfig::from_env()?)
+        .apply(&mut options)?;
+
+    if let Some(name) = flag_value(args, "--profile") {
+        let store = mdns_peer::profile::ProfileStore::open_default()?;
+        // A dry run doesn't create anything
+        if args.iter().any(|arg| arg == "--dry-run") && !store.list()?.iter().any(|p| p == name) {
+            anyhow::bail!("Profile {:?} does not exist", name);
+        }
+        let profile = store.load_or_create(name, identifier.as_deref())?;
+        options.secret_key = Some(profile.secret_key);
+        identifier = Some(profile.user_data);
+    }
+    if let Some(seed) = flag_value(args, "--seed") {
+        if args.iter().any(|arg| arg == "--profile") {
+            anyhow::bail!("--seed and --profile both choose the key; pass one");
+        }
+        let seed: u64 = seed
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid seed {:?}: {}", seed, e))?;
+        eprintln!(
+            "Using the key derived from seed {}; never use it for real devices",
+            seed
+        );
+        options.secret_key = Some(mdns_peer::profile::seeded_key(seed));
+    }
+
+    if let Some(secs) = flag_value(args, "--summary-interval") {
+        let interval = parse_secs("--summary-interval", secs)?;
+        options.summary_interval = (!interval.is_zero()).then_some(interval);
+    }
+
+    if let Some(policy) = flag_value(args, "--duplicate") {
+        options.on_duplicate = policy.parse()?;
+    }
+    if let Some(relays) = flag_value(args, "--relays") {
+        options.relays = Some(parse_relays(relays)?);
+    }
+    if args.iter().any(|arg| arg == "--lan-only") {
+        options.lan_only = true;
+    }
+    if args.iter().any(|arg| arg == "--log-names") {
+        options.log_names = true;
+    }
+    if let Some(secs) = flag_value(args, "--dns-fallback") {
+        let window = parse_secs("--dns-fallback", secs)?;
+        options.dns_fallback =
+            (!window.is_zero()).then(|| mdns_peer::fallback::DnsFallback::new(window));
+    }
+    if let Some(path) = flag_value(args, "--record") {
+        options.record = Some(path.into());
+    }
+    if let Some(name) = flag_value(args, "--mdns-service") {
+        options.mdns.service_name = name.to_string();
+    }
+    if let Some(secs) = flag_value(args, "--mdns-cadence") {
+        options.mdns.cadence = parse_secs("--mdns-cadence", secs)?;
+    }
+    if let Some(rate) = flag_value(args, "--mdns-response-rate") {
+        options.mdns.response_rate = rate.parse()?;
+    }
+    options.mdns.validate()?;
+    if let Some(addrs) = flag_value(args, "--bind") {
+        options.bind = mdns_peer::bind::BindAddrs::parse(addrs)?;
+    }
+    if let Some(payloads) = flag_value(args, "--pair") {
+        options.paired = payloads
+            .split(',')
+            .map(mdns_peer::pairing::decode_payload)
+            .collect::<Result<_>>()?;
+    }
+
+    match mdns_peer::notes::PeerNotes::open_default() {
+        Ok(notes) => options.notes = notes,
+        Err(e) => eprintln!("Peer aliases unavailable: {:#}", e),
+    }
+    match mdns_peer::history::SessionHistory::open_default() {
+        Ok(history) => options.history = history,
+        Err(e) => eprintln!("Session history unavailable: {:#}", e),
+    }
+
+    let identifier =
+        identifier.ok_or_else(|| anyhow::anyhow!("Missing identifier or --profile"))?;
+    Ok((identifier, options))
+}
+
+/// `mdns-peer <identifier> [options] --dry-run`: check the identifier and
+/// options, including config files, profiles and pinned ports, without
+/// starting a peer
+fn run_dry_run(args: &[String]) -> Result<()> {
+    let (identifier, options) = peer_options(args)?;
+    mdns_peer::MdnsPeer::builder()
+        .identifier(identifier.as_str())
+        .options(options)
+        .validate()?;
+    println!("Configuration for {} is valid", identifier);
+    Ok(())
+}
+
+/// `mdns-peer --replay <file> [--speed <factor>]`: feed a recording back
+/// through the registry, logging events as a live peer would
+async fn run_replay(args: &[String]) -> Result<()> {
+    let path = flag_value(args, "--replay").ok_or_else(|| anyhow::anyhow!("Missing recording"))?;
+    let speed = match flag_value(args, "--speed") {
+        Some(speed) => speed.parse()?,
+        None => 1.0,
+    };
+    mdns_peer::run_replay(path.as_ref(), speed).await
+}
+
+/// Comma-separated relay URLs
+fn parse_relays(list: &str) -> Result<Vec<iroh::RelayUrl>> {
+    list.split(',')
+        .map(|url| {
+            url.trim()
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid relay URL {:?}: {}", url, e))
+        })
+        .collect()
+}
+
+/// `mdns-peer relays [--relays <url,...>]`: show the home relay a fresh
+/// endpoint picks and the latency to every configured relay
+async fn run_relays(args: &[String]) -> Result<()> {
+    let mut options = mdns_peer::PeerOptions::default();
+    if let Some(relays) = flag_value(args, "--relays") {
+        options.relays = Some(parse_relays(relays)?);
+    }
+    mdns_peer::run_relay_probe(options).await
+}
+
+/// `mdns-peer snapshot <identifier> <file> [--listen <secs>]`: listen to
+/// discovery for a while (10 s by default) and save the peers found
+async fn run_snapshot(args: &[String]) -> Result<()> {
+    let [_, path, rest @ ..] = args else {
+        anyhow::bail!("Missing identifier or snapshot file");
+    };
+    let listen = match flag_value(rest, "--listen") {
+        Some(secs) => parse_secs("--listen", secs)?,
+        None => Duration::from_secs(10),
+    };
+    let (identifier, options) = peer_options(args)?;
+
+    let endpoint = mdns_peer::bind_endpoint_with(&identifier, &options).await?;
+    eprintln!("Listening for {:?}...", listen);
+    let snapshot = mdns_peer::snapshot::Snapshot::collect(
+        mdns_peer::discovery::endpoint_source(&endpoint),
+        endpoint.node_id(),
+        Some(identifier),
+        listen,
+    )
+    .await;
+    endpoint.close().await;
+
+    snapshot.write(path)?;
+    println!("{} peer(s) saved to {}", snapshot.peers.len(), path);
+    Ok(())
+}
+
+/// `mdns-peer find <pattern> [--listen <secs>] [--mdns-service <name>]`:
+/// listen to discovery for a while (3 s by default) and list the peers
+/// whose user data or alias matches, see [`mdns_peer::find`]
+async fn run_find(args: &[String]) -> Result<()> {
+    let Some(pattern) = args.first().filter(|arg| !arg.starts_with("--")) else {
+        anyhow::bail!("Missing pattern");
+    };
+    let pattern = mdns_peer::find::PeerPattern::new(pattern);
+    let listen = match flag_value(args, "--listen") {
+        Some(secs) => parse_secs("--listen", secs)?,
+        None => mdns_peer::naming::DEFAULT_LISTEN,
+    };
+    let mut options = mdns_peer::PeerOptions::default();
+    if let Some(name) = flag_value(args, "--mdns-service") {
+        options.mdns.service_name = name.to_string();
+    }
+    let notes = mdns_peer::notes::PeerNotes::open_default().unwrap_or_default();
+
+    let endpoint = mdns_peer::bind_endpoint_with("find", &options).await?;
+    let registry = Arc::new(Mutex::new(mdns_peer::registry::PeerRegistry::for_mdns(
+        &options.mdns,
+    )));
+    eprintln!("Listening for {:?}...", listen);
+    let _ = tokio::time::timeout(
+        listen,
+        mdns_peer::discovery::run_discovery_loop(
+            mdns_peer::discovery::endpoint_source(&endpoint),
+            endpoint.node_id(),
+            registry.clone(),
+            |_| {},
+        ),
+    )
+    .await;
+    endpoint.close().await;
+
+    let found = mdns_peer::find::find_peers(&registry.lock().unwrap(), &notes, &pattern);
+    for peer in &found {
+        println!(
+            "{}  {}  {}",
+            peer.node_id,
+            peer.user_data.as_deref().unwrap_or("-"),
+            peer.alias.as_deref().unwrap_or("")
+        );
+    }
+    if found.is_empty() {
+        eprintln!("No matching peers");
+    }
+    Ok(())
+}
+
+/// `mdns-peer diff <old> <new>`: compare two snapshots, exiting with 1 if
+/// the peer sets differ
+fn run_diff(args: &[String]) -> Result<()> {
+    let [old, new, ..] = args else {
+        anyhow::bail!("Missing snapshot files");
+    };
+    let old = mdns_peer::snapshot::Snapshot::read(old)?;
+    let new = mdns_peer::snapshot::Snapshot::read(new)?;
+    let diff = mdns_peer::snapshot::SnapshotDiff::new(&old, &new);
+    println!("{}", diff);
+    if !diff.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// `mdns-peer stats <identifier> [--interval <secs>]`: run a peer and log
+/// its protocol traffic periodically
+async fn run_stats(args: &[String]) -> Result<()> {
+    let (identifier, options) = peer_options(args)?;
+    let interval = match flag_value(args, "--interval") {
+        Some(secs) => parse_secs("--interval", secs)?,
+        None => Duration::from_secs(5),
+    };
+
+    let protocols = options.protocols.clone();
+    let transfers = options.transfers.clone();
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            let mut report = protocols.stats();
+            report.transfers = transfers.transfers();
+            mdns_peer::stats::log_stats(&report);
+        }
+    });
+
+    env::set_var("PEER_ID", identifier);
+    mdns_peer::run_desktop(options).await
+}
+
+/// `mdns-peer daemon <identifier> [--dashboard <addr:port>]
+/// [--grpc <addr:port>]`: run a peer with the services a long-running
+/// desktop peer offers
+///
+/// `--dashboard` serves the browser dashboard, e.g. on `127.0.0.1:8090`, or
+/// `0.0.0.0:8090` to reach it from other machines on the LAN. `--grpc`
+/// serves the control plane in [`mdns_peer::grpc`], with the `grpc` feature.
+async fn run_daemon(args: &[String]) -> Result<()> {
+    let (identifier, options) = peer_options(args)?;
+    let dashboard_addr = flag_value(args, "--dashboard")
+        .map(|addr| {
+            addr.parse()
+                .map_err(|e| anyhow::anyhow!("Invalid dashboard address {:?}: {}", addr, e))
+        })
+        .transpose()?;
+    let grpc_addr: Option<std::net::SocketAddr> = flag_value(args, "--grpc")
+        .map(|addr| {
+            addr.parse()
+                .map_err(|e| anyhow::anyhow!("Invalid gRPC address {:?}: {}", addr, e))
+        })
+        .transpose()?;
+    #[cfg(not(feature = "grpc"))]
+    anyhow::ensure!(
+        grpc_addr.is_none(),
+        "--grpc needs mdns-peer built with the grpc feature"
+    );
+
+    let mut events = mdns_peer::events::discard_events();
+    if dashboard_addr.is_some() || grpc_addr.is_some() {
+        // The gRPC service answers from the dashboard's peer table and log
+        let dashboard = mdns_peer::dashboard::Dashboard::new(options.protocols.clone());
+        if let Some(addr) = dashboard_addr {
+            dashboard.spawn(addr).await?;
+        }
+        #[cfg(feature = "grpc")]
+        if let Some(addr) = grpc_addr {
+            mdns_peer::grpc::ControlService::new(dashboard.clone(), &options)
+                .spawn(addr)
+                .await?;
+        }
+        events = dashboard.event_sink();
+    }
+
+    env::set_var("PEER_ID", identifier);
+    mdns_peer::run_desktop_with_events(options, events).await
+}
+
+/// `mdns-peer tasks [<addr:port>]`: list the background tasks of a daemon
+/// started with `--dashboard`, by default the one on `127.0.0.1:8090`
+///
+/// For finding the stuck component of a hung peer, see [`mdns_peer::tasks`].
+async fn run_tasks(args: &[String]) -> Result<()> {
+    let addr = args.first().map_or("127.0.0.1:8090", String::as_str);
+    let addr = addr
+        .parse()
+        .map_err(|e| anyhow::anyhow!("Invalid dashboard address {:?}: {}", addr, e))?;
+    let tasks = mdns_peer::dashboard::fetch_tasks(addr).await?;
+    println!(
+        "{:>6}  {:<16} {:<8} {:>12} {:>12} {:>8}",
+        "ID", "TASK", "STATE", "FOR", "AGE", "POLLS"
+    );
+    for task in tasks {
+        let state = match task.state {
+            mdns_peer::tasks::TaskState::Waiting => "waiting",
+            mdns_peer::tasks::TaskState::Running => "running",
+        };
+        println!(
+            "{:>6}  {:<16} {:<8} {:>12} {:>12} {:>8}",
+            task.id,
+            task.name,
+            state,
+            format!("{:.1}s", task.state_ms as f64 / 1000.0),
+            format!("{:.1}s", task.age_ms as f64 / 1000.0),
+            task.polls
+        );
+    }
+    Ok(())
+}
+
+/// `mdns-peer history`: print the summaries of past sessions as JSON, to
+/// attach to an issue
+fn run_history() -> Result<()> {
+    let history = mdns_peer::history::SessionHistory::open_default()?;
+    println!("{}", serde_json::to_string_pretty(&history.sessions())?);
+    Ok(())
+}
+
+/// `mdns-peer alias [<node_id> [<alias>] [--notes <text>]]`: name a peer
+/// locally, or list the named ones
+///
+/// An empty alias or notes removes them.
+fn run_alias(args: &[String]) -> Result<()> {
+    let notes = mdns_peer::notes::PeerNotes::open_default()?;
+    let Some(node_id) = args.first() else {
+        for (node_id, note) in notes.all() {
+            println!(
+                "{}  {}  {}",
+                node_id,
+                note.alias.as_deref().unwrap_or("-"),
+                note.notes.as_deref().unwrap_or("")
+            );
+        }
+        return Ok(());
+    };
+
+    let node_id = node_id
+        .parse()
+        .map_err(|e| anyhow::anyhow!("Invalid node ID {:?}: {}", node_id, e))?;
+    if let Some(alias) = args.get(1).filter(|arg| !arg.starts_with("--")) {
+        notes.set_alias(node_id, Some(alias))?;
+    }
+    if let Some(text) = flag_value(args, "--notes") {
+        notes.set_notes(node_id, Some(text))?;
+    }
+    Ok(())
+}
+
+/// Exit status of a command that gave up at its `--timeout`, as
+/// `timeout(1)` uses
+const EXIT_TIMED_OUT: i32 = 124;
+
+/// Exit with [`EXIT_TIMED_OUT`] if `result` failed by timing out, so
+/// scripts can tell that apart from other failures
+fn exit_on_timeout(result: Result<()>) -> Result<()> {
+    if let Err(e) = &result {
+        if mdns_peer::deadline::is_timed_out(e) {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(EXIT_TIMED_OUT);
+        }
+    }
+    result
+}
+
+/// How many times `mdns-peer send` tries before giving up
+const SEND_ATTEMPTS: u32 = 5;
+
+/// Pause between `mdns-peer send` attempts
+const SEND_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// `mdns-peer send <identifier-or-node-id> <path> --as <identifier>
+/// [--timeout <secs>]`: run a peer just long enough to send one file
+///
+/// Waits up to `--timeout` (30 s by default) for the receiver to be
+/// discovered, unless it's given by node ID, and as long again for it to
+/// accept the file; missing either exits with [`EXIT_TIMED_OUT`]. A
+/// transfer that breaks off is retried, continuing where it stopped;
+/// running the command again later resumes it as well. `--profile` can
+/// stand in for `--as`.
+async fn run_send(args: &[String]) -> Result<()> {
+    let [target, path, rest @ ..] = args else {
+        anyhow::bail!("Missing receiver or file");
+    };
+    let path = std::path::Path::new(path);
+    anyhow::ensure!(path.is_file(), "{} is not a file", path.display());
+    let timeout = match flag_value(rest, "--timeout") {
+        Some(secs) => parse_secs("--timeout", secs)?,
+        None => Duration::from_secs(30),
+    };
+    let mut peer_args: Vec<String> = flag_value(rest, "--as")
+        .map(String::from)
+        .into_iter()
+        .collect();
+    peer_args.extend_from_slice(rest);
+    let (identifier, options) = peer_options(&peer_args)?;
+
+    let transfers = options.transfers.clone();
+    transfers.set_progress(Some(Arc::new(print_progress)));
+    let (events, mut seen) = watch_peers();
+    env::set_var("PEER_ID", identifier);
+    let peer = tokio::spawn(mdns_peer::run_desktop_with_events(options, events));
+    let node_id = discover(target, "discover the receiver", timeout, &mut seen, peer).await?;
+
+    let mut attempt = 1;
+    loop {
+        match transfers
+            .send_file_within(node_id, path, Some(timeout))
+            .await
+        {
+            Ok(hash) => {
+                println!("{}  {}", hash, path.display());
+                return Ok(());
+            }
+            Err(e)
+                if e.is::<mdns_peer::transfer::Refused>()
+                    || mdns_peer::deadline::is_timed_out(&e)
+                    || attempt == SEND_ATTEMPTS =>
+            {
+                return Err(e)
+            }
+            Err(e) => {
+                eprintln!();
+                eprintln!("Transfer interrupted ({:#}), resuming...", e);
+                attempt += 1;
+                tokio::time::sleep(SEND_RETRY_DELAY).await;
+            }
+        }
+    }
+}
+
+/// `mdns-peer ping <identifier-or-node-id> --as <identifier> [--timeout
+/// <secs>]`: connect to a peer once it's discovered and print the
+/// round-trip time
+///
+/// Waits up to 30 seconds (`--timeout`) for the peer to be discovered and as
+/// long again to connect, and exits with status 124 if either runs out. The
+/// connection is on the message ALPN, which every peer but a lite one
+/// serves, like `peer_ping`.
+async fn run_ping(args: &[String]) -> Result<()> {
+    let [target, rest @ ..] = args else {
+        anyhow::bail!("Missing peer to ping");
+    };
+    let timeout = match flag_value(rest, "--timeout") {
+        Some(secs) => parse_secs("--timeout", secs)?,
+        None => Duration::from_secs(30),
+    };
+    let mut peer_args: Vec<String> = flag_value(rest, "--as")
+        .map(String::from)
+        .into_iter()
+        .collect();
+    peer_args.extend_from_slice(rest);
+    let (identifier, options) = peer_options(&peer_args)?;
+
+    let protocols = options.protocols.clone();
+    let (events, mut seen) = watch_peers();
+    env::set_var("PEER_ID", identifier);
+    let peer = tokio::spawn(mdns_peer::run_desktop_with_events(options, events));
+    let node_id = discover(target, "discover the peer", timeout, &mut seen, peer).await?;
+
+    let endpoint = protocols
+        .endpoint()
+        .ok_or_else(|| anyhow::anyhow!("The peer isn't running"))?;
+    let alpn = mdns_peer::messages::MESSAGE_ALPN;
+    let conn = protocols
+        .connect_within(&endpoint, node_id, alpn, Some(timeout))
+        .await?;
+    println!(
+        "{} answered in {:.1} ms",
+        target,
+        conn.rtt().as_secs_f64() * 1000.0
+    );
+    Ok(())
+}
+
+/// Wait up to `timeout` for `target`, an identifier or node ID, to be
+/// discovered by the peer `running` in the background
+///
+/// Fails with [`mdns_peer::deadline::TimedOut`] once `timeout` passes, or
+/// with the peer's error if it stopped first; `operation` is what the
+/// timeout error says was waited for.
+async fn discover(
+    target: &str,
+    operation: &'static str,
+    timeout: Duration,
+    seen: &mut watch::Receiver<SeenPeers>,
+    running: tokio::task::JoinHandle<Result<()>>,
+) -> Result<NodeId> {
+    let found = tokio::time::timeout(
+        timeout,
+        seen.wait_for(|seen| seen.running && seen.resolve(target).is_some()),
+    )
+    .await;
+    match found {
+        Ok(Ok(seen)) => Ok(seen.resolve(target).expect("checked by wait_for")),
+        Ok(Err(_)) => {
+            running.await??;
+            anyhow::bail!("The peer stopped before {} was found", target);
+        }
+        Err(_) => {
+            let timed_out = mdns_peer::deadline::TimedOut {
+                operation,
+                after: timeout,
+            };
+            Err(anyhow::Error::new(timed_out).context(format!("{} not found", target)))
+        }
+    }
+}
+
+/// An event sink printing every event to stdout as one line of JSON, the
+/// same the FFI event callback gets, for `--events`
+fn print_events() -> mdns_peer::EventSink {
+    Arc::new(|event| {
+        let event = mdns_peer::events::TimedEvent::now(event.clone());
+        println!("{}", event.to_json());
+    })
+}
+
+/// `mdns-peer recv <identifier> --accept-from <identifier-or-node-id,...>
+/// --out <dir> [--max-transfers <n>]`: run a peer that takes files from the
+/// listed peers
+///
+/// Files from anyone else are refused. Interrupted transfers are kept in
+/// `--out` and continue when the sender tries again. Beyond
+/// `--max-transfers` at once, senders wait; 0 (the default) doesn't limit.
+async fn run_recv(args: &[String]) -> Result<()> {
+    let (identifier, options) = peer_options(args)?;
+    let accepted: HashSet<String> = flag_value(args, "--accept-from")
+        .ok_or_else(|| anyhow::anyhow!("Missing --accept-from"))?
+        .split(',')
+        .map(|name| name.trim().to_string())
+        .collect();
+    let dir = flag_value(args, "--out").ok_or_else(|| anyhow::anyhow!("Missing --out"))?;
+
+    let (events, seen) = watch_peers();
+    options
+        .transfers
+        .set_receive(Some(mdns_peer::transfer::ReceiveOptions {
+            dir: dir.into(),
+            accept_from: Arc::new(move |node_id| {
+                let seen = seen.borrow();
+                accepted.contains(&node_id.to_string())
+                    || seen
+                        .peers
+                        .get(&node_id)
+                        .is_some_and(|user_data| accepted.contains(user_data))
+            }),
+        }));
+    options
+        .transfers
+        .set_progress(Some(Arc::new(print_progress)));
+    if let Some(max) = flag_value(args, "--max-transfers") {
+        let max: usize = max.parse()?;
+        options.transfers.set_max_active((max > 0).then_some(max));
+    }
+
+    env::set_var("PEER_ID", identifier);
+    mdns_peer::run_desktop_with_events(options, events).await
+}
+
+/// `mdns-peer doc <identifier> [--join <ticket>]`: create a shared
+/// document, or join one with its ticket, and edit it from stdin
+///
+/// Prints the ticket of a new document, then reads `set <key> <value>` and
+/// `get <key>` lines until stdin closes. Changes from other peers are logged
+/// as they sync.
+#[cfg(feature = "docs")]
+async fn run_doc(args: &[String]) -> Result<()> {
+    use tokio::io::AsyncBufReadExt;
+
+    let (identifier, options) = peer_options(args)?;
+    let docs = options.docs.clone();
+    let (events, mut seen) = watch_peers();
+    env::set_var("PEER_ID", identifier);
+    let peer = tokio::spawn(mdns_peer::run_desktop_with_events(options, events));
+    if seen.wait_for(|seen| seen.running).await.is_err() {
+        peer.await??;
+        anyhow::bail!("The peer stopped before it was running");
+    }
+
+    let id = match flag_value(args, "--join") {
+        Some(ticket) => docs.join(ticket).await?,
+        None => {
+            let share = docs.create().await?;
+            println!("Ticket: {}", share.ticket);
+            share.id
+        }
+    };
+    println!("Document {}", id);
+    println!("Commands: set <key> <value>, get <key>");
+
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    while let Some(line) = lines.next_line().await? {
+        let mut words = line.trim().splitn(3, ' ');
+        let done = match (words.next(), words.next(), words.next()) {
+            (Some("set"), Some(key), Some(value)) => {
+                docs.set(&id, key.as_bytes(), value.as_bytes().to_vec())
+                    .await
+            }
+            (Some("get"), Some(key), None) => {
+                docs.get(&id, key.as_bytes())
+                    .await
+                    .map(|value| match value {
+                        Some(value) => println!("{}", String::from_utf8_lossy(&value)),
+                        None => println!("(not set)"),
+                    })
+            }
+            (Some(""), None, None) => Ok(()),
+            _ => Err(anyhow::anyhow!("Commands: set <key> <value>, get <key>")),
+        };
+        if let Err(e) = done {
+            eprintln!("{:#}", e);
+        }
+    }
+    Ok(())
+}
+
+/// What `send` and `recv` know about other peers
+#[derive(Debug, Clone, Default)]
+struct SeenPeers {
+    /// Whether the peer is running, so files can be sent
+    running: bool,
+    /// User data of discovered peers
+    peers: HashMap<NodeId, String>,
+}
+
+impl SeenPeers {
+    /// The node ID `target` names, by node ID or discovered user data
+    fn resolve(&self, target: &str) -> Option<NodeId> {
+        if let Ok(node_id) = target.parse() {
+            return Some(node_id);
+        }
+        self.peers
+            .iter()
+            .find(|(_, user_data)| *user_data == target)
+            .map(|(node_id, _)| *node_id)
+    }
+}
+
+/// An event sink keeping [`SeenPeers`] up to date, and a watch on it
+fn watch_peers() -> (mdns_peer::EventSink, watch::Receiver<SeenPeers>) {
+    let (tx, rx) = watch::channel(SeenPeers::default());
+    let sink: mdns_peer::EventSink = Arc::new(move |event| {
+        tx.send_modify(|seen| match event {
+            mdns_peer::PeerEvent::StatusChanged { status, .. } => {
+                seen.running = *status != mdns_peer::PeerStatus::Stopped;
+            }
+            mdns_peer::PeerEvent::Discovered {
+                node_id,
+                user_data: Some(user_data),
+                ..
+            } => {
+                seen.peers.insert(*node_id, user_data.clone());
+            }
+            mdns_peer::PeerEvent::Expired { node_id, .. } => {
+                seen.peers.remove(node_id);
+            }
+            _ => {}
+        });
+    });
+    (sink, rx)
+}
+
+/// Width of the bar drawn by [`print_progress`], in characters
+const PROGRESS_BAR_WIDTH: usize = 30;
+
+/// Redraw the progress bar of a transfer on stderr
+fn print_progress(progress: &mdns_peer::transfer::Progress) {
+    const MIB: f64 = 1024.0 * 1024.0;
+    let fraction = match progress.size {
+        0 => 1.0,
+        size => progress.bytes as f64 / size as f64,
+    };
+    let filled = (fraction * PROGRESS_BAR_WIDTH as f64) as usize;
+    let arrow = match progress.direction {
+        mdns_peer::connections::Direction::Outbound => "->",
+        mdns_peer::connections::Direction::Inbound => "<-",
+    };
+    eprint!(
+        "\r{} {} {} [{}{}] {:3.0}% {:.1}/{:.1} MiB",
+        progress.name,
+        arrow,
+        progress.node_id.fmt_short(),
+        "#".repeat(filled),
+        "-".repeat(PROGRESS_BAR_WIDTH - filled),
+        fraction * 100.0,
+        progress.bytes as f64 / MIB,
+        progress.size as f64 / MIB,
+    );
+    if progress.bytes >= progress.size {
+        eprintln!();
+    }
+    let _ = std::io::stderr().flush();
+}
+
+/// `mdns-peer doctor`: check the local network for common discovery
+/// problems, exiting with 1 if any check fails
+async fn run_doctor() -> Result<()> {
+    let checks = tokio::task::spawn_blocking(mdns_peer::doctor::run_doctor).await?;
+    mdns_peer::doctor::print_report(&checks);
+
+    if checks
+        .iter()
+        .any(|check| check.status == mdns_peer::doctor::Status::Fail)
+    {
         std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// `mdns-peer sniff [--all] [--mdns-service <name>]`: print the mDNS
+/// queries and announcements seen on this machine until Ctrl+C
+///
+/// Only packets about the app's discovery service are shown, unless `--all`
+/// is given.
+async fn run_sniff(args: &[String]) -> Result<()> {
+    let service = match flag_value(args, "--mdns-service") {
+        Some(name) => name.to_string(),
+        None => mdns_peer::mdns::MdnsOptions::default().service_name,
     };
+    let all = args.iter().any(|arg| arg == "--all");
+    let started = Instant::now();
+
+    println!(
+        "Listening for mDNS traffic{}, Ctrl+C to stop",
+        if all {
+            String::new()
+        } else {
+            format!(" about _{}._udp.local", service)
+        }
+    );
+    let sniffing = mdns_peer::sniff::sniff(|from, packet| {
+        let elapsed = started.elapsed().as_secs_f64();
+        match packet {
+            Some(packet) if all || packet.mentions(&service) => {
+                println!("[{:>9.3}s] {} {}", elapsed, from, packet)
+            }
+            Some(_) => {}
+            None if all => println!("[{:>9.3}s] {} malformed packet", elapsed, from),
+            None => {}
+        }
+    });
+    tokio::select! {
+        result = sniffing => result,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    }
+}
+
+/// `mdns-peer fake [--count <n>] [--prefix <name>] [--rotate <secs>]`
+///
+/// `--rotate 0` keeps every fake peer online.
+async fn run_fake(args: &[String]) -> Result<()> {
+    let mut options = mdns_peer::fake::FakeOptions::default();
+
+    if let Some(count) = flag_value(args, "--count") {
+        options.count = count.parse()?;
+    }
+    if let Some(prefix) = flag_value(args, "--prefix") {
+        options.prefix = prefix.to_string();
+    }
+    if let Some(secs) = flag_value(args, "--rotate") {
+        let interval = parse_secs("--rotate", secs)?;
+        options.rotate_interval = (!interval.is_zero()).then_some(interval);
+    }
 
-    // Set as env var for the shared implementation
-    env::set_var("PEER_ID", &identifier);
+    mdns_peer::fake::run_fake(options).await
+}
+
+/// `mdns-peer soak [--hours <n>]`
+async fn run_soak(args: &[String]) -> Result<()> {
+    let mut options = mdns_peer::soak::SoakOptions::default();
+
+    if let Some(hours) = flag_value(args, "--hours") {
+        let duration = parse_secs("--hours", hours)?.checked_mul(60 * 60);
+        options.duration = duration
+            .filter(|duration| !duration.is_zero())
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Invalid --hours {:?}: not a positive number of hours",
+                    hours
+                )
+            })?;
+    }
+
+    mdns_peer::soak::run_soak(options).await.check()
+}
+
+/// Seconds given to `flag`, which may have a fraction but can't be
+/// negative, NaN or infinite
+fn parse_secs(flag: &str, secs: &str) -> Result<Duration> {
+    let parsed = secs
+        .parse()
+        .map_err(anyhow::Error::from)
+        .and_then(|secs| Ok(Duration::try_from_secs_f64(secs)?));
+    parsed.map_err(|e| anyhow::anyhow!("Invalid {} {:?}: {}", flag, secs, e))
+}
 
-    mdns_peer::run_desktop().await
+/// Value following `flag` in `args`, if present
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
 }
@@ -0,0 +1,318 @@
+//! Tuning the local network discovery protocol
+//!
+//! iroh's local discovery runs swarm-discovery with fixed settings: the
+//! `iroh.local.swarm` service, a 0.7 s cadence and a response rate of 2.5 Hz
+//! (its "interactive" preset). iroh 0.92 doesn't let those be changed, so
+//! when [`MdnsOptions`] differ from the defaults the endpoint gets
+//! [`TunedMdns`] instead: the same wire format (node ID as the instance
+//! name, user data in the `user-data` TXT attribute) on top of swarm-discovery
+//! with the requested settings.
+//!
+//! - A longer **cadence** makes every peer query and answer less often: less
+//!   multicast traffic on a crowded network, slower discovery and expiry.
+//! - A lower **response rate** caps how many answers the whole swarm sends
+//!   per second; with many peers each one is announced less often, and
+//!   responders wait longer before answering.
+//! - A different **service name** puts the peer in a separate swarm: it only
+//!   sees peers using the same name.
+//!
+//! [`TunedMdns`] also runs with default settings while the host manages
+//! peer-to-peer interfaces, see [`crate::p2p`].
+
+use std::collections::{BTreeSet, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use iroh::discovery::{
+    mdns::NAME, Discovery, DiscoveryContext, DiscoveryError, DiscoveryEvent, DiscoveryItem,
+    IntoDiscovery, IntoDiscoveryError, NodeData, NodeInfo,
+};
+use iroh::NodeId;
+use n0_future::{boxed::BoxStream, stream, StreamExt};
+use swarm_discovery::{Discoverer, DropGuard, IpClass, Peer};
+use tokio::sync::broadcast;
+use tracing::{debug, warn};
+
+use crate::p2p::PeerToPeer;
+
+/// TXT attribute iroh stores user data under
+const USER_DATA_ATTRIBUTE: &str = "user-data";
+
+/// How long [`TunedMdns`] waits for a node that hasn't been seen yet
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Discovery events buffered per subscriber
+const EVENT_CAPACITY: usize = 64;
+
+/// Longest service name accepted, which keeps the record name well within
+/// DNS limits
+const MAX_SERVICE_NAME_LEN: usize = 63;
+
+/// swarm-discovery parameters, see the [module docs](self)
+#[derive(Debug, Clone, PartialEq)]
+pub struct MdnsOptions {
+    /// Advertised as `_<service_name>._udp.local.`
+    pub service_name: String,
+    /// Target time for a new peer to discover the swarm (τ)
+    pub cadence: Duration,
+    /// Responses per second the whole swarm aims for (φ)
+    pub response_rate: f32,
+}
+
+impl Default for MdnsOptions {
+    /// What iroh's built-in local discovery uses
+    fn default() -> Self {
+        Self {
+            service_name: "iroh.local.swarm".to_string(),
+            cadence: Duration::from_millis(700),
+            response_rate: 2.5,
+        }
+    }
+}
+
+impl MdnsOptions {
+    /// Whether iroh's built-in discovery can be used as is
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Check the settings are ones swarm-discovery can work with
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let name = &self.service_name;
+        if name.is_empty() || name.len() > MAX_SERVICE_NAME_LEN {
+            anyhow::bail!(
+                "mDNS service name must be 1 to {} bytes, got {:?}",
+                MAX_SERVICE_NAME_LEN,
+                name
+            );
+        }
+        if !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        {
+            anyhow::bail!(
+                "mDNS service name may only contain letters, digits, '-' and '.', got {:?}",
+                name
+            );
+        }
+        if self.cadence.is_zero() {
+            anyhow::bail!("mDNS cadence must be positive");
+        }
+        // swarm-discovery's rate limiting needs at least one response per cycle
+        let responses = self.responses_per_cadence_f32();
+        if !responses.is_finite() || responses <= 1.0 {
+            anyhow::bail!(
+                "mDNS cadence times response rate must be above 1, got {:?} x {} Hz",
+                self.cadence,
+                self.response_rate
+            );
+        }
+        Ok(())
+    }
+
+    /// Responses the swarm shares per cadence interval, rounded up
+    pub fn responses_per_cadence(&self) -> u32 {
+        self.responses_per_cadence_f32().ceil() as u32
+    }
+
+    fn responses_per_cadence_f32(&self) -> f32 {
+        self.cadence.as_secs_f32() * self.response_rate
+    }
+}
+
+impl IntoDiscovery for MdnsOptions {
+    fn into_discovery(
+        self,
+        context: &DiscoveryContext,
+    ) -> Result<impl Discovery, IntoDiscoveryError> {
+        TunedMdns::spawn(context.node_id(), &self)
+    }
+}
+
+/// [`MdnsOptions`] with the peer-to-peer interfaces whose addresses
+/// [`TunedMdns`] filters
+#[derive(Debug)]
+pub(crate) struct WithPeerToPeer(pub MdnsOptions, pub PeerToPeer);
+
+impl IntoDiscovery for WithPeerToPeer {
+    fn into_discovery(
+        self,
+        context: &DiscoveryContext,
+    ) -> Result<impl Discovery, IntoDiscoveryError> {
+        TunedMdns::spawn_with(context.node_id(), &self.0, self.1)
+    }
+}
+
+/// Local network discovery with [`MdnsOptions`], interoperable with iroh's
+/// built-in one when the service name matches
+pub struct TunedMdns {
+    discoverer: DropGuard,
+    peers: Arc<Mutex<HashMap<NodeId, DiscoveryItem>>>,
+    events: broadcast::Sender<DiscoveryEvent>,
+    p2p: PeerToPeer,
+}
+
+impl std::fmt::Debug for TunedMdns {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TunedMdns")
+            .field("peers", &self.peers.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl TunedMdns {
+    /// Start discovering as `node_id`; must be called within a tokio runtime
+    pub fn spawn(node_id: NodeId, options: &MdnsOptions) -> Result<Self, IntoDiscoveryError> {
+        Self::spawn_with(node_id, options, PeerToPeer::default())
+    }
+
+    /// Like [`TunedMdns::spawn`], leaving out addresses on the peer-to-peer
+    /// interfaces `p2p` excludes, see [`crate::p2p`]
+    pub fn spawn_with(
+        node_id: NodeId,
+        options: &MdnsOptions,
+        p2p: PeerToPeer,
+    ) -> Result<Self, IntoDiscoveryError> {
+        let peers: Arc<Mutex<HashMap<NodeId, DiscoveryItem>>> = Default::default();
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+
+        let callback = {
+            let peers = peers.clone();
+            let events = events.clone();
+            let p2p = p2p.clone();
+            move |instance: &str, peer: &Peer| {
+                let Ok(remote) = instance.parse::<NodeId>() else {
+                    debug!("Ignoring mDNS instance {:?}, not a node ID", instance);
+                    return;
+                };
+                if remote == node_id {
+                    return;
+                }
+                let mut peers = peers.lock().unwrap();
+                let event = if peer.is_expiry() {
+                    peers
+                        .remove(&remote)
+                        .map(|_| DiscoveryEvent::Expired(remote))
+                } else {
+                    let item = discovery_item(remote, peer, &p2p);
+                    let changed = peers
+                        .get(&remote)
+                        .is_none_or(|known| known.node_info().data != item.node_info().data);
+                    changed.then(|| {
+                        peers.insert(remote, item.clone());
+                        DiscoveryEvent::Discovered(item)
+                    })
+                };
+                if let Some(event) = event {
+                    // No receivers just means nobody subscribed yet
+                    let _ = events.send(event);
+                }
+            }
+        };
+
+        let instance = data_encoding::BASE32_NOPAD
+            .encode(node_id.as_bytes())
+            .to_ascii_lowercase();
+        let discoverer = Discoverer::new(options.service_name.clone(), instance)
+            .with_cadence(options.cadence)
+            .with_response_rate(options.response_rate)
+            .with_callback(callback)
+            .with_ip_class(IpClass::Auto)
+            .spawn(&tokio::runtime::Handle::current())
+            .map_err(|e| IntoDiscoveryError::from_err("mdns", e))?;
+
+        Ok(Self {
+            discoverer,
+            peers,
+            events,
+            p2p,
+        })
+    }
+}
+
+impl Discovery for TunedMdns {
+    fn publish(&self, data: &NodeData) {
+        self.discoverer.remove_all();
+        for (port, addrs) in addrs_by_port(&self.p2p.announced(data.direct_addresses())) {
+            self.discoverer.add(port, addrs);
+        }
+        let user_data = data.user_data().map(|d| d.to_string());
+        if let Err(e) = self
+            .discoverer
+            .set_txt_attribute(USER_DATA_ATTRIBUTE.to_string(), user_data)
+        {
+            warn!("Failed to advertise user data over mDNS: {}", e);
+        }
+    }
+
+    fn resolve(&self, node_id: NodeId) -> Option<BoxStream<Result<DiscoveryItem, DiscoveryError>>> {
+        if let Some(item) = self.peers.lock().unwrap().get(&node_id).cloned() {
+            return Some(Box::pin(stream::once(Ok(item))));
+        }
+
+        // Wait a while for the node to announce itself
+        let mut events = self.events.subscribe();
+        let found = async move {
+            let wait = async {
+                loop {
+                    match events.recv().await {
+                        Ok(DiscoveryEvent::Discovered(item)) if item.node_id() == node_id => {
+                            return Some(item)
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return None,
+                        _ => {}
+                    }
+                }
+            };
+            tokio::time::timeout(RESOLVE_TIMEOUT, wait)
+                .await
+                .ok()
+                .flatten()
+        };
+        Some(Box::pin(
+            stream::once_future(found).filter_map(|item| item.map(Ok)),
+        ))
+    }
+
+    fn subscribe(&self) -> Option<BoxStream<DiscoveryEvent>> {
+        let events = self.events.subscribe();
+        Some(Box::pin(stream::unfold(events, |mut events| async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => return Some((event, events)),
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!("mDNS subscriber fell behind, skipped {} events", missed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })))
+    }
+}
+
+/// What a swarm-discovery peer record says about `node_id`, without the
+/// addresses `p2p` rules out for dialing
+fn discovery_item(node_id: NodeId, peer: &Peer, p2p: &PeerToPeer) -> DiscoveryItem {
+    let direct_addrs: BTreeSet<SocketAddr> = peer
+        .addrs()
+        .iter()
+        .map(|(ip, port)| SocketAddr::new(*ip, *port))
+        .collect();
+    let user_data = match peer.txt_attribute(USER_DATA_ATTRIBUTE) {
+        Some(Some(data)) => data.parse().ok(),
+        _ => None,
+    };
+    let info = NodeInfo::new(node_id)
+        .with_direct_addresses(p2p.dialable(&direct_addrs))
+        .with_user_data(user_data);
+    DiscoveryItem::new(info, NAME, None)
+}
+
+fn addrs_by_port(addrs: &BTreeSet<SocketAddr>) -> HashMap<u16, Vec<IpAddr>> {
+    let mut by_port: HashMap<u16, Vec<IpAddr>> = HashMap::new();
+    for addr in addrs {
+        by_port.entry(addr.port()).or_default().push(addr.ip());
+    }
+    by_port
+}
@@ -0,0 +1,984 @@
+//! Whole messages to other peers, buffered while they are unreachable
+//!
+//! A message is one stream on [`MESSAGE_ALPN`]: after the version byte (see
+//! [`crate::version`]), the sender writes a [`MessageFrame`] with the
+//! [`MessageId`] and the payload and finishes, the receiver hands it to the
+//! [`MessageHandler`] and answers with a [`MessageReply::Ack`] frame as the
+//! delivery receipt. Going through [`Protocols`] means messages share the
+//! cached connections, transfer limits and stats with the host's own
+//! protocols.
+//!
+//! Each message's progress is reported by ID: [`PeerEvent::MessageSent`]
+//! once its stream opens, then [`PeerEvent::MessageDelivered`] when the
+//! receipt arrives or [`PeerEvent::MessageFailed`] with the reason. A message
+//! sent again because its receipt was lost is acknowledged but not handed
+//! to the receiver's handler twice.
+//!
+//! [`Messages::send`] never fails because the peer is away. Messages wait in
+//! a queue per peer (at most [`MAX_QUEUED_MESSAGES`]) and are sent in order
+//! as soon as the peer is reachable: when the peer runs, when the target is
+//! discovered, and whenever a connection to it opens. A message that fails
+//! in transit stays at the head of its queue for the next attempt; one the
+//! receiver rejects, e.g. for being too large, is dropped. The bytes queued
+//! for all peers together are capped by the
+//! [`MemoryBudget`](crate::budget::MemoryBudget), which either refuses
+//! further messages or fails the longest-queued ones to make room.
+//!
+//! What a peer can make this one receive is bounded by [`MessageLimits`]:
+//! messages over the size limit are rejected, and a peer sending faster
+//! than the rate limit gets [`MessageReply::Busy`] instead of the receipt,
+//! which makes a sender running this library back off and retry. Either is
+//! reported as a [`PeerEvent::RateLimited`]. The defaults are generous for a
+//! chat but stop a misbehaving peer from flooding the host.
+//!
+//! [`Messages::send_sealed`] additionally seals the payload with the
+//! [`PairKey`] of the two peers, for payloads the receiver stores or passes
+//! on. The receiver opens it and hands it to the sealed message handler; one
+//! that doesn't open is rejected.
+//!
+//! [`PeerEvent::MessageSent`]: crate::PeerEvent::MessageSent
+//! [`PeerEvent::MessageDelivered`]: crate::PeerEvent::MessageDelivered
+//! [`PeerEvent::MessageFailed`]: crate::PeerEvent::MessageFailed
+//! [`PeerEvent::RateLimited`]: crate::PeerEvent::RateLimited
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use iroh::NodeId;
+use tokio::sync::oneshot;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::budget::{DropPolicy, MemoryBudget, Overflow};
+use crate::deadline;
+use crate::events::{EventSink, MemoryPool, MessageLimit, PeerEvent};
+use crate::protocol::{self, MessageFrame, MessageReply, MESSAGE_OVERHEAD};
+use crate::protocols::{Protocols, StreamHandler, StreamId};
+use crate::seal::{PairKey, SEAL_OVERHEAD};
+use crate::supervise;
+use crate::version;
+
+/// ALPN messages are exchanged on
+pub const MESSAGE_ALPN: &[u8] = b"mdns-peer/message";
+
+/// Largest message payload accepted, in bytes
+pub const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// Messages accepted from one peer per second by default
+pub const DEFAULT_MESSAGE_RATE: u32 = 20;
+
+/// Messages one peer may send back to back by default
+pub const DEFAULT_MESSAGE_BURST: u32 = 50;
+
+/// Messages waiting per peer before [`Messages::send`] refuses more
+pub const MAX_QUEUED_MESSAGES: usize = 256;
+
+/// How long one message may take to be acknowledged before it is retried
+/// on the next attempt
+pub const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a sender waits after a [`MessageReply::Busy`] before sending
+/// again
+const BUSY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Shortest time between two [`PeerEvent::RateLimited`] about one peer
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Peers whose rate is tracked before idle ones are forgotten
+const TRACKED_SENDERS: usize = 1024;
+
+/// IDs of recently received messages remembered to drop duplicates
+const RECENT_IDS: usize = 1024;
+
+/// Identifies one message in events and to the receiver
+pub type MessageId = Uuid;
+
+/// What one peer may send this one, see [`Messages::set_limits`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageLimits {
+    /// Largest payload accepted, in bytes; never more than
+    /// [`MAX_MESSAGE_SIZE`]
+    pub max_size: usize,
+    /// Messages accepted from one peer per second on average, `None` for
+    /// no limit
+    pub per_second: Option<u32>,
+    /// Messages one peer may send back to back before `per_second` applies
+    pub burst: u32,
+}
+
+impl Default for MessageLimits {
+    fn default() -> Self {
+        Self {
+            max_size: MAX_MESSAGE_SIZE,
+            per_second: Some(DEFAULT_MESSAGE_RATE),
+            burst: DEFAULT_MESSAGE_BURST,
+        }
+    }
+}
+
+/// Receives messages from other peers
+///
+/// Called on runtime threads, so it should return quickly.
+pub trait MessageHandler: Send + Sync + 'static {
+    fn on_message(&self, node_id: NodeId, id: MessageId, data: &[u8]);
+}
+
+/// Outbound queues and inbound message assembly
+///
+/// Clones share the same queues. Sending works before the peer runs; the
+/// queues are flushed once [`Messages::attach`] connects them to the
+/// protocols the peer serves.
+#[derive(Clone, Default)]
+pub struct Messages {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    queues: Mutex<HashMap<NodeId, VecDeque<Queued>>>,
+    /// Peers a flush is running for
+    flushing: Mutex<HashSet<NodeId>>,
+    protocols: Mutex<Option<Protocols>>,
+    handler: Mutex<Option<Arc<dyn MessageHandler>>>,
+    sealed_handler: Mutex<Option<Arc<dyn MessageHandler>>>,
+    streams: Mutex<HashMap<StreamId, StreamState>>,
+    /// Received message IDs, oldest first
+    recent: Mutex<VecDeque<MessageId>>,
+    limits: Mutex<MessageLimits>,
+    /// Rate and refusals of each peer sending to this one
+    senders: Mutex<HashMap<NodeId, Allowance>>,
+    budget: Mutex<MemoryBudget>,
+    /// Messages refused or dropped over the budget, not reported yet
+    overflow: Mutex<Overflow>,
+    /// Waiting for their message to be delivered or given up on
+    settled: Mutex<HashMap<MessageId, OnSettled>>,
+}
+
+/// Called once with the event that ended a message, see
+/// [`Messages::send_then`]
+pub type OnSettled = Box<dyn FnOnce(&PeerEvent) + Send>;
+
+/// Token bucket of one sending peer, and refusals not yet reported
+struct Allowance {
+    tokens: f64,
+    refilled: Instant,
+    over_rate: Refusals,
+    over_size: Refusals,
+}
+
+#[derive(Default)]
+struct Refusals {
+    count: u64,
+    reported: Option<Instant>,
+}
+
+impl Allowance {
+    fn new(burst: u32) -> Self {
+        Self {
+            tokens: burst as f64,
+            refilled: Instant::now(),
+            over_rate: Refusals::default(),
+            over_size: Refusals::default(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Queued {
+    id: MessageId,
+    data: Vec<u8>,
+    /// Seal `data` for the receiver when sending it
+    sealed: bool,
+    queued: Instant,
+    /// How long it may take to be delivered, from `queued`
+    timeout: Option<Duration>,
+}
+
+impl Queued {
+    /// Time left until its timeout, `None` without one
+    fn remaining(&self, now: Instant) -> Option<Duration> {
+        let deadline = self.queued + self.timeout?;
+        Some(deadline.saturating_duration_since(now))
+    }
+
+    fn expired(&self, now: Instant) -> bool {
+        self.remaining(now).is_some_and(|left| left.is_zero())
+    }
+}
+
+/// The event reporting that message `id` missed its timeout of `after`
+fn timed_out(id: MessageId, node_id: NodeId, after: Duration) -> PeerEvent {
+    let timed_out = deadline::TimedOut {
+        operation: "deliver the message",
+        after,
+    };
+    PeerEvent::MessageFailed {
+        id,
+        node_id,
+        reason: timed_out.to_string(),
+        retrying: false,
+        timed_out: true,
+    }
+}
+
+enum StreamState {
+    Receiving {
+        node_id: NodeId,
+        data: Vec<u8>,
+        /// The limit the message went over, if any
+        refused: Option<MessageLimit>,
+    },
+    Sending {
+        id: MessageId,
+        reply: Vec<u8>,
+        done: oneshot::Sender<Result<MessageReply, String>>,
+    },
+}
+
+impl std::fmt::Debug for Messages {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Messages")
+            .field("queued", &self.queue_depths())
+            .finish()
+    }
+}
+
+impl Messages {
+    /// Queue `data` for `node_id` and try to send it right away, returning
+    /// the ID its progress is reported under
+    ///
+    /// Fails only if the message is too large, the peer's queue is full, or
+    /// the message doesn't fit the memory budget.
+    pub fn send(&self, node_id: NodeId, data: Vec<u8>) -> anyhow::Result<MessageId> {
+        self.send_within(node_id, data, None)
+    }
+
+    /// Like [`Messages::send`], giving up on the message if it isn't
+    /// delivered within `timeout`
+    ///
+    /// A message that misses its timeout, queued or in transit, is dropped
+    /// and reported as [`PeerEvent::MessageFailed`] with `timed_out` set.
+    /// `None` keeps it queued until it is delivered or rejected.
+    ///
+    /// [`PeerEvent::MessageFailed`]: crate::PeerEvent::MessageFailed
+    pub fn send_within(
+        &self,
+        node_id: NodeId,
+        data: Vec<u8>,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<MessageId> {
+        self.enqueue(node_id, data, false, timeout, None)
+    }
+
+    /// Like [`Messages::send`], sealing `data` so only `node_id` and this
+    /// node can open it, see [`crate::seal`]
+    pub fn send_sealed(&self, node_id: NodeId, data: Vec<u8>) -> anyhow::Result<MessageId> {
+        self.send_sealed_within(node_id, data, None)
+    }
+
+    /// Like [`Messages::send_sealed`], with a timeout as in
+    /// [`Messages::send_within`]
+    pub fn send_sealed_within(
+        &self,
+        node_id: NodeId,
+        data: Vec<u8>,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<MessageId> {
+        self.enqueue(node_id, data, true, timeout, None)
+    }
+
+    /// Like [`Messages::send_within`], or [`Messages::send_sealed_within`]
+    /// if `sealed`, calling `settled` once the message is delivered or
+    /// given up on
+    ///
+    /// `settled` gets the [`PeerEvent::MessageDelivered`] or final
+    /// [`PeerEvent::MessageFailed`] reporting it, right after the event
+    /// sink. Without a timeout, that can take until the peer shows up.
+    ///
+    /// [`PeerEvent::MessageDelivered`]: crate::PeerEvent::MessageDelivered
+    /// [`PeerEvent::MessageFailed`]: crate::PeerEvent::MessageFailed
+    pub fn send_then(
+        &self,
+        node_id: NodeId,
+        data: Vec<u8>,
+        sealed: bool,
+        timeout: Option<Duration>,
+        settled: impl FnOnce(&PeerEvent) + Send + 'static,
+    ) -> anyhow::Result<MessageId> {
+        self.enqueue(node_id, data, sealed, timeout, Some(Box::new(settled)))
+    }
+
+    fn enqueue(
+        &self,
+        node_id: NodeId,
+        data: Vec<u8>,
+        sealed: bool,
+        timeout: Option<Duration>,
+        settled: Option<OnSettled>,
+    ) -> anyhow::Result<MessageId> {
+        if sealed {
+            anyhow::ensure!(
+                data.len() + SEAL_OVERHEAD <= MAX_MESSAGE_SIZE,
+                "Sealed message is larger than {} bytes",
+                MAX_MESSAGE_SIZE - SEAL_OVERHEAD
+            );
+        } else {
+            anyhow::ensure!(
+                data.len() <= MAX_MESSAGE_SIZE,
+                "Message is larger than {} bytes",
+                MAX_MESSAGE_SIZE
+            );
+        }
+        let id = Uuid::new_v4();
+        let budget = *self.inner.budget.lock().unwrap();
+        let in_flight = self.inner.flushing.lock().unwrap().clone();
+        let dropped = {
+            let mut queues = self.inner.queues.lock().unwrap();
+            let depth = queues.get(&node_id).map_or(0, VecDeque::len);
+            anyhow::ensure!(
+                depth < MAX_QUEUED_MESSAGES,
+                "{} messages already queued for {}",
+                MAX_QUEUED_MESSAGES,
+                node_id.fmt_short()
+            );
+            let dropped = make_room(&mut queues, &in_flight, data.len(), &budget);
+            if dropped.is_some() {
+                if let Some(settled) = settled {
+                    // Listed before a flush can see the message
+                    self.inner.settled.lock().unwrap().insert(id, settled);
+                }
+                queues.entry(node_id).or_default().push_back(Queued {
+                    id,
+                    data,
+                    sealed,
+                    queued: Instant::now(),
+                    timeout,
+                });
+            }
+            dropped
+        };
+
+        let events = self
+            .inner
+            .protocols
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(Protocols::events);
+        let Some(dropped) = dropped else {
+            self.report_overflow(events.as_ref(), 1);
+            anyhow::bail!(
+                "Queued messages would go over the memory budget of {} bytes",
+                budget.queued_message_bytes
+            );
+        };
+        for (node_id, message) in &dropped {
+            let failed = PeerEvent::MessageFailed {
+                id: message.id,
+                node_id: *node_id,
+                reason: "Dropped to stay within the memory budget".to_string(),
+                retrying: false,
+                timed_out: false,
+            };
+            self.settle(events.as_ref(), &failed);
+        }
+        self.report_overflow(events.as_ref(), dropped.len() as u64);
+        if let (Some(timeout), Some(events)) = (timeout, events) {
+            // Before the runtime exists, the next flush drops it instead
+            if tokio::runtime::Handle::try_current().is_ok() {
+                let messages = self.clone();
+                let reported = events.clone();
+                supervise::spawn_supervised("message_timeout", events, async move {
+                    tokio::time::sleep(timeout).await;
+                    messages.expire(node_id, &reported);
+                });
+            }
+        }
+        self.flush(node_id);
+        Ok(id)
+    }
+
+    /// Drop and report the messages for `node_id` that missed their
+    /// timeout
+    ///
+    /// One in transit is left to [`Messages::run_flush`], whose attempt
+    /// ends by its timeout.
+    fn expire(&self, node_id: NodeId, events: &EventSink) {
+        let now = Instant::now();
+        let in_flight = self.inner.flushing.lock().unwrap().contains(&node_id);
+        let expired = {
+            let mut queues = self.inner.queues.lock().unwrap();
+            let Some(queue) = queues.get_mut(&node_id) else {
+                return;
+            };
+            let mut expired = Vec::new();
+            let mut kept = VecDeque::with_capacity(queue.len());
+            for (index, queued) in std::mem::take(queue).into_iter().enumerate() {
+                if (index > 0 || !in_flight) && queued.expired(now) {
+                    expired.push(queued);
+                } else {
+                    kept.push_back(queued);
+                }
+            }
+            *queue = kept;
+            if queue.is_empty() {
+                queues.remove(&node_id);
+            }
+            expired
+        };
+        for queued in expired {
+            let timeout = queued.timeout.unwrap_or_default();
+            self.settle(Some(events), &timed_out(queued.id, node_id, timeout));
+        }
+    }
+
+    /// Report the event that ended a message, then hand it to the callback
+    /// waiting for it, if any
+    fn settle(&self, events: Option<&EventSink>, event: &PeerEvent) {
+        if let Some(events) = events {
+            events(event);
+        }
+        let (PeerEvent::MessageDelivered { id, .. } | PeerEvent::MessageFailed { id, .. }) = event
+        else {
+            return;
+        };
+        let settled = self.inner.settled.lock().unwrap().remove(id);
+        if let Some(settled) = settled {
+            settled(event);
+        }
+    }
+
+    /// Count `dropped` messages over the budget, reporting them at most
+    /// once a second
+    fn report_overflow(&self, events: Option<&EventSink>, dropped: u64) {
+        let due = {
+            let mut overflow = self.inner.overflow.lock().unwrap();
+            overflow.add(dropped);
+            overflow.due()
+        };
+        if let (Some(events), Some(dropped)) = (events, due) {
+            events(&PeerEvent::OverBudget {
+                pool: MemoryPool::QueuedMessages,
+                limit: self.inner.budget.lock().unwrap().queued_message_bytes as u64,
+                dropped,
+            });
+        }
+    }
+
+    /// Cap the bytes queued for all peers together
+    ///
+    /// Applies to messages queued from now on; what's already queued stays.
+    pub fn set_budget(&self, budget: &MemoryBudget) {
+        *self.inner.budget.lock().unwrap() = *budget;
+    }
+
+    /// Payload bytes waiting to be sent, across all peers
+    pub fn queued_bytes(&self) -> usize {
+        let queues = self.inner.queues.lock().unwrap();
+        queues
+            .values()
+            .flatten()
+            .map(|queued| queued.data.len())
+            .sum()
+    }
+
+    /// Messages waiting per peer, only listing peers with some
+    pub fn queue_depths(&self) -> BTreeMap<NodeId, usize> {
+        let queues = self.inner.queues.lock().unwrap();
+        queues
+            .iter()
+            .filter(|(_, queue)| !queue.is_empty())
+            .map(|(node_id, queue)| (*node_id, queue.len()))
+            .collect()
+    }
+
+    /// Hand inbound messages to `handler`, or drop them with `None`
+    pub fn set_handler(&self, handler: Option<Arc<dyn MessageHandler>>) {
+        *self.inner.handler.lock().unwrap() = handler;
+    }
+
+    /// Hand inbound sealed messages to `handler` once opened, or drop them
+    /// with `None`
+    pub fn set_sealed_handler(&self, handler: Option<Arc<dyn MessageHandler>>) {
+        *self.inner.sealed_handler.lock().unwrap() = handler;
+    }
+
+    /// Limit what each peer may send this one
+    ///
+    /// Applies to messages arriving from now on.
+    pub fn set_limits(&self, limits: MessageLimits) {
+        *self.inner.limits.lock().unwrap() = limits;
+    }
+
+    pub fn limits(&self) -> MessageLimits {
+        *self.inner.limits.lock().unwrap()
+    }
+
+    /// Serve [`MESSAGE_ALPN`] on `protocols` and send queued messages
+    /// through it
+    ///
+    /// Must be called before the router is spawned.
+    pub fn attach(&self, protocols: &Protocols) {
+        protocols.register(MESSAGE_ALPN, Arc::new(self.clone()));
+        protocols.set_compatibility(version::MESSAGES);
+        *self.inner.protocols.lock().unwrap() = Some(protocols.clone());
+    }
+
+    /// Flush the queue of a peer that was just discovered, came back or
+    /// connected
+    pub fn peer_event(&self, event: &PeerEvent) {
+        if let PeerEvent::Discovered { node_id, .. }
+        | PeerEvent::Flapped { node_id, .. }
+        | PeerEvent::Connected { node_id, .. } = event
+        {
+            self.flush(*node_id);
+        }
+    }
+
+    /// Try to send everything queued for every peer
+    pub fn flush_all(&self) {
+        let node_ids: Vec<_> = self.queue_depths().into_keys().collect();
+        for node_id in node_ids {
+            self.flush(node_id);
+        }
+    }
+
+    /// Send what is queued for `node_id` in the background, unless a flush
+    /// is already running or the peer isn't
+    fn flush(&self, node_id: NodeId) {
+        let Some(protocols) = self.inner.protocols.lock().unwrap().clone() else {
+            return;
+        };
+        if protocols.endpoint().is_none() {
+            return;
+        }
+        self.expire(node_id, &protocols.events());
+        if !self.has_queued(node_id) {
+            return;
+        }
+        if !self.inner.flushing.lock().unwrap().insert(node_id) {
+            return;
+        }
+        let messages = self.clone();
+        supervise::spawn_supervised("messages", protocols.events(), async move {
+            messages.run_flush(&protocols, node_id).await;
+            messages.inner.flushing.lock().unwrap().remove(&node_id);
+            // Messages queued while the last send was finishing
+            if messages.has_queued(node_id) && protocols.is_connected(node_id, MESSAGE_ALPN) {
+                messages.flush(node_id);
+            }
+        });
+    }
+
+    fn has_queued(&self, node_id: NodeId) -> bool {
+        let queues = self.inner.queues.lock().unwrap();
+        queues.get(&node_id).is_some_and(|queue| !queue.is_empty())
+    }
+
+    fn front(&self, node_id: NodeId) -> Option<Queued> {
+        let queues = self.inner.queues.lock().unwrap();
+        queues.get(&node_id)?.front().cloned()
+    }
+
+    /// Take message `id` out of `node_id`'s queue, unless the budget
+    /// already did
+    fn remove(&self, node_id: NodeId, id: MessageId) {
+        let mut queues = self.inner.queues.lock().unwrap();
+        if let Some(queue) = queues.get_mut(&node_id) {
+            queue.retain(|queued| queued.id != id);
+            if queue.is_empty() {
+                queues.remove(&node_id);
+            }
+        }
+    }
+
+    /// Send queued messages one at a time, in order, until the queue is
+    /// empty or one fails in transit
+    async fn run_flush(&self, protocols: &Protocols, node_id: NodeId) {
+        let events = protocols.events();
+        while let Some(message) = self.front(node_id) {
+            let id = message.id;
+            let timeout = message.timeout.unwrap_or_default();
+            let now = Instant::now();
+            if message.expired(now) {
+                self.remove(node_id, id);
+                self.settle(Some(&events), &timed_out(id, node_id, timeout));
+                continue;
+            }
+            // An attempt ends by the message's timeout at the latest
+            let left = message.remaining(now);
+            let attempt = left.map_or(SEND_TIMEOUT, |left| left.min(SEND_TIMEOUT));
+            let gives_up = left.map(|left| now + left);
+            match self.deliver(protocols, node_id, message, attempt).await {
+                Ok(MessageReply::Ack) => {
+                    self.remove(node_id, id);
+                    self.settle(Some(&events), &PeerEvent::MessageDelivered { id, node_id });
+                }
+                Ok(MessageReply::Busy) => {
+                    events(&PeerEvent::MessageFailed {
+                        id,
+                        node_id,
+                        reason: "Rate limited by the peer".to_string(),
+                        retrying: true,
+                        timed_out: false,
+                    });
+                    tokio::time::sleep(BUSY_BACKOFF).await;
+                }
+                Ok(MessageReply::Rejected) => {
+                    self.remove(node_id, id);
+                    let rejected = PeerEvent::MessageFailed {
+                        id,
+                        node_id,
+                        reason: "Rejected by the peer".to_string(),
+                        retrying: false,
+                        timed_out: false,
+                    };
+                    self.settle(Some(&events), &rejected);
+                }
+                Err(_) if gives_up.is_some_and(|gives_up| Instant::now() >= gives_up) => {
+                    self.remove(node_id, id);
+                    self.settle(Some(&events), &timed_out(id, node_id, timeout));
+                }
+                Err(e) => {
+                    events(&PeerEvent::MessageFailed {
+                        id,
+                        node_id,
+                        reason: format!("{:#}", e),
+                        retrying: true,
+                        timed_out: false,
+                    });
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Send one message, resolving to whether the receiver took it
+    async fn deliver(
+        &self,
+        protocols: &Protocols,
+        node_id: NodeId,
+        message: Queued,
+        timeout: Duration,
+    ) -> anyhow::Result<MessageReply> {
+        let endpoint = protocols
+            .endpoint()
+            .ok_or_else(|| anyhow::anyhow!("The peer isn't running"))?;
+        let (done, result) = oneshot::channel();
+        let stream = {
+            // Held until the state is in place, so callbacks always find it
+            let mut streams = self.inner.streams.lock().unwrap();
+            let stream = protocols.open_stream(&endpoint, node_id, MESSAGE_ALPN)?;
+            streams.insert(
+                stream,
+                StreamState::Sending {
+                    id: message.id,
+                    reply: Vec::new(),
+                    done,
+                },
+            );
+            stream
+        };
+        let payload = if message.sealed {
+            let key = PairKey::new(endpoint.secret_key(), &node_id);
+            key.seal(&message.data)
+        } else {
+            message.data
+        };
+        let frame = MessageFrame {
+            id: message.id,
+            sealed: message.sealed,
+            payload,
+        };
+        protocols.write(stream, protocol::encode(&frame));
+        protocols.finish(stream);
+
+        let result = tokio::time::timeout(timeout, result)
+            .await
+            .map_err(|_| anyhow::anyhow!("Timed out"))?
+            .map_err(|_| anyhow::anyhow!("Stream dropped"))?;
+        result.map_err(anyhow::Error::msg)
+    }
+
+    /// Take one message from `node_id` out of its rate allowance, or
+    /// `false` if it has none left
+    fn within_rate(&self, node_id: NodeId) -> bool {
+        let limits = self.limits();
+        let Some(per_second) = limits.per_second else {
+            return true;
+        };
+        let mut senders = self.inner.senders.lock().unwrap();
+        if senders.len() >= TRACKED_SENDERS && !senders.contains_key(&node_id) {
+            // Forget peers whose allowance has refilled since they last sent
+            let refill = Duration::from_secs_f64(limits.burst as f64 / per_second.max(1) as f64);
+            senders.retain(|_, sender| sender.refilled.elapsed() < refill);
+        }
+        let sender = senders
+            .entry(node_id)
+            .or_insert_with(|| Allowance::new(limits.burst));
+        let now = Instant::now();
+        let elapsed = now.duration_since(sender.refilled).as_secs_f64();
+        sender.tokens =
+            (sender.tokens + elapsed * per_second as f64).min(limits.burst.max(1) as f64);
+        sender.refilled = now;
+        if sender.tokens < 1.0 {
+            return false;
+        }
+        sender.tokens -= 1.0;
+        true
+    }
+
+    /// Count a message refused from `node_id`, reporting the refusals for
+    /// each limit at most every [`REPORT_INTERVAL`]
+    fn refuse(&self, protocols: &Protocols, node_id: NodeId, limit: MessageLimit) {
+        let refused = {
+            let mut senders = self.inner.senders.lock().unwrap();
+            let sender = senders
+                .entry(node_id)
+                .or_insert_with(|| Allowance::new(self.limits().burst));
+            let refusals = match limit {
+                MessageLimit::Rate => &mut sender.over_rate,
+                MessageLimit::Size => &mut sender.over_size,
+            };
+            refusals.count += 1;
+            if refusals
+                .reported
+                .is_some_and(|reported| reported.elapsed() < REPORT_INTERVAL)
+            {
+                return;
+            }
+            refusals.reported = Some(Instant::now());
+            std::mem::take(&mut refusals.count)
+        };
+        (protocols.events())(&PeerEvent::RateLimited {
+            node_id,
+            limit,
+            refused,
+        });
+    }
+
+    /// Hand a received message to the handler unless it was seen before;
+    /// returns false if it is sealed and doesn't open
+    fn receive(&self, protocols: &Protocols, node_id: NodeId, frame: MessageFrame) -> bool {
+        let MessageFrame {
+            id,
+            sealed,
+            payload,
+        } = frame;
+        let opened = if sealed {
+            let Some(endpoint) = protocols.endpoint() else {
+                return false;
+            };
+            match PairKey::new(endpoint.secret_key(), &node_id).open(&payload) {
+                Ok(opened) => Some(opened),
+                Err(e) => {
+                    warn!("Sealed message from {}: {:#}", node_id.fmt_short(), e);
+                    return false;
+                }
+            }
+        } else {
+            None
+        };
+        {
+            let mut recent = self.inner.recent.lock().unwrap();
+            if recent.contains(&id) {
+                debug!(
+                    "Message {} from {} again, dropping it",
+                    id,
+                    node_id.fmt_short()
+                );
+                return true;
+            }
+            if recent.len() == RECENT_IDS {
+                recent.pop_front();
+            }
+            recent.push_back(id);
+        }
+
+        let (handler, data) = match &opened {
+            Some(opened) => (
+                self.inner.sealed_handler.lock().unwrap().clone(),
+                &opened[..],
+            ),
+            None => (self.inner.handler.lock().unwrap().clone(), &payload[..]),
+        };
+        match handler {
+            Some(handler) => handler.on_message(node_id, id, data),
+            None => debug!("Dropping message from {}, no handler", node_id.fmt_short()),
+        }
+        true
+    }
+}
+
+/// Make room for `incoming` more bytes within `budget`, returning the
+/// messages dropped for it, or `None` if the new one doesn't fit
+///
+/// The message a flush is sending for a peer in `in_flight`, the head of
+/// its queue, is never dropped.
+fn make_room(
+    queues: &mut HashMap<NodeId, VecDeque<Queued>>,
+    in_flight: &HashSet<NodeId>,
+    incoming: usize,
+    budget: &MemoryBudget,
+) -> Option<Vec<(NodeId, Queued)>> {
+    let limit = budget.queued_message_bytes;
+    let queued: usize = queues
+        .values()
+        .flatten()
+        .map(|queued| queued.data.len())
+        .sum();
+    if queued + incoming <= limit {
+        return Some(Vec::new());
+    }
+    if budget.policy == DropPolicy::RefuseNew || incoming > limit {
+        return None;
+    }
+    // The head of a queue being flushed is in transit
+    let first_droppable = |node_id: &NodeId| usize::from(in_flight.contains(node_id));
+    let in_transit: usize = in_flight
+        .iter()
+        .filter_map(|node_id| queues.get(node_id)?.front())
+        .map(|queued| queued.data.len())
+        .sum();
+    if in_transit + incoming > limit {
+        return None;
+    }
+
+    let mut dropped = Vec::new();
+    let mut queued = queued;
+    while queued + incoming > limit {
+        let (node_id, index) = queues
+            .iter()
+            .filter_map(|(node_id, queue)| {
+                let index = first_droppable(node_id);
+                queue.get(index).map(|q| (q.queued, *node_id, index))
+            })
+            .min_by_key(|(queued, _, _)| *queued)
+            .map(|(_, node_id, index)| (node_id, index))
+            .expect("messages in transit fit the budget");
+        let queue = queues.get_mut(&node_id).expect("just found");
+        let message = queue.remove(index).expect("just found");
+        if queue.is_empty() {
+            queues.remove(&node_id);
+        }
+        queued -= message.data.len();
+        dropped.push((node_id, message));
+    }
+    Some(dropped)
+}
+
+impl StreamHandler for Messages {
+    fn on_open(&self, stream: StreamId, node_id: NodeId) {
+        let mut streams = self.inner.streams.lock().unwrap();
+        // Outbound streams are registered before they open
+        match streams.get(&stream) {
+            Some(StreamState::Sending { id, .. }) => {
+                let sent = PeerEvent::MessageSent { id: *id, node_id };
+                drop(streams);
+                if let Some(protocols) = self.inner.protocols.lock().unwrap().clone() {
+                    (protocols.events())(&sent);
+                }
+            }
+            Some(StreamState::Receiving { .. }) => {}
+            None => {
+                let refused = (!self.within_rate(node_id)).then_some(MessageLimit::Rate);
+                streams.insert(
+                    stream,
+                    StreamState::Receiving {
+                        node_id,
+                        data: Vec::new(),
+                        refused,
+                    },
+                );
+            }
+        }
+    }
+
+    fn on_data(&self, stream: StreamId, chunk: &[u8]) {
+        let max_size = self.limits().max_size.min(MAX_MESSAGE_SIZE);
+        let mut streams = self.inner.streams.lock().unwrap();
+        match streams.get_mut(&stream) {
+            Some(StreamState::Receiving { data, refused, .. }) => {
+                if refused.is_some() {
+                    // Nothing of a refused message is kept
+                } else if data.len() + chunk.len() > MESSAGE_OVERHEAD + max_size {
+                    *refused = Some(MessageLimit::Size);
+                    *data = Vec::new();
+                } else {
+                    data.extend_from_slice(chunk);
+                }
+            }
+            Some(StreamState::Sending { reply, .. }) => reply.extend_from_slice(chunk),
+            None => {}
+        }
+    }
+
+    fn on_close(&self, stream: StreamId, error: Option<&str>) {
+        let state = self.inner.streams.lock().unwrap().remove(&stream);
+        match state {
+            Some(StreamState::Receiving {
+                node_id,
+                data,
+                refused,
+            }) => {
+                if error.is_some() {
+                    return;
+                }
+                let Some(protocols) = self.inner.protocols.lock().unwrap().clone() else {
+                    return;
+                };
+                let max_size = self.limits().max_size.min(MAX_MESSAGE_SIZE);
+                let frame = match refused {
+                    Some(limit) => Err(limit),
+                    None => match protocol::decode::<MessageFrame>(&data) {
+                        Ok(Some((frame, []))) if frame.payload.len() > max_size => {
+                            Err(MessageLimit::Size)
+                        }
+                        Ok(Some((frame, []))) => Ok(Some(frame)),
+                        _ => Ok(None),
+                    },
+                };
+                let reply = match frame {
+                    Err(limit) => {
+                        debug!(
+                            "Refusing message from {} over the {:?} limit",
+                            node_id.fmt_short(),
+                            limit
+                        );
+                        self.refuse(&protocols, node_id, limit);
+                        match limit {
+                            MessageLimit::Rate => MessageReply::Busy,
+                            MessageLimit::Size => MessageReply::Rejected,
+                        }
+                    }
+                    Ok(Some(frame)) => {
+                        if self.receive(&protocols, node_id, frame) {
+                            MessageReply::Ack
+                        } else {
+                            MessageReply::Rejected
+                        }
+                    }
+                    Ok(None) => {
+                        warn!("Rejecting malformed message from {}", node_id.fmt_short());
+                        MessageReply::Rejected
+                    }
+                };
+                protocols.write(stream, protocol::encode(&reply));
+                protocols.finish(stream);
+            }
+            Some(StreamState::Sending { reply, done, .. }) => {
+                let result = match error {
+                    Some(error) => Err(error.to_string()),
+                    // No reply, or one that doesn't decode, is a rejection
+                    None => Ok(match protocol::decode::<MessageReply>(&reply) {
+                        Ok(Some((reply, []))) => reply,
+                        _ => MessageReply::Rejected,
+                    }),
+                };
+                let _ = done.send(result);
+            }
+            None => {}
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Which interfaces actually carry multicast, checked without iroh
+//!
+//! "Discovery is silent" can mean the OS or the network never let a
+//! multicast packet through, or that it did and the peer mishandled it.
+//! [`probe`] tells the two apart: it sends a beacon of its own to the mDNS
+//! group out of each interface and listens for it on the group, using plain
+//! sockets. An interface whose beacon comes back delivers multicast as far
+//! as this machine is concerned; one whose send fails (errno 65 on iOS
+//! without the multicast entitlement) or whose beacon never arrives (an
+//! inbound firewall) doesn't, and no setting of this crate will change
+//! that. mDNS traffic from other hosts heard meanwhile shows the network
+//! delivers it too.
+//!
+//! The beacon is an mDNS query for a random name under
+//! `_mdns-peer-probe._udp.local`, which no responder answers. `mdns-peer
+//! doctor` runs the probe, and the FFI returns it from
+//! `peer_probe_multicast`.
+
+use std::collections::BTreeSet;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use socket2::{Domain, Protocol, Socket, Type};
+
+/// mDNS IPv4 group and port (RFC 6762)
+pub(crate) const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+pub(crate) const MDNS_PORT: u16 = 5353;
+
+/// How long [`probe`] listens by default
+pub const DEFAULT_PROBE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Outcome of [`probe`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct MulticastReport {
+    /// Every interface that is up and has an IPv4 address, loopback aside
+    pub interfaces: Vec<InterfaceProbe>,
+    /// Other hosts whose mDNS traffic arrived while listening
+    pub heard_from: BTreeSet<Ipv4Addr>,
+}
+
+impl MulticastReport {
+    /// Interfaces whose beacon came back
+    pub fn delivering(&self) -> impl Iterator<Item = &InterfaceProbe> {
+        self.interfaces.iter().filter(|iface| iface.delivers)
+    }
+}
+
+/// How one interface fared
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InterfaceProbe {
+    pub name: String,
+    pub addr: Ipv4Addr,
+    /// The OS flags the interface as supporting multicast
+    pub multicast_flag: bool,
+    /// The beacon sent out of this interface arrived on the group
+    pub delivers: bool,
+    /// Why joining the group or sending the beacon failed
+    pub error: Option<String>,
+}
+
+/// Send a beacon out of every interface and listen on the group for
+/// `window`; blocks for that long
+///
+/// Fails only if the mDNS port can't be bound at all; problems with one
+/// interface are reported in its [`InterfaceProbe::error`].
+pub fn probe(window: Duration) -> io::Result<MulticastReport> {
+    let group = bind_mdns(true)?;
+    group.set_multicast_loop_v4(true)?;
+    group.set_read_timeout(Some(Duration::from_millis(50)))?;
+
+    let nonce: u32 = rand::random();
+    let mut report = MulticastReport::default();
+    let mut beacons = Vec::new();
+    for iface in netdev::get_interfaces() {
+        if !iface.is_up() || iface.is_loopback() {
+            continue;
+        }
+        // The group can only be joined once per interface
+        let Some(net) = iface.ipv4.first() else {
+            continue;
+        };
+        let addr = net.addr();
+        let beacon = beacon(nonce, report.interfaces.len());
+        let sent = group
+            .join_multicast_v4(&MDNS_GROUP, &addr)
+            .and_then(|()| send_beacon(addr, &beacon));
+        report.interfaces.push(InterfaceProbe {
+            name: iface.name.clone(),
+            addr,
+            multicast_flag: iface.is_multicast(),
+            delivers: false,
+            error: sent.err().map(|e| e.to_string()),
+        });
+        beacons.push(beacon);
+    }
+
+    let ours: BTreeSet<_> = report.interfaces.iter().map(|iface| iface.addr).collect();
+    let mut buf = [0u8; 9000];
+    let deadline = Instant::now() + window;
+    while Instant::now() < deadline {
+        let Some((len, SocketAddr::V4(from))) = recv(&group, &mut buf)? else {
+            continue;
+        };
+        let packet = &buf[..len];
+        if let Some(i) = beacons.iter().position(|beacon| beacon[..] == *packet) {
+            report.interfaces[i].delivers = true;
+        } else if !ours.contains(from.ip()) && !from.ip().is_loopback() {
+            report.heard_from.insert(*from.ip());
+        }
+    }
+    Ok(report)
+}
+
+/// Send `beacon` to the mDNS group out of the interface with `addr`
+fn send_beacon(addr: Ipv4Addr, beacon: &[u8]) -> io::Result<()> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_multicast_if_v4(&addr)?;
+    socket.set_multicast_loop_v4(true)?;
+    socket.bind(&SocketAddrV4::new(addr, 0).into())?;
+    socket.send_to(beacon, &SocketAddrV4::new(MDNS_GROUP, MDNS_PORT).into())?;
+    Ok(())
+}
+
+/// The beacon for interface number `index`: a query for
+/// `<nonce>-<index>._mdns-peer-probe._udp.local TXT`
+fn beacon(nonce: u32, index: usize) -> Vec<u8> {
+    // ID 0, standard query, one question
+    let mut packet = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    let name = format!("{:08x}-{}", nonce, index);
+    for label in [name.as_str(), "_mdns-peer-probe", "_udp", "local"] {
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    // Root label, type TXT, class IN
+    packet.extend_from_slice(&[0, 0, 16, 0, 1]);
+    packet
+}
+
+/// One datagram, or `None` if nothing arrived before the read timeout
+pub(crate) fn recv(socket: &UdpSocket, buf: &mut [u8]) -> io::Result<Option<(usize, SocketAddr)>> {
+    match socket.recv_from(buf) {
+        Ok(received) => Ok(Some(received)),
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Bind UDP 5353 on all interfaces, optionally sharing it the way mDNS
+/// responders (and iroh's local discovery) do
+pub(crate) fn bind_mdns(reuse: bool) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    if reuse {
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+    }
+    socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, MDNS_PORT).into())?;
+    Ok(socket.into())
+}
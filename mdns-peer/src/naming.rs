@@ -0,0 +1,110 @@
+//! Picking an identifier no other peer on the network presents
+//!
+//! Two devices both called "Jamie's iPhone" can't be told apart in a peer
+//! list. Before starting, an app can listen to local discovery for a moment
+//! and advertise `Jamie's iPhone-2` instead, the way
+//! [`DuplicatePolicy::Suffix`](crate::instance::DuplicatePolicy::Suffix)
+//! does for two instances on one machine. Names are compared ignoring case,
+//! since a list showing `iphone` next to `iPhone` looks just as confusing.
+//!
+//! Only peers online while listening are seen, so this makes collisions
+//! unlikely rather than impossible.
+//!
+//! Device names come from users and may not fit in discovery user data at
+//! all: [`sanitize_identifier`] cuts them to [`MAX_IDENTIFIER_BYTES`]
+//! without splitting a character or an emoji sequence, so the host can show
+//! the name that is actually advertised.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use anyhow::Context;
+use iroh::discovery::{Discovery, UserData};
+use iroh::SecretKey;
+use n0_future::StreamExt;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::discovery::DiscoveryEventSource;
+use crate::mdns::{MdnsOptions, TunedMdns};
+use crate::snapshot::Snapshot;
+
+/// How long [`pick_identifier`] listens by default; a few mDNS query cycles
+pub const DEFAULT_LISTEN: Duration = Duration::from_secs(3);
+
+/// Longest identifier discovery can carry, in bytes of UTF-8
+pub const MAX_IDENTIFIER_BYTES: usize = UserData::MAX_LENGTH;
+
+/// `name` as valid user data: control characters and surrounding whitespace
+/// removed, and cut to [`MAX_IDENTIFIER_BYTES`] between two graphemes
+///
+/// A flag or a family emoji is several code points; cutting inside one
+/// would advertise a broken glyph, so the whole grapheme is dropped instead.
+pub fn sanitize_identifier(name: &str) -> String {
+    fit(name, MAX_IDENTIFIER_BYTES)
+}
+
+fn fit(name: &str, max_bytes: usize) -> String {
+    let cleaned: String = name.chars().filter(|c| !c.is_control()).collect();
+    let mut fitted = String::new();
+    for grapheme in cleaned.trim().graphemes(true) {
+        if fitted.len() + grapheme.len() > max_bytes {
+            break;
+        }
+        fitted.push_str(grapheme);
+    }
+    fitted.trim_end().to_string()
+}
+
+/// `base`, or the first of `base-2`, `base-3`, ... that isn't in `taken`
+///
+/// `base` is shortened as in [`sanitize_identifier`] where a suffix would
+/// make it too long. Fails if `base` itself isn't valid user data.
+pub fn unique_identifier<'a>(
+    base: &str,
+    taken: impl IntoIterator<Item = &'a str>,
+) -> anyhow::Result<String> {
+    base.parse::<UserData>()
+        .with_context(|| format!("Invalid identifier {:?}", base))?;
+    let taken: HashSet<String> = taken.into_iter().map(str::to_lowercase).collect();
+    let free = (1u32..)
+        .map(|n| match n {
+            1 => base.to_string(),
+            n => {
+                let suffix = format!("-{}", n);
+                fit(base, MAX_IDENTIFIER_BYTES - suffix.len()) + &suffix
+            }
+        })
+        .find(|candidate| !taken.contains(&candidate.to_lowercase()))
+        .expect("more candidates than taken names");
+    Ok(free)
+}
+
+/// Listen to local discovery for `listen`, then pick an identifier based on
+/// `base` that none of the peers found uses, see [`unique_identifier`]
+///
+/// Listens with a throwaway identity and announces nothing; call it before
+/// starting the peer, with the same `mdns` settings.
+pub async fn pick_identifier(
+    base: &str,
+    mdns: &MdnsOptions,
+    listen: Duration,
+) -> anyhow::Result<String> {
+    base.parse::<UserData>()
+        .with_context(|| format!("Invalid identifier {:?}", base))?;
+    let node_id = SecretKey::generate(rand::rngs::OsRng).public();
+    let discovery = TunedMdns::spawn(node_id, mdns)
+        .map_err(|e| anyhow::anyhow!("Failed to start mDNS discovery: {}", e))?;
+    let events = discovery
+        .subscribe()
+        .context("mDNS discovery has no event stream")?;
+    let source: DiscoveryEventSource = Box::pin(events.map(Ok));
+
+    let snapshot = Snapshot::collect(source, node_id, None, listen).await;
+    unique_identifier(
+        base,
+        snapshot
+            .peers
+            .iter()
+            .filter_map(|peer| peer.user_data.as_deref()),
+    )
+}
@@ -0,0 +1,136 @@
+//! Changes to the local endpoint's own addresses
+//!
+//! Roaming between Wi-Fi networks or bringing a VPN up changes the direct
+//! addresses we announce. iroh re-publishes them to discovery on its own;
+//! this module tells the host it happened.
+//!
+//! It also reports what the rest of the network sees us as. Relays tell the
+//! endpoint the address our packets arrive from (QUIC address discovery),
+//! and [`ObservedAddrs`] sets those reflexive addresses next to the ones
+//! bound locally. When discovery finds a peer but dialing it fails, this
+//! tells a NAT or VPN apart from a firewall: an observed IP that no
+//! interface has means a NAT or VPN rewrites our packets, and one IP seen
+//! at several ports points at a symmetric NAT, which hole punching can't
+//! get through.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::{IpAddr, SocketAddr};
+
+use iroh::endpoint::{DirectAddr, DirectAddrType};
+use iroh::{Endpoint, RelayUrl, Watcher};
+use serde::Serialize;
+
+use crate::events::{LocalAddrs, PeerEvent};
+
+/// Emit [`PeerEvent::LocalAddrsChanged`] whenever the direct addresses or
+/// home relay of `endpoint` change
+///
+/// The first set of direct addresses found after binding is the baseline and
+/// is not reported. Returns once the endpoint is closed.
+pub async fn watch_local_addrs(endpoint: Endpoint, mut on_event: impl FnMut(PeerEvent)) {
+    let mut direct = endpoint.direct_addresses();
+    let mut relay = endpoint.home_relay();
+    let mut current: Option<LocalAddrs> = None;
+
+    loop {
+        if let Some(addrs) = direct.get() {
+            let next = local_addrs(addrs, relay.get());
+            match current.replace(next.clone()) {
+                Some(previous) if previous != next => on_event(PeerEvent::LocalAddrsChanged {
+                    previous,
+                    current: next,
+                }),
+                _ => {}
+            }
+        }
+
+        let result = tokio::select! {
+            r = direct.updated() => r.map(|_| ()),
+            r = relay.updated() => r.map(|_| ()),
+        };
+        if result.is_err() {
+            // The endpoint was closed
+            return;
+        }
+    }
+}
+
+fn local_addrs(direct: BTreeSet<DirectAddr>, relay: Vec<RelayUrl>) -> LocalAddrs {
+    let mut direct_addrs: Vec<SocketAddr> = direct.into_iter().map(|addr| addr.addr).collect();
+    direct_addrs.sort();
+    LocalAddrs {
+        direct_addrs,
+        home_relay: relay.iter().map(ToString::to_string).collect(),
+    }
+}
+
+/// The local interface whose subnet holds `ip`, if any
+pub(crate) fn on_link_interface(
+    interfaces: &[netdev::Interface],
+    ip: IpAddr,
+) -> Option<&netdev::Interface> {
+    interfaces.iter().find(|iface| match ip.to_canonical() {
+        IpAddr::V4(ip) => iface.ipv4.iter().any(|net| net.contains(&ip)),
+        IpAddr::V6(ip) => iface.ipv6.iter().any(|net| net.contains(&ip)),
+    })
+}
+
+/// Our addresses as bound and as seen from outside, see the
+/// [module docs](self)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ObservedAddrs {
+    /// Addresses of the local interfaces
+    pub local: Vec<SocketAddr>,
+    /// Addresses our packets were observed arriving from
+    pub observed: Vec<SocketAddr>,
+    /// Addresses mapped on the router with UPnP, NAT-PMP or PCP
+    pub portmapped: Vec<SocketAddr>,
+    /// An observed IP isn't on any local interface, so a NAT or VPN
+    /// rewrites our packets on the way out
+    pub translated: bool,
+    /// One observed IP was seen at more than one port, so the NAT picks a
+    /// new port per destination and peers can't dial the observed address
+    pub symmetric_nat: bool,
+}
+
+impl ObservedAddrs {
+    pub fn new(
+        mut local: Vec<SocketAddr>,
+        mut observed: Vec<SocketAddr>,
+        mut portmapped: Vec<SocketAddr>,
+    ) -> Self {
+        local.sort();
+        observed.sort();
+        portmapped.sort();
+        let local_ips: BTreeSet<IpAddr> = local.iter().map(SocketAddr::ip).collect();
+        let mut ports: BTreeMap<IpAddr, BTreeSet<u16>> = BTreeMap::new();
+        for addr in &observed {
+            ports.entry(addr.ip()).or_default().insert(addr.port());
+        }
+        Self {
+            translated: ports.keys().any(|ip| !local_ips.contains(ip)),
+            symmetric_nat: ports.values().any(|ports| ports.len() > 1),
+            local,
+            observed,
+            portmapped,
+        }
+    }
+}
+
+/// What `endpoint` knows of its own addresses right now, `None` before the
+/// first ones are found
+pub fn observed_addrs(endpoint: &Endpoint) -> Option<ObservedAddrs> {
+    let addrs = endpoint.direct_addresses().get()?;
+    let (mut local, mut observed, mut portmapped) = (Vec::new(), Vec::new(), Vec::new());
+    for addr in addrs {
+        match addr.typ {
+            DirectAddrType::Local => local.push(addr.addr),
+            DirectAddrType::Portmapped => portmapped.push(addr.addr),
+            DirectAddrType::Unknown => {}
+            // Reported by relays, from the port we bound or the one the
+            // NAT maps it to
+            _ => observed.push(addr.addr),
+        }
+    }
+    Some(ObservedAddrs::new(local, observed, portmapped))
+}
@@ -0,0 +1,161 @@
+//! Local aliases and notes for other peers
+//!
+//! Node IDs are unreadable and user data is whatever the remote chose to
+//! advertise, so the user can name peers themselves ("Dad's MacBook") and
+//! jot down notes. Both stay on this device, in `<state dir>/peers.json`
+//! next to the profiles:
+//!
+//! ```json
+//! {"a8a2...":{"alias":"Dad's MacBook","notes":"Upstairs office"}}
+//! ```
+//!
+//! They are included with each peer in `summary` events and the dashboard's
+//! peer table.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use iroh::NodeId;
+use serde::{Deserialize, Serialize};
+
+use crate::profile;
+use crate::state;
+
+/// Longest alias accepted, in characters
+pub const MAX_ALIAS_LEN: usize = 64;
+
+/// Longest notes accepted, in bytes
+pub const MAX_NOTES_LEN: usize = 4096;
+
+/// What the user recorded about one peer
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerNote {
+    pub alias: Option<String>,
+    pub notes: Option<String>,
+}
+
+impl PeerNote {
+    fn is_empty(&self) -> bool {
+        self.alias.is_none() && self.notes.is_none()
+    }
+}
+
+/// Aliases and notes by node ID, saved on every change
+///
+/// Clones share the same notes. The default keeps them in memory only.
+#[derive(Debug, Clone, Default)]
+pub struct PeerNotes {
+    path: Option<PathBuf>,
+    notes: Arc<Mutex<BTreeMap<NodeId, PeerNote>>>,
+}
+
+impl PeerNotes {
+    /// Notes stored under `state_dir`
+    pub fn open(state_dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        state::migrate(state_dir.as_ref())?;
+        let path = state_dir.as_ref().join("peers.json");
+        let notes = match fs::read_to_string(&path) {
+            Ok(contents) => {
+                let file: BTreeMap<String, PeerNote> = serde_json::from_str(&contents)
+                    .with_context(|| format!("Corrupt peer notes {}", path.display()))?;
+                file.into_iter()
+                    .map(|(node_id, note)| {
+                        let node_id = node_id.parse().with_context(|| {
+                            format!("Invalid node ID {:?} in {}", node_id, path.display())
+                        })?;
+                        anyhow::Ok((node_id, note))
+                    })
+                    .collect::<anyhow::Result<_>>()?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+        Ok(Self {
+            path: Some(path),
+            notes: Arc::new(Mutex::new(notes)),
+        })
+    }
+
+    /// Notes under `$MDNS_PEER_HOME`, or `~/.mdns-peer` if that is unset
+    pub fn open_default() -> anyhow::Result<Self> {
+        Self::open(profile::default_state_dir()?)
+    }
+
+    /// What is recorded about `node_id`, empty if nothing
+    pub fn get(&self, node_id: NodeId) -> PeerNote {
+        let notes = self.notes.lock().unwrap();
+        notes.get(&node_id).cloned().unwrap_or_default()
+    }
+
+    /// Every peer with an alias or notes, ordered by node ID
+    pub fn all(&self) -> BTreeMap<NodeId, PeerNote> {
+        self.notes.lock().unwrap().clone()
+    }
+
+    /// Name `node_id`, or remove its alias with `None` or a blank one
+    pub fn set_alias(&self, node_id: NodeId, alias: Option<&str>) -> anyhow::Result<()> {
+        let alias = alias.map(str::trim).filter(|a| !a.is_empty());
+        if let Some(alias) = alias {
+            anyhow::ensure!(
+                alias.chars().count() <= MAX_ALIAS_LEN,
+                "Alias is longer than {} characters",
+                MAX_ALIAS_LEN
+            );
+        }
+        self.update(node_id, |note| note.alias = alias.map(str::to_string))
+    }
+
+    /// Move what is recorded about `old` to `new`, e.g. after the peer
+    /// rotated its key, unless `new` has notes of its own
+    pub fn rename(&self, old: NodeId, new: NodeId) -> anyhow::Result<()> {
+        let mut notes = self.notes.lock().unwrap();
+        if notes.contains_key(&new) {
+            return Ok(());
+        }
+        let Some(note) = notes.remove(&old) else {
+            return Ok(());
+        };
+        notes.insert(new, note);
+        self.save(&notes)
+    }
+
+    /// Replace the notes on `node_id`, or remove them with `None` or blank
+    /// ones
+    pub fn set_notes(&self, node_id: NodeId, notes: Option<&str>) -> anyhow::Result<()> {
+        let notes = notes.filter(|n| !n.trim().is_empty());
+        if let Some(notes) = notes {
+            anyhow::ensure!(
+                notes.len() <= MAX_NOTES_LEN,
+                "Notes are longer than {} bytes",
+                MAX_NOTES_LEN
+            );
+        }
+        self.update(node_id, |note| note.notes = notes.map(str::to_string))
+    }
+
+    fn update(&self, node_id: NodeId, change: impl FnOnce(&mut PeerNote)) -> anyhow::Result<()> {
+        let mut notes = self.notes.lock().unwrap();
+        let note = notes.entry(node_id).or_default();
+        change(note);
+        if note.is_empty() {
+            notes.remove(&node_id);
+        }
+        self.save(&notes)
+    }
+
+    fn save(&self, notes: &BTreeMap<NodeId, PeerNote>) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let file: BTreeMap<String, &PeerNote> = notes
+            .iter()
+            .map(|(node_id, note)| (node_id.to_string(), note))
+            .collect();
+        state::write_atomic(path, &serde_json::to_vec(&file)?)
+    }
+}
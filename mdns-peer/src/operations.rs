@@ -0,0 +1,139 @@
+//! Operations the host can cancel while they run
+//!
+//! A connect or a file transfer can take a while, and the user may back
+//! out of the screen that started it. Each such operation gets an
+//! [`OperationId`] when it starts, and [`cancel`] stops it, whatever kind
+//! it is: `peer_cancel(op_id)` over FFI. IDs come from one counter shared
+//! by all kinds, so transfer IDs are operation IDs too.
+//!
+//! An operation run with [`cancellable`] fails with [`Cancelled`] once
+//! cancelled; kinds with their own way of stopping, like transfers,
+//! [`register`] it instead.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use schemars::JsonSchema;
+use serde::Serialize;
+use tokio::sync::Notify;
+
+/// Identifies one operation, never 0
+pub type OperationId = u64;
+
+static OPERATIONS: Mutex<BTreeMap<OperationId, Entry>> = Mutex::new(BTreeMap::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// What an operation does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationKind {
+    Connect,
+    Ping,
+    Transfer,
+}
+
+/// The operation was stopped with [`cancel`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Whether `err`, or anything it wraps, is [`Cancelled`]
+pub fn is_cancelled(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| cause.is::<Cancelled>())
+}
+
+struct Entry {
+    kind: OperationKind,
+    cancel: Arc<dyn Fn() + Send + Sync>,
+}
+
+/// A fresh ID, for operations that need it before they [`register`]
+pub fn next_id() -> OperationId {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Keeps an operation cancellable; dropping it unlists the operation
+#[derive(Debug)]
+pub struct Registration {
+    id: OperationId,
+}
+
+impl Registration {
+    pub fn id(&self) -> OperationId {
+        self.id
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        OPERATIONS.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// List operation `id`, which [`cancel`] stops by calling `cancel`
+pub fn register(
+    id: OperationId,
+    kind: OperationKind,
+    cancel: impl Fn() + Send + Sync + 'static,
+) -> Registration {
+    let entry = Entry {
+        kind,
+        cancel: Arc::new(cancel),
+    };
+    OPERATIONS.lock().unwrap().insert(id, entry);
+    Registration { id }
+}
+
+/// `future` as a cancellable operation, and its ID
+///
+/// The operation is listed from now until the returned future completes or
+/// is dropped, and resolves to [`Cancelled`] if cancelled before it
+/// finished, even before it was first polled.
+pub fn cancellable<T>(
+    kind: OperationKind,
+    future: impl Future<Output = anyhow::Result<T>>,
+) -> (OperationId, impl Future<Output = anyhow::Result<T>>) {
+    let cancelled = Arc::new(Notify::new());
+    let registration = register(next_id(), kind, {
+        let cancelled = cancelled.clone();
+        // Stores a permit if the future isn't waiting yet
+        move || cancelled.notify_one()
+    });
+    let id = registration.id();
+    let future = async move {
+        let _registration = registration;
+        tokio::select! {
+            // A cancel wins over a result that is ready at the same time
+            biased;
+            _ = cancelled.notified() => Err(Cancelled.into()),
+            result = future => result,
+        }
+    };
+    (id, future)
+}
+
+/// Stop operation `id`
+///
+/// Returns false if it already finished or never existed.
+pub fn cancel(id: OperationId) -> bool {
+    let cancel = match OPERATIONS.lock().unwrap().get(&id) {
+        Some(entry) => entry.cancel.clone(),
+        None => return false,
+    };
+    cancel();
+    true
+}
+
+/// The kind of operation `id`, while it runs
+pub fn kind(id: OperationId) -> Option<OperationKind> {
+    OPERATIONS.lock().unwrap().get(&id).map(|entry| entry.kind)
+}
@@ -0,0 +1,150 @@
+//! Settings for a running peer
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use iroh::{NodeAddr, NodeId, RelayUrl, SecretKey};
+
+use crate::bind::BindAddrs;
+use crate::budget::MemoryBudget;
+use crate::candidates::Candidates;
+#[cfg(feature = "docs")]
+use crate::docs::SharedDocs;
+use crate::fallback::DnsFallback;
+use crate::history::SessionHistory;
+use crate::instance::DuplicatePolicy;
+use crate::mdns::MdnsOptions;
+use crate::messages::Messages;
+use crate::notes::PeerNotes;
+use crate::p2p::PeerToPeer;
+use crate::protocols::Protocols;
+use crate::reconnect::Resume;
+use crate::registry::{PeerRegistry, DEFAULT_EXPIRY_GRACE};
+#[cfg(feature = "gossip")]
+use crate::topics::Topics;
+use crate::transfer::FileTransfers;
+
+/// Settings for [`run_peer`](crate::run_peer)
+#[derive(Debug, Clone)]
+pub struct PeerOptions {
+    /// How often to log and emit a [`PeerEvent::Summary`](crate::PeerEvent::Summary),
+    /// `None` to disable it
+    pub summary_interval: Option<Duration>,
+    /// Identity to bind with, a fresh random one if `None`
+    /// (see [`crate::profile`] for persistent identities)
+    pub secret_key: Option<SecretKey>,
+    /// Host-defined protocols to accept connections for
+    pub protocols: Protocols,
+    /// Connect to trusted peers as soon as they are discovered
+    pub warm_up: Option<WarmUp>,
+    /// Record raw discovery events to this file, see [`crate::session`]
+    pub record: Option<PathBuf>,
+    /// What to do if another instance on this machine already advertises
+    /// the same identifier, see [`crate::instance`]
+    pub on_duplicate: DuplicatePolicy,
+    /// Relays to choose the home relay from instead of iroh's defaults, see
+    /// [`crate::relay`]
+    pub relays: Option<Vec<RelayUrl>>,
+    /// Never let traffic leave the local network through a relay, see
+    /// [`crate::relay`]
+    pub lan_only: bool,
+    /// Local discovery settings, see [`crate::mdns`]
+    pub mdns: MdnsOptions,
+    /// Local addresses and UDP ports to bind, see [`crate::bind`]
+    pub bind: BindAddrs,
+    /// Peers to reach without discovering them first, from pairing payloads
+    /// (see [`crate::pairing`])
+    pub paired: Vec<NodeAddr>,
+    /// Peers the host learned about over another channel, see
+    /// [`crate::candidates`]
+    pub candidates: Candidates,
+    /// Look peers up in DNS when local discovery finds nobody, see
+    /// [`crate::fallback`]; off if `None`
+    pub dns_fallback: Option<DnsFallback>,
+    /// Peer-to-peer interfaces such as AWDL the host reported, see
+    /// [`crate::p2p`]
+    pub p2p: PeerToPeer,
+    /// Local aliases and notes included in summaries, see [`crate::notes`]
+    pub notes: PeerNotes,
+    /// Where this session's summary is recorded, see [`crate::history`]
+    pub history: SessionHistory,
+    /// Outbound message queues and the inbound message handler, see
+    /// [`crate::messages`]
+    pub messages: Messages,
+    /// Re-dials trusted peers when the app returns to the foreground, see
+    /// [`crate::reconnect`]
+    pub resume: Resume,
+    /// Files to send and where received ones go, see [`crate::transfer`]
+    pub transfers: FileTransfers,
+    /// Subscribed gossip topics and their handlers, see `crate::topics`
+    #[cfg(feature = "gossip")]
+    pub topics: Topics,
+    /// Key-value documents synced with other peers, see [`crate::docs`]
+    #[cfg(feature = "docs")]
+    pub docs: SharedDocs,
+    /// Caps on queued messages and discovered peers, see [`crate::budget`]
+    pub budget: MemoryBudget,
+    /// How long an expired peer stays listed in case it comes straight
+    /// back, see [`crate::registry`]; zero reports expiries right away
+    pub expiry_grace: Duration,
+    /// Peers currently discovered, for callers polling them while the peer
+    /// runs; emptied when it starts and stops
+    pub registry: Arc<Mutex<PeerRegistry>>,
+    /// Run only discovery and one outbound file transfer at a time, for
+    /// short-lived processes such as an iOS Share Extension
+    ///
+    /// Nothing is accepted, so other peers can't connect; messages, topics,
+    /// documents and `warm_up` are left out.
+    pub lite: bool,
+    /// Log user data, identifiers and aliases as they are instead of
+    /// hashed, see [`crate::privacy`]
+    pub log_names: bool,
+}
+
+impl Default for PeerOptions {
+    fn default() -> Self {
+        Self {
+            summary_interval: Some(Duration::from_secs(5)),
+            secret_key: None,
+            protocols: Protocols::default(),
+            warm_up: None,
+            record: None,
+            on_duplicate: DuplicatePolicy::default(),
+            relays: None,
+            lan_only: false,
+            mdns: MdnsOptions::default(),
+            bind: BindAddrs::default(),
+            paired: Vec::new(),
+            candidates: Candidates::default(),
+            dns_fallback: None,
+            p2p: PeerToPeer::default(),
+            notes: PeerNotes::default(),
+            history: SessionHistory::default(),
+            messages: Messages::default(),
+            resume: Resume::default(),
+            transfers: FileTransfers::default(),
+            #[cfg(feature = "gossip")]
+            topics: Topics::default(),
+            #[cfg(feature = "docs")]
+            docs: SharedDocs::default(),
+            budget: MemoryBudget::default(),
+            expiry_grace: DEFAULT_EXPIRY_GRACE,
+            registry: Arc::default(),
+            lite: false,
+            log_names: false,
+        }
+    }
+}
+
+/// Pre-establish connections so the first message skips the QUIC handshake
+///
+/// The connection is made on `alpn`, which the remote must have registered
+/// too, and is reused by [`Protocols::open_stream`].
+#[derive(Debug, Clone)]
+pub struct WarmUp {
+    pub alpn: Vec<u8>,
+    /// Peers worth connecting to eagerly
+    pub trusted: HashSet<NodeId>,
+}
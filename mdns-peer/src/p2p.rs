@@ -0,0 +1,158 @@
+//! Peer-to-peer interfaces the host brings up
+//!
+//! Apple devices can reach each other without a shared network over AWDL
+//! (`awdl0`, with `llw0` as its low-latency side) and Wi-Fi Aware. The OS
+//! only keeps such a link usable while an app asked for it, through
+//! Network.framework's `includePeerToPeer`, NetworkExtension or the Wi-Fi
+//! Aware APIs, and the peer can't see that request. The host tells it
+//! instead, through [`PeerToPeer::set_available`] (`peer_set_p2p_interface`
+//! over FFI).
+//!
+//! Once the host reported any interface, the peer manages all of them:
+//! addresses on a peer-to-peer interface the host hasn't reported available
+//! are left out of what local discovery announces, and out of the addresses
+//! discovered peers are dialed at, so no connection waits on a link that
+//! isn't there. Reporting one available puts its addresses back; every
+//! change re-announces the peer. Interfaces named `awdl*` and `llw*` are
+//! peer-to-peer ones, and any other name the host reports, e.g. a Wi-Fi
+//! Aware interface, is treated the same way. Until the host reports one,
+//! nothing is filtered.
+//!
+//! iroh's built-in local discovery can't filter addresses, so a peer bound
+//! while interfaces are managed uses [`TunedMdns`](crate::mdns::TunedMdns)
+//! even with default [`MdnsOptions`](crate::mdns::MdnsOptions). Report
+//! interfaces before starting the peer, as unavailable if they aren't up
+//! yet, to have them managed from the start.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+use tracing::info;
+
+use crate::network;
+
+/// Name prefixes of the interfaces treated as peer-to-peer without the host
+/// reporting them
+pub const P2P_PREFIXES: &[&str] = &["awdl", "llw"];
+
+/// Peer-to-peer interfaces the host reported, shared between the host and
+/// the running peer
+///
+/// Clones share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct PeerToPeer {
+    /// Whether each reported interface is available
+    reported: Arc<Mutex<BTreeMap<String, bool>>>,
+    changed: Arc<Notify>,
+}
+
+impl PeerToPeer {
+    /// Record whether the interface `name` is usable right now; returns
+    /// whether that changed anything
+    pub fn set_available(&self, name: &str, available: bool) -> bool {
+        let previous = self
+            .reported
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), available);
+        let changed = previous != Some(available);
+        if changed {
+            info!(
+                "Peer-to-peer interface {} {}",
+                name,
+                if available {
+                    "available"
+                } else {
+                    "unavailable"
+                }
+            );
+            self.changed.notify_waiters();
+        }
+        changed
+    }
+
+    /// Whether the host reported any interface, which makes the peer
+    /// manage all of them
+    pub fn is_managed(&self) -> bool {
+        !self.reported.lock().unwrap().is_empty()
+    }
+
+    /// Interfaces the host reported available, in name order
+    pub fn available(&self) -> Vec<String> {
+        let reported = self.reported.lock().unwrap();
+        reported
+            .iter()
+            .filter(|(_, available)| **available)
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Whether `name` is a peer-to-peer interface
+    pub fn is_p2p(&self, name: &str) -> bool {
+        P2P_PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+            || self.reported.lock().unwrap().contains_key(name)
+    }
+
+    /// Whether addresses on the interface `name` are left out
+    pub fn excludes(&self, name: &str) -> bool {
+        let reported = self.reported.lock().unwrap();
+        !reported.is_empty()
+            && reported.get(name) != Some(&true)
+            && (reported.contains_key(name)
+                || P2P_PREFIXES.iter().any(|prefix| name.starts_with(prefix)))
+    }
+
+    /// Of our own `addrs`, the ones to announce
+    pub fn announced(&self, addrs: &BTreeSet<SocketAddr>) -> BTreeSet<SocketAddr> {
+        if !self.is_managed() {
+            return addrs.clone();
+        }
+        let excluded: BTreeSet<IpAddr> = netdev::get_interfaces()
+            .into_iter()
+            .filter(|iface| self.excludes(&iface.name))
+            .flat_map(|iface| {
+                let v4 = iface.ipv4.into_iter().map(|net| IpAddr::V4(net.addr()));
+                let v6 = iface.ipv6.into_iter().map(|net| IpAddr::V6(net.addr()));
+                v4.chain(v6).collect::<Vec<_>>()
+            })
+            .collect();
+        addrs
+            .iter()
+            .filter(|addr| !excluded.contains(&addr.ip()))
+            .copied()
+            .collect()
+    }
+
+    /// Of a discovered peer's `addrs`, the ones to dial
+    ///
+    /// An address is left out if it carries the scope of an excluded
+    /// interface, or lies on such an interface's subnet; link-local IPv6
+    /// addresses without a scope can't be told apart and are kept.
+    pub fn dialable(&self, addrs: &BTreeSet<SocketAddr>) -> BTreeSet<SocketAddr> {
+        if !self.is_managed() {
+            return addrs.clone();
+        }
+        let interfaces = netdev::get_interfaces();
+        let excluded = |addr: &SocketAddr| match addr {
+            SocketAddr::V6(v6) if v6.scope_id() != 0 => interfaces
+                .iter()
+                .find(|iface| iface.index == v6.scope_id())
+                .is_some_and(|iface| self.excludes(&iface.name)),
+            SocketAddr::V6(v6) if v6.ip().is_unicast_link_local() => false,
+            _ => network::on_link_interface(&interfaces, addr.ip())
+                .is_some_and(|iface| self.excludes(&iface.name)),
+        };
+        addrs
+            .iter()
+            .filter(|&addr| !excluded(addr))
+            .copied()
+            .collect()
+    }
+
+    /// Wait for the next change [`PeerToPeer::set_available`] reports
+    pub(crate) async fn changed(&self) {
+        self.changed.notified().await;
+    }
+}
@@ -0,0 +1,58 @@
+//! Pairing by QR code when local discovery can't work
+//!
+//! Hotel and enterprise Wi-Fi often drop multicast, so mDNS never sees the
+//! other peer even though they can reach each other (directly on the same
+//! network, or through a relay). One peer shows its pairing payload as a QR
+//! code and the other scans it and adds it with [`add_paired_peer`].
+//!
+//! The payload is an iroh node ticket: node ID, home relay and direct
+//! addresses, postcard-encoded and base32'd behind a `node` prefix. It is
+//! emitted in upper case so QR encoders can use their alphanumeric mode,
+//! which fits about 45% more characters per module than byte mode; decoding
+//! accepts either case.
+
+use std::time::Duration;
+
+use iroh::{Endpoint, NodeAddr, Watcher};
+use iroh_base::ticket::{NodeTicket, Ticket};
+use tracing::info;
+
+/// Source recorded in the endpoint's address book for paired peers
+pub const PAIRING_SOURCE: &str = "pairing";
+
+/// Encode `addr` as a pairing payload, see the [module docs](self)
+pub fn encode_payload(addr: &NodeAddr) -> String {
+    NodeTicket::new(addr.clone())
+        .serialize()
+        .to_ascii_uppercase()
+}
+
+/// Decode a payload produced by [`encode_payload`], in either case
+pub fn decode_payload(payload: &str) -> anyhow::Result<NodeAddr> {
+    let ticket = NodeTicket::deserialize(&payload.trim().to_ascii_lowercase())
+        .map_err(|e| anyhow::anyhow!("Invalid pairing payload: {}", e))?;
+    Ok(ticket.node_addr().clone())
+}
+
+/// The pairing payload for `endpoint`, once it knows at least one address
+/// to be reached on
+///
+/// Waits up to `timeout` for a direct address or home relay; `None` if it
+/// has neither by then.
+pub async fn local_payload(endpoint: &Endpoint, timeout: Duration) -> Option<String> {
+    let mut watcher = endpoint.node_addr();
+    let addr = tokio::time::timeout(timeout, watcher.initialized())
+        .await
+        .ok()?;
+    Some(encode_payload(&addr))
+}
+
+/// Teach `endpoint` how to reach a peer from its pairing payload
+///
+/// Connections to the peer then work without it ever being discovered.
+pub fn add_paired_peer(endpoint: &Endpoint, addr: NodeAddr) -> anyhow::Result<()> {
+    let node_id = addr.node_id;
+    endpoint.add_node_addr_with_source(addr, PAIRING_SOURCE)?;
+    info!("Paired with {}", node_id.fmt_short());
+    Ok(())
+}
@@ -0,0 +1,328 @@
+//! Rust API for embedding the peer
+//!
+//! Rust desktop apps can run the peer on their own tokio runtime instead of
+//! going through the C functions in `ffi` or the `PEER_ID` variable that
+//! `run_desktop` reads:
+//!
+//! ```no_run
+//! # use n0_future::StreamExt;
+//! # async fn example() -> anyhow::Result<()> {
+//! let peer = mdns_peer::MdnsPeer::builder()
+//!     .identifier("alice")
+//!     .spawn()
+//!     .await?;
+//! println!("{} is {}", peer.identifier(), peer.node_id());
+//! let mut events = peer.events();
+//! while let Some(event) = events.next().await {
+//!     println!("{}", event.to_json());
+//! #   break;
+//! }
+//! let reason = peer.shutdown().await;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`MdnsPeer`] is `Send + Sync` and cheap to clone, so every task of the app
+//! can hold its own handle. Several peers with different identifiers can run
+//! in one process; the C layer only ever has one.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use iroh::discovery::UserData;
+use iroh::{Endpoint, NodeId};
+use n0_future::{stream, Stream};
+use tokio::sync::{broadcast, watch};
+use tracing::warn;
+
+use crate::events::{self, EventSink, PeerEvent, PeerStatus, ShutdownReason};
+use crate::privacy;
+use crate::reconnect::Resume;
+use crate::remote_info::RemoteInfoReport;
+use crate::{
+    bind_peer_endpoint, instance, pairing, protocols, run_endpoint, supervise, tasks, PeerOptions,
+};
+
+/// Events buffered for each [`MdnsPeer::events`] stream before the oldest
+/// are dropped
+pub const EVENT_STREAM_CAPACITY: usize = 256;
+
+/// Settings for [`MdnsPeer::builder`]
+#[derive(Default)]
+pub struct MdnsPeerBuilder {
+    identifier: Option<String>,
+    options: PeerOptions,
+    events: Option<EventSink>,
+}
+
+impl MdnsPeerBuilder {
+    /// User data the peer advertises through discovery; required
+    pub fn identifier(mut self, identifier: impl Into<String>) -> Self {
+        self.identifier = Some(identifier.into());
+        self
+    }
+
+    /// Everything else, as for [`run_peer`](crate::run_peer)
+    pub fn options(mut self, options: PeerOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Also pass every event to `events`, starting with
+    /// [`PeerStatus::Starting`]
+    pub fn on_event(mut self, events: EventSink) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Check the settings without binding the endpoint or starting the peer
+    ///
+    /// Catches what [`spawn`](Self::spawn) would only fail on later: a
+    /// missing or invalid identifier, invalid discovery settings, an empty
+    /// relay list, a recording in a directory that doesn't exist, another
+    /// instance advertising the identifier, or a pinned UDP port that is
+    /// taken.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let identifier = self.identifier.as_deref().context("No identifier set")?;
+        identifier
+            .parse::<UserData>()
+            .with_context(|| format!("Invalid identifier {:?}", identifier))?;
+        let options = &self.options;
+        options.mdns.validate()?;
+        if let Some(relays) = &options.relays {
+            anyhow::ensure!(!relays.is_empty(), "No relays configured");
+        }
+        if let Some(warm_up) = &options.warm_up {
+            anyhow::ensure!(!warm_up.alpn.is_empty(), "Warm-up ALPN is empty");
+        }
+        if let Some(dir) = options.record.as_deref().and_then(Path::parent) {
+            anyhow::ensure!(
+                dir.as_os_str().is_empty() || dir.is_dir(),
+                "Directory for the recording {} does not exist",
+                dir.display()
+            );
+        }
+        // Released right away; spawn claims it again
+        drop(instance::claim(
+            identifier,
+            options.on_duplicate,
+            options.secret_key.is_some(),
+        )?);
+        options.bind.check_free()
+    }
+
+    /// Bind the endpoint and run the peer on the current tokio runtime
+    ///
+    /// Fails without spawning anything if the identifier is missing or
+    /// invalid, another instance advertises it (see
+    /// [`PeerOptions::on_duplicate`]), or the endpoint can't bind.
+    pub async fn spawn(self) -> anyhow::Result<MdnsPeer> {
+        let Self {
+            identifier,
+            options,
+            events,
+        } = self;
+        let identifier = identifier.context("No identifier set")?;
+        identifier.parse::<UserData>()?;
+        let instance = instance::claim(
+            &identifier,
+            options.on_duplicate,
+            options.secret_key.is_some(),
+        )?;
+        if instance.identifier() != identifier {
+            warn!(
+                "{} is already running on this machine, advertising {} instead",
+                privacy::redact(&identifier),
+                privacy::redact(instance.identifier())
+            );
+        }
+        let identifier = instance.identifier().to_string();
+
+        let host_events = events.unwrap_or_else(events::discard_events);
+        let (status_tx, status) = watch::channel((PeerStatus::Starting, None));
+        let (stream_tx, _) = broadcast::channel(EVENT_STREAM_CAPACITY);
+        let stream_events = stream_tx.clone();
+        let events: EventSink = Arc::new(move |event| {
+            // Nobody streaming is fine
+            let _ = stream_events.send(event.clone());
+            host_events(event);
+            // Last, so the host has seen the event once `stopped` returns
+            if let PeerEvent::StatusChanged { status, reason } = event {
+                status_tx.send_replace((*status, *reason));
+            }
+        });
+        events(&PeerEvent::status(PeerStatus::Starting));
+
+        let endpoint = match bind_peer_endpoint(&identifier, &options).await {
+            Ok(endpoint) => endpoint,
+            Err((reason, e)) => {
+                events(&PeerEvent::stopped(reason));
+                return Err(e);
+            }
+        };
+        let (shutdown, shutdown_rx) = broadcast::channel(1);
+        let peer = MdnsPeer {
+            identifier,
+            endpoint,
+            shutdown,
+            status,
+            events: stream_tx,
+            protocols: options.protocols.clone(),
+            resume: options.resume.clone(),
+        };
+
+        supervise::install_panic_hook();
+        let identifier = peer.identifier.clone();
+        let endpoint = peer.endpoint.clone();
+        tokio::spawn(async move {
+            let result = supervise::catch_panic(tasks::track(
+                "peer",
+                run_endpoint(&identifier, endpoint, options, shutdown_rx, events.clone()),
+            ))
+            .await;
+            drop(instance);
+
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    warn!("{} error: {}", privacy::redact(&identifier), e);
+                    supervise::report_error("peer", &e, &events);
+                    events(&PeerEvent::stopped(ShutdownReason::Failed));
+                }
+                Err(panic) => {
+                    warn!(
+                        "{} panicked: {}",
+                        privacy::redact(&identifier),
+                        panic.message
+                    );
+                    supervise::report_panic("peer", panic, &events);
+                    events(&PeerEvent::stopped(ShutdownReason::RuntimePanic));
+                }
+            }
+        });
+        Ok(peer)
+    }
+}
+
+/// A running peer, see [`MdnsPeer::builder`]
+///
+/// Clones control the same peer. Dropping every handle leaves it running;
+/// stop it with [`MdnsPeer::shutdown`].
+#[derive(Debug, Clone)]
+pub struct MdnsPeer {
+    identifier: String,
+    endpoint: Endpoint,
+    shutdown: broadcast::Sender<()>,
+    status: watch::Receiver<(PeerStatus, Option<ShutdownReason>)>,
+    events: broadcast::Sender<PeerEvent>,
+    protocols: protocols::Protocols,
+    resume: Resume,
+}
+
+impl MdnsPeer {
+    pub fn builder() -> MdnsPeerBuilder {
+        MdnsPeerBuilder::default()
+    }
+
+    /// The user data being advertised, which differs from the requested
+    /// identifier if [`DuplicatePolicy::Suffix`](crate::instance::DuplicatePolicy::Suffix)
+    /// picked another one
+    pub fn identifier(&self) -> &str {
+        &self.identifier
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        self.endpoint.node_id()
+    }
+
+    /// The peer's endpoint, for dialing or queries of its own
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    pub fn status(&self) -> PeerStatus {
+        self.status.borrow().0
+    }
+
+    /// Why the peer stopped, or `None` while it runs
+    pub fn shutdown_reason(&self) -> Option<ShutdownReason> {
+        self.status.borrow().1
+    }
+
+    /// Every event the peer emits from now on: discoveries, connections,
+    /// messages, errors and status changes
+    ///
+    /// Ends after the final [`PeerStatus::Stopped`] event, or right away if
+    /// the peer already stopped. Each stream buffers up to
+    /// [`EVENT_STREAM_CAPACITY`] events; a consumer that falls further behind
+    /// skips the oldest, with a warning.
+    pub fn events(&self) -> impl Stream<Item = PeerEvent> + Send + Unpin + 'static {
+        let events = self.events.subscribe();
+        let events = (self.status() != PeerStatus::Stopped).then_some(events);
+        Box::pin(stream::unfold(events, |events| async move {
+            let mut events = events?;
+            loop {
+                match events.recv().await {
+                    Ok(event) => {
+                        let last = matches!(
+                            event,
+                            PeerEvent::StatusChanged {
+                                status: PeerStatus::Stopped,
+                                ..
+                            }
+                        );
+                        return Some((event, (!last).then_some(events)));
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!("Event stream fell behind, skipped {} events", missed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        }))
+    }
+
+    /// iroh's view of one peer, with the QUIC stats of its open connections
+    pub fn remote_info(&self, node_id: NodeId) -> Option<RemoteInfoReport> {
+        let mut report = RemoteInfoReport::from(self.endpoint.remote_info(node_id)?);
+        report.quic = self.protocols.quic_stats(node_id);
+        Some(report)
+    }
+
+    /// A pairing payload for this peer, once it has an address to put in
+    /// it, see [`crate::pairing`]
+    pub async fn pairing_payload(&self, timeout: Duration) -> Option<String> {
+        pairing::local_payload(&self.endpoint, timeout).await
+    }
+
+    /// Re-dial trusted peers, e.g. after the app was suspended, see
+    /// [`PeerOptions::warm_up`]
+    pub fn resume(&self) {
+        self.resume.resume();
+    }
+
+    /// Wait until the peer stopped, for whatever reason
+    pub async fn stopped(&self) -> ShutdownReason {
+        let mut status = self.status.clone();
+        let stopped = status
+            .wait_for(|(status, _)| *status == PeerStatus::Stopped)
+            .await
+            .map(|stopped| stopped.1);
+        // The peer task reports a reason on every way out, so a missing one
+        // means it went away without a word
+        stopped.ok().flatten().unwrap_or(ShutdownReason::Failed)
+    }
+
+    /// Stop the peer, closing its endpoint gracefully, and wait until it
+    /// stopped
+    ///
+    /// Returns [`ShutdownReason::HostRequested`], or why the peer had
+    /// already stopped.
+    pub async fn shutdown(&self) -> ShutdownReason {
+        // No receiver left means the peer already stopped
+        let _ = self.shutdown.send(());
+        self.stopped().await
+    }
+}
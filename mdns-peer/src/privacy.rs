@@ -0,0 +1,64 @@
+//! Keeping device names out of logs
+//!
+//! Testers paste logs into public issues, and user data is often a device
+//! name like "Jamie's iPhone". So by default, logs and recordings (see
+//! [`crate::session`]) show user data, identifiers and aliases only as a
+//! short hash:
+//!
+//! ```text
+//! [[[ SUCCESS ]]]: Discovered peer '#3f2a1c9e'!
+//! ```
+//!
+//! The same name gives the same hash on every device, so logs from several
+//! testers still line up. Events reach the app unchanged, and so do the
+//! summaries and reports it asks for. A short, guessable name can be found
+//! by hashing candidates, so this keeps names from being read at a glance,
+//! not from a determined reader.
+//!
+//! Set [`PeerOptions::log_names`](crate::PeerOptions::log_names) (`--log-names`
+//! on the command line, `peer_set_log_names` over FFI) to log names as they
+//! are.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether names are logged as they are, for the whole process
+static LOG_NAMES: AtomicBool = AtomicBool::new(false);
+
+/// Hex digits of the hash shown in place of a name
+const HASH_LEN: usize = 8;
+
+/// Log names as they are (`true`) or hashed (`false`, the default)
+pub fn set_log_names(enabled: bool) {
+    LOG_NAMES.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether names are logged as they are
+pub fn log_names() -> bool {
+    LOG_NAMES.load(Ordering::Relaxed)
+}
+
+/// `name` for a log line: hashed unless [`log_names`] is on
+pub fn redact(name: &str) -> Redacted<'_> {
+    Redacted(name)
+}
+
+/// The hash `name` is shown as, whether or not [`log_names`] is on
+pub fn hash_name(name: &str) -> String {
+    let hash = blake3::derive_key("mdns-peer log name", name.as_bytes());
+    format!("#{}", data_encoding::HEXLOWER.encode(&hash[..HASH_LEN / 2]))
+}
+
+/// A name that displays as its hash unless [`log_names`] is on
+#[derive(Debug, Clone, Copy)]
+pub struct Redacted<'a>(&'a str);
+
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if log_names() {
+            f.write_str(self.0)
+        } else {
+            f.write_str(&hash_name(self.0))
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//! Named identities stored on disk
+//!
+//! A profile pairs a secret key with the user data it advertises, so one
+//! machine can present as several logical devices ("work", "home") without
+//! juggling key files. Profiles live in `<state dir>/profiles/<name>.json`
+//! (see [`crate::state`]):
+//!
+//! ```json
+//! {"secret_key":"9f3c...","user_data":"alice"}
+//! ```
+//!
+//! With a [`SecretStore`] the key is kept there instead and the file only
+//! holds the user data, see [`crate::keystore`].
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Context;
+use iroh::{discovery::UserData, SecretKey};
+use serde::{Deserialize, Serialize};
+
+use crate::keystore::{self, SecretStore};
+use crate::state;
+
+/// A stored identity
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub secret_key: SecretKey,
+    /// Identifier advertised as discovery user data
+    pub user_data: String,
+}
+
+/// On-disk representation, with the key hex encoded unless it's in a
+/// [`SecretStore`]
+#[derive(Serialize, Deserialize)]
+struct ProfileFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    secret_key: Option<String>,
+    user_data: String,
+}
+
+/// Directory of profiles
+#[derive(Clone)]
+pub struct ProfileStore {
+    state_dir: PathBuf,
+    dir: PathBuf,
+    secrets: Option<Arc<dyn SecretStore>>,
+}
+
+impl std::fmt::Debug for ProfileStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProfileStore")
+            .field("dir", &self.dir)
+            .field("secret_store", &self.secrets.is_some())
+            .finish()
+    }
+}
+
+impl ProfileStore {
+    /// Profiles under `state_dir`
+    pub fn new(state_dir: impl AsRef<Path>) -> Self {
+        Self {
+            state_dir: state_dir.as_ref().to_path_buf(),
+            dir: state_dir.as_ref().join("profiles"),
+            secrets: None,
+        }
+    }
+
+    /// Keep secret keys in `secrets` rather than in the profile files,
+    /// moving keys already in files there as their profiles load
+    pub fn with_secret_store(mut self, secrets: Arc<dyn SecretStore>) -> Self {
+        self.secrets = Some(secrets);
+        self
+    }
+
+    /// Profiles under `$MDNS_PEER_HOME`, or `~/.mdns-peer` if that is unset
+    pub fn open_default() -> anyhow::Result<Self> {
+        Ok(Self::new(default_state_dir()?))
+    }
+
+    /// Load profile `name`, creating it with a fresh key if it doesn't exist
+    ///
+    /// If `user_data` is given it replaces the stored user data. New profiles
+    /// without explicit user data advertise their name.
+    pub fn load_or_create(&self, name: &str, user_data: Option<&str>) -> anyhow::Result<Profile> {
+        let path = self.path(name)?;
+        state::migrate(&self.state_dir)?;
+
+        let file: Option<ProfileFile> = match fs::read_to_string(&path) {
+            Ok(contents) => Some(
+                serde_json::from_str(&contents)
+                    .with_context(|| format!("Corrupt profile {}", path.display()))?,
+            ),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+        let in_file = match file.as_ref().and_then(|file| file.secret_key.as_deref()) {
+            Some(key) => Some(
+                key.parse::<SecretKey>()
+                    .with_context(|| format!("Invalid secret key in {}", path.display()))?,
+            ),
+            None => None,
+        };
+
+        let (secret_key, mut dirty) = match &self.secrets {
+            Some(secrets) => {
+                let account = keystore::profile_account(name);
+                let stored = secrets.get(&account).with_context(|| {
+                    format!(
+                        "Failed to read the key of profile {} from the secure store",
+                        name
+                    )
+                })?;
+                match stored {
+                    // A key left in the file is dropped from it
+                    Some(key) => (
+                        secret_key_from(name, &key)?,
+                        file.is_none() || in_file.is_some(),
+                    ),
+                    None => {
+                        anyhow::ensure!(
+                            file.is_none() || in_file.is_some(),
+                            "The secure store has no key for profile {}",
+                            name
+                        );
+                        let key = in_file.unwrap_or_else(|| SecretKey::generate(rand::rngs::OsRng));
+                        secrets.set(&account, &key.to_bytes()).with_context(|| {
+                            format!("Failed to store the key of profile {}", name)
+                        })?;
+                        (key, true)
+                    }
+                }
+            }
+            None => match in_file {
+                Some(key) => (key, false),
+                None => {
+                    anyhow::ensure!(
+                        file.is_none(),
+                        "Profile {} keeps its key in a secure store, which isn't set",
+                        name
+                    );
+                    (SecretKey::generate(rand::rngs::OsRng), true)
+                }
+            },
+        };
+        let mut profile = Profile {
+            name: name.to_string(),
+            secret_key,
+            user_data: file.map_or_else(|| name.to_string(), |file| file.user_data),
+        };
+
+        if let Some(user_data) = user_data.filter(|u| *u != profile.user_data) {
+            profile.user_data = user_data.to_string();
+            dirty = true;
+        }
+        if dirty {
+            self.save(&profile)?;
+        }
+        Ok(profile)
+    }
+
+    /// Give profile `name` a new secret key, keeping its user data, e.g.
+    /// when rotating it (see [`crate::rotation`])
+    pub fn replace_key(&self, name: &str, secret_key: SecretKey) -> anyhow::Result<Profile> {
+        let mut profile = self.load_or_create(name, None)?;
+        profile.secret_key = secret_key;
+        if let Some(secrets) = &self.secrets {
+            secrets
+                .set(
+                    &keystore::profile_account(name),
+                    &profile.secret_key.to_bytes(),
+                )
+                .with_context(|| format!("Failed to store the key of profile {}", name))?;
+        }
+        self.save(&profile)?;
+        Ok(profile)
+    }
+
+    /// Store `profile` as it is, e.g. one brought over from another device,
+    /// unless a profile with its name exists; returns whether it was stored
+    pub fn insert(&self, profile: &Profile) -> anyhow::Result<bool> {
+        let path = self.path(&profile.name)?;
+        state::migrate(&self.state_dir)?;
+        if path.exists() {
+            return Ok(false);
+        }
+        if let Some(secrets) = &self.secrets {
+            secrets
+                .set(
+                    &keystore::profile_account(&profile.name),
+                    &profile.secret_key.to_bytes(),
+                )
+                .with_context(|| format!("Failed to store the key of profile {}", profile.name))?;
+        }
+        self.save(profile)?;
+        Ok(true)
+    }
+
+    /// Names of all stored profiles, sorted
+    pub fn list(&self) -> anyhow::Result<Vec<String>> {
+        state::migrate(&self.state_dir)?;
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut names = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                    names.push(stem.to_string());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn save(&self, profile: &Profile) -> anyhow::Result<()> {
+        profile.user_data.parse::<UserData>()?;
+
+        let file = ProfileFile {
+            secret_key: self
+                .secrets
+                .is_none()
+                .then(|| hex(&profile.secret_key.to_bytes())),
+            user_data: profile.user_data.clone(),
+        };
+        state::write_atomic_private(&self.path(&profile.name)?, &serde_json::to_vec(&file)?)
+    }
+
+    fn path(&self, name: &str) -> anyhow::Result<PathBuf> {
+        // Names become file names, so keep them boring
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        anyhow::ensure!(
+            valid,
+            "Invalid profile name {:?}: use letters, digits, '-' and '_'",
+            name
+        );
+        Ok(self.dir.join(format!("{}.json", name)))
+    }
+}
+
+/// `$MDNS_PEER_HOME`, or `~/.mdns-peer` if that is unset
+pub(crate) fn default_state_dir() -> anyhow::Result<PathBuf> {
+    if let Some(dir) = std::env::var_os("MDNS_PEER_HOME") {
+        return Ok(dir.into());
+    }
+    let home = std::env::var_os("HOME").context("Neither MDNS_PEER_HOME nor HOME is set")?;
+    Ok(Path::new(&home).join(".mdns-peer"))
+}
+
+/// Secret key derived from `seed`, the same on every run and machine
+///
+/// For documentation, screenshots and integration tests that need stable
+/// node IDs. Anyone can derive the key from the seed, so it must never
+/// identify a device that matters.
+pub fn seeded_key(seed: u64) -> SecretKey {
+    SecretKey::from_bytes(&blake3::derive_key(
+        "mdns-peer seeded key",
+        &seed.to_le_bytes(),
+    ))
+}
+
+/// A key as the secure store returned it for profile `name`
+fn secret_key_from(name: &str, bytes: &[u8]) -> anyhow::Result<SecretKey> {
+    let bytes: [u8; 32] = bytes.try_into().map_err(|_| {
+        anyhow::anyhow!(
+            "The key of profile {} in the secure store is {} bytes, not 32",
+            name,
+            bytes.len()
+        )
+    })?;
+    Ok(SecretKey::from_bytes(&bytes))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,130 @@
+//! Wire format of messages and file transfers
+//!
+//! Everything the built-in protocols exchange, apart from the version byte
+//! each stream opens with (see [`crate::version`]) and the bytes of a file
+//! itself, is a frame: the body's length as a big-endian `u32`, then the
+//! body, one of the types below encoded with
+//! [postcard](https://docs.rs/postcard). Postcard's format is fixed by its
+//! spec, so builds for iOS, desktop and Android stay byte-compatible as long
+//! as they agree on these types; changing one means bumping the protocol's
+//! version.
+//!
+//! Not to be confused with [`crate::protocols`], which runs the streams
+//! these frames travel on.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::messages::MessageId;
+
+/// Length of the prefix before every frame body
+pub const PREFIX_LEN: usize = 4;
+
+/// Bytes a [`MessageFrame`] adds around its payload at most: the prefix,
+/// the ID with its length, the sealed flag and the payload's length
+pub const MESSAGE_OVERHEAD: usize = PREFIX_LEN + 17 + 1 + 10;
+
+/// One message, see [`crate::messages`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageFrame {
+    pub id: MessageId,
+    /// `payload` is sealed with the pair's key, see [`crate::seal`]
+    pub sealed: bool,
+    pub payload: Vec<u8>,
+}
+
+/// The receiver's answer to a [`MessageFrame`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MessageReply {
+    /// Delivered, or seen before
+    Ack,
+    /// Over the receiver's rate limit; send it again later
+    Busy,
+    /// Malformed or over the size limit; sending it again won't help
+    Rejected,
+}
+
+/// What the sender says about a file before sending it, see
+/// [`crate::transfer`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileOffer {
+    /// File name, without directories
+    pub name: String,
+    pub size: u64,
+}
+
+/// The receiver's answer to a [`FileOffer`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FileAnswer {
+    /// Send the file from `offset` on; the receiver kept that much from
+    /// earlier attempts
+    Accepted {
+        offset: u64,
+    },
+    Refused,
+}
+
+/// Follows the bytes of a file: its BLAKE3 hash
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileEnd {
+    pub hash: [u8; 32],
+}
+
+/// How a transfer ended for the receiver, its last frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FileOutcome {
+    /// The whole file arrived and matched its hash
+    Complete,
+    /// The stream ended early; what arrived is kept to resume from
+    Incomplete,
+    /// The hash didn't match, so the file was dropped
+    Corrupt,
+}
+
+/// A peer's new node ID, sent from its old one, see [`crate::rotation`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyRotation {
+    pub new_node_id: [u8; 32],
+    /// Ed25519 signature by the new key over both node IDs
+    pub signature: Vec<u8>,
+}
+
+/// `frame` with its length prefix
+pub fn encode<T: Serialize>(frame: &T) -> Vec<u8> {
+    let body = postcard::to_allocvec(frame).expect("frames are always serializable");
+    let mut framed = Vec::with_capacity(PREFIX_LEN + body.len());
+    framed.extend((body.len() as u32).to_be_bytes());
+    framed.extend(body);
+    framed
+}
+
+/// Length of the body following `prefix`
+pub fn body_len(prefix: [u8; PREFIX_LEN]) -> usize {
+    u32::from_be_bytes(prefix) as usize
+}
+
+/// A frame body, which must be exactly one `T`
+pub fn decode_body<T: DeserializeOwned>(body: &[u8]) -> anyhow::Result<T> {
+    let (frame, rest) =
+        postcard::take_from_bytes(body).map_err(|e| anyhow::anyhow!("Malformed frame: {}", e))?;
+    anyhow::ensure!(
+        rest.is_empty(),
+        "Malformed frame: {} bytes left over",
+        rest.len()
+    );
+    Ok(frame)
+}
+
+/// The frame at the start of `bytes` and what follows it, or `None` if
+/// `bytes` doesn't hold all of it yet
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> anyhow::Result<Option<(T, &[u8])>> {
+    let Some((prefix, rest)) = bytes.split_first_chunk::<PREFIX_LEN>() else {
+        return Ok(None);
+    };
+    let len = body_len(*prefix);
+    if rest.len() < len {
+        return Ok(None);
+    }
+    let (body, rest) = rest.split_at(len);
+    Ok(Some((decode_body(body)?, rest)))
+}
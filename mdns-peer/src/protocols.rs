@@ -0,0 +1,1195 @@
+//! Host-defined protocols on top of the endpoint
+//!
+//! The host registers a [`StreamHandler`] per ALPN. Inbound bidirectional
+//! streams on that ALPN, and outbound ones opened with
+//! [`Protocols::open_stream`], are surfaced to the handler as open/data/close
+//! callbacks; the host writes back with [`Protocols::write`] and
+//! [`Protocols::finish`]. This makes the peer a general transport rather than
+//! a fixed demo protocol.
+//!
+//! A stream lives until both directions are done: the remote finished sending
+//! (reported through [`StreamHandler::on_close`]) and the host called
+//! [`Protocols::finish`].
+//!
+//! For payloads too large to hold in memory, [`Protocols::open_send_stream`]
+//! opens a send-only stream instead. The receiver gets it through the same
+//! callbacks, chunk by chunk, and can't write back. The sender writes with
+//! [`Protocols::write_buffered`], which waits while more than
+//! [`MAX_BUFFERED_WRITE`] bytes are still waiting to go out, so neither side
+//! holds more than a window of the payload at a time.
+//!
+//! ALPNs registered with [`Protocols::register_pull`] aren't pushed to
+//! `on_data`; the host pulls incoming data with [`Protocols::read`] at its
+//! own pace instead. Until it does, no more than a couple of chunks are held
+//! and QUIC flow control stops the remote.
+//!
+//! Connections are cached per node and ALPN, in both directions, so a stream
+//! to a node we already talk to skips the QUIC handshake. [`Protocols::connect`]
+//! can establish one ahead of time ("warm-up").
+//!
+//! Inbound connections go through the [`AcceptPolicy`] first, see
+//! [`crate::accept`]. Connections in both directions are reported as they
+//! open, change path and close, see [`crate::connections`], and their QUIC
+//! transport state is available from [`Protocols::quic_stats`].
+//!
+//! Streams on ALPNs given a [`Compatibility`] open with a version byte,
+//! written and checked here so handlers only see the protocol's own data;
+//! a peer speaking an incompatible version is turned away, see
+//! [`crate::version`].
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use iroh::endpoint::{Connection, ConnectionError, RecvStream, SendStream};
+use iroh::protocol::{AcceptError, ProtocolHandler, Router, RouterBuilder};
+use iroh::{Endpoint, NodeAddr, NodeId};
+use tokio::sync::{mpsc, Notify};
+use tracing::{debug, warn};
+
+use crate::accept::{
+    self, AcceptLimits, AcceptPolicy, InboundRequest, QuotaGuard, Quotas, DENIED_ERROR_CODE,
+    LIMIT_ERROR_CODE,
+};
+use crate::connections::{self, ConnectionTracker, Direction};
+use crate::deadline;
+use crate::events::{discard_events, EventSink, PeerEvent};
+use crate::limits::{RateLimiter, SlotGuard, StreamSlots, TransferLimits};
+use crate::quic::{QuicStats, RttVariance, RTT_SAMPLE_INTERVAL};
+use crate::remote_info::ConnectionReport;
+use crate::stats::{StatsReport, Traffic};
+use crate::supervise;
+use crate::version::{Compatibility, VERSION_ERROR_CODE};
+
+/// Identifies one stream across callbacks, never 0
+pub type StreamId = u64;
+
+/// Connections and flow state are tracked per remote node and ALPN
+type ConnectionKey = (NodeId, Vec<u8>);
+
+/// Largest chunk handed to [`StreamHandler::on_data`] at once
+const MAX_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Writes are split into chunks of this size and paced individually
+const WRITE_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Bytes queued on one stream beyond which [`Protocols::write_buffered`]
+/// waits for them to be sent
+pub const MAX_BUFFERED_WRITE: usize = 1024 * 1024;
+
+/// Receives stream activity for one registered ALPN
+///
+/// Callbacks run on the tokio runtime, so they should return quickly.
+pub trait StreamHandler: Send + Sync + 'static {
+    /// A stream with `node_id` is ready; for inbound streams this happens
+    /// once the remote sends its first data
+    fn on_open(&self, stream: StreamId, node_id: NodeId);
+    /// Data arrived on `stream`; not called for ALPNs registered with
+    /// [`Protocols::register_pull`]
+    fn on_data(&self, stream: StreamId, data: &[u8]);
+    /// The remote finished sending (`error` is `None`), or the stream failed
+    ///
+    /// After a clean close the host may still write and must call
+    /// [`Protocols::finish`]; after an error the stream is gone. Send-only
+    /// streams are done after either: inbound ones once the remote finished,
+    /// outbound ones once the remote received everything.
+    fn on_close(&self, stream: StreamId, error: Option<&str>);
+}
+
+enum StreamCommand {
+    Write(Bytes),
+    Finish,
+}
+
+/// Incoming data of a stream on a pulled ALPN, waiting for
+/// [`Protocols::read`]
+struct PullReader {
+    /// Chunks from the stream, closed at its end
+    chunks: mpsc::Receiver<Bytes>,
+    /// Rest of the last chunk, beyond what the host asked for
+    pending: Bytes,
+}
+
+/// Sending side of a stream the host writes to
+struct StreamEntry {
+    commands: mpsc::UnboundedSender<StreamCommand>,
+    buffered: Arc<WriteBuffer>,
+}
+
+/// Bytes written to a stream but not sent yet
+#[derive(Debug, Default)]
+struct WriteBuffer {
+    bytes: AtomicUsize,
+    /// Signalled whenever `bytes` drops or the stream goes away
+    drained: Notify,
+}
+
+impl WriteBuffer {
+    fn sent(&self, len: usize) {
+        self.bytes.fetch_sub(len, Ordering::Relaxed);
+        self.drained.notify_waiters();
+    }
+}
+
+/// Registered protocols and the streams currently open on them
+#[derive(Clone, Default)]
+pub struct Protocols {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    handlers: Mutex<HashMap<Vec<u8>, Arc<dyn StreamHandler>>>,
+    /// ALPNs whose incoming data the host reads with [`Protocols::read`]
+    pulled: Mutex<HashSet<Vec<u8>>>,
+    /// Versioned ALPNs and what they speak
+    versions: Mutex<HashMap<Vec<u8>, Compatibility>>,
+    /// Readers of open streams on pulled ALPNs
+    pulls: Mutex<HashMap<StreamId, Arc<tokio::sync::Mutex<PullReader>>>>,
+    streams: Mutex<HashMap<StreamId, StreamEntry>>,
+    /// Live connections by remote node and ALPN
+    connections: Mutex<HashMap<ConnectionKey, Connection>>,
+    /// RTT variance of live connections by stable ID
+    rtt: Mutex<HashMap<usize, RttVariance>>,
+    /// Endpoint the router was spawned on, for connection types in stats
+    endpoint: Mutex<Option<Endpoint>>,
+    traffic: Traffic,
+    limits: Mutex<TransferLimits>,
+    /// Pacing and stream slots per remote node and ALPN
+    flow: Mutex<HashMap<ConnectionKey, Arc<Flow>>>,
+    /// Where panics in connection and stream tasks are reported
+    events: Mutex<Option<EventSink>>,
+    /// Peers reported as trusted in inbound connection events
+    trusted: Mutex<HashSet<NodeId>>,
+    /// Decides on inbound connections, accepting all if `None`
+    accept_policy: Mutex<Option<Arc<dyn AcceptPolicy>>>,
+    accept_limits: Mutex<AcceptLimits>,
+    /// What remote peers hold open against `accept_limits`
+    quotas: Arc<Quotas>,
+    /// Open connections per peer, for path change events
+    tracker: ConnectionTracker,
+    /// Relays are disabled, so failed dials are reported as refusing one
+    lan_only: AtomicBool,
+    next_id: AtomicU64,
+}
+
+/// Flow control state shared by the streams of one connection
+#[derive(Debug, Default)]
+struct Flow {
+    rate: RateLimiter,
+    slots: Arc<StreamSlots>,
+}
+
+impl std::fmt::Debug for Protocols {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let handlers = self.inner.handlers.lock().unwrap();
+        let alpns: Vec<_> = handlers
+            .keys()
+            .map(|a| String::from_utf8_lossy(a))
+            .collect();
+        f.debug_struct("Protocols").field("alpns", &alpns).finish()
+    }
+}
+
+impl Protocols {
+    /// Handle streams on `alpn` with `handler`, replacing any previous one
+    ///
+    /// Inbound connections are only accepted for ALPNs registered before the
+    /// router is spawned, i.e. before the peer starts.
+    pub fn register(&self, alpn: impl Into<Vec<u8>>, handler: Arc<dyn StreamHandler>) {
+        let alpn = alpn.into();
+        self.inner.pulled.lock().unwrap().remove(&alpn);
+        self.inner.handlers.lock().unwrap().insert(alpn, handler);
+    }
+
+    /// Like [`Protocols::register`], but incoming data on `alpn` waits for
+    /// [`Protocols::read`] rather than going to the handler's `on_data`
+    pub fn register_pull(&self, alpn: impl Into<Vec<u8>>, handler: Arc<dyn StreamHandler>) {
+        let alpn = alpn.into();
+        self.inner.pulled.lock().unwrap().insert(alpn.clone());
+        self.inner.handlers.lock().unwrap().insert(alpn, handler);
+    }
+
+    /// Open streams on `compatibility.alpn` with its version byte and turn
+    /// away peers that speak an incompatible one, see [`crate::version`]
+    pub fn set_compatibility(&self, compatibility: Compatibility) {
+        let alpn = compatibility.alpn.to_vec();
+        self.inner
+            .versions
+            .lock()
+            .unwrap()
+            .insert(alpn, compatibility);
+    }
+
+    fn compatibility(&self, alpn: &[u8]) -> Option<Compatibility> {
+        self.inner.versions.lock().unwrap().get(alpn).copied()
+    }
+
+    /// Report panics in connection and stream tasks to `events`
+    pub fn set_event_sink(&self, events: EventSink) {
+        *self.inner.events.lock().unwrap() = Some(events);
+    }
+
+    /// Replace the set of trusted peers
+    pub fn set_trusted(&self, trusted: HashSet<NodeId>) {
+        *self.inner.trusted.lock().unwrap() = trusted;
+    }
+
+    /// Trust `new` instead of `old`, returning whether `old` was trusted
+    pub fn replace_trusted(&self, old: NodeId, new: NodeId) -> bool {
+        let mut trusted = self.inner.trusted.lock().unwrap();
+        let replaced = trusted.remove(&old);
+        if replaced {
+            trusted.insert(new);
+        }
+        replaced
+    }
+
+    /// Whether `node_id` is in the trusted set
+    pub fn is_trusted(&self, node_id: NodeId) -> bool {
+        self.inner.trusted.lock().unwrap().contains(&node_id)
+    }
+
+    /// Decide on inbound connections with `policy`, or accept all with `None`
+    ///
+    /// Applies to connections arriving from now on.
+    pub fn set_accept_policy(&self, policy: Option<Arc<dyn AcceptPolicy>>) {
+        *self.inner.accept_policy.lock().unwrap() = policy;
+    }
+
+    /// Cap what remote peers may open on this one
+    ///
+    /// Applies to connections and streams arriving from now on; those
+    /// already open are left alone.
+    pub fn set_accept_limits(&self, limits: AcceptLimits) {
+        *self.inner.accept_limits.lock().unwrap() = limits;
+    }
+
+    /// Report dials that fail as [`PeerEvent::RelayRefused`], for endpoints
+    /// bound without relays, see [`crate::relay`]
+    pub fn set_lan_only(&self, lan_only: bool) {
+        self.inner.lan_only.store(lan_only, Ordering::Relaxed);
+    }
+
+    pub fn accept_limits(&self) -> AcceptLimits {
+        *self.inner.accept_limits.lock().unwrap()
+    }
+
+    /// Whether to serve an inbound connection from `node_id` on `alpn`,
+    /// reporting the decision as a [`PeerEvent::InboundConnection`] and
+    /// closing `conn` if it is refused
+    ///
+    /// An admitted connection holds its quota until the guard is dropped.
+    async fn admit(&self, conn: &Connection, node_id: NodeId, alpn: &[u8]) -> Option<QuotaGuard> {
+        let trusted = self.is_trusted(node_id);
+        let limit = self.accept_limits().max_inbound_connections;
+        let Some(quota) = self.inner.quotas.try_connection(limit) else {
+            warn!(
+                "Refusing connection from {}: {} inbound connections already open",
+                node_id.fmt_short(),
+                limit.unwrap_or_default()
+            );
+            self.report_inbound(node_id, alpn, trusted, false);
+            conn.close(LIMIT_ERROR_CODE.into(), b"too many connections");
+            return None;
+        };
+        let policy = self.inner.accept_policy.lock().unwrap().clone();
+        let accepted = match policy {
+            Some(policy) => {
+                let request = InboundRequest {
+                    node_id,
+                    alpn: alpn.to_vec(),
+                    trusted,
+                };
+                accept::decide(policy.as_ref(), request).await
+            }
+            None => true,
+        };
+        self.report_inbound(node_id, alpn, trusted, accepted);
+        if !accepted {
+            conn.close(DENIED_ERROR_CODE.into(), b"not accepted");
+            return None;
+        }
+        Some(quota)
+    }
+
+    fn report_inbound(&self, node_id: NodeId, alpn: &[u8], trusted: bool, accepted: bool) {
+        (self.events())(&PeerEvent::InboundConnection {
+            node_id,
+            alpn: String::from_utf8_lossy(alpn).into_owned(),
+            trusted,
+            accepted,
+        });
+    }
+
+    pub(crate) fn events(&self) -> EventSink {
+        self.inner
+            .events
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap_or_else(discard_events)
+    }
+
+    /// Spawn `future`, reporting a panic inside it
+    fn spawn(
+        &self,
+        task: &'static str,
+        future: impl std::future::Future<Output = ()> + Send + 'static,
+    ) {
+        supervise::spawn_supervised(task, self.events(), future);
+    }
+
+    /// Accept connections for every registered ALPN on `endpoint`
+    ///
+    /// Shutting the router down also closes the endpoint.
+    pub fn spawn_router(&self, endpoint: Endpoint) -> Router {
+        self.spawn_router_with(endpoint, |builder| builder)
+    }
+
+    /// Like [`Protocols::spawn_router`], letting `extra` accept more ALPNs
+    /// with handlers of their own, such as gossip
+    pub fn spawn_router_with(
+        &self,
+        endpoint: Endpoint,
+        extra: impl FnOnce(RouterBuilder) -> RouterBuilder,
+    ) -> Router {
+        self.attach_endpoint(endpoint.clone());
+        let alpns: Vec<_> = self
+            .inner
+            .handlers
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect();
+        let mut builder = Router::builder(endpoint);
+        for alpn in alpns {
+            let acceptor = Acceptor {
+                alpn: alpn.clone(),
+                protocols: self.clone(),
+            };
+            builder = builder.accept(alpn, acceptor);
+        }
+        extra(builder).spawn()
+    }
+
+    /// Open streams on `endpoint` without accepting any, for a peer that
+    /// only dials out (see [`PeerOptions::lite`](crate::PeerOptions::lite))
+    pub fn attach_endpoint(&self, endpoint: Endpoint) {
+        *self.inner.endpoint.lock().unwrap() = Some(endpoint);
+    }
+
+    /// Forget the endpoint, its connections and the event sink, once the
+    /// peer stopped
+    pub fn detach(&self) {
+        self.inner.endpoint.lock().unwrap().take();
+        self.inner.connections.lock().unwrap().clear();
+        self.inner.events.lock().unwrap().take();
+    }
+
+    /// The endpoint the router was spawned on, once the peer is running
+    pub fn endpoint(&self) -> Option<Endpoint> {
+        self.inner.endpoint.lock().unwrap().clone()
+    }
+
+    /// Open a stream to `node` on a registered `alpn`
+    ///
+    /// Returns immediately; the handler's `on_open` fires once the stream is
+    /// established, or `on_close` with an error if connecting fails. Writes
+    /// issued before that are queued. Must be called within a tokio runtime.
+    pub fn open_stream(
+        &self,
+        endpoint: &Endpoint,
+        node: impl Into<NodeAddr>,
+        alpn: &[u8],
+    ) -> anyhow::Result<StreamId> {
+        self.open_stream_within(endpoint, node, alpn, None)
+    }
+
+    /// Like [`Protocols::open_stream`], closing the stream with a
+    /// [`TimedOut`](crate::deadline::TimedOut) error if it isn't open
+    /// within `timeout`
+    pub fn open_stream_within(
+        &self,
+        endpoint: &Endpoint,
+        node: impl Into<NodeAddr>,
+        alpn: &[u8],
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<StreamId> {
+        let handler = self.handler(alpn).ok_or_else(|| {
+            anyhow::anyhow!(
+                "No protocol registered for {:?}",
+                String::from_utf8_lossy(alpn)
+            )
+        })?;
+        let (id, commands) = self.add_stream();
+
+        let node = node.into();
+        let endpoint = endpoint.clone();
+        let alpn = alpn.to_vec();
+        let protocols = self.clone();
+        self.spawn("open_stream", async move {
+            let node_id = node.node_id;
+            let opened = deadline::within(timeout, "open a stream", async {
+                let conn = protocols.connect(&endpoint, node, &alpn).await?;
+                let slot = protocols.acquire_slot(node_id, &alpn).await;
+                let (mut send, recv) = conn.open_bi().await?;
+                protocols.write_version(&alpn, &mut send).await?;
+                anyhow::Ok((conn, send, recv, slot))
+            })
+            .await;
+
+            match opened {
+                Ok((conn, send, recv, _slot)) => {
+                    protocols
+                        .run_stream(id, &alpn, handler, conn, send, recv, commands)
+                        .await
+                }
+                Err(e) => {
+                    protocols.remove_stream(id);
+                    handler.on_close(id, Some(&format!("{:#}", e)));
+                }
+            }
+        });
+
+        Ok(id)
+    }
+
+    /// Open a send-only stream to `node` on a registered `alpn`
+    ///
+    /// Like [`Protocols::open_stream`], but nothing is read back: the
+    /// handler's `on_close` fires once the remote received everything up to
+    /// [`Protocols::finish`], or with an error if the stream failed.
+    pub fn open_send_stream(
+        &self,
+        endpoint: &Endpoint,
+        node: impl Into<NodeAddr>,
+        alpn: &[u8],
+    ) -> anyhow::Result<StreamId> {
+        self.open_send_stream_within(endpoint, node, alpn, None)
+    }
+
+    /// Like [`Protocols::open_send_stream`], with a timeout for opening the
+    /// stream as in [`Protocols::open_stream_within`]
+    pub fn open_send_stream_within(
+        &self,
+        endpoint: &Endpoint,
+        node: impl Into<NodeAddr>,
+        alpn: &[u8],
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<StreamId> {
+        let handler = self.handler(alpn).ok_or_else(|| {
+            anyhow::anyhow!(
+                "No protocol registered for {:?}",
+                String::from_utf8_lossy(alpn)
+            )
+        })?;
+        let (id, commands) = self.add_stream();
+
+        let node = node.into();
+        let endpoint = endpoint.clone();
+        let alpn = alpn.to_vec();
+        let protocols = self.clone();
+        self.spawn("open_send_stream", async move {
+            let node_id = node.node_id;
+            let opened = deadline::within(timeout, "open a stream", async {
+                let conn = protocols.connect(&endpoint, node, &alpn).await?;
+                let slot = protocols.acquire_slot(node_id, &alpn).await;
+                let mut send = conn.open_uni().await?;
+                protocols.write_version(&alpn, &mut send).await?;
+                anyhow::Ok((send, slot))
+            })
+            .await;
+
+            let sent = match opened {
+                Ok((send, _slot)) => {
+                    handler.on_open(id, node_id);
+                    protocols
+                        .write_stream(id, node_id, &alpn, send, commands)
+                        .await
+                }
+                Err(e) => Err(e),
+            };
+            protocols.remove_stream(id);
+            match sent {
+                Ok(()) => handler.on_close(id, None),
+                Err(e) => handler.on_close(id, Some(&format!("{:#}", e))),
+            }
+        });
+
+        Ok(id)
+    }
+
+    /// A connection to `node` on `alpn`, reusing a live one if there is one
+    ///
+    /// New connections are cached and serve inbound streams as well, so
+    /// calling this ahead of time saves the handshake on the first stream.
+    pub async fn connect(
+        &self,
+        endpoint: &Endpoint,
+        node: impl Into<NodeAddr>,
+        alpn: &[u8],
+    ) -> anyhow::Result<Connection> {
+        self.connect_within(endpoint, node, alpn, None).await
+    }
+
+    /// Like [`Protocols::connect`], failing with
+    /// [`TimedOut`](crate::deadline::TimedOut) if the handshake takes
+    /// longer than `timeout`
+    pub async fn connect_within(
+        &self,
+        endpoint: &Endpoint,
+        node: impl Into<NodeAddr>,
+        alpn: &[u8],
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<Connection> {
+        let node = node.into();
+        if let Some(conn) = self.cached_connection(node.node_id, alpn) {
+            return Ok(conn);
+        }
+
+        let connecting = async { Ok(endpoint.connect(node.clone(), alpn).await?) };
+        let conn = match deadline::within(timeout, "connect", connecting).await {
+            Ok(conn) => conn,
+            Err(e) if deadline::is_timed_out(&e) => return Err(e),
+            Err(e) if self.inner.lan_only.load(Ordering::Relaxed) => {
+                let reason = format!("{:#}", e);
+                (self.events())(&PeerEvent::RelayRefused {
+                    node_id: node.node_id,
+                    alpn: String::from_utf8_lossy(alpn).into_owned(),
+                    reason: reason.clone(),
+                });
+                anyhow::bail!(
+                    "No direct path to {} and relays are disabled: {}",
+                    node.node_id.fmt_short(),
+                    reason
+                );
+            }
+            Err(e) => return Err(e),
+        };
+        self.inner
+            .connections
+            .lock()
+            .unwrap()
+            .insert((node.node_id, alpn.to_vec()), conn.clone());
+        let protocols = self.clone();
+        let alpn = alpn.to_vec();
+        let served = conn.clone();
+        self.spawn("accept", async move {
+            protocols
+                .serve_connection(&alpn, served, Direction::Outbound)
+                .await
+        });
+        Ok(conn)
+    }
+
+    /// Whether a live connection to `node_id` on `alpn` is cached
+    pub fn is_connected(&self, node_id: NodeId, alpn: &[u8]) -> bool {
+        self.cached_connection(node_id, alpn).is_some()
+    }
+
+    fn cached_connection(&self, node_id: NodeId, alpn: &[u8]) -> Option<Connection> {
+        let connections = self.inner.connections.lock().unwrap();
+        connections
+            .get(&(node_id, alpn.to_vec()))
+            .filter(|conn| conn.close_reason().is_none())
+            .cloned()
+    }
+
+    /// Cache `conn` and run every stream the remote opens on it, until it
+    /// closes, reporting it opening and closing
+    async fn serve_connection(&self, alpn: &[u8], conn: Connection, direction: Direction) {
+        let Ok(node_id) = conn.remote_node_id() else {
+            return;
+        };
+        let key = (node_id, alpn.to_vec());
+        self.inner
+            .connections
+            .lock()
+            .unwrap()
+            .insert(key.clone(), conn.clone());
+        let protocols = self.clone();
+        let sampled = conn.clone();
+        self.spawn(
+            "rtt_sample",
+            async move { protocols.sample_rtt(sampled).await },
+        );
+
+        let events = self.events();
+        let endpoint = self.endpoint();
+        let connection = match &endpoint {
+            Some(endpoint) => {
+                self.inner.tracker.opened(endpoint, node_id, events.clone());
+                connections::current_path(endpoint, node_id)
+            }
+            None => ConnectionReport::None,
+        };
+        events(&PeerEvent::Connected {
+            node_id,
+            alpn: String::from_utf8_lossy(alpn).into_owned(),
+            direction,
+            connection,
+        });
+
+        loop {
+            let (send, mut recv) = tokio::select! {
+                bi = conn.accept_bi() => match bi {
+                    Ok((send, recv)) => (Some(send), recv),
+                    Err(_) => break,
+                },
+                uni = conn.accept_uni() => match uni {
+                    Ok(recv) => (None, recv),
+                    Err(_) => break,
+                },
+            };
+            let Some(handler) = self.handler(alpn) else {
+                break;
+            };
+            let limit = self.accept_limits().max_streams_per_peer;
+            let Some(quota) = self.inner.quotas.try_stream(node_id, limit) else {
+                debug!(
+                    "Refusing stream from {}: {} streams already open",
+                    node_id.fmt_short(),
+                    limit.unwrap_or_default()
+                );
+                if let Some(mut send) = send {
+                    let _ = send.reset(LIMIT_ERROR_CODE.into());
+                }
+                let _ = recv.stop(LIMIT_ERROR_CODE.into());
+                continue;
+            };
+            let protocols = self.clone();
+            let conn = conn.clone();
+            let alpn = alpn.to_vec();
+            match send {
+                Some(send) => {
+                    let (id, commands) = self.add_stream();
+                    self.spawn("stream", async move {
+                        let _quota = quota;
+                        if !protocols
+                            .read_version(&conn, node_id, &alpn, &mut recv)
+                            .await
+                        {
+                            protocols.remove_stream(id);
+                            return;
+                        }
+                        // The stream only starts once a slot is free
+                        let _slot = protocols.acquire_slot(node_id, &alpn).await;
+                        protocols
+                            .run_stream(id, &alpn, handler, conn, send, recv, commands)
+                            .await
+                    });
+                }
+                None => {
+                    let id = self.next_stream_id();
+                    self.spawn("stream", async move {
+                        let _quota = quota;
+                        if !protocols
+                            .read_version(&conn, node_id, &alpn, &mut recv)
+                            .await
+                        {
+                            return;
+                        }
+                        let _slot = protocols.acquire_slot(node_id, &alpn).await;
+                        let pull = protocols.start_pull(id, &alpn);
+                        handler.on_open(id, node_id);
+                        protocols
+                            .read_stream(id, node_id, &alpn, handler.as_ref(), recv, pull)
+                            .await;
+                        debug!("Stream {} from {} done", id, node_id.fmt_short());
+                    });
+                }
+            }
+        }
+
+        // Only forget the connection if it wasn't replaced meanwhile
+        {
+            let mut connections = self.inner.connections.lock().unwrap();
+            if connections
+                .get(&key)
+                .is_some_and(|cached| cached.stable_id() == conn.stable_id())
+            {
+                connections.remove(&key);
+            }
+        }
+
+        let reason = conn.closed().await;
+        if endpoint.is_some() {
+            self.inner.tracker.closed(node_id);
+        }
+        // The remote turned away the version this peer sent
+        if let (ConnectionError::ApplicationClosed(close), Some(compatibility)) =
+            (&reason, self.compatibility(alpn))
+        {
+            if close.error_code == VERSION_ERROR_CODE.into() {
+                events(&PeerEvent::VersionMismatch {
+                    node_id,
+                    alpn: String::from_utf8_lossy(alpn).into_owned(),
+                    local_version: compatibility.version,
+                    remote_version: None,
+                    reason: String::from_utf8_lossy(&close.reason).into_owned(),
+                });
+            }
+        }
+        events(&PeerEvent::ConnectionClosed {
+            node_id,
+            alpn: String::from_utf8_lossy(alpn).into_owned(),
+            direction,
+            reason: reason.to_string(),
+        });
+    }
+
+    /// Open an outbound stream on a versioned ALPN with its version byte
+    async fn write_version(&self, alpn: &[u8], send: &mut SendStream) -> anyhow::Result<()> {
+        if let Some(compatibility) = self.compatibility(alpn) {
+            send.write_all(&[compatibility.version]).await?;
+        }
+        Ok(())
+    }
+
+    /// Read the version byte an inbound stream on a versioned ALPN opens
+    /// with, closing the connection if this peer doesn't read that version
+    ///
+    /// Returns whether the stream goes on.
+    async fn read_version(
+        &self,
+        conn: &Connection,
+        node_id: NodeId,
+        alpn: &[u8],
+        recv: &mut RecvStream,
+    ) -> bool {
+        let Some(compatibility) = self.compatibility(alpn) else {
+            return true;
+        };
+        let mut version = [0u8];
+        if let Err(e) = recv.read_exact(&mut version).await {
+            debug!(
+                "Stream from {} ended before its version: {}",
+                node_id.fmt_short(),
+                e
+            );
+            return false;
+        }
+        let [version] = version;
+        if compatibility.accepts(version) {
+            return true;
+        }
+        let reason = compatibility.mismatch(version);
+        self.reject_version(conn, node_id, alpn, &compatibility, version, reason);
+        false
+    }
+
+    /// Close `conn` over a version mismatch, reporting it
+    fn reject_version(
+        &self,
+        conn: &Connection,
+        node_id: NodeId,
+        alpn: &[u8],
+        compatibility: &Compatibility,
+        remote_version: u8,
+        reason: String,
+    ) {
+        conn.close(VERSION_ERROR_CODE.into(), reason.as_bytes());
+        (self.events())(&PeerEvent::VersionMismatch {
+            node_id,
+            alpn: String::from_utf8_lossy(alpn).into_owned(),
+            local_version: compatibility.version,
+            remote_version: Some(remote_version),
+            reason,
+        });
+    }
+
+    /// Sample the RTT of `conn` until it closes
+    async fn sample_rtt(&self, conn: Connection) {
+        let id = conn.stable_id();
+        let mut ticks = crate::suspend::interval(RTT_SAMPLE_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = conn.closed() => break,
+                _ = ticks.tick() => {
+                    let mut rtt = self.inner.rtt.lock().unwrap();
+                    rtt.entry(id).or_default().sample(conn.rtt());
+                }
+            }
+        }
+        self.inner.rtt.lock().unwrap().remove(&id);
+    }
+
+    /// QUIC transport state of the live connections to `node_id`, one per
+    /// ALPN
+    pub fn quic_stats(&self, node_id: NodeId) -> Vec<QuicStats> {
+        let connections: Vec<_> = {
+            let connections = self.inner.connections.lock().unwrap();
+            connections
+                .iter()
+                .filter(|((node, _), conn)| *node == node_id && conn.close_reason().is_none())
+                .map(|((_, alpn), conn)| (alpn.clone(), conn.clone()))
+                .collect()
+        };
+        let rtt = self.inner.rtt.lock().unwrap();
+        let mut stats: Vec<_> = connections
+            .iter()
+            .map(|(alpn, conn)| {
+                let variance = rtt.get(&conn.stable_id()).copied().unwrap_or_default();
+                QuicStats::new(alpn, &conn.stats(), &variance)
+            })
+            .collect();
+        stats.sort_by(|a, b| a.alpn.cmp(&b.alpn));
+        stats
+    }
+
+    /// Change the flow control limits, including for streams already open
+    pub fn set_limits(&self, limits: TransferLimits) {
+        *self.inner.limits.lock().unwrap() = limits;
+        for flow in self.inner.flow.lock().unwrap().values() {
+            flow.slots.limit_changed();
+        }
+    }
+
+    /// Current flow control limits
+    pub fn limits(&self) -> TransferLimits {
+        *self.inner.limits.lock().unwrap()
+    }
+
+    fn flow(&self, node_id: NodeId, alpn: &[u8]) -> Arc<Flow> {
+        let mut flow = self.inner.flow.lock().unwrap();
+        flow.entry((node_id, alpn.to_vec())).or_default().clone()
+    }
+
+    async fn acquire_slot(&self, node_id: NodeId, alpn: &[u8]) -> SlotGuard {
+        let slots = self.flow(node_id, alpn).slots.clone();
+        slots.acquire(|| self.limits().max_concurrent_streams).await
+    }
+
+    /// Bytes exchanged per peer and ALPN, with rolling rates
+    pub fn stats(&self) -> StatsReport {
+        let mut report = self.inner.traffic.report(self.endpoint().as_ref());
+        for peer in &mut report.peers {
+            peer.quic = self.quic_stats(peer.node_id);
+        }
+        report
+    }
+
+    /// Up to `max` bytes of incoming data on `stream`, waiting for some to
+    /// arrive
+    ///
+    /// Only for streams on ALPNs registered with [`Protocols::register_pull`].
+    /// Returns `None` once the remote finished sending and everything was
+    /// read, and an error if the stream failed or isn't read by pulling.
+    pub async fn read(&self, stream: StreamId, max: usize) -> anyhow::Result<Option<Bytes>> {
+        let reader = self.inner.pulls.lock().unwrap().get(&stream).cloned();
+        let Some(reader) = reader else {
+            anyhow::bail!("Stream {} is unknown or not read by pulling", stream);
+        };
+        let mut reader = reader.lock().await;
+        if reader.pending.is_empty() {
+            match reader.chunks.recv().await {
+                Some(chunk) => reader.pending = chunk,
+                // A failed stream is forgotten before its chunks end
+                None if self.inner.pulls.lock().unwrap().remove(&stream).is_some() => {
+                    return Ok(None)
+                }
+                None => anyhow::bail!("Stream {} failed", stream),
+            }
+        }
+        let len = max.min(reader.pending.len());
+        Ok(Some(reader.pending.split_to(len)))
+    }
+
+    /// Queue `data` to be sent on `stream`
+    ///
+    /// `data` isn't copied: QUIC holds on to it until the remote
+    /// acknowledged it, so [`Bytes::from_owner`] can lend a buffer that is
+    /// released once every byte arrived or the stream is gone. Returns false
+    /// if the stream is unknown or already finished.
+    pub fn write(&self, stream: StreamId, data: impl Into<Bytes>) -> bool {
+        let data = data.into();
+        let streams = self.inner.streams.lock().unwrap();
+        let Some(entry) = streams.get(&stream) else {
+            return false;
+        };
+        let len = data.len();
+        entry.buffered.bytes.fetch_add(len, Ordering::Relaxed);
+        if entry.commands.send(StreamCommand::Write(data)).is_err() {
+            entry.buffered.sent(len);
+            return false;
+        }
+        true
+    }
+
+    /// Queue `data` to be sent on `stream`, first waiting until no more than
+    /// [`MAX_BUFFERED_WRITE`] bytes are still queued on it
+    ///
+    /// Returns false if the stream is unknown, already finished, or fails
+    /// while waiting.
+    pub async fn write_buffered(&self, stream: StreamId, data: impl Into<Bytes>) -> bool {
+        let data = data.into();
+        let buffered = match self.inner.streams.lock().unwrap().get(&stream) {
+            Some(entry) => entry.buffered.clone(),
+            None => return false,
+        };
+        loop {
+            let drained = buffered.drained.notified();
+            tokio::pin!(drained);
+            drained.as_mut().enable();
+            let open = (self.inner.streams.lock().unwrap().get(&stream))
+                .is_some_and(|entry| !entry.commands.is_closed());
+            if !open {
+                return false;
+            }
+            if buffered.bytes.load(Ordering::Relaxed) < MAX_BUFFERED_WRITE {
+                return self.write(stream, data);
+            }
+            drained.await;
+        }
+    }
+
+    /// Finish the sending side of `stream` once queued writes are sent
+    pub fn finish(&self, stream: StreamId) -> bool {
+        self.send_command(stream, StreamCommand::Finish)
+    }
+
+    /// Give up on `stream` without waiting for the remote
+    ///
+    /// No more writes are taken and the sending side finishes. On pulled
+    /// ALPNs incoming data is no longer read, so the remote's writes fail;
+    /// otherwise it keeps going to the handler until the remote stops.
+    pub fn close(&self, stream: StreamId) -> bool {
+        let pulled = self.inner.pulls.lock().unwrap().remove(&stream).is_some();
+        let open = self.inner.streams.lock().unwrap().contains_key(&stream);
+        self.remove_stream(stream);
+        pulled || open
+    }
+
+    fn send_command(&self, stream: StreamId, command: StreamCommand) -> bool {
+        let streams = self.inner.streams.lock().unwrap();
+        streams
+            .get(&stream)
+            .is_some_and(|entry| entry.commands.send(command).is_ok())
+    }
+
+    fn handler(&self, alpn: &[u8]) -> Option<Arc<dyn StreamHandler>> {
+        self.inner.handlers.lock().unwrap().get(alpn).cloned()
+    }
+
+    fn next_stream_id(&self) -> StreamId {
+        self.inner.next_id.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn add_stream(&self) -> (StreamId, mpsc::UnboundedReceiver<StreamCommand>) {
+        let id = self.next_stream_id();
+        let (commands, rx) = mpsc::unbounded_channel();
+        let entry = StreamEntry {
+            commands,
+            buffered: Default::default(),
+        };
+        self.inner.streams.lock().unwrap().insert(id, entry);
+        (id, rx)
+    }
+
+    fn remove_stream(&self, id: StreamId) {
+        let removed = self.inner.streams.lock().unwrap().remove(&id);
+        // Writers waiting for room find the stream gone
+        if let Some(entry) = removed {
+            entry.buffered.drained.notify_waiters();
+        }
+    }
+
+    /// Pump one established stream until both directions are done
+    #[allow(clippy::too_many_arguments)]
+    async fn run_stream(
+        &self,
+        id: StreamId,
+        alpn: &[u8],
+        handler: Arc<dyn StreamHandler>,
+        conn: Connection,
+        send: SendStream,
+        recv: RecvStream,
+        commands: mpsc::UnboundedReceiver<StreamCommand>,
+    ) {
+        let node_id = match conn.remote_node_id() {
+            Ok(node_id) => node_id,
+            Err(e) => {
+                self.remove_stream(id);
+                handler.on_close(id, Some(&e.to_string()));
+                return;
+            }
+        };
+        // Readable from `on_open` on, which is where hosts start pulling
+        let pull = self.start_pull(id, alpn);
+        handler.on_open(id, node_id);
+
+        let writer = async {
+            if let Err(e) = self.write_stream(id, node_id, alpn, send, commands).await {
+                warn!("Stream {} write failed: {:#}", id, e);
+            }
+        };
+        tokio::join!(
+            self.read_stream(id, node_id, alpn, handler.as_ref(), recv, pull),
+            writer
+        );
+        self.remove_stream(id);
+        debug!("Stream {} with {} done", id, node_id.fmt_short());
+    }
+
+    /// On pulled ALPNs, the reader [`Protocols::read`] takes `id`'s data
+    /// from, and the sender feeding it
+    fn start_pull(&self, id: StreamId, alpn: &[u8]) -> Option<mpsc::Sender<Bytes>> {
+        if !self.inner.pulled.lock().unwrap().contains(alpn) {
+            return None;
+        }
+        // Room for one chunk, so the remote waits until the host reads
+        let (tx, chunks) = mpsc::channel(1);
+        let reader = PullReader {
+            chunks,
+            pending: Bytes::new(),
+        };
+        let reader = Arc::new(tokio::sync::Mutex::new(reader));
+        self.inner.pulls.lock().unwrap().insert(id, reader);
+        Some(tx)
+    }
+
+    /// Hand everything the remote sends on `recv` to `handler`, or to
+    /// [`Protocols::read`] through `pull` on pulled ALPNs, then close
+    async fn read_stream(
+        &self,
+        id: StreamId,
+        node_id: NodeId,
+        alpn: &[u8],
+        handler: &dyn StreamHandler,
+        mut recv: RecvStream,
+        mut pull: Option<mpsc::Sender<Bytes>>,
+    ) {
+        let traffic = &self.inner.traffic;
+        loop {
+            match recv.read_chunk(MAX_CHUNK_SIZE, true).await {
+                Ok(Some(chunk)) => {
+                    traffic.record_received(node_id, alpn, chunk.bytes.len());
+                    match &pull {
+                        Some(tx) => {
+                            if tx.send(chunk.bytes).await.is_err() {
+                                break;
+                            }
+                        }
+                        None => handler.on_data(id, &chunk.bytes),
+                    }
+                }
+                Ok(None) => {
+                    // Reads return what's left, then the end
+                    pull.take();
+                    handler.on_close(id, None);
+                    break;
+                }
+                Err(e) => {
+                    if pull.take().is_some() {
+                        self.inner.pulls.lock().unwrap().remove(&id);
+                    }
+                    // Dropping the sender stops the writer too
+                    self.remove_stream(id);
+                    // With the cause, e.g. the reason the remote closed the
+                    // connection with
+                    let error = format!("{:#}", anyhow::Error::from(e));
+                    handler.on_close(id, Some(&error));
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Send what the host writes until it finishes, then wait until the
+    /// remote has all of it
+    async fn write_stream(
+        &self,
+        id: StreamId,
+        node_id: NodeId,
+        alpn: &[u8],
+        send: SendStream,
+        commands: mpsc::UnboundedReceiver<StreamCommand>,
+    ) -> anyhow::Result<()> {
+        let buffered = match self.inner.streams.lock().unwrap().get(&id) {
+            Some(entry) => entry.buffered.clone(),
+            None => return Ok(()),
+        };
+        let written = self
+            .send_writes(node_id, alpn, send, commands, &buffered)
+            .await;
+        // Writers waiting for room find the stream closed
+        buffered.drained.notify_waiters();
+        written
+    }
+
+    async fn send_writes(
+        &self,
+        node_id: NodeId,
+        alpn: &[u8],
+        mut send: SendStream,
+        mut commands: mpsc::UnboundedReceiver<StreamCommand>,
+        buffered: &WriteBuffer,
+    ) -> anyhow::Result<()> {
+        let traffic = &self.inner.traffic;
+        let flow = self.flow(node_id, alpn);
+        while let Some(command) = commands.recv().await {
+            match command {
+                StreamCommand::Write(mut data) => {
+                    // Small chunks keep pacing smooth under a rate limit;
+                    // slicing shares the buffer rather than copying it
+                    while !data.is_empty() {
+                        let chunk = data.split_to(data.len().min(WRITE_CHUNK_SIZE));
+                        let len = chunk.len();
+                        if let Some(rate) = self.limits().send_bytes_per_sec {
+                            flow.rate.acquire(len, rate).await;
+                        }
+                        send.write_chunk(chunk).await?;
+                        traffic.record_sent(node_id, alpn, len);
+                        buffered.sent(len);
+                    }
+                }
+                StreamCommand::Finish => break,
+            }
+        }
+        send.finish()?;
+        // Keep the connection open until the remote has everything
+        if let Some(code) = send.stopped().await? {
+            anyhow::bail!("Stopped by the remote with code {}", code);
+        }
+        Ok(())
+    }
+}
+
+/// Accepts connections for one ALPN and serves them
+#[derive(Clone)]
+struct Acceptor {
+    alpn: Vec<u8>,
+    protocols: Protocols,
+}
+
+impl std::fmt::Debug for Acceptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Acceptor")
+            .field("alpn", &String::from_utf8_lossy(&self.alpn))
+            .finish()
+    }
+}
+
+impl ProtocolHandler for Acceptor {
+    async fn accept(&self, conn: Connection) -> Result<(), AcceptError> {
+        let node_id = conn.remote_node_id()?;
+        let Some(_quota) = self.protocols.admit(&conn, node_id, &self.alpn).await else {
+            return Ok(());
+        };
+
+        let served = supervise::catch_panic(self.protocols.serve_connection(
+            &self.alpn,
+            conn,
+            Direction::Inbound,
+        ));
+        if let Err(panic) = served.await {
+            supervise::report_panic("accept", panic, &self.protocols.events());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+//! QUIC transport statistics of open connections
+//!
+//! The byte counts in [`crate::stats`] say how much data moved; these say
+//! how well the transport moved it, to find out why a LAN transfer is slower
+//! than the link allows: a congestion window that stays small, packet loss,
+//! or an RTT that jumps around while a phone's Wi-Fi dozes between beacons.
+
+use std::time::Duration;
+
+use iroh::endpoint::ConnectionStats;
+use serde::Serialize;
+
+/// How often the RTT of each open connection is sampled for its variance
+pub(crate) const RTT_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Transport state of one QUIC connection
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QuicStats {
+    /// Protocol the connection was opened for
+    pub alpn: String,
+    /// Smoothed round-trip time of the current path
+    pub rtt_ms: f64,
+    /// How much the RTT varies between samples, like TCP's RTTVAR
+    pub rtt_var_ms: f64,
+    /// Congestion window in bytes, the most that may be in flight
+    pub cwnd: u64,
+    pub congestion_events: u64,
+    pub sent_packets: u64,
+    pub lost_packets: u64,
+    pub lost_bytes: u64,
+    /// Share of sent packets that were lost, from 0 to 1
+    pub loss_rate: f64,
+    /// Largest UDP payload the path is known to carry
+    pub mtu: u16,
+    /// UDP datagrams sent and received, each holding one or more packets
+    pub udp_datagrams_sent: u64,
+    pub udp_datagrams_received: u64,
+    pub udp_bytes_sent: u64,
+    pub udp_bytes_received: u64,
+}
+
+impl QuicStats {
+    pub(crate) fn new(alpn: &[u8], stats: &ConnectionStats, rtt: &RttVariance) -> Self {
+        let path = &stats.path;
+        Self {
+            alpn: String::from_utf8_lossy(alpn).into_owned(),
+            rtt_ms: path.rtt.as_secs_f64() * 1000.0,
+            rtt_var_ms: rtt.var.as_secs_f64() * 1000.0,
+            cwnd: path.cwnd,
+            congestion_events: path.congestion_events,
+            sent_packets: path.sent_packets,
+            lost_packets: path.lost_packets,
+            lost_bytes: path.lost_bytes,
+            loss_rate: if path.sent_packets == 0 {
+                0.0
+            } else {
+                path.lost_packets as f64 / path.sent_packets as f64
+            },
+            mtu: path.current_mtu,
+            udp_datagrams_sent: stats.udp_tx.datagrams,
+            udp_datagrams_received: stats.udp_rx.datagrams,
+            udp_bytes_sent: stats.udp_tx.bytes,
+            udp_bytes_received: stats.udp_rx.bytes,
+        }
+    }
+}
+
+/// Smoothed deviation between successive RTT samples of one connection
+///
+/// QUIC keeps an RTT variance internally but doesn't expose it, so it is
+/// estimated the way RFC 6298 does from samples taken every
+/// [`RTT_SAMPLE_INTERVAL`].
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct RttVariance {
+    last: Option<Duration>,
+    var: Duration,
+}
+
+impl RttVariance {
+    pub(crate) fn sample(&mut self, rtt: Duration) {
+        self.var = match self.last {
+            None => rtt / 2,
+            Some(last) => (self.var * 3 + last.abs_diff(rtt)) / 4,
+        };
+        self.last = Some(rtt);
+    }
+}
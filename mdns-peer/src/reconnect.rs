@@ -0,0 +1,40 @@
+//! Re-dialing trusted peers when the app comes back to the foreground
+//!
+//! iOS suspends the app in the background, and its connections time out
+//! while it can't answer. When the host calls [`Resume::resume`] (over FFI,
+//! `peer_resume`), every trusted peer (see [`WarmUp`]) without an open
+//! connection is dialed again, at most [`RECONNECT_TIMEOUT`] each and all
+//! at once. Each attempt is reported as a [`PeerEvent::Reconnect`], and a
+//! successful one as a [`PeerEvent::Connected`] as well.
+//!
+//! [`WarmUp`]: crate::options::WarmUp
+//! [`PeerEvent::Reconnect`]: crate::PeerEvent::Reconnect
+//! [`PeerEvent::Connected`]: crate::PeerEvent::Connected
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::Notify;
+
+/// How long each re-dial may take before it counts as failed
+pub const RECONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Tells a running peer that the app is back in the foreground
+///
+/// Clones signal the same peer. Resuming while no peer runs does nothing.
+#[derive(Debug, Clone, Default)]
+pub struct Resume {
+    notify: Arc<Notify>,
+}
+
+impl Resume {
+    /// Re-dial trusted peers that lost their connection
+    pub fn resume(&self) {
+        self.notify.notify_waiters();
+    }
+
+    /// Wait for the next [`Resume::resume`]
+    pub(crate) async fn resumed(&self) {
+        self.notify.notified().await;
+    }
+}
@@ -0,0 +1,348 @@
+//! Registry of peers seen through discovery
+//!
+//! mDNS re-announces every peer periodically, so the raw discovery stream
+//! repeats the same node many times. The registry keeps one entry per node
+//! and only reports changes that are interesting to the host: a new peer, a
+//! peer whose user data changed, or a known peer expiring.
+//!
+//! How many peers it lists is capped by the
+//! [`MemoryBudget`](crate::budget::MemoryBudget): once full, a new peer is
+//! either left out or pushes out the peer announced longest ago, which is
+//! reported as expired.
+//!
+//! Phones duty-cycle their radios, so a peer can expire and be announced
+//! again moments later. An expiry is therefore held back for a grace period
+//! ([`DEFAULT_EXPIRY_GRACE`] unless set otherwise), during which the peer
+//! stays listed. If it comes back in time, the host sees one
+//! [`PeerEvent::Flapped`] with how long it was gone and how often it
+//! flapped, rather than an expiry and a rediscovery.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use iroh::{discovery::DiscoveryEvent, NodeId};
+
+use crate::budget::{DropPolicy, MemoryBudget, Overflow};
+use crate::discovery;
+use crate::events::{MemoryPool, PeerEvent};
+use crate::mdns::MdnsOptions;
+
+/// How long an expired peer stays listed in case it's announced again
+pub const DEFAULT_EXPIRY_GRACE: Duration = Duration::from_secs(5);
+
+/// A peer currently known through discovery
+#[derive(Debug, Clone)]
+pub struct PeerEntry {
+    pub node_id: NodeId,
+    pub user_data: Option<String>,
+    /// Discovery mechanism that last reported this peer (e.g. "mdns")
+    pub provenance: &'static str,
+    pub first_seen: Instant,
+    pub last_seen: Instant,
+    /// Number of announcements received, including duplicates
+    pub announcements: u64,
+    /// Whether a pre-established connection is ready, see
+    /// [`WarmUp`](crate::options::WarmUp)
+    pub warm: bool,
+    /// When discovery reported the peer expired, while its grace period
+    /// runs
+    pub expired_at: Option<Instant>,
+    /// Times the peer expired and came back within the grace period
+    pub flaps: u64,
+}
+
+/// Peers discovered so far, keyed by node ID
+#[derive(Debug)]
+pub struct PeerRegistry {
+    peers: HashMap<NodeId, PeerEntry>,
+    /// Announcement cadence of the discovery swarm
+    cadence: Duration,
+    /// Responses per cadence interval, shared by the whole swarm
+    responses_per_cadence: u32,
+    max_peers: usize,
+    policy: DropPolicy,
+    /// How long expiries are held back
+    grace: Duration,
+    /// Peers pushed out to make room, not reported yet
+    evicted: Vec<PeerEvent>,
+    overflow: Overflow,
+}
+
+impl Default for PeerRegistry {
+    fn default() -> Self {
+        Self::for_mdns(&MdnsOptions::default())
+    }
+}
+
+impl PeerRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry whose expiry estimates match discovery tuned with `options`
+    pub fn for_mdns(options: &MdnsOptions) -> Self {
+        Self {
+            peers: HashMap::new(),
+            cadence: options.cadence,
+            responses_per_cadence: options.responses_per_cadence().max(1),
+            max_peers: MemoryBudget::default().discovered_peers,
+            policy: DropPolicy::default(),
+            grace: DEFAULT_EXPIRY_GRACE,
+            evicted: Vec::new(),
+            overflow: Overflow::new(),
+        }
+    }
+
+    /// Cap the registry at `budget.discovered_peers`
+    ///
+    /// Peers already listed beyond the cap stay until they expire.
+    pub fn set_budget(&mut self, budget: &MemoryBudget) {
+        self.max_peers = budget.discovered_peers;
+        self.policy = budget.policy;
+    }
+
+    /// Hold expiries back for `grace`, or report them right away with zero
+    pub fn set_expiry_grace(&mut self, grace: Duration) {
+        self.grace = grace;
+    }
+
+    /// Apply a discovery event, returning the event to report (if any)
+    ///
+    /// Repeated announcements of a known peer with unchanged user data only
+    /// refresh its `last_seen` time and return `None`. Expiries are held
+    /// back and returned by [`PeerRegistry::expire_due`] once their grace
+    /// period is over.
+    pub fn apply(&mut self, event: &DiscoveryEvent) -> Option<PeerEvent> {
+        match event {
+            DiscoveryEvent::Discovered(item) => {
+                let node_id = item.node_id();
+                let user_data = item.node_info().data.user_data().map(|d| d.to_string());
+                let provenance = item.provenance();
+                let now = Instant::now();
+
+                if let Some(entry) = self.peers.get_mut(&node_id) {
+                    let offline = entry.expired_at.take().map(|at| now - at);
+                    entry.last_seen = now;
+                    entry.announcements += 1;
+                    entry.provenance = provenance;
+                    if offline.is_some() {
+                        entry.flaps += 1;
+                    }
+                    if entry.user_data == user_data {
+                        return offline.map(|offline| PeerEvent::Flapped {
+                            node_id,
+                            user_data,
+                            offline_ms: offline.as_millis() as u64,
+                            flaps: entry.flaps,
+                        });
+                    }
+                    entry.user_data = user_data.clone();
+                } else {
+                    if !self.make_room() {
+                        return None;
+                    }
+                    self.peers.insert(
+                        node_id,
+                        PeerEntry {
+                            node_id,
+                            user_data: user_data.clone(),
+                            provenance,
+                            first_seen: now,
+                            last_seen: now,
+                            announcements: 1,
+                            warm: false,
+                            expired_at: None,
+                            flaps: 0,
+                        },
+                    );
+                }
+
+                Some(PeerEvent::Discovered {
+                    node_id,
+                    user_data,
+                    provenance,
+                    origin: discovery::origin(item),
+                })
+            }
+            DiscoveryEvent::Expired(node_id) => {
+                if !self.grace.is_zero() {
+                    let entry = self.peers.get_mut(node_id)?;
+                    entry.expired_at.get_or_insert_with(Instant::now);
+                    return None;
+                }
+                let entry = self.peers.remove(node_id)?;
+                Some(PeerEvent::Expired {
+                    node_id: entry.node_id,
+                    user_data: entry.user_data,
+                })
+            }
+        }
+    }
+
+    /// When the next held-back expiry is due, if any
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.peers
+            .values()
+            .filter_map(|entry| entry.expired_at)
+            .min()
+            .map(|at| at + self.grace)
+    }
+
+    /// Remove the peers whose grace period ended by `now`, returning them
+    /// as [`PeerEvent::Expired`]
+    pub fn expire_due(&mut self, now: Instant) -> Vec<PeerEvent> {
+        let grace = self.grace;
+        self.expire_where(|expired_at| expired_at + grace <= now)
+    }
+
+    /// Remove every peer whose expiry is held back, e.g. once discovery
+    /// ended and none can come back
+    pub fn expire_pending(&mut self) -> Vec<PeerEvent> {
+        self.expire_where(|_| true)
+    }
+
+    fn expire_where(&mut self, due: impl Fn(Instant) -> bool) -> Vec<PeerEvent> {
+        let mut expired = Vec::new();
+        self.peers.retain(|_, entry| match entry.expired_at {
+            Some(expired_at) if due(expired_at) => {
+                expired.push(PeerEvent::Expired {
+                    node_id: entry.node_id,
+                    user_data: entry.user_data.take(),
+                });
+                false
+            }
+            _ => true,
+        });
+        expired
+    }
+
+    /// Whether a new peer fits, pushing out the peer announced longest ago
+    /// if the policy allows it
+    fn make_room(&mut self) -> bool {
+        if self.peers.len() < self.max_peers {
+            return true;
+        }
+        let oldest = self
+            .peers
+            .values()
+            .min_by_key(|entry| entry.last_seen)
+            .map(|entry| entry.node_id);
+        match (self.policy, oldest) {
+            (DropPolicy::DropOldest, Some(oldest)) => {
+                let entry = self.peers.remove(&oldest).expect("just found");
+                self.evicted.push(PeerEvent::Expired {
+                    node_id: entry.node_id,
+                    user_data: entry.user_data,
+                });
+                self.overflow.add(1);
+                true
+            }
+            _ => {
+                self.overflow.add(1);
+                false
+            }
+        }
+    }
+
+    /// Events about peers dropped to stay within the budget since the last
+    /// call: an expiry for each peer pushed out, and a
+    /// [`PeerEvent::OverBudget`] once a second while peers are dropped
+    pub fn take_overflow(&mut self) -> Vec<PeerEvent> {
+        let mut events = std::mem::take(&mut self.evicted);
+        if let Some(dropped) = self.overflow.due() {
+            events.push(PeerEvent::OverBudget {
+                pool: MemoryPool::DiscoveredPeers,
+                limit: self.max_peers as u64,
+                dropped,
+            });
+        }
+        events
+    }
+
+    /// Look up a single peer
+    pub fn get(&self, node_id: &NodeId) -> Option<&PeerEntry> {
+        self.peers.get(node_id)
+    }
+
+    /// Record whether a warm connection to `node_id` is up
+    ///
+    /// Ignored for peers that aren't (or are no longer) in the registry.
+    pub fn set_warm(&mut self, node_id: &NodeId, warm: bool) {
+        if let Some(entry) = self.peers.get_mut(node_id) {
+            entry.warm = warm;
+        }
+    }
+
+    /// How long a peer stays listed without announcing itself again
+    ///
+    /// Mirrors swarm-discovery's expiry: the swarm (including us) shares a
+    /// fixed number of responses per cadence interval, and a peer is dropped
+    /// after missing three of its expected announcements. Bigger swarms
+    /// announce each peer less often, so the TTL grows with the registry.
+    pub fn record_ttl(&self) -> Duration {
+        let swarm = self.peers.len() as u32 + 1;
+        let responses = self.responses_per_cadence.min(swarm);
+        self.cadence * 3 * swarm / responses
+    }
+
+    /// When `entry` is expected to expire unless it's announced again
+    ///
+    /// A peer close to its deadline has likely gone away; UIs can gray it
+    /// out instead of waiting for the expiry event.
+    pub fn expires_at(&self, entry: &PeerEntry) -> Instant {
+        entry.last_seen + self.record_ttl()
+    }
+
+    /// Account for `elapsed` passing without [`Instant`] noticing, e.g. while
+    /// the device slept (see [`crate::suspend`])
+    ///
+    /// Every peer's last announcement moves back by `elapsed`; peers that
+    /// are past their deadline as a result are removed and returned as
+    /// [`PeerEvent::Expired`], and so are peers whose expiry was held back.
+    pub fn age(&mut self, elapsed: Duration) -> Vec<PeerEvent> {
+        let ttl = self.record_ttl();
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        self.peers
+            .retain(|_, entry| match entry.last_seen.checked_sub(elapsed) {
+                Some(last_seen) if entry.expired_at.is_none() && last_seen + ttl > now => {
+                    entry.last_seen = last_seen;
+                    true
+                }
+                _ => {
+                    expired.push(PeerEvent::Expired {
+                        node_id: entry.node_id,
+                        user_data: entry.user_data.take(),
+                    });
+                    false
+                }
+            });
+        expired
+    }
+
+    /// Whether a listed peer announces exactly `user_data`
+    pub fn has_user_data(&self, user_data: &str) -> bool {
+        self.peers
+            .values()
+            .any(|entry| entry.user_data.as_deref() == Some(user_data))
+    }
+
+    /// Forget every peer, e.g. once the peer stops
+    pub fn clear(&mut self) {
+        self.peers.clear();
+        self.evicted.clear();
+    }
+
+    /// All known peers, in no particular order
+    pub fn peers(&self) -> impl Iterator<Item = &PeerEntry> {
+        self.peers.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+}
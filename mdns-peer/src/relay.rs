@@ -0,0 +1,161 @@
+//! Relay servers: which one is home, and how far away each one is
+//!
+//! Peers that can't reach each other directly fall back to the home relay,
+//! so a distant home relay makes those connections slow. Probing every
+//! configured relay shows whether a nearer one is available to put in
+//! [`PeerOptions::relays`](crate::PeerOptions::relays).
+//!
+//! Falling back to a relay is transparent, but each time it happens a
+//! [`PeerEvent::RelayFallback`] says so, since the traffic then leaves the
+//! local network. Privacy-sensitive hosts can set
+//! [`PeerOptions::lan_only`](crate::PeerOptions::lan_only) instead: relays
+//! are disabled altogether, and a dial that finds no direct path fails with
+//! a [`PeerEvent::RelayRefused`].
+//!
+//! [`PeerEvent::RelayFallback`]: crate::PeerEvent::RelayFallback
+//! [`PeerEvent::RelayRefused`]: crate::PeerEvent::RelayRefused
+
+use std::time::{Duration, Instant};
+
+use iroh::{endpoint::default_relay_mode, Endpoint, RelayMode, RelayUrl, Watcher};
+use serde::Serialize;
+use tokio::net::TcpStream;
+use tracing::info;
+
+use crate::PeerOptions;
+
+/// Connection attempts per relay; the fastest one counts
+const PROBE_ATTEMPTS: usize = 3;
+
+/// Give up on an attempt after this long
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Relay mode for `options`: none with `lan_only`, otherwise the configured
+/// relays or iroh's defaults
+pub fn relay_mode(options: &PeerOptions) -> RelayMode {
+    if options.lan_only {
+        return RelayMode::Disabled;
+    }
+    match &options.relays {
+        Some(relays) => RelayMode::Custom(relays.iter().cloned().collect()),
+        None => default_relay_mode(),
+    }
+}
+
+/// Relays the endpoint for `options` can pick its home relay from
+pub fn configured_relays(options: &PeerOptions) -> Vec<RelayUrl> {
+    relay_mode(options).relay_map().urls().cloned().collect()
+}
+
+/// The relay `endpoint` is currently reachable through
+pub fn home_relay(endpoint: &Endpoint) -> Option<RelayUrl> {
+    endpoint.home_relay().get().into_iter().next()
+}
+
+/// Wait up to `timeout` for `endpoint` to select a home relay
+pub async fn wait_for_home_relay(endpoint: &Endpoint, timeout: Duration) -> Option<RelayUrl> {
+    let mut watcher = endpoint.home_relay();
+    tokio::time::timeout(timeout, async {
+        loop {
+            if let Some(url) = watcher.get().into_iter().next() {
+                return Some(url);
+            }
+            watcher.updated().await.ok()?;
+        }
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+/// Latency to each relay, fastest first
+#[derive(Debug, Clone, Serialize)]
+pub struct RelayReport {
+    /// The relay this endpoint is reachable through, if one was selected
+    pub home_relay: Option<RelayUrl>,
+    pub relays: Vec<RelayProbe>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RelayProbe {
+    pub url: RelayUrl,
+    /// Fastest TCP handshake to the relay, `None` if it couldn't be reached
+    pub latency_ms: Option<f64>,
+    /// Why the relay couldn't be reached
+    pub error: Option<String>,
+    pub home: bool,
+}
+
+/// Probe every relay in `relays` concurrently
+///
+/// Latency is the time to complete a TCP handshake with the relay's HTTPS
+/// port, which tracks the round trip without needing TLS.
+pub async fn probe_relays(relays: Vec<RelayUrl>, home_relay: Option<RelayUrl>) -> RelayReport {
+    let probes = relays.into_iter().map(|url| {
+        let home = home_relay.as_ref() == Some(&url);
+        async move {
+            let (latency_ms, error) = match probe(&url).await {
+                Ok(latency) => (Some(latency.as_secs_f64() * 1000.0), None),
+                Err(e) => (None, Some(format!("{:#}", e))),
+            };
+            RelayProbe {
+                url,
+                latency_ms,
+                error,
+                home,
+            }
+        }
+    });
+    let mut relays = n0_future::join_all(probes).await;
+
+    // Reachable relays first, nearest first
+    relays.sort_by(|a, b| match (a.latency_ms, b.latency_ms) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => a.url.cmp(&b.url),
+    });
+    RelayReport { home_relay, relays }
+}
+
+async fn probe(url: &RelayUrl) -> anyhow::Result<Duration> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow::anyhow!("relay URL has no host"))?;
+    let port = url.port_or_known_default().unwrap_or(443);
+
+    let mut fastest: Option<Duration> = None;
+    let mut last_error = None;
+    for _ in 0..PROBE_ATTEMPTS {
+        let started = Instant::now();
+        match tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect((host, port))).await {
+            Ok(Ok(_)) => {
+                let elapsed = started.elapsed();
+                fastest = Some(fastest.map_or(elapsed, |f| f.min(elapsed)));
+            }
+            Ok(Err(e)) => last_error = Some(anyhow::Error::from(e)),
+            Err(_) => last_error = Some(anyhow::anyhow!("timed out after {:?}", PROBE_TIMEOUT)),
+        }
+    }
+    match (fastest, last_error) {
+        (Some(fastest), _) => Ok(fastest),
+        (None, Some(e)) => Err(e),
+        (None, None) => unreachable!("at least one attempt is made"),
+    }
+}
+
+/// Log a report, one relay per line
+pub fn log_relay_report(report: &RelayReport) {
+    match &report.home_relay {
+        Some(url) => info!("Home relay: {}", url),
+        None => info!("No home relay selected"),
+    }
+    for relay in &report.relays {
+        let home = if relay.home { " (home)" } else { "" };
+        match (relay.latency_ms, &relay.error) {
+            (Some(ms), _) => info!("  {}{}: {:.1} ms", relay.url, home, ms),
+            (None, Some(e)) => info!("  {}{}: unreachable ({})", relay.url, home, e),
+            (None, None) => info!("  {}{}: unreachable", relay.url, home),
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! JSON view of iroh's per-peer [`RemoteInfo`]
+//!
+//! Durations are converted to milliseconds so the host doesn't need to know
+//! about Rust's `Duration` serialization. "Ago" values are the time elapsed
+//! since the event, at the moment the report was taken.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use iroh::endpoint::{ConnectionType, DirectAddrInfo, RemoteInfo};
+use iroh::{Endpoint, NodeAddr, NodeId};
+use iroh_base::ticket::NodeTicket;
+use serde::Serialize;
+
+use crate::quic::QuicStats;
+
+/// Everything the endpoint knows about one remote node
+#[derive(Debug, Clone, Serialize)]
+pub struct RemoteInfoReport {
+    pub node_id: NodeId,
+    pub connection: ConnectionReport,
+    /// Latency of the current network path
+    pub latency_ms: Option<f64>,
+    /// Time since anything was sent to or received from the node
+    pub last_used_ago_ms: Option<u64>,
+    pub relay: Option<RelayReport>,
+    pub addrs: Vec<AddrReport>,
+    /// Transport state of the open connections, filled in by callers that
+    /// have them (see [`Protocols::quic_stats`](crate::protocols::Protocols::quic_stats))
+    pub quic: Vec<QuicStats>,
+}
+
+/// Where a remote node can be dialed, for endpoints other than ours
+///
+/// Hosts running their own iroh endpoint can parse `ticket` as a
+/// `NodeTicket`; other QUIC stacks can use the addresses.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NodeAddrReport {
+    pub node_id: NodeId,
+    pub relay_url: Option<String>,
+    pub direct_addrs: Vec<SocketAddr>,
+    /// The same address as an iroh node ticket, which also works as a
+    /// pairing payload (see [`crate::pairing`])
+    pub ticket: String,
+}
+
+impl From<NodeAddr> for NodeAddrReport {
+    fn from(addr: NodeAddr) -> Self {
+        Self {
+            node_id: addr.node_id,
+            relay_url: addr.relay_url.as_ref().map(ToString::to_string),
+            direct_addrs: addr.direct_addresses.iter().copied().collect(),
+            ticket: NodeTicket::new(addr.clone()).to_string(),
+        }
+    }
+}
+
+/// Everything `endpoint` knows about where `node_id` is, whichever way it
+/// learned it, or `None` if it knows nothing
+pub fn node_addr(endpoint: &Endpoint, node_id: NodeId) -> Option<NodeAddr> {
+    let info = endpoint.remote_info(node_id)?;
+    Some(NodeAddr::from_parts(
+        node_id,
+        info.relay_url.map(|relay| relay.relay_url),
+        info.addrs.into_iter().map(|addr| addr.addr),
+    ))
+}
+
+/// How we currently reach the node
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, schemars::JsonSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ConnectionReport {
+    Direct {
+        addr: SocketAddr,
+    },
+    Relay {
+        url: String,
+    },
+    /// UDP address known but not recently confirmed, relay used alongside
+    Mixed {
+        addr: SocketAddr,
+        url: String,
+    },
+    None,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RelayReport {
+    pub url: String,
+    pub latency_ms: Option<f64>,
+    pub last_alive_ago_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AddrReport {
+    pub addr: SocketAddr,
+    pub latency_ms: Option<f64>,
+    /// Time since a QUIC payload was last received on this path
+    pub last_payload_ago_ms: Option<u64>,
+    /// Time since this path was last confirmed to exist
+    pub last_alive_ago_ms: Option<u64>,
+    /// Most recent control message received about this path ("ping←", "pong←", "call me")
+    pub last_control: Option<String>,
+}
+
+impl From<RemoteInfo> for RemoteInfoReport {
+    fn from(info: RemoteInfo) -> Self {
+        Self {
+            node_id: info.node_id,
+            connection: info.conn_type.into(),
+            latency_ms: info.latency.map(latency_ms),
+            last_used_ago_ms: info.last_used.map(ago_ms),
+            // iroh doesn't export the relay info type, so convert inline
+            relay: info.relay_url.map(|relay| RelayReport {
+                url: relay.relay_url.to_string(),
+                latency_ms: relay.latency.map(latency_ms),
+                last_alive_ago_ms: relay.last_alive.map(ago_ms),
+            }),
+            addrs: info.addrs.into_iter().map(Into::into).collect(),
+            quic: Vec::new(),
+        }
+    }
+}
+
+impl From<ConnectionType> for ConnectionReport {
+    fn from(conn_type: ConnectionType) -> Self {
+        match conn_type {
+            ConnectionType::Direct(addr) => Self::Direct { addr },
+            ConnectionType::Relay(url) => Self::Relay {
+                url: url.to_string(),
+            },
+            ConnectionType::Mixed(addr, url) => Self::Mixed {
+                addr,
+                url: url.to_string(),
+            },
+            ConnectionType::None => Self::None,
+        }
+    }
+}
+
+impl From<DirectAddrInfo> for AddrReport {
+    fn from(info: DirectAddrInfo) -> Self {
+        Self {
+            addr: info.addr,
+            latency_ms: info.latency.map(latency_ms),
+            last_payload_ago_ms: info.last_payload.map(ago_ms),
+            last_alive_ago_ms: info.last_alive.map(ago_ms),
+            last_control: info.last_control.map(|(_, msg)| msg.to_string()),
+        }
+    }
+}
+
+/// Latencies keep sub-millisecond precision, LAN paths are often below 1ms
+fn latency_ms(latency: Duration) -> f64 {
+    latency.as_secs_f64() * 1000.0
+}
+
+fn ago_ms(elapsed: Duration) -> u64 {
+    elapsed.as_millis() as u64
+}
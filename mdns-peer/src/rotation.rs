@@ -0,0 +1,215 @@
+//! Moving to a new secret key without losing trusted peers
+//!
+//! A device that suspects its key leaked calls `peer_rotate_key`: the peer
+//! restarts on a freshly generated key, and for an overlap period an
+//! endpoint with the old key keeps running beside it. That endpoint keeps
+//! announcing the old node ID, so peers that only know it still see the
+//! device, and tells every trusted peer (see [`trusted_peers`]) about the
+//! new node ID over [`ROTATION_ALPN`], retrying the ones it hasn't reached
+//! every [`ANNOUNCE_INTERVAL`].
+//!
+//! The notice is a [`KeyRotation`] frame: the new node ID, signed by the
+//! new key over both IDs. The connection it arrives on proves the sender
+//! holds the old key and the signature proves it holds the new one. A
+//! receiving peer then trusts the new ID in place of the old one, moves the
+//! alias and notes over, and reports a [`PeerEvent::KeyRotated`] so the app
+//! can update what it stores. Whoever stole the old key could announce a
+//! rotation to a key of their own too, so an app guarding something
+//! valuable should confirm a rotation with the user before following it.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use anyhow::Context;
+use iroh::endpoint::Connection;
+use iroh::protocol::{AcceptError, ProtocolHandler};
+use iroh::{Endpoint, NodeId, PublicKey, SecretKey};
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+
+use crate::events::PeerEvent;
+use crate::notes::PeerNotes;
+use crate::protocol::{self, KeyRotation};
+use crate::protocols::Protocols;
+use crate::PeerOptions;
+
+/// ALPN rotation notices are sent on
+pub const ROTATION_ALPN: &[u8] = b"mdns-peer/rotation/0";
+
+/// How long the old key stays announced when the host doesn't say
+pub const DEFAULT_OVERLAP: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often the old endpoint retries trusted peers it hasn't reached
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Give up on telling one peer after this long, until the next round
+const ANNOUNCE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest notice read, well above the size of a [`KeyRotation`]
+const MAX_NOTICE_LEN: usize = 1024;
+
+/// Prefix of the signed bytes, so the signature can't be mistaken for
+/// anything else the key signs
+const SIGNING_CONTEXT: &[u8] = b"mdns-peer key rotation";
+
+fn signed_bytes(old: NodeId, new: NodeId) -> Vec<u8> {
+    [SIGNING_CONTEXT, old.as_bytes(), new.as_bytes()].concat()
+}
+
+/// Notice that the peer with node ID `old` moves to `new_key`
+pub fn sign(old: NodeId, new_key: &SecretKey) -> KeyRotation {
+    let new = new_key.public();
+    KeyRotation {
+        new_node_id: *new.as_bytes(),
+        signature: new_key.sign(&signed_bytes(old, new)).to_bytes().to_vec(),
+    }
+}
+
+/// The new node ID in `rotation`, if the notice came from `old` and is
+/// signed by the new key
+pub fn verify(old: NodeId, rotation: &KeyRotation) -> anyhow::Result<NodeId> {
+    let new = PublicKey::from_bytes(&rotation.new_node_id).context("Invalid new node ID")?;
+    anyhow::ensure!(new != old, "The new node ID is the old one");
+    let signature: [u8; 64] = rotation
+        .signature
+        .as_slice()
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Signature is {} bytes", rotation.signature.len()))?;
+    new.verify(
+        &signed_bytes(old, new),
+        &iroh_base::Signature::from_bytes(&signature),
+    )
+    .map_err(|_| anyhow::anyhow!("Bad signature"))?;
+    Ok(new)
+}
+
+/// Peers told about a rotation: the ones warm connections are kept to and
+/// the paired ones
+pub fn trusted_peers(options: &PeerOptions) -> HashSet<NodeId> {
+    let warm = options
+        .warm_up
+        .iter()
+        .flat_map(|w| w.trusted.iter().copied());
+    let paired = options.paired.iter().map(|addr| addr.node_id);
+    warm.chain(paired).collect()
+}
+
+/// Tell `node_id` about `rotation` from `endpoint`, the one with the old
+/// key
+pub async fn announce(
+    endpoint: &Endpoint,
+    node_id: NodeId,
+    rotation: &KeyRotation,
+) -> anyhow::Result<()> {
+    let conn = endpoint.connect(node_id, ROTATION_ALPN).await?;
+    let mut send = conn.open_uni().await?;
+    send.write_all(&protocol::encode(rotation)).await?;
+    send.finish()?;
+    // Returns once the remote has read it all
+    send.stopped().await?;
+    conn.close(0u32.into(), b"rotated");
+    Ok(())
+}
+
+/// Keep `old`, an endpoint with the key being retired, announcing for
+/// `overlap` and tell each of `trusted` about `rotation`, then close it
+///
+/// Stops early once `shutdown` fires.
+pub async fn run_overlap(
+    old: Endpoint,
+    rotation: KeyRotation,
+    mut trusted: HashSet<NodeId>,
+    overlap: Duration,
+    mut shutdown: broadcast::Receiver<()>,
+) {
+    info!(
+        "Announcing old node ID {} for {:?} while trusted peers learn the new one",
+        old.node_id().fmt_short(),
+        overlap
+    );
+    let deadline = tokio::time::sleep(overlap);
+    tokio::pin!(deadline);
+    let mut retry = tokio::time::interval(ANNOUNCE_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = &mut deadline => break,
+            _ = shutdown.recv() => break,
+            _ = retry.tick(), if !trusted.is_empty() => {
+                for node_id in trusted.clone() {
+                    let announced = tokio::time::timeout(
+                        ANNOUNCE_TIMEOUT,
+                        announce(&old, node_id, &rotation),
+                    )
+                    .await
+                    .unwrap_or_else(|_| Err(anyhow::anyhow!("timed out")));
+                    match announced {
+                        Ok(()) => {
+                            info!("Told {} about the new key", node_id.fmt_short());
+                            trusted.remove(&node_id);
+                        }
+                        Err(e) => debug!(
+                            "Couldn't tell {} about the new key yet: {:#}",
+                            node_id.fmt_short(),
+                            e
+                        ),
+                    }
+                }
+            }
+        }
+    }
+    if !trusted.is_empty() {
+        warn!(
+            "{} trusted peers never heard about the new key",
+            trusted.len()
+        );
+    }
+    info!("Retiring old node ID {}", old.node_id().fmt_short());
+    old.close().await;
+}
+
+/// Receives rotation notices from other peers on [`ROTATION_ALPN`]
+#[derive(Debug, Clone)]
+pub struct RotationHandler {
+    protocols: Protocols,
+    notes: PeerNotes,
+}
+
+impl RotationHandler {
+    /// Update the trusted set of `protocols` and `notes` on a rotation, and
+    /// report it to the event sink of `protocols`
+    pub fn new(protocols: Protocols, notes: PeerNotes) -> Self {
+        Self { protocols, notes }
+    }
+}
+
+impl ProtocolHandler for RotationHandler {
+    async fn accept(&self, conn: Connection) -> Result<(), AcceptError> {
+        let old = conn.remote_node_id()?;
+        let new = match receive(&conn, old).await {
+            Ok(new) => new,
+            Err(e) => {
+                warn!("Ignoring key rotation from {}: {:#}", old.fmt_short(), e);
+                return Ok(());
+            }
+        };
+        let trusted = self.protocols.replace_trusted(old, new);
+        if let Err(e) = self.notes.rename(old, new) {
+            warn!("Failed to move notes to {}: {:#}", new.fmt_short(), e);
+        }
+        (self.protocols.events())(&PeerEvent::KeyRotated {
+            old_node_id: old,
+            new_node_id: new,
+            trusted,
+        });
+        Ok(())
+    }
+}
+
+async fn receive(conn: &Connection, old: NodeId) -> anyhow::Result<NodeId> {
+    let mut recv = conn.accept_uni().await?;
+    let notice = recv.read_to_end(MAX_NOTICE_LEN).await?;
+    let (rotation, rest) =
+        protocol::decode::<KeyRotation>(&notice)?.context("Truncated rotation notice")?;
+    anyhow::ensure!(rest.is_empty(), "{} bytes after the notice", rest.len());
+    verify(old, &rotation)
+}
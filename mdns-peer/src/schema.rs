@@ -0,0 +1,118 @@
+//! JSON schema of the events handed to the host
+//!
+//! The schema is generated from the Rust types in [`crate::events`], so it
+//! can't drift from what the library sends. `cargo xtask event-schema` writes
+//! it to a file for the Swift side to check its decoder against, and
+//! `peer_event_schema_version` lets the app notice at runtime that it was
+//! built against another version.
+//!
+//! Debug builds check every outgoing event against the schema with
+//! [`check_event`] and log an error for any that doesn't match, which means a
+//! serialization attribute no longer agrees with the derived schema.
+
+use std::sync::OnceLock;
+
+use serde_json::{json, Value};
+
+use crate::events::TimedEvent;
+
+/// Version of the event schema
+///
+/// Bump when an event or field is renamed or removed, or a field changes
+/// type. New event types and fields don't need a bump; decoders ignore what
+/// they don't know.
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+
+/// The schema of one delivered event (a [`TimedEvent`]), with its version
+pub fn event_schema() -> &'static Value {
+    static SCHEMA: OnceLock<Value> = OnceLock::new();
+    SCHEMA.get_or_init(|| {
+        let mut schema = serde_json::to_value(schemars::schema_for!(TimedEvent))
+            .expect("schemas are always serializable");
+        schema["$id"] = json!(format!(
+            "https://github.com/jamiepine/iroh-mdns-ios-demo/peer-event.v{}.json",
+            EVENT_SCHEMA_VERSION
+        ));
+        schema["version"] = json!(EVENT_SCHEMA_VERSION);
+        schema
+    })
+}
+
+/// Check an event's JSON against [`event_schema`]
+///
+/// Covers what a decoder relies on: the `type` tag names a known event,
+/// every required field is present, and top-level fields have the declared
+/// JSON types. Nested objects are not descended into.
+pub fn check_event(event: &Value) -> Result<(), String> {
+    let schema = event_schema();
+    let tag = event
+        .get("type")
+        .and_then(Value::as_str)
+        .ok_or("missing type tag")?;
+    let variant = variants(schema)
+        .find(|variant| {
+            variant["properties"]["type"]["enum"]
+                .as_array()
+                .is_some_and(|tags| tags.iter().any(|t| t == tag))
+        })
+        .ok_or_else(|| format!("unknown event type {:?}", tag))?;
+
+    for level in [schema, variant] {
+        for field in level["required"].as_array().into_iter().flatten() {
+            let field = field.as_str().unwrap_or_default();
+            if event.get(field).is_none() {
+                return Err(format!("{}: missing field {:?}", tag, field));
+            }
+        }
+        let properties = level["properties"].as_object().into_iter().flatten();
+        for (field, property) in properties {
+            let Some(value) = event.get(field) else {
+                continue;
+            };
+            if !matches_type(value, &property["type"]) {
+                return Err(format!(
+                    "{}: field {:?} is {}, expected {}",
+                    tag, field, value, property["type"]
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The schemas of the event types, wherever the generator put the `oneOf`
+/// of the flattened enum: at the top or in an `allOf`, inline or behind a
+/// `$ref` to its definitions
+fn variants(schema: &'static Value) -> impl Iterator<Item = &'static Value> {
+    let resolve = move |subschema: &'static Value| match subschema["$ref"].as_str() {
+        Some(reference) => reference
+            .strip_prefix("#/definitions/")
+            .map_or(&Value::Null, |name| &schema["definitions"][name]),
+        None => subschema,
+    };
+    std::iter::once(schema)
+        .chain(schema["allOf"].as_array().into_iter().flatten())
+        .map(resolve)
+        .flat_map(|subschema| subschema["oneOf"].as_array().into_iter().flatten())
+        .map(resolve)
+}
+
+/// Whether `value` is one of the JSON types in `expected` (a name or a list
+/// of names); no declared type matches anything
+fn matches_type(value: &Value, expected: &Value) -> bool {
+    let is = |name: &str| match name {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "integer" => value.is_u64() || value.is_i64(),
+        "number" => value.is_number(),
+        "string" => value.is_string(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => true,
+    };
+    match expected {
+        Value::String(name) => is(name),
+        Value::Array(names) => names.iter().filter_map(Value::as_str).any(is),
+        _ => true,
+    }
+}
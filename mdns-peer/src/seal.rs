@@ -0,0 +1,80 @@
+//! Sealing payloads for one peer on top of QUIC's transport encryption
+//!
+//! QUIC already encrypts everything in transit, but only up to the other
+//! endpoint: a payload the app stores, or forwards through something other
+//! than this connection, is plain text again. A [`PairKey`] seals payloads
+//! so only the two peers of a pairing can open them, wherever they end up.
+//!
+//! The key is derived from both node identities (X25519 between the
+//! Montgomery forms of the Ed25519 keys, as in iroh's own disco messages),
+//! so both sides get the same key without exchanging anything beyond the
+//! node IDs they learned while pairing, and it stays the same for as long as
+//! the identities do (see [`crate::profile`]). Each sealed payload is
+//!
+//! ```text
+//! [version: 1 byte] [nonce: 24 bytes] [XChaCha20-Poly1305 ciphertext and tag]
+//! ```
+
+use crypto_box::aead::{Aead, AeadCore, OsRng};
+use crypto_box::ChaChaBox;
+use iroh::{PublicKey, SecretKey};
+
+/// Format version written before each sealed payload
+const SEAL_VERSION: u8 = 1;
+
+const NONCE_LEN: usize = 24;
+
+/// Bytes a sealed payload is longer than the plain one
+pub const SEAL_OVERHEAD: usize = 1 + NONCE_LEN + 16;
+
+/// Symmetric key shared by this node and one other
+pub struct PairKey(ChaChaBox);
+
+impl std::fmt::Debug for PairKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PairKey")
+    }
+}
+
+impl PairKey {
+    /// The key between `local` and `remote`; `remote`'s key for `local` is
+    /// the same one
+    pub fn new(local: &SecretKey, remote: &PublicKey) -> Self {
+        let secret = crypto_box::SecretKey::from(local.secret().to_scalar());
+        let public = crypto_box::PublicKey::from(remote.public().to_montgomery());
+        Self(ChaChaBox::new(&public, &secret))
+    }
+
+    /// Encrypt and authenticate `plain`
+    pub fn seal(&self, plain: &[u8]) -> Vec<u8> {
+        let nonce = ChaChaBox::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .0
+            .encrypt(&nonce, plain)
+            .expect("encrypting into a Vec never fails");
+        let mut sealed = Vec::with_capacity(SEAL_OVERHEAD + plain.len());
+        sealed.push(SEAL_VERSION);
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        sealed
+    }
+
+    /// Decrypt a payload sealed with this key, failing if it was sealed with
+    /// another one or tampered with
+    pub fn open(&self, sealed: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let Some((&version, rest)) = sealed.split_first() else {
+            anyhow::bail!("Sealed payload is empty");
+        };
+        anyhow::ensure!(
+            version == SEAL_VERSION,
+            "Unknown sealed payload version {}",
+            version
+        );
+        let Some((nonce, ciphertext)) = rest.split_first_chunk::<NONCE_LEN>() else {
+            anyhow::bail!("Sealed payload is truncated");
+        };
+        self.0
+            .decrypt(nonce.into(), ciphertext)
+            .map_err(|_| anyhow::anyhow!("Sealed payload doesn't open with this key"))
+    }
+}
@@ -0,0 +1,280 @@
+//! Loopback self-test: two temporary peers in this process have to find and
+//! message each other
+//!
+//! Whether discovery works on a device depends on things no single check
+//! can see: the multicast entitlement, the Local Network permission, the
+//! interface the phone is on, a firewall. Rather than probing each one,
+//! [`run_self_test`] starts two throwaway peers, waits for each to discover
+//! the other over mDNS and sends a message from one to the other, which
+//! exercises all of it without a second device. It's what a "Run
+//! Diagnostics" button calls (`peer_self_test` over FFI).
+//!
+//! The temporary peers advertise on [`SELF_TEST_SERVICE`] rather than the
+//! app's own service name, never use a relay, and keep their history in
+//! memory, so other peers and the running peer don't notice them.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use iroh::NodeId;
+use serde::Serialize;
+use tokio::sync::{mpsc, oneshot, watch};
+use uuid::Uuid;
+
+use crate::events::EventSink;
+use crate::instance::DuplicatePolicy;
+use crate::mdns::MdnsOptions;
+use crate::messages::{MessageHandler, MessageId, Messages};
+use crate::{MdnsPeer, PeerEvent, PeerOptions};
+
+/// mDNS service name the temporary peers advertise on
+pub const SELF_TEST_SERVICE: &str = "mdns-peer.self-test";
+
+/// How long discovery, and then the message, may take by default
+pub const DEFAULT_STEP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Outcome of [`run_self_test`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SelfTestReport {
+    /// Every step passed
+    pub passed: bool,
+    /// In order, up to and including the first that failed
+    pub steps: Vec<SelfTestStep>,
+}
+
+/// One step of the self-test
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SelfTestStep {
+    /// `start`, `discover` or `message`
+    pub name: &'static str,
+    pub passed: bool,
+    pub elapsed_ms: u64,
+    pub detail: String,
+    /// What to try when the step failed
+    pub hint: Option<String>,
+}
+
+impl SelfTestReport {
+    fn push(&mut self, name: &'static str, started: Instant, outcome: Result<String, Failure>) {
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        let step = match outcome {
+            Ok(detail) => SelfTestStep {
+                name,
+                passed: true,
+                elapsed_ms,
+                detail,
+                hint: None,
+            },
+            Err(Failure { detail, hint }) => SelfTestStep {
+                name,
+                passed: false,
+                elapsed_ms,
+                detail,
+                hint: Some(hint.to_string()),
+            },
+        };
+        self.steps.push(step);
+        self.passed = self.steps.iter().all(|step| step.passed);
+    }
+}
+
+struct Failure {
+    detail: String,
+    hint: &'static str,
+}
+
+impl Failure {
+    fn new(detail: impl Into<String>, hint: &'static str) -> Self {
+        Self {
+            detail: detail.into(),
+            hint,
+        }
+    }
+}
+
+/// Start two temporary peers with the cadence and response rate of `mdns`,
+/// check they discover each other and that a message gets from one to the
+/// other, then stop them
+///
+/// Discovery and the message may each take up to `timeout`. Never fails:
+/// problems are reported as a failed step, after which the remaining steps
+/// are skipped.
+pub async fn run_self_test(mdns: &MdnsOptions, timeout: Duration) -> SelfTestReport {
+    let mut report = SelfTestReport::default();
+    let mdns = MdnsOptions {
+        service_name: SELF_TEST_SERVICE.to_string(),
+        ..mdns.clone()
+    };
+    let run = Uuid::new_v4().simple().to_string();
+    let run = &run[..8];
+
+    let started = Instant::now();
+    let a = Side::start(format!("self-test-{}-a", run), &mdns).await;
+    let b = Side::start(format!("self-test-{}-b", run), &mdns).await;
+    let (mut a, mut b) = match (a, b) {
+        (Ok(a), Ok(b)) => {
+            report.push("start", started, Ok("started two temporary peers".into()));
+            (a, b)
+        }
+        (a, b) => {
+            let mut errors = Vec::new();
+            for side in [a, b] {
+                match side {
+                    Ok(side) => {
+                        side.peer.shutdown().await;
+                    }
+                    Err(e) => errors.push(format!("{:#}", e)),
+                }
+            }
+            let failure = Failure::new(
+                errors.join("; "),
+                "the app couldn't bind a UDP socket; check it may use the network",
+            );
+            report.push("start", started, Err(failure));
+            return report;
+        }
+    };
+
+    let started = Instant::now();
+    let outcome = discover(&mut a, &mut b, timeout).await;
+    let discovered = outcome.is_ok();
+    report.push("discover", started, outcome);
+
+    if discovered {
+        let started = Instant::now();
+        let outcome = exchange(&a, &mut b, run, timeout).await;
+        report.push("message", started, outcome);
+    }
+
+    a.peer.shutdown().await;
+    b.peer.shutdown().await;
+    report
+}
+
+/// Wait for each peer to discover the other
+async fn discover(a: &mut Side, b: &mut Side, timeout: Duration) -> Result<String, Failure> {
+    let (a_id, b_id) = (a.peer.node_id(), b.peer.node_id());
+    let both = async {
+        let _ = tokio::join!(
+            a.discovered.wait_for(|seen| seen.contains(&b_id)),
+            b.discovered.wait_for(|seen| seen.contains(&a_id)),
+        );
+    };
+    if tokio::time::timeout(timeout, both).await.is_ok() {
+        return Ok("each peer discovered the other over mDNS".into());
+    }
+
+    let a_found = a.discovered.borrow().contains(&b_id);
+    let b_found = b.discovered.borrow().contains(&a_id);
+    let failure = match (a_found, b_found) {
+        (false, false) => Failure::new(
+            format!(
+                "neither peer discovered the other within {} ms",
+                timeout.as_millis()
+            ),
+            "multicast isn't getting through: on iOS the app needs the multicast \
+             entitlement and Local Network permission, and the device has to be on Wi-Fi \
+             or Ethernet rather than only cellular",
+        ),
+        _ => Failure::new(
+            format!(
+                "only one peer discovered the other within {} ms",
+                timeout.as_millis()
+            ),
+            "multicast works one way only, which points at a firewall or a VPN taking \
+             over the multicast route",
+        ),
+    };
+    Err(failure)
+}
+
+/// Send a message from `a` to `b` and wait for its delivery receipt
+async fn exchange(a: &Side, b: &mut Side, run: &str, timeout: Duration) -> Result<String, Failure> {
+    const HINT: &str = "the peers find each other but can't connect: a firewall may be \
+                        dropping UDP between them";
+
+    let payload = format!("self-test {}", run).into_bytes();
+    let (tx, settled) = oneshot::channel();
+    let sent = a.messages.send_then(
+        b.peer.node_id(),
+        payload.clone(),
+        false,
+        Some(timeout),
+        move |event| {
+            let _ = tx.send(event.clone());
+        },
+    );
+    if let Err(e) = sent {
+        return Err(Failure::new(format!("{:#}", e), HINT));
+    }
+
+    match tokio::time::timeout(timeout, settled).await {
+        Ok(Ok(PeerEvent::MessageDelivered { .. })) => {}
+        Ok(Ok(PeerEvent::MessageFailed { reason, .. })) => {
+            return Err(Failure::new(reason, HINT));
+        }
+        _ => {
+            let detail = format!("no delivery receipt within {} ms", timeout.as_millis());
+            return Err(Failure::new(detail, HINT));
+        }
+    }
+    match b.inbox.try_recv() {
+        Ok(received) if received == payload => Ok("a message and its receipt went through".into()),
+        _ => Err(Failure::new(
+            "the receipt arrived, but the message didn't reach the handler",
+            "this is a bug in mdns-peer; please report it",
+        )),
+    }
+}
+
+/// One of the two temporary peers
+struct Side {
+    peer: MdnsPeer,
+    messages: Messages,
+    /// Node IDs it discovered
+    discovered: watch::Receiver<HashSet<NodeId>>,
+    inbox: mpsc::UnboundedReceiver<Vec<u8>>,
+}
+
+struct Inbox(mpsc::UnboundedSender<Vec<u8>>);
+
+impl MessageHandler for Inbox {
+    fn on_message(&self, _node_id: NodeId, _id: MessageId, data: &[u8]) {
+        let _ = self.0.send(data.to_vec());
+    }
+}
+
+impl Side {
+    async fn start(identifier: String, mdns: &MdnsOptions) -> anyhow::Result<Self> {
+        let (tx, discovered) = watch::channel(HashSet::new());
+        let events: EventSink = Arc::new(move |event| {
+            if let PeerEvent::Discovered { node_id, .. } = event {
+                tx.send_if_modified(|seen| seen.insert(*node_id));
+            }
+        });
+        let options = PeerOptions {
+            summary_interval: None,
+            on_duplicate: DuplicatePolicy::Allow,
+            lan_only: true,
+            mdns: mdns.clone(),
+            ..Default::default()
+        };
+        let messages = options.messages.clone();
+        let (tx, inbox) = mpsc::unbounded_channel();
+        messages.set_handler(Some(Arc::new(Inbox(tx))));
+
+        let peer = MdnsPeer::builder()
+            .identifier(identifier)
+            .options(options)
+            .on_event(events)
+            .spawn()
+            .await?;
+        Ok(Self {
+            peer,
+            messages,
+            discovered,
+            inbox,
+        })
+    }
+}
@@ -0,0 +1,274 @@
+//! Recording and replaying discovery sessions
+//!
+//! A recording is NDJSON: a `start` line naming the local node, then one line
+//! per raw discovery event with its offset from the start of the recording.
+//! `at_ms` comes from a monotonic clock and orders the events; `wall_ms` is
+//! the wall-clock time, for lining a recording up with other logs.
+//! Replaying feeds those events back through the same registry and event
+//! pipeline as a live endpoint, so a field report can be reproduced offline.
+//! Peers record with user data hashed unless names are logged, see
+//! [`crate::privacy`]; such a recording replays the same with hashed names.
+//!
+//! ```json
+//! {"type":"start","wall_ms":1760601600000,"node_id":"a8a2...","user_data":"bob"}
+//! {"type":"discovered","at_ms":1250,"wall_ms":1760601601250,"node_id":"5c1e...","user_data":"alice","provenance":"local.swarm.discovery","relay_url":null,"direct_addrs":["192.168.1.20:51234"]}
+//! {"type":"expired","at_ms":31800,"wall_ms":1760601631800,"node_id":"5c1e..."}
+//! ```
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, LineWriter, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use iroh::{
+    discovery::{DiscoveryEvent, DiscoveryItem, NodeInfo},
+    NodeId,
+};
+use n0_future::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::discovery::{self, DiscoveryEventSource};
+use crate::events::{wall_clock_ms, PeerEvent};
+use crate::privacy;
+use crate::registry::PeerRegistry;
+use crate::warnings::{self, Condition};
+
+/// One line of a recording
+///
+/// `wall_ms` defaults to 0 so recordings made before it was added still load.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum SessionLine {
+    Start {
+        #[serde(default)]
+        wall_ms: u64,
+        node_id: NodeId,
+        user_data: Option<String>,
+    },
+    Discovered {
+        at_ms: u64,
+        #[serde(default)]
+        wall_ms: u64,
+        node_id: NodeId,
+        user_data: Option<String>,
+        provenance: String,
+        relay_url: Option<String>,
+        direct_addrs: Vec<SocketAddr>,
+    },
+    Expired {
+        at_ms: u64,
+        #[serde(default)]
+        wall_ms: u64,
+        node_id: NodeId,
+    },
+}
+
+/// Appends raw discovery events to a recording
+#[derive(Clone)]
+pub struct SessionRecorder {
+    started: Instant,
+    file: Arc<Mutex<LineWriter<File>>>,
+    /// Write user data as [`privacy::hash_name`] hashes
+    hash_names: bool,
+    /// Failing writes, e.g. on a full disk, logged as they start and end
+    failing: Arc<Mutex<Condition>>,
+}
+
+impl SessionRecorder {
+    /// Create (or truncate) the recording at `path` for local node `node_id`
+    pub fn create(
+        path: impl AsRef<Path>,
+        node_id: NodeId,
+        user_data: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        Self::create_with(path, node_id, user_data, false)
+    }
+
+    /// Like [`SessionRecorder::create`], writing all user data hashed if
+    /// `hash_names` is set
+    pub fn create_with(
+        path: impl AsRef<Path>,
+        node_id: NodeId,
+        user_data: Option<&str>,
+        hash_names: bool,
+    ) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .with_context(|| format!("failed to create recording {}", path.display()))?;
+        let recorder = Self {
+            started: Instant::now(),
+            file: Arc::new(Mutex::new(LineWriter::new(file))),
+            hash_names,
+            failing: Arc::new(Mutex::new(Condition::new("recording_failed"))),
+        };
+        recorder.write(&SessionLine::Start {
+            wall_ms: wall_clock_ms(),
+            node_id,
+            user_data: user_data.map(|d| recorder.name(d)),
+        });
+        Ok(recorder)
+    }
+
+    /// Record `event` at the current offset
+    pub fn record(&self, event: &DiscoveryEvent) {
+        let at_ms = self.started.elapsed().as_millis() as u64;
+        let wall_ms = wall_clock_ms();
+        let line = match event {
+            DiscoveryEvent::Discovered(item) => {
+                let data = &item.node_info().data;
+                SessionLine::Discovered {
+                    at_ms,
+                    wall_ms,
+                    node_id: item.node_id(),
+                    user_data: data.user_data().map(|d| self.name(d.as_ref())),
+                    provenance: item.provenance().to_string(),
+                    relay_url: data.relay_url().map(ToString::to_string),
+                    direct_addrs: data.direct_addresses().iter().copied().collect(),
+                }
+            }
+            DiscoveryEvent::Expired(node_id) => SessionLine::Expired {
+                at_ms,
+                wall_ms,
+                node_id: *node_id,
+            },
+        };
+        self.write(&line);
+    }
+
+    /// Wrap `source` so every event it yields is recorded
+    pub fn record_source(&self, source: DiscoveryEventSource) -> DiscoveryEventSource {
+        let recorder = self.clone();
+        Box::pin(source.inspect(move |event| {
+            if let Ok(event) = event {
+                recorder.record(event);
+            }
+        }))
+    }
+
+    fn name(&self, user_data: &str) -> String {
+        if self.hash_names {
+            privacy::hash_name(user_data)
+        } else {
+            user_data.to_string()
+        }
+    }
+
+    fn write(&self, line: &SessionLine) {
+        let json = serde_json::to_string(line).expect("session lines are always serializable");
+        // A failing disk shouldn't take discovery down with it
+        let written = writeln!(self.file.lock().unwrap(), "{}", json);
+        let mut failing = self.failing.lock().unwrap();
+        let warning = match written {
+            Ok(()) => failing.clear(Instant::now()),
+            Err(e) => failing.hold(Instant::now(), format!("Failed to write recording: {}", e)),
+        };
+        if let Some(warning) = warning {
+            warnings::log(&warning);
+        }
+    }
+}
+
+/// A recording read back from disk
+#[derive(Debug, Clone)]
+pub struct Session {
+    /// The node that made the recording; its own announcements are skipped
+    /// on replay just like live
+    pub node_id: NodeId,
+    pub user_data: Option<String>,
+    /// Events with their offset from the start of the recording
+    pub events: Vec<(Duration, DiscoveryEvent)>,
+}
+
+impl Session {
+    pub fn read(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .with_context(|| format!("failed to open recording {}", path.display()))?;
+
+        let mut start = None;
+        let mut events = Vec::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let line: SessionLine = serde_json::from_str(&line)
+                .with_context(|| format!("{}:{}: invalid line", path.display(), number + 1))?;
+            match line {
+                SessionLine::Start {
+                    node_id, user_data, ..
+                } => start = Some((node_id, user_data)),
+                SessionLine::Discovered {
+                    at_ms,
+                    node_id,
+                    user_data,
+                    provenance,
+                    relay_url,
+                    direct_addrs,
+                    ..
+                } => {
+                    let info = NodeInfo::new(node_id)
+                        .with_user_data(user_data.map(|d| d.parse()).transpose()?)
+                        .with_relay_url(relay_url.map(|url| url.parse()).transpose()?)
+                        .with_direct_addresses(direct_addrs.into_iter().collect());
+                    let item = DiscoveryItem::new(info, intern(&provenance), None);
+                    events.push((
+                        Duration::from_millis(at_ms),
+                        DiscoveryEvent::Discovered(item),
+                    ));
+                }
+                SessionLine::Expired { at_ms, node_id, .. } => {
+                    events.push((
+                        Duration::from_millis(at_ms),
+                        DiscoveryEvent::Expired(node_id),
+                    ));
+                }
+            }
+        }
+
+        let (node_id, user_data) =
+            start.with_context(|| format!("{}: missing start line", path.display()))?;
+        Ok(Self {
+            node_id,
+            user_data,
+            events,
+        })
+    }
+
+    /// The events as a discovery source, paced like the original recording
+    /// sped up by `speed` (2.0 plays twice as fast)
+    pub fn source(self, speed: f64) -> DiscoveryEventSource {
+        let speed = if speed > 0.0 { speed } else { 1.0 };
+        let started = tokio::time::Instant::now();
+        Box::pin(
+            stream::iter(self.events).then(move |(at, event)| async move {
+                tokio::time::sleep_until(started + at.div_f64(speed)).await;
+                Ok(event)
+            }),
+        )
+    }
+
+    /// Feed the recording through a fresh registry, passing each resulting
+    /// [`PeerEvent`] to `on_event`; returns once every event was replayed
+    pub async fn replay(self, speed: f64, on_event: impl FnMut(PeerEvent)) {
+        let node_id = self.node_id;
+        let registry = Arc::new(Mutex::new(PeerRegistry::new()));
+        discovery::run_discovery_loop(self.source(speed), node_id, registry, on_event).await;
+    }
+}
+
+/// Provenance strings are `&'static str` in iroh; the handful of distinct
+/// values in a recording are leaked once each
+fn intern(provenance: &str) -> &'static str {
+    static INTERNED: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+    let mut interned = INTERNED.lock().unwrap();
+    if let Some(existing) = interned.iter().find(|p| **p == provenance) {
+        return existing;
+    }
+    let leaked: &'static str = Box::leak(provenance.to_string().into_boxed_str());
+    interned.push(leaked);
+    leaked
+}
@@ -0,0 +1,240 @@
+//! Snapshots of the discovered peer set, and what changed between two
+//!
+//! A snapshot is every peer one node found while listening to discovery for
+//! a while, saved as JSON. Comparing snapshots taken on different networks
+//! or days shows which peers came and went and whose addresses changed,
+//! which is where triaging "it saw 5 peers yesterday, 2 today" starts.
+//!
+//! Peers are matched by node ID. Peers without a persistent identity (see
+//! [`crate::profile`]) get a new node ID on every run, so a peer that is
+//! gone by node ID but back with the same user data is reported as changed,
+//! not as one removed and one added peer.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use iroh::discovery::DiscoveryEvent;
+use iroh::NodeId;
+use n0_future::StreamExt;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::discovery::DiscoveryEventSource;
+use crate::events::wall_clock_ms;
+
+/// The peers one node discovered within a listening window
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// When listening ended, in milliseconds since the Unix epoch
+    pub wall_ms: u64,
+    /// The node that took the snapshot
+    pub node_id: NodeId,
+    pub user_data: Option<String>,
+    /// How long discovery was listened to
+    pub listened_ms: u64,
+    /// Ordered by node ID
+    pub peers: Vec<SnapshotPeer>,
+}
+
+/// One discovered peer, as last announced
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotPeer {
+    pub node_id: NodeId,
+    pub user_data: Option<String>,
+    /// Discovery mechanism that last reported the peer
+    pub provenance: String,
+    pub relay_url: Option<String>,
+    pub direct_addrs: Vec<SocketAddr>,
+}
+
+impl SnapshotPeer {
+    /// User data if the peer announced any, otherwise the short node ID
+    fn label(&self) -> String {
+        self.user_data
+            .clone()
+            .unwrap_or_else(|| self.node_id.fmt_short().to_string())
+    }
+}
+
+impl Snapshot {
+    /// Listen to `source` for `listen` and return the peers still known at
+    /// the end, skipping `node_id` itself
+    pub async fn collect(
+        mut source: DiscoveryEventSource,
+        node_id: NodeId,
+        user_data: Option<String>,
+        listen: Duration,
+    ) -> Self {
+        let started = Instant::now();
+        let mut peers = BTreeMap::new();
+        let listening = async {
+            while let Some(event) = source.next().await {
+                match event {
+                    Ok(DiscoveryEvent::Discovered(item)) if item.node_id() != node_id => {
+                        let data = &item.node_info().data;
+                        let peer = SnapshotPeer {
+                            node_id: item.node_id(),
+                            user_data: data.user_data().map(|d| d.to_string()),
+                            provenance: item.provenance().to_string(),
+                            relay_url: data.relay_url().map(ToString::to_string),
+                            direct_addrs: data.direct_addresses().iter().copied().collect(),
+                        };
+                        peers.insert(peer.node_id, peer);
+                    }
+                    Ok(DiscoveryEvent::Expired(expired)) => {
+                        peers.remove(&expired);
+                    }
+                    Ok(_) => {}
+                    Err(e) => warn!("Discovery error: {}", e),
+                }
+            }
+        };
+        // The window ending is the normal way out; a source that ends early
+        // (scripted) just leaves less to wait for
+        let _ = tokio::time::timeout(listen, listening).await;
+        Self {
+            wall_ms: wall_clock_ms(),
+            node_id,
+            user_data,
+            listened_ms: started.elapsed().as_millis() as u64,
+            peers: peers.into_values().collect(),
+        }
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read(path)
+            .with_context(|| format!("Failed to read snapshot {}", path.display()))?;
+        serde_json::from_slice(&json)
+            .with_context(|| format!("Corrupt snapshot {}", path.display()))
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let json = serde_json::to_vec_pretty(self)?;
+        std::fs::write(path, json)
+            .with_context(|| format!("Failed to write snapshot {}", path.display()))
+    }
+}
+
+/// How the peer set changed from one snapshot to another
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SnapshotDiff {
+    pub added: Vec<SnapshotPeer>,
+    pub removed: Vec<SnapshotPeer>,
+    /// Peers in both whose announcement differs
+    pub changed: Vec<PeerChange>,
+    /// Peers announced identically in both
+    pub unchanged: usize,
+}
+
+/// The same peer in two snapshots, by node ID or else by user data
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PeerChange {
+    pub before: SnapshotPeer,
+    pub after: SnapshotPeer,
+}
+
+impl SnapshotDiff {
+    pub fn new(old: &Snapshot, new: &Snapshot) -> Self {
+        let mut diff = Self::default();
+        let mut after: BTreeMap<NodeId, &SnapshotPeer> =
+            new.peers.iter().map(|peer| (peer.node_id, peer)).collect();
+        let mut gone = Vec::new();
+        for before in &old.peers {
+            match after.remove(&before.node_id) {
+                Some(peer) if peer == before => diff.unchanged += 1,
+                Some(peer) => diff.changed.push(PeerChange {
+                    before: before.clone(),
+                    after: peer.clone(),
+                }),
+                None => gone.push(before),
+            }
+        }
+        for before in gone {
+            let renamed = before.user_data.as_ref().and_then(|user_data| {
+                let (node_id, _) = after
+                    .iter()
+                    .find(|(_, peer)| peer.user_data.as_ref() == Some(user_data))?;
+                let node_id = *node_id;
+                after.remove(&node_id)
+            });
+            match renamed {
+                Some(peer) => diff.changed.push(PeerChange {
+                    before: before.clone(),
+                    after: peer.clone(),
+                }),
+                None => diff.removed.push(before.clone()),
+            }
+        }
+        diff.added = after.into_values().cloned().collect();
+        diff
+    }
+
+    /// Whether both snapshots saw the same peers announced the same way
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// One line per peer that differs: `+` added, `-` removed, `~` changed
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let addrs = |peer: &SnapshotPeer| {
+            let mut addrs: Vec<_> = peer.direct_addrs.iter().map(ToString::to_string).collect();
+            addrs.extend(peer.relay_url.clone());
+            addrs.join(", ")
+        };
+        for peer in &self.added {
+            writeln!(
+                f,
+                "+ {} ({}) {}",
+                peer.label(),
+                peer.node_id.fmt_short(),
+                addrs(peer)
+            )?;
+        }
+        for peer in &self.removed {
+            writeln!(
+                f,
+                "- {} ({}) {}",
+                peer.label(),
+                peer.node_id.fmt_short(),
+                addrs(peer)
+            )?;
+        }
+        for PeerChange { before, after } in &self.changed {
+            write!(f, "~ {}:", before.label())?;
+            if before.node_id != after.node_id {
+                write!(
+                    f,
+                    " node ID {} -> {};",
+                    before.node_id.fmt_short(),
+                    after.node_id.fmt_short()
+                )?;
+            }
+            if before.user_data != after.user_data {
+                write!(f, " user data -> {};", after.label())?;
+            }
+            if addrs(before) != addrs(after) {
+                write!(f, " addresses [{}] -> [{}];", addrs(before), addrs(after))?;
+            }
+            if before.provenance != after.provenance {
+                write!(f, " via {} -> {};", before.provenance, after.provenance)?;
+            }
+            writeln!(f)?;
+        }
+        write!(
+            f,
+            "{} added, {} removed, {} changed, {} unchanged",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len(),
+            self.unchanged
+        )
+    }
+}
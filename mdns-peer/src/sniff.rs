@@ -0,0 +1,305 @@
+//! Passive view of the mDNS traffic on this machine (`mdns-peer sniff`)
+//!
+//! When two peers don't see each other, the first question is whether their
+//! packets reach the machine at all. This joins the mDNS group on every
+//! IPv4 interface, sharing port 5353 with the responders already running,
+//! and decodes each datagram into its questions and records. Nothing is
+//! sent.
+//!
+//! Only IPv4 is covered; iroh's local discovery announces over IPv4 too.
+
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tokio::net::UdpSocket;
+use tracing::warn;
+
+use crate::multicast::{bind_mdns, MDNS_GROUP};
+
+/// Largest datagram read; mDNS packets stay well below a jumbo frame
+const MAX_PACKET: usize = 9000;
+
+/// Record types worth naming in the output
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+
+/// A decoded mDNS datagram
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MdnsPacket {
+    /// An answer rather than a query
+    pub response: bool,
+    pub questions: Vec<Question>,
+    /// Answer, authority and additional records, in that order
+    pub records: Vec<Record>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Question {
+    /// Dotted name without the trailing dot, e.g. `_iroh._udp.local`
+    pub name: String,
+    pub qtype: u16,
+    /// The QU bit: the asker wants a unicast reply
+    pub unicast_response: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub name: String,
+    pub ttl: u32,
+    pub data: RecordData,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Ptr(String),
+    Srv {
+        port: u16,
+        target: String,
+    },
+    Txt(Vec<String>),
+    /// Any other type, with the length of its data
+    Other {
+        rtype: u16,
+        len: usize,
+    },
+}
+
+impl MdnsPacket {
+    /// Decode a datagram, or `None` if it isn't well-formed DNS
+    pub fn parse(packet: &[u8]) -> Option<Self> {
+        let mut reader = Reader { packet, pos: 12 };
+        let header = packet.get(..12)?;
+        let count = |i: usize| u16::from_be_bytes([header[i], header[i + 1]]);
+        let (questions, records) = (
+            count(4),
+            count(6) as usize + count(8) as usize + count(10) as usize,
+        );
+
+        let mut parsed = Self {
+            response: header[2] & 0x80 != 0,
+            questions: Vec::new(),
+            records: Vec::new(),
+        };
+        for _ in 0..questions {
+            let name = reader.name()?;
+            let qtype = reader.u16()?;
+            let class = reader.u16()?;
+            parsed.questions.push(Question {
+                name,
+                qtype,
+                unicast_response: class & 0x8000 != 0,
+            });
+        }
+        for _ in 0..records {
+            parsed.records.push(reader.record()?);
+        }
+        Some(parsed)
+    }
+
+    /// Whether any question or record names something under `_<service>._udp.local`
+    pub fn mentions(&self, service: &str) -> bool {
+        let suffix = format!("_{}._udp.local", service).to_ascii_lowercase();
+        let matches = |name: &str| name.to_ascii_lowercase().ends_with(&suffix);
+        self.questions.iter().any(|q| matches(&q.name))
+            || self.records.iter().any(|r| {
+                matches(&r.name) || matches!(&r.data, RecordData::Ptr(target) if matches(target))
+            })
+    }
+}
+
+/// One line per question and record, indented below a summary line
+impl fmt::Display for MdnsPacket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({} questions, {} records)",
+            if self.response { "response" } else { "query" },
+            self.questions.len(),
+            self.records.len()
+        )?;
+        for question in &self.questions {
+            write!(
+                f,
+                "\n    ? {} {}{}",
+                type_name(question.qtype),
+                question.name,
+                if question.unicast_response {
+                    " (QU)"
+                } else {
+                    ""
+                }
+            )?;
+        }
+        for record in &self.records {
+            write!(f, "\n    {} ttl={} ", record.name, record.ttl)?;
+            match &record.data {
+                RecordData::A(ip) => write!(f, "A {}", ip)?,
+                RecordData::Aaaa(ip) => write!(f, "AAAA {}", ip)?,
+                RecordData::Ptr(target) => write!(f, "PTR {}", target)?,
+                RecordData::Srv { port, target } => write!(f, "SRV {}:{}", target, port)?,
+                RecordData::Txt(strings) => write!(f, "TXT {:?}", strings)?,
+                RecordData::Other { rtype, len } => {
+                    write!(f, "{} ({} bytes)", type_name(*rtype), len)?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn type_name(rtype: u16) -> String {
+    match rtype {
+        TYPE_A => "A".to_string(),
+        TYPE_PTR => "PTR".to_string(),
+        TYPE_TXT => "TXT".to_string(),
+        TYPE_AAAA => "AAAA".to_string(),
+        TYPE_SRV => "SRV".to_string(),
+        TYPE_ANY => "ANY".to_string(),
+        other => format!("TYPE{}", other),
+    }
+}
+
+/// Cursor over a DNS message, following name compression pointers
+struct Reader<'a> {
+    packet: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, len: usize) -> Option<&[u8]> {
+        let bytes = self.packet.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.bytes(2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let bytes = self.bytes(4)?;
+        Some(u32::from_be_bytes(bytes.try_into().ok()?))
+    }
+
+    /// A possibly compressed name, leaving the cursor after it
+    fn name(&mut self) -> Option<String> {
+        let mut labels = Vec::new();
+        let mut pos = self.pos;
+        let mut end = None;
+        // Each pointer must go backwards, which also rules out loops
+        let mut limit = pos;
+        loop {
+            let len = *self.packet.get(pos)? as usize;
+            match len {
+                0 => {
+                    pos += 1;
+                    break;
+                }
+                len if len & 0xc0 == 0xc0 => {
+                    let target = ((len & 0x3f) << 8) | *self.packet.get(pos + 1)? as usize;
+                    if target >= limit {
+                        return None;
+                    }
+                    end.get_or_insert(pos + 2);
+                    pos = target;
+                    limit = target;
+                }
+                len if len & 0xc0 == 0 => {
+                    let label = self.packet.get(pos + 1..pos + 1 + len)?;
+                    labels.push(String::from_utf8_lossy(label).into_owned());
+                    pos += 1 + len;
+                }
+                _ => return None,
+            }
+        }
+        self.pos = end.unwrap_or(pos);
+        Some(labels.join("."))
+    }
+
+    fn record(&mut self) -> Option<Record> {
+        let name = self.name()?;
+        let rtype = self.u16()?;
+        let _class = self.u16()?;
+        let ttl = self.u32()?;
+        let len = self.u16()? as usize;
+        let start = self.pos;
+        let rdata = self.bytes(len)?;
+        let data = match rtype {
+            TYPE_A => RecordData::A(<[u8; 4]>::try_from(rdata).ok()?.into()),
+            TYPE_AAAA => RecordData::Aaaa(<[u8; 16]>::try_from(rdata).ok()?.into()),
+            TYPE_PTR => {
+                let mut inner = Reader {
+                    packet: self.packet,
+                    pos: start,
+                };
+                RecordData::Ptr(inner.name()?)
+            }
+            TYPE_SRV => {
+                let mut inner = Reader {
+                    packet: self.packet,
+                    pos: start,
+                };
+                let _priority = inner.u16()?;
+                let _weight = inner.u16()?;
+                let port = inner.u16()?;
+                RecordData::Srv {
+                    port,
+                    target: inner.name()?,
+                }
+            }
+            TYPE_TXT => {
+                let mut strings = Vec::new();
+                let mut rest = rdata;
+                while let Some((&len, tail)) = rest.split_first() {
+                    let string = tail.get(..len as usize)?;
+                    strings.push(String::from_utf8_lossy(string).into_owned());
+                    rest = &tail[len as usize..];
+                }
+                RecordData::Txt(strings)
+            }
+            rtype => RecordData::Other { rtype, len },
+        };
+        Some(Record { name, ttl, data })
+    }
+}
+
+/// Listen to the mDNS group on every IPv4 interface until the returned
+/// future is dropped, passing each decoded packet and its sender to
+/// `on_packet`
+///
+/// Datagrams that don't decode are reported with `None`.
+pub async fn sniff(
+    mut on_packet: impl FnMut(SocketAddr, Option<MdnsPacket>),
+) -> anyhow::Result<()> {
+    let socket = bind_mdns(true)?;
+    let mut joined = 0;
+    for iface in netdev::get_interfaces() {
+        if !iface.is_up() || !iface.is_multicast() {
+            continue;
+        }
+        for net in &iface.ipv4 {
+            match socket.join_multicast_v4(&MDNS_GROUP, &net.addr()) {
+                Ok(()) => joined += 1,
+                Err(e) => warn!("Not listening on {} ({}): {}", iface.name, net.addr(), e),
+            }
+        }
+    }
+    if joined == 0 {
+        socket.join_multicast_v4(&MDNS_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+    }
+    socket.set_nonblocking(true)?;
+    let socket = UdpSocket::from_std(socket)?;
+
+    let mut buf = vec![0u8; MAX_PACKET];
+    loop {
+        let (len, from) = socket.recv_from(&mut buf).await?;
+        on_packet(from, MdnsPacket::parse(&buf[..len]));
+    }
+}
@@ -0,0 +1,370 @@
+//! Long-running soak test (`mdns-peer soak`)
+//!
+//! Repeatedly starts a pair of full peers, waits for them to discover each
+//! other, and stops them again. With the `ffi` feature one of the pair goes
+//! through `peer_start` and `peer_stop`, the way the app runs its peer, and
+//! the other is an [`MdnsPeer`]; without it both are. After every cycle the
+//! process memory, open file descriptors and live tokio tasks are sampled,
+//! so slow leaks show up as steady growth over the run, and
+//! [`SoakReport::check`] fails if tasks or descriptors kept growing through
+//! the second half of the run. Memory is read from `/proc` on Linux and
+//! from `ps` elsewhere, and left out where neither is available.
+
+#[cfg(feature = "ffi")]
+use std::ffi::CString;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::events::{EventSink, PeerEvent};
+use crate::initialize_logging;
+use crate::peer::MdnsPeer;
+
+/// How far live tasks or open file descriptors may end above the baseline
+/// before [`SoakReport::check`] calls it a leak
+///
+/// Both wobble by a couple dozen while the sockets and timers of the last
+/// few cycles wind down. A leak of even one per cycle exceeds it within
+/// the first hour.
+pub const GROWTH_ALLOWANCE: usize = 32;
+
+/// Longest a peer may take to stop before its cycle counts as failed
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the FFI peer's registry and status are polled
+#[cfg(feature = "ffi")]
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Settings for a soak run
+#[derive(Debug, Clone)]
+pub struct SoakOptions {
+    /// Total time to keep cycling
+    pub duration: Duration,
+    /// How long each cycle waits for mutual discovery before counting a failure
+    pub discovery_timeout: Duration,
+    /// Pause between cycles
+    pub pause: Duration,
+}
+
+impl Default for SoakOptions {
+    fn default() -> Self {
+        Self {
+            duration: Duration::from_secs(8 * 60 * 60),
+            discovery_timeout: Duration::from_secs(30),
+            pause: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Process resource usage at one point in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceSample {
+    /// Resident set size in KiB, where the platform exposes it
+    pub rss_kib: Option<u64>,
+    /// Open file descriptors, where the platform exposes them
+    pub open_fds: Option<usize>,
+    /// Tasks alive on the current tokio runtime and, with the `ffi` feature,
+    /// on the runtime `peer_start` runs the peer on
+    pub alive_tasks: usize,
+}
+
+impl ResourceSample {
+    /// Sample this process, from within a tokio runtime
+    pub fn take() -> Self {
+        Self {
+            rss_kib: rss_kib(),
+            open_fds: open_fds(),
+            alive_tasks: tokio::runtime::Handle::current()
+                .metrics()
+                .num_alive_tasks()
+                + ffi_tasks(),
+        }
+    }
+}
+
+impl std::fmt::Display for ResourceSample {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.rss_kib {
+            Some(rss) => write!(f, "rss={}KiB", rss)?,
+            None => write!(f, "rss=?")?,
+        }
+        match self.open_fds {
+            Some(fds) => write!(f, " fds={}", fds)?,
+            None => write!(f, " fds=?")?,
+        }
+        write!(f, " tasks={}", self.alive_tasks)
+    }
+}
+
+/// What a soak run found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SoakReport {
+    pub cycles: u64,
+    /// Cycles whose peers didn't discover each other in time, or didn't stop
+    pub failures: u64,
+    /// Usage after the first cycle past half the run, once the runtimes,
+    /// threads, sockets and caches of the first cycles have settled
+    pub baseline: ResourceSample,
+    /// Usage after the last cycle
+    pub last: ResourceSample,
+}
+
+impl SoakReport {
+    /// Fails if any cycle did, or if live tasks or open file descriptors
+    /// grew by more than [`GROWTH_ALLOWANCE`] after the baseline
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.failures > 0 {
+            anyhow::bail!("{} of {} cycles failed", self.failures, self.cycles);
+        }
+        let (from, to) = (self.baseline.alive_tasks, self.last.alive_tasks);
+        if to > from + GROWTH_ALLOWANCE {
+            anyhow::bail!(
+                "live tasks grew from {} to {} in the second half of {} cycles",
+                from,
+                to,
+                self.cycles
+            );
+        }
+        if let (Some(from), Some(to)) = (self.baseline.open_fds, self.last.open_fds) {
+            if to > from + GROWTH_ALLOWANCE {
+                anyhow::bail!(
+                    "open file descriptors grew from {} to {} in the second half of {} cycles",
+                    from,
+                    to,
+                    self.cycles
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run start/discover/stop cycles until `options.duration` has elapsed
+///
+/// Cycles that fail are counted in the report rather than ending the run,
+/// see [`SoakReport::check`].
+pub async fn run_soak(options: SoakOptions) -> SoakReport {
+    initialize_logging();
+
+    info!(
+        "Soak test starting: {:?} total, {:?} discovery timeout per cycle",
+        options.duration, options.discovery_timeout
+    );
+
+    let started = Instant::now();
+    let mut last = ResourceSample::take();
+    info!("Before the first cycle: {}", last);
+
+    let mut cycles = 0u64;
+    let mut failures = 0u64;
+    let mut baseline = None;
+
+    while started.elapsed() < options.duration {
+        cycles += 1;
+        match run_cycle(cycles, options.discovery_timeout).await {
+            Ok(elapsed) => info!("Cycle {}: mutual discovery in {:?}", cycles, elapsed),
+            Err(e) => {
+                failures += 1;
+                warn!("Cycle {} failed: {}", cycles, e);
+            }
+        }
+
+        // Sampled after the pause, once the stopped peers' tasks wound down
+        tokio::time::sleep(options.pause).await;
+        last = ResourceSample::take();
+        if started.elapsed() >= options.duration / 2 {
+            baseline.get_or_insert(last);
+        }
+        info!(
+            "Cycle {}: {} (uptime {:?}, {} failures)",
+            cycles,
+            last,
+            started.elapsed(),
+            failures
+        );
+    }
+
+    let report = SoakReport {
+        cycles,
+        failures,
+        baseline: baseline.unwrap_or(last),
+        last,
+    };
+    info!(
+        "Soak test finished after {} cycles, {} failures",
+        report.cycles, report.failures
+    );
+    info!("Baseline: {}", report.baseline);
+    info!("Final:    {}", report.last);
+    report
+}
+
+/// Start two full peers, wait for mutual discovery, and stop them
+async fn run_cycle(cycle: u64, timeout: Duration) -> anyhow::Result<Duration> {
+    let start = Instant::now();
+    let a_name = format!("soak-a-{}", cycle);
+    let b_name = format!("soak-b-{}", cycle);
+
+    let mut a = CyclePeer::start_app(&a_name).await?;
+    let mut b = match CyclePeer::start_embedded(&b_name).await {
+        Ok(b) => b,
+        Err(e) => {
+            a.stop().await;
+            return Err(e);
+        }
+    };
+
+    let result = tokio::time::timeout(timeout, async {
+        tokio::join!(a.discovered(&b_name), b.discovered(&a_name))
+    })
+    .await;
+
+    let (a_stopped, b_stopped) = tokio::join!(a.stop(), b.stop());
+    anyhow::ensure!(
+        a_stopped && b_stopped,
+        "peers didn't stop within {:?}",
+        STOP_TIMEOUT
+    );
+
+    match result {
+        Ok((true, true)) => Ok(start.elapsed()),
+        Ok(_) => anyhow::bail!("a peer stopped before discovery"),
+        Err(_) => anyhow::bail!("no mutual discovery within {:?}", timeout),
+    }
+}
+
+/// One of the two peers of a cycle
+enum CyclePeer {
+    /// Started with `peer_start`, the way the app runs its peer
+    #[cfg(feature = "ffi")]
+    App,
+    /// With the user data of every peer it discovers, from the first event
+    /// on, so an early discovery isn't missed
+    Embedded(Box<MdnsPeer>, mpsc::UnboundedReceiver<String>),
+}
+
+impl CyclePeer {
+    /// Start through `peer_start`
+    #[cfg(feature = "ffi")]
+    async fn start_app(identifier: &str) -> anyhow::Result<Self> {
+        let c_identifier = CString::new(identifier)?;
+        let started = unsafe { crate::ffi::peer_start(c_identifier.as_ptr()) };
+        anyhow::ensure!(started, "peer_start refused {}", identifier);
+        Ok(Self::App)
+    }
+
+    /// Without the FFI, the app's side is an [`MdnsPeer`] too
+    #[cfg(not(feature = "ffi"))]
+    async fn start_app(identifier: &str) -> anyhow::Result<Self> {
+        Self::start_embedded(identifier).await
+    }
+
+    async fn start_embedded(identifier: &str) -> anyhow::Result<Self> {
+        let (found_tx, found) = mpsc::unbounded_channel();
+        let events: EventSink = Arc::new(move |event| {
+            if let PeerEvent::Discovered {
+                user_data: Some(user_data),
+                ..
+            } = event
+            {
+                // Nobody waiting any more is fine
+                let _ = found_tx.send(user_data.clone());
+            }
+        });
+        let peer = MdnsPeer::builder()
+            .identifier(identifier)
+            .on_event(events)
+            .spawn()
+            .await?;
+        Ok(Self::Embedded(Box::new(peer), found))
+    }
+
+    /// Wait until this peer discovers one announcing `identifier`, false if
+    /// it stops first
+    async fn discovered(&mut self, identifier: &str) -> bool {
+        match self {
+            #[cfg(feature = "ffi")]
+            Self::App => {
+                let Ok(c_identifier) = CString::new(identifier) else {
+                    return false;
+                };
+                let stopped = crate::events::PeerStatus::Stopped as i32;
+                loop {
+                    if unsafe { crate::ffi::peer_has_peer(c_identifier.as_ptr()) } {
+                        return true;
+                    }
+                    if crate::ffi::peer_status() == stopped {
+                        return false;
+                    }
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+            }
+            Self::Embedded(_, found) => {
+                while let Some(user_data) = found.recv().await {
+                    if user_data == identifier {
+                        return true;
+                    }
+                }
+                false
+            }
+        }
+    }
+
+    /// Stop the peer and wait for it, false if it took longer than
+    /// [`STOP_TIMEOUT`]
+    async fn stop(&mut self) -> bool {
+        let stopped = async {
+            match self {
+                #[cfg(feature = "ffi")]
+                Self::App => {
+                    crate::ffi::peer_stop();
+                    while crate::ffi::peer_shutdown_reason() == 0 {
+                        tokio::time::sleep(POLL_INTERVAL).await;
+                    }
+                }
+                Self::Embedded(peer, _) => {
+                    peer.shutdown().await;
+                }
+            }
+        };
+        tokio::time::timeout(STOP_TIMEOUT, stopped).await.is_ok()
+    }
+}
+
+/// Tasks alive on the runtime `peer_start` runs the peer on
+#[cfg(feature = "ffi")]
+fn ffi_tasks() -> usize {
+    crate::ffi::alive_tasks()
+}
+
+#[cfg(not(feature = "ffi"))]
+fn ffi_tasks() -> usize {
+    0
+}
+
+#[cfg(target_os = "linux")]
+fn rss_kib() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// `ps` reports it in KiB on macOS and the BSDs
+#[cfg(not(target_os = "linux"))]
+fn rss_kib() -> Option<u64> {
+    let output = std::process::Command::new("ps")
+        .args(["-o", "rss=", "-p", &std::process::id().to_string()])
+        .output()
+        .ok()?;
+    String::from_utf8(output.stdout).ok()?.trim().parse().ok()
+}
+
+fn open_fds() -> Option<usize> {
+    // /proc/self/fd on Linux, /dev/fd on macOS and the BSDs
+    ["/proc/self/fd", "/dev/fd"]
+        .iter()
+        .find_map(|dir| std::fs::read_dir(dir).ok())
+        .map(|entries| entries.count())
+}
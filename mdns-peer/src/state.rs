@@ -0,0 +1,216 @@
+//! Layout of the state directory, and keeping it readable across releases
+//!
+//! Everything the library persists lives in one directory: the one set with
+//! `peer_set_state_dir`, or `$MDNS_PEER_HOME`, or `~/.mdns-peer`.
+//!
+//! | Path              | Holds                                   | See                  |
+//! |-------------------|-----------------------------------------|----------------------|
+//! | `version`         | Layout version, [`STATE_VERSION`]       |                      |
+//! | `profiles/*.json` | Secret keys and the user data they send | [`crate::profile`]   |
+//! | `peers.json`      | Aliases and notes for known peers       | [`crate::notes`]     |
+//! | `groups.json`     | Peer groups                             | [`crate::groups`]    |
+//! | `history.json`    | Past sessions                           | [`crate::history`]   |
+//!
+//! Files are replaced with [`write_atomic`]: the new contents go to a
+//! temporary file next to the old one, are flushed to disk and renamed over
+//! it, so an app killed mid-write leaves either the old file or the new one,
+//! never a mix.
+//!
+//! Before a store reads the directory, [`migrate`] brings it up to
+//! [`STATE_VERSION`] by running each [`Migration`] from the recorded version
+//! on, recording the new version after every step. Since the app can be
+//! killed between a step and the record, a migration must be safe to run
+//! twice. A directory written by a newer release is refused rather than
+//! read, so running an older build can't overwrite state it doesn't
+//! understand.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use tracing::info;
+
+/// Layout version this release reads and writes
+pub const STATE_VERSION: u32 = 1;
+
+/// File holding the layout version, as a decimal number
+const VERSION_FILE: &str = "version";
+
+/// Suffix of the temporary files [`write_atomic`] renames into place
+const TEMP_SUFFIX: &str = ".tmp";
+
+/// One step of the layout, from its index in the list to the next version
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    /// What changes, logged while the step runs
+    pub description: &'static str,
+    /// Rewrite the state directory passed in
+    pub run: fn(&Path) -> anyhow::Result<()>,
+}
+
+/// Every step so far; entry `n` migrates version `n` to `n + 1`
+pub const MIGRATIONS: &[Migration] = &[Migration {
+    description: "record the layout version of unversioned directories",
+    run: |_| Ok(()),
+}];
+
+const _: () = assert!(MIGRATIONS.len() as u32 == STATE_VERSION);
+
+/// Bring `dir` up to [`STATE_VERSION`], creating it if it doesn't exist
+///
+/// Directories written before the layout was versioned count as version 0.
+pub fn migrate(dir: &Path) -> anyhow::Result<()> {
+    migrate_with(dir, MIGRATIONS)
+}
+
+/// [`migrate`] with the steps in `migrations`, whose length is the version
+/// the directory ends up at
+pub fn migrate_with(dir: &Path, migrations: &[Migration]) -> anyhow::Result<()> {
+    let target = migrations.len() as u32;
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    remove_temp_files(dir)?;
+
+    let version = match version(dir)? {
+        Some(version) => version,
+        None if is_empty(dir)? => {
+            return write_version(dir, target);
+        }
+        None => 0,
+    };
+    anyhow::ensure!(
+        version <= target,
+        "State directory {} has layout version {}, newer than the {} this release reads; \
+         update the app or use another directory",
+        dir.display(),
+        version,
+        target
+    );
+
+    for (from, migration) in migrations.iter().enumerate().skip(version as usize) {
+        info!(
+            "Migrating state directory {} from version {}: {}",
+            dir.display(),
+            from,
+            migration.description
+        );
+        (migration.run)(dir).with_context(|| {
+            format!("Failed to migrate {} from version {}", dir.display(), from)
+        })?;
+        write_version(dir, from as u32 + 1)?;
+    }
+    Ok(())
+}
+
+/// Layout version recorded in `dir`, `None` if there is none
+pub fn version(dir: &Path) -> anyhow::Result<Option<u32>> {
+    let path = dir.join(VERSION_FILE);
+    match fs::read_to_string(&path) {
+        Ok(contents) => {
+            let version = contents
+                .trim()
+                .parse()
+                .with_context(|| format!("Corrupt layout version in {}", path.display()))?;
+            Ok(Some(version))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+fn write_version(dir: &Path, version: u32) -> anyhow::Result<()> {
+    write_atomic(&dir.join(VERSION_FILE), version.to_string().as_bytes())
+}
+
+/// Replace `path` with `contents` all at once, creating its directory if
+/// needed
+pub fn write_atomic(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    replace(path, contents, false)
+}
+
+/// [`write_atomic`] for a file only the current user can read, such as one
+/// holding a secret key
+pub fn write_atomic_private(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    replace(path, contents, true)
+}
+
+fn replace(path: &Path, contents: &[u8], private: bool) -> anyhow::Result<()> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        anyhow::bail!("Not a file path: {}", path.display());
+    };
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let mut temp_name = name.to_owned();
+    temp_name.push(TEMP_SUFFIX);
+    let temp = dir.join(temp_name);
+    // A leftover from an interrupted write may have other permissions
+    let _ = fs::remove_file(&temp);
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        if private {
+            options.mode(0o600);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    let written = options.open(&temp).and_then(|mut file| {
+        file.write_all(contents)?;
+        file.sync_all()
+    });
+    if let Err(e) = written.and_then(|()| fs::rename(&temp, path)) {
+        let _ = fs::remove_file(&temp);
+        return Err(e).with_context(|| format!("Failed to write {}", path.display()));
+    }
+    sync_dir(dir);
+    Ok(())
+}
+
+/// Flush the rename in `dir` to disk, where the platform allows it
+fn sync_dir(dir: &Path) {
+    #[cfg(unix)]
+    {
+        if let Ok(dir) = fs::File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = dir;
+}
+
+/// Remove what interrupted writes left in `dir` and its subdirectories
+fn remove_temp_files(dir: &Path) -> anyhow::Result<()> {
+    for path in entries(dir)? {
+        if path.is_dir() {
+            for path in entries(&path)? {
+                remove_if_temp(&path);
+            }
+        } else {
+            remove_if_temp(&path);
+        }
+    }
+    Ok(())
+}
+
+fn remove_if_temp(path: &Path) {
+    let is_temp = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.ends_with(TEMP_SUFFIX));
+    if is_temp && path.is_file() {
+        let _ = fs::remove_file(path);
+    }
+}
+
+fn is_empty(dir: &Path) -> anyhow::Result<bool> {
+    Ok(entries(dir)?.is_empty())
+}
+
+fn entries(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    fs::read_dir(dir)
+        .and_then(|entries| entries.map(|entry| Ok(entry?.path())).collect())
+        .with_context(|| format!("Failed to read {}", dir.display()))
+}
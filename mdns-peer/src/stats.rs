@@ -0,0 +1,246 @@
+//! Bandwidth accounting for host protocol streams
+//!
+//! Payload bytes are counted per peer and ALPN as they pass through
+//! [`Protocols`](crate::protocols::Protocols), with rates over a rolling
+//! 10-second window. Reports include the current connection type, so it's
+//! visible whether a transfer is using the direct LAN path or a relay, and
+//! the addresses the network sees us as, see [`crate::network`].
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use iroh::{Endpoint, NodeId};
+use serde::Serialize;
+use tracing::info;
+
+use crate::network::{self, ObservedAddrs};
+use crate::quic::QuicStats;
+use crate::remote_info::ConnectionReport;
+use crate::transfer::TransferInfo;
+
+/// Length of the window rates are averaged over
+const RATE_WINDOW_SECS: u64 = 10;
+
+/// Byte counters keyed by peer and ALPN
+pub(crate) struct Traffic {
+    started: Instant,
+    counters: Mutex<HashMap<(NodeId, Vec<u8>), Counter>>,
+}
+
+impl Default for Traffic {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            counters: Mutex::default(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counter {
+    sent: u64,
+    received: u64,
+    /// One slot per second of the window: (second, sent, received)
+    buckets: [(u64, u64, u64); RATE_WINDOW_SECS as usize],
+}
+
+impl Counter {
+    fn record(&mut self, now: u64, sent: u64, received: u64) {
+        self.sent += sent;
+        self.received += received;
+
+        let bucket = &mut self.buckets[(now % RATE_WINDOW_SECS) as usize];
+        if bucket.0 != now {
+            *bucket = (now, 0, 0);
+        }
+        bucket.1 += sent;
+        bucket.2 += received;
+    }
+
+    /// Average (sent, received) bytes per second over the window
+    fn rates(&self, now: u64) -> (f64, f64) {
+        let (sent, received) = self
+            .buckets
+            .iter()
+            .filter(|(second, _, _)| now - second < RATE_WINDOW_SECS)
+            .fold((0, 0), |(s, r), (_, sent, received)| {
+                (s + sent, r + received)
+            });
+        let window = RATE_WINDOW_SECS as f64;
+        (sent as f64 / window, received as f64 / window)
+    }
+}
+
+impl Traffic {
+    pub(crate) fn record_sent(&self, node_id: NodeId, alpn: &[u8], bytes: usize) {
+        self.record(node_id, alpn, bytes as u64, 0);
+    }
+
+    pub(crate) fn record_received(&self, node_id: NodeId, alpn: &[u8], bytes: usize) {
+        self.record(node_id, alpn, 0, bytes as u64);
+    }
+
+    fn record(&self, node_id: NodeId, alpn: &[u8], sent: u64, received: u64) {
+        let now = self.now();
+        let mut counters = self.counters.lock().unwrap();
+        counters
+            .entry((node_id, alpn.to_vec()))
+            .or_default()
+            .record(now, sent, received);
+    }
+
+    fn now(&self) -> u64 {
+        self.started.elapsed().as_secs()
+    }
+
+    /// Snapshot of all counters, with connection types from `endpoint`
+    pub(crate) fn report(&self, endpoint: Option<&Endpoint>) -> StatsReport {
+        let now = self.now();
+        let counters = self.counters.lock().unwrap();
+
+        let mut peers: BTreeMap<NodeId, PeerStats> = BTreeMap::new();
+        for ((node_id, alpn), counter) in counters.iter() {
+            let (send_rate, receive_rate) = counter.rates(now);
+            let peer = peers.entry(*node_id).or_insert_with(|| PeerStats {
+                node_id: *node_id,
+                connection: endpoint
+                    .and_then(|ep| ep.remote_info(*node_id))
+                    .map_or(ConnectionReport::None, |info| info.conn_type.into()),
+                bytes_sent: 0,
+                bytes_received: 0,
+                send_bytes_per_sec: 0.0,
+                receive_bytes_per_sec: 0.0,
+                protocols: Vec::new(),
+                quic: Vec::new(),
+            });
+            peer.bytes_sent += counter.sent;
+            peer.bytes_received += counter.received;
+            peer.send_bytes_per_sec += send_rate;
+            peer.receive_bytes_per_sec += receive_rate;
+            peer.protocols.push(ProtocolStats {
+                alpn: String::from_utf8_lossy(alpn).into_owned(),
+                bytes_sent: counter.sent,
+                bytes_received: counter.received,
+                send_bytes_per_sec: send_rate,
+                receive_bytes_per_sec: receive_rate,
+            });
+        }
+
+        let mut peers: Vec<_> = peers.into_values().collect();
+        for peer in &mut peers {
+            peer.protocols.sort_by(|a, b| a.alpn.cmp(&b.alpn));
+        }
+        StatsReport {
+            window_secs: RATE_WINDOW_SECS,
+            peers,
+            transfers: Vec::new(),
+            addrs: endpoint.and_then(network::observed_addrs),
+        }
+    }
+}
+
+/// Traffic totals and rates for every peer exchanged data with
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsReport {
+    /// Window the `*_per_sec` rates are averaged over
+    pub window_secs: u64,
+    pub peers: Vec<PeerStats>,
+    /// File transfers in progress, see
+    /// [`FileTransfers::transfers`](crate::transfer::FileTransfers::transfers)
+    pub transfers: Vec<TransferInfo>,
+    /// Our own addresses, as bound and as seen by relays; `None` while the
+    /// peer isn't running or hasn't found any yet
+    pub addrs: Option<ObservedAddrs>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerStats {
+    pub node_id: NodeId,
+    /// How the peer is reached right now
+    pub connection: ConnectionReport,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub send_bytes_per_sec: f64,
+    pub receive_bytes_per_sec: f64,
+    pub protocols: Vec<ProtocolStats>,
+    /// Transport state of the open connections, see [`crate::quic`]
+    pub quic: Vec<QuicStats>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProtocolStats {
+    pub alpn: String,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub send_bytes_per_sec: f64,
+    pub receive_bytes_per_sec: f64,
+}
+
+/// Log a report as a short table
+pub fn log_stats(report: &StatsReport) {
+    if let Some(addrs) = &report.addrs {
+        info!(
+            "Seen as {:?} (local {:?}){}{}",
+            addrs.observed,
+            addrs.local,
+            if addrs.translated {
+                ", behind a NAT or VPN"
+            } else {
+                ""
+            },
+            if addrs.symmetric_nat {
+                ", symmetric NAT"
+            } else {
+                ""
+            }
+        );
+    }
+    for transfer in &report.transfers {
+        info!(
+            "Transfer {} ({:?}) of {} with {}: {} of {} B ({:.0} B/s){}",
+            transfer.id,
+            transfer.direction,
+            transfer.name,
+            transfer.node_id.fmt_short(),
+            transfer.bytes,
+            transfer.size,
+            transfer.bytes_per_sec,
+            if transfer.queued { ", queued" } else { "" }
+        );
+    }
+    if report.peers.is_empty() {
+        info!("No protocol traffic yet");
+        return;
+    }
+    for peer in &report.peers {
+        info!(
+            "{} via {:?}: sent {} B ({:.0} B/s), received {} B ({:.0} B/s)",
+            peer.node_id.fmt_short(),
+            peer.connection,
+            peer.bytes_sent,
+            peer.send_bytes_per_sec,
+            peer.bytes_received,
+            peer.receive_bytes_per_sec
+        );
+        for protocol in &peer.protocols {
+            info!(
+                "  {}: sent {} B, received {} B",
+                protocol.alpn, protocol.bytes_sent, protocol.bytes_received
+            );
+        }
+        for quic in &peer.quic {
+            info!(
+                "  {} QUIC: rtt {:.1} ms (±{:.1}), cwnd {} B, lost {} of {} packets, {} datagrams out, {} in",
+                quic.alpn,
+                quic.rtt_ms,
+                quic.rtt_var_ms,
+                quic.cwnd,
+                quic.lost_packets,
+                quic.sent_packets,
+                quic.udp_datagrams_sent,
+                quic.udp_datagrams_received
+            );
+        }
+    }
+}
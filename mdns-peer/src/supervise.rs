@@ -0,0 +1,126 @@
+//! Reporting panics and errors from background tasks
+//!
+//! A panic inside a spawned task (the discovery loop, a connection's accept
+//! loop) only kills that task: the peer keeps running with a piece missing,
+//! and the only trace is a line on stderr that nobody sees on a phone. Tasks
+//! spawned with [`spawn_supervised`] instead report the panic, with its
+//! backtrace, as a [`PeerEvent::Error`].
+//!
+//! The backtrace is captured by a panic hook ([`install_panic_hook`]) on the
+//! panicking thread and picked up by [`catch_panic`] on the same thread as the
+//! unwind reaches it. The hook chains to the previous one, so panics are still
+//! printed as usual.
+
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+use std::task::Poll;
+
+use tokio::task::JoinHandle;
+
+use crate::events::{EventSink, PeerEvent};
+use crate::tasks;
+
+thread_local! {
+    /// The last panic on this thread, as seen by the hook
+    static LAST_PANIC: RefCell<Option<TaskPanic>> = const { RefCell::new(None) };
+}
+
+/// A panic caught in a task
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskPanic {
+    /// The panic message and where it was raised
+    pub message: String,
+    /// `None` if the panic hook wasn't installed
+    pub backtrace: Option<String>,
+}
+
+/// Capture a backtrace for every panic, for [`catch_panic`] to report
+///
+/// Idempotent; [`run_endpoint`](crate::run_endpoint) calls it.
+pub fn install_panic_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let message = info.payload_as_str().unwrap_or("Box<dyn Any>");
+            let message = match info.location() {
+                Some(location) => format!("{} at {}", message, location),
+                None => message.to_string(),
+            };
+            let backtrace = Backtrace::force_capture().to_string();
+            LAST_PANIC.with(|last| {
+                *last.borrow_mut() = Some(TaskPanic {
+                    message,
+                    backtrace: Some(backtrace),
+                })
+            });
+            previous(info);
+        }));
+    });
+}
+
+/// Run `task`, turning a panic inside it into an error
+pub async fn catch_panic<F: Future>(task: F) -> Result<F::Output, TaskPanic> {
+    let mut task = std::pin::pin!(task);
+    std::future::poll_fn(|cx| {
+        match panic::catch_unwind(AssertUnwindSafe(|| task.as_mut().poll(cx))) {
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(payload) => Poll::Ready(Err(take_panic(payload))),
+        }
+    })
+    .await
+}
+
+/// The panic the hook saw on this thread, or what the payload says
+fn take_panic(payload: Box<dyn Any + Send>) -> TaskPanic {
+    LAST_PANIC
+        .with(|last| last.borrow_mut().take())
+        .unwrap_or_else(|| TaskPanic {
+            message: payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "Box<dyn Any>".to_string()),
+            backtrace: None,
+        })
+}
+
+/// Spawn `task`, reporting a panic inside it to `events`
+///
+/// The task is listed by [`tasks::tasks`] while it runs.
+pub fn spawn_supervised(
+    task: &'static str,
+    events: EventSink,
+    future: impl Future<Output = ()> + Send + 'static,
+) -> JoinHandle<()> {
+    tokio::spawn(tasks::track(task, async move {
+        if let Err(panic) = catch_panic(future).await {
+            report_panic(task, panic, &events);
+        }
+    }))
+}
+
+/// Emit a panic in `task` as a [`PeerEvent::Error`]
+pub fn report_panic(task: &str, panic: TaskPanic, events: &EventSink) {
+    events(&PeerEvent::Error {
+        task: task.to_string(),
+        message: format!("panicked: {}", panic.message),
+        backtrace: panic.backtrace,
+    });
+}
+
+/// Emit an error that ended `task` as a [`PeerEvent::Error`]
+pub fn report_error(task: &str, err: &anyhow::Error, events: &EventSink) {
+    let backtrace = err.backtrace();
+    events(&PeerEvent::Error {
+        task: task.to_string(),
+        message: format!("{:#}", err),
+        backtrace: (backtrace.status() == std::backtrace::BacktraceStatus::Captured)
+            .then(|| backtrace.to_string()),
+    });
+}
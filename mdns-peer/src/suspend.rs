@@ -0,0 +1,72 @@
+//! Noticing that the device slept, and timers that cope with it
+//!
+//! `Instant` pauses while an iPhone or a laptop sleeps, so after waking every
+//! deadline measured with it is as far away as when the device went to
+//! sleep: peers that left hours ago would stay listed for their remaining
+//! TTL. [`SuspendDetector`] compares the monotonic clock with the wall clock
+//! to find such gaps, and the peer then expires what went stale and
+//! refreshes its own announcements (see [`run_endpoint`](crate::run_endpoint)).
+//!
+//! A process frozen in the background without the device sleeping shows up
+//! as a late tick instead, and is caught the same way. A wall clock set
+//! forward by more than [`MIN_SUSPEND`] looks like a suspend too, which only
+//! costs an early refresh.
+//!
+//! [`interval`] is `tokio::time::interval` without the burst of catch-up
+//! ticks tokio fires by default after such a gap.
+
+use std::time::{Duration, Instant, SystemTime};
+
+use tokio::time::{Interval, MissedTickBehavior};
+
+/// Smallest unexplained gap treated as a suspend
+pub const MIN_SUSPEND: Duration = Duration::from_secs(10);
+
+/// A timer ticking every `period` that skips the ticks it missed while the
+/// process wasn't running instead of firing them all at once
+pub fn interval(period: Duration) -> Interval {
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    interval
+}
+
+/// Finds the time the process spent asleep between two checks
+#[derive(Debug, Clone, Copy)]
+pub struct SuspendDetector {
+    /// Longest expected gap between checks
+    period: Duration,
+    instant: Instant,
+    wall: SystemTime,
+}
+
+impl SuspendDetector {
+    /// A detector checked about every `period`
+    pub fn new(period: Duration) -> Self {
+        Self {
+            period,
+            instant: Instant::now(),
+            wall: SystemTime::now(),
+        }
+    }
+
+    /// How long the process was suspended since the last check, if at all
+    pub fn check(&mut self) -> Option<Duration> {
+        self.observe(Instant::now(), SystemTime::now())
+    }
+
+    /// [`check`](Self::check) with the clocks read at `instant` and `wall`
+    pub fn observe(&mut self, instant: Instant, wall: SystemTime) -> Option<Duration> {
+        let monotonic = instant.saturating_duration_since(self.instant);
+        // A wall clock set backwards counts as no time passing
+        let wall_elapsed = wall.duration_since(self.wall).unwrap_or_default();
+        self.instant = instant;
+        self.wall = wall;
+
+        // The device slept: the wall clock moved on, the monotonic one didn't
+        let asleep = wall_elapsed.saturating_sub(monotonic);
+        // The process was frozen: both moved on, but no check ran
+        let frozen = monotonic.saturating_sub(self.period);
+        let suspended = asleep.max(frozen);
+        (suspended >= MIN_SUSPEND).then_some(suspended)
+    }
+}
@@ -0,0 +1,131 @@
+//! Which background tasks are running, and what they are doing
+//!
+//! When a peer hangs, the question is which piece stopped making progress:
+//! the discovery loop, an accept loop, a file transfer. Every task spawned
+//! with [`spawn_supervised`](crate::supervise::spawn_supervised), and the
+//! peer's own task, is tracked here under its name until it ends. A
+//! [`TaskReport`] says whether the task is `waiting` to be woken or
+//! `running` inside a poll, and for how long: a task `running` for seconds
+//! is stuck in blocking code, one `waiting` far longer than expected is
+//! stuck on something it awaits.
+//!
+//! [`tasks`] lists them; over FFI `peer_get_tasks()` returns the list as
+//! JSON, and `mdns-peer tasks` asks a daemon's dashboard for it.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+static TASKS: Mutex<BTreeMap<u64, Arc<Tracked>>> = Mutex::new(BTreeMap::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// What a tracked task is doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskState {
+    /// Waiting to be woken, e.g. for a packet or a timer
+    Waiting,
+    /// Inside a poll, running code
+    Running,
+}
+
+/// One running task, as [`tasks`] reports it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskReport {
+    /// Unique for the life of the process, in spawn order
+    pub id: u64,
+    /// What the task is, e.g. `discovery` or `file_send`
+    pub name: String,
+    pub state: TaskState,
+    /// How long it has been in `state`
+    pub state_ms: u64,
+    /// Since it was spawned
+    pub age_ms: u64,
+    /// Times it was polled
+    pub polls: u64,
+}
+
+#[derive(Debug)]
+struct Tracked {
+    name: &'static str,
+    spawned_ms: u64,
+    running: AtomicBool,
+    /// When `running` last changed
+    changed_ms: AtomicU64,
+    polls: AtomicU64,
+}
+
+/// Milliseconds on a clock shared by all tasks
+fn now_ms() -> u64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_millis() as u64
+}
+
+/// Removes the task from the registry when it ends, however it ends
+struct Registration {
+    id: u64,
+    tracked: Arc<Tracked>,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        TASKS.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// `future`, listed under `name` by [`tasks`] from now until it completes
+/// or is dropped
+pub fn track<F: Future>(name: &'static str, future: F) -> impl Future<Output = F::Output> {
+    let now = now_ms();
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let tracked = Arc::new(Tracked {
+        name,
+        spawned_ms: now,
+        running: AtomicBool::new(false),
+        changed_ms: AtomicU64::new(now),
+        polls: AtomicU64::new(0),
+    });
+    TASKS.lock().unwrap().insert(id, tracked.clone());
+    let registration = Registration { id, tracked };
+
+    async move {
+        let mut future = std::pin::pin!(future);
+        std::future::poll_fn(|cx| {
+            let tracked = &registration.tracked;
+            tracked.polls.fetch_add(1, Ordering::Relaxed);
+            tracked.running.store(true, Ordering::Relaxed);
+            tracked.changed_ms.store(now_ms(), Ordering::Relaxed);
+            let poll = future.as_mut().poll(cx);
+            tracked.running.store(false, Ordering::Relaxed);
+            tracked.changed_ms.store(now_ms(), Ordering::Relaxed);
+            poll
+        })
+        .await
+    }
+}
+
+/// Every tracked task that hasn't ended, oldest first
+pub fn tasks() -> Vec<TaskReport> {
+    let now = now_ms();
+    TASKS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(&id, tracked)| TaskReport {
+            id,
+            name: tracked.name.to_string(),
+            state: if tracked.running.load(Ordering::Relaxed) {
+                TaskState::Running
+            } else {
+                TaskState::Waiting
+            },
+            state_ms: now.saturating_sub(tracked.changed_ms.load(Ordering::Relaxed)),
+            age_ms: now.saturating_sub(tracked.spawned_ms),
+            polls: tracked.polls.load(Ordering::Relaxed),
+        })
+        .collect()
+}
@@ -0,0 +1,299 @@
+//! Topic-based publish/subscribe over iroh-gossip
+//!
+//! Every peer subscribed to a topic joins one gossip swarm for it, and a
+//! payload published to the topic spreads through the swarm to all of them,
+//! so multi-device state can be kept in sync without dialing each device.
+//! Topics are plain names; the swarm's [`TopicId`] is the BLAKE3 hash of the
+//! name, so peers agree on it without coordinating.
+//!
+//! A swarm is joined through peers this node already knows: every peer that
+//! is discovered or connects is offered to each subscribed topic. Direct
+//! gossip neighbours coming and going are reported as
+//! [`PeerEvent::NeighborUp`] and [`PeerEvent::NeighborDown`].
+//!
+//! Subscribing works before the peer runs; the swarms are joined once
+//! [`Topics::attach`] hands them the endpoint. Gossip runs on its own ALPN
+//! ([`iroh_gossip::ALPN`]) beside [`Protocols`](crate::protocols::Protocols),
+//! so its connections bypass the accept policy, transfer limits and stats.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use iroh::{Endpoint, NodeId};
+use iroh_gossip::api::{Event, GossipSender};
+use iroh_gossip::net::Gossip;
+use iroh_gossip::proto::TopicId;
+use n0_future::StreamExt;
+use tokio::task::AbortHandle;
+use tracing::{debug, warn};
+
+use crate::events::{discard_events, EventSink, PeerEvent};
+use crate::supervise;
+
+/// Largest payload [`Topics::publish`] accepts, in bytes
+pub const MAX_PUBLISH_SIZE: usize = 16 * 1024;
+
+/// Longest topic name accepted, in bytes
+pub const MAX_TOPIC_LEN: usize = 256;
+
+/// Room for gossip's own framing on top of the payload
+const FRAME_OVERHEAD: usize = 1024;
+
+/// Receives what is published to one topic
+///
+/// Called on runtime threads, so it should return quickly. `node_id` is the
+/// neighbour that delivered the payload, which isn't necessarily the peer
+/// that published it; put the author in the payload if it matters.
+pub trait TopicHandler: Send + Sync + 'static {
+    fn on_message(&self, topic: &str, node_id: NodeId, data: &[u8]);
+}
+
+/// Subscribed topics and the gossip instance serving them
+///
+/// Clones share the same subscriptions.
+#[derive(Clone, Default)]
+pub struct Topics {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    gossip: Mutex<Option<Gossip>>,
+    events: Mutex<Option<EventSink>>,
+    /// Peers discovered or connected, offered to every topic as neighbours
+    known: Mutex<HashSet<NodeId>>,
+    subscriptions: Mutex<HashMap<String, Subscription>>,
+}
+
+struct Subscription {
+    handler: Arc<dyn TopicHandler>,
+    /// Set once the swarm is joined
+    sender: Option<Arc<GossipSender>>,
+    /// Receives from the swarm while the peer runs
+    task: Option<AbortHandle>,
+}
+
+impl std::fmt::Debug for Topics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Topics")
+            .field("subscribed", &self.subscribed())
+            .finish()
+    }
+}
+
+impl Topics {
+    /// Hand what is published to `topic` to `handler`, replacing any
+    /// previous handler for it
+    ///
+    /// Joins the topic's swarm right away if the peer runs, otherwise once
+    /// it starts.
+    pub fn subscribe(&self, topic: &str, handler: Arc<dyn TopicHandler>) -> anyhow::Result<()> {
+        anyhow::ensure!(!topic.is_empty(), "Topic name is empty");
+        anyhow::ensure!(
+            topic.len() <= MAX_TOPIC_LEN,
+            "Topic name is longer than {} bytes",
+            MAX_TOPIC_LEN
+        );
+        let previous = self.inner.subscriptions.lock().unwrap().insert(
+            topic.to_string(),
+            Subscription {
+                handler,
+                sender: None,
+                task: None,
+            },
+        );
+        if let Some(task) = previous.and_then(|s| s.task) {
+            task.abort();
+        }
+        self.join(topic);
+        Ok(())
+    }
+
+    /// Leave `topic`, returning whether it was subscribed
+    pub fn unsubscribe(&self, topic: &str) -> bool {
+        let removed = self.inner.subscriptions.lock().unwrap().remove(topic);
+        let Some(subscription) = removed else {
+            return false;
+        };
+        // Dropping both halves of the subscription leaves the swarm
+        if let Some(task) = subscription.task {
+            task.abort();
+        }
+        true
+    }
+
+    /// Names of the subscribed topics
+    pub fn subscribed(&self) -> Vec<String> {
+        let mut topics: Vec<_> = self
+            .inner
+            .subscriptions
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect();
+        topics.sort();
+        topics
+    }
+
+    /// Send `data` to every peer subscribed to `topic`
+    ///
+    /// The topic must be subscribed and its swarm joined, i.e. the peer must
+    /// be running. Gossip is best effort: peers outside the swarm right now
+    /// never see the payload.
+    pub async fn publish(&self, topic: &str, data: Vec<u8>) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            data.len() <= MAX_PUBLISH_SIZE,
+            "Payload is larger than {} bytes",
+            MAX_PUBLISH_SIZE
+        );
+        let sender = {
+            let subscriptions = self.inner.subscriptions.lock().unwrap();
+            let subscription = subscriptions
+                .get(topic)
+                .ok_or_else(|| anyhow::anyhow!("Not subscribed to {:?}", topic))?;
+            subscription
+                .sender
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("Not joined {:?} yet", topic))?
+        };
+        sender.broadcast(data.into()).await?;
+        Ok(())
+    }
+
+    /// Start gossip on `endpoint` and join every subscribed topic, returning
+    /// the protocol handler to accept [`iroh_gossip::ALPN`] with
+    ///
+    /// Neighbour changes are reported to `events`.
+    pub fn attach(&self, endpoint: &Endpoint, events: EventSink) -> Gossip {
+        let gossip = Gossip::builder()
+            .max_message_size(MAX_PUBLISH_SIZE + FRAME_OVERHEAD)
+            .spawn(endpoint.clone());
+        *self.inner.gossip.lock().unwrap() = Some(gossip.clone());
+        *self.inner.events.lock().unwrap() = Some(events);
+        for topic in self.subscribed() {
+            self.join(&topic);
+        }
+        gossip
+    }
+
+    /// Leave every topic and drop gossip, once the peer stopped
+    ///
+    /// Subscriptions are kept, and joined again by the next [`Topics::attach`].
+    pub fn detach(&self) {
+        self.inner.gossip.lock().unwrap().take();
+        self.inner.events.lock().unwrap().take();
+        self.inner.known.lock().unwrap().clear();
+        for subscription in self.inner.subscriptions.lock().unwrap().values_mut() {
+            subscription.sender = None;
+            if let Some(task) = subscription.task.take() {
+                task.abort();
+            }
+        }
+    }
+
+    /// Offer peers that were just discovered or connected to every topic
+    pub fn peer_event(&self, event: &PeerEvent) {
+        let node_id = match event {
+            PeerEvent::Discovered { node_id, .. } | PeerEvent::Connected { node_id, .. } => {
+                *node_id
+            }
+            PeerEvent::Expired { node_id, .. } => {
+                self.inner.known.lock().unwrap().remove(node_id);
+                return;
+            }
+            _ => return,
+        };
+        if !self.inner.known.lock().unwrap().insert(node_id) {
+            return;
+        }
+        let senders: Vec<_> = {
+            let subscriptions = self.inner.subscriptions.lock().unwrap();
+            subscriptions
+                .values()
+                .filter_map(|s| s.sender.clone())
+                .collect()
+        };
+        for sender in senders {
+            supervise::spawn_supervised("gossip_join", self.events(), async move {
+                if let Err(e) = sender.join_peers(vec![node_id]).await {
+                    debug!("Failed to offer {} to gossip: {:#}", node_id.fmt_short(), e);
+                }
+            });
+        }
+    }
+
+    fn events(&self) -> EventSink {
+        self.inner
+            .events
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap_or_else(discard_events)
+    }
+
+    /// Join the swarm of `topic` in the background if the peer runs,
+    /// replacing any earlier attempt
+    fn join(&self, topic: &str) {
+        let Some(gossip) = self.inner.gossip.lock().unwrap().clone() else {
+            return;
+        };
+        let mut subscriptions = self.inner.subscriptions.lock().unwrap();
+        let Some(subscription) = subscriptions.get_mut(topic) else {
+            return;
+        };
+        let bootstrap: Vec<_> = self.inner.known.lock().unwrap().iter().copied().collect();
+        let handler = subscription.handler.clone();
+        subscription.sender = None;
+        let events = self.events();
+        let topics = self.clone();
+        let name = topic.to_string();
+        let task = supervise::spawn_supervised("gossip", events.clone(), async move {
+            let subscribed = gossip.subscribe(topic_id(&name), bootstrap).await;
+            let (sender, mut receiver) = match subscribed {
+                Ok(topic) => topic.split(),
+                Err(e) => {
+                    warn!("Failed to join topic {:?}: {:#}", name, e);
+                    return;
+                }
+            };
+            let sender = Arc::new(sender);
+            if let Some(s) = topics.inner.subscriptions.lock().unwrap().get_mut(&name) {
+                s.sender = Some(sender.clone());
+            }
+            // Peers learned about while subscribing
+            let known: Vec<_> = topics.inner.known.lock().unwrap().iter().copied().collect();
+            if let Err(e) = sender.join_peers(known).await {
+                debug!("Failed to offer peers to gossip: {:#}", e);
+            }
+            while let Some(event) = receiver.next().await {
+                match event {
+                    Ok(Event::Received(message)) => {
+                        handler.on_message(&name, message.delivered_from, &message.content);
+                    }
+                    Ok(Event::NeighborUp(node_id)) => events(&PeerEvent::NeighborUp {
+                        topic: name.clone(),
+                        node_id,
+                    }),
+                    Ok(Event::NeighborDown(node_id)) => events(&PeerEvent::NeighborDown {
+                        topic: name.clone(),
+                        node_id,
+                    }),
+                    Ok(Event::Lagged) => warn!("Missed gossip on topic {:?}", name),
+                    Err(e) => {
+                        debug!("Left topic {:?}: {:#}", name, e);
+                        break;
+                    }
+                }
+            }
+        });
+        if let Some(previous) = subscription.task.replace(task.abort_handle()) {
+            previous.abort();
+        }
+    }
+}
+
+/// Swarm ID of the topic called `name`
+pub fn topic_id(name: &str) -> TopicId {
+    TopicId::from_bytes(*blake3::hash(name.as_bytes()).as_bytes())
+}
@@ -0,0 +1,1025 @@
+//! Sending files to peers, resuming interrupted transfers
+//!
+//! A file is one stream on [`FILE_ALPN`] carrying [`crate::protocol`]
+//! frames. After the version byte (see [`crate::version`]), the sender
+//! offers the file with a [`FileOffer`], and the receiver answers with
+//! [`FileAnswer::Refused`], or [`FileAnswer::Accepted`] with the offset to
+//! continue from. The sender writes the rest of the file from there as it
+//! is, then a [`FileEnd`] with the file's BLAKE3 hash, and finishes. Once
+//! the receiver has it all it checks the hash, moves the file into place
+//! and answers with a [`FileOutcome`].
+//!
+//! The receiver keeps what arrived of an interrupted transfer next to the
+//! destination, keyed by sender, name and size, so sending the same file
+//! again continues where the last attempt stopped. Both sides hash what the
+//! receiver already has before continuing, so the hash covers the whole
+//! file even across attempts; a copy that doesn't match is dropped, and the
+//! next attempt starts over.
+//!
+//! Incoming files go through a [`ReceivePolicy`] first, which sees the
+//! sender, name and size and picks the directory the file goes to, or
+//! refuses it. That's where a host checks free space and keeps files inside
+//! its sandbox; [`ReceiveOptions`] is the simple policy of one directory and
+//! a set of senders. Files are only ever written inside the chosen
+//! directory.
+//!
+//! Every transfer in either direction gets a [`TransferId`] and is listed by
+//! [`FileTransfers::transfers`] with its rate and ETA until it ends. At most
+//! [`FileTransfers::set_max_active`] run at once; the rest wait for a slot,
+//! inbound ones before answering the sender. Starting and finishing are
+//! reported as [`PeerEvent::TransferStarted`] and
+//! [`PeerEvent::TransferFinished`], and [`FileTransfers::cancel`] stops a
+//! transfer, keeping what the receiver has so it can be resumed.
+//!
+//! Streams go through [`Protocols`] with pulled reads, so neither side holds
+//! more than a window of the file in memory, and transfers share
+//! connections, transfer limits and stats with everything else.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use iroh::NodeId;
+use n0_future::boxed::BoxFuture;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{oneshot, Notify};
+use tracing::{info, warn};
+
+use crate::accept::DECISION_TIMEOUT;
+use crate::connections::Direction;
+use crate::deadline;
+use crate::events::{discard_events, EventSink, PeerEvent};
+use crate::limits::StreamSlots;
+use crate::operations::{self, OperationKind, Registration};
+use crate::protocol::{self, FileAnswer, FileEnd, FileOffer, FileOutcome};
+use crate::protocols::{Protocols, StreamHandler, StreamId};
+use crate::supervise;
+use crate::version;
+
+/// ALPN files are sent on
+pub const FILE_ALPN: &[u8] = b"mdns-peer/file";
+
+/// Largest frame accepted, in bytes; only offers, with the file name, get
+/// anywhere near it
+const MAX_FRAME_LEN: usize = 4096;
+
+/// Size of the pieces files are read and written in
+const CHUNK_SIZE: usize = 256 * 1024;
+
+/// Identifies one transfer in either direction, never 0
+///
+/// Also its [`OperationId`](crate::operations::OperationId), so
+/// [`operations::cancel`] stops it like [`FileTransfers::cancel`].
+pub type TransferId = u64;
+
+/// How far one transfer got, reported as it runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Progress {
+    pub id: TransferId,
+    pub node_id: NodeId,
+    /// File name, without directories
+    pub name: String,
+    /// [`Direction::Outbound`] for files we send
+    pub direction: Direction,
+    /// Bytes the receiver has, including ones from earlier attempts
+    pub bytes: u64,
+    pub size: u64,
+}
+
+/// Called with every [`Progress`] update, on runtime threads
+pub type ProgressSink = Arc<dyn Fn(&Progress) + Send + Sync>;
+
+/// A transfer that hasn't ended yet, see [`FileTransfers::transfers`]
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferInfo {
+    pub id: TransferId,
+    pub node_id: NodeId,
+    pub name: String,
+    pub direction: Direction,
+    /// Waiting for a slot under [`FileTransfers::set_max_active`], or for
+    /// the other side to answer
+    pub queued: bool,
+    /// Bytes the receiver has, including ones from earlier attempts
+    pub bytes: u64,
+    /// 0 until the size is known
+    pub size: u64,
+    /// Average since the transfer started, not counting earlier attempts
+    pub bytes_per_sec: f64,
+    /// Seconds left at the current rate, if anything was sent yet
+    pub eta_secs: Option<f64>,
+}
+
+/// How a transfer ended, see [`PeerEvent::TransferFinished`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferOutcome {
+    Completed,
+    Failed,
+    /// Stopped with [`FileTransfers::cancel`]
+    Cancelled,
+    /// The receiver didn't answer within the timeout given to
+    /// [`FileTransfers::spawn_send_within`]
+    TimedOut,
+}
+
+/// The receiver doesn't take files from us, or not this one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Refused {
+    pub node_id: NodeId,
+    pub name: String,
+}
+
+impl std::fmt::Display for Refused {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} refused {}", self.node_id.fmt_short(), self.name)
+    }
+}
+
+impl std::error::Error for Refused {}
+
+/// A file another peer wants to send us, see [`ReceivePolicy`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncomingFile {
+    pub node_id: NodeId,
+    /// File name, without directories
+    pub name: String,
+    /// Size in bytes, as the sender states it
+    pub size: u64,
+}
+
+/// Decides where incoming files go
+pub trait ReceivePolicy: Send + Sync + 'static {
+    /// Resolve to the directory to write `file` to, or `None` to refuse it
+    ///
+    /// Waited for up to [`DECISION_TIMEOUT`], after which the file is
+    /// refused.
+    fn decide(&self, file: IncomingFile) -> BoxFuture<Option<PathBuf>>;
+}
+
+/// Where incoming files go, and who may send them
+#[derive(Clone)]
+pub struct ReceiveOptions {
+    /// Directory completed files are written to
+    pub dir: PathBuf,
+    /// Whether to take files from this peer
+    pub accept_from: Arc<dyn Fn(NodeId) -> bool + Send + Sync>,
+}
+
+impl std::fmt::Debug for ReceiveOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReceiveOptions")
+            .field("dir", &self.dir)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ReceiveOptions {
+    /// Take files from the peers in `node_ids` only
+    pub fn from_peers(dir: impl Into<PathBuf>, node_ids: HashSet<NodeId>) -> Self {
+        Self {
+            dir: dir.into(),
+            accept_from: Arc::new(move |node_id| node_ids.contains(&node_id)),
+        }
+    }
+}
+
+impl ReceivePolicy for ReceiveOptions {
+    fn decide(&self, file: IncomingFile) -> BoxFuture<Option<PathBuf>> {
+        let dir = (self.accept_from)(file.node_id).then(|| self.dir.clone());
+        Box::pin(async move { dir })
+    }
+}
+
+/// Outbound and inbound file transfers
+///
+/// Clones share the same state. Incoming files are refused until
+/// [`FileTransfers::set_receive`] or [`FileTransfers::set_receive_policy`]
+/// says where they go.
+#[derive(Clone, Default)]
+pub struct FileTransfers {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    protocols: Mutex<Option<Protocols>>,
+    receive: Mutex<Option<Arc<dyn ReceivePolicy>>>,
+    progress: Mutex<Option<ProgressSink>>,
+    /// Streams opened by [`FileTransfers::send_file`], until they open or fail
+    opening: Mutex<HashMap<StreamId, oneshot::Sender<Result<(), String>>>>,
+    /// Transfers that haven't ended, by ID
+    active: Mutex<BTreeMap<TransferId, Active>>,
+    /// Transfers allowed to run at once, `None` for no limit
+    max_active: Mutex<Option<usize>>,
+    slots: Arc<StreamSlots>,
+}
+
+/// Bookkeeping for one transfer until it ends
+struct Active {
+    info: TransferInfo,
+    /// Stream carrying the file, once opened
+    stream: Option<StreamId>,
+    /// When it got a slot, and the bytes the receiver had then
+    started: Option<(Instant, u64)>,
+    cancel: Arc<Notify>,
+    /// Lets [`operations::cancel`] stop it too
+    _operation: Registration,
+    /// Called with the event reporting how it ended
+    finished: Option<OnFinished>,
+}
+
+/// Called once with the [`PeerEvent::TransferFinished`] of a transfer, see
+/// [`FileTransfers::spawn_send_then`]
+pub type OnFinished = Box<dyn FnOnce(&PeerEvent) + Send>;
+
+impl Active {
+    /// Current state, with the rate and ETA as of now
+    fn info(&self) -> TransferInfo {
+        let mut info = self.info.clone();
+        if let Some((started, offset)) = self.started {
+            let elapsed = started.elapsed().as_secs_f64();
+            let sent = info.bytes.saturating_sub(offset);
+            if elapsed > 0.0 && sent > 0 {
+                info.bytes_per_sec = sent as f64 / elapsed;
+                let left = info.size.saturating_sub(info.bytes);
+                info.eta_secs = Some(left as f64 / info.bytes_per_sec);
+            }
+        }
+        info
+    }
+}
+
+impl std::fmt::Debug for FileTransfers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileTransfers")
+            .field("receiving", &self.inner.receive.lock().unwrap().is_some())
+            .field("max_active", &self.inner.max_active.lock().unwrap())
+            .finish()
+    }
+}
+
+impl FileTransfers {
+    /// Take incoming files as `receive` says, or refuse them all with `None`
+    pub fn set_receive(&self, receive: Option<ReceiveOptions>) {
+        self.set_receive_policy(receive.map(|r| Arc::new(r) as Arc<dyn ReceivePolicy>));
+    }
+
+    /// Ask `policy` about every incoming file, or refuse them all with `None`
+    pub fn set_receive_policy(&self, policy: Option<Arc<dyn ReceivePolicy>>) {
+        *self.inner.receive.lock().unwrap() = policy;
+    }
+
+    /// Report transfer progress to `progress`
+    pub fn set_progress(&self, progress: Option<ProgressSink>) {
+        *self.inner.progress.lock().unwrap() = progress;
+    }
+
+    /// Let at most `max` transfers run at once, in both directions together
+    ///
+    /// Applies immediately; transfers already running keep going, and
+    /// raising the limit starts waiting ones.
+    pub fn set_max_active(&self, max: Option<usize>) {
+        *self.inner.max_active.lock().unwrap() = max;
+        self.inner.slots.limit_changed();
+    }
+
+    /// Serve [`FILE_ALPN`] on `protocols` and send files through it
+    ///
+    /// Must be called before the router is spawned.
+    pub fn attach(&self, protocols: &Protocols) {
+        protocols.register_pull(FILE_ALPN, Arc::new(self.clone()));
+        protocols.set_compatibility(version::FILES);
+        *self.inner.protocols.lock().unwrap() = Some(protocols.clone());
+    }
+
+    /// Transfers that haven't ended, oldest first
+    pub fn transfers(&self) -> Vec<TransferInfo> {
+        let active = self.inner.active.lock().unwrap();
+        active.values().map(Active::info).collect()
+    }
+
+    /// Stop transfer `id`, returning false if there's no such transfer
+    ///
+    /// The receiver keeps what it has, so sending the file again resumes it.
+    pub fn cancel(&self, id: TransferId) -> bool {
+        match self.inner.active.lock().unwrap().get(&id) {
+            Some(active) => {
+                active.cancel.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn protocols(&self) -> Option<Protocols> {
+        self.inner.protocols.lock().unwrap().clone()
+    }
+
+    fn events(&self) -> EventSink {
+        self.protocols()
+            .map_or_else(discard_events, |protocols| protocols.events())
+    }
+
+    fn report(&self, progress: Progress) {
+        if let Some(active) = self.inner.active.lock().unwrap().get_mut(&progress.id) {
+            active.info.bytes = progress.bytes;
+        }
+        let sink = self.inner.progress.lock().unwrap().clone();
+        if let Some(sink) = sink {
+            sink(&progress);
+        }
+    }
+
+    /// List a new transfer as queued
+    fn track(&self, node_id: NodeId, name: String, direction: Direction, size: u64) -> TransferId {
+        let id = operations::next_id();
+        let info = TransferInfo {
+            id,
+            node_id,
+            name,
+            direction,
+            queued: true,
+            bytes: 0,
+            size,
+            bytes_per_sec: 0.0,
+            eta_secs: None,
+        };
+        let cancel = Arc::new(Notify::new());
+        let operation = operations::register(id, OperationKind::Transfer, {
+            let cancel = cancel.clone();
+            move || cancel.notify_one()
+        });
+        let active = Active {
+            info,
+            stream: None,
+            started: None,
+            cancel,
+            _operation: operation,
+            finished: None,
+        };
+        self.inner.active.lock().unwrap().insert(id, active);
+        id
+    }
+
+    fn set_stream(&self, id: TransferId, stream: StreamId) {
+        if let Some(active) = self.inner.active.lock().unwrap().get_mut(&id) {
+            active.stream = Some(stream);
+        }
+    }
+
+    /// Mark transfer `id` running from `offset` and report it
+    fn started(&self, id: TransferId, offset: u64, size: u64) {
+        let event = {
+            let mut active = self.inner.active.lock().unwrap();
+            let Some(active) = active.get_mut(&id) else {
+                return;
+            };
+            active.info.queued = false;
+            active.info.bytes = offset;
+            active.info.size = size;
+            active.started = Some((Instant::now(), offset));
+            PeerEvent::TransferStarted {
+                id,
+                node_id: active.info.node_id,
+                name: active.info.name.clone(),
+                direction: active.info.direction,
+                offset,
+                size,
+            }
+        };
+        self.events()(&event);
+    }
+
+    /// Wait for a slot under the concurrency limit
+    async fn acquire_slot(&self) -> crate::limits::SlotGuard {
+        let slots = self.inner.slots.clone();
+        slots
+            .acquire(|| *self.inner.max_active.lock().unwrap())
+            .await
+    }
+
+    /// Run `work` for transfer `id` until it ends or is cancelled, then
+    /// stop listing it and report how it ended
+    ///
+    /// `work` resolves to the file's verified hash.
+    async fn run(
+        &self,
+        id: TransferId,
+        work: impl Future<Output = anyhow::Result<String>>,
+    ) -> anyhow::Result<String> {
+        let cancel = match self.inner.active.lock().unwrap().get(&id) {
+            Some(active) => active.cancel.clone(),
+            None => anyhow::bail!("Transfer {} isn't tracked", id),
+        };
+        let (result, cancelled) = tokio::select! {
+            result = work => (result, false),
+            _ = cancel.notified() => (Err(anyhow::anyhow!("Cancelled")), true),
+        };
+        let Some(active) = self.inner.active.lock().unwrap().remove(&id) else {
+            return result;
+        };
+
+        let timed_out = result.as_ref().is_err_and(deadline::is_timed_out);
+        if let (Some(stream), Some(protocols)) = (active.stream, self.protocols()) {
+            if timed_out {
+                self.inner.opening.lock().unwrap().remove(&stream);
+                protocols.close(stream);
+            } else if cancelled {
+                // The receiver keeps what arrived when the sender finishes early
+                match active.info.direction {
+                    Direction::Outbound => protocols.finish(stream),
+                    Direction::Inbound => protocols.close(stream),
+                };
+            }
+        }
+        let outcome = match (&result, cancelled) {
+            (_, true) => TransferOutcome::Cancelled,
+            (Ok(_), _) => TransferOutcome::Completed,
+            (Err(_), _) if timed_out => TransferOutcome::TimedOut,
+            (Err(_), _) => TransferOutcome::Failed,
+        };
+        let finished = PeerEvent::TransferFinished {
+            id,
+            node_id: active.info.node_id,
+            name: active.info.name,
+            direction: active.info.direction,
+            outcome,
+            bytes: active.info.bytes,
+            hash: result.as_ref().ok().cloned(),
+            error: result.as_ref().err().map(|e| format!("{:#}", e)),
+        };
+        self.events()(&finished);
+        if let Some(on_finished) = active.finished {
+            on_finished(&finished);
+        }
+        result
+    }
+
+    /// Send the file at `path` to `node_id`, resolving to its BLAKE3 hash
+    /// in hex once the receiver has all of it and verified it
+    ///
+    /// Fails with [`Refused`] if the receiver doesn't take the file, or
+    /// otherwise if the peer isn't running, the transfer was cancelled or
+    /// broke off; sending the file again then resumes it.
+    pub async fn send_file(&self, node_id: NodeId, path: &Path) -> anyhow::Result<String> {
+        self.send_file_within(node_id, path, None).await
+    }
+
+    /// Like [`FileTransfers::send_file`], failing with
+    /// [`TimedOut`](crate::deadline::TimedOut) if the receiver hasn't
+    /// answered the offer within `timeout`
+    ///
+    /// The timeout covers connecting and the receiver deciding whether to
+    /// take the file, not sending it once it does.
+    pub async fn send_file_within(
+        &self,
+        node_id: NodeId,
+        path: &Path,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<String> {
+        let name = file_name(path)?;
+        let id = self.track(node_id, name.clone(), Direction::Outbound, 0);
+        let sending = self.send_tracked(id, node_id, name, open_file(path), timeout);
+        self.run(id, sending).await
+    }
+
+    /// Like [`FileTransfers::send_file`], but in the background, returning
+    /// the transfer's ID right away
+    ///
+    /// How it ended is reported as [`PeerEvent::TransferFinished`].
+    pub fn spawn_send(&self, node_id: NodeId, path: PathBuf) -> anyhow::Result<TransferId> {
+        self.spawn_send_within(node_id, path, None)
+    }
+
+    /// Like [`FileTransfers::spawn_send`], with a timeout for the transfer
+    /// to start as in [`FileTransfers::send_file_within`]
+    ///
+    /// A transfer that doesn't start in time ends as
+    /// [`TransferOutcome::TimedOut`].
+    pub fn spawn_send_within(
+        &self,
+        node_id: NodeId,
+        path: PathBuf,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<TransferId> {
+        let name = file_name(&path)?;
+        let source = async move { open_file(&path).await };
+        self.spawn_send_source(node_id, name, source, timeout, None)
+    }
+
+    /// Like [`FileTransfers::spawn_send_within`], also calling `finished`
+    /// with the [`PeerEvent::TransferFinished`] reporting how it ended,
+    /// right after the event sink
+    pub fn spawn_send_then(
+        &self,
+        node_id: NodeId,
+        path: PathBuf,
+        timeout: Option<Duration>,
+        finished: impl FnOnce(&PeerEvent) + Send + 'static,
+    ) -> anyhow::Result<TransferId> {
+        let name = file_name(&path)?;
+        let source = async move { open_file(&path).await };
+        self.spawn_send_source(node_id, name, source, timeout, Some(Box::new(finished)))
+    }
+
+    /// Send `size` bytes from `reader` to `node_id` as a file called `name`,
+    /// like [`FileTransfers::send_file`]
+    ///
+    /// For content that isn't a file on disk, such as a photo streamed out
+    /// of the library. `reader` is read on a blocking thread, so it may
+    /// block, and must yield exactly `size` bytes. To resume, the receiver's
+    /// part is read again to hash it, so only the network transfer is saved.
+    pub async fn send_reader(
+        &self,
+        node_id: NodeId,
+        name: String,
+        size: u64,
+        reader: Box<dyn Read + Send>,
+    ) -> anyhow::Result<String> {
+        anyhow::ensure!(
+            safe_file_name(&name).is_some(),
+            "Invalid file name {:?}",
+            name
+        );
+        let id = self.track(node_id, name.clone(), Direction::Outbound, size);
+        let source = async move { Ok((Source::Reader(Some(reader)), size)) };
+        self.run(id, self.send_tracked(id, node_id, name, source, None))
+            .await
+    }
+
+    /// Like [`FileTransfers::send_reader`], but in the background, as
+    /// [`FileTransfers::spawn_send`] does
+    pub fn spawn_send_reader(
+        &self,
+        node_id: NodeId,
+        name: String,
+        size: u64,
+        reader: Box<dyn Read + Send>,
+    ) -> anyhow::Result<TransferId> {
+        self.spawn_send_reader_within(node_id, name, size, reader, None)
+    }
+
+    /// Like [`FileTransfers::spawn_send_reader`], with a timeout for the
+    /// transfer to start as in [`FileTransfers::send_file_within`]
+    pub fn spawn_send_reader_within(
+        &self,
+        node_id: NodeId,
+        name: String,
+        size: u64,
+        reader: Box<dyn Read + Send>,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<TransferId> {
+        anyhow::ensure!(
+            safe_file_name(&name).is_some(),
+            "Invalid file name {:?}",
+            name
+        );
+        let source = async move { Ok((Source::Reader(Some(reader)), size)) };
+        self.spawn_send_source(node_id, name, source, timeout, None)
+    }
+
+    /// Like [`FileTransfers::spawn_send_reader_within`], calling `finished`
+    /// as [`FileTransfers::spawn_send_then`] does
+    pub fn spawn_send_reader_then(
+        &self,
+        node_id: NodeId,
+        name: String,
+        size: u64,
+        reader: Box<dyn Read + Send>,
+        timeout: Option<Duration>,
+        finished: impl FnOnce(&PeerEvent) + Send + 'static,
+    ) -> anyhow::Result<TransferId> {
+        anyhow::ensure!(
+            safe_file_name(&name).is_some(),
+            "Invalid file name {:?}",
+            name
+        );
+        let source = async move { Ok((Source::Reader(Some(reader)), size)) };
+        self.spawn_send_source(node_id, name, source, timeout, Some(Box::new(finished)))
+    }
+
+    fn spawn_send_source(
+        &self,
+        node_id: NodeId,
+        name: String,
+        source: impl Future<Output = anyhow::Result<(Source, u64)>> + Send + 'static,
+        timeout: Option<Duration>,
+        finished: Option<OnFinished>,
+    ) -> anyhow::Result<TransferId> {
+        anyhow::ensure!(self.protocols().is_some(), "The peer isn't running");
+        let id = self.track(node_id, name.clone(), Direction::Outbound, 0);
+        if let Some(active) = self.inner.active.lock().unwrap().get_mut(&id) {
+            active.finished = finished;
+        }
+        let transfers = self.clone();
+        supervise::spawn_supervised("file_send", self.events(), async move {
+            // Reported through TransferFinished
+            let _ = transfers
+                .run(
+                    id,
+                    transfers.send_tracked(id, node_id, name, source, timeout),
+                )
+                .await;
+        });
+        Ok(id)
+    }
+
+    async fn send_tracked(
+        &self,
+        id: TransferId,
+        node_id: NodeId,
+        name: String,
+        source: impl Future<Output = anyhow::Result<(Source, u64)>>,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<String> {
+        let protocols = self.protocols();
+        let Some((protocols, endpoint)) = protocols.and_then(|p| Some((p.clone(), p.endpoint()?)))
+        else {
+            anyhow::bail!("The peer isn't running");
+        };
+        let (mut file, size) = source.await?;
+        let _slot = self.acquire_slot().await;
+
+        let (opened, open_result) = oneshot::channel();
+        let stream = {
+            // Held until the stream is listed, so on_open always finds it
+            let mut opening = self.inner.opening.lock().unwrap();
+            let stream = protocols.open_stream(&endpoint, node_id, FILE_ALPN)?;
+            opening.insert(stream, opened);
+            stream
+        };
+        self.set_stream(id, stream);
+        let answer = deadline::within(timeout, "start the transfer", async {
+            open_result
+                .await
+                .map_err(|_| anyhow::anyhow!("Stream dropped"))?
+                .map_err(anyhow::Error::msg)?;
+
+            let offer = FileOffer {
+                name: name.clone(),
+                size,
+            };
+            protocols.write(stream, protocol::encode(&offer));
+            read_frame::<FileAnswer>(&protocols, stream).await
+        })
+        .await?;
+        let offset = match answer {
+            FileAnswer::Accepted { offset } => offset,
+            FileAnswer::Refused => return Err(Refused { node_id, name }.into()),
+        };
+        anyhow::ensure!(offset <= size, "Receiver is ahead of the file");
+        // Leaves the file at the offset
+        let mut hasher = blake3::Hasher::new();
+        hash_prefix(&mut file, offset, &mut hasher).await?;
+        self.started(id, offset, size);
+
+        let mut sent = offset;
+        let mut buf = vec![0; CHUNK_SIZE];
+        let report = |bytes| {
+            self.report(Progress {
+                id,
+                node_id,
+                name: name.clone(),
+                direction: Direction::Outbound,
+                bytes,
+                size,
+            })
+        };
+        report(sent);
+        while sent < size {
+            let want = (size - sent).min(CHUNK_SIZE as u64) as usize;
+            let len = file.read(&mut buf[..want]).await?;
+            anyhow::ensure!(len > 0, "{} got shorter while sending", name);
+            hasher.update(&buf[..len]);
+            anyhow::ensure!(
+                protocols.write_buffered(stream, buf[..len].to_vec()).await,
+                "Stream to {} closed",
+                node_id.fmt_short()
+            );
+            sent += len as u64;
+            report(sent);
+        }
+        let hash = hasher.finalize();
+        let end = FileEnd {
+            hash: *hash.as_bytes(),
+        };
+        protocols.write(stream, protocol::encode(&end));
+        protocols.finish(stream);
+
+        match read_frame(&protocols, stream).await? {
+            FileOutcome::Complete => {}
+            FileOutcome::Corrupt => anyhow::bail!(
+                "{} got a corrupted copy and dropped it, sending again starts over",
+                node_id.fmt_short()
+            ),
+            FileOutcome::Incomplete => {
+                anyhow::bail!("{} didn't get all of the file", node_id.fmt_short())
+            }
+        }
+        info!("Sent {} to {}, BLAKE3 {}", name, node_id.fmt_short(), hash);
+        Ok(hash.to_hex().to_string())
+    }
+
+    /// Take one incoming file, answering the sender as described in the
+    /// module docs
+    async fn receive(&self, protocols: &Protocols, stream: StreamId, node_id: NodeId) {
+        match self.receive_file(protocols, stream, node_id).await {
+            Ok((name, hash)) => info!(
+                "Received {} from {}, BLAKE3 {}",
+                name,
+                node_id.fmt_short(),
+                hash
+            ),
+            Err(e) => warn!("Receiving a file from {}: {:#}", node_id.fmt_short(), e),
+        }
+        protocols.finish(stream);
+    }
+
+    async fn receive_file(
+        &self,
+        protocols: &Protocols,
+        stream: StreamId,
+        node_id: NodeId,
+    ) -> anyhow::Result<(String, String)> {
+        let offer: FileOffer = read_frame(protocols, stream).await?;
+
+        let Some(name) = safe_file_name(&offer.name) else {
+            protocols.write(stream, protocol::encode(&FileAnswer::Refused));
+            anyhow::bail!("Refused a file named {:?}", offer.name);
+        };
+        let policy = self.inner.receive.lock().unwrap().clone();
+        let file = IncomingFile {
+            node_id,
+            name: name.clone(),
+            size: offer.size,
+        };
+        let dir = match policy {
+            Some(policy) => tokio::time::timeout(DECISION_TIMEOUT, policy.decide(file))
+                .await
+                .unwrap_or(None),
+            None => None,
+        };
+        let Some(dir) = dir else {
+            protocols.write(stream, protocol::encode(&FileAnswer::Refused));
+            anyhow::bail!("Refused {}", name);
+        };
+
+        let id = self.track(node_id, name.clone(), Direction::Inbound, offer.size);
+        self.set_stream(id, stream);
+        let hash = self
+            .run(
+                id,
+                self.receive_tracked(id, protocols, stream, node_id, &dir, &name, offer.size),
+            )
+            .await?;
+        Ok((name, hash))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn receive_tracked(
+        &self,
+        id: TransferId,
+        protocols: &Protocols,
+        stream: StreamId,
+        node_id: NodeId,
+        dir: &Path,
+        name: &str,
+        size: u64,
+    ) -> anyhow::Result<String> {
+        // The sender waits for the answer meanwhile
+        let _slot = self.acquire_slot().await;
+        tokio::fs::create_dir_all(dir).await?;
+        let partial = dir.join(format!(".{}.{}.{}.part", name, node_id.fmt_short(), size));
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&partial)
+            .await?;
+        let mut received = file.metadata().await?.len().min(size);
+        file.set_len(received).await?;
+        let answer = FileAnswer::Accepted { offset: received };
+        protocols.write(stream, protocol::encode(&answer));
+        self.started(id, received, size);
+
+        let report = |bytes| {
+            self.report(Progress {
+                id,
+                node_id,
+                name: name.to_string(),
+                direction: Direction::Inbound,
+                bytes,
+                size,
+            })
+        };
+        let mut hasher = blake3::Hasher::new();
+        let mut kept = Source::File(tokio::fs::File::open(&partial).await?);
+        hash_prefix(&mut kept, received, &mut hasher).await?;
+        report(received);
+        while received < size {
+            let want = (size - received).min(CHUNK_SIZE as u64) as usize;
+            let Some(data) = protocols.read(stream, want).await? else {
+                break;
+            };
+            file.write_all(&data).await?;
+            hasher.update(&data);
+            received += data.len() as u64;
+            report(received);
+        }
+        file.flush().await?;
+
+        let end = if received == size {
+            read_frame::<FileEnd>(protocols, stream).await.ok()
+        } else {
+            None
+        };
+        let Some(end) = end else {
+            protocols.write(stream, protocol::encode(&FileOutcome::Incomplete));
+            anyhow::bail!(
+                "{} ended after {} of {} bytes, keeping it to resume",
+                name,
+                received,
+                size
+            );
+        };
+        anyhow::ensure!(
+            protocols.read(stream, 1).await?.is_none(),
+            "Sender sent more than the file and its hash"
+        );
+        let computed = hasher.finalize();
+        if computed != blake3::Hash::from_bytes(end.hash) {
+            drop(file);
+            tokio::fs::remove_file(&partial).await?;
+            protocols.write(stream, protocol::encode(&FileOutcome::Corrupt));
+            anyhow::bail!("{} doesn't match its hash, dropped it", name);
+        }
+
+        let destination = free_path(dir, name);
+        tokio::fs::rename(&partial, &destination).await?;
+        protocols.write(stream, protocol::encode(&FileOutcome::Complete));
+        Ok(computed.to_hex().to_string())
+    }
+}
+
+/// Where the bytes of an outgoing file come from
+enum Source {
+    File(tokio::fs::File),
+    /// Read on a blocking thread; `None` while a read is in flight
+    Reader(Option<Box<dyn Read + Send>>),
+}
+
+impl Source {
+    /// Up to `buf.len()` bytes, 0 at the end
+    async fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let slot = match self {
+            Source::File(file) => return file.read(buf).await,
+            Source::Reader(slot) => slot,
+        };
+        let Some(mut reader) = slot.take() else {
+            return Err(std::io::Error::other("An earlier read was abandoned"));
+        };
+        let len = buf.len();
+        let (reader, chunk) = tokio::task::spawn_blocking(move || {
+            let mut chunk = vec![0; len];
+            let read = reader.read(&mut chunk).map(|read| {
+                chunk.truncate(read);
+                chunk
+            });
+            (reader, read)
+        })
+        .await
+        .map_err(std::io::Error::other)?;
+        *slot = Some(reader);
+        let chunk = chunk?;
+        buf[..chunk.len()].copy_from_slice(&chunk);
+        Ok(chunk.len())
+    }
+}
+
+/// The file at `path` and its size
+async fn open_file(path: &Path) -> anyhow::Result<(Source, u64)> {
+    let file = tokio::fs::File::open(path).await?;
+    let size = file.metadata().await?.len();
+    Ok((Source::File(file), size))
+}
+
+/// Feed the first `len` bytes of `file` to `hasher`, leaving it positioned
+/// after them
+async fn hash_prefix(
+    file: &mut Source,
+    len: u64,
+    hasher: &mut blake3::Hasher,
+) -> anyhow::Result<()> {
+    let mut buf = vec![0; CHUNK_SIZE];
+    let mut hashed = 0;
+    while hashed < len {
+        let want = (len - hashed).min(CHUNK_SIZE as u64) as usize;
+        let read = file.read(&mut buf[..want]).await?;
+        anyhow::ensure!(read > 0, "File is shorter than {} bytes", len);
+        hasher.update(&buf[..read]);
+        hashed += read as u64;
+    }
+    Ok(())
+}
+
+/// File name of `path`, as sent in the header
+fn file_name(path: &Path) -> anyhow::Result<String> {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow::anyhow!("No file name in {}", path.display()))?;
+    Ok(name.to_string())
+}
+
+/// `name` without directories, or `None` if nothing usable is left
+fn safe_file_name(name: &str) -> Option<String> {
+    let name = Path::new(name).file_name()?.to_str()?;
+    let name = name.trim_start_matches('.');
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// `dir/name`, or `dir/name (n)` with the first `n` not taken yet
+fn free_path(dir: &Path, name: &str) -> PathBuf {
+    let path = dir.join(name);
+    if !path.exists() {
+        return path;
+    }
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+        _ => (name, String::new()),
+    };
+    (1..)
+        .map(|n| dir.join(format!("{} ({}){}", stem, n, extension)))
+        .find(|path| !path.exists())
+        .expect("some suffix is free")
+}
+
+/// One frame from `stream`, see [`crate::protocol`]
+async fn read_frame<T: DeserializeOwned>(
+    protocols: &Protocols,
+    stream: StreamId,
+) -> anyhow::Result<T> {
+    let prefix = read_exact::<{ protocol::PREFIX_LEN }>(protocols, stream).await?;
+    let len = protocol::body_len(prefix);
+    anyhow::ensure!(len <= MAX_FRAME_LEN, "Frame is too large");
+    protocol::decode_body(&read_to_vec(protocols, stream, len).await?)
+}
+
+/// Exactly `N` bytes from `stream`, failing if it ends first
+async fn read_exact<const N: usize>(
+    protocols: &Protocols,
+    stream: StreamId,
+) -> anyhow::Result<[u8; N]> {
+    let bytes = read_to_vec(protocols, stream, N).await?;
+    Ok(bytes
+        .try_into()
+        .expect("read_to_vec reads exactly len bytes"))
+}
+
+/// Exactly `len` bytes from `stream`, failing if it ends first
+async fn read_to_vec(
+    protocols: &Protocols,
+    stream: StreamId,
+    len: usize,
+) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(len);
+    while bytes.len() < len {
+        match protocols.read(stream, len - bytes.len()).await? {
+            Some(data) => bytes.extend_from_slice(&data),
+            None => anyhow::bail!("Stream ended early"),
+        }
+    }
+    Ok(bytes)
+}
+
+impl StreamHandler for FileTransfers {
+    fn on_open(&self, stream: StreamId, node_id: NodeId) {
+        // Outbound streams are listed before they open
+        if let Some(opened) = self.inner.opening.lock().unwrap().remove(&stream) {
+            let _ = opened.send(Ok(()));
+            return;
+        }
+        let Some(protocols) = self.protocols() else {
+            return;
+        };
+        let transfers = self.clone();
+        supervise::spawn_supervised("file_receive", protocols.events(), async move {
+            transfers.receive(&protocols, stream, node_id).await;
+        });
+    }
+
+    fn on_data(&self, _stream: StreamId, _data: &[u8]) {
+        // Data is pulled with Protocols::read
+    }
+
+    fn on_close(&self, stream: StreamId, error: Option<&str>) {
+        if let Some(opened) = self.inner.opening.lock().unwrap().remove(&stream) {
+            let error = error.unwrap_or("Closed before opening");
+            let _ = opened.send(Err(error.to_string()));
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! Protocol versions of the built-in ALPNs, and turning away peers that
+//! speak another one
+//!
+//! Every stream on a versioned ALPN starts with one version byte, written
+//! and checked by [`Protocols`] before the protocol's own frames. A peer
+//! sending a version this one doesn't read has its connection closed with
+//! [`VERSION_ERROR_CODE`] and a reason naming both versions, and both sides
+//! report a [`PeerEvent::VersionMismatch`]. During a rollout the app can
+//! then tell the user which device to update, rather than showing messages
+//! that never arrive.
+//!
+//! [`COMPATIBILITY`] lists what each built-in protocol speaks and reads.
+//! Hosts can version their own protocols the same way with
+//! [`Protocols::set_compatibility`].
+//!
+//! [`Protocols`]: crate::protocols::Protocols
+//! [`Protocols::set_compatibility`]: crate::protocols::Protocols::set_compatibility
+//! [`PeerEvent::VersionMismatch`]: crate::PeerEvent::VersionMismatch
+
+use crate::messages::MESSAGE_ALPN;
+use crate::transfer::FILE_ALPN;
+
+/// Application error code of connections closed over a version mismatch,
+/// HTTP's "Upgrade Required"
+pub const VERSION_ERROR_CODE: u32 = 426;
+
+/// What one versioned protocol speaks and reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compatibility {
+    pub alpn: &'static [u8],
+    /// Version written at the start of every stream
+    pub version: u8,
+    /// Oldest version still read from peers
+    pub oldest: u8,
+}
+
+/// [`crate::messages`]
+pub const MESSAGES: Compatibility = Compatibility {
+    alpn: MESSAGE_ALPN,
+    version: 1,
+    oldest: 1,
+};
+
+/// [`crate::transfer`]
+pub const FILES: Compatibility = Compatibility {
+    alpn: FILE_ALPN,
+    version: 1,
+    oldest: 1,
+};
+
+/// Every built-in versioned protocol
+pub const COMPATIBILITY: &[Compatibility] = &[MESSAGES, FILES];
+
+impl Compatibility {
+    /// Whether streams opening with `version` are read
+    pub fn accepts(&self, version: u8) -> bool {
+        (self.oldest..=self.version).contains(&version)
+    }
+
+    /// Close reason for a stream that opened with `version`, written for
+    /// both ends of the connection
+    pub fn mismatch(&self, version: u8) -> String {
+        let outdated = if version < self.oldest {
+            "sender"
+        } else {
+            "receiver"
+        };
+        format!(
+            "{} version {} is not supported by the receiver, which reads {}; the {} needs an update",
+            String::from_utf8_lossy(self.alpn),
+            version,
+            self.readable(),
+            outdated
+        )
+    }
+
+    fn readable(&self) -> String {
+        if self.oldest == self.version {
+            format!("version {}", self.version)
+        } else {
+            format!("versions {} to {}", self.oldest, self.version)
+        }
+    }
+}
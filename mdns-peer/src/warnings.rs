@@ -0,0 +1,123 @@
+//! Warnings about conditions that last
+//!
+//! Some problems are checked over and over: no peer discovered yet,
+//! discovery failing, the recording's disk being full. Warning on every
+//! check buries everything else in the log, so a [`Condition`] reports only
+//! when the problem starts, again every [`DEFAULT_REPEAT`] while it lasts,
+//! and once when it clears. Each report is a [`PeerEvent::Warning`], which
+//! the peer logs with [`log`] and hands to the app like a status change.
+
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+use crate::events::PeerEvent;
+
+/// How often a condition that keeps holding is reported again
+pub const DEFAULT_REPEAT: Duration = Duration::from_secs(5 * 60);
+
+/// A problem that can hold for a while, see the [module docs](self)
+#[derive(Debug, Clone)]
+pub struct Condition {
+    name: &'static str,
+    repeat: Duration,
+    /// When it started holding, `None` while it doesn't
+    since: Option<Instant>,
+    last_reported: Option<Instant>,
+    /// Times it was seen since it started
+    occurrences: u64,
+}
+
+impl Condition {
+    /// A condition reported as `name` in [`PeerEvent::Warning`], e.g.
+    /// `no_peers`
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            repeat: DEFAULT_REPEAT,
+            since: None,
+            last_reported: None,
+            occurrences: 0,
+        }
+    }
+
+    /// Report a condition that keeps holding every `repeat` instead of
+    /// every [`DEFAULT_REPEAT`]
+    pub fn with_repeat(mut self, repeat: Duration) -> Self {
+        self.repeat = repeat;
+        self
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.since.is_some()
+    }
+
+    /// The condition was seen at `now`, described by `message`
+    ///
+    /// Returns the warning to report if it just started or is due to be
+    /// reported again.
+    pub fn hold(&mut self, now: Instant, message: impl Into<String>) -> Option<PeerEvent> {
+        let since = *self.since.get_or_insert(now);
+        self.occurrences += 1;
+        let due = self
+            .last_reported
+            .is_none_or(|reported| now.saturating_duration_since(reported) >= self.repeat);
+        if !due {
+            return None;
+        }
+        self.last_reported = Some(now);
+        Some(PeerEvent::Warning {
+            condition: self.name.to_string(),
+            active: true,
+            message: Some(message.into()),
+            duration_ms: now.saturating_duration_since(since).as_millis() as u64,
+            occurrences: self.occurrences,
+        })
+    }
+
+    /// The condition no longer holds at `now`
+    ///
+    /// Returns the warning to report if it held until now.
+    pub fn clear(&mut self, now: Instant) -> Option<PeerEvent> {
+        let since = self.since.take()?;
+        self.last_reported = None;
+        Some(PeerEvent::Warning {
+            condition: self.name.to_string(),
+            active: false,
+            message: None,
+            duration_ms: now.saturating_duration_since(since).as_millis() as u64,
+            occurrences: std::mem::take(&mut self.occurrences),
+        })
+    }
+}
+
+/// Log a [`PeerEvent::Warning`]; other events are ignored
+pub fn log(event: &PeerEvent) {
+    let PeerEvent::Warning {
+        condition,
+        active,
+        message,
+        duration_ms,
+        occurrences,
+    } = event
+    else {
+        return;
+    };
+    let message = message.as_deref().unwrap_or(condition);
+    if !active {
+        info!(
+            "Resolved after {:?}: {}",
+            Duration::from_millis(*duration_ms),
+            condition
+        );
+    } else if *occurrences <= 1 {
+        warn!("{}", message);
+    } else {
+        warn!(
+            "{} (for {:?}, {} times)",
+            message,
+            Duration::from_millis(*duration_ms),
+            occurrences
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! Inbound connection events and the accept policy
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use iroh::NodeId;
+use mdns_peer::accept::{AcceptLimits, AcceptPolicy, InboundRequest};
+use mdns_peer::protocols::{StreamHandler, StreamId};
+use mdns_peer::PeerEvent;
+use n0_future::boxed::BoxFuture;
+use tokio::sync::{mpsc, oneshot};
+
+use common::Side;
+
+mod common;
+
+const ALPN: &[u8] = b"mdns-peer/test-accept/0";
+
+/// Reports whether each outbound stream opened or failed
+struct Outcomes(mpsc::UnboundedSender<Result<(), String>>);
+
+impl StreamHandler for Outcomes {
+    fn on_open(&self, _stream: StreamId, _node_id: NodeId) {
+        let _ = self.0.send(Ok(()));
+    }
+
+    fn on_data(&self, _stream: StreamId, _data: &[u8]) {}
+
+    fn on_close(&self, _stream: StreamId, error: Option<&str>) {
+        if let Some(error) = error {
+            let _ = self.0.send(Err(error.to_string()));
+        }
+    }
+}
+
+/// Hands each request to the test, which answers like a user would
+struct Interactive(mpsc::UnboundedSender<(InboundRequest, oneshot::Sender<bool>)>);
+
+impl AcceptPolicy for Interactive {
+    fn decide(&self, request: InboundRequest) -> BoxFuture<bool> {
+        let (tx, rx) = oneshot::channel();
+        let _ = self.0.send((request, tx));
+        Box::pin(async move { rx.await.unwrap_or(false) })
+    }
+}
+
+/// A side whose handler reports whether each outbound stream opened
+async fn bind(identifier: &str) -> anyhow::Result<Side<Result<(), String>>> {
+    Side::bind(identifier, |protocols, tx| {
+        protocols.register(ALPN, Arc::new(Outcomes(tx)));
+    })
+    .await
+}
+
+/// Open a stream from `client` to `server`, returning whether it opened
+async fn connect<T>(
+    client: &mut Side<Result<(), String>>,
+    server: &Side<T>,
+) -> anyhow::Result<Result<(), String>> {
+    client
+        .protocols
+        .open_stream(&client.endpoint, server.addr().await, ALPN)?;
+    client.next_received().await
+}
+
+/// `inbound_connection` events, rather than connection lifecycle events
+fn inbound(event: &PeerEvent) -> bool {
+    matches!(event, PeerEvent::InboundConnection { .. })
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn inbound_connections_are_reported_with_trust() -> anyhow::Result<()> {
+    let mut server = bind("accept-server").await?;
+    let mut trusted = bind("accept-trusted").await?;
+    let mut stranger = bind("accept-stranger").await?;
+    server
+        .protocols
+        .set_trusted([trusted.endpoint.node_id()].into());
+
+    for (client, is_trusted) in [(&mut trusted, true), (&mut stranger, false)] {
+        assert_eq!(connect(client, &server).await?, Ok(()));
+        assert_eq!(
+            server.next_event(inbound).await?,
+            PeerEvent::InboundConnection {
+                node_id: client.endpoint.node_id(),
+                alpn: "mdns-peer/test-accept/0".to_string(),
+                trusted: is_trusted,
+                accepted: true,
+            }
+        );
+    }
+
+    trusted.shutdown().await?;
+    stranger.shutdown().await?;
+    server.shutdown().await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn policy_decides_interactively() -> anyhow::Result<()> {
+    let mut server = bind("accept-policy-server").await?;
+    let mut alice = bind("accept-policy-alice").await?;
+    let mut mallory = bind("accept-policy-mallory").await?;
+    let (tx, mut requests) = mpsc::unbounded_channel();
+    server
+        .protocols
+        .set_accept_policy(Some(Arc::new(Interactive(tx))));
+
+    // The user takes a moment, then approves
+    let approve = tokio::spawn(async move {
+        let (request, answer) = requests.recv().await.expect("policy asked");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let _ = answer.send(true);
+        let denied = requests.recv().await.expect("policy asked");
+        let _ = denied.1.send(false);
+        (request, denied.0)
+    });
+
+    assert_eq!(connect(&mut alice, &server).await?, Ok(()));
+    let event = server.next_event(inbound).await?;
+    assert!(matches!(
+        event,
+        PeerEvent::InboundConnection { node_id, accepted: true, .. } if node_id == alice.endpoint.node_id()
+    ));
+
+    // Opening a stream is local, the denial arrives as the connection closing
+    assert_eq!(connect(&mut mallory, &server).await?, Ok(()));
+    let refused = mallory.next_received().await?;
+    assert!(refused.is_err(), "mallory's stream stayed open");
+    let event = server.next_event(inbound).await?;
+    assert!(matches!(
+        event,
+        PeerEvent::InboundConnection { node_id, accepted: false, .. } if node_id == mallory.endpoint.node_id()
+    ));
+
+    let (asked_alice, asked_mallory) = approve.await?;
+    assert_eq!(asked_alice.node_id, alice.endpoint.node_id());
+    assert_eq!(asked_alice.alpn, ALPN);
+    assert!(!asked_alice.trusted);
+    assert_eq!(asked_mallory.node_id, mallory.endpoint.node_id());
+
+    alice.shutdown().await?;
+    mallory.shutdown().await?;
+    server.shutdown().await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn connections_over_the_limit_are_refused() -> anyhow::Result<()> {
+    let mut server = bind("accept-limit-server").await?;
+    let mut alice = bind("accept-limit-alice").await?;
+    let mut bob = bind("accept-limit-bob").await?;
+    server.protocols.set_accept_limits(AcceptLimits {
+        max_inbound_connections: Some(1),
+        max_streams_per_peer: None,
+    });
+
+    assert_eq!(connect(&mut alice, &server).await?, Ok(()));
+    assert!(matches!(
+        server.next_event(inbound).await?,
+        PeerEvent::InboundConnection { accepted: true, .. }
+    ));
+
+    // Alice's connection stays open, so there is no room for Bob's
+    assert_eq!(connect(&mut bob, &server).await?, Ok(()));
+    let refused = bob.next_received().await?;
+    assert!(refused.is_err(), "bob's stream stayed open");
+    let event = server.next_event(inbound).await?;
+    assert!(matches!(
+        event,
+        PeerEvent::InboundConnection { node_id, accepted: false, .. } if node_id == bob.endpoint.node_id()
+    ));
+
+    alice.shutdown().await?;
+    bob.shutdown().await?;
+    server.shutdown().await
+}
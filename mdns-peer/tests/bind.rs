@@ -0,0 +1,70 @@
+//! Pinned bind addresses and ports
+
+use std::net::{SocketAddrV4, UdpSocket};
+
+use iroh::{Endpoint, RelayMode};
+use mdns_peer::bind::BindAddrs;
+
+async fn bind(addrs: BindAddrs) -> anyhow::Result<Endpoint> {
+    let builder = Endpoint::builder().relay_mode(RelayMode::Disabled);
+    let endpoint = addrs.apply(builder).bind().await?;
+    if let Err(e) = addrs.check(&endpoint) {
+        endpoint.close().await;
+        return Err(e);
+    }
+    Ok(endpoint)
+}
+
+#[test]
+fn parses_ports_and_addresses() -> anyhow::Result<()> {
+    assert_eq!(BindAddrs::parse("7777")?, BindAddrs::port(7777));
+    let addrs = BindAddrs::parse("192.168.1.5:7777, [::1]:7778")?;
+    assert_eq!(addrs.v4, Some("192.168.1.5:7777".parse()?));
+    assert_eq!(addrs.v6, Some("[::1]:7778".parse()?));
+    let addrs = BindAddrs::parse("10.0.0.2")?;
+    assert_eq!(addrs.v4, Some("10.0.0.2:0".parse()?));
+    assert_eq!(addrs.v6, None);
+
+    assert!(BindAddrs::parse("7777,10.0.0.2:1").is_err());
+    assert!(BindAddrs::parse("nowhere:1").is_err());
+    assert!(BindAddrs::parse("").is_err());
+    assert!(BindAddrs::default().is_default());
+    Ok(())
+}
+
+#[test]
+fn taken_ports_are_found_without_binding_an_endpoint() -> anyhow::Result<()> {
+    let taken = UdpSocket::bind("127.0.0.1:0")?;
+    let addrs = BindAddrs {
+        v4: Some(SocketAddrV4::new(
+            [127, 0, 0, 1].into(),
+            taken.local_addr()?.port(),
+        )),
+        v6: None,
+    };
+    assert!(addrs.check_free().is_err());
+    drop(taken);
+    addrs.check_free()?;
+    // Ephemeral ports are always free
+    BindAddrs::parse("127.0.0.1")?.check_free()
+}
+
+#[tokio::test]
+async fn a_taken_port_fails_instead_of_moving() -> anyhow::Result<()> {
+    // A port that was free a moment ago
+    let port = UdpSocket::bind("127.0.0.1:0")?.local_addr()?.port();
+    let addrs = BindAddrs {
+        v4: Some(SocketAddrV4::new([127, 0, 0, 1].into(), port)),
+        v6: None,
+    };
+
+    let first = bind(addrs).await?;
+    assert!(first
+        .bound_sockets()
+        .iter()
+        .any(|addr| addr.is_ipv4() && addr.port() == port));
+    assert!(bind(addrs).await.is_err());
+
+    first.close().await;
+    Ok(())
+}
@@ -0,0 +1,114 @@
+//! Moving state to another device in an encrypted bundle
+
+use common::node;
+use iroh::{NodeAddr, NodeId};
+use mdns_peer::bundle::StateBundle;
+use mdns_peer::groups::PeerGroups;
+use mdns_peer::notes::PeerNotes;
+use mdns_peer::options::WarmUp;
+use mdns_peer::profile::ProfileStore;
+
+mod common;
+
+/// Few rounds, so the tests don't spend a second per seal
+const ROUNDS: u32 = 16;
+
+fn sealed_bundle(passphrase: &str) -> anyhow::Result<(Vec<u8>, NodeId)> {
+    let dir = tempfile::tempdir()?;
+    let profiles = ProfileStore::new(dir.path());
+    let work = profiles.load_or_create("work", Some("alice-work"))?;
+    let notes = PeerNotes::open(dir.path())?;
+    notes.set_alias(node(1), Some("Laptop"))?;
+    notes.set_notes(node(2), Some("Lab phone"))?;
+    let groups = PeerGroups::open(dir.path())?;
+    groups.create("My devices")?;
+    groups.add("My devices", node(1))?;
+    let warm_up = WarmUp {
+        alpn: b"demo/0".to_vec(),
+        trusted: [node(1)].into(),
+    };
+
+    let bundle = StateBundle::collect(
+        &profiles,
+        &notes,
+        &groups,
+        Some(&warm_up),
+        &[NodeAddr::new(node(3))],
+    )?;
+    Ok((
+        bundle.seal_with_rounds(passphrase, ROUNDS)?,
+        work.secret_key.public(),
+    ))
+}
+
+#[test]
+fn bundle_restores_identity_and_pairings() -> anyhow::Result<()> {
+    let (sealed, work_id) = sealed_bundle("correct horse")?;
+
+    let dir = tempfile::tempdir()?;
+    let profiles = ProfileStore::new(dir.path());
+    let notes = PeerNotes::open(dir.path())?;
+    let groups = PeerGroups::open(dir.path())?;
+    let bundle = StateBundle::open(&sealed, "correct horse")?;
+    let report = bundle.restore(&profiles, &notes, &groups)?;
+
+    assert_eq!(report.profiles, ["work"]);
+    assert_eq!(report.notes, 2);
+    assert_eq!(report.groups, 1);
+    assert_eq!(report.trusted, [node(1)]);
+    assert_eq!(report.paired, 1);
+
+    let work = profiles.load_or_create("work", None)?;
+    assert_eq!(work.secret_key.public(), work_id);
+    assert_eq!(work.user_data, "alice-work");
+    assert_eq!(notes.get(node(1)).alias.as_deref(), Some("Laptop"));
+    assert_eq!(notes.get(node(2)).notes.as_deref(), Some("Lab phone"));
+    assert_eq!(groups.members("My devices"), Some([node(1)].into()));
+    assert_eq!(bundle.warm_up().map(|w| w.alpn), Some(b"demo/0".to_vec()));
+    assert_eq!(
+        bundle
+            .paired_addrs()?
+            .iter()
+            .map(|addr| addr.node_id)
+            .collect::<Vec<_>>(),
+        [node(3)]
+    );
+    Ok(())
+}
+
+#[test]
+fn existing_profiles_are_kept() -> anyhow::Result<()> {
+    let (sealed, work_id) = sealed_bundle("correct horse")?;
+
+    let dir = tempfile::tempdir()?;
+    let profiles = ProfileStore::new(dir.path());
+    let existing = profiles.load_or_create("work", None)?;
+    let report = StateBundle::open(&sealed, "correct horse")?.restore(
+        &profiles,
+        &PeerNotes::open(dir.path())?,
+        &PeerGroups::open(dir.path())?,
+    )?;
+
+    assert!(report.profiles.is_empty());
+    assert_eq!(report.skipped_profiles, ["work"]);
+    let work = profiles.load_or_create("work", None)?;
+    assert_eq!(work.secret_key.public(), existing.secret_key.public());
+    assert_ne!(work.secret_key.public(), work_id);
+    Ok(())
+}
+
+#[test]
+fn wrong_passphrase_or_tampering_fails() -> anyhow::Result<()> {
+    let (sealed, _) = sealed_bundle("correct horse")?;
+
+    let err = StateBundle::open(&sealed, "battery staple").unwrap_err();
+    assert!(err.to_string().contains("Wrong passphrase"));
+
+    let mut tampered = sealed.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert!(StateBundle::open(&tampered, "correct horse").is_err());
+
+    assert!(StateBundle::open(b"not a bundle", "correct horse").is_err());
+    assert!(StateBundle::default().seal_with_rounds("", ROUNDS).is_err());
+    Ok(())
+}
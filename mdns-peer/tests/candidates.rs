@@ -0,0 +1,97 @@
+//! Peer candidates injected by the host
+
+use std::time::Duration;
+
+use iroh::discovery::Discovery;
+use iroh::{Endpoint, NodeAddr, RelayMode, SecretKey, Watcher};
+use mdns_peer::candidates::Candidates;
+use mdns_peer::PeerOptions;
+use n0_future::StreamExt;
+
+const ALPN: &[u8] = b"mdns-peer/candidates-test/0";
+const DEADLINE: Duration = Duration::from_secs(10);
+const TTL: Duration = Duration::from_secs(60);
+
+fn addr(seed: u8, direct: &str) -> NodeAddr {
+    NodeAddr::from_parts(
+        SecretKey::from_bytes(&[seed; 32]).public(),
+        None,
+        [direct.parse().unwrap()],
+    )
+}
+
+#[test]
+fn injecting_again_replaces_instead_of_duplicating() {
+    let candidates = Candidates::default();
+    assert!(candidates.inject(addr(1, "192.168.1.20:4433"), TTL));
+    assert!(candidates.inject(addr(2, "192.168.1.21:4433"), TTL));
+    assert!(!candidates.inject(addr(1, "10.0.0.5:4433"), TTL));
+
+    let mut current = candidates.current();
+    current.sort_by_key(|addr| addr.node_id);
+    let mut expected = vec![addr(1, "10.0.0.5:4433"), addr(2, "192.168.1.21:4433")];
+    expected.sort_by_key(|addr| addr.node_id);
+    assert_eq!(current, expected);
+
+    assert!(candidates.remove(addr(2, "192.168.1.21:4433").node_id));
+    assert!(!candidates.remove(addr(2, "192.168.1.21:4433").node_id));
+    candidates.clear();
+    assert!(candidates.current().is_empty());
+}
+
+#[tokio::test]
+async fn candidates_expire_unless_refreshed() {
+    let candidates = Candidates::default();
+    let (stale, fresh) = (addr(1, "192.168.1.20:4433"), addr(2, "192.168.1.21:4433"));
+    candidates.inject(stale.clone(), Duration::from_millis(50));
+    candidates.inject(fresh.clone(), Duration::from_millis(50));
+    tokio::time::sleep(Duration::from_millis(30)).await;
+    candidates.inject(fresh.clone(), Duration::from_millis(50));
+    tokio::time::sleep(Duration::from_millis(30)).await;
+
+    assert!(candidates.resolve(stale.node_id).is_none());
+    let item = candidates
+        .resolve(fresh.node_id)
+        .expect("fresh candidate resolves")
+        .next()
+        .await
+        .expect("one item")
+        .expect("no error");
+    assert_eq!(item.node_id(), fresh.node_id);
+    assert_eq!(candidates.current(), vec![fresh]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn injected_candidates_can_be_dialed_by_node_id() -> anyhow::Result<()> {
+    // Nothing to discover alice by: no mDNS, no relay
+    let alice = Endpoint::builder()
+        .relay_mode(RelayMode::Disabled)
+        .alpns(vec![ALPN.to_vec()])
+        .bind()
+        .await?;
+    let options = PeerOptions::default();
+    let bob = mdns_peer::bind_endpoint_with("candidates-bob", &options).await?;
+
+    let alice_addr = tokio::time::timeout(DEADLINE, alice.node_addr().initialized()).await?;
+    options.candidates.inject(alice_addr, TTL);
+
+    let accept = tokio::spawn({
+        let alice = alice.clone();
+        async move {
+            let incoming = alice.accept().await.expect("alice is open");
+            let conn = incoming.await?;
+            anyhow::Ok(conn.remote_node_id()?)
+        }
+    });
+    let conn = tokio::time::timeout(DEADLINE, bob.connect(alice.node_id(), ALPN)).await??;
+    assert_eq!(conn.remote_node_id()?, alice.node_id());
+    assert_eq!(
+        tokio::time::timeout(DEADLINE, accept).await???,
+        bob.node_id()
+    );
+
+    conn.close(0u32.into(), b"done");
+    alice.close().await;
+    bob.close().await;
+    Ok(())
+}
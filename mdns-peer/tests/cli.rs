@@ -0,0 +1,48 @@
+//! Arguments of the `mdns-peer` binary
+#![cfg(feature = "cli")]
+
+use std::process::Command;
+
+/// Run `mdns-peer` with `args`, expecting it to fail with an error rather
+/// than a panic, and return what it printed
+fn rejected(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_mdns-peer"))
+        .args(args)
+        .output()
+        .expect("mdns-peer runs");
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    // A panic exits with 101
+    assert_eq!(output.status.code(), Some(1), "{:?}: {}", args, stderr);
+    stderr
+}
+
+#[test]
+fn seconds_must_be_finite_and_not_negative() {
+    let manifest = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+    for args in [
+        &["cli-test", "--summary-interval", "inf"][..],
+        &["cli-test", "--dns-fallback", "-1"],
+        &["cli-test", "--mdns-cadence", "nan"],
+        &["snapshot", "cli-test", "out.json", "--listen", "-1"],
+        &["find", "cli-test", "--listen", "inf"],
+        &["stats", "cli-test", "--interval", "nan"],
+        &["send", "cli-test", manifest, "--timeout", "-1"],
+        &["ping", "cli-test", "--timeout", "inf"],
+        &["fake", "--rotate", "inf"],
+        &["soak", "--hours", "-1"],
+        &["soak", "--hours", "nan"],
+        &["soak", "--hours", "inf"],
+        &["soak", "--hours", "0"],
+        &["soak", "--hours", "1e300"],
+    ] {
+        let flag = args.iter().find(|arg| arg.starts_with("--")).unwrap();
+        let stderr = rejected(args);
+        assert!(stderr.contains(&format!("Invalid {}", flag)), "{}", stderr);
+    }
+}
+
+#[test]
+fn seconds_must_be_a_number() {
+    let stderr = rejected(&["find", "cli-test", "--listen", "soon"]);
+    assert!(stderr.contains("Invalid --listen \"soon\""), "{}", stderr);
+}
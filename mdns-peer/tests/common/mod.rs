@@ -0,0 +1,163 @@
+//! Fixtures shared by the integration tests
+//!
+//! Each test file compiles this module on its own and uses only part of it.
+#![allow(dead_code)]
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use iroh::{protocol::Router, Endpoint, NodeAddr, NodeId, RelayMode, SecretKey, Watcher};
+use mdns_peer::protocols::Protocols;
+use mdns_peer::{pairing, EventSink, PeerEvent};
+use tokio::sync::mpsc;
+
+/// How long a test waits for anything before failing
+pub const DEADLINE: Duration = Duration::from_secs(20);
+
+/// A node ID that stays the same across runs
+pub fn node(seed: u8) -> NodeId {
+    SecretKey::from_bytes(&[seed; 32]).public()
+}
+
+/// A sink forwarding every event to the returned channel
+pub fn event_channel() -> (EventSink, mpsc::UnboundedReceiver<PeerEvent>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let sink: EventSink = Arc::new(move |event: &PeerEvent| {
+        let _ = tx.send(event.clone());
+    });
+    (sink, rx)
+}
+
+/// Every event a sink received, for tests that look back at them
+#[derive(Debug, Clone, Default)]
+pub struct EventLog(Arc<Mutex<Vec<PeerEvent>>>);
+
+impl EventLog {
+    pub fn sink(&self) -> EventSink {
+        let events = self.0.clone();
+        Arc::new(move |event: &PeerEvent| events.lock().unwrap().push(event.clone()))
+    }
+
+    pub fn events(&self) -> Vec<PeerEvent> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// What `find` makes of the first event it accepts, once one arrived
+    pub async fn wait_for<T>(&self, find: impl Fn(&PeerEvent) -> Option<T>) -> anyhow::Result<T> {
+        let found = async {
+            loop {
+                if let Some(found) = self.events().iter().find_map(&find) {
+                    return found;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        };
+        Ok(tokio::time::timeout(DEADLINE, found).await?)
+    }
+}
+
+/// An endpoint without relays, reachable only through addresses added by
+/// hand, with `seed`'s key if given
+pub async fn local_endpoint(seed: Option<u8>) -> anyhow::Result<Endpoint> {
+    let mut builder = Endpoint::builder().relay_mode(RelayMode::Disabled);
+    if let Some(seed) = seed {
+        builder = builder.secret_key(SecretKey::from_bytes(&[seed; 32]));
+    }
+    Ok(builder.bind().await?)
+}
+
+/// Add `other`'s addresses to `endpoint`, as pairing does
+pub async fn learn(endpoint: &Endpoint, other: &Endpoint) -> anyhow::Result<()> {
+    let addr = tokio::time::timeout(DEADLINE, other.node_addr().initialized()).await?;
+    pairing::add_paired_peer(endpoint, addr)
+}
+
+/// An endpoint serving [`Protocols`], with what its handlers and its event
+/// sink receive
+pub struct Side<T = ()> {
+    pub endpoint: Endpoint,
+    pub protocols: Protocols,
+    pub router: Router,
+    /// What the handlers set up by `register` passed on
+    pub received: mpsc::UnboundedReceiver<T>,
+    pub events: mpsc::UnboundedReceiver<PeerEvent>,
+}
+
+impl<T> Side<T> {
+    /// Bind like a peer does and serve whatever `register` sets up, handing
+    /// it the sender of [`Side::received`]
+    pub async fn bind(
+        identifier: &str,
+        register: impl FnOnce(&Protocols, mpsc::UnboundedSender<T>),
+    ) -> anyhow::Result<Self> {
+        let endpoint = mdns_peer::bind_endpoint(identifier).await?;
+        Ok(Self::serve(endpoint, register))
+    }
+
+    /// Like [`Side::bind`], on a [`local_endpoint`]
+    pub async fn local(
+        seed: Option<u8>,
+        register: impl FnOnce(&Protocols, mpsc::UnboundedSender<T>),
+    ) -> anyhow::Result<Self> {
+        let endpoint = local_endpoint(seed).await?;
+        Ok(Self::serve(endpoint, register))
+    }
+
+    fn serve(
+        endpoint: Endpoint,
+        register: impl FnOnce(&Protocols, mpsc::UnboundedSender<T>),
+    ) -> Self {
+        let protocols = Protocols::default();
+        let (tx, received) = mpsc::unbounded_channel();
+        register(&protocols, tx);
+        let (sink, events) = event_channel();
+        protocols.set_event_sink(sink);
+        let router = protocols.spawn_router(endpoint.clone());
+        Self {
+            endpoint,
+            protocols,
+            router,
+            received,
+            events,
+        }
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        self.endpoint.node_id()
+    }
+
+    pub async fn addr(&self) -> NodeAddr {
+        self.endpoint.node_addr().initialized().await
+    }
+
+    /// Add `other`'s addresses, as pairing does
+    pub async fn learn<U>(&self, other: &Side<U>) -> anyhow::Result<()> {
+        learn(&self.endpoint, &other.endpoint).await
+    }
+
+    /// The next thing a handler passed on
+    pub async fn next_received(&mut self) -> anyhow::Result<T> {
+        let received = tokio::time::timeout(DEADLINE, self.received.recv()).await?;
+        Ok(received.expect("handler dropped"))
+    }
+
+    /// The next event `matching` accepts, skipping the others
+    pub async fn next_event(
+        &mut self,
+        matching: impl Fn(&PeerEvent) -> bool,
+    ) -> anyhow::Result<PeerEvent> {
+        loop {
+            let event = tokio::time::timeout(DEADLINE, self.events.recv())
+                .await?
+                .expect("sink dropped");
+            if matching(&event) {
+                return Ok(event);
+            }
+        }
+    }
+
+    pub async fn shutdown(self) -> anyhow::Result<()> {
+        self.router.shutdown().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+//! Startup settings from the environment and config files
+
+use std::time::Duration;
+
+use iroh::RelayUrl;
+use mdns_peer::config::PeerConfig;
+use mdns_peer::instance::DuplicatePolicy;
+use mdns_peer::PeerOptions;
+
+#[test]
+fn environment_overrides_the_file() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("MdnsPeerConfig.plist");
+    std::fs::write(
+        &path,
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>summary_interval</key>
+    <integer>0</integer>
+    <key>duplicate</key>
+    <string>suffix</string>
+    <key>mdns_service</key>
+    <string>mdns-lab</string>
+    <key>mdns_cadence</key>
+    <real>2.5</real>
+</dict>
+</plist>"#,
+    )?;
+    let file = PeerConfig::load(&path)?;
+    let env = PeerConfig::from_vars([
+        ("MDNS_PEER_MDNS_CADENCE", "3"),
+        (
+            "MDNS_PEER_RELAYS",
+            "https://a.example.com, https://b.example.com",
+        ),
+        ("MDNS_PEER_LAN_ONLY", "true"),
+        ("MDNS_PEER_RECORD", ""),
+        ("MDNS_PEER_HOME", "/tmp/elsewhere"),
+        ("HOME", "/root"),
+    ])?;
+
+    let mut options = PeerOptions::default();
+    file.merge(env).apply(&mut options)?;
+    assert_eq!(options.summary_interval, None);
+    assert_eq!(options.on_duplicate, DuplicatePolicy::Suffix);
+    assert_eq!(options.mdns.service_name, "mdns-lab");
+    assert_eq!(options.mdns.cadence, Duration::from_secs(3));
+    let relays: Vec<RelayUrl> = ["https://a.example.com", "https://b.example.com"]
+        .iter()
+        .map(|url| url.parse().unwrap())
+        .collect();
+    assert_eq!(options.relays, Some(relays));
+    assert!(options.lan_only);
+    assert_eq!(options.record, None);
+    Ok(())
+}
+
+#[test]
+fn json_files_reject_unknown_keys() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("config.json");
+    std::fs::write(
+        &path,
+        r#"{"mdns_response_rate": 1.5, "record": "events.ndjson"}"#,
+    )?;
+    let config = PeerConfig::load(&path)?;
+    assert_eq!(config.mdns_response_rate, Some(1.5));
+    assert_eq!(config.record, Some("events.ndjson".into()));
+
+    std::fs::write(&path, r#"{"mdns_cadance": 3}"#)?;
+    assert!(PeerConfig::load(&path).is_err());
+    assert!(PeerConfig::load(&dir.path().join("missing.json")).is_err());
+    Ok(())
+}
+
+#[test]
+fn invalid_settings_change_nothing() {
+    assert!(PeerConfig::from_vars([("MDNS_PEER_MDNS_CADENCE", "soon")]).is_err());
+
+    let mut options = PeerOptions::default();
+    let config = PeerConfig {
+        mdns_service: Some("mdns-lab".to_string()),
+        duplicate: Some("sometimes".to_string()),
+        ..PeerConfig::default()
+    };
+    assert!(config.apply(&mut options).is_err());
+    // Cadence times rate must stay above 1
+    let config = PeerConfig {
+        mdns_cadence: Some(0.5),
+        mdns_response_rate: Some(1.0),
+        ..PeerConfig::default()
+    };
+    assert!(config.apply(&mut options).is_err());
+    assert_eq!(options.mdns, PeerOptions::default().mdns);
+    assert_eq!(options.on_duplicate, DuplicatePolicy::Refuse);
+}
+
+#[test]
+fn seed_gives_a_stable_key() -> anyhow::Result<()> {
+    let config = PeerConfig::from_vars([("MDNS_PEER_SEED", "42")])?;
+    assert_eq!(config.seed, Some(42));
+
+    let mut first = PeerOptions::default();
+    config.apply(&mut first)?;
+    let mut second = PeerOptions::default();
+    config.apply(&mut second)?;
+    let node_id = |options: &PeerOptions| options.secret_key.as_ref().map(|key| key.public());
+    assert!(node_id(&first).is_some());
+    assert_eq!(node_id(&first), node_id(&second));
+
+    let mut other = PeerOptions::default();
+    PeerConfig {
+        seed: Some(43),
+        ..PeerConfig::default()
+    }
+    .apply(&mut other)?;
+    assert_ne!(node_id(&first), node_id(&other));
+    assert!(PeerConfig::from_vars([("MDNS_PEER_SEED", "-1")]).is_err());
+    Ok(())
+}
@@ -0,0 +1,97 @@
+//! Connection lifecycle events on host protocols
+
+use std::sync::Arc;
+
+use iroh::{NodeId, Watcher};
+use mdns_peer::connections::Direction;
+use mdns_peer::protocols::{StreamHandler, StreamId};
+use mdns_peer::PeerEvent;
+
+use common::{Side, DEADLINE};
+
+mod common;
+
+const ALPN: &[u8] = b"mdns-peer/test-connections/0";
+
+struct Ignore;
+
+impl StreamHandler for Ignore {
+    fn on_open(&self, _stream: StreamId, _node_id: NodeId) {}
+
+    fn on_data(&self, _stream: StreamId, _data: &[u8]) {}
+
+    fn on_close(&self, _stream: StreamId, _error: Option<&str>) {}
+}
+
+async fn bind(identifier: &str) -> anyhow::Result<Side> {
+    Side::bind(identifier, |protocols, _| {
+        protocols.register(ALPN, Arc::new(Ignore));
+    })
+    .await
+}
+
+/// Connection events, rather than the rest
+fn connection(event: &PeerEvent) -> bool {
+    matches!(
+        event,
+        PeerEvent::Connected { .. } | PeerEvent::ConnectionClosed { .. }
+    )
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn connections_are_reported_opening_and_closing() -> anyhow::Result<()> {
+    let mut server = bind("connections-server").await?;
+    let mut client = bind("connections-client").await?;
+    let server_addr = server.endpoint.node_addr().initialized().await;
+
+    let conn = tokio::time::timeout(
+        DEADLINE,
+        client
+            .protocols
+            .connect(&client.endpoint, server_addr, ALPN),
+    )
+    .await??;
+    // The server only sees the connection once the client uses it
+    let (mut send, _recv) = conn.open_bi().await?;
+    send.write_all(b"hello").await?;
+
+    let event = client.next_event(connection).await?;
+    assert!(matches!(
+        &event,
+        PeerEvent::Connected { node_id, alpn, direction: Direction::Outbound, .. }
+            if *node_id == server.endpoint.node_id() && alpn == "mdns-peer/test-connections/0"
+    ));
+    let event = server.next_event(connection).await?;
+    assert!(matches!(
+        &event,
+        PeerEvent::Connected { node_id, direction: Direction::Inbound, .. }
+            if *node_id == client.endpoint.node_id()
+    ));
+
+    conn.close(7u32.into(), b"bye");
+    let event = client.next_event(connection).await?;
+    assert!(matches!(
+        &event,
+        PeerEvent::ConnectionClosed {
+            direction: Direction::Outbound,
+            ..
+        }
+    ));
+    let event = server.next_event(connection).await?;
+    match event {
+        PeerEvent::ConnectionClosed {
+            node_id,
+            direction,
+            reason,
+            ..
+        } => {
+            assert_eq!(node_id, client.endpoint.node_id());
+            assert_eq!(direction, Direction::Inbound);
+            assert!(reason.contains("closed by peer"), "{reason}");
+        }
+        other => panic!("expected connection_closed, got {other:?}"),
+    }
+
+    client.shutdown().await?;
+    server.shutdown().await
+}
@@ -0,0 +1,156 @@
+//! Browser dashboard served by `mdns-peer daemon --dashboard`
+#![cfg(feature = "cli")]
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use common::node;
+use iroh::NodeId;
+use mdns_peer::dashboard::Dashboard;
+use mdns_peer::events::{DiscoveryOrigin, PeerSummary};
+use mdns_peer::protocols::Protocols;
+use mdns_peer::remote_info::ConnectionReport;
+use mdns_peer::PeerEvent;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+mod common;
+
+const DEADLINE: Duration = Duration::from_secs(5);
+
+fn discovered(node_id: NodeId, user_data: &str) -> PeerEvent {
+    PeerEvent::Discovered {
+        node_id,
+        user_data: Some(user_data.to_string()),
+        provenance: "mdns",
+        origin: DiscoveryOrigin::default(),
+    }
+}
+
+async fn start() -> anyhow::Result<(Dashboard, SocketAddr)> {
+    let dashboard = Dashboard::new(Protocols::default());
+    let addr = dashboard.spawn("127.0.0.1:0".parse()?).await?;
+    Ok((dashboard, addr))
+}
+
+/// Status line and body of a `Connection: close` response
+async fn request(addr: SocketAddr, method: &str, path: &str) -> anyhow::Result<(String, String)> {
+    let mut stream = TcpStream::connect(addr).await?;
+    let request = format!("{} {} HTTP/1.1\r\nHost: {}\r\n\r\n", method, path, addr);
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = String::new();
+    tokio::time::timeout(DEADLINE, stream.read_to_string(&mut response)).await??;
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or_default();
+    let status = head.lines().next().unwrap_or_default().to_string();
+    Ok((status, body.to_string()))
+}
+
+#[tokio::test]
+async fn serves_the_page() -> anyhow::Result<()> {
+    let (_dashboard, addr) = start().await?;
+
+    let (status, body) = request(addr, "GET", "/").await?;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert!(body.contains("<title>mdns-peer</title>"));
+
+    let (status, _) = request(addr, "GET", "/missing").await?;
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+    Ok(())
+}
+
+#[tokio::test]
+async fn peer_table_follows_events() -> anyhow::Result<()> {
+    let (dashboard, addr) = start().await?;
+    let (alice, bob) = (node(1), node(2));
+    dashboard.record(&discovered(alice, "alice"));
+    dashboard.record(&discovered(bob, "bob"));
+    dashboard.record(&PeerEvent::Summary {
+        routing_table_size: 2,
+        peers: vec![PeerSummary {
+            node_id: alice,
+            user_data: Some("alice".to_string()),
+            alias: Some("Alice's phone".to_string()),
+            notes: Some("Test device".to_string()),
+            connection: ConnectionReport::Direct {
+                addr: "192.168.1.20:4433".parse()?,
+            },
+            warm: true,
+            expires_in_ms: 2100,
+        }],
+    });
+    dashboard.record(&PeerEvent::Expired {
+        node_id: bob,
+        user_data: Some("bob".to_string()),
+    });
+
+    let (status, body) = request(addr, "GET", "/api/peers").await?;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    let peers: serde_json::Value = serde_json::from_str(&body)?;
+    assert_eq!(
+        peers,
+        serde_json::json!([{
+            "node_id": alice.to_string(),
+            "user_data": "alice",
+            "alias": "Alice's phone",
+            "notes": "Test device",
+            "provenance": "mdns",
+            "connection": { "kind": "direct", "addr": "192.168.1.20:4433" },
+            "warm": true,
+            "expires_in_ms": 2100,
+        }])
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn event_stream_replays_the_log_then_goes_live() -> anyhow::Result<()> {
+    let (dashboard, addr) = start().await?;
+    dashboard.record(&discovered(node(1), "alice"));
+
+    let mut stream = TcpStream::connect(addr).await?;
+    stream
+        .write_all(b"GET /api/events HTTP/1.1\r\nHost: dashboard\r\n\r\n")
+        .await?;
+
+    let mut received = String::new();
+    let mut buf = [0; 4096];
+    let mut sent_live = false;
+    while !received.contains("\"user_data\":\"bob\"") {
+        let n = tokio::time::timeout(DEADLINE, stream.read(&mut buf)).await??;
+        assert!(n > 0, "event stream closed early: {}", received);
+        received.push_str(&String::from_utf8_lossy(&buf[..n]));
+        if !sent_live && received.contains("\"user_data\":\"alice\"") {
+            sent_live = true;
+            dashboard.record(&discovered(node(2), "bob"));
+        }
+    }
+
+    assert!(received.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(received.contains("Content-Type: text/event-stream"));
+    let frames: Vec<_> = received
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .map(serde_json::from_str::<serde_json::Value>)
+        .collect::<Result<_, _>>()?;
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0]["user_data"], "alice");
+    assert_eq!(frames[1]["user_data"], "bob");
+    assert!(frames[1]["monotonic_ms"].is_u64());
+    Ok(())
+}
+
+#[tokio::test]
+async fn actions_fail_cleanly_before_the_peer_runs() -> anyhow::Result<()> {
+    let (_dashboard, addr) = start().await?;
+
+    let path = format!("/api/peers/{}/ping", node(1));
+    let (status, body) = request(addr, "POST", &path).await?;
+    assert_eq!(status, "HTTP/1.1 502 Bad Gateway");
+    let error: serde_json::Value = serde_json::from_str(&body)?;
+    assert_eq!(error["error"], "The peer isn't running yet");
+
+    let (status, _) = request(addr, "POST", "/api/peers/not-a-node/ping").await?;
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+    Ok(())
+}
@@ -0,0 +1,52 @@
+//! Caller-chosen timeouts and how they are told apart from other failures
+
+use std::time::Duration;
+
+use mdns_peer::deadline::{self, ResultCode, TimedOut};
+
+#[tokio::test]
+async fn slow_operations_time_out() {
+    let result: anyhow::Result<()> = deadline::within(
+        Some(Duration::from_millis(20)),
+        "connect",
+        std::future::pending(),
+    )
+    .await;
+    let err = result.unwrap_err();
+    assert!(deadline::is_timed_out(&err));
+    assert_eq!(err.to_string(), "Timed out after 20 ms waiting to connect");
+    assert_eq!(ResultCode::from_error(&err), ResultCode::TimedOut);
+}
+
+#[tokio::test]
+async fn operations_within_their_timeout_keep_their_result() {
+    let done = deadline::within(Some(Duration::from_secs(5)), "connect", async { Ok(7) }).await;
+    assert_eq!(done.unwrap(), 7);
+
+    let failed: anyhow::Result<()> = deadline::within(None, "connect", async {
+        anyhow::bail!("connection refused")
+    })
+    .await;
+    let err = failed.unwrap_err();
+    assert!(!deadline::is_timed_out(&err));
+    assert_eq!(ResultCode::from_error(&err), ResultCode::Failed);
+}
+
+#[test]
+fn timeouts_are_found_under_context() {
+    let err = anyhow::Error::new(TimedOut {
+        operation: "discover the receiver",
+        after: Duration::from_secs(30),
+    })
+    .context("bob not found");
+    assert!(deadline::is_timed_out(&err));
+}
+
+#[test]
+fn zero_millis_means_no_timeout() {
+    assert_eq!(deadline::from_millis(0), None);
+    assert_eq!(
+        deadline::from_millis(1500),
+        Some(Duration::from_millis(1500))
+    );
+}
@@ -0,0 +1,136 @@
+//! In-process discovery tests
+//!
+//! These bind several endpoints with local network discovery inside one
+//! process and rely on multicast working on the loopback/LAN interfaces of
+//! the machine running the tests.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use iroh::{discovery::DiscoveryEvent, Endpoint, NodeId};
+use mdns_peer::PeerEvent;
+use n0_future::StreamExt;
+use tokio::sync::broadcast;
+
+const DISCOVERY_DEADLINE: Duration = Duration::from_secs(30);
+const SHUTDOWN_DEADLINE: Duration = Duration::from_secs(10);
+
+/// Wait until `endpoint` discovers `target`, returning the advertised user data
+async fn wait_for_peer(endpoint: &Endpoint, target: NodeId) -> Option<String> {
+    let mut events = endpoint.discovery_stream();
+    while let Some(event) = events.next().await {
+        if let Ok(DiscoveryEvent::Discovered(item)) = event {
+            if item.node_id() == target {
+                return item
+                    .node_info()
+                    .data
+                    .user_data()
+                    .map(|data| data.to_string());
+            }
+        }
+    }
+    None
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn endpoints_discover_each_other_with_user_data() -> anyhow::Result<()> {
+    let alice = mdns_peer::bind_endpoint("alice").await?;
+    let bob = mdns_peer::bind_endpoint("bob").await?;
+
+    let (alice_saw, bob_saw) = tokio::time::timeout(DISCOVERY_DEADLINE, async {
+        tokio::join!(
+            wait_for_peer(&alice, bob.node_id()),
+            wait_for_peer(&bob, alice.node_id()),
+        )
+    })
+    .await?;
+
+    assert_eq!(alice_saw.as_deref(), Some("bob"));
+    assert_eq!(bob_saw.as_deref(), Some("alice"));
+
+    alice.close().await;
+    bob.close().await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn three_endpoints_discover_all_others() -> anyhow::Result<()> {
+    let names = ["carol", "dave", "erin"];
+    let mut endpoints = Vec::new();
+    for name in names {
+        endpoints.push(mdns_peer::bind_endpoint(name).await?);
+    }
+
+    for (i, endpoint) in endpoints.iter().enumerate() {
+        for (j, other) in endpoints.iter().enumerate() {
+            if i == j {
+                continue;
+            }
+            let seen =
+                tokio::time::timeout(DISCOVERY_DEADLINE, wait_for_peer(endpoint, other.node_id()))
+                    .await?;
+            assert_eq!(seen.as_deref(), Some(names[j]));
+        }
+    }
+
+    for endpoint in endpoints {
+        endpoint.close().await;
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn invalid_identifier_is_rejected() {
+    // user_data is limited in length, so an oversized identifier must fail to bind
+    let identifier = "x".repeat(1024);
+    assert!(mdns_peer::bind_endpoint(&identifier).await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_peer_stops_on_shutdown_signal() -> anyhow::Result<()> {
+    let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+    let peer = tokio::spawn(async move {
+        mdns_peer::run_peer(
+            "frank",
+            mdns_peer::PeerOptions::default(),
+            shutdown_rx,
+            mdns_peer::events::discard_events(),
+        )
+        .await
+    });
+
+    // Give the endpoint time to bind before asking it to stop
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    shutdown_tx.send(())?;
+
+    let result = tokio::time::timeout(SHUTDOWN_DEADLINE, peer).await??;
+    assert!(result.is_ok());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_peer_emits_summary_at_configured_interval() -> anyhow::Result<()> {
+    let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+    let (summary_tx, mut summary_rx) = tokio::sync::mpsc::unbounded_channel();
+    let events: mdns_peer::EventSink = Arc::new(move |event: &PeerEvent| {
+        if matches!(event, PeerEvent::Summary { .. }) {
+            let _ = summary_tx.send(event.clone());
+        }
+    });
+    let options = mdns_peer::PeerOptions {
+        summary_interval: Some(Duration::from_millis(100)),
+        ..Default::default()
+    };
+    let peer = tokio::spawn(mdns_peer::run_peer("grace", options, shutdown_rx, events));
+
+    // The default interval is 5s, so several summaries in 1s prove the option is used
+    for _ in 0..3 {
+        tokio::time::timeout(Duration::from_secs(1), summary_rx.recv())
+            .await?
+            .expect("summary channel closed");
+    }
+
+    shutdown_tx.send(())?;
+    tokio::time::timeout(SHUTDOWN_DEADLINE, peer).await???;
+    Ok(())
+}
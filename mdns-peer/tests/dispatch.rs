@@ -0,0 +1,117 @@
+//! Ordering, drop-oldest and batching behavior of the event dispatch thread
+
+use std::sync::mpsc;
+use std::time::Duration;
+
+use mdns_peer::dispatch::EventDispatcher;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[test]
+fn delivers_in_order() {
+    let (tx, rx) = mpsc::channel();
+    let dispatcher = EventDispatcher::spawn(16, move |event: u32| tx.send(event).unwrap());
+
+    for i in 0..10 {
+        dispatcher.push(i);
+    }
+
+    let delivered: Vec<_> = (0..10).map(|_| rx.recv_timeout(TIMEOUT).unwrap()).collect();
+    assert_eq!(delivered, (0..10).collect::<Vec<_>>());
+    assert_eq!(dispatcher.dropped(), 0);
+}
+
+#[test]
+fn slow_consumer_drops_oldest() {
+    let (started_tx, started_rx) = mpsc::channel();
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+    let (delivered_tx, delivered_rx) = mpsc::channel();
+
+    let dispatcher = EventDispatcher::spawn(2, move |event: u32| {
+        if event == 0 {
+            // Block on the first event until the test has filled the queue
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        }
+        delivered_tx.send(event).unwrap();
+    });
+
+    dispatcher.push(0);
+    started_rx.recv_timeout(TIMEOUT).unwrap();
+
+    // Producers must not block even though the consumer is stuck
+    for i in 1..=5 {
+        dispatcher.push(i);
+    }
+    assert_eq!(dispatcher.dropped(), 3);
+
+    release_tx.send(()).unwrap();
+    let delivered: Vec<_> = (0..3)
+        .map(|_| delivered_rx.recv_timeout(TIMEOUT).unwrap())
+        .collect();
+    assert_eq!(delivered, vec![0, 4, 5]);
+}
+
+#[test]
+fn drains_queue_after_drop() {
+    let (tx, rx) = mpsc::channel();
+    let dispatcher = EventDispatcher::spawn(8, move |event: u32| tx.send(event).unwrap());
+
+    dispatcher.push(1);
+    dispatcher.push(2);
+    drop(dispatcher);
+
+    assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), 1);
+    assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), 2);
+    // The thread exits once drained, closing the channel
+    assert!(rx.recv_timeout(TIMEOUT).is_err());
+}
+
+#[test]
+fn batch_window_coalesces_bursts() {
+    let (tx, rx) = mpsc::channel();
+    let dispatcher =
+        EventDispatcher::spawn_batched(16, move |batch: Vec<u32>| tx.send(batch).unwrap());
+    dispatcher.set_batch_window(Duration::from_millis(200));
+
+    for i in 0..5 {
+        dispatcher.push(i);
+    }
+
+    assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), vec![0, 1, 2, 3, 4]);
+    assert!(rx.recv_timeout(Duration::from_millis(300)).is_err());
+}
+
+#[test]
+fn capacity_can_shrink() {
+    let (started_tx, started_rx) = mpsc::channel();
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+    let (delivered_tx, delivered_rx) = mpsc::channel();
+
+    let dispatcher = EventDispatcher::spawn(8, move |event: u32| {
+        if event == 0 {
+            started_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+        }
+        delivered_tx.send(event).unwrap();
+    });
+
+    dispatcher.push(0);
+    started_rx.recv_timeout(TIMEOUT).unwrap();
+    for i in 1..=4 {
+        dispatcher.push(i);
+    }
+    assert_eq!(dispatcher.dropped(), 0);
+
+    // Shrinking drops the oldest queued events on the next push
+    dispatcher.set_capacity(2);
+    assert_eq!(dispatcher.capacity(), 2);
+    dispatcher.push(5);
+    assert_eq!(dispatcher.dropped(), 3);
+
+    release_tx.send(()).unwrap();
+    let delivered: Vec<_> = (0..3)
+        .map(|_| delivered_rx.recv_timeout(TIMEOUT).unwrap())
+        .collect();
+    assert_eq!(delivered, vec![0, 4, 5]);
+}
@@ -0,0 +1,101 @@
+//! Documents edited by two peers at once
+#![cfg(feature = "docs")]
+
+use common::EventLog;
+use iroh::{protocol::Router, Endpoint, NodeId};
+use mdns_peer::docs::SharedDocs;
+use mdns_peer::protocols::Protocols;
+use mdns_peer::topics::Topics;
+use mdns_peer::PeerEvent;
+
+mod common;
+
+struct Peer {
+    endpoint: Endpoint,
+    docs: SharedDocs,
+    router: Router,
+    events: EventLog,
+}
+
+impl Peer {
+    async fn start() -> anyhow::Result<Self> {
+        let endpoint = common::local_endpoint(None).await?;
+        let events = EventLog::default();
+        let gossip = Topics::default().attach(&endpoint, events.sink());
+        let docs = SharedDocs::default();
+        let protocols = docs
+            .attach(&endpoint, gossip.clone(), events.sink())
+            .await?;
+        let router = Protocols::default().spawn_router_with(endpoint.clone(), |builder| {
+            protocols.accept(builder).accept(iroh_gossip::ALPN, gossip)
+        });
+        Ok(Self {
+            endpoint,
+            docs,
+            router,
+            events,
+        })
+    }
+
+    /// Wait until `key` in `doc` changed through a write from `from`
+    async fn changed(&self, doc: &str, key: &str, from: Option<NodeId>) -> anyhow::Result<()> {
+        let expected = PeerEvent::DocChanged {
+            doc: doc.to_string(),
+            key: key.to_string(),
+            node_id: from,
+        };
+        self.events
+            .wait_for(|event| (*event == expected).then_some(()))
+            .await
+    }
+
+    async fn shutdown(self) -> anyhow::Result<()> {
+        self.docs.detach();
+        self.router.shutdown().await?;
+        Ok(())
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn writes_sync_both_ways() -> anyhow::Result<()> {
+    let phone = Peer::start().await?;
+    let desktop = Peer::start().await?;
+
+    let share = phone.docs.create().await?;
+    phone
+        .docs
+        .set(&share.id, b"title", b"Holiday".to_vec())
+        .await?;
+    phone.changed(&share.id, "title", None).await?;
+
+    let id = desktop.docs.join(&share.ticket).await?;
+    assert_eq!(id, share.id);
+    desktop
+        .changed(&id, "title", Some(phone.endpoint.node_id()))
+        .await?;
+    assert_eq!(
+        desktop.docs.get(&id, b"title").await?,
+        Some(b"Holiday".to_vec())
+    );
+
+    desktop.docs.set(&id, b"title", b"Summer".to_vec()).await?;
+    phone
+        .changed(&id, "title", Some(desktop.endpoint.node_id()))
+        .await?;
+    assert_eq!(
+        phone.docs.get(&id, b"title").await?,
+        Some(b"Summer".to_vec())
+    );
+    assert_eq!(phone.docs.get(&id, b"missing").await?, None);
+
+    phone.shutdown().await?;
+    desktop.shutdown().await
+}
+
+#[tokio::test]
+async fn nothing_works_before_the_peer_runs() {
+    let docs = SharedDocs::default();
+    assert!(docs.create().await.is_err());
+    assert!(docs.set("doc", b"key", Vec::new()).await.is_err());
+    assert!(docs.open().is_empty());
+}
@@ -0,0 +1,66 @@
+//! The environment report attached to bug reports
+
+use iroh::SecretKey;
+use mdns_peer::environment::{EnvironmentReport, IROH_VERSION};
+use mdns_peer::mdns::MdnsOptions;
+use mdns_peer::PeerOptions;
+
+#[test]
+fn defaults_are_reported_without_an_endpoint() {
+    let report = EnvironmentReport::collect(&PeerOptions::default(), None);
+    assert_eq!(report.version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(report.iroh_version, IROH_VERSION);
+    assert_eq!(report.os, std::env::consts::OS);
+    assert_eq!(report.discovery, ["mdns", "candidates"]);
+    assert_eq!(report.relay_mode, "default");
+    assert!(!report.relays.is_empty());
+    assert!(!report.persistent_key);
+    assert_eq!(report.node_id, None);
+    assert!(report.bound_sockets.is_empty());
+}
+
+#[test]
+fn options_show_up_in_the_report() {
+    let options = PeerOptions {
+        lan_only: true,
+        secret_key: Some(SecretKey::generate(rand::rngs::OsRng)),
+        mdns: MdnsOptions {
+            service_name: "my-app".to_string(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let report = EnvironmentReport::collect(&options, None);
+    assert_eq!(report.relay_mode, "disabled");
+    assert!(report.relays.is_empty());
+    assert!(report.persistent_key);
+    assert!(report.discovery[0].starts_with("mdns (service my-app,"));
+}
+
+#[tokio::test]
+async fn a_bound_endpoint_adds_its_identity() -> anyhow::Result<()> {
+    let endpoint = iroh::Endpoint::builder()
+        .relay_mode(iroh::RelayMode::Disabled)
+        .bind()
+        .await?;
+    let report = EnvironmentReport::collect(&PeerOptions::default(), Some(&endpoint));
+    assert_eq!(report.node_id, Some(endpoint.node_id()));
+    assert_eq!(
+        report.key_fingerprint,
+        Some(endpoint.node_id().fmt_short().to_string())
+    );
+    assert!(!report.bound_sockets.is_empty());
+
+    let json = serde_json::to_value(&report)?;
+    for key in [
+        "version",
+        "iroh_version",
+        "interfaces",
+        "relay_mode",
+        "node_id",
+    ] {
+        assert!(json.get(key).is_some(), "missing {key}");
+    }
+    endpoint.close().await;
+    Ok(())
+}
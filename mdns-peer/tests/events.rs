@@ -0,0 +1,673 @@
+//! JSON shape of events handed to FFI consumers
+
+use iroh::SecretKey;
+use mdns_peer::connections::Direction;
+use mdns_peer::deadline::ResultCode;
+use mdns_peer::events::{
+    event_mask, DiscoveryOrigin, LocalAddrs, MemoryPool, MessageLimit, PeerSummary, TimedEvent,
+    Timestamp,
+};
+use mdns_peer::fallback::DiscoveryMode;
+use mdns_peer::hotspot;
+use mdns_peer::messages::MessageId;
+use mdns_peer::operations::OperationKind;
+use mdns_peer::remote_info::ConnectionReport;
+use mdns_peer::{PeerEvent, PeerStatus, ShutdownReason};
+use serde_json::json;
+
+#[test]
+fn discovered_event_json() {
+    let node_id = SecretKey::from_bytes(&[7; 32]).public();
+    let event = PeerEvent::Discovered {
+        node_id,
+        user_data: Some("alice".to_string()),
+        provenance: "mdns",
+        origin: DiscoveryOrigin {
+            interface: Some("en0".to_string()),
+            addr: Some("192.168.1.20:51234".parse().unwrap()),
+            direct_addrs: vec!["192.168.1.20:51234".parse().unwrap()],
+            relay_url: None,
+        },
+    };
+
+    let value: serde_json::Value = serde_json::from_str(&event.to_json()).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "type": "discovered",
+            "node_id": node_id.to_string(),
+            "user_data": "alice",
+            "provenance": "mdns",
+            "origin": {
+                "interface": "en0",
+                "addr": "192.168.1.20:51234",
+                "direct_addrs": ["192.168.1.20:51234"],
+                "relay_url": null,
+            },
+        })
+    );
+}
+
+#[test]
+fn timed_event_json_carries_both_clocks() {
+    let event = TimedEvent {
+        event: PeerEvent::status(PeerStatus::Running),
+        at: Timestamp {
+            monotonic_ms: 5120,
+            wall_ms: 1_760_601_600_000,
+        },
+    };
+
+    let value: serde_json::Value = serde_json::from_str(&event.to_json()).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "type": "status_changed",
+            "status": "running",
+            "monotonic_ms": 5120,
+            "wall_ms": 1_760_601_600_000u64,
+        })
+    );
+}
+
+#[test]
+fn monotonic_timestamps_never_go_backwards() {
+    let mut previous = Timestamp::now();
+    for _ in 0..1000 {
+        let now = Timestamp::now();
+        assert!(now.monotonic_ms >= previous.monotonic_ms);
+        previous = now;
+    }
+    assert!(previous.wall_ms > 0);
+}
+
+#[test]
+fn status_event_json() {
+    let event = PeerEvent::status(PeerStatus::LocalNetworkPermissionLikelyDenied);
+
+    let value: serde_json::Value = serde_json::from_str(&event.to_json()).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "type": "status_changed",
+            "status": "local_network_permission_likely_denied",
+        })
+    );
+}
+
+#[test]
+fn stopped_event_json_carries_the_reason() {
+    let value: serde_json::Value =
+        serde_json::from_str(&PeerEvent::stopped(ShutdownReason::BindLost).to_json()).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "type": "status_changed",
+            "status": "stopped",
+            "reason": "bind_lost",
+        })
+    );
+    assert_eq!(ShutdownReason::EndpointClosedRemotely as i32, 4);
+}
+
+#[test]
+fn summary_event_json() {
+    let node_id = SecretKey::from_bytes(&[7; 32]).public();
+    let event = PeerEvent::Summary {
+        routing_table_size: 2,
+        peers: vec![PeerSummary {
+            node_id,
+            user_data: Some("alice".to_string()),
+            alias: Some("Dad's MacBook".to_string()),
+            notes: None,
+            connection: ConnectionReport::None,
+            warm: true,
+            expires_in_ms: 2100,
+        }],
+    };
+
+    let value: serde_json::Value = serde_json::from_str(&event.to_json()).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "type": "summary",
+            "routing_table_size": 2,
+            "peers": [{
+                "node_id": node_id.to_string(),
+                "user_data": "alice",
+                "alias": "Dad's MacBook",
+                "notes": null,
+                "connection": { "kind": "none" },
+                "warm": true,
+                "expires_in_ms": 2100,
+            }],
+        })
+    );
+}
+
+#[test]
+fn local_addrs_changed_event_json() {
+    let event = PeerEvent::LocalAddrsChanged {
+        previous: LocalAddrs {
+            direct_addrs: vec!["192.168.1.20:51000".parse().unwrap()],
+            home_relay: Vec::new(),
+        },
+        current: LocalAddrs {
+            direct_addrs: vec!["10.0.0.5:51000".parse().unwrap()],
+            home_relay: vec!["https://relay.example/".to_string()],
+        },
+    };
+
+    let value: serde_json::Value = serde_json::from_str(&event.to_json()).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "type": "local_addrs_changed",
+            "previous": { "direct_addrs": ["192.168.1.20:51000"], "home_relay": [] },
+            "current": { "direct_addrs": ["10.0.0.5:51000"], "home_relay": ["https://relay.example/"] },
+        })
+    );
+}
+
+#[test]
+fn error_event_json() {
+    let event = PeerEvent::Error {
+        task: "accept".to_string(),
+        message: "panicked: boom at src/protocols.rs:12:5".to_string(),
+        backtrace: Some("0: mdns_peer::protocols::...".to_string()),
+    };
+
+    let value: serde_json::Value = serde_json::from_str(&event.to_json()).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "type": "error",
+            "task": "accept",
+            "message": "panicked: boom at src/protocols.rs:12:5",
+            "backtrace": "0: mdns_peer::protocols::...",
+        })
+    );
+}
+
+#[test]
+fn inbound_connection_event_json() {
+    let node_id = SecretKey::from_bytes(&[7; 32]).public();
+    let event = PeerEvent::InboundConnection {
+        node_id,
+        alpn: "app/chat/1".to_string(),
+        trusted: true,
+        accepted: false,
+    };
+
+    let value: serde_json::Value = serde_json::from_str(&event.to_json()).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "type": "inbound_connection",
+            "node_id": node_id.to_string(),
+            "alpn": "app/chat/1",
+            "trusted": true,
+            "accepted": false,
+        })
+    );
+}
+
+#[test]
+fn connection_event_json() {
+    let node_id = SecretKey::from_bytes(&[7; 32]).public();
+    let connected = PeerEvent::Connected {
+        node_id,
+        alpn: "app/chat/1".to_string(),
+        direction: Direction::Outbound,
+        connection: ConnectionReport::Relay {
+            url: "https://relay.example/".to_string(),
+        },
+    };
+    let changed = PeerEvent::PathChanged {
+        node_id,
+        previous: ConnectionReport::Relay {
+            url: "https://relay.example/".to_string(),
+        },
+        current: ConnectionReport::Direct {
+            addr: "192.168.1.20:4433".parse().unwrap(),
+        },
+        previous_interface: Some("pdp_ip0".to_string()),
+        current_interface: Some("en0".to_string()),
+    };
+    let closed = PeerEvent::ConnectionClosed {
+        node_id,
+        alpn: "app/chat/1".to_string(),
+        direction: Direction::Inbound,
+        reason: "timed out".to_string(),
+    };
+
+    let values: Vec<serde_json::Value> = [connected, changed, closed]
+        .iter()
+        .map(|event| serde_json::from_str(&event.to_json()).unwrap())
+        .collect();
+    assert_eq!(
+        values,
+        [
+            json!({
+                "type": "connected",
+                "node_id": node_id.to_string(),
+                "alpn": "app/chat/1",
+                "direction": "outbound",
+                "connection": {"kind": "relay", "url": "https://relay.example/"},
+            }),
+            json!({
+                "type": "path_changed",
+                "node_id": node_id.to_string(),
+                "previous": {"kind": "relay", "url": "https://relay.example/"},
+                "current": {"kind": "direct", "addr": "192.168.1.20:4433"},
+                "previous_interface": "pdp_ip0",
+                "current_interface": "en0",
+            }),
+            json!({
+                "type": "connection_closed",
+                "node_id": node_id.to_string(),
+                "alpn": "app/chat/1",
+                "direction": "inbound",
+                "reason": "timed out",
+            }),
+        ]
+    );
+}
+
+#[test]
+fn reconnect_event_json() {
+    let node_id = SecretKey::from_bytes(&[7; 32]).public();
+    let event = PeerEvent::Reconnect {
+        node_id,
+        connected: false,
+        error: Some("Timed out".to_string()),
+    };
+
+    let value: serde_json::Value = serde_json::from_str(&event.to_json()).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "type": "reconnect",
+            "node_id": node_id.to_string(),
+            "connected": false,
+            "error": "Timed out",
+        })
+    );
+}
+
+#[test]
+fn message_failed_event_json() {
+    let node_id = SecretKey::from_bytes(&[7; 32]).public();
+    let id: MessageId = "67e55044-10b1-426f-9247-bb680e5fe0c8".parse().unwrap();
+    let event = PeerEvent::MessageFailed {
+        id,
+        node_id,
+        reason: "Timed out".to_string(),
+        retrying: true,
+        timed_out: false,
+    };
+
+    let value: serde_json::Value = serde_json::from_str(&event.to_json()).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "type": "message_failed",
+            "id": "67e55044-10b1-426f-9247-bb680e5fe0c8",
+            "node_id": node_id.to_string(),
+            "reason": "Timed out",
+            "retrying": true,
+            "timed_out": false,
+        })
+    );
+}
+
+#[test]
+fn rate_limited_event_json() {
+    let node_id = SecretKey::from_bytes(&[7; 32]).public();
+    let event = PeerEvent::RateLimited {
+        node_id,
+        limit: MessageLimit::Rate,
+        refused: 12,
+    };
+
+    let value: serde_json::Value = serde_json::from_str(&event.to_json()).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "type": "rate_limited",
+            "node_id": node_id.to_string(),
+            "limit": "rate",
+            "refused": 12,
+        })
+    );
+    assert_eq!(event.mask_bit(), event_mask::RATE_LIMITED);
+}
+
+#[test]
+fn over_budget_event_json() {
+    let event = PeerEvent::OverBudget {
+        pool: MemoryPool::QueuedMessages,
+        limit: 1024,
+        dropped: 3,
+    };
+
+    let value: serde_json::Value = serde_json::from_str(&event.to_json()).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "type": "over_budget",
+            "pool": "queued_messages",
+            "limit": 1024,
+            "dropped": 3,
+        })
+    );
+    assert_eq!(event.mask_bit(), event_mask::OVER_BUDGET);
+}
+
+#[test]
+fn flapped_event_json() {
+    let node_id = SecretKey::from_bytes(&[7; 32]).public();
+    let event = PeerEvent::Flapped {
+        node_id,
+        user_data: Some("alice".to_string()),
+        offline_ms: 1200,
+        flaps: 2,
+    };
+
+    let value: serde_json::Value = serde_json::from_str(&event.to_json()).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "type": "flapped",
+            "node_id": node_id.to_string(),
+            "user_data": "alice",
+            "offline_ms": 1200,
+            "flaps": 2,
+        })
+    );
+    assert_eq!(event.mask_bit(), event_mask::FLAPPED);
+}
+
+#[test]
+fn key_rotated_event_json() {
+    let old_node_id = SecretKey::from_bytes(&[7; 32]).public();
+    let new_node_id = SecretKey::from_bytes(&[8; 32]).public();
+    let event = PeerEvent::KeyRotated {
+        old_node_id,
+        new_node_id,
+        trusted: true,
+    };
+
+    let value: serde_json::Value = serde_json::from_str(&event.to_json()).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "type": "key_rotated",
+            "old_node_id": old_node_id.to_string(),
+            "new_node_id": new_node_id.to_string(),
+            "trusted": true,
+        })
+    );
+    assert_eq!(event.mask_bit(), event_mask::KEY_ROTATED);
+}
+
+#[test]
+fn operation_finished_event_json() {
+    let event = PeerEvent::OperationFinished {
+        id: 12,
+        kind: OperationKind::Ping,
+        status: ResultCode::Ok,
+        error: None,
+        rtt_us: Some(1500),
+    };
+
+    let value: serde_json::Value = serde_json::from_str(&event.to_json()).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "type": "operation_finished",
+            "id": 12,
+            "kind": "ping",
+            "status": "ok",
+            "rtt_us": 1500,
+        })
+    );
+    assert_eq!(event.mask_bit(), event_mask::OPERATIONS);
+
+    let cancelled = PeerEvent::OperationFinished {
+        id: 13,
+        kind: OperationKind::Connect,
+        status: ResultCode::Cancelled,
+        error: Some("Cancelled".to_string()),
+        rtt_us: None,
+    };
+    let value: serde_json::Value = serde_json::from_str(&cancelled.to_json()).unwrap();
+    assert_eq!(value["status"], "cancelled");
+    assert_eq!(value["error"], "Cancelled");
+    assert!(value.get("rtt_us").is_none());
+}
+
+#[test]
+fn discovery_mode_changed_event_json() {
+    let event = PeerEvent::DiscoveryModeChanged {
+        mode: DiscoveryMode::Fallback,
+        reason: "local discovery found no peers for 30 s".to_string(),
+    };
+
+    let value: serde_json::Value = serde_json::from_str(&event.to_json()).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "type": "discovery_mode_changed",
+            "mode": "fallback",
+            "reason": "local discovery found no peers for 30 s",
+        })
+    );
+    assert_eq!(event.mask_bit(), event_mask::DISCOVERY_MODE);
+}
+
+#[test]
+fn hotspot_changed_event_json() {
+    let joined = hotspot::detect([("en0", "172.20.10.3".parse().unwrap())])
+        .unwrap()
+        .with_direct_addrs(["172.20.10.3:51234".parse().unwrap()]);
+    let event = PeerEvent::HotspotChanged {
+        hotspot: Some(joined),
+    };
+
+    let value: serde_json::Value = serde_json::from_str(&event.to_json()).unwrap();
+    assert_eq!(value["type"], "hotspot_changed");
+    assert_eq!(value["hotspot"]["role"], "client");
+    assert_eq!(value["hotspot"]["interface"], "en0");
+    assert_eq!(value["hotspot"]["addr"], "172.20.10.3");
+    assert_eq!(value["hotspot"]["host_addr"], "172.20.10.1");
+    assert_eq!(
+        value["hotspot"]["direct_addrs"],
+        json!(["172.20.10.3:51234"])
+    );
+    assert_eq!(event.mask_bit(), event_mask::HOTSPOT);
+
+    let left = PeerEvent::HotspotChanged { hotspot: None };
+    assert_eq!(
+        left.to_json(),
+        r#"{"type":"hotspot_changed","hotspot":null}"#
+    );
+}
+
+#[test]
+fn relay_event_json() {
+    let node_id = SecretKey::from_bytes(&[7; 32]).public();
+    let fallback = PeerEvent::RelayFallback {
+        node_id,
+        url: "https://relay.example.com/".to_string(),
+    };
+    let value: serde_json::Value = serde_json::from_str(&fallback.to_json()).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "type": "relay_fallback",
+            "node_id": node_id.to_string(),
+            "url": "https://relay.example.com/",
+        })
+    );
+
+    let refused = PeerEvent::RelayRefused {
+        node_id,
+        alpn: "mdns-peer/message".to_string(),
+        reason: "timed out".to_string(),
+    };
+    let value: serde_json::Value = serde_json::from_str(&refused.to_json()).unwrap();
+    assert_eq!(value["type"], "relay_refused");
+    assert_eq!(value["reason"], "timed out");
+    assert_eq!(fallback.mask_bit(), event_mask::RELAY);
+    assert_eq!(refused.mask_bit(), event_mask::RELAY);
+}
+
+#[test]
+fn version_mismatch_event_json() {
+    let node_id = SecretKey::from_bytes(&[7; 32]).public();
+    let event = PeerEvent::VersionMismatch {
+        node_id,
+        alpn: "mdns-peer/message".to_string(),
+        local_version: 3,
+        remote_version: Some(4),
+        reason: "too new".to_string(),
+    };
+
+    let value: serde_json::Value = serde_json::from_str(&event.to_json()).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "type": "version_mismatch",
+            "node_id": node_id.to_string(),
+            "alpn": "mdns-peer/message",
+            "local_version": 3,
+            "remote_version": 4,
+            "reason": "too new",
+        })
+    );
+    assert_eq!(event.mask_bit(), event_mask::VERSION_MISMATCH);
+}
+
+#[test]
+fn message_events_share_a_mask_bit() {
+    let node_id = SecretKey::from_bytes(&[7; 32]).public();
+    let id = MessageId::nil();
+    let sent = PeerEvent::MessageSent { id, node_id };
+    let failed = PeerEvent::MessageFailed {
+        id,
+        node_id,
+        reason: String::new(),
+        retrying: false,
+        timed_out: false,
+    };
+    assert_eq!(sent.mask_bit(), event_mask::MESSAGES);
+    assert_eq!(failed.mask_bit(), event_mask::MESSAGES);
+}
+
+#[test]
+fn event_mask_bits_are_distinct() {
+    let node_id = SecretKey::from_bytes(&[7; 32]).public();
+    let events = [
+        PeerEvent::Discovered {
+            node_id,
+            user_data: None,
+            provenance: "mdns",
+            origin: DiscoveryOrigin::default(),
+        },
+        PeerEvent::Expired {
+            node_id,
+            user_data: None,
+        },
+        PeerEvent::status(PeerStatus::Running),
+        PeerEvent::Summary {
+            routing_table_size: 0,
+            peers: Vec::new(),
+        },
+        PeerEvent::LocalAddrsChanged {
+            previous: LocalAddrs {
+                direct_addrs: Vec::new(),
+                home_relay: Vec::new(),
+            },
+            current: LocalAddrs {
+                direct_addrs: Vec::new(),
+                home_relay: Vec::new(),
+            },
+        },
+        PeerEvent::InboundConnection {
+            node_id,
+            alpn: "app/chat/1".to_string(),
+            trusted: false,
+            accepted: true,
+        },
+        PeerEvent::Connected {
+            node_id,
+            alpn: "app/chat/1".to_string(),
+            direction: Direction::Inbound,
+            connection: ConnectionReport::None,
+        },
+        PeerEvent::PathChanged {
+            node_id,
+            previous: ConnectionReport::None,
+            current: ConnectionReport::None,
+            previous_interface: None,
+            current_interface: None,
+        },
+        PeerEvent::ConnectionClosed {
+            node_id,
+            alpn: "app/chat/1".to_string(),
+            direction: Direction::Inbound,
+            reason: "timed out".to_string(),
+        },
+        PeerEvent::Reconnect {
+            node_id,
+            connected: true,
+            error: None,
+        },
+        PeerEvent::MessageDelivered {
+            id: MessageId::nil(),
+            node_id,
+        },
+        PeerEvent::Error {
+            task: "discovery".to_string(),
+            message: "panicked: boom".to_string(),
+            backtrace: None,
+        },
+        PeerEvent::RelayFallback {
+            node_id,
+            url: String::new(),
+        },
+        PeerEvent::Flapped {
+            node_id,
+            user_data: None,
+            offline_ms: 0,
+            flaps: 1,
+        },
+        PeerEvent::KeyRotated {
+            old_node_id: node_id,
+            new_node_id: node_id,
+            trusted: false,
+        },
+        PeerEvent::OperationFinished {
+            id: 1,
+            kind: OperationKind::Connect,
+            status: ResultCode::Ok,
+            error: None,
+            rtt_us: None,
+        },
+        PeerEvent::DiscoveryModeChanged {
+            mode: DiscoveryMode::Local,
+            reason: "starting with local discovery".to_string(),
+        },
+        PeerEvent::HotspotChanged { hotspot: None },
+    ];
+
+    let mut seen = 0;
+    for event in &events {
+        let bit = event.mask_bit();
+        assert_eq!(bit.count_ones(), 1);
+        assert_eq!(seen & bit, 0, "{:?} shares a mask bit", event);
+        assert_ne!(event_mask::ALL & bit, 0);
+        seen |= bit;
+    }
+}
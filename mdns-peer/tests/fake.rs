@@ -0,0 +1,57 @@
+//! Synthetic peer fleet used for UI development
+#![cfg(feature = "cli")]
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use iroh::discovery::DiscoveryEvent;
+use mdns_peer::fake::{FakeFleet, FakeOptions};
+use n0_future::StreamExt;
+
+const DISCOVERY_DEADLINE: Duration = Duration::from_secs(30);
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fleet_is_discovered_and_rotates() -> anyhow::Result<()> {
+    let options = FakeOptions {
+        count: 3,
+        prefix: "ui-fake".to_string(),
+        rotate_interval: None,
+    };
+    let mut fleet = FakeFleet::start(&options).await?;
+    let started: HashMap<_, _> = fleet.online().into_iter().collect();
+    assert_eq!(started.len(), 3);
+
+    let observer = mdns_peer::bind_endpoint("fake-observer").await?;
+    let mut events = observer.discovery_stream();
+    let mut seen = HashMap::new();
+    tokio::time::timeout(DISCOVERY_DEADLINE, async {
+        while seen.len() < 3 {
+            let Some(Ok(DiscoveryEvent::Discovered(item))) = events.next().await else {
+                continue;
+            };
+            if let Some(user_data) = item.node_info().data.user_data() {
+                if user_data.to_string().starts_with("ui-fake-") {
+                    seen.insert(user_data.to_string(), item.node_id());
+                }
+            }
+        }
+    })
+    .await?;
+    assert_eq!(seen, started);
+
+    // One of three leaves, then it returns with the same node ID as another leaves
+    fleet.rotate().await?;
+    let first = fleet.online();
+    assert_eq!(first.len(), 2);
+    fleet.rotate().await?;
+    let second = fleet.online();
+    assert_eq!(second.len(), 2);
+    assert_ne!(first, second);
+    for (user_data, node_id) in second {
+        assert_eq!(started[&user_data], node_id);
+    }
+
+    fleet.close().await;
+    observer.close().await;
+    Ok(())
+}
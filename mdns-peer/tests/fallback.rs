@@ -0,0 +1,83 @@
+//! Switching between local discovery and the DNS fallback
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::node;
+use iroh::discovery::{Discovery, DiscoveryEvent, DiscoveryItem, NodeInfo};
+use mdns_peer::discovery;
+use mdns_peer::fallback::{DiscoveryMode, DnsFallback};
+use mdns_peer::PeerEvent;
+use n0_future::{stream, StreamExt};
+
+mod common;
+
+const WINDOW: Duration = Duration::from_secs(30);
+
+fn discovered(seed: u8, provenance: &'static str) -> DiscoveryEvent {
+    DiscoveryEvent::Discovered(DiscoveryItem::new(
+        NodeInfo::new(node(seed)),
+        provenance,
+        None,
+    ))
+}
+
+#[test]
+fn falls_back_after_the_window_and_returns_when_a_local_peer_shows_up() {
+    let fallback = DnsFallback::new(WINDOW);
+    assert_eq!(fallback.mode(), DiscoveryMode::Local);
+
+    assert_eq!(fallback.check(false, WINDOW / 2), None);
+    assert_eq!(fallback.check(true, WINDOW * 2), None);
+
+    let Some(PeerEvent::DiscoveryModeChanged { mode, reason }) = fallback.check(false, WINDOW)
+    else {
+        panic!("expected a switch to the fallback");
+    };
+    assert_eq!(mode, DiscoveryMode::Fallback);
+    assert_eq!(reason, "local discovery found no peers for 30 s");
+    assert_eq!(fallback.mode(), DiscoveryMode::Fallback);
+    assert_eq!(fallback.check(false, WINDOW * 2), None);
+
+    let event = fallback.check(true, Duration::ZERO);
+    assert!(matches!(
+        event,
+        Some(PeerEvent::DiscoveryModeChanged {
+            mode: DiscoveryMode::Local,
+            ..
+        })
+    ));
+    assert_eq!(fallback.clone().mode(), DiscoveryMode::Local);
+}
+
+#[test]
+fn resolves_nothing_in_local_mode() {
+    let fallback = DnsFallback::new(WINDOW);
+    assert!(fallback.resolve(node(1)).is_none());
+}
+
+#[tokio::test]
+async fn merged_events_are_not_counted_as_local_traffic() {
+    let counter = Arc::new(AtomicU64::new(0));
+    // A live source only ends with the endpoint
+    let local = discovery::scripted_source([discovered(1, "mdns"), discovered(2, "mdns")])
+        .chain(stream::pending());
+    let source = discovery::count_events(Box::pin(local), counter.clone());
+    let extra = Box::pin(stream::iter([discovered(3, "dns")]));
+
+    let mut events: Vec<_> = discovery::merge_events(source, extra)
+        .take(3)
+        .map(|event| match event.unwrap() {
+            DiscoveryEvent::Discovered(item) => item.node_id(),
+            DiscoveryEvent::Expired(node_id) => node_id,
+        })
+        .collect()
+        .await;
+    events.sort();
+    let mut expected = vec![node(1), node(2), node(3)];
+    expected.sort();
+
+    assert_eq!(events, expected);
+    assert_eq!(counter.load(Ordering::Relaxed), 2);
+}
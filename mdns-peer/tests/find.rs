@@ -0,0 +1,66 @@
+//! Finding discovered peers by name
+
+use common::node;
+use iroh::discovery::{DiscoveryEvent, DiscoveryItem, NodeInfo};
+use iroh::NodeId;
+use mdns_peer::find::{find_peers, PeerPattern};
+use mdns_peer::notes::PeerNotes;
+use mdns_peer::registry::PeerRegistry;
+
+mod common;
+
+fn discovered(node_id: NodeId, user_data: &str) -> DiscoveryEvent {
+    let info = NodeInfo::new(node_id).with_user_data(Some(user_data.parse().unwrap()));
+    DiscoveryEvent::Discovered(DiscoveryItem::new(info, "mdns", None))
+}
+
+#[test]
+fn patterns_without_wildcards_match_prefixes() {
+    let pattern = PeerPattern::new("Kitchen");
+    assert!(pattern.matches("kitchen-ipad"));
+    assert!(pattern.matches("KITCHEN"));
+    assert!(!pattern.matches("my-kitchen"));
+    assert!(PeerPattern::new("").matches("anything"));
+}
+
+#[test]
+fn globs_match_whole_names() {
+    let pattern = PeerPattern::new("*-ipad");
+    assert!(pattern.matches("kitchen-ipad"));
+    assert!(pattern.matches("-iPad"));
+    assert!(!pattern.matches("kitchen-ipad-2"));
+
+    let pattern = PeerPattern::new("studio-?-*mac*");
+    assert!(pattern.matches("studio-1-macbook"));
+    assert!(pattern.matches("studio-2-old-imac"));
+    assert!(!pattern.matches("studio-12-mac"));
+    assert!(PeerPattern::new("*").matches(""));
+}
+
+#[test]
+fn peers_match_by_user_data_or_alias() -> anyhow::Result<()> {
+    let (ipad, mac, phone) = (node(2), node(3), node(4));
+    let mut registry = PeerRegistry::new();
+    registry.apply(&discovered(ipad, "kitchen-ipad"));
+    registry.apply(&discovered(mac, "kitchen-mac"));
+    registry.apply(&discovered(phone, "Jamie's iPhone"));
+    let notes = PeerNotes::default();
+    notes.set_alias(phone, Some("Kitchen phone"))?;
+
+    let found = find_peers(&registry, &notes, &PeerPattern::new("kitchen"));
+    let names: Vec<_> = found.iter().map(|p| p.user_data.as_deref()).collect();
+    assert_eq!(
+        names,
+        [
+            Some("Jamie's iPhone"),
+            Some("kitchen-ipad"),
+            Some("kitchen-mac")
+        ]
+    );
+    assert_eq!(found[0].alias.as_deref(), Some("Kitchen phone"));
+
+    let found = find_peers(&registry, &notes, &PeerPattern::new("*-mac"));
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].node_id, mac);
+    Ok(())
+}
@@ -0,0 +1,74 @@
+//! Named groups of peers
+
+use std::collections::BTreeSet;
+
+use common::node;
+use mdns_peer::groups::{PeerGroups, MAX_GROUP_NAME_LEN};
+use mdns_peer::messages::{Messages, MAX_MESSAGE_SIZE};
+
+mod common;
+
+#[test]
+fn groups_persist_in_the_state_dir() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let groups = PeerGroups::open(dir.path())?;
+    groups.create(" My devices ")?;
+    groups.create("Family")?;
+    groups.add("My devices", node(1))?;
+    groups.add("My devices", node(2))?;
+    groups.add("Family", node(2))?;
+
+    let reopened = PeerGroups::open(dir.path())?;
+    assert_eq!(
+        reopened.members("My devices"),
+        Some([node(1), node(2)].into())
+    );
+    assert_eq!(reopened.groups_of(node(2)), ["Family", "My devices"]);
+    assert!(reopened.groups_of(node(3)).is_empty());
+    assert!(dir.path().join("groups.json").exists());
+
+    // Creating it again keeps the members
+    reopened.create("My devices")?;
+    assert_eq!(reopened.members("My devices").map(|m| m.len()), Some(2));
+    Ok(())
+}
+
+#[test]
+fn members_need_a_group_and_names_are_checked() -> anyhow::Result<()> {
+    let groups = PeerGroups::default();
+    assert!(groups.add("Nowhere", node(1)).is_err());
+    assert!(groups.create("  ").is_err());
+    assert!(groups.create(&"x".repeat(MAX_GROUP_NAME_LEN + 1)).is_err());
+
+    groups.create("Work")?;
+    groups.add("Work", node(1))?;
+    assert!(groups.remove("Work", node(1))?);
+    assert!(!groups.remove("Work", node(1))?);
+    assert!(groups.delete("Work")?);
+    assert!(!groups.delete("Work")?);
+    assert!(groups.all().is_empty());
+    Ok(())
+}
+
+#[test]
+fn sending_to_a_group_queues_a_message_per_member() -> anyhow::Result<()> {
+    let groups = PeerGroups::default();
+    let messages = Messages::default();
+    groups.create("My devices")?;
+    groups.add("My devices", node(1))?;
+    groups.add("My devices", node(2))?;
+
+    let sent = groups.send(&messages, "My devices", b"hello")?;
+    assert_eq!(
+        sent.keys().copied().collect::<BTreeSet<_>>(),
+        [node(1), node(2)].into()
+    );
+    assert_ne!(sent[&node(1)], sent[&node(2)]);
+    assert_eq!(messages.queue_depths(), [(node(1), 1), (node(2), 1)].into());
+
+    assert!(groups.send(&messages, "Nowhere", b"hello").is_err());
+    let too_large = vec![0; MAX_MESSAGE_SIZE + 1];
+    assert!(groups.send(&messages, "My devices", &too_large).is_err());
+    assert_eq!(messages.queue_depths().values().sum::<usize>(), 2);
+    Ok(())
+}
@@ -0,0 +1,165 @@
+//! gRPC control plane served by `mdns-peer daemon --grpc`
+#![cfg(feature = "grpc")]
+
+use std::time::Duration;
+
+use common::node;
+use iroh::NodeId;
+use mdns_peer::dashboard::Dashboard;
+use mdns_peer::events::DiscoveryOrigin;
+use mdns_peer::grpc::proto::peer_control_client::PeerControlClient;
+use mdns_peer::grpc::proto::{
+    ConnectRequest, GetStatsRequest, ListPeersRequest, SendMessageRequest, StreamEventsRequest,
+};
+use mdns_peer::grpc::ControlService;
+use mdns_peer::messages::MAX_MESSAGE_SIZE;
+use mdns_peer::{PeerEvent, PeerOptions};
+use tonic::transport::Channel;
+use tonic::Code;
+
+mod common;
+
+const DEADLINE: Duration = Duration::from_secs(5);
+
+fn discovered(node_id: NodeId, user_data: &str) -> PeerEvent {
+    PeerEvent::Discovered {
+        node_id,
+        user_data: Some(user_data.to_string()),
+        provenance: "mdns",
+        origin: DiscoveryOrigin::default(),
+    }
+}
+
+/// A service for a peer that isn't running, and a client connected to it
+async fn start() -> anyhow::Result<(Dashboard, PeerControlClient<Channel>)> {
+    let options = PeerOptions::default();
+    let dashboard = Dashboard::new(options.protocols.clone());
+    let addr = ControlService::new(dashboard.clone(), &options)
+        .spawn("127.0.0.1:0".parse()?)
+        .await?;
+    let channel = Channel::from_shared(format!("http://{}", addr))?
+        .connect()
+        .await?;
+    let client = PeerControlClient::new(channel);
+    Ok((dashboard, client))
+}
+
+#[tokio::test]
+async fn lists_discovered_peers() -> anyhow::Result<()> {
+    let (dashboard, mut client) = start().await?;
+    dashboard.record(&discovered(node(2), "bob"));
+    dashboard.record(&discovered(node(1), "alice"));
+
+    let peers = client
+        .list_peers(ListPeersRequest {})
+        .await?
+        .into_inner()
+        .peers;
+    let ids: Vec<_> = peers.iter().map(|p| p.node_id.clone()).collect();
+    let mut expected = [node(1).to_string(), node(2).to_string()];
+    expected.sort();
+    assert_eq!(ids, expected);
+    let alice = peers
+        .iter()
+        .find(|p| p.node_id == node(1).to_string())
+        .unwrap();
+    assert_eq!(alice.user_data.as_deref(), Some("alice"));
+    assert_eq!(alice.provenance.as_deref(), Some("mdns"));
+    assert_eq!(alice.connection.as_ref().unwrap().kind, "none");
+    Ok(())
+}
+
+#[tokio::test]
+async fn streams_the_log_then_new_events() -> anyhow::Result<()> {
+    let (dashboard, mut client) = start().await?;
+    dashboard.record(&discovered(node(1), "alice"));
+
+    let mut events = client
+        .stream_events(StreamEventsRequest::default())
+        .await?
+        .into_inner();
+    let first = tokio::time::timeout(DEADLINE, events.message())
+        .await??
+        .unwrap();
+    assert_eq!(first.r#type, "discovered");
+    assert!(first.json.contains("\"user_data\":\"alice\""));
+    assert!(first.wall_ms > 0);
+
+    dashboard.record(&PeerEvent::Expired {
+        node_id: node(1),
+        user_data: Some("alice".to_string()),
+    });
+    let next = tokio::time::timeout(DEADLINE, events.message())
+        .await??
+        .unwrap();
+    assert_eq!(next.r#type, "expired");
+
+    // Without the backlog only later events arrive
+    let mut later = client
+        .stream_events(StreamEventsRequest { skip_backlog: true })
+        .await?
+        .into_inner();
+    dashboard.record(&discovered(node(2), "bob"));
+    let event = tokio::time::timeout(DEADLINE, later.message())
+        .await??
+        .unwrap();
+    assert!(event.json.contains("\"user_data\":\"bob\""));
+    Ok(())
+}
+
+#[tokio::test]
+async fn rejects_bad_requests() -> anyhow::Result<()> {
+    let (_dashboard, mut client) = start().await?;
+
+    let invalid = client
+        .connect(ConnectRequest {
+            node_id: "not-a-node".to_string(),
+            timeout_ms: None,
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(invalid.code(), Code::InvalidArgument);
+
+    let not_running = client
+        .connect(ConnectRequest {
+            node_id: node(1).to_string(),
+            timeout_ms: Some(100),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(not_running.code(), Code::Unavailable);
+
+    let too_large = client
+        .send_message(SendMessageRequest {
+            node_id: node(1).to_string(),
+            data: vec![0; MAX_MESSAGE_SIZE + 1],
+            sealed: false,
+            timeout_ms: None,
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(too_large.code(), Code::ResourceExhausted);
+    Ok(())
+}
+
+#[tokio::test]
+async fn queues_messages_and_reports_stats() -> anyhow::Result<()> {
+    let (_dashboard, mut client) = start().await?;
+
+    let sent = client
+        .send_message(SendMessageRequest {
+            node_id: node(1).to_string(),
+            data: b"hello".to_vec(),
+            sealed: false,
+            timeout_ms: Some(1000),
+        })
+        .await?
+        .into_inner();
+    assert!(sent.message_id.parse::<uuid::Uuid>().is_ok());
+
+    let stats = client.get_stats(GetStatsRequest {}).await?.into_inner();
+    assert_eq!(stats.window_secs, 10);
+    assert!(stats.peers.is_empty());
+    assert!(stats.transfers.is_empty());
+    Ok(())
+}
@@ -0,0 +1,87 @@
+//! Session summaries kept across runs
+
+use common::node;
+use mdns_peer::events::DiscoveryOrigin;
+use mdns_peer::history::{SessionHistory, MAX_SESSIONS};
+use mdns_peer::PeerEvent;
+
+mod common;
+
+fn discovered(seed: u8) -> PeerEvent {
+    PeerEvent::Discovered {
+        node_id: node(seed),
+        user_data: None,
+        provenance: "mdns",
+        origin: DiscoveryOrigin::default(),
+    }
+}
+
+#[test]
+fn sessions_are_counted_and_saved() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let history = SessionHistory::open(dir.path())?;
+    assert!(history.sessions().is_empty());
+
+    history.begin("alice", node(1));
+    for event in [
+        discovered(2),
+        discovered(2),
+        discovered(3),
+        PeerEvent::Reconnect {
+            node_id: node(2),
+            connected: true,
+            error: None,
+        },
+        PeerEvent::Reconnect {
+            node_id: node(3),
+            connected: false,
+            error: Some("timed out".to_string()),
+        },
+        PeerEvent::Error {
+            task: "discovery".to_string(),
+            message: "socket closed".to_string(),
+            backtrace: None,
+        },
+    ] {
+        history.peer_event(&event);
+    }
+    // Running sessions are on disk before they end
+    let running = SessionHistory::open(dir.path())?.sessions();
+    assert_eq!(running.len(), 1);
+    assert!(!running[0].ended);
+
+    history.end();
+    let sessions = SessionHistory::open(dir.path())?.sessions();
+    assert_eq!(sessions.len(), 1);
+    let session = &sessions[0];
+    assert_eq!(
+        (session.identifier.as_str(), session.node_id),
+        ("alice", node(1))
+    );
+    assert_eq!(session.peers_discovered, 2);
+    assert_eq!((session.reconnects, session.reconnects_failed), (2, 1));
+    assert_eq!(session.errors, 1);
+    assert_eq!(
+        session.last_error.as_deref(),
+        Some("discovery: socket closed")
+    );
+    assert!(session.ended);
+
+    // Events between sessions count towards nothing
+    history.peer_event(&discovered(4));
+    assert_eq!(history.sessions()[0].peers_discovered, 2);
+    Ok(())
+}
+
+#[test]
+fn only_the_newest_sessions_are_kept() {
+    let history = SessionHistory::default();
+    for _ in 0..MAX_SESSIONS + 5 {
+        history.begin("alice", node(1));
+    }
+    history.end();
+    let sessions = history.sessions();
+    assert_eq!(sessions.len(), MAX_SESSIONS);
+    // Starting a session ends the previous one
+    assert!(sessions.iter().all(|session| session.ended));
+}
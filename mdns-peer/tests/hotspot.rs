@@ -0,0 +1,49 @@
+//! Recognising an iPhone's Personal Hotspot
+
+use std::net::{Ipv4Addr, SocketAddr};
+
+use mdns_peer::hotspot::{self, HotspotRole, HOTSPOT_HOST};
+
+fn ip(addr: &str) -> Ipv4Addr {
+    addr.parse().unwrap()
+}
+
+#[test]
+fn the_phone_is_the_host_on_its_bridge() {
+    let hotspot = hotspot::detect([
+        ("pdp_ip0", ip("10.64.12.9")),
+        ("bridge100", ip("172.20.10.1")),
+    ])
+    .unwrap();
+    assert_eq!(hotspot.role, HotspotRole::Host);
+    assert_eq!(hotspot.interface, "bridge100");
+    assert_eq!(hotspot.addr, HOTSPOT_HOST);
+    assert!(hotspot.hint.contains("bridge100"));
+}
+
+#[test]
+fn a_device_that_joined_is_a_client() {
+    let hotspot = hotspot::detect([("en0", ip("172.20.10.3")), ("utun3", ip("10.8.0.2"))])
+        .unwrap()
+        .with_direct_addrs([
+            "10.8.0.2:51234".parse().unwrap(),
+            "172.20.10.3:51234".parse().unwrap(),
+            "[::ffff:172.20.10.3]:51235".parse().unwrap(),
+        ]);
+    assert_eq!(hotspot.role, HotspotRole::Client);
+    assert_eq!(hotspot.interface, "en0");
+    assert_eq!(hotspot.host_addr, HOTSPOT_HOST);
+    let on_hotspot: Vec<SocketAddr> = ["172.20.10.3:51234", "[::ffff:172.20.10.3]:51235"]
+        .iter()
+        .map(|addr| addr.parse().unwrap())
+        .collect();
+    assert_eq!(hotspot.direct_addrs, on_hotspot);
+}
+
+#[test]
+fn other_networks_are_not_a_hotspot() {
+    assert!(hotspot::detect([("en0", ip("192.168.1.23"))]).is_none());
+    // Just outside 172.20.10.0/28
+    assert!(hotspot::detect([("en0", ip("172.20.10.16"))]).is_none());
+    assert!(hotspot::on_hotspot(ip("172.20.10.14")));
+}
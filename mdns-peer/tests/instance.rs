@@ -0,0 +1,51 @@
+//! Claiming an identifier on this machine
+
+use mdns_peer::instance::{claim, AlreadyRunning, DuplicatePolicy};
+
+#[test]
+fn second_claim_is_refused_until_the_first_is_dropped() -> anyhow::Result<()> {
+    let first = claim("instance-refuse", DuplicatePolicy::Refuse, false)?;
+    assert_eq!(first.identifier(), "instance-refuse");
+
+    let err = claim("instance-refuse", DuplicatePolicy::Refuse, false).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<AlreadyRunning>(),
+        Some(&AlreadyRunning {
+            identifier: "instance-refuse".to_string()
+        })
+    );
+
+    drop(first);
+    claim("instance-refuse", DuplicatePolicy::Refuse, false)?;
+    Ok(())
+}
+
+#[test]
+fn suffix_picks_the_next_free_identifier() -> anyhow::Result<()> {
+    let first = claim("instance-suffix", DuplicatePolicy::Suffix, false)?;
+    let second = claim("instance-suffix", DuplicatePolicy::Suffix, false)?;
+    let third = claim("instance-suffix", DuplicatePolicy::Suffix, false)?;
+    assert_eq!(first.identifier(), "instance-suffix");
+    assert_eq!(second.identifier(), "instance-suffix-2");
+    assert_eq!(third.identifier(), "instance-suffix-3");
+
+    // A fixed key keeps the node ID, so suffixing wouldn't help
+    assert!(claim("instance-suffix", DuplicatePolicy::Suffix, true).is_err());
+    Ok(())
+}
+
+#[test]
+fn allow_skips_the_check() -> anyhow::Result<()> {
+    let _first = claim("instance-allow", DuplicatePolicy::Refuse, false)?;
+    let second = claim("instance-allow", DuplicatePolicy::Allow, false)?;
+    assert_eq!(second.identifier(), "instance-allow");
+    Ok(())
+}
+
+#[test]
+fn identifiers_that_are_not_file_names_can_be_claimed() -> anyhow::Result<()> {
+    let _a = claim("../Ünïcode peer/", DuplicatePolicy::Refuse, false)?;
+    let _b = claim("../Unicode peer/", DuplicatePolicy::Refuse, false)?;
+    assert!(claim("../Ünïcode peer/", DuplicatePolicy::Refuse, false).is_err());
+    Ok(())
+}
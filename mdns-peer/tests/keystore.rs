@@ -0,0 +1,74 @@
+//! Profile keys kept in a secure store instead of the state directory
+
+use std::fs;
+use std::sync::Arc;
+
+use mdns_peer::keystore::{profile_account, MemorySecretStore, SecretStore};
+use mdns_peer::profile::ProfileStore;
+
+#[test]
+fn keys_stay_out_of_profile_files() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let secrets = MemorySecretStore::default();
+    let store = ProfileStore::new(dir.path()).with_secret_store(Arc::new(secrets.clone()));
+
+    let created = store.load_or_create("work", None)?;
+    let file = fs::read_to_string(dir.path().join("profiles").join("work.json"))?;
+    assert!(!file.contains("secret_key"));
+    assert_eq!(
+        secrets.get(&profile_account("work"))?,
+        Some(created.secret_key.to_bytes().to_vec())
+    );
+
+    let loaded = store.load_or_create("work", None)?;
+    assert_eq!(loaded.secret_key.public(), created.secret_key.public());
+    Ok(())
+}
+
+#[test]
+fn keys_in_files_move_into_the_store() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let created = ProfileStore::new(dir.path()).load_or_create("home", Some("alice"))?;
+
+    let secrets = MemorySecretStore::default();
+    let store = ProfileStore::new(dir.path()).with_secret_store(Arc::new(secrets.clone()));
+    let loaded = store.load_or_create("home", None)?;
+    assert_eq!(loaded.secret_key.public(), created.secret_key.public());
+    assert_eq!(loaded.user_data, "alice");
+
+    let file = fs::read_to_string(dir.path().join("profiles").join("home.json"))?;
+    assert!(!file.contains("secret_key"));
+    assert!(secrets.get(&profile_account("home"))?.is_some());
+    Ok(())
+}
+
+#[test]
+fn profiles_without_their_key_fail_to_load() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let store =
+        ProfileStore::new(dir.path()).with_secret_store(Arc::new(MemorySecretStore::default()));
+    store.load_or_create("work", None)?;
+
+    // Another (empty) store, or none at all
+    let other =
+        ProfileStore::new(dir.path()).with_secret_store(Arc::new(MemorySecretStore::default()));
+    assert!(other.load_or_create("work", None).is_err());
+    assert!(ProfileStore::new(dir.path())
+        .load_or_create("work", None)
+        .is_err());
+    Ok(())
+}
+
+#[test]
+fn a_key_stored_before_a_crash_is_reused() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let secrets = MemorySecretStore::default();
+    let store = ProfileStore::new(dir.path()).with_secret_store(Arc::new(secrets.clone()));
+    let created = store.load_or_create("work", None)?;
+
+    // The key reached the store but the profile file didn't
+    fs::remove_file(dir.path().join("profiles").join("work.json"))?;
+    let loaded = store.load_or_create("work", None)?;
+    assert_eq!(loaded.secret_key.public(), created.secret_key.public());
+    Ok(())
+}
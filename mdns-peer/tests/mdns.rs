@@ -0,0 +1,141 @@
+//! Tuned local discovery settings
+//!
+//! The discovery tests bind endpoints in one process, like
+//! `tests/discovery.rs`, and need multicast to work on the machine.
+
+use std::time::Duration;
+
+use iroh::{discovery::DiscoveryEvent, Endpoint, NodeId};
+use mdns_peer::mdns::MdnsOptions;
+use mdns_peer::registry::PeerRegistry;
+use mdns_peer::PeerOptions;
+use n0_future::StreamExt;
+
+const DISCOVERY_DEADLINE: Duration = Duration::from_secs(30);
+
+fn tuned(service_name: &str) -> PeerOptions {
+    PeerOptions {
+        mdns: MdnsOptions {
+            service_name: service_name.to_string(),
+            cadence: Duration::from_millis(500),
+            response_rate: 4.0,
+        },
+        ..PeerOptions::default()
+    }
+}
+
+/// Wait until `endpoint` discovers `target`, returning the advertised user data
+async fn wait_for_peer(endpoint: &Endpoint, target: NodeId) -> Option<String> {
+    let mut events = endpoint.discovery_stream();
+    while let Some(event) = events.next().await {
+        if let Ok(DiscoveryEvent::Discovered(item)) = event {
+            if item.node_id() == target {
+                return item
+                    .node_info()
+                    .data
+                    .user_data()
+                    .map(|data| data.to_string());
+            }
+        }
+    }
+    None
+}
+
+#[test]
+fn defaults_match_iroh_and_validate() {
+    let options = MdnsOptions::default();
+    assert!(options.is_default());
+    assert!(options.validate().is_ok());
+    assert_eq!(options.responses_per_cadence(), 2);
+}
+
+#[test]
+fn invalid_settings_are_rejected() {
+    let cases = [
+        MdnsOptions {
+            service_name: String::new(),
+            ..MdnsOptions::default()
+        },
+        MdnsOptions {
+            service_name: "has spaces".to_string(),
+            ..MdnsOptions::default()
+        },
+        MdnsOptions {
+            cadence: Duration::ZERO,
+            ..MdnsOptions::default()
+        },
+        // 0.2s x 2 Hz is less than one response per cycle
+        MdnsOptions {
+            cadence: Duration::from_millis(200),
+            response_rate: 2.0,
+            ..MdnsOptions::default()
+        },
+        MdnsOptions {
+            response_rate: f32::NAN,
+            ..MdnsOptions::default()
+        },
+    ];
+    for options in cases {
+        assert!(options.validate().is_err(), "{:?} validated", options);
+    }
+}
+
+#[test]
+fn registry_expiry_follows_the_cadence() {
+    let slow = MdnsOptions {
+        cadence: Duration::from_secs(10),
+        response_rate: 1.0,
+        ..MdnsOptions::default()
+    };
+    // Alone in the swarm: expected every 10s, expired after missing three
+    assert_eq!(
+        PeerRegistry::for_mdns(&slow).record_ttl(),
+        Duration::from_secs(30)
+    );
+    assert_eq!(
+        PeerRegistry::new().record_ttl(),
+        PeerRegistry::for_mdns(&MdnsOptions::default()).record_ttl()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tuned_endpoints_discover_each_other() -> anyhow::Result<()> {
+    let options = tuned("mdns-peer-test");
+    let alice = mdns_peer::bind_endpoint_with("tuned-alice", &options).await?;
+    let bob = mdns_peer::bind_endpoint_with("tuned-bob", &options).await?;
+
+    let (alice_saw, bob_saw) = tokio::time::timeout(DISCOVERY_DEADLINE, async {
+        tokio::join!(
+            wait_for_peer(&alice, bob.node_id()),
+            wait_for_peer(&bob, alice.node_id()),
+        )
+    })
+    .await?;
+    assert_eq!(alice_saw.as_deref(), Some("tuned-bob"));
+    assert_eq!(bob_saw.as_deref(), Some("tuned-alice"));
+
+    alice.close().await;
+    bob.close().await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn tuned_endpoint_interoperates_with_iroh_discovery() -> anyhow::Result<()> {
+    let options = tuned(&MdnsOptions::default().service_name);
+    let tuned = mdns_peer::bind_endpoint_with("interop-tuned", &options).await?;
+    let stock = mdns_peer::bind_endpoint("interop-stock").await?;
+
+    let (tuned_saw, stock_saw) = tokio::time::timeout(DISCOVERY_DEADLINE, async {
+        tokio::join!(
+            wait_for_peer(&tuned, stock.node_id()),
+            wait_for_peer(&stock, tuned.node_id()),
+        )
+    })
+    .await?;
+    assert_eq!(tuned_saw.as_deref(), Some("interop-stock"));
+    assert_eq!(stock_saw.as_deref(), Some("interop-tuned"));
+
+    tuned.close().await;
+    stock.close().await;
+    Ok(())
+}
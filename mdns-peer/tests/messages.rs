@@ -0,0 +1,364 @@
+//! Whole messages, delivery receipts and the offline queue
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::{node, Side, DEADLINE};
+use iroh::NodeId;
+use mdns_peer::budget::{DropPolicy, MemoryBudget};
+use mdns_peer::events::{DiscoveryOrigin, MessageLimit};
+use mdns_peer::messages::{
+    MessageHandler, MessageId, MessageLimits, Messages, MAX_MESSAGE_SIZE, MAX_QUEUED_MESSAGES,
+};
+use mdns_peer::PeerEvent;
+use tokio::sync::mpsc;
+
+mod common;
+
+type Received = (NodeId, MessageId, Vec<u8>);
+
+struct Inbox(mpsc::UnboundedSender<Received>);
+
+impl MessageHandler for Inbox {
+    fn on_message(&self, node_id: NodeId, id: MessageId, data: &[u8]) {
+        let _ = self.0.send((node_id, id, data.to_vec()));
+    }
+}
+
+/// A [`Side`] receiving `messages`, with the sealed ones kept apart
+struct Peer {
+    side: Side<Received>,
+    messages: Messages,
+    sealed_inbox: mpsc::UnboundedReceiver<Received>,
+}
+
+impl Peer {
+    /// A peer with `seed` as its key, reachable only through addresses
+    /// added by hand
+    async fn start(seed: u8, messages: Messages) -> anyhow::Result<Self> {
+        let (tx, sealed_inbox) = mpsc::unbounded_channel();
+        messages.set_sealed_handler(Some(Arc::new(Inbox(tx))));
+        let side = Side::local(Some(seed), |protocols, tx| {
+            messages.set_handler(Some(Arc::new(Inbox(tx))));
+            messages.attach(protocols);
+        })
+        .await?;
+        messages.flush_all();
+        Ok(Self {
+            side,
+            messages,
+            sealed_inbox,
+        })
+    }
+
+    async fn learn(&self, other: &Peer) -> anyhow::Result<()> {
+        self.side.learn(&other.side).await
+    }
+
+    async fn next_message(&mut self) -> anyhow::Result<Received> {
+        self.side.next_received().await
+    }
+
+    /// The next message progress event, skipping the rest
+    async fn next_message_event(&mut self) -> anyhow::Result<PeerEvent> {
+        self.side
+            .next_event(|event| {
+                matches!(
+                    event,
+                    PeerEvent::MessageSent { .. }
+                        | PeerEvent::MessageDelivered { .. }
+                        | PeerEvent::MessageFailed { .. }
+                )
+            })
+            .await
+    }
+}
+
+#[test]
+fn sends_are_queued_until_the_peer_runs() {
+    let messages = Messages::default();
+    let node_id = node(1);
+    for i in 0..MAX_QUEUED_MESSAGES {
+        messages.send(node_id, vec![i as u8]).unwrap();
+    }
+    assert!(messages.send(node_id, b"one too many".to_vec()).is_err());
+    assert!(messages
+        .send(node_id, vec![0; MAX_MESSAGE_SIZE + 1])
+        .is_err());
+    assert_eq!(
+        messages.queue_depths().into_iter().collect::<Vec<_>>(),
+        [(node_id, MAX_QUEUED_MESSAGES)]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn queued_messages_are_delivered_in_order_once_running() -> anyhow::Result<()> {
+    let mut alice = Peer::start(1, Messages::default()).await?;
+    let messages = Messages::default();
+    let first = messages.send(alice.side.endpoint.node_id(), b"first".to_vec())?;
+    let second = messages.send(alice.side.endpoint.node_id(), b"second".to_vec())?;
+    assert_ne!(first, second);
+    let mut bob = Peer::start(2, messages.clone()).await?;
+    bob.learn(&alice).await?;
+    // Learning an address isn't an event, so nudge the queue like discovery would
+    messages.peer_event(&PeerEvent::Discovered {
+        node_id: alice.side.endpoint.node_id(),
+        user_data: None,
+        provenance: "test",
+        origin: DiscoveryOrigin::default(),
+    });
+
+    let bob_id = bob.side.endpoint.node_id();
+    assert_eq!(
+        alice.next_message().await?,
+        (bob_id, first, b"first".to_vec())
+    );
+    assert_eq!(
+        alice.next_message().await?,
+        (bob_id, second, b"second".to_vec())
+    );
+
+    let alice_id = alice.side.endpoint.node_id();
+    for id in [first, second] {
+        assert_eq!(
+            bob.next_message_event().await?,
+            PeerEvent::MessageSent {
+                id,
+                node_id: alice_id
+            }
+        );
+        assert_eq!(
+            bob.next_message_event().await?,
+            PeerEvent::MessageDelivered {
+                id,
+                node_id: alice_id
+            }
+        );
+    }
+    assert!(bob.messages.queue_depths().is_empty());
+
+    alice.side.router.shutdown().await?;
+    bob.side.router.shutdown().await?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn messages_to_an_offline_peer_wait_for_it() -> anyhow::Result<()> {
+    let mut bob = Peer::start(4, Messages::default()).await?;
+    let alice_id = node(3);
+
+    // Alice isn't running, so the attempt fails and the message stays
+    let id = bob.messages.send(alice_id, b"are you there?".to_vec())?;
+    let event = bob.next_message_event().await?;
+    assert!(
+        matches!(event, PeerEvent::MessageFailed { id: failed, retrying: true, .. } if failed == id),
+        "{event:?}"
+    );
+    assert_eq!(bob.messages.queue_depths().get(&alice_id), Some(&1));
+
+    let mut alice = Peer::start(3, Messages::default()).await?;
+    bob.learn(&alice).await?;
+    bob.messages.peer_event(&PeerEvent::Discovered {
+        node_id: alice_id,
+        user_data: None,
+        provenance: "test",
+        origin: DiscoveryOrigin::default(),
+    });
+    assert_eq!(
+        alice.next_message().await?,
+        (bob.side.endpoint.node_id(), id, b"are you there?".to_vec())
+    );
+
+    alice.side.router.shutdown().await?;
+    bob.side.router.shutdown().await?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn messages_missing_their_timeout_are_dropped() -> anyhow::Result<()> {
+    let mut bob = Peer::start(4, Messages::default()).await?;
+    let alice_id = node(3);
+
+    let id = bob.messages.send_within(
+        alice_id,
+        b"now or never".to_vec(),
+        Some(Duration::from_millis(500)),
+    )?;
+    let (retrying, reason) = loop {
+        match bob.next_message_event().await? {
+            PeerEvent::MessageFailed {
+                id: failed,
+                retrying,
+                reason,
+                timed_out: true,
+                ..
+            } if failed == id => break (retrying, reason),
+            _ => {}
+        }
+    };
+    assert!(!retrying);
+    assert!(reason.starts_with("Timed out"), "{reason}");
+    assert!(bob.messages.queue_depths().is_empty());
+
+    bob.side.router.shutdown().await?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn settled_callbacks_get_the_event_that_ended_the_message() -> anyhow::Result<()> {
+    let bob = Peer::start(9, Messages::default()).await?;
+    let alice_id = node(3);
+
+    let (settled, ended) = tokio::sync::oneshot::channel();
+    let id = bob.messages.send_then(
+        alice_id,
+        b"now or never".to_vec(),
+        true,
+        Some(Duration::from_millis(500)),
+        move |event| {
+            let _ = settled.send(event.clone());
+        },
+    )?;
+    match tokio::time::timeout(DEADLINE, ended).await?? {
+        PeerEvent::MessageFailed {
+            id: failed,
+            retrying,
+            timed_out,
+            ..
+        } => {
+            assert_eq!(failed, id);
+            assert!(!retrying);
+            assert!(timed_out);
+        }
+        other => panic!("Unexpected {other:?}"),
+    }
+
+    // A message that isn't queued never settles
+    let (settled, ended) = tokio::sync::oneshot::channel::<()>();
+    let too_large =
+        bob.messages
+            .send_then(alice_id, vec![0; MAX_MESSAGE_SIZE], true, None, move |_| {
+                let _ = settled.send(());
+            });
+    assert!(too_large.is_err());
+    assert!(ended.await.is_err());
+
+    bob.side.router.shutdown().await?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sealed_messages_arrive_opened_on_their_own_handler() -> anyhow::Result<()> {
+    let mut alice = Peer::start(5, Messages::default()).await?;
+    let bob = Peer::start(6, Messages::default()).await?;
+    bob.learn(&alice).await?;
+
+    let alice_id = alice.side.endpoint.node_id();
+    let sealed = bob
+        .messages
+        .send_sealed(alice_id, b"for your eyes only".to_vec())?;
+    let plain = bob.messages.send(alice_id, b"hello".to_vec())?;
+
+    let received = tokio::time::timeout(DEADLINE, alice.sealed_inbox.recv())
+        .await?
+        .expect("handler dropped");
+    assert_eq!(
+        received,
+        (
+            bob.side.endpoint.node_id(),
+            sealed,
+            b"for your eyes only".to_vec()
+        )
+    );
+    assert_eq!(
+        alice.next_message().await?,
+        (bob.side.endpoint.node_id(), plain, b"hello".to_vec())
+    );
+
+    alice.side.router.shutdown().await?;
+    bob.side.router.shutdown().await?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn peers_over_the_limits_are_refused() -> anyhow::Result<()> {
+    let limited = Messages::default();
+    limited.set_limits(MessageLimits {
+        max_size: 16,
+        per_second: Some(1),
+        burst: 1,
+    });
+    let mut alice = Peer::start(7, limited).await?;
+    let mut bob = Peer::start(8, Messages::default()).await?;
+    bob.learn(&alice).await?;
+    let alice_id = alice.side.endpoint.node_id();
+    let bob_id = bob.side.endpoint.node_id();
+
+    let large = bob.messages.send(alice_id, vec![0; 17])?;
+    let rejected = loop {
+        match bob.next_message_event().await? {
+            PeerEvent::MessageFailed { id, retrying, .. } if id == large => break retrying,
+            PeerEvent::MessageSent { .. } => {}
+            event => panic!("unexpected {event:?}"),
+        }
+    };
+    assert!(!rejected, "an oversized message is dropped");
+
+    // The burst is spent on the first message, the second waits its turn
+    let first = bob.messages.send(alice_id, b"first".to_vec())?;
+    let second = bob.messages.send(alice_id, b"second".to_vec())?;
+    assert_eq!(
+        alice.next_message().await?,
+        (bob_id, first, b"first".to_vec())
+    );
+    assert_eq!(
+        alice.next_message().await?,
+        (bob_id, second, b"second".to_vec())
+    );
+
+    let mut limits = Vec::new();
+    while limits.len() < 2 {
+        let event = alice
+            .side
+            .next_event(|event| matches!(event, PeerEvent::RateLimited { .. }))
+            .await?;
+        if let PeerEvent::RateLimited { node_id, limit, .. } = event {
+            assert_eq!(node_id, bob_id);
+            limits.push(limit);
+        }
+    }
+    assert_eq!(limits, [MessageLimit::Size, MessageLimit::Rate]);
+
+    alice.side.router.shutdown().await?;
+    bob.side.router.shutdown().await?;
+    Ok(())
+}
+
+#[test]
+fn queued_bytes_stay_within_the_budget() {
+    let messages = Messages::default();
+    let (alice, bob) = (node(1), node(2));
+    let mut budget = MemoryBudget {
+        queued_message_bytes: 100,
+        ..MemoryBudget::default()
+    };
+    messages.set_budget(&budget);
+
+    messages.send(alice, vec![1; 60]).unwrap();
+    assert!(messages.send(bob, vec![2; 60]).is_err());
+    assert!(messages.send(bob, vec![2; 40]).is_ok());
+    assert_eq!(messages.queued_bytes(), 100);
+
+    // Making room drops the longest-queued message, whoever it's for
+    budget.policy = DropPolicy::DropOldest;
+    messages.set_budget(&budget);
+    messages.send(bob, vec![3; 50]).unwrap();
+    assert_eq!(messages.queued_bytes(), 90);
+    assert_eq!(
+        messages.queue_depths().into_iter().collect::<Vec<_>>(),
+        [(bob, 2)]
+    );
+    // Nothing makes room for a message over the whole budget
+    assert!(messages.send(alice, vec![4; 101]).is_err());
+    assert_eq!(messages.queued_bytes(), 90);
+}
@@ -0,0 +1,28 @@
+//! Multicast delivery probe
+//!
+//! Needs multicast to work on the machine running the tests, like the
+//! discovery tests.
+
+use std::time::Duration;
+
+use mdns_peer::multicast::probe;
+
+#[test]
+fn beacons_come_back_on_a_multicast_interface() {
+    let report = probe(Duration::from_secs(1)).unwrap();
+    assert!(
+        report.delivering().any(|iface| iface.multicast_flag),
+        "{:#?}",
+        report
+    );
+    for iface in report.delivering() {
+        assert!(iface.error.is_none());
+        assert!(!report.heard_from.contains(&iface.addr));
+    }
+
+    let json = serde_json::to_value(&report).unwrap();
+    let first = &json["interfaces"][0];
+    for field in ["name", "addr", "multicast_flag", "delivers", "error"] {
+        assert!(first.get(field).is_some(), "missing {}", field);
+    }
+}
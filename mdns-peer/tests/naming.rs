@@ -0,0 +1,79 @@
+//! Picking identifiers no other peer presents
+#![cfg(feature = "cli")]
+
+use std::time::Duration;
+
+use iroh::discovery::UserData;
+use mdns_peer::fake::{FakeFleet, FakeOptions};
+use mdns_peer::mdns::MdnsOptions;
+use mdns_peer::naming::{
+    pick_identifier, sanitize_identifier, unique_identifier, MAX_IDENTIFIER_BYTES,
+};
+
+#[test]
+fn suffixes_taken_names() {
+    assert_eq!(unique_identifier("alice", []).unwrap(), "alice");
+    assert_eq!(unique_identifier("alice", ["bob"]).unwrap(), "alice");
+    assert_eq!(
+        unique_identifier("Jamie's iPhone", ["Jamie's iPhone"]).unwrap(),
+        "Jamie's iPhone-2"
+    );
+    // Gaps are filled, and case doesn't make a name different
+    assert_eq!(
+        unique_identifier("alice", ["ALICE", "alice-2", "alice-4"]).unwrap(),
+        "alice-3"
+    );
+}
+
+#[test]
+fn rejects_invalid_identifiers() {
+    assert!(unique_identifier(&"x".repeat(1000), []).is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn avoids_names_on_the_network() -> anyhow::Result<()> {
+    let options = FakeOptions {
+        count: 1,
+        prefix: "naming".to_string(),
+        rotate_interval: None,
+    };
+    let fleet = FakeFleet::start(&options).await?;
+
+    let mdns = MdnsOptions::default();
+    let listen = Duration::from_secs(10);
+    assert_eq!(
+        pick_identifier("NAMING-01", &mdns, listen).await?,
+        "NAMING-01-2"
+    );
+    assert_eq!(
+        pick_identifier("naming-unused", &mdns, Duration::from_millis(100)).await?,
+        "naming-unused"
+    );
+    fleet.close().await;
+    Ok(())
+}
+
+#[test]
+fn sanitizing_keeps_graphemes_whole() {
+    assert_eq!(sanitize_identifier("  Jamie's iPhone\n"), "Jamie's iPhone");
+    assert_eq!(sanitize_identifier("a\u{0}b\tc"), "abc");
+
+    // A family emoji is 25 bytes; the last one that doesn't fit is dropped whole
+    let family = "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}\u{200d}\u{1f466}";
+    let name = family.repeat(20);
+    let sanitized = sanitize_identifier(&name);
+    assert_eq!(
+        sanitized,
+        family.repeat(MAX_IDENTIFIER_BYTES / family.len())
+    );
+    sanitized.parse::<UserData>().unwrap();
+}
+
+#[test]
+fn suffixes_fit_after_long_names() {
+    let base = "\u{e9}".repeat(MAX_IDENTIFIER_BYTES / 2);
+    let unique = unique_identifier(&base, [base.as_str()]).unwrap();
+    assert!(unique.ends_with("-2"));
+    assert!(unique.len() <= MAX_IDENTIFIER_BYTES);
+    unique.parse::<UserData>().unwrap();
+}
@@ -0,0 +1,42 @@
+//! What the network sees us as
+
+use std::net::SocketAddr;
+
+use mdns_peer::network::ObservedAddrs;
+
+fn addrs(list: &[&str]) -> Vec<SocketAddr> {
+    list.iter().map(|addr| addr.parse().unwrap()).collect()
+}
+
+#[test]
+fn lan_addresses_are_not_translated() {
+    let report = ObservedAddrs::new(
+        addrs(&["192.168.1.20:4000", "10.0.0.5:4000"]),
+        addrs(&["192.168.1.20:4000"]),
+        Vec::new(),
+    );
+    assert!(!report.translated);
+    assert!(!report.symmetric_nat);
+    assert_eq!(report.local, addrs(&["10.0.0.5:4000", "192.168.1.20:4000"]));
+}
+
+#[test]
+fn nat_is_told_apart_from_symmetric_nat() {
+    let local = addrs(&["192.168.1.20:4000"]);
+    let report = ObservedAddrs::new(local.clone(), addrs(&["203.0.113.7:4000"]), Vec::new());
+    assert!(report.translated);
+    assert!(!report.symmetric_nat);
+
+    // Each relay saw a different port
+    let report = ObservedAddrs::new(
+        local,
+        addrs(&["203.0.113.7:61002", "203.0.113.7:4000"]),
+        Vec::new(),
+    );
+    assert!(report.translated);
+    assert!(report.symmetric_nat);
+    assert_eq!(
+        report.observed,
+        addrs(&["203.0.113.7:4000", "203.0.113.7:61002"])
+    );
+}
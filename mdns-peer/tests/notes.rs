@@ -0,0 +1,64 @@
+//! Local aliases and notes for other peers
+
+use common::node;
+use mdns_peer::notes::{PeerNote, PeerNotes, MAX_ALIAS_LEN};
+
+mod common;
+
+#[test]
+fn notes_persist_in_the_state_dir() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let notes = PeerNotes::open(dir.path())?;
+    notes.set_alias(node(1), Some("  Dad's MacBook "))?;
+    notes.set_notes(node(1), Some("Upstairs office"))?;
+    notes.set_notes(node(2), Some("Shared iPad"))?;
+
+    let reopened = PeerNotes::open(dir.path())?;
+    assert_eq!(
+        reopened.get(node(1)),
+        PeerNote {
+            alias: Some("Dad's MacBook".to_string()),
+            notes: Some("Upstairs office".to_string()),
+        }
+    );
+    assert_eq!(reopened.get(node(2)).alias, None);
+    assert_eq!(reopened.get(node(3)), PeerNote::default());
+    assert!(dir.path().join("peers.json").exists());
+    Ok(())
+}
+
+#[test]
+fn clearing_everything_forgets_the_peer() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let notes = PeerNotes::open(dir.path())?;
+    notes.set_alias(node(1), Some("Laptop"))?;
+    notes.set_notes(node(1), Some("Work"))?;
+
+    notes.set_alias(node(1), Some(" "))?;
+    assert_eq!(notes.all().len(), 1);
+    notes.set_notes(node(1), None)?;
+    assert!(notes.all().is_empty());
+    assert!(PeerNotes::open(dir.path())?.all().is_empty());
+    Ok(())
+}
+
+#[test]
+fn clones_share_notes_and_limits_apply() -> anyhow::Result<()> {
+    let notes = PeerNotes::default();
+    let running = notes.clone();
+    notes.set_alias(node(1), Some("Phone"))?;
+    assert_eq!(running.get(node(1)).alias.as_deref(), Some("Phone"));
+
+    let too_long = "x".repeat(MAX_ALIAS_LEN + 1);
+    assert!(notes.set_alias(node(1), Some(&too_long)).is_err());
+    assert_eq!(running.get(node(1)).alias.as_deref(), Some("Phone"));
+    Ok(())
+}
+
+#[test]
+fn corrupt_file_is_reported() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("peers.json"), r#"{"not-a-node":{}}"#)?;
+    assert!(PeerNotes::open(dir.path()).is_err());
+    Ok(())
+}
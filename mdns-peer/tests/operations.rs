@@ -0,0 +1,50 @@
+//! Cancelling operations by ID
+
+use std::time::Duration;
+
+use mdns_peer::deadline::ResultCode;
+use mdns_peer::operations::{self, OperationKind};
+
+#[tokio::test]
+async fn cancelled_operations_fail_with_cancelled() {
+    let (id, operation) = operations::cancellable(OperationKind::Connect, async {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        Ok(())
+    });
+    let operation = tokio::spawn(operation);
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    assert_eq!(operations::kind(id), Some(OperationKind::Connect));
+    assert!(operations::cancel(id));
+    let err = operation.await.unwrap().unwrap_err();
+    assert!(operations::is_cancelled(&err));
+    assert_eq!(ResultCode::from_error(&err), ResultCode::Cancelled);
+    assert_eq!(operations::kind(id), None);
+    assert!(!operations::cancel(id));
+}
+
+#[tokio::test]
+async fn operations_cancelled_before_they_start_never_run() {
+    let (id, operation) = operations::cancellable(OperationKind::Ping, async { Ok("pong") });
+    assert!(operations::cancel(id));
+
+    let err = operation.await.unwrap_err();
+    assert!(operations::is_cancelled(&err));
+}
+
+#[tokio::test]
+async fn finished_operations_are_unlisted() {
+    let (id, operation) = operations::cancellable(OperationKind::Connect, async { Ok(7) });
+    assert_eq!(operation.await.unwrap(), 7);
+
+    assert_eq!(operations::kind(id), None);
+    assert!(!operations::cancel(id));
+}
+
+#[test]
+fn operation_ids_are_never_reused() {
+    let first = operations::next_id();
+    let second = operations::next_id();
+    assert_ne!(first, 0);
+    assert!(second > first);
+}
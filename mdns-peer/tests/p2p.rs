@@ -0,0 +1,67 @@
+//! Peer-to-peer interfaces reported by the host
+
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+
+use mdns_peer::p2p::PeerToPeer;
+
+fn addrs(list: &[&str]) -> BTreeSet<SocketAddr> {
+    list.iter().map(|addr| addr.parse().unwrap()).collect()
+}
+
+#[test]
+fn nothing_is_managed_until_the_host_reports_an_interface() {
+    let p2p = PeerToPeer::default();
+    assert!(!p2p.is_managed());
+    assert!(!p2p.excludes("awdl0"));
+    let all = addrs(&["127.0.0.1:4433", "[fe80::1%1]:4433"]);
+    assert_eq!(p2p.announced(&all), all);
+    assert_eq!(p2p.dialable(&all), all);
+}
+
+#[test]
+fn reported_interfaces_are_excluded_until_available() {
+    let p2p = PeerToPeer::default();
+    assert!(p2p.set_available("nan0", false));
+    assert!(!p2p.set_available("nan0", false));
+    assert!(p2p.is_managed());
+
+    // AWDL is known without being reported; other interfaces are left alone
+    assert!(p2p.excludes("awdl0"));
+    assert!(p2p.excludes("llw0"));
+    assert!(p2p.excludes("nan0"));
+    assert!(!p2p.excludes("en0"));
+    assert!(p2p.is_p2p("nan0"));
+    assert!(!p2p.is_p2p("en0"));
+
+    assert!(p2p.set_available("awdl0", true));
+    assert!(!p2p.excludes("awdl0"));
+    assert!(p2p.excludes("llw0"));
+    assert_eq!(p2p.available(), ["awdl0"]);
+
+    // Clones share the reports
+    p2p.clone().set_available("awdl0", false);
+    assert!(p2p.excludes("awdl0"));
+    assert!(p2p.available().is_empty());
+}
+
+#[test]
+fn addresses_on_excluded_interfaces_are_left_out() {
+    let Some(loopback) = netdev::get_interfaces()
+        .into_iter()
+        .find(|iface| iface.is_loopback() && !iface.ipv4.is_empty())
+    else {
+        return;
+    };
+    let p2p = PeerToPeer::default();
+    let all = addrs(&["127.0.0.1:4433", "192.0.2.7:4433"]);
+
+    // Treat loopback as a peer-to-peer link that isn't up
+    p2p.set_available(&loopback.name, false);
+    assert_eq!(p2p.announced(&all), addrs(&["192.0.2.7:4433"]));
+    assert_eq!(p2p.dialable(&all), addrs(&["192.0.2.7:4433"]));
+
+    p2p.set_available(&loopback.name, true);
+    assert_eq!(p2p.announced(&all), all);
+    assert_eq!(p2p.dialable(&all), all);
+}
@@ -0,0 +1,96 @@
+//! Pairing payloads for peers that can't discover each other
+
+use std::time::Duration;
+
+use iroh::{Endpoint, NodeAddr, RelayMode, SecretKey};
+use mdns_peer::pairing::{add_paired_peer, decode_payload, encode_payload, local_payload};
+
+const ALPN: &[u8] = b"mdns-peer/pairing-test/0";
+const DEADLINE: Duration = Duration::from_secs(10);
+
+fn addr() -> NodeAddr {
+    NodeAddr::from_parts(
+        SecretKey::from_bytes(&[7; 32]).public(),
+        Some("https://relay.example.com".parse().unwrap()),
+        [
+            "192.168.1.20:4433".parse().unwrap(),
+            "[fe80::1]:4433".parse().unwrap(),
+        ],
+    )
+}
+
+/// Endpoint with no discovery and no relay, only reachable by direct address
+async fn isolated_endpoint() -> anyhow::Result<Endpoint> {
+    let endpoint = Endpoint::builder()
+        .relay_mode(RelayMode::Disabled)
+        .alpns(vec![ALPN.to_vec()])
+        .bind()
+        .await?;
+    Ok(endpoint)
+}
+
+#[test]
+fn payload_round_trips_in_qr_alphanumeric_characters() -> anyhow::Result<()> {
+    let payload = encode_payload(&addr());
+    assert!(payload.starts_with("NODE"));
+    assert!(
+        payload
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit()),
+        "{} needs QR byte mode",
+        payload
+    );
+
+    assert_eq!(decode_payload(&payload)?, addr());
+    // Some scanners lower-case what they read, or keep a trailing newline
+    assert_eq!(
+        decode_payload(&format!("{}\n", payload.to_lowercase()))?,
+        addr()
+    );
+    Ok(())
+}
+
+#[test]
+fn invalid_payloads_are_rejected() {
+    let payload = encode_payload(&addr());
+    for invalid in [
+        "",
+        "NODE",
+        &payload[4..],
+        &payload[..payload.len() - 5],
+        "https://example.com",
+    ] {
+        assert!(decode_payload(invalid).is_err(), "{:?} decoded", invalid);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn paired_peers_connect_without_discovery() -> anyhow::Result<()> {
+    let alice = isolated_endpoint().await?;
+    let bob = isolated_endpoint().await?;
+
+    let payload = local_payload(&alice, DEADLINE)
+        .await
+        .expect("alice has direct addresses");
+    add_paired_peer(&bob, decode_payload(&payload)?)?;
+
+    let accept = tokio::spawn({
+        let alice = alice.clone();
+        async move {
+            let incoming = alice.accept().await.expect("alice is open");
+            let conn = incoming.await?;
+            anyhow::Ok(conn.remote_node_id()?)
+        }
+    });
+    let conn = tokio::time::timeout(DEADLINE, bob.connect(alice.node_id(), ALPN)).await??;
+    assert_eq!(conn.remote_node_id()?, alice.node_id());
+    assert_eq!(
+        tokio::time::timeout(DEADLINE, accept).await???,
+        bob.node_id()
+    );
+
+    conn.close(0u32.into(), b"done");
+    alice.close().await;
+    bob.close().await;
+    Ok(())
+}
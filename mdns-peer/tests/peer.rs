@@ -0,0 +1,216 @@
+//! Embedding the peer through the Rust API
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use iroh::{NodeAddr, SecretKey, Watcher};
+use mdns_peer::instance::DuplicatePolicy;
+use mdns_peer::{MdnsPeer, MdnsPeerBuilder, PeerEvent, PeerOptions, PeerStatus, ShutdownReason};
+use n0_future::StreamExt;
+use tokio::sync::broadcast;
+
+const DEADLINE: Duration = Duration::from_secs(20);
+
+fn options() -> PeerOptions {
+    PeerOptions {
+        summary_interval: None,
+        on_duplicate: DuplicatePolicy::Allow,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn handles_can_be_shared_across_threads() {
+    fn assert_send_sync<T: Send + Sync + 'static>() {}
+    fn assert_send<T: Send>(_: &T) {}
+
+    assert_send_sync::<MdnsPeer>();
+    assert_send_sync::<PeerOptions>();
+    assert_send_sync::<PeerEvent>();
+    assert_send::<MdnsPeerBuilder>(&MdnsPeer::builder());
+    // Spawning works from inside other tasks
+    assert_send(&MdnsPeer::builder().spawn());
+}
+
+#[test]
+fn validate_checks_without_binding() {
+    assert!(MdnsPeer::builder().validate().is_err());
+    let valid = MdnsPeer::builder()
+        .identifier("validated")
+        .options(options());
+    valid.validate().unwrap();
+
+    let mut relays = options();
+    relays.relays = Some(Vec::new());
+    assert!(MdnsPeer::builder()
+        .identifier("validated")
+        .options(relays)
+        .validate()
+        .is_err());
+
+    let mut record = options();
+    record.record = Some("/nonexistent/dir/session.ndjson".into());
+    assert!(MdnsPeer::builder()
+        .identifier("validated")
+        .options(record)
+        .validate()
+        .is_err());
+
+    let mut mdns = options();
+    mdns.mdns.service_name = String::new();
+    assert!(MdnsPeer::builder()
+        .identifier("validated")
+        .options(mdns)
+        .validate()
+        .is_err());
+}
+
+#[tokio::test]
+async fn spawn_needs_a_valid_identifier() {
+    assert!(MdnsPeer::builder().spawn().await.is_err());
+    assert!(MdnsPeer::builder()
+        .identifier("x".repeat(1000))
+        .spawn()
+        .await
+        .is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn invalid_options_stop_as_failed() {
+    let stopped = Arc::new(Mutex::new(None));
+    let sink_stopped = stopped.clone();
+    let mut invalid = options();
+    invalid.mdns.service_name = String::new();
+    let result = MdnsPeer::builder()
+        .identifier("misconfigured")
+        .options(invalid)
+        .on_event(Arc::new(move |event: &PeerEvent| {
+            if let PeerEvent::StatusChanged {
+                status: PeerStatus::Stopped,
+                reason,
+            } = event
+            {
+                *sink_stopped.lock().unwrap() = *reason;
+            }
+        }))
+        .spawn()
+        .await;
+    assert!(result.is_err());
+    assert_eq!(*stopped.lock().unwrap(), Some(ShutdownReason::Failed));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn runs_until_shut_down() -> anyhow::Result<()> {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let sink_seen = seen.clone();
+    let peer = MdnsPeer::builder()
+        .identifier("embedded")
+        .options(options())
+        .on_event(Arc::new(move |event: &PeerEvent| {
+            if let PeerEvent::StatusChanged { status, .. } = event {
+                sink_seen.lock().unwrap().push(*status);
+            }
+        }))
+        .spawn()
+        .await?;
+    assert_eq!(peer.identifier(), "embedded");
+    assert_eq!(peer.endpoint().node_id(), peer.node_id());
+    assert_eq!(peer.shutdown_reason(), None);
+
+    // Shutting down from a clone stops the same peer
+    let reason = tokio::time::timeout(DEADLINE, peer.clone().shutdown()).await?;
+    assert_eq!(reason, ShutdownReason::HostRequested);
+    assert_eq!(peer.status(), PeerStatus::Stopped);
+    assert_eq!(peer.shutdown_reason(), Some(ShutdownReason::HostRequested));
+    assert_eq!(
+        tokio::time::timeout(DEADLINE, peer.stopped()).await?,
+        ShutdownReason::HostRequested
+    );
+    assert_eq!(
+        *seen.lock().unwrap(),
+        [
+            PeerStatus::Starting,
+            PeerStatus::Running,
+            PeerStatus::Stopped
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn event_stream_ends_with_the_stop() -> anyhow::Result<()> {
+    let peer = MdnsPeer::builder()
+        .identifier("streamed")
+        .options(options())
+        .spawn()
+        .await?;
+    let events = peer.events();
+    let collecting = tokio::spawn(events.collect::<Vec<_>>());
+    peer.shutdown().await;
+
+    let events = tokio::time::timeout(DEADLINE, collecting).await??;
+    assert_eq!(
+        events.last(),
+        Some(&PeerEvent::stopped(ShutdownReason::HostRequested))
+    );
+    // Streams opened after the stop are empty rather than pending forever
+    let late: Vec<_> = tokio::time::timeout(DEADLINE, peer.events().collect()).await?;
+    assert!(late.is_empty());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lite_peers_accept_nothing() -> anyhow::Result<()> {
+    let peer = MdnsPeer::builder()
+        .identifier("lite")
+        .options(PeerOptions {
+            lite: true,
+            ..options()
+        })
+        .spawn()
+        .await?;
+    let addr = tokio::time::timeout(DEADLINE, peer.endpoint().node_addr().initialized()).await?;
+
+    // Not even the file protocol it sends with is served
+    let other = mdns_peer::bind_endpoint("lite-dialer").await?;
+    let connect = other.connect(addr, mdns_peer::transfer::FILE_ALPN);
+    assert!(tokio::time::timeout(DEADLINE, connect).await?.is_err());
+
+    other.close().await;
+    let reason = tokio::time::timeout(DEADLINE, peer.shutdown()).await?;
+    assert_eq!(reason, ShutdownReason::HostRequested);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failing_to_start_closes_the_endpoint() -> anyhow::Result<()> {
+    async fn start(options: PeerOptions) -> anyhow::Result<()> {
+        let endpoint = mdns_peer::bind_endpoint_with("unstarted", &options).await?;
+        let (_shutdown_tx, shutdown_rx) = broadcast::channel(1);
+        let result = mdns_peer::run_endpoint(
+            "unstarted",
+            endpoint.clone(),
+            options,
+            shutdown_rx,
+            mdns_peer::events::discard_events(),
+        )
+        .await;
+        assert!(result.is_err());
+        assert!(endpoint.is_closed());
+        Ok(())
+    }
+
+    start(PeerOptions {
+        record: Some("/nonexistent/dir/session.ndjson".into()),
+        ..options()
+    })
+    .await?;
+    // An endpoint can't be paired with itself
+    let secret_key = SecretKey::generate(rand::rngs::OsRng);
+    start(PeerOptions {
+        paired: vec![NodeAddr::new(secret_key.public())],
+        secret_key: Some(secret_key),
+        ..options()
+    })
+    .await
+}
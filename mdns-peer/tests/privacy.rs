@@ -0,0 +1,44 @@
+//! Hashing names in logs and recordings
+
+use common::node;
+use iroh::discovery::{DiscoveryEvent, DiscoveryItem, NodeInfo};
+use mdns_peer::privacy;
+use mdns_peer::session::{Session, SessionRecorder};
+
+mod common;
+
+#[test]
+fn names_are_hashed_unless_logged() {
+    let hash = privacy::hash_name("Jamie's iPhone");
+    assert_eq!(hash, privacy::hash_name("Jamie's iPhone"));
+    assert_ne!(hash, privacy::hash_name("Jamie's iPad"));
+    assert!(hash.starts_with('#'));
+    assert_eq!(hash.len(), 9);
+
+    assert!(!privacy::log_names());
+    assert_eq!(privacy::redact("Jamie's iPhone").to_string(), hash);
+    privacy::set_log_names(true);
+    assert_eq!(
+        privacy::redact("Jamie's iPhone").to_string(),
+        "Jamie's iPhone"
+    );
+    privacy::set_log_names(false);
+}
+
+#[test]
+fn recordings_can_hash_user_data() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("session.ndjson");
+    let recorder = SessionRecorder::create_with(&path, node(1), Some("Jamie's Mac"), true)?;
+    let info = NodeInfo::new(node(2)).with_user_data(Some("Jamie's iPhone".parse()?));
+    recorder.record(&DiscoveryEvent::Discovered(DiscoveryItem::new(
+        info, "mdns", None,
+    )));
+    drop(recorder);
+
+    assert!(!std::fs::read_to_string(&path)?.contains("Jamie"));
+    let session = Session::read(&path)?;
+    assert_eq!(session.user_data, Some(privacy::hash_name("Jamie's Mac")));
+    assert_eq!(session.events.len(), 1);
+    Ok(())
+}
@@ -0,0 +1,68 @@
+//! Persistence of named profiles
+
+use mdns_peer::profile::ProfileStore;
+
+#[test]
+fn profile_keeps_its_key_across_loads() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let store = ProfileStore::new(dir.path());
+
+    let first = store.load_or_create("work", None)?;
+    let second = store.load_or_create("work", None)?;
+
+    assert_eq!(first.secret_key.public(), second.secret_key.public());
+    assert_eq!(second.user_data, "work");
+    Ok(())
+}
+
+#[test]
+fn profiles_have_distinct_identities() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let store = ProfileStore::new(dir.path());
+
+    let work = store.load_or_create("work", Some("alice-work"))?;
+    let home = store.load_or_create("home", Some("alice-home"))?;
+
+    assert_ne!(work.secret_key.public(), home.secret_key.public());
+    assert_eq!(store.list()?, vec!["home", "work"]);
+    Ok(())
+}
+
+#[test]
+fn explicit_user_data_replaces_stored_value() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let store = ProfileStore::new(dir.path());
+
+    let original = store.load_or_create("work", Some("alice"))?;
+    store.load_or_create("work", Some("alice-laptop"))?;
+    let reloaded = store.load_or_create("work", None)?;
+
+    assert_eq!(reloaded.user_data, "alice-laptop");
+    assert_eq!(reloaded.secret_key.public(), original.secret_key.public());
+    Ok(())
+}
+
+#[test]
+fn path_like_names_are_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = ProfileStore::new(dir.path());
+
+    for name in ["", "../escape", "a/b", "work.json"] {
+        assert!(store.load_or_create(name, None).is_err(), "{:?}", name);
+    }
+}
+
+#[test]
+fn replacing_the_key_keeps_the_user_data() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let store = ProfileStore::new(dir.path());
+    let first = store.load_or_create("work", Some("alice"))?;
+
+    let key = iroh::SecretKey::from_bytes(&[5; 32]);
+    store.replace_key("work", key.clone())?;
+    let loaded = store.load_or_create("work", None)?;
+    assert_ne!(loaded.secret_key.public(), first.secret_key.public());
+    assert_eq!(loaded.secret_key.public(), key.public());
+    assert_eq!(loaded.user_data, "alice");
+    Ok(())
+}
@@ -0,0 +1,98 @@
+//! Wire format of messages and file transfers
+//!
+//! The expected bytes are spelled out so a change to the encoding, which
+//! would break other platforms' builds, fails here first.
+
+use mdns_peer::messages::{MessageId, MAX_MESSAGE_SIZE};
+use mdns_peer::protocol::{
+    self, FileAnswer, FileEnd, FileOffer, FileOutcome, MessageFrame, MessageReply, MESSAGE_OVERHEAD,
+};
+
+#[test]
+fn frames_have_a_fixed_encoding() {
+    assert_eq!(protocol::encode(&MessageReply::Ack), [0, 0, 0, 1, 0]);
+    assert_eq!(protocol::encode(&MessageReply::Busy), [0, 0, 0, 1, 1]);
+    assert_eq!(protocol::encode(&FileAnswer::Refused), [0, 0, 0, 1, 1]);
+    assert_eq!(
+        protocol::encode(&FileAnswer::Accepted { offset: 1 }),
+        [0, 0, 0, 2, 0, 1]
+    );
+    assert_eq!(protocol::encode(&FileOutcome::Corrupt), [0, 0, 0, 1, 2]);
+
+    let offer = FileOffer {
+        name: "a.txt".to_string(),
+        size: 300,
+    };
+    // Integers are varints: 300 is 0xac 0x02
+    assert_eq!(
+        protocol::encode(&offer),
+        [0, 0, 0, 8, 5, b'a', b'.', b't', b'x', b't', 0xac, 0x02]
+    );
+
+    let message = MessageFrame {
+        id: MessageId::from_bytes([7; 16]),
+        sealed: true,
+        payload: b"hi".to_vec(),
+    };
+    let mut expected = vec![0, 0, 0, 21, 16];
+    expected.extend([7; 16]);
+    expected.extend([1, 2, b'h', b'i']);
+    assert_eq!(protocol::encode(&message), expected);
+}
+
+#[test]
+fn frames_round_trip() {
+    let end = FileEnd { hash: [9; 32] };
+    let encoded = protocol::encode(&end);
+    assert_eq!(encoded.len(), protocol::PREFIX_LEN + 32);
+    assert_eq!(
+        protocol::decode::<FileEnd>(&encoded).unwrap(),
+        Some((end, &[][..]))
+    );
+
+    let message = MessageFrame {
+        id: MessageId::new_v4(),
+        sealed: false,
+        payload: vec![1; 1000],
+    };
+    let encoded = protocol::encode(&message);
+    let (decoded, rest) = protocol::decode::<MessageFrame>(&encoded).unwrap().unwrap();
+    assert_eq!(decoded, message);
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn decoding_waits_for_the_whole_frame() {
+    let mut stream = protocol::encode(&FileAnswer::Accepted { offset: 1 << 40 });
+    for len in 0..stream.len() {
+        assert!(protocol::decode::<FileAnswer>(&stream[..len])
+            .unwrap()
+            .is_none());
+    }
+
+    // Raw file bytes may follow a frame
+    stream.extend(b"file");
+    let (answer, rest) = protocol::decode::<FileAnswer>(&stream).unwrap().unwrap();
+    assert_eq!(answer, FileAnswer::Accepted { offset: 1 << 40 });
+    assert_eq!(rest, b"file");
+}
+
+#[test]
+fn malformed_frames_are_rejected() {
+    // An unknown variant
+    assert!(protocol::decode::<MessageReply>(&[0, 0, 0, 1, 9]).is_err());
+    // A body longer than one frame
+    assert!(protocol::decode::<MessageReply>(&[0, 0, 0, 2, 0, 0]).is_err());
+    // A body shorter than one frame
+    assert!(protocol::decode_body::<FileEnd>(&[0; 31]).is_err());
+}
+
+#[test]
+fn message_overhead_covers_the_largest_message() {
+    let message = MessageFrame {
+        id: MessageId::new_v4(),
+        sealed: true,
+        payload: vec![0; MAX_MESSAGE_SIZE],
+    };
+    assert!(protocol::encode(&message).len() <= MAX_MESSAGE_SIZE + MESSAGE_OVERHEAD);
+}
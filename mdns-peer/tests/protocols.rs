@@ -0,0 +1,446 @@
+//! Host-defined protocols over real endpoints
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use iroh::{NodeAddr, NodeId, Watcher};
+use mdns_peer::limits::TransferLimits;
+use mdns_peer::protocols::{Protocols, StreamHandler, StreamId, MAX_BUFFERED_WRITE};
+use tokio::sync::mpsc;
+
+use common::{Side, DEADLINE};
+
+mod common;
+
+const ALPN: &[u8] = b"mdns-peer/test-echo/0";
+
+#[derive(Debug, PartialEq, Eq)]
+enum Activity {
+    Open(StreamId, NodeId),
+    Data(StreamId, Vec<u8>),
+    Close(StreamId, Option<String>),
+}
+
+/// Forwards every callback to a channel
+struct Recorder(mpsc::UnboundedSender<Activity>);
+
+impl StreamHandler for Recorder {
+    fn on_open(&self, stream: StreamId, node_id: NodeId) {
+        let _ = self.0.send(Activity::Open(stream, node_id));
+    }
+
+    fn on_data(&self, stream: StreamId, data: &[u8]) {
+        let _ = self.0.send(Activity::Data(stream, data.to_vec()));
+    }
+
+    fn on_close(&self, stream: StreamId, error: Option<&str>) {
+        let _ = self
+            .0
+            .send(Activity::Close(stream, error.map(str::to_string)));
+    }
+}
+
+fn recorder() -> (Arc<Recorder>, mpsc::UnboundedReceiver<Activity>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    (Arc::new(Recorder(tx)), rx)
+}
+
+async fn next(rx: &mut mpsc::UnboundedReceiver<Activity>) -> Activity {
+    tokio::time::timeout(DEADLINE, rx.recv())
+        .await
+        .expect("timed out waiting for stream activity")
+        .expect("handler dropped")
+}
+
+/// A side with the test protocol registered, recording its activity
+async fn bind(identifier: &str) -> anyhow::Result<Side<Activity>> {
+    Side::bind(identifier, |protocols, tx| {
+        protocols.register(ALPN, Arc::new(Recorder(tx)));
+    })
+    .await
+}
+
+/// Like [`bind`], but incoming data waits for [`Protocols::read`]
+async fn bind_pull(identifier: &str) -> anyhow::Result<Side<Activity>> {
+    Side::bind(identifier, |protocols, tx| {
+        protocols.register_pull(ALPN, Arc::new(Recorder(tx)));
+    })
+    .await
+}
+
+fn open<T>(side: &Side<T>, to: impl Into<NodeAddr>) -> anyhow::Result<StreamId> {
+    side.protocols.open_stream(&side.endpoint, to, ALPN)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn request_and_response_on_custom_alpn() -> anyhow::Result<()> {
+    let server = mdns_peer::bind_endpoint("proto-server").await?;
+    let client = mdns_peer::bind_endpoint("proto-client").await?;
+
+    let server_protocols = Protocols::default();
+    let (server_handler, mut server_rx) = recorder();
+    server_protocols.register(ALPN, server_handler);
+    let server_router = server_protocols.spawn_router(server.clone());
+
+    let client_protocols = Protocols::default();
+    let (client_handler, mut client_rx) = recorder();
+    client_protocols.register(ALPN, client_handler);
+    let client_router = client_protocols.spawn_router(client.clone());
+
+    let server_addr = server.node_addr().initialized().await;
+    let out = client_protocols.open_stream(&client, server_addr, ALPN)?;
+    assert!(client_protocols.write(out, b"ping".to_vec()));
+    assert!(client_protocols.finish(out));
+
+    assert_eq!(
+        next(&mut client_rx).await,
+        Activity::Open(out, server.node_id())
+    );
+
+    let Activity::Open(incoming, from) = next(&mut server_rx).await else {
+        panic!("expected the server stream to open first");
+    };
+    assert_eq!(from, client.node_id());
+    assert_eq!(
+        next(&mut server_rx).await,
+        Activity::Data(incoming, b"ping".to_vec())
+    );
+    assert_eq!(next(&mut server_rx).await, Activity::Close(incoming, None));
+
+    // The request side is finished, but the server can still respond
+    assert!(server_protocols.write(incoming, b"pong".to_vec()));
+    assert!(server_protocols.finish(incoming));
+
+    assert_eq!(
+        next(&mut client_rx).await,
+        Activity::Data(out, b"pong".to_vec())
+    );
+    assert_eq!(next(&mut client_rx).await, Activity::Close(out, None));
+
+    let stats = client_protocols.stats();
+    assert_eq!(stats.peers.len(), 1);
+    let peer = &stats.peers[0];
+    assert_eq!(peer.node_id, server.node_id());
+    assert_eq!((peer.bytes_sent, peer.bytes_received), (4, 4));
+    assert_eq!(peer.protocols.len(), 1);
+    assert_eq!(peer.protocols[0].alpn, "mdns-peer/test-echo/0");
+    assert!(peer.receive_bytes_per_sec > 0.0);
+    assert_eq!(peer.quic.len(), 1);
+    let quic = &peer.quic[0];
+    assert_eq!(quic.alpn, "mdns-peer/test-echo/0");
+    assert!(quic.rtt_ms > 0.0 && quic.cwnd > 0);
+    assert!(quic.udp_datagrams_sent > 0 && quic.udp_datagrams_received > 0);
+    let quic = client_protocols.quic_stats(server.node_id());
+    assert_eq!(quic.len(), 1);
+    assert!(client_protocols.quic_stats(client.node_id()).is_empty());
+
+    client_router.shutdown().await?;
+    server_router.shutdown().await?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn send_stream_delivers_a_large_payload_in_chunks() -> anyhow::Result<()> {
+    let mut server = bind("proto-send-server").await?;
+    let mut client = bind("proto-send-client").await?;
+
+    let payload: Vec<u8> = (0..4 * MAX_BUFFERED_WRITE).map(|i| i as u8).collect();
+    let out = client
+        .protocols
+        .open_send_stream(&client.endpoint, server.addr().await, ALPN)?;
+    for piece in payload.chunks(256 * 1024) {
+        assert!(client.protocols.write_buffered(out, piece.to_vec()).await);
+    }
+    assert!(client.protocols.finish(out));
+
+    let Activity::Open(incoming, from) = server.next_received().await? else {
+        panic!("expected the server stream to open first");
+    };
+    assert_eq!(from, client.endpoint.node_id());
+    // Nothing can be written back on a send-only stream
+    assert!(!server.protocols.write(incoming, b"reply".to_vec()));
+    let mut received = Vec::new();
+    loop {
+        match server.next_received().await? {
+            Activity::Data(stream, data) if stream == incoming => received.extend(data),
+            Activity::Close(stream, None) if stream == incoming => break,
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+    assert!(received == payload, "payload arrived changed");
+
+    assert_eq!(
+        client.next_received().await?,
+        Activity::Open(out, server.endpoint.node_id())
+    );
+    assert_eq!(client.next_received().await?, Activity::Close(out, None));
+    assert!(!client.protocols.write_buffered(out, b"late".to_vec()).await);
+
+    client.shutdown().await?;
+    server.shutdown().await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pulled_streams_wait_for_the_host_to_read() -> anyhow::Result<()> {
+    let mut server = bind_pull("proto-pull-server").await?;
+    let client = bind("proto-pull-client").await?;
+
+    let payload: Vec<u8> = (0..16 * MAX_BUFFERED_WRITE)
+        .map(|i| (i / 7) as u8)
+        .collect();
+    let out = client
+        .protocols
+        .open_send_stream(&client.endpoint, server.addr().await, ALPN)?;
+    let writer = {
+        let protocols = client.protocols.clone();
+        let payload = payload.clone();
+        tokio::spawn(async move {
+            for piece in payload.chunks(256 * 1024) {
+                assert!(protocols.write_buffered(out, piece.to_vec()).await);
+            }
+            assert!(protocols.finish(out));
+        })
+    };
+
+    let Activity::Open(incoming, _) = server.next_received().await? else {
+        panic!("expected the server stream to open first");
+    };
+    // Without reads, the sender stalls well short of the whole payload
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let received = server.protocols.stats().peers[0].bytes_received;
+    assert!(
+        received < payload.len() as u64,
+        "{received} bytes arrived unread"
+    );
+    assert!(!writer.is_finished());
+
+    let mut read = Vec::new();
+    while let Some(data) = server.protocols.read(incoming, 1000).await? {
+        assert!(data.len() <= 1000);
+        read.extend_from_slice(&data);
+    }
+    assert!(read == payload, "payload arrived changed");
+    assert_eq!(
+        server.next_received().await?,
+        Activity::Close(incoming, None)
+    );
+    assert!(server.protocols.read(incoming, 1000).await.is_err());
+    writer.await?;
+
+    client.shutdown().await?;
+    server.shutdown().await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unregistered_alpn_is_rejected() -> anyhow::Result<()> {
+    let endpoint = mdns_peer::bind_endpoint("proto-unregistered").await?;
+    let protocols = Protocols::default();
+
+    let target = iroh::SecretKey::from_bytes(&[3; 32]).public();
+    assert!(protocols.open_stream(&endpoint, target, ALPN).is_err());
+
+    endpoint.close().await;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn warm_connection_is_reused_by_streams() -> anyhow::Result<()> {
+    let server = mdns_peer::bind_endpoint("warm-server").await?;
+    let client = mdns_peer::bind_endpoint("warm-client").await?;
+
+    let server_protocols = Protocols::default();
+    let (server_handler, mut server_rx) = recorder();
+    server_protocols.register(ALPN, server_handler);
+    let server_router = server_protocols.spawn_router(server.clone());
+
+    let client_protocols = Protocols::default();
+    let (client_handler, _client_rx) = recorder();
+    client_protocols.register(ALPN, client_handler);
+    let client_router = client_protocols.spawn_router(client.clone());
+
+    let server_addr = server.node_addr().initialized().await;
+    let warm = client_protocols.connect(&client, server_addr, ALPN).await?;
+    assert!(client_protocols.is_connected(server.node_id(), ALPN));
+
+    let out = client_protocols.open_stream(&client, server.node_id(), ALPN)?;
+    assert!(client_protocols.write(out, b"hi".to_vec()));
+    assert!(matches!(next(&mut server_rx).await, Activity::Open(_, _)));
+
+    // The stream went over the warm connection rather than a new one
+    let reused = client_protocols
+        .connect(&client, server.node_id(), ALPN)
+        .await?;
+    assert_eq!(reused.stable_id(), warm.stable_id());
+
+    client_router.shutdown().await?;
+    server_router.shutdown().await?;
+    Ok(())
+}
+#[tokio::test(flavor = "multi_thread")]
+async fn send_rate_limit_paces_writes() -> anyhow::Result<()> {
+    let mut server = bind("rate-server").await?;
+    let mut client = bind("rate-client").await?;
+    client.protocols.set_limits(TransferLimits {
+        send_bytes_per_sec: Some(20_000),
+        ..Default::default()
+    });
+
+    let out = open(&client, server.addr().await)?;
+    assert!(matches!(
+        client.next_received().await?,
+        Activity::Open(_, _)
+    ));
+
+    let started = std::time::Instant::now();
+    assert!(client.protocols.write(out, vec![7; 60_000]));
+    assert!(client.protocols.finish(out));
+
+    let mut received = 0;
+    loop {
+        match server.next_received().await? {
+            Activity::Data(_, data) => received += data.len(),
+            Activity::Close(_, None) => break,
+            Activity::Open(..) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    // 60 kB at 20 kB/s with an up-to-one-second burst takes at least 2s
+    assert_eq!(received, 60_000);
+    assert!(
+        started.elapsed() >= Duration::from_millis(1800),
+        "{:?}",
+        started.elapsed()
+    );
+
+    client.shutdown().await?;
+    server.shutdown().await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrent_stream_cap_queues_extra_streams() -> anyhow::Result<()> {
+    let mut server = bind("cap-server").await?;
+    let mut client = bind("cap-client").await?;
+    client.protocols.set_limits(TransferLimits {
+        max_concurrent_streams: Some(1),
+        ..Default::default()
+    });
+
+    let server_addr = server.addr().await;
+    let streams = [
+        open(&client, server_addr.clone())?,
+        open(&client, server_addr)?,
+    ];
+    for stream in streams {
+        assert!(client.protocols.write(stream, b"x".to_vec()));
+        assert!(client.protocols.finish(stream));
+    }
+
+    // Either stream may win the only slot
+    let Activity::Open(first, _) = client.next_received().await? else {
+        panic!("expected a stream to open");
+    };
+    let second = if first == streams[0] {
+        streams[1]
+    } else {
+        streams[0]
+    };
+
+    let Activity::Open(incoming, _) = server.next_received().await? else {
+        panic!("expected the server stream to open");
+    };
+    assert_eq!(
+        server.next_received().await?,
+        Activity::Data(incoming, b"x".to_vec())
+    );
+    assert_eq!(
+        server.next_received().await?,
+        Activity::Close(incoming, None)
+    );
+    assert!(
+        tokio::time::timeout(Duration::from_millis(300), client.received.recv())
+            .await
+            .is_err(),
+        "second stream opened while the first was still running"
+    );
+
+    // Finishing the first stream from the server side too frees the slot
+    assert!(server.protocols.finish(incoming));
+    assert_eq!(client.next_received().await?, Activity::Close(first, None));
+    assert_eq!(
+        client.next_received().await?,
+        Activity::Open(second, server.endpoint.node_id())
+    );
+
+    client.shutdown().await?;
+    server.shutdown().await
+}
+
+/// A lent buffer, flagging when the stream releases it
+struct Lent {
+    data: Vec<u8>,
+    released: Arc<AtomicBool>,
+}
+
+impl AsRef<[u8]> for Lent {
+    fn as_ref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Drop for Lent {
+    fn drop(&mut self) {
+        self.released.store(true, Ordering::SeqCst);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lent_buffers_are_released_once_delivered() -> anyhow::Result<()> {
+    let mut server = bind("proto-lend-server").await?;
+    let mut client = bind("proto-lend-client").await?;
+
+    let payload: Vec<u8> = (0..3 * MAX_BUFFERED_WRITE)
+        .map(|i| (i % 251) as u8)
+        .collect();
+    let released = Arc::new(AtomicBool::new(false));
+    let lent = Bytes::from_owner(Lent {
+        data: payload.clone(),
+        released: released.clone(),
+    });
+    let out = client
+        .protocols
+        .open_send_stream(&client.endpoint, server.addr().await, ALPN)?;
+    assert!(client.protocols.write_buffered(out, lent).await);
+    assert!(client.protocols.finish(out));
+
+    let Activity::Open(incoming, _) = server.next_received().await? else {
+        panic!("expected the server stream to open first");
+    };
+    let mut received = Vec::new();
+    loop {
+        match server.next_received().await? {
+            Activity::Data(stream, data) if stream == incoming => received.extend(data),
+            Activity::Close(stream, None) if stream == incoming => break,
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+    assert!(received == payload, "payload arrived changed");
+
+    client.next_received().await?;
+    assert_eq!(client.next_received().await?, Activity::Close(out, None));
+    assert!(released.load(Ordering::SeqCst), "buffer still held");
+
+    // A buffer the stream can't take is released right away
+    let released = Arc::new(AtomicBool::new(false));
+    let late = Bytes::from_owner(Lent {
+        data: b"late".to_vec(),
+        released: released.clone(),
+    });
+    assert!(!client.protocols.write(out, late));
+    assert!(released.load(Ordering::SeqCst));
+
+    client.shutdown().await?;
+    server.shutdown().await
+}
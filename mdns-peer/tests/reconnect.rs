@@ -0,0 +1,110 @@
+//! Re-dialing trusted peers after the app resumes
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use iroh::{Endpoint, NodeId, RelayMode, SecretKey, Watcher};
+use mdns_peer::options::WarmUp;
+use mdns_peer::protocols::{Protocols, StreamHandler, StreamId};
+use mdns_peer::{PeerEvent, PeerOptions};
+use tokio::sync::{broadcast, mpsc};
+
+const ALPN: &[u8] = b"mdns-peer/test-reconnect/0";
+const DEADLINE: Duration = Duration::from_secs(20);
+
+struct Ignore;
+
+impl StreamHandler for Ignore {
+    fn on_open(&self, _stream: StreamId, _node_id: NodeId) {}
+
+    fn on_data(&self, _stream: StreamId, _data: &[u8]) {}
+
+    fn on_close(&self, _stream: StreamId, _error: Option<&str>) {}
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn resume_redials_trusted_peers_and_reports_each() -> anyhow::Result<()> {
+    // Only reachable through its paired address, so nothing connects to it
+    // before the resume
+    let server = Endpoint::builder()
+        .relay_mode(RelayMode::Disabled)
+        .bind()
+        .await?;
+    let server_protocols = Protocols::default();
+    server_protocols.register(ALPN, Arc::new(Ignore));
+    let router = server_protocols.spawn_router(server.clone());
+    let server_addr = tokio::time::timeout(DEADLINE, server.node_addr().initialized()).await?;
+    let unreachable = SecretKey::from_bytes(&[9; 32]).public();
+
+    let options = PeerOptions {
+        summary_interval: None,
+        warm_up: Some(WarmUp {
+            alpn: ALPN.to_vec(),
+            trusted: [server.node_id(), unreachable].into(),
+        }),
+        paired: vec![server_addr],
+        ..Default::default()
+    };
+    options.protocols.register(ALPN, Arc::new(Ignore));
+    let resume = options.resume.clone();
+    let (tx, mut events) = mpsc::unbounded_channel();
+    let sink: mdns_peer::EventSink = Arc::new(move |event: &PeerEvent| {
+        if matches!(event, PeerEvent::Reconnect { .. }) {
+            let _ = tx.send(event.clone());
+        }
+    });
+    let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+    let client = mdns_peer::bind_endpoint_with("reconnect-client", &options).await?;
+    let peer = tokio::spawn(mdns_peer::run_endpoint(
+        "reconnect-client",
+        client,
+        options,
+        shutdown_rx,
+        sink,
+    ));
+
+    // Resuming before the peer is listening is a no-op, so keep nudging
+    let first = tokio::time::timeout(DEADLINE, async {
+        loop {
+            resume.resume();
+            tokio::select! {
+                event = events.recv() => break event.expect("sink dropped"),
+                _ = tokio::time::sleep(Duration::from_millis(100)) => {}
+            }
+        }
+    })
+    .await?;
+
+    // A nudge may have started a second round, so keep the first report
+    // per peer
+    let mut reports = BTreeMap::new();
+    let mut event = first;
+    loop {
+        match event {
+            PeerEvent::Reconnect {
+                node_id,
+                connected,
+                error,
+            } => {
+                reports
+                    .entry(node_id)
+                    .or_insert((connected, error.is_some()));
+            }
+            other => panic!("expected reconnect, got {other:?}"),
+        }
+        if reports.len() == 2 {
+            break;
+        }
+        event = tokio::time::timeout(DEADLINE, events.recv())
+            .await?
+            .expect("sink dropped");
+    }
+    assert_eq!(reports[&server.node_id()], (true, false));
+    assert_eq!(reports[&unreachable], (false, true));
+
+    shutdown_tx.send(())?;
+    tokio::time::timeout(DEADLINE, peer).await???;
+    router.shutdown().await?;
+    Ok(())
+}
@@ -0,0 +1,389 @@
+//! Registry, dedup and event behavior driven by scripted discovery events
+//!
+//! No network access: events are synthesized and fed through
+//! `run_discovery_loop` via a scripted source.
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use common::node;
+use iroh::{
+    discovery::{DiscoveryEvent, DiscoveryItem, Lagged, NodeInfo},
+    NodeId,
+};
+use mdns_peer::budget::{DropPolicy, MemoryBudget};
+use mdns_peer::events::{DiscoveryOrigin, MemoryPool};
+use mdns_peer::{discovery, registry::PeerRegistry, PeerEvent};
+use n0_future::{stream, StreamExt};
+
+mod common;
+
+fn discovered(node_id: NodeId, user_data: Option<&str>) -> DiscoveryEvent {
+    let info = NodeInfo::new(node_id).with_user_data(user_data.map(|d| d.parse().unwrap()));
+    DiscoveryEvent::Discovered(DiscoveryItem::new(info, "mdns", None))
+}
+
+/// Run a scripted sequence through the discovery loop, returning the
+/// reported events and the final registry
+async fn replay(me: NodeId, script: Vec<DiscoveryEvent>) -> (Vec<PeerEvent>, PeerRegistry) {
+    replay_into(PeerRegistry::new(), me, script).await
+}
+
+/// Like [`replay`], starting from `registry`
+async fn replay_into(
+    registry: PeerRegistry,
+    me: NodeId,
+    script: Vec<DiscoveryEvent>,
+) -> (Vec<PeerEvent>, PeerRegistry) {
+    let registry = Arc::new(Mutex::new(registry));
+    let mut events = Vec::new();
+    discovery::run_discovery_loop(
+        discovery::scripted_source(script),
+        me,
+        registry.clone(),
+        |event| events.push(event),
+    )
+    .await;
+    let registry = Arc::into_inner(registry).unwrap().into_inner().unwrap();
+    (events, registry)
+}
+
+#[tokio::test]
+async fn repeated_announcements_are_reported_once() {
+    let (me, alice) = (node(1), node(2));
+    let script = vec![
+        discovered(alice, Some("alice")),
+        discovered(alice, Some("alice")),
+        discovered(alice, Some("alice")),
+    ];
+
+    let (events, registry) = replay(me, script).await;
+
+    assert_eq!(
+        events,
+        vec![PeerEvent::Discovered {
+            node_id: alice,
+            user_data: Some("alice".to_string()),
+            provenance: "mdns",
+            origin: DiscoveryOrigin::default(),
+        }]
+    );
+    assert_eq!(registry.len(), 1);
+    assert_eq!(registry.get(&alice).unwrap().announcements, 3);
+}
+
+#[tokio::test]
+async fn user_data_change_is_reported_again() {
+    let (me, alice) = (node(1), node(2));
+    let script = vec![
+        discovered(alice, Some("alice")),
+        discovered(alice, Some("alice-laptop")),
+    ];
+
+    let (events, registry) = replay(me, script).await;
+
+    assert_eq!(events.len(), 2);
+    assert_eq!(
+        registry.get(&alice).unwrap().user_data.as_deref(),
+        Some("alice-laptop")
+    );
+}
+
+#[tokio::test]
+async fn self_discovery_is_ignored() {
+    let me = node(1);
+    let script = vec![discovered(me, Some("me")), DiscoveryEvent::Expired(me)];
+
+    let (events, registry) = replay(me, script).await;
+
+    assert!(events.is_empty());
+    assert!(registry.is_empty());
+}
+
+#[tokio::test]
+async fn expiry_removes_known_peers_only() {
+    let (me, alice, bob) = (node(1), node(2), node(3));
+    let script = vec![
+        discovered(alice, Some("alice")),
+        DiscoveryEvent::Expired(bob),
+        DiscoveryEvent::Expired(alice),
+        DiscoveryEvent::Expired(alice),
+    ];
+
+    let (events, registry) = replay(me, script).await;
+
+    assert_eq!(
+        events,
+        vec![
+            PeerEvent::Discovered {
+                node_id: alice,
+                user_data: Some("alice".to_string()),
+                provenance: "mdns",
+                origin: DiscoveryOrigin::default(),
+            },
+            PeerEvent::Expired {
+                node_id: alice,
+                user_data: Some("alice".to_string()),
+            },
+        ]
+    );
+    assert!(registry.is_empty());
+}
+
+#[tokio::test]
+async fn rediscovery_after_expiry_is_reported() {
+    let (me, alice) = (node(1), node(2));
+    let script = vec![
+        discovered(alice, Some("alice")),
+        DiscoveryEvent::Expired(alice),
+        discovered(alice, Some("alice")),
+    ];
+    let mut registry = PeerRegistry::new();
+    registry.set_expiry_grace(Duration::ZERO);
+
+    let (events, registry) = replay_into(registry, me, script).await;
+
+    assert_eq!(events.len(), 3);
+    assert_eq!(registry.get(&alice).unwrap().announcements, 1);
+}
+
+#[tokio::test]
+async fn quick_rediscovery_is_reported_as_a_flap() {
+    let (me, alice) = (node(1), node(2));
+    let script = vec![
+        discovered(alice, Some("alice")),
+        DiscoveryEvent::Expired(alice),
+        discovered(alice, Some("alice")),
+        DiscoveryEvent::Expired(alice),
+        discovered(alice, Some("alice")),
+    ];
+
+    let (events, registry) = replay(me, script).await;
+
+    assert_eq!(events.len(), 3);
+    assert!(matches!(
+        events[2],
+        PeerEvent::Flapped { node_id, flaps: 2, .. } if node_id == alice
+    ));
+    let entry = registry.get(&alice).unwrap();
+    assert_eq!(entry.flaps, 2);
+    assert_eq!(entry.expired_at, None);
+}
+
+#[tokio::test]
+async fn expiry_is_reported_once_the_grace_period_ends() {
+    let (me, alice) = (node(1), node(2));
+    let mut registry = PeerRegistry::new();
+    registry.set_expiry_grace(Duration::from_millis(200));
+    let registry = Arc::new(Mutex::new(registry));
+    // Discovery stays open, so only the grace period can end the expiry
+    let script = stream::iter([
+        discovered(alice, Some("alice")),
+        DiscoveryEvent::Expired(alice),
+    ])
+    .map(Ok::<_, Lagged>)
+    .chain(stream::pending());
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let seen = events.clone();
+    let task = tokio::spawn(discovery::run_discovery_loop(
+        Box::pin(script),
+        me,
+        registry.clone(),
+        move |event| seen.lock().unwrap().push(event),
+    ));
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(events.lock().unwrap().len(), 1);
+    assert_eq!(registry.lock().unwrap().len(), 1);
+
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(
+        events.lock().unwrap().last(),
+        Some(&PeerEvent::Expired {
+            node_id: alice,
+            user_data: Some("alice".to_string()),
+        })
+    );
+    assert!(registry.lock().unwrap().is_empty());
+    task.abort();
+}
+
+#[test]
+fn announcements_carry_their_origin() {
+    let loopback: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+    let remote: SocketAddr = "203.0.113.7:4000".parse().unwrap();
+    let info = NodeInfo::new(node(2)).with_direct_addresses([remote, loopback].into());
+    let origin = discovery::origin(&DiscoveryItem::new(info, "mdns", None));
+
+    // Only the loopback address is on a local subnet
+    assert!(origin.interface.is_some());
+    assert_eq!(origin.addr, Some(loopback));
+    assert_eq!(origin.direct_addrs.len(), 2);
+
+    let info = NodeInfo::new(node(2)).with_direct_addresses([remote].into());
+    let origin = discovery::origin(&DiscoveryItem::new(info, "mdns", None));
+    assert_eq!(origin.interface, None);
+    assert_eq!(origin.addr, None);
+}
+
+#[tokio::test]
+async fn peers_without_user_data_are_tracked() {
+    let (me, legacy) = (node(1), node(4));
+
+    let (events, registry) = replay(me, vec![discovered(legacy, None)]).await;
+
+    assert_eq!(
+        events,
+        vec![PeerEvent::Discovered {
+            node_id: legacy,
+            user_data: None,
+            provenance: "mdns",
+            origin: DiscoveryOrigin::default(),
+        }]
+    );
+    assert_eq!(registry.len(), 1);
+}
+
+#[tokio::test]
+async fn warm_state_is_tracked_for_known_peers_only() {
+    let (me, alice, stranger) = (node(1), node(2), node(5));
+
+    let (_, mut registry) = replay(me, vec![discovered(alice, Some("alice"))]).await;
+    assert!(!registry.get(&alice).unwrap().warm);
+
+    registry.set_warm(&alice, true);
+    registry.set_warm(&stranger, true);
+
+    assert!(registry.get(&alice).unwrap().warm);
+    assert!(registry.get(&stranger).is_none());
+}
+
+#[tokio::test]
+async fn expiry_deadline_follows_last_announcement_and_swarm_size() {
+    let me = node(1);
+
+    let (_, small) = replay(me, vec![discovered(node(2), Some("alice"))]).await;
+    let entry = small.get(&node(2)).unwrap();
+    // Two nodes share the responses, so each is expected every 0.7s
+    assert_eq!(small.record_ttl().as_millis(), 2100);
+    assert_eq!(
+        small.expires_at(entry),
+        entry.last_seen + small.record_ttl()
+    );
+
+    let script = (2..12).map(|seed| discovered(node(seed), None)).collect();
+    let (_, large) = replay(me, script).await;
+    // Eleven nodes still get two responses per 0.7s between them
+    assert_eq!(large.record_ttl().as_millis(), 11550);
+}
+
+#[tokio::test]
+async fn time_asleep_expires_peers_past_their_deadline() {
+    let (me, alice) = (node(1), node(2));
+    let (_, mut registry) = replay(me, vec![discovered(alice, Some("alice"))]).await;
+    let last_seen = registry.get(&alice).unwrap().last_seen;
+
+    // Well within the 2.1s TTL: kept, but closer to expiring
+    assert!(registry.age(Duration::from_millis(500)).is_empty());
+    assert!(registry.get(&alice).unwrap().last_seen < last_seen);
+
+    assert_eq!(
+        registry.age(Duration::from_secs(60)),
+        [PeerEvent::Expired {
+            node_id: alice,
+            user_data: Some("alice".to_string()),
+        }]
+    );
+    assert!(registry.is_empty());
+}
+
+fn budgeted(discovered_peers: usize, policy: DropPolicy) -> PeerRegistry {
+    let mut registry = PeerRegistry::new();
+    registry.set_budget(&MemoryBudget {
+        discovered_peers,
+        policy,
+        ..MemoryBudget::default()
+    });
+    registry
+}
+
+#[tokio::test]
+async fn a_full_registry_leaves_new_peers_out() {
+    let (me, alice, bob, carol) = (node(1), node(2), node(3), node(4));
+    let script = vec![
+        discovered(alice, None),
+        discovered(bob, None),
+        discovered(carol, None),
+        // Known peers are still refreshed
+        discovered(alice, Some("alice")),
+    ];
+
+    let registry = budgeted(2, DropPolicy::RefuseNew);
+    let (events, registry) = replay_into(registry, me, script).await;
+
+    assert_eq!(events.len(), 4);
+    assert_eq!(
+        events[2],
+        PeerEvent::OverBudget {
+            pool: MemoryPool::DiscoveredPeers,
+            limit: 2,
+            dropped: 1,
+        }
+    );
+    assert!(matches!(&events[3], PeerEvent::Discovered { node_id, .. } if *node_id == alice));
+    assert_eq!(registry.len(), 2);
+    assert!(registry.get(&carol).is_none());
+}
+
+#[tokio::test]
+async fn a_full_registry_can_push_out_the_stalest_peer() {
+    let (me, alice, bob, carol) = (node(1), node(2), node(3), node(4));
+    let script = vec![
+        discovered(alice, Some("alice")),
+        discovered(bob, Some("bob")),
+        discovered(carol, Some("carol")),
+    ];
+
+    let registry = budgeted(2, DropPolicy::DropOldest);
+    let (events, registry) = replay_into(registry, me, script).await;
+
+    assert_eq!(
+        events[2..],
+        [
+            PeerEvent::Expired {
+                node_id: alice,
+                user_data: Some("alice".to_string()),
+            },
+            PeerEvent::OverBudget {
+                pool: MemoryPool::DiscoveredPeers,
+                limit: 2,
+                dropped: 1,
+            },
+            PeerEvent::Discovered {
+                node_id: carol,
+                user_data: Some("carol".to_string()),
+                provenance: "mdns",
+                origin: DiscoveryOrigin::default(),
+            },
+        ]
+    );
+    assert_eq!(registry.len(), 2);
+    assert!(registry.get(&alice).is_none());
+}
+
+#[tokio::test]
+async fn peers_can_be_looked_up_by_user_data() {
+    let (me, alice, legacy) = (node(1), node(2), node(3));
+    let script = vec![discovered(alice, Some("alice")), discovered(legacy, None)];
+
+    let (_, mut registry) = replay(me, script).await;
+
+    assert!(registry.has_user_data("alice"));
+    assert!(!registry.has_user_data("ALICE"));
+    assert!(!registry.has_user_data(""));
+
+    registry.clear();
+    assert!(registry.is_empty());
+    assert!(!registry.has_user_data("alice"));
+}
@@ -0,0 +1,64 @@
+//! Relay configuration and latency probing against local listeners
+
+use iroh::RelayUrl;
+use mdns_peer::relay::{configured_relays, probe_relays};
+use tokio::net::TcpListener;
+
+#[test]
+fn configured_relays_replace_the_defaults() {
+    let defaults = configured_relays(&mdns_peer::PeerOptions::default());
+    assert!(!defaults.is_empty());
+
+    let custom: RelayUrl = "https://relay.example.com".parse().unwrap();
+    let options = mdns_peer::PeerOptions {
+        relays: Some(vec![custom.clone()]),
+        ..Default::default()
+    };
+    assert_eq!(configured_relays(&options), vec![custom]);
+}
+
+#[test]
+fn lan_only_disables_every_relay() {
+    let options = mdns_peer::PeerOptions {
+        relays: Some(vec!["https://relay.example.com".parse().unwrap()]),
+        lan_only: true,
+        ..Default::default()
+    };
+    assert!(configured_relays(&options).is_empty());
+}
+
+#[tokio::test]
+async fn reachable_relays_are_listed_first_with_a_latency() -> anyhow::Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            drop(stream);
+        }
+    });
+
+    // Nothing listens on a port we just freed
+    let closed = std::net::TcpListener::bind("127.0.0.1:0")?
+        .local_addr()?
+        .port();
+
+    let up: RelayUrl = format!("http://127.0.0.1:{}", port).parse()?;
+    let down: RelayUrl = format!("http://127.0.0.1:{}", closed).parse()?;
+    let report = probe_relays(vec![down.clone(), up.clone()], Some(up.clone())).await;
+
+    assert_eq!(report.home_relay, Some(up.clone()));
+    assert_eq!(report.relays.len(), 2);
+
+    let first = &report.relays[0];
+    assert_eq!(first.url, up);
+    assert!(first.home);
+    assert!(first.latency_ms.is_some());
+    assert!(first.error.is_none());
+
+    let second = &report.relays[1];
+    assert_eq!(second.url, down);
+    assert!(!second.home);
+    assert!(second.latency_ms.is_none());
+    assert!(second.error.is_some());
+    Ok(())
+}
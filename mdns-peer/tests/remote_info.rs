@@ -0,0 +1,35 @@
+//! Addresses of known peers handed to other dialers
+
+use iroh::{Endpoint, NodeAddr, RelayMode, RelayUrl, SecretKey};
+use mdns_peer::pairing::{add_paired_peer, decode_payload};
+use mdns_peer::remote_info::{node_addr, NodeAddrReport};
+
+#[tokio::test]
+async fn node_addr_covers_what_the_endpoint_learned() -> anyhow::Result<()> {
+    let endpoint = Endpoint::builder()
+        .relay_mode(RelayMode::Disabled)
+        .bind()
+        .await?;
+    let remote = SecretKey::from_bytes(&[7; 32]).public();
+    assert_eq!(node_addr(&endpoint, remote), None);
+
+    let relay: RelayUrl = "https://relay.example.com".parse()?;
+    let learned = NodeAddr::from_parts(remote, Some(relay.clone()), ["192.168.1.20:4433".parse()?]);
+    add_paired_peer(&endpoint, learned.clone())?;
+    let addr = node_addr(&endpoint, remote).expect("address was added");
+    assert_eq!(addr, learned);
+
+    let report = NodeAddrReport::from(addr);
+    assert_eq!(decode_payload(&report.ticket)?, learned);
+    let json = serde_json::to_value(&report)?;
+    assert_eq!(json["node_id"], remote.to_string());
+    // iroh writes relay hosts fully qualified, with a trailing dot
+    assert_eq!(json["relay_url"], relay.to_string());
+    assert_eq!(
+        json["direct_addrs"],
+        serde_json::json!(["192.168.1.20:4433"])
+    );
+
+    endpoint.close().await;
+    Ok(())
+}
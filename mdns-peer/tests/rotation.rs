@@ -0,0 +1,94 @@
+//! Signing, checking and delivering key rotation notices
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use iroh::{protocol::Router, Endpoint, RelayMode, SecretKey, Watcher};
+use mdns_peer::notes::PeerNotes;
+use mdns_peer::pairing;
+use mdns_peer::protocol;
+use mdns_peer::protocols::Protocols;
+use mdns_peer::rotation::{self, RotationHandler, ROTATION_ALPN};
+use mdns_peer::PeerEvent;
+use tokio::sync::mpsc;
+
+const DEADLINE: Duration = Duration::from_secs(20);
+
+#[test]
+fn notices_verify_only_for_the_old_node_id() {
+    let old = SecretKey::from_bytes(&[1; 32]).public();
+    let new_key = SecretKey::from_bytes(&[2; 32]);
+    let notice = rotation::sign(old, &new_key);
+    assert_eq!(rotation::verify(old, &notice).unwrap(), new_key.public());
+
+    // Sent from anyone else, or redirected to another key
+    let other = SecretKey::from_bytes(&[3; 32]).public();
+    assert!(rotation::verify(other, &notice).is_err());
+    let mut redirected = notice.clone();
+    redirected.new_node_id = *other.as_bytes();
+    assert!(rotation::verify(old, &redirected).is_err());
+
+    let encoded = protocol::encode(&notice);
+    let (decoded, _) = protocol::decode(&encoded).unwrap().unwrap();
+    assert_eq!(notice, decoded);
+}
+
+#[test]
+fn rotating_to_the_same_key_is_rejected() {
+    let key = SecretKey::from_bytes(&[1; 32]);
+    let notice = rotation::sign(key.public(), &key);
+    assert!(rotation::verify(key.public(), &notice).is_err());
+}
+
+#[tokio::test]
+async fn trusted_peers_follow_a_rotation() -> anyhow::Result<()> {
+    let old = Endpoint::builder()
+        .secret_key(SecretKey::from_bytes(&[1; 32]))
+        .relay_mode(RelayMode::Disabled)
+        .bind()
+        .await?;
+    let receiver = Endpoint::builder()
+        .relay_mode(RelayMode::Disabled)
+        .bind()
+        .await?;
+
+    let protocols = Protocols::default();
+    protocols.set_trusted(HashSet::from([old.node_id()]));
+    let (tx, mut events) = mpsc::unbounded_channel();
+    protocols.set_event_sink(Arc::new(move |event: &PeerEvent| {
+        let _ = tx.send(event.clone());
+    }));
+    let notes = PeerNotes::default();
+    notes.set_alias(old.node_id(), Some("Laptop"))?;
+    let router = Router::builder(receiver.clone())
+        .accept(
+            ROTATION_ALPN,
+            RotationHandler::new(protocols.clone(), notes.clone()),
+        )
+        .spawn();
+
+    let addr = tokio::time::timeout(DEADLINE, receiver.node_addr().initialized()).await?;
+    pairing::add_paired_peer(&old, addr)?;
+    let new_key = SecretKey::from_bytes(&[2; 32]);
+    let notice = rotation::sign(old.node_id(), &new_key);
+    rotation::announce(&old, receiver.node_id(), &notice).await?;
+
+    let event = tokio::time::timeout(DEADLINE, events.recv()).await?;
+    assert_eq!(
+        event,
+        Some(PeerEvent::KeyRotated {
+            old_node_id: old.node_id(),
+            new_node_id: new_key.public(),
+            trusted: true,
+        })
+    );
+    assert!(protocols.is_trusted(new_key.public()));
+    assert!(!protocols.is_trusted(old.node_id()));
+    assert_eq!(notes.get(new_key.public()).alias.as_deref(), Some("Laptop"));
+    assert_eq!(notes.get(old.node_id()).alias, None);
+
+    router.shutdown().await?;
+    old.close().await;
+    Ok(())
+}
@@ -0,0 +1,105 @@
+//! The event JSON schema agrees with the events actually sent
+
+use iroh::SecretKey;
+use mdns_peer::connections::Direction;
+use mdns_peer::events::{DiscoveryOrigin, LocalAddrs, PeerSummary, TimedEvent};
+use mdns_peer::remote_info::ConnectionReport;
+use mdns_peer::schema::{check_event, event_schema, EVENT_SCHEMA_VERSION};
+use mdns_peer::transfer::TransferOutcome;
+use mdns_peer::{PeerEvent, PeerStatus, ShutdownReason};
+use serde_json::json;
+
+fn timed_json(event: PeerEvent) -> serde_json::Value {
+    serde_json::to_value(TimedEvent::now(event)).unwrap()
+}
+
+#[test]
+fn sent_events_match_the_schema() {
+    let node_id = SecretKey::from_bytes(&[7; 32]).public();
+    let events = [
+        PeerEvent::Discovered {
+            node_id,
+            user_data: Some("alice".to_string()),
+            provenance: "mdns",
+            origin: DiscoveryOrigin::default(),
+        },
+        PeerEvent::status(PeerStatus::Running),
+        PeerEvent::stopped(ShutdownReason::RuntimePanic),
+        PeerEvent::Summary {
+            routing_table_size: 1,
+            peers: vec![PeerSummary {
+                node_id,
+                user_data: None,
+                alias: Some("laptop".to_string()),
+                notes: None,
+                connection: ConnectionReport::Direct {
+                    addr: "192.168.1.20:4433".parse().unwrap(),
+                },
+                warm: true,
+                expires_in_ms: 2000,
+            }],
+        },
+        PeerEvent::LocalAddrsChanged {
+            previous: LocalAddrs {
+                direct_addrs: Vec::new(),
+                home_relay: Vec::new(),
+            },
+            current: LocalAddrs {
+                direct_addrs: vec!["10.0.0.2:4433".parse().unwrap()],
+                home_relay: vec!["https://relay.example.com".to_string()],
+            },
+        },
+        PeerEvent::PathChanged {
+            node_id,
+            previous: ConnectionReport::None,
+            current: ConnectionReport::Relay {
+                url: "https://relay.example.com".to_string(),
+            },
+            previous_interface: None,
+            current_interface: Some("en0".to_string()),
+        },
+        PeerEvent::TransferFinished {
+            id: 3,
+            node_id,
+            name: "photo.jpg".to_string(),
+            direction: Direction::Outbound,
+            outcome: TransferOutcome::Completed,
+            bytes: 1024,
+            hash: None,
+            error: None,
+        },
+        PeerEvent::DocChanged {
+            doc: "list".to_string(),
+            key: "milk".to_string(),
+            node_id: None,
+        },
+    ];
+    for event in events {
+        let json = timed_json(event);
+        assert_eq!(check_event(&json), Ok(()), "{}", json);
+    }
+}
+
+#[test]
+fn mismatched_events_are_caught() {
+    let mut json = timed_json(PeerEvent::status(PeerStatus::Running));
+    json.as_object_mut().unwrap().remove("status");
+    assert!(check_event(&json).is_err());
+
+    let mut json = timed_json(PeerEvent::status(PeerStatus::Running));
+    json["monotonic_ms"] = json!("soon");
+    assert!(check_event(&json).is_err());
+
+    assert!(check_event(&json!({"type": "teleported"})).is_err());
+    assert!(check_event(&json!({})).is_err());
+}
+
+#[test]
+fn schema_is_versioned() {
+    let schema = event_schema();
+    assert_eq!(schema["version"], json!(EVENT_SCHEMA_VERSION));
+    assert!(schema["$id"]
+        .as_str()
+        .unwrap()
+        .ends_with(&format!("v{}.json", EVENT_SCHEMA_VERSION)));
+}
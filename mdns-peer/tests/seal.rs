@@ -0,0 +1,53 @@
+//! Sealing payloads between two peers
+
+use iroh::SecretKey;
+use mdns_peer::seal::{PairKey, SEAL_OVERHEAD};
+
+#[test]
+fn both_sides_derive_the_same_key() {
+    let alice = SecretKey::from_bytes(&[1; 32]);
+    let bob = SecretKey::from_bytes(&[2; 32]);
+
+    let sealed = PairKey::new(&alice, &bob.public()).seal(b"meet at noon");
+    assert_eq!(sealed.len(), b"meet at noon".len() + SEAL_OVERHEAD);
+    assert_eq!(
+        PairKey::new(&bob, &alice.public()).open(&sealed).unwrap(),
+        b"meet at noon"
+    );
+    // Alice can open what she stored for Bob, too
+    assert_eq!(
+        PairKey::new(&alice, &bob.public()).open(&sealed).unwrap(),
+        b"meet at noon"
+    );
+}
+
+#[test]
+fn sealing_twice_gives_different_ciphertexts() {
+    let key = PairKey::new(
+        &SecretKey::from_bytes(&[1; 32]),
+        &SecretKey::from_bytes(&[2; 32]).public(),
+    );
+    assert_ne!(key.seal(b"same"), key.seal(b"same"));
+}
+
+#[test]
+fn other_keys_and_tampering_are_refused() {
+    let alice = SecretKey::from_bytes(&[1; 32]);
+    let bob = SecretKey::from_bytes(&[2; 32]);
+    let mallory = SecretKey::from_bytes(&[3; 32]);
+    let sealed = PairKey::new(&alice, &bob.public()).seal(b"secret");
+
+    assert!(PairKey::new(&mallory, &alice.public())
+        .open(&sealed)
+        .is_err());
+
+    let bob_key = PairKey::new(&bob, &alice.public());
+    let mut tampered = sealed.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert!(bob_key.open(&tampered).is_err());
+    assert!(bob_key.open(&sealed[..10]).is_err());
+    assert!(bob_key.open(&[]).is_err());
+    let mut future = sealed;
+    future[0] = 2;
+    assert!(bob_key.open(&future).is_err());
+}
@@ -0,0 +1,33 @@
+//! Loopback self-test
+//!
+//! Needs multicast to work on the machine running the tests, like the
+//! discovery tests.
+
+use std::time::Duration;
+
+use mdns_peer::mdns::MdnsOptions;
+use mdns_peer::self_test::{run_self_test, DEFAULT_STEP_TIMEOUT};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn temporary_peers_discover_and_message_each_other() {
+    let report = run_self_test(&MdnsOptions::default(), DEFAULT_STEP_TIMEOUT).await;
+    assert!(report.passed, "{:#?}", report);
+    let steps: Vec<_> = report.steps.iter().map(|step| step.name).collect();
+    assert_eq!(steps, ["start", "discover", "message"]);
+    assert!(report.steps.iter().all(|step| step.hint.is_none()));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failures_stop_at_the_failed_step_with_a_hint() {
+    // Nothing can be discovered in no time at all
+    let report = run_self_test(&MdnsOptions::default(), Duration::ZERO).await;
+    assert!(!report.passed);
+    let last = report.steps.last().unwrap();
+    assert_eq!(last.name, "discover");
+    assert!(!last.passed);
+    assert!(last.hint.is_some());
+
+    let json = serde_json::to_value(&report).unwrap();
+    assert_eq!(json["passed"], false);
+    assert_eq!(json["steps"][1]["name"], "discover");
+}
@@ -0,0 +1,138 @@
+//! Recording discovery events and replaying them through the registry
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use common::node;
+use iroh::{
+    discovery::{DiscoveryEvent, DiscoveryItem, NodeInfo},
+    NodeId,
+};
+use mdns_peer::events::DiscoveryOrigin;
+use mdns_peer::session::{Session, SessionRecorder};
+use mdns_peer::{discovery, registry::PeerRegistry, PeerEvent};
+
+mod common;
+
+fn discovered(node_id: NodeId, user_data: &str) -> DiscoveryEvent {
+    let info = NodeInfo::new(node_id)
+        .with_user_data(Some(user_data.parse().unwrap()))
+        .with_direct_addresses(["192.168.1.20:51234".parse().unwrap()].into());
+    DiscoveryEvent::Discovered(DiscoveryItem::new(info, "mdns", None))
+}
+
+fn script(me: NodeId) -> Vec<DiscoveryEvent> {
+    vec![
+        discovered(me, "me"),
+        discovered(node(2), "alice"),
+        discovered(node(2), "alice"),
+        discovered(node(3), "bob"),
+        discovered(node(2), "alice-renamed"),
+        DiscoveryEvent::Expired(node(3)),
+    ]
+}
+
+async fn live_events(me: NodeId, script: Vec<DiscoveryEvent>) -> Vec<PeerEvent> {
+    let mut events = Vec::new();
+    discovery::run_discovery_loop(
+        discovery::scripted_source(script),
+        me,
+        Arc::new(Mutex::new(PeerRegistry::new())),
+        |event| events.push(event),
+    )
+    .await;
+    events
+}
+
+#[tokio::test]
+async fn replay_reproduces_recorded_events() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("session.ndjson");
+    let me = node(1);
+
+    let recorder = SessionRecorder::create(&path, me, Some("me"))?;
+    for event in script(me) {
+        recorder.record(&event);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    drop(recorder);
+
+    let session = Session::read(&path)?;
+    assert_eq!(session.node_id, me);
+    assert_eq!(session.user_data.as_deref(), Some("me"));
+    assert_eq!(session.events.len(), 6);
+    assert!(session.events[5].0 >= Duration::from_millis(250));
+
+    let mut replayed = Vec::new();
+    let started = Instant::now();
+    session.replay(1.0, |event| replayed.push(event)).await;
+    assert!(started.elapsed() >= Duration::from_millis(250));
+
+    assert_eq!(replayed, live_events(me, script(me)).await);
+    assert_eq!(replayed.len(), 4);
+    Ok(())
+}
+
+#[tokio::test]
+async fn replay_can_be_accelerated() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("session.ndjson");
+    std::fs::write(
+        &path,
+        format!(
+            "{{\"type\":\"start\",\"node_id\":\"{me}\",\"user_data\":null}}\n\
+             {{\"type\":\"discovered\",\"at_ms\":0,\"node_id\":\"{a}\",\"user_data\":\"alice\",\
+             \"provenance\":\"local.swarm.discovery\",\"relay_url\":null,\"direct_addrs\":[]}}\n\
+             {{\"type\":\"expired\",\"at_ms\":60000,\"node_id\":\"{a}\"}}\n",
+            me = node(1),
+            a = node(2),
+        ),
+    )?;
+
+    let mut replayed = Vec::new();
+    let started = Instant::now();
+    Session::read(&path)?
+        .replay(1000.0, |event| replayed.push(event))
+        .await;
+
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(
+        replayed,
+        vec![
+            PeerEvent::Discovered {
+                node_id: node(2),
+                user_data: Some("alice".to_string()),
+                provenance: "local.swarm.discovery",
+                origin: DiscoveryOrigin::default(),
+            },
+            PeerEvent::Expired {
+                node_id: node(2),
+                user_data: Some("alice".to_string()),
+            },
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn invalid_recording_is_rejected() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("session.ndjson");
+
+    std::fs::write(
+        &path,
+        "{\"type\":\"expired\",\"at_ms\":0,\"node_id\":\"nope\"}\n",
+    )?;
+    assert!(Session::read(&path).is_err());
+
+    // Without a start line there's no way to skip the recorder's own events
+    std::fs::write(
+        &path,
+        format!(
+            "{{\"type\":\"expired\",\"at_ms\":0,\"node_id\":\"{}\"}}\n",
+            node(2)
+        ),
+    )?;
+    assert!(Session::read(&path).is_err());
+    Ok(())
+}
@@ -0,0 +1,93 @@
+//! Saving the discovered peer set and comparing two snapshots
+
+use std::time::Duration;
+
+use common::node;
+use iroh::{
+    discovery::{DiscoveryEvent, DiscoveryItem, NodeInfo},
+    NodeId,
+};
+use mdns_peer::discovery;
+use mdns_peer::snapshot::{Snapshot, SnapshotDiff};
+
+mod common;
+
+fn discovered(node_id: NodeId, user_data: &str, addr: &str) -> DiscoveryEvent {
+    let info = NodeInfo::new(node_id)
+        .with_user_data(Some(user_data.parse().unwrap()))
+        .with_direct_addresses([addr.parse().unwrap()].into());
+    DiscoveryEvent::Discovered(DiscoveryItem::new(info, "mdns", None))
+}
+
+async fn snapshot(events: Vec<DiscoveryEvent>) -> Snapshot {
+    let source = discovery::scripted_source(events);
+    Snapshot::collect(
+        source,
+        node(1),
+        Some("me".to_string()),
+        Duration::from_secs(1),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn keeps_the_latest_announcement_of_peers_still_around() -> anyhow::Result<()> {
+    let taken = snapshot(vec![
+        discovered(node(1), "me", "192.168.1.10:4433"),
+        discovered(node(2), "alice", "192.168.1.20:4433"),
+        discovered(node(2), "alice", "192.168.1.21:4433"),
+        discovered(node(3), "bob", "192.168.1.30:4433"),
+        DiscoveryEvent::Expired(node(3)),
+    ])
+    .await;
+    assert_eq!(taken.node_id, node(1));
+    assert_eq!(taken.peers.len(), 1);
+    assert_eq!(taken.peers[0].user_data.as_deref(), Some("alice"));
+    assert_eq!(taken.peers[0].direct_addrs, ["192.168.1.21:4433".parse()?]);
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("snapshot.json");
+    taken.write(&path)?;
+    assert_eq!(Snapshot::read(&path)?, taken);
+    Ok(())
+}
+
+#[tokio::test]
+async fn diff_reports_added_removed_and_changed_peers() {
+    let yesterday = snapshot(vec![
+        discovered(node(2), "alice", "192.168.1.20:4433"),
+        discovered(node(3), "bob", "192.168.1.30:4433"),
+        discovered(node(4), "carol", "192.168.1.40:4433"),
+        discovered(node(5), "dave", "192.168.1.50:4433"),
+    ])
+    .await;
+    let today = snapshot(vec![
+        discovered(node(2), "alice", "192.168.1.20:4433"),
+        discovered(node(3), "bob", "10.0.0.30:4433"),
+        // carol restarted without a profile, with a new node ID
+        discovered(node(6), "carol", "192.168.1.40:4433"),
+        discovered(node(7), "erin", "192.168.1.70:4433"),
+    ])
+    .await;
+
+    let diff = SnapshotDiff::new(&yesterday, &today);
+    assert_eq!(diff.unchanged, 1);
+    let changed: Vec<_> = diff
+        .changed
+        .iter()
+        .map(|change| (change.before.node_id, change.after.node_id))
+        .collect();
+    assert_eq!(changed, [(node(3), node(3)), (node(4), node(6))]);
+    assert_eq!(diff.removed.len(), 1);
+    assert_eq!(diff.removed[0].node_id, node(5));
+    assert_eq!(diff.added.len(), 1);
+    assert_eq!(diff.added[0].node_id, node(7));
+
+    let report = diff.to_string();
+    assert!(report.contains("+ erin"));
+    assert!(report.contains("- dave"));
+    assert!(report.contains("~ bob: addresses [192.168.1.30:4433] -> [10.0.0.30:4433];"));
+    assert!(report.ends_with("1 added, 1 removed, 2 changed, 1 unchanged"));
+
+    assert!(SnapshotDiff::new(&today, &today).is_empty());
+}
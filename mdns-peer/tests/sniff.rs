@@ -0,0 +1,122 @@
+//! Decoding the packets shown by `mdns-peer sniff`
+#![cfg(feature = "cli")]
+
+use std::net::Ipv4Addr;
+
+use mdns_peer::sniff::{MdnsPacket, Question, RecordData};
+
+const SERVICE: &str = "iroh.local.swarm";
+
+fn name(labels: &str) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for label in labels.split('.') {
+        encoded.push(label.len() as u8);
+        encoded.extend_from_slice(label.as_bytes());
+    }
+    encoded.push(0);
+    encoded
+}
+
+fn header(flags: u16, questions: u16, answers: u16) -> Vec<u8> {
+    let mut header = vec![0, 0];
+    for field in [flags, questions, answers, 0, 0] {
+        header.extend_from_slice(&field.to_be_bytes());
+    }
+    header
+}
+
+fn record(packet: &mut Vec<u8>, name: &[u8], rtype: u16, rdata: &[u8]) {
+    packet.extend_from_slice(name);
+    packet.extend_from_slice(&rtype.to_be_bytes());
+    packet.extend_from_slice(&0x8001u16.to_be_bytes());
+    packet.extend_from_slice(&120u32.to_be_bytes());
+    packet.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    packet.extend_from_slice(rdata);
+}
+
+/// An announcement of `alice` whose names point back at earlier ones
+fn announcement() -> Vec<u8> {
+    let mut packet = header(0x8400, 0, 4);
+    // The service name sits right after the header, at offset 12
+    let mut ptr = b"\x05alice".to_vec();
+    ptr.extend_from_slice(&[0xc0, 12]);
+    let service = name(&format!("_{}._udp.local", SERVICE));
+    let alice = (12 + service.len() + 10) as u8;
+    record(&mut packet, &service, 12, &ptr);
+
+    let mut srv = vec![0, 0, 0, 0];
+    srv.extend_from_slice(&11204u16.to_be_bytes());
+    srv.extend_from_slice(&name("host.local"));
+    record(&mut packet, &[0xc0, alice], 33, &srv);
+    record(&mut packet, &[0xc0, alice], 16, b"\x0auser=alice\x00");
+    record(&mut packet, &name("host.local"), 1, &[192, 168, 1, 20]);
+    packet
+}
+
+#[test]
+fn decodes_an_announcement() {
+    let packet = MdnsPacket::parse(&announcement()).unwrap();
+    assert!(packet.response);
+    assert!(packet.questions.is_empty());
+
+    let alice = format!("alice._{}._udp.local", SERVICE);
+    let data: Vec<_> = packet.records.iter().map(|r| r.data.clone()).collect();
+    assert_eq!(
+        data,
+        [
+            RecordData::Ptr(alice.clone()),
+            RecordData::Srv {
+                port: 11204,
+                target: "host.local".to_string()
+            },
+            RecordData::Txt(vec!["user=alice".to_string(), String::new()]),
+            RecordData::A(Ipv4Addr::new(192, 168, 1, 20)),
+        ]
+    );
+    // Compressed names are expanded
+    assert_eq!(packet.records[1].name, alice);
+    assert_eq!(packet.records[1].ttl, 120);
+
+    assert!(packet.mentions(SERVICE));
+    assert!(!packet.mentions("other"));
+    let shown = packet.to_string();
+    assert!(shown.starts_with("response (0 questions, 4 records)"));
+    assert!(shown.contains(&format!("PTR {}", alice)));
+    assert!(shown.contains("SRV host.local:11204"));
+}
+
+#[test]
+fn decodes_a_query() {
+    let mut bytes = header(0, 1, 0);
+    bytes.extend_from_slice(&name("_services._dns-sd._udp.local"));
+    bytes.extend_from_slice(&12u16.to_be_bytes());
+    bytes.extend_from_slice(&0x8001u16.to_be_bytes());
+
+    let packet = MdnsPacket::parse(&bytes).unwrap();
+    assert!(!packet.response);
+    assert_eq!(
+        packet.questions,
+        [Question {
+            name: "_services._dns-sd._udp.local".to_string(),
+            qtype: 12,
+            unicast_response: true,
+        }]
+    );
+    assert!(!packet.mentions(SERVICE));
+    assert!(packet
+        .to_string()
+        .contains("? PTR _services._dns-sd._udp.local (QU)"));
+}
+
+#[test]
+fn rejects_malformed_packets() {
+    assert_eq!(MdnsPacket::parse(&[0; 5]), None);
+
+    let announcement = announcement();
+    assert_eq!(MdnsPacket::parse(&announcement[..40]), None);
+
+    // A name pointing at itself would otherwise never end
+    let mut looping = header(0, 1, 0);
+    looping.extend_from_slice(&[0xc0, 12, 0, 12, 0, 1]);
+    assert_eq!(MdnsPacket::parse(&looping), None);
+}
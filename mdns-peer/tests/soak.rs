@@ -0,0 +1,109 @@
+//! The soak test's resource samples and report
+#![cfg(feature = "cli")]
+
+use std::time::Duration;
+
+use mdns_peer::soak::{run_soak, ResourceSample, SoakOptions, SoakReport, GROWTH_ALLOWANCE};
+
+fn sample(alive_tasks: usize, open_fds: Option<usize>) -> ResourceSample {
+    ResourceSample {
+        rss_kib: None,
+        open_fds,
+        alive_tasks,
+    }
+}
+
+fn report(baseline: ResourceSample, last: ResourceSample) -> SoakReport {
+    SoakReport {
+        cycles: 4,
+        failures: 0,
+        baseline,
+        last,
+    }
+}
+
+#[test]
+fn samples_show_what_the_platform_lacks() {
+    let with_rss = ResourceSample {
+        rss_kib: Some(2048),
+        ..sample(3, None)
+    };
+    assert_eq!(with_rss.to_string(), "rss=2048KiB fds=? tasks=3");
+}
+
+#[test]
+fn failed_cycles_fail_the_report() {
+    let mut report = report(sample(0, None), sample(0, None));
+    report.check().unwrap();
+    report.failures = 1;
+    assert_eq!(
+        report.check().unwrap_err().to_string(),
+        "1 of 4 cycles failed"
+    );
+}
+
+#[test]
+fn growing_tasks_fail_the_report() {
+    let baseline = sample(54, Some(20));
+    report(baseline, sample(54 + GROWTH_ALLOWANCE, Some(20)))
+        .check()
+        .unwrap();
+    // Fewer than at the start is fine too
+    report(baseline, sample(40, Some(20))).check().unwrap();
+    assert_eq!(
+        report(baseline, sample(55 + GROWTH_ALLOWANCE, Some(20)))
+            .check()
+            .unwrap_err()
+            .to_string(),
+        format!(
+            "live tasks grew from 54 to {} in the second half of 4 cycles",
+            55 + GROWTH_ALLOWANCE
+        )
+    );
+}
+
+#[test]
+fn growing_file_descriptors_fail_the_report() {
+    let baseline = sample(54, Some(20));
+    report(baseline, sample(54, Some(20 + GROWTH_ALLOWANCE)))
+        .check()
+        .unwrap();
+    assert_eq!(
+        report(baseline, sample(54, Some(21 + GROWTH_ALLOWANCE)))
+            .check()
+            .unwrap_err()
+            .to_string(),
+        format!(
+            "open file descriptors grew from 20 to {} in the second half of 4 cycles",
+            21 + GROWTH_ALLOWANCE
+        )
+    );
+    // Nothing to compare where the platform doesn't count them
+    report(sample(54, None), sample(54, Some(1000)))
+        .check()
+        .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn samples_this_process() {
+    let sample = ResourceSample::take();
+    if cfg!(unix) {
+        assert!(sample.rss_kib.is_some_and(|rss| rss > 0), "{}", sample);
+        assert!(sample.open_fds.is_some_and(|fds| fds > 0), "{}", sample);
+    }
+}
+
+/// Starts real peers, so it needs multicast between them and a machine idle
+/// enough to discover within the timeout
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs working multicast; run with --ignored"]
+async fn reports_one_cycle() {
+    let report = run_soak(SoakOptions {
+        duration: Duration::from_millis(1),
+        discovery_timeout: Duration::from_secs(20),
+        pause: Duration::ZERO,
+    })
+    .await;
+    assert_eq!(report.cycles, 1);
+    report.check().unwrap();
+}
@@ -0,0 +1,138 @@
+//! Versioning, migration and atomic writes of the state directory
+
+use std::fs;
+use std::path::Path;
+
+use mdns_peer::notes::PeerNotes;
+use mdns_peer::state::{self, Migration, STATE_VERSION};
+
+#[test]
+fn fresh_directories_start_at_the_current_version() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let state_dir = dir.path().join("state");
+    state::migrate(&state_dir)?;
+    assert_eq!(state::version(&state_dir)?, Some(STATE_VERSION));
+    Ok(())
+}
+
+#[test]
+fn unversioned_directories_are_migrated_and_keep_their_files() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::write(dir.path().join("groups.json"), r#"{"family":[]}"#)?;
+    fs::write(dir.path().join("peers.json"), "{}")?;
+
+    // Opening any store migrates the whole directory
+    PeerNotes::open(dir.path())?;
+    assert_eq!(state::version(dir.path())?, Some(STATE_VERSION));
+    assert_eq!(
+        fs::read_to_string(dir.path().join("groups.json"))?,
+        r#"{"family":[]}"#
+    );
+    Ok(())
+}
+
+fn rename_notes(dir: &Path) -> anyhow::Result<()> {
+    let old = dir.join("notes.json");
+    if old.exists() {
+        fs::rename(old, dir.join("peers.json"))?;
+    }
+    Ok(())
+}
+
+fn fail(_: &Path) -> anyhow::Result<()> {
+    anyhow::bail!("disk full")
+}
+
+const STEPS: &[Migration] = &[
+    Migration {
+        description: "nothing",
+        run: |_| Ok(()),
+    },
+    Migration {
+        description: "rename notes.json",
+        run: rename_notes,
+    },
+];
+
+#[test]
+fn migrations_run_from_the_recorded_version() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::write(dir.path().join("version"), "1")?;
+    fs::write(dir.path().join("notes.json"), "{}")?;
+
+    state::migrate_with(dir.path(), STEPS)?;
+    assert_eq!(state::version(dir.path())?, Some(2));
+    assert!(dir.path().join("peers.json").exists());
+    assert!(!dir.path().join("notes.json").exists());
+
+    // Running them again is harmless
+    state::migrate_with(dir.path(), STEPS)?;
+    assert_eq!(state::version(dir.path())?, Some(2));
+    Ok(())
+}
+
+#[test]
+fn a_failed_migration_keeps_the_last_completed_version() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::write(dir.path().join("peers.json"), "{}")?;
+    let steps = [
+        STEPS[0],
+        Migration {
+            description: "fail",
+            run: fail,
+        },
+    ];
+
+    let error = state::migrate_with(dir.path(), &steps).unwrap_err();
+    assert!(format!("{:#}", error).contains("disk full"));
+    assert_eq!(state::version(dir.path())?, Some(1));
+    Ok(())
+}
+
+#[test]
+fn directories_from_newer_releases_are_refused() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::write(dir.path().join("version"), (STATE_VERSION + 1).to_string())?;
+    fs::write(dir.path().join("peers.json"), "{}")?;
+
+    let error = PeerNotes::open(dir.path()).unwrap_err();
+    assert!(format!("{:#}", error).contains("newer"));
+    assert_eq!(fs::read_to_string(dir.path().join("peers.json"))?, "{}");
+    Ok(())
+}
+
+#[test]
+fn atomic_writes_replace_the_whole_file() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("sub").join("peers.json");
+    state::write_atomic(&path, b"a much longer first version")?;
+    state::write_atomic(&path, b"short")?;
+    assert_eq!(fs::read(&path)?, b"short");
+    assert!(!dir.path().join("sub").join("peers.json.tmp").exists());
+    Ok(())
+}
+
+#[test]
+fn leftovers_of_interrupted_writes_are_removed() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    fs::create_dir(dir.path().join("profiles"))?;
+    fs::write(dir.path().join("peers.json.tmp"), "{")?;
+    fs::write(dir.path().join("profiles").join("work.json.tmp"), "{")?;
+
+    state::migrate(dir.path())?;
+    assert!(!dir.path().join("peers.json.tmp").exists());
+    assert!(!dir.path().join("profiles").join("work.json.tmp").exists());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn private_files_are_only_readable_by_the_owner() -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("key.json");
+    state::write_atomic_private(&path, b"secret")?;
+    assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+    Ok(())
+}
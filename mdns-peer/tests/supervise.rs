@@ -0,0 +1,68 @@
+//! Panics and errors in background tasks reach the host as events
+
+use common::EventLog;
+use mdns_peer::supervise::{self, catch_panic};
+use mdns_peer::PeerEvent;
+
+mod common;
+
+#[tokio::test]
+async fn caught_panic_carries_message_location_and_backtrace() {
+    supervise::install_panic_hook();
+
+    let panic = catch_panic(async {
+        tokio::task::yield_now().await;
+        panic!("registry poisoned");
+    })
+    .await
+    .unwrap_err();
+
+    assert!(panic.message.starts_with("registry poisoned at "));
+    assert!(panic.message.contains("supervise.rs"));
+    assert!(panic.backtrace.is_some_and(|bt| !bt.is_empty()));
+}
+
+#[tokio::test]
+async fn tasks_that_finish_are_unaffected() {
+    assert_eq!(catch_panic(async { 42 }).await, Ok(42));
+}
+
+#[tokio::test]
+async fn supervised_task_panic_becomes_error_event() {
+    supervise::install_panic_hook();
+    let log = EventLog::default();
+
+    supervise::spawn_supervised("discovery", log.sink(), async {
+        panic!("boom");
+    })
+    .await
+    .unwrap();
+
+    let events = log.events();
+    let [PeerEvent::Error {
+        task,
+        message,
+        backtrace,
+    }] = events.as_slice()
+    else {
+        panic!("expected one error event, got {:?}", events);
+    };
+    assert_eq!(task, "discovery");
+    assert!(message.starts_with("panicked: boom"));
+    assert!(backtrace.is_some());
+}
+
+#[test]
+fn task_errors_become_error_events() {
+    let log = EventLog::default();
+
+    let err = anyhow::anyhow!("address in use").context("failed to bind");
+    supervise::report_error("peer", &err, &log.sink());
+
+    let events = log.events();
+    assert!(matches!(
+        events.as_slice(),
+        [PeerEvent::Error { task, message, .. }]
+            if task == "peer" && message == "failed to bind: address in use"
+    ));
+}
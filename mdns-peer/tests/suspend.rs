@@ -0,0 +1,56 @@
+//! Noticing time spent asleep between periodic checks
+
+use std::time::{Duration, Instant, SystemTime};
+
+use mdns_peer::suspend::{SuspendDetector, MIN_SUSPEND};
+
+const PERIOD: Duration = Duration::from_secs(5);
+
+/// A detector whose last check was at the returned times
+fn baseline() -> (SuspendDetector, Instant, SystemTime) {
+    let mut detector = SuspendDetector::new(PERIOD);
+    let (instant, wall) = (Instant::now(), SystemTime::now());
+    assert_eq!(detector.observe(instant, wall), None);
+    (detector, instant, wall)
+}
+
+#[test]
+fn regular_checks_are_not_suspends() {
+    let (mut detector, instant, wall) = baseline();
+    for n in 1..10 {
+        let elapsed = PERIOD * n;
+        assert_eq!(detector.observe(instant + elapsed, wall + elapsed), None);
+    }
+    // A late tick that is still within the threshold
+    let late = PERIOD * 10 + MIN_SUSPEND / 2;
+    assert_eq!(detector.observe(instant + late, wall + late), None);
+}
+
+#[test]
+fn sleep_and_freezes_are_measured() {
+    let (mut detector, instant, wall) = baseline();
+
+    // The device slept: the monotonic clock paused, the wall clock didn't
+    let slept = Duration::from_secs(600);
+    assert_eq!(
+        detector.observe(instant + PERIOD, wall + PERIOD + slept),
+        Some(slept)
+    );
+
+    // The process was frozen in the background for a minute
+    let frozen = Duration::from_secs(60);
+    assert_eq!(
+        detector.observe(
+            instant + PERIOD * 2 + frozen,
+            wall + PERIOD * 2 + slept + frozen
+        ),
+        Some(frozen)
+    );
+}
+
+#[test]
+fn wall_clock_set_backwards_is_ignored() {
+    let (mut detector, instant, wall) = baseline();
+    let back = wall - Duration::from_secs(3600);
+    assert_eq!(detector.observe(instant + PERIOD, back), None);
+}
@@ -0,0 +1,41 @@
+//! Listing background tasks and what they are doing
+
+use mdns_peer::tasks::{self, TaskState};
+use tokio::sync::oneshot;
+
+fn find(name: &str) -> Option<tasks::TaskReport> {
+    tasks::tasks().into_iter().find(|task| task.name == name)
+}
+
+#[tokio::test]
+async fn tasks_are_listed_until_they_end() {
+    let (release, released) = oneshot::channel::<()>();
+    let task = tokio::spawn(tasks::track("test_waiting", async move {
+        // Sees itself running while it polls
+        assert_eq!(find("test_waiting").unwrap().state, TaskState::Running);
+        released.await.ok();
+    }));
+    tokio::task::yield_now().await;
+    while find("test_waiting").is_none_or(|task| task.polls == 0) {
+        tokio::task::yield_now().await;
+    }
+
+    let waiting = find("test_waiting").unwrap();
+    assert_eq!(waiting.state, TaskState::Waiting);
+    assert_eq!(waiting.polls, 1);
+
+    release.send(()).unwrap();
+    task.await.unwrap();
+    assert!(find("test_waiting").is_none());
+}
+
+#[tokio::test]
+async fn dropped_tasks_are_removed() {
+    let tracked = tasks::track("test_dropped", std::future::pending::<()>());
+    let listed = find("test_dropped").unwrap();
+    assert_eq!((listed.state, listed.polls), (TaskState::Waiting, 0));
+    assert!(tasks::tasks().windows(2).all(|w| w[0].id < w[1].id));
+
+    drop(tracked);
+    assert!(find("test_dropped").is_none());
+}
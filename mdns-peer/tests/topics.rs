@@ -0,0 +1,140 @@
+//! Publishing to gossip topics between two peers
+#![cfg(feature = "gossip")]
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use common::{EventLog, DEADLINE};
+use iroh::{protocol::Router, Endpoint, NodeId};
+use mdns_peer::events::DiscoveryOrigin;
+use mdns_peer::protocols::Protocols;
+use mdns_peer::topics::{TopicHandler, Topics, MAX_PUBLISH_SIZE};
+use mdns_peer::PeerEvent;
+
+mod common;
+
+/// Payloads received per topic
+#[derive(Default)]
+struct Inbox(Mutex<Vec<(String, NodeId, Vec<u8>)>>);
+
+impl TopicHandler for Inbox {
+    fn on_message(&self, topic: &str, node_id: NodeId, data: &[u8]) {
+        let received = (topic.to_string(), node_id, data.to_vec());
+        self.0.lock().unwrap().push(received);
+    }
+}
+
+struct Peer {
+    endpoint: Endpoint,
+    topics: Topics,
+    router: Router,
+    events: EventLog,
+}
+
+impl Peer {
+    /// A peer reachable only through addresses added by hand
+    async fn start() -> anyhow::Result<Self> {
+        let endpoint = common::local_endpoint(None).await?;
+        let topics = Topics::default();
+        let events = EventLog::default();
+        let gossip = topics.attach(&endpoint, events.sink());
+        let router = Protocols::default().spawn_router_with(endpoint.clone(), |builder| {
+            builder.accept(iroh_gossip::ALPN, gossip)
+        });
+        Ok(Self {
+            endpoint,
+            topics,
+            router,
+            events,
+        })
+    }
+
+    fn node_id(&self) -> NodeId {
+        self.endpoint.node_id()
+    }
+
+    /// Add `other`'s addresses and offer it to the topics, as discovery does
+    async fn learn(&self, other: &Peer) -> anyhow::Result<()> {
+        common::learn(&self.endpoint, &other.endpoint).await?;
+        self.topics.peer_event(&PeerEvent::Discovered {
+            node_id: other.node_id(),
+            user_data: None,
+            provenance: "test",
+            origin: DiscoveryOrigin::default(),
+        });
+        Ok(())
+    }
+
+    /// Wait until `node_id` is a gossip neighbour on `topic`
+    async fn neighbor(&self, topic: &str, node_id: NodeId) -> anyhow::Result<()> {
+        self.events
+            .wait_for(|event| {
+                matches!(event, PeerEvent::NeighborUp { topic: t, node_id: n }
+                    if t == topic && *n == node_id)
+                .then_some(())
+            })
+            .await
+    }
+
+    async fn shutdown(self) -> anyhow::Result<()> {
+        self.router.shutdown().await?;
+        Ok(())
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn published_payloads_reach_subscribers() -> anyhow::Result<()> {
+    let alice = Peer::start().await?;
+    let bob = Peer::start().await?;
+    let alice_inbox = Arc::new(Inbox::default());
+    let bob_inbox = Arc::new(Inbox::default());
+    alice.topics.subscribe("photos", alice_inbox.clone())?;
+    bob.topics.subscribe("photos", bob_inbox.clone())?;
+    bob.topics.subscribe("other", Arc::new(Inbox::default()))?;
+    alice.learn(&bob).await?;
+    bob.learn(&alice).await?;
+
+    alice.neighbor("photos", bob.node_id()).await?;
+    alice
+        .topics
+        .publish("photos", b"album updated".to_vec())
+        .await?;
+    let delivered = async {
+        while bob_inbox.0.lock().unwrap().is_empty() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    };
+    tokio::time::timeout(DEADLINE, delivered).await?;
+    assert_eq!(
+        bob_inbox.0.lock().unwrap().clone(),
+        [(
+            "photos".to_string(),
+            alice.node_id(),
+            b"album updated".to_vec()
+        )]
+    );
+    // Publishers don't hear their own payloads
+    assert!(alice_inbox.0.lock().unwrap().is_empty());
+
+    alice.shutdown().await?;
+    bob.shutdown().await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn publishing_needs_a_subscription() -> anyhow::Result<()> {
+    let topics = Topics::default();
+    assert!(topics.publish("photos", Vec::new()).await.is_err());
+    assert!(topics.subscribe("", Arc::new(Inbox::default())).is_err());
+
+    // Subscribing before the peer runs is kept for later
+    topics.subscribe("photos", Arc::new(Inbox::default()))?;
+    assert_eq!(topics.subscribed(), ["photos"]);
+    assert!(topics.publish("photos", Vec::new()).await.is_err());
+    let too_large = vec![0; MAX_PUBLISH_SIZE + 1];
+    assert!(topics.publish("photos", too_large).await.is_err());
+
+    assert!(topics.unsubscribe("photos"));
+    assert!(!topics.unsubscribe("photos"));
+    assert!(topics.subscribed().is_empty());
+    Ok(())
+}
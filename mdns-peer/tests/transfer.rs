@@ -0,0 +1,399 @@
+//! Sending files, refusing them, resuming interrupted transfers and
+//! managing several at once
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use common::{EventLog, DEADLINE};
+use iroh::{protocol::Router, Endpoint, NodeId};
+use mdns_peer::connections::Direction;
+use mdns_peer::protocols::Protocols;
+use mdns_peer::transfer::{
+    FileTransfers, IncomingFile, Progress, ReceiveOptions, ReceivePolicy, Refused, TransferId,
+    TransferOutcome,
+};
+use mdns_peer::PeerEvent;
+use n0_future::boxed::BoxFuture;
+
+mod common;
+
+struct Peer {
+    endpoint: Endpoint,
+    transfers: FileTransfers,
+    router: Router,
+    progress: Arc<Mutex<Vec<Progress>>>,
+    events: EventLog,
+}
+
+impl Peer {
+    /// A peer reachable only through addresses added by hand
+    async fn start() -> anyhow::Result<Self> {
+        let endpoint = common::local_endpoint(None).await?;
+        let transfers = FileTransfers::default();
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let recorded = progress.clone();
+        transfers.set_progress(Some(Arc::new(move |p: &Progress| {
+            recorded.lock().unwrap().push(p.clone());
+        })));
+        let protocols = Protocols::default();
+        let events = EventLog::default();
+        protocols.set_event_sink(events.sink());
+        transfers.attach(&protocols);
+        let router = protocols.spawn_router(endpoint.clone());
+        Ok(Self {
+            endpoint,
+            transfers,
+            router,
+            progress,
+            events,
+        })
+    }
+
+    fn node_id(&self) -> NodeId {
+        self.endpoint.node_id()
+    }
+
+    async fn learn(&self, other: &Peer) -> anyhow::Result<()> {
+        common::learn(&self.endpoint, &other.endpoint).await
+    }
+
+    async fn send(&self, to: &Peer, path: &std::path::Path) -> anyhow::Result<String> {
+        tokio::time::timeout(DEADLINE, self.transfers.send_file(to.node_id(), path)).await?
+    }
+
+    /// How transfer `id` ended, once it has
+    async fn outcome(&self, id: TransferId) -> anyhow::Result<TransferOutcome> {
+        self.events
+            .wait_for(|event| match event {
+                PeerEvent::TransferFinished {
+                    id: finished,
+                    outcome,
+                    ..
+                } if *finished == id => Some(*outcome),
+                _ => None,
+            })
+            .await
+    }
+
+    async fn shutdown(self) -> anyhow::Result<()> {
+        self.router.shutdown().await?;
+        Ok(())
+    }
+}
+
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn file_arrives_whole_with_progress() -> anyhow::Result<()> {
+    let sender = Peer::start().await?;
+    let receiver = Peer::start().await?;
+    sender.learn(&receiver).await?;
+    let out = tempfile::tempdir()?;
+    receiver
+        .transfers
+        .set_receive(Some(ReceiveOptions::from_peers(
+            out.path(),
+            [sender.node_id()].into(),
+        )));
+
+    let source = tempfile::tempdir()?;
+    let path = source.path().join("photo.jpg");
+    let data = payload(3 * 1024 * 1024 + 17);
+    std::fs::write(&path, &data)?;
+    let hash = sender.send(&receiver, &path).await?;
+    assert_eq!(hash, blake3::hash(&data).to_hex().as_str());
+    assert!(std::fs::read(out.path().join("photo.jpg"))? == data);
+
+    // Sending it again keeps both copies
+    sender.send(&receiver, &path).await?;
+    assert!(std::fs::read(out.path().join("photo (1).jpg"))? == data);
+
+    let sent = sender.progress.lock().unwrap().clone();
+    let last = sent.last().expect("progress reported");
+    assert_eq!(last.direction, Direction::Outbound);
+    assert_eq!(
+        (last.bytes, last.size),
+        (data.len() as u64, data.len() as u64)
+    );
+    assert!(
+        sent.windows(2)
+            .take_while(|w| w[0].bytes <= w[1].bytes)
+            .count()
+            > 1
+    );
+    let received = receiver.progress.lock().unwrap().clone();
+    let last = received.last().expect("progress reported");
+    assert_eq!(last.direction, Direction::Inbound);
+    assert_eq!(
+        (last.node_id, last.name.as_str()),
+        (sender.node_id(), "photo.jpg")
+    );
+
+    sender.shutdown().await?;
+    receiver.shutdown().await
+}
+
+/// Hands out at most `step` bytes per read, like a host streaming a photo
+struct Trickle {
+    data: std::io::Cursor<Vec<u8>>,
+    step: usize,
+}
+
+impl std::io::Read for Trickle {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(self.step);
+        std::io::Read::read(&mut self.data, &mut buf[..len])
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn file_streams_from_a_reader() -> anyhow::Result<()> {
+    let sender = Peer::start().await?;
+    let receiver = Peer::start().await?;
+    sender.learn(&receiver).await?;
+    let out = tempfile::tempdir()?;
+    receiver
+        .transfers
+        .set_receive(Some(ReceiveOptions::from_peers(
+            out.path(),
+            [sender.node_id()].into(),
+        )));
+
+    let data = payload(700 * 1024 + 3);
+    let reader = Trickle {
+        data: std::io::Cursor::new(data.clone()),
+        step: 10_000,
+    };
+    let send = sender.transfers.send_reader(
+        receiver.node_id(),
+        "IMG_0001.HEIC".to_string(),
+        data.len() as u64,
+        Box::new(reader),
+    );
+    let hash = tokio::time::timeout(DEADLINE, send).await??;
+    assert_eq!(hash, blake3::hash(&data).to_hex().as_str());
+    assert!(std::fs::read(out.path().join("IMG_0001.HEIC"))? == data);
+
+    // A reader running dry before the stated size fails the transfer
+    let reader = std::io::Cursor::new(data[..1000].to_vec());
+    let send = sender.transfers.send_reader(
+        receiver.node_id(),
+        "short.bin".to_string(),
+        data.len() as u64,
+        Box::new(reader),
+    );
+    assert!(tokio::time::timeout(DEADLINE, send).await?.is_err());
+    assert!(!out.path().join("short.bin").exists());
+
+    sender.shutdown().await?;
+    receiver.shutdown().await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn files_from_unknown_peers_are_refused() -> anyhow::Result<()> {
+    let sender = Peer::start().await?;
+    let receiver = Peer::start().await?;
+    sender.learn(&receiver).await?;
+    let out = tempfile::tempdir()?;
+    let source = tempfile::tempdir()?;
+    let path = source.path().join("notes.txt");
+    std::fs::write(&path, b"hello")?;
+
+    // Not receiving at all, then not from this sender
+    let refused = Refused {
+        node_id: receiver.node_id(),
+        name: "notes.txt".to_string(),
+    };
+    let err = sender.send(&receiver, &path).await.unwrap_err();
+    assert_eq!(err.downcast_ref::<Refused>(), Some(&refused));
+    receiver
+        .transfers
+        .set_receive(Some(ReceiveOptions::from_peers(out.path(), [].into())));
+    let err = sender.send(&receiver, &path).await.unwrap_err();
+    assert_eq!(err.downcast_ref::<Refused>(), Some(&refused));
+    assert_eq!(std::fs::read_dir(out.path())?.count(), 0);
+
+    sender.shutdown().await?;
+    receiver.shutdown().await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn interrupted_transfer_resumes_where_it_stopped() -> anyhow::Result<()> {
+    let sender = Peer::start().await?;
+    let receiver = Peer::start().await?;
+    sender.learn(&receiver).await?;
+    let out = tempfile::tempdir()?;
+    receiver
+        .transfers
+        .set_receive(Some(ReceiveOptions::from_peers(
+            out.path(),
+            [sender.node_id()].into(),
+        )));
+
+    let source = tempfile::tempdir()?;
+    let path = source.path().join("video.mov");
+    let data = payload(2 * 1024 * 1024);
+    std::fs::write(&path, &data)?;
+    // What an earlier attempt left behind
+    let partial = out.path().join(format!(
+        ".video.mov.{}.{}.part",
+        sender.node_id().fmt_short(),
+        data.len()
+    ));
+    std::fs::write(&partial, &data[..1024 * 1024])?;
+
+    sender.send(&receiver, &path).await?;
+    assert!(std::fs::read(out.path().join("video.mov"))? == data);
+    assert!(!partial.exists());
+    let first = sender.progress.lock().unwrap()[0].clone();
+    assert_eq!(first.bytes, 1024 * 1024);
+
+    sender.shutdown().await?;
+    receiver.shutdown().await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn corrupted_partial_file_is_dropped_and_sent_again() -> anyhow::Result<()> {
+    let sender = Peer::start().await?;
+    let receiver = Peer::start().await?;
+    sender.learn(&receiver).await?;
+    let out = tempfile::tempdir()?;
+    receiver
+        .transfers
+        .set_receive(Some(ReceiveOptions::from_peers(
+            out.path(),
+            [sender.node_id()].into(),
+        )));
+
+    let source = tempfile::tempdir()?;
+    let path = source.path().join("backup.zip");
+    let data = payload(256 * 1024);
+    std::fs::write(&path, &data)?;
+    // An earlier attempt that went wrong on disk
+    let partial = out.path().join(format!(
+        ".backup.zip.{}.{}.part",
+        sender.node_id().fmt_short(),
+        data.len()
+    ));
+    std::fs::write(&partial, vec![0xff; 1024])?;
+
+    assert!(sender.send(&receiver, &path).await.is_err());
+    assert!(!partial.exists());
+    assert!(!out.path().join("backup.zip").exists());
+
+    let hash = sender.send(&receiver, &path).await?;
+    assert_eq!(hash, blake3::hash(&data).to_hex().as_str());
+    assert!(std::fs::read(out.path().join("backup.zip"))? == data);
+
+    sender.shutdown().await?;
+    receiver.shutdown().await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn queued_transfers_wait_for_a_slot_and_can_be_cancelled() -> anyhow::Result<()> {
+    let sender = Peer::start().await?;
+    let receiver = Peer::start().await?;
+    sender.learn(&receiver).await?;
+    let out = tempfile::tempdir()?;
+    receiver
+        .transfers
+        .set_receive(Some(ReceiveOptions::from_peers(
+            out.path(),
+            [sender.node_id()].into(),
+        )));
+    let source = tempfile::tempdir()?;
+    let path = source.path().join("song.m4a");
+    std::fs::write(&path, payload(512 * 1024))?;
+
+    // No slots at all, so both wait
+    sender.transfers.set_max_active(Some(0));
+    let first = sender
+        .transfers
+        .spawn_send(receiver.node_id(), path.clone())?;
+    let second = sender
+        .transfers
+        .spawn_send(receiver.node_id(), path.clone())?;
+    let listed = sender.transfers.transfers();
+    assert_eq!(
+        listed.iter().map(|t| (t.id, t.queued)).collect::<Vec<_>>(),
+        [(first, true), (second, true)]
+    );
+
+    assert!(sender.transfers.cancel(first));
+    assert_eq!(sender.outcome(first).await?, TransferOutcome::Cancelled);
+    assert!(!sender.transfers.cancel(first));
+
+    sender.transfers.set_max_active(Some(1));
+    assert_eq!(sender.outcome(second).await?, TransferOutcome::Completed);
+    assert!(sender.transfers.transfers().is_empty());
+    assert!(out.path().join("song.m4a").exists());
+    assert!(!out.path().join("song (1).m4a").exists());
+
+    sender.shutdown().await?;
+    receiver.shutdown().await
+}
+
+/// Takes files up to 1 KiB into a directory per sender, recording what it
+/// was asked
+struct SmallFilesBySender {
+    dir: PathBuf,
+    asked: Arc<Mutex<Vec<IncomingFile>>>,
+}
+
+impl ReceivePolicy for SmallFilesBySender {
+    fn decide(&self, file: IncomingFile) -> BoxFuture<Option<PathBuf>> {
+        let dir = (file.size <= 1024).then(|| self.dir.join(file.node_id.fmt_short()));
+        self.asked.lock().unwrap().push(file);
+        Box::pin(async move { dir })
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn receive_policy_picks_the_directory_or_refuses() -> anyhow::Result<()> {
+    let sender = Peer::start().await?;
+    let receiver = Peer::start().await?;
+    sender.learn(&receiver).await?;
+    let out = tempfile::tempdir()?;
+    let asked = Arc::new(Mutex::new(Vec::new()));
+    receiver
+        .transfers
+        .set_receive_policy(Some(Arc::new(SmallFilesBySender {
+            dir: out.path().to_path_buf(),
+            asked: asked.clone(),
+        })));
+
+    let source = tempfile::tempdir()?;
+    let small = source.path().join("small.txt");
+    std::fs::write(&small, payload(100))?;
+    let large = source.path().join("large.bin");
+    std::fs::write(&large, payload(4096))?;
+
+    sender.send(&receiver, &small).await?;
+    let dir = out.path().join(sender.node_id().fmt_short());
+    assert!(std::fs::read(dir.join("small.txt"))? == payload(100));
+    let err = sender.send(&receiver, &large).await.unwrap_err();
+    assert!(err.is::<Refused>());
+    assert!(!dir.join("large.bin").exists());
+
+    let asked = asked.lock().unwrap().clone();
+    assert_eq!(
+        asked,
+        [
+            IncomingFile {
+                node_id: sender.node_id(),
+                name: "small.txt".to_string(),
+                size: 100,
+            },
+            IncomingFile {
+                node_id: sender.node_id(),
+                name: "large.bin".to_string(),
+                size: 4096,
+            },
+        ]
+    );
+
+    sender.shutdown().await?;
+    receiver.shutdown().await
+}
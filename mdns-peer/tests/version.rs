@@ -0,0 +1,157 @@
+//! Protocol versions and turning away peers that speak another one
+
+use std::sync::Arc;
+
+use iroh::NodeId;
+use mdns_peer::protocols::{StreamHandler, StreamId};
+use mdns_peer::version::{Compatibility, COMPATIBILITY};
+use mdns_peer::PeerEvent;
+use tokio::sync::mpsc;
+
+use common::Side;
+
+mod common;
+
+const ALPN: &[u8] = b"mdns-peer/test-version";
+
+fn speaking(version: u8, oldest: u8) -> Compatibility {
+    Compatibility {
+        alpn: ALPN,
+        version,
+        oldest,
+    }
+}
+
+/// Reports what each stream received, or how it failed
+struct Outcomes(mpsc::UnboundedSender<Result<Vec<u8>, String>>);
+
+impl StreamHandler for Outcomes {
+    fn on_open(&self, _stream: StreamId, _node_id: NodeId) {}
+
+    fn on_data(&self, _stream: StreamId, data: &[u8]) {
+        let _ = self.0.send(Ok(data.to_vec()));
+    }
+
+    fn on_close(&self, _stream: StreamId, error: Option<&str>) {
+        if let Some(error) = error {
+            let _ = self.0.send(Err(error.to_string()));
+        }
+    }
+}
+
+/// A side serving the test ALPN, versioned with `compatibility` if given
+async fn bind(
+    identifier: &str,
+    compatibility: Option<Compatibility>,
+) -> anyhow::Result<Side<Result<Vec<u8>, String>>> {
+    Side::bind(identifier, |protocols, tx| {
+        protocols.register(ALPN, Arc::new(Outcomes(tx)));
+        if let Some(compatibility) = compatibility {
+            protocols.set_compatibility(compatibility);
+        }
+    })
+    .await
+}
+
+/// Open a stream from `from` to `to` and send `data` on it
+async fn send<T, U>(from: &Side<T>, to: &Side<U>, data: &[u8]) -> anyhow::Result<()> {
+    let stream = from
+        .protocols
+        .open_stream(&from.endpoint, to.addr().await, ALPN)?;
+    from.protocols.write(stream, data.to_vec());
+    from.protocols.finish(stream);
+    Ok(())
+}
+
+/// `version_mismatch` events, rather than the rest
+fn mismatch(event: &PeerEvent) -> bool {
+    matches!(event, PeerEvent::VersionMismatch { .. })
+}
+
+#[test]
+fn accepts_versions_in_range() {
+    let compatibility = speaking(4, 2);
+    assert!(!compatibility.accepts(1));
+    assert!(compatibility.accepts(2));
+    assert!(compatibility.accepts(4));
+    assert!(!compatibility.accepts(5));
+
+    let reason = compatibility.mismatch(5);
+    assert!(reason.contains("version 5"), "{}", reason);
+    assert!(reason.contains("versions 2 to 4"), "{}", reason);
+    assert!(
+        reason.contains("the receiver needs an update"),
+        "{}",
+        reason
+    );
+    assert!(compatibility
+        .mismatch(1)
+        .contains("the sender needs an update"));
+}
+
+#[test]
+fn built_in_protocols_read_what_they_speak() {
+    for compatibility in COMPATIBILITY {
+        assert!(compatibility.accepts(compatibility.version));
+        // Versions start at 1
+        assert!(compatibility.oldest >= 1);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn matching_versions_only_see_their_own_data() -> anyhow::Result<()> {
+    let mut server = bind("version-match-server", Some(speaking(3, 2))).await?;
+    let client = bind("version-match-client", Some(speaking(2, 2))).await?;
+
+    send(&client, &server, b"hello").await?;
+    assert_eq!(server.next_received().await?, Ok(b"hello".to_vec()));
+
+    client.shutdown().await?;
+    server.shutdown().await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn newer_versions_are_turned_away_with_a_reason() -> anyhow::Result<()> {
+    let mut server = bind("version-old-server", Some(speaking(2, 2))).await?;
+    let mut client = bind("version-new-client", Some(speaking(3, 3))).await?;
+
+    send(&client, &server, b"hello").await?;
+    assert_eq!(
+        server.next_event(mismatch).await?,
+        PeerEvent::VersionMismatch {
+            node_id: client.endpoint.node_id(),
+            alpn: "mdns-peer/test-version".to_string(),
+            local_version: 2,
+            remote_version: Some(3),
+            reason: speaking(2, 2).mismatch(3),
+        }
+    );
+
+    // The sender learns why, rather than waiting on a reply
+    let failed = client.next_received().await?;
+    assert!(
+        failed
+            .as_ref()
+            .is_err_and(|e| e.contains("the receiver needs an update")),
+        "{:?}",
+        failed
+    );
+    match client.next_event(mismatch).await? {
+        PeerEvent::VersionMismatch {
+            node_id,
+            local_version,
+            remote_version,
+            reason,
+            ..
+        } => {
+            assert_eq!(node_id, server.endpoint.node_id());
+            assert_eq!(local_version, 3);
+            assert_eq!(remote_version, None);
+            assert_eq!(reason, speaking(2, 2).mismatch(3));
+        }
+        other => panic!("unexpected event {:?}", other),
+    }
+
+    client.shutdown().await?;
+    server.shutdown().await
+}
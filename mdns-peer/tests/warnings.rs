@@ -0,0 +1,90 @@
+//! Reporting lasting problems when they start, repeat and clear
+
+use std::time::{Duration, Instant};
+
+use mdns_peer::events::event_mask;
+use mdns_peer::warnings::{Condition, DEFAULT_REPEAT};
+use mdns_peer::PeerEvent;
+use serde_json::json;
+
+#[test]
+fn condition_reports_start_reminders_and_clearing() {
+    let start = Instant::now();
+    let mut condition = Condition::new("no_peers");
+    assert!(condition.clear(start).is_none());
+
+    let first = condition.hold(start, "No peers discovered yet").unwrap();
+    assert!(condition.is_active());
+    // Every check in between stays quiet
+    for secs in [5, 10, 60, 299] {
+        let at = start + Duration::from_secs(secs);
+        assert!(condition.hold(at, "No peers discovered yet").is_none());
+    }
+    let reminder = condition
+        .hold(start + DEFAULT_REPEAT, "No peers discovered yet")
+        .unwrap();
+    let cleared = condition.clear(start + Duration::from_secs(400)).unwrap();
+    assert!(!condition.is_active());
+
+    let value = |event: &PeerEvent| serde_json::from_str::<serde_json::Value>(&event.to_json());
+    assert_eq!(
+        value(&first).unwrap(),
+        json!({
+            "type": "warning",
+            "condition": "no_peers",
+            "active": true,
+            "message": "No peers discovered yet",
+            "duration_ms": 0,
+            "occurrences": 1,
+        })
+    );
+    assert_eq!(
+        value(&reminder).unwrap(),
+        json!({
+            "type": "warning",
+            "condition": "no_peers",
+            "active": true,
+            "message": "No peers discovered yet",
+            "duration_ms": 300_000,
+            "occurrences": 6,
+        })
+    );
+    assert_eq!(
+        value(&cleared).unwrap(),
+        json!({
+            "type": "warning",
+            "condition": "no_peers",
+            "active": false,
+            "duration_ms": 400_000,
+            "occurrences": 6,
+        })
+    );
+    assert_eq!(first.mask_bit(), event_mask::STATUS);
+}
+
+#[test]
+fn condition_starts_over_after_clearing() {
+    let start = Instant::now();
+    let mut condition = Condition::new("discovery_errors").with_repeat(Duration::from_secs(60));
+    condition.hold(start, "Discovery error: one").unwrap();
+    assert!(condition
+        .hold(start + Duration::from_secs(30), "Discovery error: two")
+        .is_none());
+    assert!(condition
+        .hold(start + Duration::from_secs(60), "Discovery error: three")
+        .is_some());
+    condition.clear(start + Duration::from_secs(61)).unwrap();
+
+    let again = condition
+        .hold(start + Duration::from_secs(62), "Discovery error: four")
+        .unwrap();
+    let PeerEvent::Warning {
+        duration_ms,
+        occurrences,
+        ..
+    } = again
+    else {
+        panic!("not a warning: {:?}", again);
+    };
+    assert_eq!((duration_ms, occurrences), (0, 1));
+}
@@ -0,0 +1,199 @@
+//! `cargo xtask build-linux-cross` and `cargo xtask build-windows`
+//!
+//! Builds the desktop counterpart for other platforms: the `mdns-peer` CLI,
+//! the shared library and the C header, collected in `dist/<target>/` so a
+//! tester can be handed one directory.
+//!
+//! Cross-compiling needs a linker and C toolchain per target. Rather than
+//! asking for those to be installed, the build goes through `cross` (Docker
+//! images with everything set up) or `cargo zigbuild` (zig as the linker)
+//! when either is on the PATH, and falls back to plain `cargo`, which works
+//! for the host target or with a manually configured toolchain.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::flag_value;
+use crate::header::generate_header;
+
+/// Targets built by `build-linux-cross` unless `--target` is given
+pub const LINUX_TARGETS: &[&str] = &[
+    "x86_64-unknown-linux-gnu",
+    "x86_64-unknown-linux-musl",
+    "aarch64-unknown-linux-gnu",
+    "aarch64-unknown-linux-musl",
+];
+
+/// Targets built by `build-windows` unless `--target` is given
+///
+/// The GNU ABI, because it cross-compiles from macOS and Linux; MSVC builds
+/// need the Windows SDK.
+pub const WINDOWS_TARGETS: &[&str] = &["x86_64-pc-windows-gnu"];
+
+/// What runs the build
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildTool {
+    Cargo,
+    Cross,
+    Zigbuild,
+}
+
+impl BuildTool {
+    /// `cross` if installed, then `cargo zigbuild`, then `cargo`
+    pub fn detect() -> Self {
+        if succeeds(Command::new("cross").arg("--version")) {
+            Self::Cross
+        } else if succeeds(Command::new("cargo").args(["zigbuild", "--help"])) {
+            Self::Zigbuild
+        } else {
+            Self::Cargo
+        }
+    }
+
+    fn command(self) -> Command {
+        match self {
+            Self::Cargo => {
+                let mut command = Command::new("cargo");
+                command.arg("build");
+                command
+            }
+            Self::Cross => {
+                let mut command = Command::new("cross");
+                command.arg("build");
+                command
+            }
+            Self::Zigbuild => {
+                let mut command = Command::new("cargo");
+                command.arg("zigbuild");
+                command
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for BuildTool {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "cargo" => Ok(Self::Cargo),
+            "cross" => Ok(Self::Cross),
+            "zigbuild" => Ok(Self::Zigbuild),
+            _ => anyhow::bail!("Unknown build tool {:?}: use cargo, cross or zigbuild", s),
+        }
+    }
+}
+
+fn succeeds(command: &mut Command) -> bool {
+    command
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Settings for the cross-compilation tasks
+#[derive(Debug, Clone)]
+pub struct CrossOptions {
+    pub targets: Vec<String>,
+    pub tool: BuildTool,
+    /// Artifacts end up in `<out>/<target>/`
+    pub out: PathBuf,
+}
+
+impl CrossOptions {
+    /// `[--target <triple,...>] [--tool cargo|cross|zigbuild] [--out <dir>]`
+    pub fn from_args(args: &[String], default_targets: &[&str]) -> Result<Self> {
+        let targets = match flag_value(args, "--target") {
+            Some(list) => list.split(',').map(|t| t.trim().to_string()).collect(),
+            None => default_targets.iter().map(|t| t.to_string()).collect(),
+        };
+        let tool = match flag_value(args, "--tool") {
+            Some(tool) => tool.parse()?,
+            None => BuildTool::detect(),
+        };
+        Ok(Self {
+            targets,
+            tool,
+            out: PathBuf::from(flag_value(args, "--out").unwrap_or("dist")),
+        })
+    }
+}
+
+/// Build every target in `options`, stopping at the first failure
+pub fn build_cross(options: &CrossOptions) -> Result<()> {
+    println!(
+        "🔨 Building mdns-peer for {} target(s) with {:?}...",
+        options.targets.len(),
+        options.tool
+    );
+    if options.tool == BuildTool::Cargo {
+        println!("   (neither cross nor cargo-zigbuild found; targets other than the host");
+        println!("   need their toolchain and linker configured)");
+    }
+    println!();
+
+    let header = options.out.join("include/mdns_peer.h");
+    generate_header(&header)?;
+
+    for target in &options.targets {
+        build_release(options.tool, target)?;
+
+        let dir = options.out.join(target);
+        collect_artifacts(target, &dir, &header)?;
+        println!("   ✓ {}", dir.display());
+    }
+
+    println!();
+    println!("✅ Desktop builds are in {}", options.out.display());
+    Ok(())
+}
+
+/// Release build of `mdns-peer` for `target`, into `target/<target>/release`
+pub fn build_release(tool: BuildTool, target: &str) -> Result<()> {
+    println!("📦 Building for {}...", target);
+    let status = tool
+        .command()
+        .args(["--release", "--target", target, "-p", "mdns-peer"])
+        .status()
+        .with_context(|| format!("Failed to build for {}", target))?;
+    if !status.success() {
+        anyhow::bail!("Build failed for target: {}", target);
+    }
+    Ok(())
+}
+
+/// The `mdns-peer` binary's file name on `target`
+pub fn binary_name(target: &str) -> &'static str {
+    if target.contains("windows") {
+        "mdns-peer.exe"
+    } else {
+        "mdns-peer"
+    }
+}
+
+/// Copy the binary, library and header for `target` into `dir`
+fn collect_artifacts(target: &str, dir: &Path, header: &Path) -> Result<()> {
+    let release = Path::new("target").join(target).join("release");
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let windows = target.contains("windows");
+    let binary = binary_name(target);
+    let libraries: &[&str] = if windows {
+        &["mdns_peer.dll", "libmdns_peer.dll.a"]
+    } else if target.contains("musl") {
+        // musl targets link statically and can't produce a cdylib
+        &["libmdns_peer.a"]
+    } else {
+        &["libmdns_peer.so"]
+    };
+
+    for file in std::iter::once(&binary).chain(libraries) {
+        let src = release.join(file);
+        std::fs::copy(&src, dir.join(file))
+            .with_context(|| format!("Failed to copy {}", src.display()))?;
+    }
+    std::fs::copy(header, dir.join("mdns_peer.h")).context("Failed to copy the header")?;
+    Ok(())
+}
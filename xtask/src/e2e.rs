@@ -0,0 +1,267 @@
+//! `cargo xtask e2e-desktop`: two desktop peers on this machine have to
+//! find and reach each other
+//!
+//! Builds the CLI and runs two peers as subprocesses with `--events`, which
+//! prints every event as a line of JSON on stdout between the log lines.
+//! Each peer has to report a `discovered` event carrying the other's
+//! identifier as `user_data` before the deadline; then `mdns-peer ping`
+//! has to reach one of them. Anything else fails the task with the output
+//! of every peer.
+//!
+//! The peers advertise on an mDNS service name of their own, so peers
+//! already running on the network can't make the test pass or fail.
+
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::flag_value;
+
+/// How many lines of each peer's output a failure shows
+const SHOWN_LINES: usize = 40;
+
+/// Settings for `e2e-desktop`
+#[derive(Debug, Clone)]
+pub struct E2eOptions {
+    /// CLI to run; a fresh debug build if `None`
+    pub binary: Option<PathBuf>,
+    /// How long discovery, and then the ping, may take
+    pub deadline: Duration,
+}
+
+impl E2eOptions {
+    pub fn from_args(args: &[String]) -> Result<Self> {
+        let deadline = match flag_value(args, "--timeout") {
+            Some(secs) => Duration::from_secs_f64(
+                secs.parse()
+                    .with_context(|| format!("Invalid --timeout {}", secs))?,
+            ),
+            None => Duration::from_secs(30),
+        };
+        Ok(Self {
+            binary: flag_value(args, "--bin").map(PathBuf::from),
+            deadline,
+        })
+    }
+}
+
+pub fn e2e_desktop(options: &E2eOptions) -> Result<()> {
+    let binary = match &options.binary {
+        Some(binary) => binary.clone(),
+        None => build_cli()?,
+    };
+
+    let run = std::process::id();
+    let service = format!("mdns-peer-e2e-{}", run);
+    let names = [format!("e2e-a-{}", run), format!("e2e-b-{}", run)];
+    println!("🚀 Starting {} and {} on {}", names[0], names[1], service);
+    let mut peers = [
+        Peer::spawn(&binary, &names[0], &service)?,
+        Peer::spawn(&binary, &names[1], &service)?,
+    ];
+
+    let started = Instant::now();
+    loop {
+        let found = [
+            peers[0].has_discovered(&names[1]),
+            peers[1].has_discovered(&names[0]),
+        ];
+        if found == [true, true] {
+            println!(
+                "   Each peer discovered the other in {:.1}s",
+                started.elapsed().as_secs_f64()
+            );
+            break;
+        }
+        for i in 0..peers.len() {
+            if let Some(status) = peers[i].child.try_wait()? {
+                let reason = format!("{} exited with {}", peers[i].name, status);
+                return fail(&peers, reason);
+            }
+        }
+        if started.elapsed() > options.deadline {
+            let missing: Vec<_> = [(0, 1), (1, 0)]
+                .into_iter()
+                .filter(|&(by, _)| !found[by])
+                .map(|(by, of)| format!("{} didn't discover {}", names[by], names[of]))
+                .collect();
+            return fail(
+                &peers,
+                format!(
+                    "{} within {}s",
+                    missing.join(" and "),
+                    options.deadline.as_secs_f64()
+                ),
+            );
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    println!("🏓 Pinging {}...", names[1]);
+    let ping = Command::new(&binary)
+        .args(["ping", &names[1], "--as"])
+        .arg(format!("e2e-ping-{}", run))
+        .args(["--mdns-service", &service, "--summary-interval", "0"])
+        .arg("--timeout")
+        .arg(options.deadline.as_secs_f64().to_string())
+        .output()
+        .context("Failed to run mdns-peer ping")?;
+    let stdout = String::from_utf8_lossy(&ping.stdout);
+    if !ping.status.success() {
+        let mut output = stdout.into_owned();
+        output.push_str(&String::from_utf8_lossy(&ping.stderr));
+        show_output("mdns-peer ping", output.lines());
+        return fail(
+            &peers,
+            format!("mdns-peer ping exited with {}", ping.status),
+        );
+    }
+    if let Some(line) = stdout.lines().find(|line| line.contains("answered in")) {
+        println!("   {}", line.trim());
+    }
+
+    println!();
+    println!("✅ Desktop peers discovered and reached each other");
+    Ok(())
+}
+
+/// Build the CLI for the host
+fn build_cli() -> Result<PathBuf> {
+    println!("📦 Building mdns-peer for the host...");
+    let status = Command::new("cargo")
+        .args(["build", "-p", "mdns-peer", "--bin", "mdns-peer"])
+        .status()
+        .context("Failed to run cargo build")?;
+    if !status.success() {
+        anyhow::bail!("Build failed");
+    }
+
+    let target_dir = std::env::var("CARGO_TARGET_DIR").unwrap_or_else(|_| "target".to_string());
+    Ok(Path::new(&target_dir)
+        .join("debug")
+        .join(format!("mdns-peer{}", std::env::consts::EXE_SUFFIX)))
+}
+
+/// A peer running as a subprocess, killed when dropped
+struct Peer {
+    name: String,
+    child: Child,
+    /// Everything it printed, stdout and stderr interleaved
+    output: Arc<Mutex<Vec<String>>>,
+    /// `user_data` of every peer it reported discovering
+    discovered: Arc<Mutex<BTreeSet<String>>>,
+}
+
+impl Peer {
+    fn spawn(binary: &Path, name: &str, service: &str) -> Result<Self> {
+        let mut child = Command::new(binary)
+            .arg(name)
+            .args([
+                "--events",
+                "--mdns-service",
+                service,
+                "--summary-interval",
+                "0",
+            ])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to start {}", binary.display()))?;
+
+        let output = Arc::<Mutex<Vec<String>>>::default();
+        let discovered = Arc::<Mutex<BTreeSet<String>>>::default();
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+        read_lines(stdout, output.clone(), Some(discovered.clone()));
+        read_lines(stderr, output.clone(), None);
+
+        Ok(Self {
+            name: name.to_string(),
+            child,
+            output,
+            discovered,
+        })
+    }
+
+    fn has_discovered(&self, user_data: &str) -> bool {
+        self.discovered.lock().unwrap().contains(user_data)
+    }
+}
+
+impl Drop for Peer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Collect the lines of `source` into `output` on a thread of their own,
+/// and the `user_data` of `discovered` events into `discovered`
+fn read_lines(
+    source: impl Read + Send + 'static,
+    output: Arc<Mutex<Vec<String>>>,
+    discovered: Option<Arc<Mutex<BTreeSet<String>>>>,
+) {
+    thread::spawn(move || {
+        for line in BufReader::new(source).lines() {
+            let Ok(line) = line else { break };
+            if let Some(discovered) = &discovered {
+                if line.starts_with('{')
+                    && json_string(&line, "type").as_deref() == Some("discovered")
+                {
+                    if let Some(user_data) = json_string(&line, "user_data") {
+                        discovered.lock().unwrap().insert(user_data);
+                    }
+                }
+            }
+            output.lock().unwrap().push(line);
+        }
+    });
+}
+
+/// The string value of `key` in one line of compact JSON
+///
+/// Good enough for the flat, serde-written events; a `null` or non-string
+/// value is `None`.
+fn json_string(line: &str, key: &str) -> Option<String> {
+    let start = line.find(&format!("\"{}\":\"", key))? + key.len() + 4;
+    let mut value = String::new();
+    let mut chars = line[start..].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(value),
+            '\\' => value.push(chars.next()?),
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// Print the last lines of `output`, headed by `name`
+fn show_output<'a>(name: &str, output: impl Iterator<Item = &'a str>) {
+    let lines: Vec<_> = output.collect();
+    println!();
+    println!("── {} ({} lines) ──", name, lines.len());
+    if lines.len() > SHOWN_LINES {
+        println!("   ...");
+    }
+    for line in &lines[lines.len().saturating_sub(SHOWN_LINES)..] {
+        println!("   {}", line);
+    }
+}
+
+/// Show what every peer printed and fail with `reason`
+fn fail(peers: &[Peer], reason: String) -> Result<()> {
+    for peer in peers {
+        let output = peer.output.lock().unwrap();
+        show_output(&peer.name, output.iter().map(String::as_str));
+    }
+    println!();
+    println!("❌ {}", reason);
+    anyhow::bail!("e2e-desktop failed: {}", reason)
+}
@@ -0,0 +1,39 @@
+//! `cargo xtask header`: the C header for the FFI
+//!
+//! Generated with cbindgen from `mdns-peer/src/ffi.rs`, configured by
+//! `mdns-peer/cbindgen.toml`. Desktop consumers of the cdylib include it;
+//! the iOS app binds through `@_silgen_name` instead.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+use crate::flag_value;
+
+/// Where `cargo xtask header` writes by default
+pub const DEFAULT_HEADER: &str = "target/include/mdns_peer.h";
+
+pub fn header(args: &[String]) -> Result<()> {
+    let out = PathBuf::from(flag_value(args, "--out").unwrap_or(DEFAULT_HEADER));
+    generate_header(&out)?;
+    println!("✅ Wrote {}", out.display());
+    Ok(())
+}
+
+/// Write the header to `out`, creating its directory
+pub fn generate_header(out: &Path) -> Result<()> {
+    let config = cbindgen::Config::from_file("mdns-peer/cbindgen.toml")
+        .map_err(anyhow::Error::msg)
+        .context("Failed to read mdns-peer/cbindgen.toml")?;
+    let bindings = cbindgen::Builder::new()
+        .with_crate("mdns-peer")
+        .with_config(config)
+        .generate()
+        .context("Failed to generate the C header")?;
+
+    if let Some(dir) = out.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    bindings.write_to_file(out);
+    Ok(())
+}
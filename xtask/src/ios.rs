@@ -0,0 +1,382 @@
+//! `cargo xtask build-ios`: the XCFramework for the iOS app
+//!
+//! The deployment target and the framework's bundle metadata come from, in
+//! order: command-line flags, environment variables (which
+//! `.cargo/config.toml` sets for the whole workspace), then the defaults
+//! below. Apps that target a newer iOS or ship under their own bundle ID
+//! change those instead of this code.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::flag_value;
+
+/// Per-architecture Info.plist, used unless `--plist-template` is given
+///
+/// `{{NAME}}` placeholders are filled in by [`render_template`].
+const ARCHITECTURE_INFO_PLIST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>CFBundleExecutable</key>
+    <string>{{FRAMEWORK_NAME}}</string>
+    <key>CFBundleIdentifier</key>
+    <string>{{BUNDLE_ID}}</string>
+    <key>CFBundleName</key>
+    <string>{{FRAMEWORK_NAME}}</string>
+    <key>CFBundlePackageType</key>
+    <string>FMWK</string>
+    <key>CFBundleShortVersionString</key>
+    <string>{{VERSION}}</string>
+    <key>CFBundleVersion</key>
+    <string>{{BUILD_NUMBER}}</string>
+    <key>CFBundleSupportedPlatforms</key>
+    <array>
+        <string>{{PLATFORM}}</string>
+    </array>
+    <key>MinimumOSVersion</key>
+    <string>{{MINIMUM_OS_VERSION}}</string>
+</dict>
+</plist>
+"#;
+
+/// Settings for `build-ios`
+#[derive(Debug, Clone)]
+pub struct IosOptions {
+    /// `IPHONEOS_DEPLOYMENT_TARGET` for the build and `MinimumOSVersion` in
+    /// the plists
+    pub deployment_target: String,
+    /// `CFBundleIdentifier` of the framework
+    pub bundle_id: String,
+    /// `CFBundleShortVersionString`
+    pub version: String,
+    /// `CFBundleVersion`
+    pub build_number: String,
+    /// Replaces the built-in per-architecture Info.plist
+    pub plist_template: Option<PathBuf>,
+}
+
+impl Default for IosOptions {
+    fn default() -> Self {
+        Self {
+            deployment_target: "14.0".to_string(),
+            bundle_id: "com.spacedrive.mdns-peer".to_string(),
+            version: "1.0".to_string(),
+            build_number: "1".to_string(),
+            plist_template: None,
+        }
+    }
+}
+
+impl IosOptions {
+    /// Options from `args`, falling back to the environment, then defaults
+    ///
+    /// | Flag                  | Environment                  |
+    /// | --------------------- | ---------------------------- |
+    /// | `--deployment-target` | `IPHONEOS_DEPLOYMENT_TARGET` |
+    /// | `--bundle-id`         | `MDNS_PEER_BUNDLE_ID`        |
+    /// | `--version`           | `MDNS_PEER_VERSION`          |
+    /// | `--build-number`      | `MDNS_PEER_BUILD_NUMBER`     |
+    /// | `--plist-template`    | `MDNS_PEER_PLIST_TEMPLATE`   |
+    pub fn from_args(args: &[String]) -> Result<Self> {
+        let setting = |flag: &str, var: &str| {
+            flag_value(args, flag)
+                .map(str::to_string)
+                .or_else(|| std::env::var(var).ok().filter(|v| !v.is_empty()))
+        };
+
+        let mut options = Self::default();
+        if let Some(target) = setting("--deployment-target", "IPHONEOS_DEPLOYMENT_TARGET") {
+            options.deployment_target = target;
+        }
+        if let Some(bundle_id) = setting("--bundle-id", "MDNS_PEER_BUNDLE_ID") {
+            options.bundle_id = bundle_id;
+        }
+        if let Some(version) = setting("--version", "MDNS_PEER_VERSION") {
+            options.version = version;
+        }
+        if let Some(build_number) = setting("--build-number", "MDNS_PEER_BUILD_NUMBER") {
+            options.build_number = build_number;
+        }
+        options.plist_template =
+            setting("--plist-template", "MDNS_PEER_PLIST_TEMPLATE").map(PathBuf::from);
+
+        options.validate()?;
+        Ok(options)
+    }
+
+    /// Catch typos before a multi-minute build rather than in Xcode
+    fn validate(&self) -> Result<()> {
+        if !is_dotted_version(&self.deployment_target) {
+            anyhow::bail!(
+                "Invalid deployment target {:?}: expected a version like 15.0",
+                self.deployment_target
+            );
+        }
+        if !is_dotted_version(&self.version) {
+            anyhow::bail!(
+                "Invalid version {:?}: expected a version like 1.2.0",
+                self.version
+            );
+        }
+        if !is_dotted_version(&self.build_number) {
+            anyhow::bail!(
+                "Invalid build number {:?}: expected a number like 42 or 1.2.3",
+                self.build_number
+            );
+        }
+        let valid_bundle_id = !self.bundle_id.is_empty()
+            && self
+                .bundle_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+        if !valid_bundle_id {
+            anyhow::bail!(
+                "Invalid bundle identifier {:?}: use letters, digits, '-' and '.'",
+                self.bundle_id
+            );
+        }
+        Ok(())
+    }
+}
+
+/// `15`, `15.0`, `1.2.3`
+fn is_dotted_version(version: &str) -> bool {
+    let parts: Vec<_> = version.split('.').collect();
+    parts.len() <= 3
+        && parts
+            .iter()
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Build mdns-peer for iOS devices and simulator, creating an XCFramework
+///
+/// This task:
+/// 1. Builds for aarch64-apple-ios (physical devices)
+/// 2. Builds for aarch64-apple-ios-sim (simulator)
+/// 3. Creates the XCFramework directory structure
+/// 4. Copies the static libraries to the correct locations
+///
+/// The resulting XCFramework can be imported into Xcode projects.
+pub fn build_ios(options: &IosOptions) -> Result<()> {
+    println!("🔨 Building mdns-peer for iOS...");
+    println!(
+        "   {} {}, iOS {}+",
+        options.bundle_id, options.version, options.deployment_target
+    );
+    println!();
+
+    // Target triple and corresponding XCFramework architecture directory
+    let targets = [
+        ("aarch64-apple-ios", "ios-arm64"),
+        ("aarch64-apple-ios-sim", "ios-arm64-simulator"),
+    ];
+    let framework_name = "libmdns_peer";
+
+    // Platform mapping for Info.plist
+    let platform_map = [
+        ("ios-arm64", "iPhoneOS"),
+        ("ios-arm64-simulator", "iPhoneSimulator"),
+    ];
+
+    // Render the plists up front so a bad template fails before the build
+    let plist_template = match &options.plist_template {
+        Some(path) => std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read plist template {}", path.display()))?,
+        None => ARCHITECTURE_INFO_PLIST.to_string(),
+    };
+    let info_plists = platform_map
+        .iter()
+        .map(|(_, platform)| {
+            create_architecture_info_plist(&plist_template, framework_name, platform, options)
+        })
+        .collect::<Result<Vec<_>>>()
+        .context("Failed to render Info.plist")?;
+
+    // Build for each target
+    for (target, arch) in &targets {
+        println!("📦 Building for {} ({})...", arch, target);
+
+        // Only the C ABI is needed on iOS; skip the CLI, metrics and gossip features
+        let status = Command::new("cargo")
+            .args([
+                "build",
+                "--release",
+                "--target",
+                target,
+                "-p",
+                "mdns-peer",
+                "--no-default-features",
+                "--features",
+                "ffi",
+            ])
+            .env("IPHONEOS_DEPLOYMENT_TARGET", &options.deployment_target)
+            .status()
+            .context(format!("Failed to build for {}", target))?;
+
+        if !status.success() {
+            anyhow::bail!("Build failed for target: {}", target);
+        }
+        println!("   ✓ Built successfully");
+    }
+
+    // Create XCFramework directory structure
+    println!();
+    println!("📁 Creating XCFramework structure...");
+    let xcframework_path = Path::new("mdns-peer/mdns_peer.xcframework");
+
+    for ((target, arch), info_plist) in targets.iter().zip(info_plists) {
+        let arch_dir = xcframework_path.join(arch);
+        std::fs::create_dir_all(&arch_dir)
+            .context(format!("Failed to create directory for {}", arch))?;
+
+        // Copy static library
+        let src = format!("target/{}/release/libmdns_peer.a", target);
+        let dst = arch_dir.join("libmdns_peer.a");
+        std::fs::copy(&src, &dst).context(format!("Failed to copy library for {}", arch))?;
+
+        // Write the Info.plist for this architecture
+        let plist_path = arch_dir.join("Info.plist");
+        std::fs::write(&plist_path, info_plist)
+            .context(format!("Failed to write Info.plist for {}", arch))?;
+
+        println!("   ✓ Created {} with library and Info.plist", arch);
+    }
+
+    // Create top-level XCFramework Info.plist
+    let xcframework_info_plist = create_xcframework_info_plist(framework_name);
+    let xcframework_plist_path = xcframework_path.join("Info.plist");
+    std::fs::write(&xcframework_plist_path, xcframework_info_plist)
+        .context("Failed to write XCFramework Info.plist")?;
+    println!("   ✓ Created XCFramework Info.plist");
+
+    // Success message with next steps
+    println!();
+    println!("✅ iOS framework built successfully!");
+    println!();
+    println!("📍 XCFramework location:");
+    println!("   {}", xcframework_path.display());
+    println!();
+    println!("📝 Next steps:");
+    println!("   1. Open MdnsTest/MdnsTest.xcodeproj in Xcode");
+    println!("   2. The framework reference should already be configured");
+    println!("   3. Build and run on simulator or device");
+    println!();
+
+    Ok(())
+}
+
+/// Generate an Info.plist file for each architecture in the XCFramework
+///
+/// Each architecture directory needs its own Info.plist that describes
+/// the framework metadata including bundle identifier, version, and platform.
+fn create_architecture_info_plist(
+    template: &str,
+    framework_name: &str,
+    platform: &str,
+    options: &IosOptions,
+) -> Result<String> {
+    render_template(
+        template,
+        &[
+            ("FRAMEWORK_NAME", framework_name),
+            ("BUNDLE_ID", &options.bundle_id),
+            ("VERSION", &options.version),
+            ("BUILD_NUMBER", &options.build_number),
+            ("PLATFORM", platform),
+            ("MINIMUM_OS_VERSION", &options.deployment_target),
+        ],
+    )
+}
+
+/// Replace each `{{NAME}}` in `template` with its XML-escaped value
+///
+/// Fails on a placeholder without a value, so a misspelled one in a custom
+/// template doesn't end up in the plist verbatim.
+fn render_template(template: &str, values: &[(&str, &str)]) -> Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find("}}")
+            .map(|end| start + end)
+            .context("Unterminated {{ in plist template")?;
+        let name = rest[start + 2..end].trim();
+        let value = values
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| *value)
+            .with_context(|| {
+                let known: Vec<_> = values.iter().map(|(key, _)| *key).collect();
+                format!(
+                    "Unknown placeholder {{{{{}}}}} in plist template (known: {})",
+                    name,
+                    known.join(", ")
+                )
+            })?;
+        rendered.push_str(&xml_escape(value));
+        rest = &rest[end + 2..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Generate the top-level Info.plist for the XCFramework
+///
+/// This describes the XCFramework structure and lists all available libraries
+/// for different platforms and architectures.
+fn create_xcframework_info_plist(framework_name: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>AvailableLibraries</key>
+    <array>
+        <dict>
+            <key>LibraryIdentifier</key>
+            <string>ios-arm64</string>
+            <key>LibraryPath</key>
+            <string>{}.a</string>
+            <key>SupportedArchitectures</key>
+            <array>
+                <string>arm64</string>
+            </array>
+            <key>SupportedPlatform</key>
+            <string>ios</string>
+        </dict>
+        <dict>
+            <key>LibraryIdentifier</key>
+            <string>ios-arm64-simulator</string>
+            <key>LibraryPath</key>
+            <string>{}.a</string>
+            <key>SupportedArchitectures</key>
+            <array>
+                <string>arm64</string>
+            </array>
+            <key>SupportedPlatform</key>
+            <string>ios</string>
+            <key>SupportedPlatformVariant</key>
+            <string>simulator</string>
+        </dict>
+    </array>
+    <key>CFBundlePackageType</key>
+    <string>XFWK</string>
+    <key>XCFrameworkFormatVersion</key>
+    <string>1.0</string>
+</dict>
+</plist>
+"#,
+        framework_name, framework_name
+    )
+}
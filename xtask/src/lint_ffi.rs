@@ -0,0 +1,344 @@
+//! `cargo xtask lint-ffi`: check the C ABI against the code that calls it
+//!
+//! Swift binds to the library by symbol name (`@_silgen_name("peer_start")`
+//! or `dlsym`), so renaming an exported function still compiles on both
+//! sides and only fails at link or call time on a device. This task lists
+//! the functions the static library actually exports (`nm`) and fails if:
+//!
+//! - the Swift app references a symbol the library doesn't export, or
+//! - the C header (freshly generated unless `--header` is given) declares a
+//!   function that isn't exported, or misses one that is.
+//!
+//! Functions of optional features (`docs`, `gossip`) are declared behind
+//! `#if defined(MDNS_PEER_<FEATURE>)` in the header, see `[defines]` in
+//! `mdns-peer/cbindgen.toml`, and may be missing from the library.
+//!
+//! Exported functions the Swift app doesn't use yet are listed, but are not
+//! an error.
+
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::flag_value;
+use crate::header::{generate_header, DEFAULT_HEADER};
+
+/// Where the app's Swift sources live
+const SWIFT_SOURCES: &str = "MdnsTest";
+
+/// Settings for `lint-ffi`
+#[derive(Debug, Clone, Default)]
+pub struct LintFfiOptions {
+    /// Library to inspect; a host build of the `ffi` feature if `None`
+    pub library: Option<PathBuf>,
+    /// C header to cross-check; a freshly generated one if `None`
+    pub header: Option<PathBuf>,
+}
+
+impl LintFfiOptions {
+    pub fn from_args(args: &[String]) -> Self {
+        Self {
+            library: flag_value(args, "--lib").map(PathBuf::from),
+            header: flag_value(args, "--header").map(PathBuf::from),
+        }
+    }
+}
+
+pub fn lint_ffi(options: &LintFfiOptions) -> Result<()> {
+    let library = match &options.library {
+        Some(library) => library.clone(),
+        None => build_host_library()?,
+    };
+
+    let exported = exported_functions(&library)?;
+    println!(
+        "🔎 {} exports {} FFI functions",
+        library.display(),
+        exported.len()
+    );
+    if exported.is_empty() {
+        anyhow::bail!("No FFI functions found in {}", library.display());
+    }
+
+    let mut problems = Vec::new();
+
+    let swift = swift_symbols(Path::new(SWIFT_SOURCES))?;
+    for (symbol, location) in &swift {
+        if !exported.contains(symbol) {
+            problems.push(format!(
+                "{} references {}, which the library doesn't export",
+                location, symbol
+            ));
+        }
+    }
+    let used: BTreeSet<_> = swift.iter().map(|(symbol, _)| symbol.clone()).collect();
+    println!("   Swift uses {} of them", used.len());
+
+    let header = match &options.header {
+        Some(header) => header.clone(),
+        None => {
+            let header = PathBuf::from(DEFAULT_HEADER);
+            generate_header(&header)?;
+            header
+        }
+    };
+    let declared = header_functions(&header)?;
+    println!(
+        "   {} declares {} functions",
+        header.display(),
+        declared.len()
+    );
+    for (symbol, _) in declared
+        .iter()
+        .filter(|(symbol, gated)| !**gated && !exported.contains(*symbol))
+    {
+        problems.push(format!(
+            "{} declares {}, which the library doesn't export",
+            header.display(),
+            symbol
+        ));
+    }
+    for symbol in exported
+        .iter()
+        .filter(|symbol| !declared.contains_key(*symbol))
+    {
+        problems.push(format!(
+            "{} is exported but missing from {}",
+            symbol,
+            header.display()
+        ));
+    }
+
+    let unused: Vec<_> = exported.difference(&used).collect();
+    if !unused.is_empty() {
+        println!();
+        println!("   Not used from Swift yet:");
+        for symbol in unused {
+            println!("     {}", symbol);
+        }
+    }
+
+    println!();
+    if problems.is_empty() {
+        println!("✅ FFI symbols and consumers agree");
+        Ok(())
+    } else {
+        for problem in &problems {
+            println!("❌ {}", problem);
+        }
+        anyhow::bail!("{} FFI mismatch(es)", problems.len())
+    }
+}
+
+/// Build the static library for the host with only the `ffi` feature, like
+/// the iOS build
+fn build_host_library() -> Result<PathBuf> {
+    println!("📦 Building mdns-peer (ffi) for the host...");
+    let status = Command::new("cargo")
+        .args([
+            "build",
+            "--lib",
+            "-p",
+            "mdns-peer",
+            "--no-default-features",
+            "--features",
+            "ffi",
+        ])
+        .status()
+        .context("Failed to run cargo build")?;
+    if !status.success() {
+        anyhow::bail!("Build failed");
+    }
+
+    let target_dir = std::env::var("CARGO_TARGET_DIR").unwrap_or_else(|_| "target".to_string());
+    Ok(Path::new(&target_dir).join("debug/libmdns_peer.a"))
+}
+
+/// Unmangled functions defined by the mdns_peer crate itself
+///
+/// A Rust static library bundles every dependency, so only object files
+/// named after this crate count. Apple's `nm` prefixes C symbols with `_`.
+fn exported_functions(library: &Path) -> Result<BTreeSet<String>> {
+    let output = Command::new("nm")
+        .args(["-A", "-g", "--defined-only"])
+        .arg(library)
+        .output()
+        .context("Failed to run nm")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "nm failed on {}: {}",
+            library.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let mut exported = BTreeSet::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        // <archive>:<member>: <address> <type> <symbol>
+        let mut fields = line.split_whitespace().rev();
+        let (Some(symbol), Some("T"), Some(location)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let member = location.rsplit(':').nth(1).unwrap_or_default();
+        let ours = member
+            .strip_prefix("mdns_peer")
+            .is_some_and(|rest| rest.starts_with('.') || rest.starts_with('-'));
+        if !ours {
+            continue;
+        }
+
+        let symbol = symbol
+            .strip_prefix('_')
+            .filter(|_| cfg!(target_vendor = "apple"))
+            .unwrap_or(symbol);
+        let mangled = symbol.starts_with("_ZN") || symbol.starts_with("_R");
+        if !mangled {
+            exported.insert(symbol.to_string());
+        }
+    }
+    Ok(exported)
+}
+
+/// Symbols bound from Swift, with where each one is referenced
+fn swift_symbols(root: &Path) -> Result<Vec<(String, String)>> {
+    let mut symbols = Vec::new();
+    for path in swift_files(root)? {
+        let source = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        for (number, line) in source.lines().enumerate() {
+            let code = line.split("//").next().unwrap_or_default();
+            for marker in ["@_silgen_name(", "dlsym("] {
+                let Some(start) = code.find(marker) else {
+                    continue;
+                };
+                if let Some(symbol) = quoted(&code[start + marker.len()..]) {
+                    let location = format!("{}:{}", path.display(), number + 1);
+                    symbols.push((symbol.to_string(), location));
+                }
+            }
+        }
+    }
+    Ok(symbols)
+}
+
+fn swift_files(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries =
+            std::fs::read_dir(&dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "swift") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The first string literal in `s`
+fn quoted(s: &str) -> Option<&str> {
+    let start = s.find('"')? + 1;
+    let len = s[start..].find('"')?;
+    Some(&s[start..start + len])
+}
+
+/// Names of the functions declared in a C header
+///
+/// Functions `header` declares, each with whether it's behind a feature
+/// guard
+///
+/// Good enough for a generated header: comments and preprocessor lines are
+/// dropped, and in each remaining `...(...);` declaration the identifier
+/// right before the first `(` is the function name. Typedefs and function
+/// pointer fields (callback types) are skipped.
+fn header_functions(header: &Path) -> Result<BTreeMap<String, bool>> {
+    let source = std::fs::read_to_string(header)
+        .with_context(|| format!("Failed to read {}", header.display()))?;
+
+    let mut code = String::with_capacity(source.len());
+    let mut rest = source.as_str();
+    while let Some(start) = rest.find("/*") {
+        code.push_str(&rest[..start]);
+        rest = rest[start..]
+            .find("*/")
+            .map_or("", |end| &rest[start + end + 2..]);
+    }
+    code.push_str(rest);
+
+    // Split what every build declares from what features guard
+    let mut always = String::new();
+    let mut gated = String::new();
+    let mut guards: Vec<bool> = Vec::new();
+    for line in code.lines() {
+        let line = line.split("//").next().unwrap_or_default();
+        let directive = line.trim_start();
+        if directive.starts_with("#if") {
+            guards.push(directive.starts_with("#if defined(MDNS_PEER_"));
+        } else if directive.starts_with("#endif") {
+            guards.pop();
+        } else if !directive.starts_with('#') {
+            let code = if guards.contains(&true) {
+                &mut gated
+            } else {
+                &mut always
+            };
+            code.push_str(line);
+            code.push('\n');
+        }
+    }
+
+    let mut functions: BTreeMap<_, _> = declared_functions(&always)
+        .into_iter()
+        .map(|name| (name, false))
+        .collect();
+    functions.extend(
+        declared_functions(&gated)
+            .into_iter()
+            .map(|name| (name, true)),
+    );
+    Ok(functions)
+}
+
+/// Names of the functions declared in preprocessed, comment-free `code`
+fn declared_functions(code: &str) -> BTreeSet<String> {
+    let mut functions = BTreeSet::new();
+    for declaration in code.split(';') {
+        // Drop anything up to an opening `extern "C" {` or a closing brace
+        let declaration = declaration
+            .rsplit(['{', '}'])
+            .next()
+            .unwrap_or_default()
+            .trim();
+        if declaration.starts_with("typedef") {
+            continue;
+        }
+        let Some(paren) = declaration.find('(') else {
+            continue;
+        };
+        // A function pointer field, `void (*on_open)(...)`
+        if declaration[paren + 1..].trim_start().starts_with('*') {
+            continue;
+        }
+        let name: String = declaration[..paren]
+            .trim_end()
+            .chars()
+            .rev()
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .collect();
+        if !name.is_empty() {
+            functions.insert(name);
+        }
+    }
+    functions
+}
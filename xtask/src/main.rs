@@ -7,6 +7,14 @@
 //!
 //! ```bash
 //! cargo xtask build-ios    # Build iOS framework
+//! cargo xtask build-ios --deployment-target 15.0 --bundle-id com.example.peer
+//! cargo xtask lint-ffi     # Check FFI symbols against the Swift app
+//! cargo xtask event-schema # JSON schema of the events the FFI delivers
+//! cargo xtask swift-wrapper # Swift async functions over the FFI
+//! cargo xtask e2e-desktop   # Two desktop peers must discover and ping each other
+//! cargo xtask build-linux-cross  # CLI + shared library + header per Linux target
+//! cargo xtask build-windows      # Same for x86_64 Windows
+//! cargo xtask package-desktop    # Tarballs and a Homebrew formula
 //! ```
 //!
 //! ## About xtask
@@ -22,9 +30,16 @@
 //! - Cross-platform by default
 //! - No external tools required
 
-use anyhow::{Context, Result};
-use std::path::Path;
-use std::process::Command;
+use anyhow::Result;
+
+mod cross;
+mod e2e;
+mod header;
+mod ios;
+mod lint_ffi;
+mod package;
+mod schema;
+mod swift;
 
 fn main() -> Result<()> {
     let args: Vec<String> = std::env::args().collect();
@@ -33,7 +48,28 @@ fn main() -> Result<()> {
         eprintln!("Usage: cargo xtask <command>");
         eprintln!();
         eprintln!("Commands:");
-        eprintln!("  build-ios    Build mdns-peer for iOS devices and simulator");
+        eprintln!("  build-ios          Build mdns-peer for iOS devices and simulator");
+        eprintln!("                     [--deployment-target <x.y>] [--bundle-id <id>]");
+        eprintln!("                     [--version <x.y.z>] [--build-number <n>]");
+        eprintln!("                     [--plist-template <Info.plist>]");
+        eprintln!("  build-linux-cross  Build the CLI, shared library and header for Linux");
+        eprintln!("  build-windows      Build the CLI, DLL and header for Windows");
+        eprintln!("                     [--target <triple,...>] [--tool cargo|cross|zigbuild]");
+        eprintln!("                     [--out <dir>]");
+        eprintln!("  package-desktop    Tarballs with completions and a Homebrew formula");
+        eprintln!("                     [--target <triple,...>] [--tool cargo|cross|zigbuild]");
+        eprintln!("                     [--out <dir>] [--url-base <url>]");
+        eprintln!("  header             Generate the C header for the FFI [--out <file>]");
+        eprintln!("  lint-ffi           Check exported FFI symbols against the Swift app");
+        eprintln!("                     [--lib <libmdns_peer.a>] [--header <mdns_peer.h>]");
+        eprintln!("  event-schema       Generate the JSON schema of FFI events [--out <file>]");
+        eprintln!("                     [--check <committed schema>]");
+        eprintln!(
+            "  swift-wrapper      Generate Swift async functions over the FFI [--out <file>]"
+        );
+        eprintln!("                     [--check <committed file>]");
+        eprintln!("  e2e-desktop        Run two desktop peers that must discover and ping");
+        eprintln!("                     each other [--timeout <secs>] [--bin <mdns-peer>]");
         eprintln!();
         eprintln!("Example:");
         eprintln!("  cargo xtask build-ios");
@@ -41,7 +77,23 @@ fn main() -> Result<()> {
     }
 
     match args[1].as_str() {
-        "build-ios" => build_ios()?,
+        "build-ios" => ios::build_ios(&ios::IosOptions::from_args(&args[2..])?)?,
+        "header" => header::header(&args[2..])?,
+        "build-linux-cross" => cross::build_cross(&cross::CrossOptions::from_args(
+            &args[2..],
+            cross::LINUX_TARGETS,
+        )?)?,
+        "build-windows" => cross::build_cross(&cross::CrossOptions::from_args(
+            &args[2..],
+            cross::WINDOWS_TARGETS,
+        )?)?,
+        "package-desktop" => {
+            package::package_desktop(&package::PackageOptions::from_args(&args[2..])?)?
+        }
+        "event-schema" => schema::event_schema(&args[2..])?,
+        "swift-wrapper" => swift::swift_wrapper(&args[2..])?,
+        "e2e-desktop" => e2e::e2e_desktop(&e2e::E2eOptions::from_args(&args[2..])?)?,
+        "lint-ffi" => lint_ffi::lint_ffi(&lint_ffi::LintFfiOptions::from_args(&args[2..]))?,
         _ => {
             eprintln!("Unknown command: {}", args[1]);
             eprintln!("Run 'cargo xtask' for usage information.");
@@ -52,176 +104,10 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-/// Build mdns-peer for iOS devices and simulator, creating an XCFramework
-///
-/// This task:
-/// 1. Builds for aarch64-apple-ios (physical devices)
-/// 2. Builds for aarch64-apple-ios-sim (simulator)
-/// 3. Creates the XCFramework directory structure
-/// 4. Copies the static libraries to the correct locations
-///
-/// The resulting XCFramework can be imported into Xcode projects.
-fn build_ios() -> Result<()> {
-    println!("🔨 Building mdns-peer for iOS...");
-    println!();
-
-    // Target triple and corresponding XCFramework architecture directory
-    let targets = [
-        ("aarch64-apple-ios", "ios-arm64"),
-        ("aarch64-apple-ios-sim", "ios-arm64-simulator"),
-    ];
-
-    // Build for each target
-    for (target, arch) in &targets {
-        println!("📦 Building for {} ({})...", arch, target);
-
-        let status = Command::new("cargo")
-            .args(&["build", "--release", "--target", target, "-p", "mdns-peer"])
-            .env("IPHONEOS_DEPLOYMENT_TARGET", "14.0")
-            .status()
-            .context(format!("Failed to build for {}", target))?;
-
-        if !status.success() {
-            anyhow::bail!("Build failed for target: {}", target);
-        }
-        println!("   ✓ Built successfully");
-    }
-
-    // Create XCFramework directory structure
-    println!();
-    println!("📁 Creating XCFramework structure...");
-    let xcframework_path = Path::new("mdns-peer/mdns_peer.xcframework");
-    let framework_name = "libmdns_peer";
-
-    // Platform mapping for Info.plist
-    let platform_map = [
-        ("ios-arm64", "iPhoneOS"),
-        ("ios-arm64-simulator", "iPhoneSimulator"),
-    ];
-
-    for ((target, arch), (_, platform)) in targets.iter().zip(platform_map.iter()) {
-        let arch_dir = xcframework_path.join(arch);
-        std::fs::create_dir_all(&arch_dir)
-            .context(format!("Failed to create directory for {}", arch))?;
-
-        // Copy static library
-        let src = format!("target/{}/release/libmdns_peer.a", target);
-        let dst = arch_dir.join("libmdns_peer.a");
-        std::fs::copy(&src, &dst).context(format!("Failed to copy library for {}", arch))?;
-
-        // Create Info.plist for this architecture
-        let info_plist = create_architecture_info_plist(framework_name, platform);
-        let plist_path = arch_dir.join("Info.plist");
-        std::fs::write(&plist_path, info_plist)
-            .context(format!("Failed to write Info.plist for {}", arch))?;
-
-        println!("   ✓ Created {} with library and Info.plist", arch);
-    }
-
-    // Create top-level XCFramework Info.plist
-    let xcframework_info_plist = create_xcframework_info_plist(framework_name);
-    let xcframework_plist_path = xcframework_path.join("Info.plist");
-    std::fs::write(&xcframework_plist_path, xcframework_info_plist)
-        .context("Failed to write XCFramework Info.plist")?;
-    println!("   ✓ Created XCFramework Info.plist");
-
-    // Success message with next steps
-    println!();
-    println!("✅ iOS framework built successfully!");
-    println!();
-    println!("📍 XCFramework location:");
-    println!("   {}", xcframework_path.display());
-    println!();
-    println!("📝 Next steps:");
-    println!("   1. Open MdnsTest/MdnsTest.xcodeproj in Xcode");
-    println!("   2. The framework reference should already be configured");
-    println!("   3. Build and run on simulator or device");
-    println!();
-
-    Ok(())
-}
-
-/// Generate an Info.plist file for each architecture in the XCFramework
-///
-/// Each architecture directory needs its own Info.plist that describes
-/// the framework metadata including bundle identifier, version, and platform.
-fn create_architecture_info_plist(framework_name: &str, platform: &str) -> String {
-    format!(
-        r#"<?xml version="1.0" encoding="UTF-8"?>
-<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
-<plist version="1.0">
-<dict>
-    <key>CFBundleExecutable</key>
-    <string>{}</string>
-    <key>CFBundleIdentifier</key>
-    <string>com.spacedrive.mdns-peer</string>
-    <key>CFBundleName</key>
-    <string>{}</string>
-    <key>CFBundlePackageType</key>
-    <string>FMWK</string>
-    <key>CFBundleShortVersionString</key>
-    <string>1.0</string>
-    <key>CFBundleVersion</key>
-    <string>1</string>
-    <key>CFBundleSupportedPlatforms</key>
-    <array>
-        <string>{}</string>
-    </array>
-    <key>MinimumOSVersion</key>
-    <string>14.0</string>
-</dict>
-</plist>
-"#,
-        framework_name, framework_name, platform
-    )
-}
-
-/// Generate the top-level Info.plist for the XCFramework
-///
-/// This describes the XCFramework structure and lists all available libraries
-/// for different platforms and architectures.
-fn create_xcframework_info_plist(framework_name: &str) -> String {
-    format!(
-        r#"<?xml version="1.0" encoding="UTF-8"?>
-<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
-<plist version="1.0">
-<dict>
-    <key>AvailableLibraries</key>
-    <array>
-        <dict>
-            <key>LibraryIdentifier</key>
-            <string>ios-arm64</string>
-            <key>LibraryPath</key>
-            <string>{}.a</string>
-            <key>SupportedArchitectures</key>
-            <array>
-                <string>arm64</string>
-            </array>
-            <key>SupportedPlatform</key>
-            <string>ios</string>
-        </dict>
-        <dict>
-            <key>LibraryIdentifier</key>
-            <string>ios-arm64-simulator</string>
-            <key>LibraryPath</key>
-            <string>{}.a</string>
-            <key>SupportedArchitectures</key>
-            <array>
-                <string>arm64</string>
-            </array>
-            <key>SupportedPlatform</key>
-            <string>ios</string>
-            <key>SupportedPlatformVariant</key>
-            <string>simulator</string>
-        </dict>
-    </array>
-    <key>CFBundlePackageType</key>
-    <string>XFWK</string>
-    <key>XCFrameworkFormatVersion</key>
-    <string>1.0</string>
-</dict>
-</plist>
-"#,
-        framework_name, framework_name
-    )
+/// Value following `flag` in `args`, if present
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
 }
@@ -0,0 +1,246 @@
+//! `cargo xtask package-desktop`: tarballs and a Homebrew formula
+//!
+//! For each target, builds the release binary and packs it with the default
+//! environment file and shell completions from `mdns-peer/packaging/`:
+//!
+//! ```text
+//! dist/mdns-peer-0.1.0-aarch64-apple-darwin.tar.gz
+//! └── mdns-peer-0.1.0-aarch64-apple-darwin/
+//!     ├── mdns-peer
+//!     ├── mdns-peer.env
+//!     ├── README.md
+//!     └── completions/ (bash, zsh, fish)
+//! ```
+//!
+//! `dist/mdns-peer.rb` is a Homebrew formula pointing at those tarballs with
+//! their SHA-256s filled in. Upload the tarballs to `--url-base` (a GitHub
+//! release by default) and the formula is ready for a tap.
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::cross::{binary_name, build_release, BuildTool};
+use crate::flag_value;
+
+const PACKAGING: &str = "mdns-peer/packaging";
+
+/// Targets the formula knows about, by Homebrew platform block
+const FORMULA_TARGETS: &[(&str, &str, &str)] = &[
+    ("on_macos", "on_arm", "aarch64-apple-darwin"),
+    ("on_macos", "on_intel", "x86_64-apple-darwin"),
+    ("on_linux", "on_arm", "aarch64-unknown-linux-musl"),
+    ("on_linux", "on_intel", "x86_64-unknown-linux-musl"),
+];
+
+/// Settings for `package-desktop`
+#[derive(Debug, Clone)]
+pub struct PackageOptions {
+    /// The host target if none are given
+    pub targets: Vec<String>,
+    pub tool: BuildTool,
+    pub out: PathBuf,
+    /// Where the tarballs will be downloaded from
+    pub url_base: Option<String>,
+}
+
+impl PackageOptions {
+    /// `[--target <triple,...>] [--tool cargo|cross|zigbuild] [--out <dir>]
+    /// [--url-base <url>]`
+    pub fn from_args(args: &[String]) -> Result<Self> {
+        let targets = match flag_value(args, "--target") {
+            Some(list) => list.split(',').map(|t| t.trim().to_string()).collect(),
+            None => vec![host_target()?],
+        };
+        let tool = match flag_value(args, "--tool") {
+            Some(tool) => tool.parse()?,
+            None => BuildTool::detect(),
+        };
+        Ok(Self {
+            targets,
+            tool,
+            out: PathBuf::from(flag_value(args, "--out").unwrap_or("dist")),
+            url_base: flag_value(args, "--url-base").map(str::to_string),
+        })
+    }
+}
+
+pub fn package_desktop(options: &PackageOptions) -> Result<()> {
+    let version = package_version()?;
+    println!("🔨 Packaging mdns-peer {}...", version);
+    println!();
+
+    let mut tarballs = Vec::new();
+    for target in &options.targets {
+        build_release(options.tool, target)?;
+        let tarball = write_tarball(&version, target, &options.out)?;
+        let sha256 = sha256_file(&tarball)?;
+        println!("   ✓ {} ({})", tarball.display(), &sha256[..12]);
+        tarballs.push((target.clone(), sha256));
+    }
+
+    let url_base = options.url_base.clone().unwrap_or_else(|| {
+        format!(
+            "https://github.com/jamiepine/iroh-mdns-ios-demo/releases/download/v{}",
+            version
+        )
+    });
+    let formula_path = options.out.join("mdns-peer.rb");
+    std::fs::write(&formula_path, formula(&version, &url_base, &tarballs))
+        .with_context(|| format!("Failed to write {}", formula_path.display()))?;
+
+    println!();
+    println!("✅ Packages are in {}", options.out.display());
+    println!();
+    println!("📝 Homebrew formula: {}", formula_path.display());
+    let missing: Vec<_> = FORMULA_TARGETS
+        .iter()
+        .filter(|(_, _, target)| !tarballs.iter().any(|(t, _)| t == target))
+        .map(|(_, _, target)| *target)
+        .collect();
+    if !missing.is_empty() {
+        println!("   Not packaged, so left as placeholders in the formula:");
+        for target in missing {
+            println!("     {}", target);
+        }
+    }
+    println!("   Upload the tarballs to {}", url_base);
+    Ok(())
+}
+
+/// Stage the package directory for `target` and tar it up
+fn write_tarball(version: &str, target: &str, out: &Path) -> Result<PathBuf> {
+    let name = format!("mdns-peer-{}-{}", version, target);
+    let staging = out.join("package").join(&name);
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging)
+            .with_context(|| format!("Failed to clear {}", staging.display()))?;
+    }
+    let completions = staging.join("completions");
+    std::fs::create_dir_all(&completions)
+        .with_context(|| format!("Failed to create {}", completions.display()))?;
+
+    let binary = binary_name(target);
+    let packaging = Path::new(PACKAGING);
+    let files = [
+        (
+            Path::new("target")
+                .join(target)
+                .join("release")
+                .join(binary),
+            staging.join(binary),
+        ),
+        (
+            packaging.join("mdns-peer.env"),
+            staging.join("mdns-peer.env"),
+        ),
+        (PathBuf::from("README.md"), staging.join("README.md")),
+    ];
+    for (src, dst) in &files {
+        std::fs::copy(src, dst).with_context(|| format!("Failed to copy {}", src.display()))?;
+    }
+    for entry in std::fs::read_dir(packaging.join("completions"))? {
+        let src = entry?.path();
+        let dst = completions.join(src.file_name().context("completion without a name")?);
+        std::fs::copy(&src, &dst).with_context(|| format!("Failed to copy {}", src.display()))?;
+    }
+
+    let tarball = out.join(format!("{}.tar.gz", name));
+    let status = Command::new("tar")
+        .arg("-czf")
+        .arg(&tarball)
+        .arg("-C")
+        .arg(out.join("package"))
+        .arg(&name)
+        .status()
+        .context("Failed to run tar")?;
+    if !status.success() {
+        anyhow::bail!("tar failed for {}", name);
+    }
+    Ok(tarball)
+}
+
+fn sha256_file(path: &Path) -> Result<String> {
+    let bytes =
+        std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(Sha256::digest(&bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+/// A formula with one `url`/`sha256` pair per platform
+///
+/// Targets that weren't packaged keep a placeholder checksum, so the
+/// formula still shows what is missing.
+fn formula(version: &str, url_base: &str, tarballs: &[(String, String)]) -> String {
+    let url_base = url_base.trim_end_matches('/');
+    let mut platforms = String::new();
+    for os in ["on_macos", "on_linux"] {
+        platforms.push_str(&format!("  {} do\n", os));
+        for (_, arch, target) in FORMULA_TARGETS.iter().filter(|(o, _, _)| *o == os) {
+            let sha256 = tarballs
+                .iter()
+                .find(|(t, _)| t == target)
+                .map_or("REPLACE_WITH_SHA256", |(_, sha256)| sha256.as_str());
+            platforms.push_str(&format!(
+                "    {} do\n      url \"{}/mdns-peer-{}-{}.tar.gz\"\n      sha256 \"{}\"\n    end\n",
+                arch, url_base, version, target, sha256
+            ));
+        }
+        platforms.push_str("  end\n");
+    }
+
+    format!(
+        r##"# Generated by `cargo xtask package-desktop`
+class MdnsPeer < Formula
+  desc "Desktop counterpart for the iroh mDNS discovery demo"
+  homepage "https://github.com/jamiepine/iroh-mdns-ios-demo"
+  version "{version}"
+
+{platforms}
+  def install
+    bin.install "mdns-peer"
+    etc.install "mdns-peer.env"
+    bash_completion.install "completions/mdns-peer.bash" => "mdns-peer"
+    zsh_completion.install "completions/_mdns-peer"
+    fish_completion.install "completions/mdns-peer.fish"
+  end
+
+  test do
+    assert_match "Usage", shell_output("#{{bin}}/mdns-peer 2>&1", 1)
+  end
+end
+"##
+    )
+}
+
+/// `version` from mdns-peer's manifest
+fn package_version() -> Result<String> {
+    let manifest = std::fs::read_to_string("mdns-peer/Cargo.toml")
+        .context("Failed to read mdns-peer/Cargo.toml")?;
+    manifest
+        .lines()
+        .find_map(|line| {
+            let value = line
+                .strip_prefix("version")?
+                .trim_start()
+                .strip_prefix('=')?;
+            Some(value.trim().trim_matches('"').to_string())
+        })
+        .context("No version in mdns-peer/Cargo.toml")
+}
+
+/// The triple `rustc` builds for by default
+fn host_target() -> Result<String> {
+    let output = Command::new("rustc")
+        .arg("-vV")
+        .output()
+        .context("Failed to run rustc")?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .map(str::to_string)
+        .context("rustc -vV didn't report a host target")
+}
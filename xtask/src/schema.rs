@@ -0,0 +1,70 @@
+//! `cargo xtask event-schema`: the JSON schema of the FFI's events
+//!
+//! Generated from the Rust event types by the `event_schema` example of
+//! `mdns-peer` (see `mdns-peer/src/schema.rs`). The Swift side checks its
+//! decoder against the file; `--check` fails if a committed copy no longer
+//! matches what the library sends, for CI.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::flag_value;
+
+/// Where `cargo xtask event-schema` writes by default
+pub const DEFAULT_SCHEMA: &str = "target/schema/peer-event.schema.json";
+
+pub fn event_schema(args: &[String]) -> Result<()> {
+    let schema = generate_schema()?;
+    if let Some(committed) = flag_value(args, "--check") {
+        let current = std::fs::read_to_string(committed)
+            .with_context(|| format!("Failed to read {}", committed))?;
+        if current.trim() != schema.trim() {
+            anyhow::bail!(
+                "{} is out of date, regenerate it with `cargo xtask event-schema --out {}`",
+                committed,
+                committed
+            );
+        }
+        println!("✅ {} matches the event types", committed);
+        return Ok(());
+    }
+
+    let out = PathBuf::from(flag_value(args, "--out").unwrap_or(DEFAULT_SCHEMA));
+    write_schema(&out, &schema)?;
+    println!("✅ Wrote {}", out.display());
+    Ok(())
+}
+
+/// The schema as the library generates it, pretty-printed
+fn generate_schema() -> Result<String> {
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--quiet",
+            "-p",
+            "mdns-peer",
+            "--no-default-features",
+            "--features",
+            "ffi",
+            "--example",
+            "event_schema",
+        ])
+        .output()
+        .context("Failed to run cargo run")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Generating the event schema failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    String::from_utf8(output.stdout).context("Event schema is not UTF-8")
+}
+
+fn write_schema(out: &Path, schema: &str) -> Result<()> {
+    if let Some(dir) = out.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    std::fs::write(out, schema).with_context(|| format!("Failed to write {}", out.display()))
+}
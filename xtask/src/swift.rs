@@ -0,0 +1,593 @@
+//! `cargo xtask swift-wrapper`: Swift `async` functions over the FFI
+//!
+//! Each `peer_*_async` call reports how it ended through a completion
+//! callback (`CompletionCallback` in `mdns-peer/src/ffi.rs`), and events
+//! arrive through the event callback. Wiring continuations to C callbacks
+//! by hand is easy to get wrong, so this task reads the Rust sources and
+//! writes a Swift file that binds every `_async` call and wraps it in an
+//! `async throws` function, which cancels the operation with `peer_cancel`
+//! when its Swift task is cancelled, and offers the events as an
+//! `AsyncStream`. The result codes and event mask bits are copied from
+//! `deadline.rs` and `events.rs`.
+//!
+//! The file goes into the app's sources, where `lint-ffi` checks its
+//! symbols like the rest. `--check` fails if the committed copy no longer
+//! matches the Rust side, for CI.
+
+use anyhow::{Context, Result};
+use std::path::Path;
+
+use crate::flag_value;
+
+/// Where `cargo xtask swift-wrapper` writes by default, in the app's
+/// sources
+pub const DEFAULT_SWIFT_WRAPPER: &str = "MdnsTest/MdnsTest/PeerAsync.swift";
+
+const FFI_SOURCE: &str = "mdns-peer/src/ffi.rs";
+const RESULT_CODE_SOURCE: &str = "mdns-peer/src/deadline.rs";
+const EVENT_MASK_SOURCE: &str = "mdns-peer/src/events.rs";
+
+pub fn swift_wrapper(args: &[String]) -> Result<()> {
+    let swift = generate_swift()?;
+    if let Some(committed) = flag_value(args, "--check") {
+        let current = std::fs::read_to_string(committed)
+            .with_context(|| format!("Failed to read {}", committed))?;
+        if current != swift {
+            anyhow::bail!(
+                "{} is out of date, regenerate it with `cargo xtask swift-wrapper --out {}`",
+                committed,
+                committed
+            );
+        }
+        println!("✅ {} matches the FFI", committed);
+        return Ok(());
+    }
+
+    let out = Path::new(flag_value(args, "--out").unwrap_or(DEFAULT_SWIFT_WRAPPER));
+    if let Some(dir) = out.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    std::fs::write(out, swift).with_context(|| format!("Failed to write {}", out.display()))?;
+    println!("✅ Wrote {}", out.display());
+    Ok(())
+}
+
+/// The Swift file for the current Rust sources
+fn generate_swift() -> Result<String> {
+    let read = |path: &str| {
+        std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))
+    };
+    let mut operations = Vec::new();
+    for function in extern_functions(&read(FFI_SOURCE)?) {
+        if !function.name.ends_with("_async") {
+            continue;
+        }
+        match AsyncCall::from_function(&function) {
+            Some(call) => operations.push(call),
+            None => println!(
+                "⚠️  Skipping {}, its arguments have no Swift form yet",
+                function.name
+            ),
+        }
+    }
+    if operations.is_empty() {
+        anyhow::bail!("No `_async` functions found in {}", FFI_SOURCE);
+    }
+    let result_codes = enum_variants(&read(RESULT_CODE_SOURCE)?, "ResultCode")
+        .with_context(|| format!("No ResultCode enum in {}", RESULT_CODE_SOURCE))?;
+    let masks = module_consts(&read(EVENT_MASK_SOURCE)?, "event_mask")
+        .with_context(|| format!("No event_mask module in {}", EVENT_MASK_SOURCE))?;
+    Ok(render(&operations, &result_codes, &masks))
+}
+
+/// An exported function, as declared in Rust
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ExternFunction {
+    name: String,
+    /// `(name, type)` of each parameter
+    params: Vec<(String, String)>,
+}
+
+/// The `extern "C"` functions declared in `source`
+///
+/// Good enough for `ffi.rs`: a declaration starts with `pub extern "C" fn`
+/// or `pub unsafe extern "C" fn` and its signature ends at the first `{`.
+fn extern_functions(source: &str) -> Vec<ExternFunction> {
+    let mut functions = Vec::new();
+    let mut rest = source;
+    while let Some(start) = ["pub extern \"C\" fn ", "pub unsafe extern \"C\" fn "]
+        .iter()
+        .filter_map(|marker| rest.find(marker).map(|at| (at, marker.len())))
+        .min()
+    {
+        let declaration = &rest[start.0 + start.1..];
+        let signature = &declaration[..declaration.find('{').unwrap_or(declaration.len())];
+        rest = &declaration[signature.len()..];
+
+        let (Some(open), Some(close)) = (signature.find('('), signature.rfind(')')) else {
+            continue;
+        };
+        let params = split_top_level(&signature[open + 1..close])
+            .into_iter()
+            .filter_map(|param| {
+                let (name, ty) = param.split_once(':')?;
+                Some((name.trim().to_string(), normalize(ty)))
+            })
+            .collect();
+        functions.push(ExternFunction {
+            name: signature[..open].trim().to_string(),
+            params,
+        });
+    }
+    functions
+}
+
+/// `list` split at commas outside of `<...>` and `(...)`, without empty
+/// entries
+fn split_top_level(list: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    for (i, c) in list.char_indices() {
+        match c {
+            '<' | '(' => depth += 1,
+            '>' | ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&list[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&list[start..]);
+    parts
+        .into_iter()
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .collect()
+}
+
+/// A type with its whitespace collapsed, e.g. `*const c_char`
+fn normalize(ty: &str) -> String {
+    ty.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// One argument of an `_async` call as the Swift wrapper takes it
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Argument {
+    /// `*const c_char`, passed as a `String`
+    Str(String),
+    /// `*const u8` followed by its length, passed as `Data`
+    Bytes(String),
+    /// A number or flag, passed as is
+    Value(String, &'static str),
+}
+
+/// An `_async` FFI call the wrapper covers
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct AsyncCall {
+    symbol: String,
+    /// The C parameters in order, as `(name, Swift type)`
+    binding: Vec<(String, &'static str)>,
+    /// The wrapper's arguments, without the completion
+    arguments: Vec<Argument>,
+}
+
+impl AsyncCall {
+    /// The call for `function`, or `None` if it doesn't take a completion
+    /// last or takes something else Swift can't pass simply
+    fn from_function(function: &ExternFunction) -> Option<Self> {
+        let params = &function.params;
+        let callback = params
+            .iter()
+            .position(|(_, ty)| ty == "Option<CompletionCallback>")?;
+        if callback + 2 != params.len() || params[callback + 1].1 != "*mut c_void" {
+            return None;
+        }
+
+        let mut binding = Vec::new();
+        let mut arguments = Vec::new();
+        let mut i = 0;
+        while i < callback {
+            let (name, ty) = &params[i];
+            match ty.as_str() {
+                "*const c_char" => {
+                    binding.push((name.clone(), "UnsafePointer<CChar>?"));
+                    arguments.push(Argument::Str(name.clone()));
+                }
+                "*const u8" if params.get(i + 1).is_some_and(|(_, ty)| ty == "usize") => {
+                    binding.push((name.clone(), "UnsafePointer<UInt8>?"));
+                    binding.push((params[i + 1].0.clone(), "Int"));
+                    arguments.push(Argument::Bytes(name.clone()));
+                    i += 1;
+                }
+                ty => {
+                    let swift = swift_value_type(ty)?;
+                    binding.push((name.clone(), swift));
+                    arguments.push(Argument::Value(name.clone(), swift));
+                }
+            }
+            i += 1;
+        }
+        binding.push((params[callback].0.clone(), "PeerCompletionCallback?"));
+        binding.push((params[callback + 1].0.clone(), "UnsafeMutableRawPointer?"));
+        Some(Self {
+            symbol: function.name.clone(),
+            binding,
+            arguments,
+        })
+    }
+
+    /// Name of the Swift wrapper, `peer_send_file_async` -> `sendFile`
+    fn swift_name(&self) -> String {
+        let name = self.symbol.strip_prefix("peer_").unwrap_or(&self.symbol);
+        camel_case(name.strip_suffix("_async").unwrap_or(name))
+    }
+}
+
+fn swift_value_type(ty: &str) -> Option<&'static str> {
+    Some(match ty {
+        "bool" => "Bool",
+        "u8" => "UInt8",
+        "u16" => "UInt16",
+        "u32" => "UInt32",
+        "u64" => "UInt64",
+        "i32" => "Int32",
+        "i64" => "Int64",
+        "usize" => "Int",
+        "f64" => "Double",
+        _ => return None,
+    })
+}
+
+/// `snake_case` or `SCREAMING_CASE` as `lowerCamelCase`
+fn camel_case(name: &str) -> String {
+    let mut camel = String::new();
+    for (i, word) in name.split('_').filter(|word| !word.is_empty()).enumerate() {
+        let word = word.to_ascii_lowercase();
+        if i == 0 {
+            camel.push_str(&word);
+        } else {
+            let mut chars = word.chars();
+            if let Some(first) = chars.next() {
+                camel.push(first.to_ascii_uppercase());
+                camel.push_str(chars.as_str());
+            }
+        }
+    }
+    camel
+}
+
+/// `PascalCase` as `lowerCamelCase`
+fn lower_first(name: &str) -> String {
+    let mut chars = name.chars();
+    chars.next().map_or_else(String::new, |first| {
+        first.to_ascii_lowercase().to_string() + chars.as_str()
+    })
+}
+
+/// `(variant, discriminant)` of `pub enum <name>` with explicit
+/// discriminants
+fn enum_variants(source: &str, name: &str) -> Option<Vec<(String, String)>> {
+    let body = item_body(source, &format!("pub enum {} {{", name))?;
+    let variants = split_top_level(&strip_comments(body))
+        .into_iter()
+        .filter_map(|variant| {
+            let (variant, value) = variant.split_once('=')?;
+            Some((variant.trim().to_string(), value.trim().to_string()))
+        })
+        .collect();
+    Some(variants)
+}
+
+/// `(name, value)` of the `u32` constants in `pub mod <name>`
+fn module_consts(source: &str, name: &str) -> Option<Vec<(String, String)>> {
+    let body = item_body(source, &format!("pub mod {} {{", name))?;
+    let consts = strip_comments(body)
+        .split(';')
+        .filter_map(|item| {
+            let item = normalize(item);
+            let (name, value) = item.strip_prefix("pub const ")?.split_once(": u32 =")?;
+            Some((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect();
+    Some(consts)
+}
+
+/// What is between the braces of the item starting with `header`, which
+/// itself ends with the opening brace
+fn item_body<'a>(source: &'a str, header: &str) -> Option<&'a str> {
+    let start = source.find(header)? + header.len();
+    let len = source[start..].find("\n}")?;
+    Some(&source[start..start + len])
+}
+
+/// `source` without `//` comments
+fn strip_comments(source: &str) -> String {
+    source
+        .lines()
+        .map(|line| line.split("//").next().unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A mask value in Swift: constants renamed, `u32::MAX` spelled out
+fn swift_mask_value(value: &str) -> String {
+    if value == "u32::MAX" {
+        return "UInt32.max".to_string();
+    }
+    value
+        .split_whitespace()
+        .map(|token| {
+            if token.starts_with(|c: char| c.is_ascii_uppercase()) {
+                camel_case(token)
+            } else {
+                token.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn render(
+    operations: &[AsyncCall],
+    result_codes: &[(String, String)],
+    masks: &[(String, String)],
+) -> String {
+    let mut swift = String::from(HEADER);
+
+    swift.push_str("\n/// How an operation ended, see `ResultCode` in mdns-peer/src/deadline.rs\n");
+    swift.push_str("enum PeerResultCode: Int32 {\n");
+    for (variant, value) in result_codes {
+        swift.push_str(&format!("    case {} = {}\n", lower_first(variant), value));
+    }
+    swift.push_str("}\n");
+
+    swift.push_str("\n/// Event mask bits, see `event_mask` in mdns-peer/src/events.rs\n");
+    swift.push_str("enum PeerEventMask {\n");
+    for (name, value) in masks {
+        swift.push_str(&format!(
+            "    static let {}: UInt32 = {}\n",
+            camel_case(name),
+            swift_mask_value(value)
+        ));
+    }
+    swift.push_str("}\n");
+
+    swift.push_str(SUPPORT);
+
+    for call in operations {
+        let params: Vec<_> = call
+            .binding
+            .iter()
+            .map(|(name, ty)| format!("_ {}: {}", name, ty))
+            .collect();
+        swift.push_str(&format!(
+            "\n@_silgen_name(\"{}\")\nprivate func raw_{}({}) -> UInt64\n",
+            call.symbol,
+            call.symbol,
+            params.join(", ")
+        ));
+    }
+
+    swift.push_str("\n/// The `peer_*_async` calls as Swift `async` functions\n");
+    swift.push_str("///\n");
+    swift.push_str("/// Each returns the operation ID and the JSON of the event it ended with,\n");
+    swift.push_str("/// and throws a `PeerOperationError` unless that was a success.\n");
+    swift.push_str("/// Cancelling the calling task cancels the operation.\n");
+    swift.push_str("enum PeerAsync {");
+    for call in operations {
+        let params: Vec<_> = call
+            .arguments
+            .iter()
+            .map(|argument| match argument {
+                Argument::Str(name) => format!("{}: String", camel_case(name)),
+                Argument::Bytes(name) => format!("{}: Data", camel_case(name)),
+                Argument::Value(name, ty) => format!("{}: {}", camel_case(name), ty),
+            })
+            .collect();
+        let mut args: Vec<String> = Vec::new();
+        let mut bytes = None;
+        for argument in &call.arguments {
+            match argument {
+                Argument::Str(name) | Argument::Value(name, _) => args.push(camel_case(name)),
+                Argument::Bytes(name) => {
+                    let name = camel_case(name);
+                    args.push(format!("{}.bindMemory(to: UInt8.self).baseAddress", name));
+                    args.push(format!("{}.count", name));
+                    bytes = Some(name);
+                }
+            }
+        }
+        args.push("callback".to_string());
+        args.push("context".to_string());
+        let raw_call = format!("raw_{}({})", call.symbol, args.join(", "));
+
+        swift.push_str(&format!(
+            "\n    /// `{}`, see mdns-peer/src/ffi.rs\n",
+            call.symbol
+        ));
+        swift.push_str(&format!(
+            "    static func {}({}) async throws -> PeerOperationResult {{\n",
+            call.swift_name(),
+            params.join(", ")
+        ));
+        swift.push_str("        try await awaitOperation { callback, context in\n");
+        match bytes {
+            Some(name) => {
+                swift.push_str(&format!(
+                    "            {}.withUnsafeBytes {{ {} in\n",
+                    name, name
+                ));
+                swift.push_str(&format!("                {}\n", raw_call));
+                swift.push_str("            }\n");
+            }
+            None => swift.push_str(&format!("            {}\n", raw_call)),
+        }
+        swift.push_str("        }\n");
+        swift.push_str("    }\n");
+    }
+    swift.push_str("}\n");
+    swift
+}
+
+const HEADER: &str = r#"// Generated by `cargo xtask swift-wrapper` from mdns-peer/src, do not edit.
+//
+// The completion-callback FFI as Swift `async` functions (`PeerAsync`), and
+// the event callback as an `AsyncStream` (`PeerEvents`).
+
+import Foundation
+"#;
+
+const SUPPORT: &str = r#"
+/// Called once when an `_async` call ends, see `CompletionCallback` in
+/// mdns-peer/src/ffi.rs
+typealias PeerCompletionCallback = @convention(c) (UInt64, Int32, UnsafePointer<CChar>?, UnsafeMutableRawPointer?) -> Void
+
+@_silgen_name("peer_cancel")
+private func raw_peer_cancel(_ op_id: UInt64) -> Bool
+
+@_silgen_name("peer_set_event_callback_filtered")
+private func raw_peer_set_event_callback_filtered(_ callback: (@convention(c) (UnsafePointer<CChar>?, UnsafeMutableRawPointer?) -> Void)?, _ context: UnsafeMutableRawPointer?, _ mask: UInt32)
+
+/// An operation that succeeded: its ID and the event that reported it, as
+/// JSON
+struct PeerOperationResult {
+    let opId: UInt64
+    let json: String
+}
+
+/// An operation that didn't succeed
+///
+/// `json` is the event that reported it. A call refused before it started,
+/// for an invalid argument or because the peer isn't running, throws
+/// `invalidArgument` with `opId` 0 and empty `json`.
+struct PeerOperationError: Error {
+    let code: PeerResultCode
+    let opId: UInt64
+    let json: String
+}
+
+/// One call in flight, shared by its continuation, the completion callback
+/// and the task's cancellation handler
+private final class PendingOperation: @unchecked Sendable {
+    private let lock = NSLock()
+    private var continuation: CheckedContinuation<PeerOperationResult, Error>?
+    private var opId: UInt64 = 0
+    private var cancelled = false
+
+    func wait(_ continuation: CheckedContinuation<PeerOperationResult, Error>) {
+        lock.lock()
+        self.continuation = continuation
+        lock.unlock()
+    }
+
+    /// The call returned `opId`, 0 if it was refused
+    func started(_ opId: UInt64) {
+        lock.lock()
+        self.opId = opId
+        let cancelled = self.cancelled
+        lock.unlock()
+        if opId == 0 {
+            finish(status: PeerResultCode.invalidArgument.rawValue, opId: 0, json: "")
+        } else if cancelled {
+            _ = raw_peer_cancel(opId)
+        }
+    }
+
+    func cancel() {
+        lock.lock()
+        cancelled = true
+        let opId = self.opId
+        lock.unlock()
+        if opId != 0 {
+            _ = raw_peer_cancel(opId)
+        }
+    }
+
+    func finish(status: Int32, opId: UInt64, json: String) {
+        lock.lock()
+        let continuation = self.continuation
+        self.continuation = nil
+        lock.unlock()
+        if status == PeerResultCode.ok.rawValue {
+            continuation?.resume(returning: PeerOperationResult(opId: opId, json: json))
+        } else {
+            let code = PeerResultCode(rawValue: status) ?? .failed
+            continuation?.resume(throwing: PeerOperationError(code: code, opId: opId, json: json))
+        }
+    }
+}
+
+private let completionTrampoline: PeerCompletionCallback = { opId, status, json, context in
+    guard let context else { return }
+    let pending = Unmanaged<PendingOperation>.fromOpaque(context).takeRetainedValue()
+    pending.finish(status: status, opId: opId, json: json.map { String(cString: $0) } ?? "")
+}
+
+/// Run one `_async` call, whose `start` passes the callback and context on
+/// and returns the operation ID
+private func awaitOperation(
+    _ start: (PeerCompletionCallback, UnsafeMutableRawPointer) -> UInt64
+) async throws -> PeerOperationResult {
+    let pending = PendingOperation()
+    return try await withTaskCancellationHandler {
+        try await withCheckedThrowingContinuation { continuation in
+            pending.wait(continuation)
+            // Released by the completion, which a refused call never calls
+            let context = Unmanaged.passRetained(pending).toOpaque()
+            let opId = start(completionTrampoline, context)
+            if opId == 0 {
+                Unmanaged<PendingOperation>.fromOpaque(context).release()
+            }
+            pending.started(opId)
+        }
+    } onCancel: {
+        pending.cancel()
+    }
+}
+
+/// The events the library emits, as an `AsyncStream`
+enum PeerEvents {
+    private static let lock = NSLock()
+    private static var continuation: AsyncStream<String>.Continuation?
+    private static var generation = 0
+
+    /// Events whose bit is set in `mask`, as the JSON the event callback
+    /// gets (an array per batch with `peer_set_event_batch_window`)
+    ///
+    /// Registers the event callback, replacing any other, so only the
+    /// latest stream gets events. Ending the stream unregisters it.
+    static func stream(mask: UInt32 = PeerEventMask.all) -> AsyncStream<String> {
+        AsyncStream { continuation in
+            PeerEvents.lock.lock()
+            PeerEvents.generation += 1
+            let current = PeerEvents.generation
+            let previous = PeerEvents.continuation
+            PeerEvents.continuation = continuation
+            PeerEvents.lock.unlock()
+            previous?.finish()
+
+            continuation.onTermination = { _ in
+                PeerEvents.lock.lock()
+                let latest = PeerEvents.generation == current
+                if latest {
+                    PeerEvents.continuation = nil
+                }
+                PeerEvents.lock.unlock()
+                if latest {
+                    raw_peer_set_event_callback_filtered(nil, nil, 0)
+                }
+            }
+            raw_peer_set_event_callback_filtered({ json, _ in
+                guard let json else { return }
+                PeerEvents.lock.lock()
+                let continuation = PeerEvents.continuation
+                PeerEvents.lock.unlock()
+                continuation?.yield(String(cString: json))
+            }, nil, mask)
+        }
+    }
+}
+"#;